@@ -21,7 +21,7 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use webrender_traits::{BlobImageData, BlobImageDescriptor, BlobImageError, BlobImageRenderer};
 use webrender_traits::{BlobImageResult, ClipRegion, ColorF, Epoch, GlyphInstance};
-use webrender_traits::{DeviceIntPoint, DeviceUintSize, DeviceUintRect, LayoutPoint, LayoutRect, LayoutSize};
+use webrender_traits::{DeviceIntPoint, DeviceUintPoint, DeviceUintSize, DeviceUintRect, LayoutPoint, LayoutRect, LayoutSize};
 use webrender_traits::{ImageData, ImageDescriptor, ImageFormat, ImageKey, ImageRendering};
 use webrender_traits::{PipelineId, RasterizedBlobImage};
 
@@ -236,8 +236,11 @@ fn main() {
     };
 
     let size = DeviceUintSize::new(width, height);
-    let (mut renderer, sender) = webrender::renderer::Renderer::new(gl, opts, size).unwrap();
+    let (mut renderer, sender) = webrender::renderer::Renderer::new(gl, opts).unwrap();
     let api = sender.create_api();
+    api.set_window_parameters(size,
+                              DeviceUintRect::new(DeviceUintPoint::zero(), size),
+                              window.hidpi_factor());
 
     let notifier = Box::new(Notifier::new(window.create_window_proxy()));
     renderer.set_render_notifier(notifier);
@@ -399,7 +402,7 @@ fn main() {
         builder.finalize(),
         true);
     api.set_root_pipeline(pipeline_id);
-    api.generate_frame(None);
+    api.generate_frame(None, None);
 
     let mut touch_state = TouchState::new();
 
@@ -420,17 +423,17 @@ fn main() {
                                              _, Some(glutin::VirtualKeyCode::P)) => {
                     let enable_profiler = !renderer.get_profiler_enabled();
                     renderer.set_profiler_enabled(enable_profiler);
-                    api.generate_frame(None);
+                    api.generate_frame(None, None);
                 }
                 glutin::Event::Touch(touch) => {
                     match touch_state.handle_event(touch) {
                         TouchResult::Pan(pan) => {
                             api.set_pan(pan);
-                            api.generate_frame(None);
+                            api.generate_frame(None, None);
                         }
                         TouchResult::Zoom(zoom) => {
                             api.set_pinch_zoom(webrender_traits::ZoomFactor::new(zoom));
-                            api.generate_frame(None);
+                            api.generate_frame(None, None);
                         }
                         TouchResult::None => {}
                     }
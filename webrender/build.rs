@@ -7,6 +7,31 @@ use std::path::{Path, PathBuf};
 use std::io::prelude::*;
 use std::fs::{canonicalize, read_dir, File};
 
+const DATA_FETCH_BEGIN: &'static str = "// WR_DATA_FETCH_BEGIN";
+const DATA_FETCH_END: &'static str = "// WR_DATA_FETCH_END";
+
+fn read_file(path: &Path) -> String {
+    let mut source = String::new();
+    File::open(path).unwrap().read_to_string(&mut source).unwrap();
+    source
+}
+
+// Generates prim_shared_ubo, the variant of prim_shared that reads the
+// layers and render tasks from uniform buffers instead of data textures.
+fn write_ubo_variant(res_dir: &Path, out_dir: &Path) -> PathBuf {
+    let prim_shared = read_file(&res_dir.join("prim_shared.glsl"));
+    let ubo_data = read_file(&res_dir.join("ubo_data.glsl"));
+
+    let begin = prim_shared.find(DATA_FETCH_BEGIN).expect("prim_shared has no data fetch section");
+    let end = prim_shared.find(DATA_FETCH_END).expect("prim_shared has no data fetch section") +
+              DATA_FETCH_END.len();
+
+    let variant_path = out_dir.join("prim_shared_ubo.glsl");
+    let mut variant_file = File::create(&variant_path).unwrap();
+    write!(variant_file, "{}{}{}", &prim_shared[..begin], ubo_data, &prim_shared[end..]).unwrap();
+    variant_path
+}
+
 fn write_shaders(glsl_files: Vec<PathBuf>, shader_file_path: &Path) {
     let mut shader_file = File::create(shader_file_path).unwrap();
 
@@ -50,5 +75,7 @@ fn main() {
         }
     }
 
+    glsl_files.push(write_ubo_variant(res_dir, Path::new(&out_dir)));
+
     write_shaders(glsl_files, &shaders_file);
 }
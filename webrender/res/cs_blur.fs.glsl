@@ -18,8 +18,20 @@ void main(void) {
     vec4 cache_sample = texture(sCacheRGBA8, vUv);
     vec4 color = vec4(cache_sample.rgb, 1.0) * (cache_sample.a * gauss(0.0, vSigma));
 
+#ifdef WR_FEATURE_FAST_BLUR
+    // Fetch two taps at a time from between their texels, and let the
+    // bilinear filter weight them. Alpha is then premultiplied after
+    // filtering, which slightly softens edges between colors.
+    for (int i=1 ; i < vBlurRadius ; i += 2) {
+        float weight0 = gauss(float(i), vSigma);
+        float weight1 = gauss(float(i + 1), vSigma);
+        float weight = weight0 + weight1;
+        vec2 offset = vec2(float(i) + weight1 / weight) * vOffsetScale;
+#else
     for (int i=1 ; i < vBlurRadius ; ++i) {
+        float weight = gauss(float(i), vSigma);
         vec2 offset = vec2(float(i)) * vOffsetScale;
+#endif
 
         vec2 st0 = clamp(vUv.xy + offset, vUvRect.xy, vUvRect.zw);
         vec4 color0 = texture(sCacheRGBA8, vec3(st0, vUv.z));
@@ -28,7 +40,6 @@ void main(void) {
         vec4 color1 = texture(sCacheRGBA8, vec3(st1, vUv.z));
 
         // Alpha must be premultiplied in order to properly blur the alpha channel.
-        float weight = gauss(float(i), vSigma);
         color += vec4(color0.rgb * color0.a, color0.a) * weight;
         color += vec4(color1.rgb * color1.a, color1.a) * weight;
     }
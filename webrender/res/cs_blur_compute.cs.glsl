@@ -0,0 +1,122 @@
+#line 1
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+// Applies a separable gaussian blur in one direction, like cs_blur, but
+// each texel of the source is fetched once per work group and shared
+// between the invocations that need it, instead of once per tap.
+//
+// A work group blurs WR_BLUR_GROUP_SIZE pixels of one row (or column) of
+// the task. As in cs_blur, the source rect is stretched over the task
+// rect, and sampled with a linear filter.
+
+#define DIR_HORIZONTAL  0
+#define DIR_VERTICAL    1
+
+// The source texels a work group can need: one per pixel, plus the taps
+// past either end, plus one more on each side for the linear filter.
+#define WR_BLUR_CACHE_SIZE (WR_BLUR_GROUP_SIZE + 2 * WR_MAX_BLUR_RADIUS + 4)
+
+layout(local_size_x = WR_BLUR_GROUP_SIZE, local_size_y = 1) in;
+
+uniform sampler2DArray sCacheRGBA8;
+layout(rgba8) uniform writeonly image2D uDestination;
+
+// The task and source rects, as origin and size in texels.
+uniform ivec4 uTaskRect;
+uniform ivec4 uSourceRect;
+uniform int uSourceLayer;
+uniform int uBlurRadius;
+uniform int uBlurDirection;
+
+shared vec4 sSourceTexels[WR_BLUR_CACHE_SIZE];
+
+float gauss(float x, float sigma) {
+    return (1.0 / sqrt(6.283185307179586 * sigma * sigma)) * exp(-(x * x) / (2.0 * sigma * sigma));
+}
+
+// Swizzles a position along and across the blur into texel coordinates.
+ivec2 to_texel(int along, int across) {
+    return uBlurDirection == DIR_HORIZONTAL ? ivec2(along, across) : ivec2(across, along);
+}
+
+vec4 dither(vec4 color, ivec2 texel) {
+#ifdef WR_FEATURE_DITHERING
+    const int matrix_mask = 7;
+
+    ivec2 pos = texel & ivec2(matrix_mask);
+    float noise_normalized = (texelFetch(sDither, pos, 0).r * 255.0 + 0.5) / 64.0;
+    float noise = (noise_normalized - 0.5) / 256.0; // scale down to the unit length
+
+    return color + vec4(noise, noise, noise, 0);
+#else
+    return color;
+#endif
+}
+
+void main(void) {
+    // The sizes of the task and source rects, and the source origin,
+    // along and across the blur.
+    bool horizontal = uBlurDirection == DIR_HORIZONTAL;
+    ivec2 task_size = horizontal ? uTaskRect.zw : uTaskRect.wz;
+    ivec2 src_origin = horizontal ? uSourceRect.xy : uSourceRect.yx;
+    ivec2 src_size = horizontal ? uSourceRect.zw : uSourceRect.wz;
+
+    // The position of this pixel in the task, along and across the blur.
+    int along = int(gl_GlobalInvocationID.x);
+    int across = int(gl_WorkGroupID.y);
+    int group_start = int(gl_WorkGroupID.x) * WR_BLUR_GROUP_SIZE;
+
+    // Where the first pixel of the group and this pixel land in the
+    // source, in texels along the blur.
+    float scale = float(src_size.x) / float(task_size.x);
+    float group_center = (float(group_start) + 0.5) * scale;
+    float center = (float(along) + 0.5) * scale;
+    int first_texel = int(floor(group_center - 0.5)) - uBlurRadius;
+
+    // Across the blur, the hardware filter does the interpolation.
+    vec2 texture_size = vec2(textureSize(sCacheRGBA8, 0).xy);
+    float across_coord = float(src_origin.y) + (float(across) + 0.5) * float(src_size.y) / float(task_size.y);
+
+    for (int i = int(gl_LocalInvocationID.x) ; i < WR_BLUR_CACHE_SIZE ; i += WR_BLUR_GROUP_SIZE) {
+        int texel = clamp(first_texel + i, 0, src_size.x - 1);
+        vec2 pos = horizontal ?
+            vec2(float(src_origin.x + texel) + 0.5, across_coord) :
+            vec2(across_coord, float(src_origin.x + texel) + 0.5);
+        sSourceTexels[i] = textureLod(sCacheRGBA8, vec3(pos / texture_size, float(uSourceLayer)), 0.0);
+    }
+
+    barrier();
+
+    if (along >= task_size.x) {
+        return;
+    }
+
+    // All the taps of this pixel are the same fraction of a texel past
+    // a cached texel.
+    float first = center - 0.5 - float(first_texel);
+    int index = int(floor(first));
+    float frac = first - float(index);
+
+    float sigma = float(uBlurRadius) * 0.5;
+    vec4 sample0 = mix(sSourceTexels[index], sSourceTexels[index + 1], frac);
+    vec4 color = vec4(sample0.rgb, 1.0) * (sample0.a * gauss(0.0, sigma));
+
+    for (int i=1 ; i < uBlurRadius ; ++i) {
+        float weight = gauss(float(i), sigma);
+
+        vec4 color0 = mix(sSourceTexels[index + i], sSourceTexels[index + i + 1], frac);
+        vec4 color1 = mix(sSourceTexels[index - i], sSourceTexels[index - i + 1], frac);
+
+        // Alpha must be premultiplied in order to properly blur the alpha channel.
+        color += vec4(color0.rgb * color0.a, color0.a) * weight;
+        color += vec4(color1.rgb * color1.a, color1.a) * weight;
+    }
+
+    // Unpremultiply the alpha.
+    color.rgb /= color.a;
+
+    ivec2 texel = uTaskRect.xy + to_texel(along, across);
+    imageStore(uDestination, texel, dither(color, texel));
+}
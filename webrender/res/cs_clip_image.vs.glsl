@@ -11,10 +11,10 @@ struct ImageMaskData {
 ImageMaskData fetch_mask_data(int index) {
     ImageMaskData info;
 
-    ivec2 uv = get_fetch_uv_2(index);
+    ivec2 uv = get_gpu_cache_uv(index);
 
-    info.uv_rect = texelFetchOffset(sData32, uv, 0, ivec2(0, 0));
-    info.local_rect = texelFetchOffset(sData32, uv, 0, ivec2(1, 0));
+    info.uv_rect = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
+    info.local_rect = texelFetchOffset(sGpuCache, uv, 0, ivec2(1, 0));
 
     return info;
 }
@@ -11,10 +11,10 @@ struct ClipRect {
 ClipRect fetch_clip_rect(int index) {
     ClipRect rect;
 
-    ivec2 uv = get_fetch_uv_2(index);
+    ivec2 uv = get_gpu_cache_uv(index);
 
-    rect.rect = texelFetchOffset(sData32, uv, 0, ivec2(0, 0));
-    rect.mode = texelFetchOffset(sData32, uv, 0, ivec2(1, 0));
+    rect.rect = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
+    rect.mode = texelFetchOffset(sGpuCache, uv, 0, ivec2(1, 0));
 
     return rect;
 }
@@ -27,10 +27,10 @@ struct ClipCorner {
 ClipCorner fetch_clip_corner(int index) {
     ClipCorner corner;
 
-    ivec2 uv = get_fetch_uv_2(index);
+    ivec2 uv = get_gpu_cache_uv(index);
 
-    corner.rect = texelFetchOffset(sData32, uv, 0, ivec2(0, 0));
-    corner.outer_inner_radius = texelFetchOffset(sData32, uv, 0, ivec2(1, 0));
+    corner.rect = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
+    corner.outer_inner_radius = texelFetchOffset(sGpuCache, uv, 0, ivec2(1, 0));
 
     return corner;
 }
@@ -47,10 +47,10 @@ ClipData fetch_clip(int index) {
     ClipData clip;
 
     clip.rect = fetch_clip_rect(index + 0);
-    clip.top_left = fetch_clip_corner(index + 1);
-    clip.top_right = fetch_clip_corner(index + 2);
-    clip.bottom_left = fetch_clip_corner(index + 3);
-    clip.bottom_right = fetch_clip_corner(index + 4);
+    clip.top_left = fetch_clip_corner(index + 2);
+    clip.top_right = fetch_clip_corner(index + 4);
+    clip.bottom_left = fetch_clip_corner(index + 6);
+    clip.bottom_right = fetch_clip_corner(index + 8);
 
     return clip;
 }
@@ -12,7 +12,7 @@ void main(void) {
     RenderTaskData task = fetch_render_task(cpi.render_task_index);
     TextRun text = fetch_text_run(cpi.specific_prim_index);
     Glyph glyph = fetch_glyph(cpi.sub_index);
-    PrimitiveGeometry pg = fetch_prim_geometry(cpi.global_prim_index);
+    PrimitiveGeometry pg = fetch_prim_geometry(cpi.geometry_address);
     ResourceRect res = fetch_resource_rect(cpi.user_data.x);
 
     // Glyphs size is already in device-pixels.
@@ -0,0 +1,28 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+uniform sampler2DArray sCacheA8;
+uniform float uLayer;
+
+varying vec2 vUv;
+
+// Maps 0..1 to black, blue, magenta, red, yellow and white, so that
+// coverage values close to each other can still be told apart.
+vec3 false_color(float value) {
+    vec3 ramp[6] = vec3[6](vec3(0.0, 0.0, 0.0),
+                           vec3(0.0, 0.0, 1.0),
+                           vec3(1.0, 0.0, 1.0),
+                           vec3(1.0, 0.0, 0.0),
+                           vec3(1.0, 1.0, 0.0),
+                           vec3(1.0, 1.0, 1.0));
+    float position = clamp(value, 0.0, 1.0) * 5.0;
+    int index = int(min(floor(position), 4.0));
+    return mix(ramp[index], ramp[index + 1], position - float(index));
+}
+
+void main(void)
+{
+    float value = texture(sCacheA8, vec3(vUv, uLayer)).r;
+    oFragColor = vec4(false_color(value), 1.0);
+}
@@ -0,0 +1,13 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+in vec4 aColorTexCoord;
+
+varying vec2 vUv;
+
+void main(void)
+{
+    vUv = aColorTexCoord.xy;
+    gl_Position = uTransform * vec4(aPosition, 1.0);
+}
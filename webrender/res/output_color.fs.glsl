@@ -0,0 +1,94 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+// The frame, drawn at the size of the framebuffer.
+uniform sampler2DArray sCacheRGBA8;
+
+// The sRGB transfer function, extended to negative values and values
+// above 1 by symmetry.
+vec3 srgb_to_linear(vec3 c) {
+    vec3 a = abs(c);
+    vec3 lo = a / 12.92;
+    vec3 hi = pow((a + 0.055) / 1.055, vec3(2.4));
+    return sign(c) * mix(lo, hi, step(vec3(0.04045), a));
+}
+
+#if defined(WR_FEATURE_COLOR_LUT)
+// A 3D table of uLutSize^3 entries, stored in sColor0 as one slice per
+// blue value, stacked from the top.
+uniform float uLutSize;
+
+vec3 apply_color_transform(vec3 rgb) {
+    float n = uLutSize;
+    vec3 p = clamp(rgb, 0.0, 1.0) * (n - 1.0);
+
+    // Each slice is filtered in red and green, blending between the
+    // two nearest slices does the same in blue.
+    float b0 = floor(p.b);
+    float b1 = min(b0 + 1.0, n - 1.0);
+    vec2 uv = (p.rg + 0.5) / vec2(n, n * n);
+    vec3 c0 = texture(sColor0, uv + vec2(0.0, b0 / n)).rgb;
+    vec3 c1 = texture(sColor0, uv + vec2(0.0, b1 / n)).rgb;
+
+    return mix(c0, c1, p.b - b0);
+}
+#elif defined(WR_FEATURE_HDR_OUTPUT)
+// Converts linear RGB in the color space of the frame to the primaries
+// of the output.
+uniform mat3 uOutputPrimaries;
+// The luminance of SDR white, which is 1.0 in the frame, in nits.
+uniform float uSdrWhiteLevel;
+// Luminance above the knee is compressed to fit below the maximum.
+uniform float uToneMapKnee;
+uniform float uMaxLuminance;
+
+vec3 tone_map(vec3 nits) {
+    float peak = max(max(nits.r, nits.g), nits.b);
+    if (peak <= uToneMapKnee) {
+        return nits;
+    }
+    float range = uMaxLuminance - uToneMapKnee;
+    float mapped = uToneMapKnee + range * (1.0 - exp((uToneMapKnee - peak) / range));
+    return nits * (mapped / peak);
+}
+
+#ifdef WR_FEATURE_HDR10
+// The SMPTE ST 2084 (PQ) transfer function, for up to 10000 nits.
+vec3 nits_to_pq(vec3 nits) {
+    vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(0.1593017578125));
+    return pow((0.8359375 + 18.8515625 * y) / (1.0 + 18.6875 * y), vec3(78.84375));
+}
+#endif
+
+vec3 apply_color_transform(vec3 rgb) {
+    vec3 nits = uOutputPrimaries * srgb_to_linear(rgb) * uSdrWhiteLevel;
+    nits = tone_map(nits);
+#ifdef WR_FEATURE_HDR10
+    return nits_to_pq(nits);
+#else
+    // scRGB, where 1.0 is 80 nits.
+    return nits / 80.0;
+#endif
+}
+#else
+// Converts linear sRGB to linear display RGB.
+uniform mat3 uColorMatrix;
+uniform float uOutputGamma;
+
+vec3 apply_color_transform(vec3 rgb) {
+    vec3 linear = clamp(uColorMatrix * srgb_to_linear(rgb), 0.0, 1.0);
+    return pow(linear, vec3(1.0 / uOutputGamma));
+}
+#endif
+
+void main(void)
+{
+    vec4 color = texelFetch(sCacheRGBA8, ivec3(gl_FragCoord.xy, 0), 0);
+
+    // The frame is premultiplied, the transform applies to the colors.
+    vec3 rgb = color.a > 0.0 ? color.rgb / color.a : vec3(0.0);
+    rgb = apply_color_transform(rgb);
+
+    oFragColor = vec4(rgb * color.a, color.a);
+}
@@ -43,20 +43,11 @@ varying vec3 vClipMaskUv;
 
 #define VECS_PER_LAYER             13
 #define VECS_PER_RENDER_TASK        3
-#define VECS_PER_PRIM_GEOM          2
 
-uniform sampler2D sLayers;
-uniform sampler2D sRenderTasks;
-uniform sampler2D sPrimGeometry;
-
-uniform sampler2D sData16;
-uniform sampler2D sData32;
-uniform sampler2D sData64;
-uniform sampler2D sData128;
-uniform sampler2D sResourceRects;
+uniform sampler2D sGpuCache;
 
 // Instanced attributes
-in int aGlobalPrimId;
+in int aGeometryAddress;
 in int aPrimitiveAddress;
 in int aTaskIndex;
 in int aClipTaskIndex;
@@ -71,20 +62,11 @@ in int aZIndex;
 // https://github.com/servo/servo/issues/13953
 #define get_fetch_uv(i, vpi)  ivec2(vpi * (i % (WR_MAX_VERTEX_TEXTURE_WIDTH/vpi)), i / (WR_MAX_VERTEX_TEXTURE_WIDTH/vpi))
 
-ivec2 get_fetch_uv_1(int index) {
-    return get_fetch_uv(index, 1);
-}
-
-ivec2 get_fetch_uv_2(int index) {
-    return get_fetch_uv(index, 2);
-}
-
-ivec2 get_fetch_uv_4(int index) {
-    return get_fetch_uv(index, 4);
-}
-
-ivec2 get_fetch_uv_8(int index) {
-    return get_fetch_uv(index, 8);
+// Items in the GPU cache are aligned to their size, so all the blocks
+// of an item are in the same row as its address.
+ivec2 get_gpu_cache_uv(int address) {
+    return ivec2(address % WR_MAX_VERTEX_TEXTURE_WIDTH,
+                 address / WR_MAX_VERTEX_TEXTURE_WIDTH);
 }
 
 struct RectWithSize {
@@ -137,6 +119,20 @@ struct Layer {
     vec4 screen_vertices[4];
 };
 
+struct RenderTaskData {
+    vec4 data0;
+    vec4 data1;
+    vec4 data2;
+};
+
+// WR_DATA_FETCH_BEGIN
+// The layers and render tasks are read from data textures here. build.rs
+// generates prim_shared_ubo, which reads them from uniform buffers, by
+// replacing everything up to WR_DATA_FETCH_END with ubo_data.glsl.
+
+uniform sampler2D sLayers;
+uniform sampler2D sRenderTasks;
+
 Layer fetch_layer(int index) {
     Layer layer;
 
@@ -169,12 +165,6 @@ Layer fetch_layer(int index) {
     return layer;
 }
 
-struct RenderTaskData {
-    vec4 data0;
-    vec4 data1;
-    vec4 data2;
-};
-
 RenderTaskData fetch_render_task(int index) {
     RenderTaskData task;
 
@@ -186,6 +176,7 @@ RenderTaskData fetch_render_task(int index) {
 
     return task;
 }
+// WR_DATA_FETCH_END
 
 struct AlphaBatchTask {
     vec2 screen_space_origin;
@@ -237,10 +228,10 @@ struct Gradient {
 Gradient fetch_gradient(int index) {
     Gradient gradient;
 
-    ivec2 uv = get_fetch_uv_2(index);
+    ivec2 uv = get_gpu_cache_uv(index);
 
-    gradient.start_end_point = texelFetchOffset(sData32, uv, 0, ivec2(0, 0));
-    gradient.extend_mode = texelFetchOffset(sData32, uv, 0, ivec2(1, 0));
+    gradient.start_end_point = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
+    gradient.extend_mode = texelFetchOffset(sGpuCache, uv, 0, ivec2(1, 0));
 
     return gradient;
 }
@@ -253,10 +244,10 @@ struct GradientStop {
 GradientStop fetch_gradient_stop(int index) {
     GradientStop stop;
 
-    ivec2 uv = get_fetch_uv_2(index);
+    ivec2 uv = get_gpu_cache_uv(index);
 
-    stop.color = texelFetchOffset(sData32, uv, 0, ivec2(0, 0));
-    stop.offset = texelFetchOffset(sData32, uv, 0, ivec2(1, 0));
+    stop.color = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
+    stop.offset = texelFetchOffset(sGpuCache, uv, 0, ivec2(1, 0));
 
     return stop;
 }
@@ -269,10 +260,10 @@ struct RadialGradient {
 RadialGradient fetch_radial_gradient(int index) {
     RadialGradient gradient;
 
-    ivec2 uv = get_fetch_uv_2(index);
+    ivec2 uv = get_gpu_cache_uv(index);
 
-    gradient.start_end_center = texelFetchOffset(sData32, uv, 0, ivec2(0, 0));
-    gradient.start_end_radius_ratio_xy_extend_mode = texelFetchOffset(sData32, uv, 0, ivec2(1, 0));
+    gradient.start_end_center = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
+    gradient.start_end_radius_ratio_xy_extend_mode = texelFetchOffset(sGpuCache, uv, 0, ivec2(1, 0));
 
     return gradient;
 }
@@ -284,17 +275,17 @@ struct Glyph {
 Glyph fetch_glyph(int index) {
     Glyph glyph;
 
-    ivec2 uv = get_fetch_uv_1(index);
+    ivec2 uv = get_gpu_cache_uv(index);
 
-    glyph.offset = texelFetchOffset(sData16, uv, 0, ivec2(0, 0));
+    glyph.offset = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
 
     return glyph;
 }
 
 RectWithSize fetch_instance_geometry(int index) {
-    ivec2 uv = get_fetch_uv_1(index);
+    ivec2 uv = get_gpu_cache_uv(index);
 
-    vec4 rect = texelFetchOffset(sData16, uv, 0, ivec2(0, 0));
+    vec4 rect = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
 
     return RectWithSize(rect.xy, rect.zw);
 }
@@ -307,18 +298,18 @@ struct PrimitiveGeometry {
 PrimitiveGeometry fetch_prim_geometry(int index) {
     PrimitiveGeometry pg;
 
-    ivec2 uv = get_fetch_uv(index, VECS_PER_PRIM_GEOM);
+    ivec2 uv = get_gpu_cache_uv(index);
 
-    vec4 local_rect = texelFetchOffset(sPrimGeometry, uv, 0, ivec2(0, 0));
+    vec4 local_rect = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
     pg.local_rect = RectWithSize(local_rect.xy, local_rect.zw);
-    vec4 local_clip_rect = texelFetchOffset(sPrimGeometry, uv, 0, ivec2(1, 0));
+    vec4 local_clip_rect = texelFetchOffset(sGpuCache, uv, 0, ivec2(1, 0));
     pg.local_clip_rect = RectWithSize(local_clip_rect.xy, local_clip_rect.zw);
 
     return pg;
 }
 
 struct PrimitiveInstance {
-    int global_prim_index;
+    int geometry_address;
     int specific_prim_index;
     int render_task_index;
     int clip_task_index;
@@ -331,7 +322,7 @@ struct PrimitiveInstance {
 PrimitiveInstance fetch_prim_instance() {
     PrimitiveInstance pi;
 
-    pi.global_prim_index = aGlobalPrimId;
+    pi.geometry_address = aGeometryAddress;
     pi.specific_prim_index = aPrimitiveAddress;
     pi.render_task_index = aTaskIndex;
     pi.clip_task_index = aClipTaskIndex;
@@ -344,7 +335,7 @@ PrimitiveInstance fetch_prim_instance() {
 }
 
 struct CachePrimitiveInstance {
-    int global_prim_index;
+    int geometry_address;
     int specific_prim_index;
     int render_task_index;
     int sub_index;
@@ -356,7 +347,7 @@ CachePrimitiveInstance fetch_cache_instance() {
 
     PrimitiveInstance pi = fetch_prim_instance();
 
-    cpi.global_prim_index = pi.global_prim_index;
+    cpi.geometry_address = pi.geometry_address;
     cpi.specific_prim_index = pi.specific_prim_index;
     cpi.render_task_index = pi.render_task_index;
     cpi.sub_index = pi.sub_index;
@@ -386,7 +377,7 @@ Primitive load_primitive_custom(PrimitiveInstance pi) {
     prim.clip_area = fetch_clip_area(pi.clip_task_index);
     prim.task = fetch_alpha_batch_task(pi.render_task_index);
 
-    PrimitiveGeometry pg = fetch_prim_geometry(pi.global_prim_index);
+    PrimitiveGeometry pg = fetch_prim_geometry(pi.geometry_address);
     prim.local_rect = pg.local_rect;
     prim.local_clip_rect = pg.local_clip_rect;
 
@@ -613,9 +604,9 @@ struct ResourceRect {
 ResourceRect fetch_resource_rect(int index) {
     ResourceRect rect;
 
-    ivec2 uv = get_fetch_uv_1(index);
+    ivec2 uv = get_gpu_cache_uv(index);
 
-    rect.uv_rect = texelFetchOffset(sResourceRects, uv, 0, ivec2(0, 0));
+    rect.uv_rect = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
 
     return rect;
 }
@@ -627,9 +618,9 @@ struct Rectangle {
 Rectangle fetch_rectangle(int index) {
     Rectangle rect;
 
-    ivec2 uv = get_fetch_uv_1(index);
+    ivec2 uv = get_gpu_cache_uv(index);
 
-    rect.color = texelFetchOffset(sData16, uv, 0, ivec2(0, 0));
+    rect.color = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
 
     return rect;
 }
@@ -641,9 +632,9 @@ struct TextRun {
 TextRun fetch_text_run(int index) {
     TextRun text;
 
-    ivec2 uv = get_fetch_uv_1(index);
+    ivec2 uv = get_gpu_cache_uv(index);
 
-    text.color = texelFetchOffset(sData16, uv, 0, ivec2(0, 0));
+    text.color = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
 
     return text;
 }
@@ -651,14 +642,18 @@ TextRun fetch_text_run(int index) {
 struct Image {
     vec4 stretch_size_and_tile_spacing;  // Size of the actual image and amount of space between
                                          //     tiled instances of this image.
+    vec4 color;                          // Color of A8 images.
 };
 
 Image fetch_image(int index) {
     Image image;
 
-    ivec2 uv = get_fetch_uv_1(index);
+    ivec2 uv = get_gpu_cache_uv(index);
+    image.stretch_size_and_tile_spacing = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
 
-    image.stretch_size_and_tile_spacing = texelFetchOffset(sData16, uv, 0, ivec2(0, 0));
+    // The color is stored in the next item, which may start a new row.
+    uv = get_gpu_cache_uv(index + 1);
+    image.color = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
 
     return image;
 }
@@ -666,6 +661,7 @@ Image fetch_image(int index) {
 // YUV color spaces
 #define YUV_REC601 1
 #define YUV_REC709 2
+#define YUV_REC2020 3
 
 struct YuvImage {
     vec4 y_st_rect;
@@ -678,9 +674,9 @@ struct YuvImage {
 YuvImage fetch_yuv_image(int index) {
     YuvImage image;
 
-    ivec2 uv = get_fetch_uv_1(index);
+    ivec2 uv = get_gpu_cache_uv(index);
 
-    vec4 size_color_space = texelFetchOffset(sData16, uv, 0, ivec2(0, 0));
+    vec4 size_color_space = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
     image.size = size_color_space.xy;
     image.color_space = int(size_color_space.z);
 
@@ -697,12 +693,12 @@ struct BoxShadow {
 BoxShadow fetch_boxshadow(int index) {
     BoxShadow bs;
 
-    ivec2 uv = get_fetch_uv_4(index);
+    ivec2 uv = get_gpu_cache_uv(index);
 
-    bs.src_rect = texelFetchOffset(sData64, uv, 0, ivec2(0, 0));
-    bs.bs_rect = texelFetchOffset(sData64, uv, 0, ivec2(1, 0));
-    bs.color = texelFetchOffset(sData64, uv, 0, ivec2(2, 0));
-    bs.border_radius_edge_size_blur_radius_inverted = texelFetchOffset(sData64, uv, 0, ivec2(3, 0));
+    bs.src_rect = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
+    bs.bs_rect = texelFetchOffset(sGpuCache, uv, 0, ivec2(1, 0));
+    bs.color = texelFetchOffset(sGpuCache, uv, 0, ivec2(2, 0));
+    bs.border_radius_edge_size_blur_radius_inverted = texelFetchOffset(sGpuCache, uv, 0, ivec2(3, 0));
 
     return bs;
 }
@@ -721,10 +717,60 @@ float signed_distance_rect(vec2 pos, vec2 p0, vec2 p1) {
     return length(max(vec2(0.0), d)) + min(0.0, max(d.x, d.y));
 }
 
+// The signed distance from pos to the ellipse with the given radii around
+// center, negative inside, along with the normal of the ellipse there. This
+// first order approximation is exact for circles, and accurate near the
+// edge of other ellipses, which is all that anti-aliasing needs.
+float signed_distance_ellipse(vec2 pos, vec2 center, vec2 radii, out vec2 normal) {
+    vec2 offset = pos - center;
+    vec2 inv_radii_sq = 1.0 / (radii * radii);
+    vec2 gradient = 2.0 * offset * inv_radii_sq;
+    float gradient_length = length(gradient);
+    if (gradient_length == 0.0) {
+        normal = vec2(0.0);
+        return -min(radii.x, radii.y);
+    }
+    normal = gradient / gradient_length;
+    if (radii.x == radii.y) {
+        return length(offset) - radii.x;
+    }
+    return (dot(offset * offset, inv_radii_sq) - 1.0) / gradient_length;
+}
+
+// The part of a pixel-wide box filter centered on the fragment that lies
+// inside an edge, given the signed distance to the edge in local units and
+// the screen-space derivatives of the local position. The distance is
+// converted to device pixels along the normal of the edge, so edges stay
+// sharp under transforms that scale the axes differently. The derivatives
+// are taken by the caller, outside of any branch that varies per fragment.
+float edge_coverage(float signed_distance, vec2 normal, vec2 pos_dx, vec2 pos_dy) {
+    float units_per_pixel = length(vec2(dot(pos_dx, normal), dot(pos_dy, normal)));
+    return clamp(0.5 - signed_distance / max(units_per_pixel, 1.0e-6), 0.0, 1.0);
+}
+
 vec2 init_transform_fs(vec3 local_pos, vec4 local_rect, out float fragment_alpha) {
     fragment_alpha = 1.0;
     vec2 pos = local_pos.xy / local_pos.z;
 
+#ifdef WR_FEATURE_HIGH_QUALITY_EDGE_AA
+    // Measure the distance to each edge in device pixels rather than local
+    // units. The screen-space gradient of each local coordinate tells how
+    // many local units a single pixel step covers along it, which stays
+    // correct under any scale and at glancing rotations, where the
+    // approximation below either blurs or aliases the edges.
+    vec4 d_local = vec4(local_rect.xy - pos,
+                        pos - (local_rect.xy + local_rect.zw));
+    vec2 grad_x = vec2(dFdx(pos.x), dFdy(pos.x));
+    vec2 grad_y = vec2(dFdx(pos.y), dFdy(pos.y));
+    vec2 units_per_pixel = max(vec2(length(grad_x), length(grad_y)), vec2(1.0e-6));
+    vec4 d = d_local / units_per_pixel.xyxy;
+
+    // Each edge covers the half of a pixel-wide box filter centered on the
+    // fragment that lies inside it. Pixel centers exactly half a pixel away
+    // from an edge are therefore either fully in or fully out.
+    vec4 coverage = clamp(0.5 - d, vec4(0.0), vec4(1.0));
+    fragment_alpha = coverage.x * coverage.y * coverage.z * coverage.w;
+#else
     // Because the local rect is placed on whole coordinates, but the interpolation
     // occurs at pixel centers, we need to offset the signed distance by that amount.
     // In the simple case of no zoom, and no transform, this is 0.5. However, we
@@ -745,6 +791,7 @@ vec2 init_transform_fs(vec3 local_pos, vec4 local_rect, out float fragment_alpha
 
     // Only apply AA to fragments outside the signed distance field.
     fragment_alpha = 1.0 - smoothstep(0.0, afwidth, d);
+#endif
 
     return pos;
 }
@@ -760,6 +807,7 @@ float do_clip() {
 }
 
 vec4 dither(vec4 color) {
+#ifdef WR_FEATURE_DITHERING
     const int matrix_mask = 7;
 
     ivec2 pos = ivec2(gl_FragCoord.xy) & ivec2(matrix_mask);
@@ -767,5 +815,8 @@ vec4 dither(vec4 color) {
     float noise = (noise_normalized - 0.5) / 256.0; // scale down to the unit length
 
     return color + vec4(noise, noise, noise, 0);
+#else
+    return color;
+#endif
 }
 #endif //WR_FRAGMENT_SHADER
@@ -8,13 +8,19 @@ void main(void) {
     vec2 texture_size = vec2(textureSize(sGradients, 0));
 
     // Either saturate or modulo the offset depending on repeat mode, then scale to number of
-    // gradient color entries (texture width / 2).
-    float x = mix(clamp(vOffset, 0.0, 1.0), fract(vOffset), vGradientRepeat) * 0.5 * texture_size.x;
+    // gradient color entries (texture width / 2 for each table).
+    float entry_count = 0.5 * texture_size.x;
+    float x = mix(clamp(vOffset, 0.0, 1.0), fract(vOffset), vGradientRepeat) *
+              vGradientTableCount * entry_count;
+
+    // Find the table the entry is in, keeping the end of the gradient in the last one.
+    float table = min(floor(x / entry_count), vGradientTableCount - 1.0);
+    x -= table * entry_count;
 
     x = 2.0 * floor(x) + 0.5 + fract(x);
 
     // Use linear filtering to mix in the low bits (vGradientIndex + 1) with the high
     // bits (vGradientIndex)
-    float y = vGradientIndex * 2.0 + 0.5 + 1.0 / 256.0;
+    float y = (vGradientIndex + table) * 2.0 + 0.5 + 1.0 / 256.0;
     oFragColor = dither(texture(sGradients, vec2(x, y) / texture_size));
 }
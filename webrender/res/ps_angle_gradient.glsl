@@ -3,5 +3,6 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 flat varying float vGradientIndex;
+flat varying float vGradientTableCount;
 flat varying float vGradientRepeat;
 varying float vOffset;
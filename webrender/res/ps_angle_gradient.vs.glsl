@@ -28,6 +28,7 @@ void main(void) {
 
     // V coordinate of gradient row in lookup texture.
     vGradientIndex = float(prim.sub_index);
+    vGradientTableCount = float(prim.user_data.x);
 
     // Whether to repeat the gradient instead of clamping.
     vGradientRepeat = float(int(gradient.extend_mode.x) == EXTEND_MODE_REPEAT);
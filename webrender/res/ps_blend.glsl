@@ -3,5 +3,5 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 varying vec3 vUv;
-flat varying float vAmount;
-flat varying int vOp;
+flat varying mat4 vColorMat;
+flat varying vec4 vColorOffset;
@@ -3,6 +3,100 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+#define FILTER_BLUR         0
+#define FILTER_CONTRAST     1
+#define FILTER_GRAYSCALE    2
+#define FILTER_HUE_ROTATE   3
+#define FILTER_INVERT       4
+#define FILTER_SATURATE     5
+#define FILTER_SEPIA        6
+#define FILTER_BRIGHTNESS   7
+#define FILTER_OPACITY      8
+
+// Sets up the color matrix and offset of a filter, as given by the
+// Filter Effects spec. Each column of the matrix holds a row of the
+// spec, so the fragment shader multiplies the color on the left.
+void set_filter_params(int op, float amount) {
+    vColorMat = mat4(1.0);
+    vColorOffset = vec4(0.0);
+
+    switch (op) {
+        case FILTER_CONTRAST:
+            vColorMat = mat4(amount, 0.0, 0.0, 0.0,
+                             0.0, amount, 0.0, 0.0,
+                             0.0, 0.0, amount, 0.0,
+                             0.0, 0.0, 0.0, 1.0);
+            vColorOffset = vec4(vec3(0.5 - 0.5 * amount), 0.0);
+            break;
+        case FILTER_GRAYSCALE: {
+            float ia = 1.0 - min(amount, 1.0);
+            vColorMat = mat4(0.2126 + 0.7874 * ia, 0.7152 - 0.7152 * ia, 0.0722 - 0.0722 * ia, 0.0,
+                             0.2126 - 0.2126 * ia, 0.7152 + 0.2848 * ia, 0.0722 - 0.0722 * ia, 0.0,
+                             0.2126 - 0.2126 * ia, 0.7152 - 0.7152 * ia, 0.0722 + 0.9278 * ia, 0.0,
+                             0.0, 0.0, 0.0, 1.0);
+            break;
+        }
+        case FILTER_HUE_ROTATE: {
+            float c = cos(amount);
+            float s = sin(amount);
+            vColorMat = mat4(0.213 + 0.787 * c - 0.213 * s,
+                             0.715 - 0.715 * c - 0.715 * s,
+                             0.072 - 0.072 * c + 0.928 * s,
+                             0.0,
+                             0.213 - 0.213 * c + 0.143 * s,
+                             0.715 + 0.285 * c + 0.140 * s,
+                             0.072 - 0.072 * c - 0.283 * s,
+                             0.0,
+                             0.213 - 0.213 * c - 0.787 * s,
+                             0.715 - 0.715 * c + 0.715 * s,
+                             0.072 + 0.928 * c + 0.072 * s,
+                             0.0,
+                             0.0, 0.0, 0.0, 1.0);
+            break;
+        }
+        case FILTER_INVERT: {
+            float a = min(amount, 1.0);
+            vColorMat = mat4(1.0 - 2.0 * a, 0.0, 0.0, 0.0,
+                             0.0, 1.0 - 2.0 * a, 0.0, 0.0,
+                             0.0, 0.0, 1.0 - 2.0 * a, 0.0,
+                             0.0, 0.0, 0.0, 1.0);
+            vColorOffset = vec4(vec3(a), 0.0);
+            break;
+        }
+        case FILTER_SATURATE:
+            vColorMat = mat4(0.213 + 0.787 * amount, 0.715 - 0.715 * amount, 0.072 - 0.072 * amount, 0.0,
+                             0.213 - 0.213 * amount, 0.715 + 0.285 * amount, 0.072 - 0.072 * amount, 0.0,
+                             0.213 - 0.213 * amount, 0.715 - 0.715 * amount, 0.072 + 0.928 * amount, 0.0,
+                             0.0, 0.0, 0.0, 1.0);
+            break;
+        case FILTER_SEPIA: {
+            float ia = 1.0 - min(amount, 1.0);
+            vColorMat = mat4(0.393 + 0.607 * ia, 0.769 - 0.769 * ia, 0.189 - 0.189 * ia, 0.0,
+                             0.349 - 0.349 * ia, 0.686 + 0.314 * ia, 0.168 - 0.168 * ia, 0.0,
+                             0.272 - 0.272 * ia, 0.534 - 0.534 * ia, 0.131 + 0.869 * ia, 0.0,
+                             0.0, 0.0, 0.0, 1.0);
+            break;
+        }
+        case FILTER_BRIGHTNESS:
+            vColorMat = mat4(amount, 0.0, 0.0, 0.0,
+                             0.0, amount, 0.0, 0.0,
+                             0.0, 0.0, amount, 0.0,
+                             0.0, 0.0, 0.0, 1.0);
+            break;
+        case FILTER_OPACITY:
+            vColorMat = mat4(1.0, 0.0, 0.0, 0.0,
+                             0.0, 1.0, 0.0, 0.0,
+                             0.0, 0.0, 1.0, 0.0,
+                             0.0, 0.0, 0.0, amount);
+            break;
+        case FILTER_BLUR:
+        default:
+            // TODO: Blurs aren't supported yet, so the pass only copies
+            // its input.
+            break;
+    }
+}
+
 void main(void) {
     PrimitiveInstance pi = fetch_prim_instance();
     AlphaBatchTask dest_task = fetch_alpha_batch_task(pi.render_task_index);
@@ -21,8 +115,7 @@ void main(void) {
     vec2 st1 = (src_task.render_target_origin + src_task.size) / texture_size;
     vUv = vec3(mix(st0, st1, aPosition.xy), src_task.render_target_layer_index);
 
-    vOp = pi.sub_index;
-    vAmount = float(pi.user_data.y) / 65535.0;
+    set_filter_params(pi.sub_index, float(pi.user_data.y) / 65535.0);
 
     gl_Position = uTransform * vec4(local_pos, pi.z, 1.0);
 }
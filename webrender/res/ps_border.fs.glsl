@@ -56,57 +56,27 @@ float alpha_for_solid_border(float distance_from_ref,
   return 1.0 - smoothstep(0.0, 1.0, distance_from_border);
 }
 
-float alpha_for_solid_ellipse_border(vec2 local_pos,
-                                     vec2 inner_radius,
-                                     vec2 outer_radius,
-                                     float pixels_per_fragment) {
-  vec2 distance_from_ref = local_pos - vRefPoint;
-
-  float nudge = pixels_per_fragment;
-  inner_radius += nudge;
-  outer_radius -= nudge;
-
-  float inner_ellipse = distance_from_ref.x * distance_from_ref.x / inner_radius.x / inner_radius.x +
-                        distance_from_ref.y * distance_from_ref.y / inner_radius.y / inner_radius.y;
-  float outer_ellipse = distance_from_ref.x * distance_from_ref.x / outer_radius.x / outer_radius.x +
-                        distance_from_ref.y * distance_from_ref.y / outer_radius.y / outer_radius.y;
-  if (inner_ellipse > 1.0 && outer_ellipse < 1.0) {
-      return 1.0;
-  }
-
-  vec2 offset = step(inner_radius.yx, inner_radius.xy) *
-                (sqrt(abs(inner_radius.x * inner_radius.x - inner_radius.y * inner_radius.y)));
-  vec2 focus1 = vRefPoint + offset;
-  vec2 focus2 = vRefPoint - offset;
-
-  float inner_distance_from_border = max(inner_radius.x, inner_radius.y) -
-                                     (distance(focus1, local_pos) + distance(focus2, local_pos)) / 2.0;
-
-  offset = step(outer_radius.yx, outer_radius.xy) *
-           (sqrt(abs(outer_radius.x * outer_radius.x - outer_radius.y * outer_radius.y)));
-  focus1 = vRefPoint + offset;
-  focus2 = vRefPoint - offset;
-  float outer_distance_from_border = (distance(focus1, local_pos) + distance(focus2, local_pos)) / 2.0 -
-                                     max(outer_radius.x, outer_radius.y);
-
-  float distance_from_border = max(inner_distance_from_border, outer_distance_from_border);
-
-  // Move the distance back into pixels.
-  distance_from_border /= pixels_per_fragment;
-
-  return 1.0 - smoothstep(0.0, 1.0, distance_from_border);
-}
-
+// The coverage of a fragment by the band between the inner and outer ellipses
+// of a rounded corner. Each edge is anti-aliased on its own, with a box filter,
+// so thin borders keep their weight and the edges stay where they are.
 float alpha_for_solid_border_corner(vec2 local_pos,
                                     vec2 inner_radius,
-                                    vec2 outer_radius,
-                                    float pixels_per_fragment) {
-  if (inner_radius.x == inner_radius.y && outer_radius.x == outer_radius.y) {
-    float distance_from_ref = distance(vRefPoint, local_pos);
-    return alpha_for_solid_border(distance_from_ref, inner_radius.x, outer_radius.x, pixels_per_fragment);
-  } else {
-    return alpha_for_solid_ellipse_border(local_pos, inner_radius, outer_radius, pixels_per_fragment);
+                                    vec2 outer_radius) {
+  vec2 pos_dx = dFdx(local_pos);
+  vec2 pos_dy = dFdy(local_pos);
+
+  vec2 normal;
+  float outer_distance = signed_distance_ellipse(local_pos, vRefPoint, outer_radius, normal);
+  float alpha = edge_coverage(outer_distance, normal, pos_dx, pos_dy);
+
+  // Without an inner radius, the inner edge is the square corner of the
+  // padding box, which the corner piece doesn't reach.
+  if (inner_radius.x > 0.0 && inner_radius.y > 0.0) {
+    float inner_distance = signed_distance_ellipse(local_pos, vRefPoint, inner_radius, normal);
+    alpha -= edge_coverage(inner_distance, normal, pos_dx, pos_dy);
   }
+
+  return max(alpha, 0.0);
 }
 
 vec4 draw_dotted_edge(vec2 local_pos, vec4 piece_rect, float pixels_per_fragment) {
@@ -190,8 +160,7 @@ void draw_dashed_or_dotted_border(vec2 local_pos, float distance_from_mix_line)
       if (vRadii.x > 0.0) {
         oFragColor *= vec4(1.0, 1.0, 1.0, alpha_for_solid_border_corner(local_pos,
                                                                   vRadii.zw,
-                                                                  vRadii.xy,
-                                                                  pixels_per_fragment));
+                                                                  vRadii.xy));
       }
 
       break;
@@ -268,14 +237,12 @@ vec4 draw_double_edge_corner_with_radius(vec2 local_pos,
   float alpha = alpha_for_solid_border_corner(local_pos,
                                               vec2(vRadii.x - one_third_width,
                                                    vRadii.y - one_third_height),
-                                              vec2(vRadii.x, vRadii.y),
-                                              pixels_per_fragment);
+                                              vec2(vRadii.x, vRadii.y));
 
   // Contribution of the inner border segment.
   alpha += alpha_for_solid_border_corner(local_pos,
                                          vec2(vRadii.z, vRadii.w),
-                                         vec2(vRadii.z + one_third_width, vRadii.w + one_third_height),
-                                         pixels_per_fragment);
+                                         vec2(vRadii.z + one_third_width, vRadii.w + one_third_height));
   return get_fragment_color(distance_from_mix_line, pixels_per_fragment) * vec4(1.0, 1.0, 1.0, alpha);
 }
 
@@ -339,7 +306,7 @@ void draw_solid_border(float distanceFromMixLine, vec2 localPos) {
       oFragColor = get_fragment_color(distanceFromMixLine, pixelsPerFragment);
 
       if (vRadii.x > 0.0) {
-        float alpha = alpha_for_solid_border_corner(localPos, vRadii.zw, vRadii.xy, pixelsPerFragment);
+        float alpha = alpha_for_solid_border_corner(localPos, vRadii.zw, vRadii.xy);
         oFragColor *= vec4(1.0, 1.0, 1.0, alpha);
       }
 
@@ -352,9 +319,20 @@ void draw_solid_border(float distanceFromMixLine, vec2 localPos) {
   }
 }
 
+// The shade of a half of a groove or ridge border. The dark half is a
+// darker version of the border color and the light half is the color
+// itself, except for black, which is shaded with grays as for inset and
+// outset borders.
+vec4 shade_mixed_edge(vec4 color, float modulator) {
+  if (all(equal(color.rgb, vec3(0.0)))) {
+    return vec4(vec3(modulator < 1.0 ? 0.3 : 0.7), color.a);
+  }
+  return vec4(color.rgb * modulator, color.a);
+}
+
 vec4 draw_mixed_edge(float distance, float border_len, vec4 color, vec2 brightness_mod) {
   float modulator = distance / border_len > 0.5 ? brightness_mod.x : brightness_mod.y;
-  return vec4(color.xyz * modulator, color.a);
+  return shade_mixed_edge(color, modulator);
 }
 
 void draw_mixed_border(float distanceFromMixLine, float distanceFromMiddle, vec2 localPos, vec2 brightness_mod) {
@@ -368,14 +346,20 @@ void draw_mixed_border(float distanceFromMixLine, float distanceFromMiddle, vec2
       vec4 color = get_fragment_color(distanceFromMixLine, pixelsPerFragment);
 
       if (vRadii.x > 0.0) {
-        float distance = distance(vRefPoint, localPos) - vRadii.z;
-        float length = vRadii.x - vRadii.z;
+        // The halves meet on the ellipse halfway between the inner and
+        // outer ones.
+        vec2 normal;
+        float distance = signed_distance_ellipse(localPos,
+                                                 vRefPoint,
+                                                 0.5 * (vRadii.xy + vRadii.zw),
+                                                 normal);
         if (distanceFromMiddle < 0.0) {
-          distance = length - distance;
+          distance = -distance;
         }
 
-        oFragColor = 0.0 <= distance && distance <= length ?
-          draw_mixed_edge(distance, length, color, brightness_mod) : vec4(0.0, 0.0, 0.0, 0.0);
+        float modulator = distance > 0.0 ? brightness_mod.x : brightness_mod.y;
+        float alpha = alpha_for_solid_border_corner(localPos, vRadii.zw, vRadii.xy);
+        oFragColor = shade_mixed_edge(color, modulator) * vec4(1.0, 1.0, 1.0, alpha);
         break;
       }
 
@@ -427,7 +411,7 @@ void main(void) {
     float distance_from_middle = vDistanceFromMiddle;
 #endif
 
-    vec2 brightness_mod = vec2(0.7, 1.3);
+    vec2 brightness_mod = vec2(0.7, 1.0);
     bool needs_discard = false;
 
     // Note: we can't pass-through in the following cases,
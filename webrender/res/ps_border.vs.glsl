@@ -13,16 +13,16 @@ struct Border {
 Border fetch_border(int index) {
     Border border;
 
-    ivec2 uv = get_fetch_uv_8(index);
-
-    border.style = texelFetchOffset(sData128, uv, 0, ivec2(0, 0));
-    border.widths = texelFetchOffset(sData128, uv, 0, ivec2(1, 0));
-    border.colors[0] = texelFetchOffset(sData128, uv, 0, ivec2(2, 0));
-    border.colors[1] = texelFetchOffset(sData128, uv, 0, ivec2(3, 0));
-    border.colors[2] = texelFetchOffset(sData128, uv, 0, ivec2(4, 0));
-    border.colors[3] = texelFetchOffset(sData128, uv, 0, ivec2(5, 0));
-    border.radii[0] = texelFetchOffset(sData128, uv, 0, ivec2(6, 0));
-    border.radii[1] = texelFetchOffset(sData128, uv, 0, ivec2(7, 0));
+    ivec2 uv = get_gpu_cache_uv(index);
+
+    border.style = texelFetchOffset(sGpuCache, uv, 0, ivec2(0, 0));
+    border.widths = texelFetchOffset(sGpuCache, uv, 0, ivec2(1, 0));
+    border.colors[0] = texelFetchOffset(sGpuCache, uv, 0, ivec2(2, 0));
+    border.colors[1] = texelFetchOffset(sGpuCache, uv, 0, ivec2(3, 0));
+    border.colors[2] = texelFetchOffset(sGpuCache, uv, 0, ivec2(4, 0));
+    border.colors[3] = texelFetchOffset(sGpuCache, uv, 0, ivec2(5, 0));
+    border.radii[0] = texelFetchOffset(sGpuCache, uv, 0, ivec2(6, 0));
+    border.radii[1] = texelFetchOffset(sGpuCache, uv, 0, ivec2(7, 0));
 
     return border;
 }
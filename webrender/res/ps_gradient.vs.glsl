@@ -8,7 +8,7 @@ void main(void) {
     Gradient gradient = fetch_gradient(prim.prim_index);
 
     GradientStop g0 = fetch_gradient_stop(prim.sub_index + 0);
-    GradientStop g1 = fetch_gradient_stop(prim.sub_index + 1);
+    GradientStop g1 = fetch_gradient_stop(prim.sub_index + 2);
 
     RectWithSize segment_rect;
     vec2 axis;
@@ -32,7 +32,13 @@ void main(void) {
 #ifdef WR_FEATURE_TEXTURE_RECT
     // textureLod doesn't support sampler2DRect. Use texture() instead.
     oFragColor = vec4(alpha) * texture(sColor0, st);
+#else
+#ifdef WR_FEATURE_ALPHA_MASK
+    // A8 textures are single channel, or expanded to all four channels
+    // where single channel formats aren't used, so red holds the coverage.
+    oFragColor = vColor * alpha * textureLod(sColor0, st, 0.0).r;
 #else
     oFragColor = vec4(alpha) * textureLod(sColor0, st, 0.0);
 #endif
+#endif
 }
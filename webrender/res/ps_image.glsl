@@ -10,6 +10,10 @@ flat varying vec2 vTextureSize;   // Size of the image in the texture atlas.
 flat varying vec2 vTileSpacing;   // Amount of space between tiled instances of this image.
 flat varying vec4 vStRect;        // Rectangle of valid texture rect.
 
+#ifdef WR_FEATURE_ALPHA_MASK
+flat varying vec4 vColor;         // Premultiplied color A8 images are drawn in.
+#endif
+
 #ifdef WR_FEATURE_TRANSFORM
 varying vec3 vLocalPos;
 flat varying vec4 vLocalRect;
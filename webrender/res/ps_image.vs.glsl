@@ -51,4 +51,8 @@ void main(void) {
     // in order to avoid sampling outside of the texture area.
     vec2 half_texel = vec2(0.5) / texture_size_normalization_factor;
     vStRect = vec4(min(st0, st1) + half_texel, max(st0, st1) - half_texel);
+
+#ifdef WR_FEATURE_ALPHA_MASK
+    vColor = vec4(image.color.rgb * image.color.a, image.color.a);
+#endif
 }
@@ -3,6 +3,7 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 flat varying float vGradientIndex;
+flat varying float vGradientTableCount;
 flat varying float vGradientRepeat;
 flat varying vec2 vStartCenter;
 flat varying vec2 vEndCenter;
@@ -35,6 +35,7 @@ void main(void) {
 
     // V coordinate of gradient row in lookup texture.
     vGradientIndex = float(prim.sub_index);
+    vGradientTableCount = float(prim.user_data.x);
 
     // Whether to repeat the gradient instead of clamping.
     vGradientRepeat = float(int(gradient.start_end_radius_ratio_xy_extend_mode.w) == EXTEND_MODE_REPEAT);
@@ -30,9 +30,16 @@ void main(void) {
     vec2 st_u = vTextureOffsetU + uv_offset;
     vec2 st_v = vTextureOffsetV + uv_offset;
 
+#ifdef WR_FEATURE_TEXTURE_RECT
+    // textureLod doesn't support sampler2DRect. Use texture() instead.
+    float y = texture(sColor0, st_y).r;
+    float u = texture(sColor1, st_u).r;
+    float v = texture(sColor2, st_v).r;
+#else
     float y = textureLod(sColor0, st_y, 0.0).r;
     float u = textureLod(sColor1, st_u, 0.0).r;
     float v = textureLod(sColor2, st_v, 0.0).r;
+#endif
 
     // See the vertex shader for an explanation of where the constants come from.
     vec3 rgb = vYuvColorMatrix * vec3(y - 0.06275, u - 0.50196, v - 0.50196);
@@ -27,14 +27,24 @@ void main(void) {
     ResourceRect u_rect = fetch_resource_rect(prim.user_data.x + 1);
     ResourceRect v_rect = fetch_resource_rect(prim.user_data.x + 2);
 
+    // If this is in WR_FEATURE_TEXTURE_RECT mode, the rects and sizes use
+    // non-normalized texture coordinates.
+#ifdef WR_FEATURE_TEXTURE_RECT
+    vec2 y_texture_size = vec2(1, 1);
+#else
     vec2 y_texture_size = vec2(textureSize(sColor0, 0));
+#endif
     vec2 y_st0 = y_rect.uv_rect.xy / y_texture_size;
     vec2 y_st1 = y_rect.uv_rect.zw / y_texture_size;
 
     vTextureSizeY = y_st1 - y_st0;
     vTextureOffsetY = y_st0;
 
+#ifdef WR_FEATURE_TEXTURE_RECT
+    vec2 uv_texture_size = vec2(1, 1);
+#else
     vec2 uv_texture_size = vec2(textureSize(sColor1, 0));
+#endif
     vec2 u_st0 = u_rect.uv_rect.xy / uv_texture_size;
     vec2 u_st1 = u_rect.uv_rect.zw / uv_texture_size;
 
@@ -64,6 +74,18 @@ void main(void) {
             1.16438, -0.39176, -0.81297,
             1.16438,  2.01723,  0.0
         );
+    } else if (image.color_space == YUV_REC2020) {
+        // From Rec2020 (non-constant luminance):
+        // [R]   [1.1643835616438356,  0.0,                  1.6786741071428571]   [Y -  16]
+        // [G] = [1.1643835616438358, -0.18732610421817189, -0.6504243535437060] x [U - 128]
+        // [B]   [1.1643835616438356,  2.1417723214285714,   0.0               ]   [V - 128]
+        //
+        // For the range [0,1] instead of [0,255]:
+        vYuvColorMatrix = mat3(
+            1.16438,  0.0,      1.67867,
+            1.16438, -0.18733, -0.65042,
+            1.16438,  2.14177,  0.0
+        );
     } else { // if (image.color_space == YUV_REC709)
         // From Rec709:
         // [R]   [1.1643835616438356,  4.2781193979771426e-17, 1.7927410714285714]   [Y -  16]
@@ -0,0 +1,54 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+// The layers and render tasks are read from uniform buffers here, which
+// is faster than texel fetches on some mobile GPUs. build.rs splices this
+// into prim_shared in place of the data texture fetches. The renderer
+// only uses these shaders when the frame's data fits in the buffers.
+
+layout(std140) uniform Layers {
+    vec4 uLayers[WR_MAX_UBO_LAYERS * VECS_PER_LAYER];
+};
+
+layout(std140) uniform RenderTasks {
+    vec4 uRenderTasks[WR_MAX_UBO_RENDER_TASKS * VECS_PER_RENDER_TASK];
+};
+
+Layer fetch_layer(int index) {
+    Layer layer;
+
+    int base = index * VECS_PER_LAYER;
+
+    layer.transform[0] = uLayers[base + 0];
+    layer.transform[1] = uLayers[base + 1];
+    layer.transform[2] = uLayers[base + 2];
+    layer.transform[3] = uLayers[base + 3];
+
+    layer.inv_transform[0] = uLayers[base + 4];
+    layer.inv_transform[1] = uLayers[base + 5];
+    layer.inv_transform[2] = uLayers[base + 6];
+    layer.inv_transform[3] = uLayers[base + 7];
+
+    vec4 clip_rect = uLayers[base + 8];
+    layer.local_clip_rect = RectWithSize(clip_rect.xy, clip_rect.zw);
+
+    layer.screen_vertices[0] = uLayers[base + 9];
+    layer.screen_vertices[1] = uLayers[base + 10];
+    layer.screen_vertices[2] = uLayers[base + 11];
+    layer.screen_vertices[3] = uLayers[base + 12];
+
+    return layer;
+}
+
+RenderTaskData fetch_render_task(int index) {
+    RenderTaskData task;
+
+    int base = index * VECS_PER_RENDER_TASK;
+
+    task.data0 = uRenderTasks[base + 0];
+    task.data1 = uRenderTasks[base + 1];
+    task.data2 = uRenderTasks[base + 2];
+
+    return task;
+}
@@ -14,6 +14,10 @@ pub trait BorderSideHelpers {
                     scale_factor_1: f32,
                     black_color_0: f32,
                     black_color_1: f32) -> ColorF;
+
+    /// The side as drawn with the given width. Double borders need room for
+    /// two lines and the gap between them, so thinner ones are drawn solid.
+    fn for_width(&self, width: f32) -> BorderSide;
 }
 
 impl BorderSideHelpers for BorderSide {
@@ -40,4 +44,16 @@ impl BorderSideHelpers for BorderSide {
             _ => self.color,
         }
     }
+
+    fn for_width(&self, width: f32) -> BorderSide {
+        match self.style {
+            BorderStyle::Double if width < 3.0 => {
+                BorderSide {
+                    color: self.color,
+                    style: BorderStyle::Solid,
+                }
+            }
+            _ => *self,
+        }
+    }
 }
@@ -4,15 +4,16 @@
 
 use euclid::Point3D;
 use geometry::ray_intersects_rect;
+use gpu_cache::GpuCache;
 use mask_cache::{ClipSource, MaskCacheInfo, RegionMode};
-use prim_store::GpuBlock32;
-use renderer::VertexDataStore;
 use spring::{DAMPING, STIFFNESS, Spring};
 use tiling::PackedLayerIndex;
-use util::TransformedRect;
+use time::precise_time_ns;
+use util::{TransformedRect, lerp};
 use webrender_traits::{ClipRegion, LayerPixel, LayerPoint, LayerRect, LayerSize};
-use webrender_traits::{LayerToScrollTransform, LayerToWorldTransform, PipelineId};
-use webrender_traits::{ScrollEventPhase, ScrollLayerId, ScrollLayerRect, ScrollLocation};
+use webrender_traits::{LayerToScrollTransform, LayerToWorldTransform, OverscrollBehavior};
+use webrender_traits::{PipelineId, ScrollAnimation, ScrollClamping, ScrollEasing, ScrollEventPhase};
+use webrender_traits::{ScrollLayerId, ScrollLayerRect, ScrollLocation};
 use webrender_traits::{WorldPoint, WorldPoint4D};
 
 #[cfg(target_os = "macos")]
@@ -21,6 +22,15 @@ const CAN_OVERSCROLL: bool = true;
 #[cfg(not(target_os = "macos"))]
 const CAN_OVERSCROLL: bool = false;
 
+/// The overscroll behavior of scroll nodes the embedder hasn't configured.
+pub fn default_overscroll_behavior() -> OverscrollBehavior {
+    if CAN_OVERSCROLL {
+        OverscrollBehavior::Bounce { stiffness: STIFFNESS, damping: DAMPING }
+    } else {
+        OverscrollBehavior::Clamp
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ClipInfo {
     /// The ClipSource for this node, which is used to generate mask_cache_info.
@@ -42,14 +52,14 @@ pub struct ClipInfo {
 
 impl ClipInfo {
     pub fn new(clip_region: &ClipRegion,
-               clip_store: &mut VertexDataStore<GpuBlock32>,
+               gpu_cache: &mut GpuCache,
                packed_layer_index: PackedLayerIndex,)
                -> ClipInfo {
         // We pass true here for the MaskCacheInfo because this type of
         // mask needs an extra clip for the clip rectangle.
         let clip_sources = vec![ClipSource::Region(clip_region.clone(), RegionMode::IncludeRect)];
         ClipInfo {
-            mask_cache_info: MaskCacheInfo::new(&clip_sources, clip_store),
+            mask_cache_info: MaskCacheInfo::new(&clip_sources, gpu_cache),
             clip_sources: clip_sources,
             packed_layer_index: packed_layer_index,
             xf_rect: None,
@@ -189,6 +199,17 @@ impl ClipScrollNode {
     }
 
     pub fn set_scroll_origin(&mut self, origin: &LayerPoint) -> bool {
+        self.scroll_to(origin, ScrollClamping::ToContentBounds, None)
+    }
+
+    /// Scrolls so that `origin` ends up at the top left of the viewport,
+    /// either at once or by starting an animation. Returns true if the
+    /// node moved or started animating.
+    pub fn scroll_to(&mut self,
+                     origin: &LayerPoint,
+                     clamping: ScrollClamping,
+                     animation: Option<ScrollAnimation>)
+                     -> bool {
         match self.node_type {
             NodeType::ReferenceFrame(_) => {
                 warn!("Tried to scroll a reference frame.");
@@ -197,25 +218,87 @@ impl ClipScrollNode {
             NodeType::Clip(_) => {}
         };
 
+        let new_offset = match clamping {
+            ScrollClamping::ToContentBounds => {
+                let scrollable_height = self.scrollable_height();
+                let scrollable_width = self.scrollable_width();
+                if scrollable_height <= 0. && scrollable_width <= 0. {
+                    return false;
+                }
 
-        let scrollable_height = self.scrollable_height();
-        let scrollable_width = self.scrollable_width();
-        if scrollable_height <= 0. && scrollable_width <= 0. {
-            return false;
-        }
+                LayerPoint::new((-origin.x).max(-scrollable_width).min(0.0).round(),
+                                (-origin.y).max(-scrollable_height).min(0.0).round())
+            }
+            ScrollClamping::NoClamping => LayerPoint::new(-origin.x, -origin.y),
+        };
 
-        let new_offset = LayerPoint::new((-origin.x).max(-scrollable_width).min(0.0).round(),
-                                         (-origin.y).max(-scrollable_height).min(0.0).round());
+        self.scrolling.scroll_animation = None;
         if new_offset == self.scrolling.offset {
             return false;
         }
 
-        self.scrolling.offset = new_offset;
         self.scrolling.bouncing_back = false;
         self.scrolling.started_bouncing_back = false;
+
+        let now = precise_time_ns();
+        match animation {
+            Some(animation) if animation.duration_ms > 0 => {
+                self.scrolling.scroll_animation = Some(ScrollAnimationState {
+                    from: self.scrolling.offset,
+                    to: new_offset,
+                    start_time_ns: now,
+                    duration_ns: animation.duration_ms as u64 * 1_000_000,
+                    easing: animation.easing,
+                });
+            }
+            _ => {
+                self.scrolling.offset = new_offset;
+                self.reset_overscroll_spring();
+            }
+        }
+
+        self.scrolling.last_scroll_time_ns = now;
         return true;
     }
 
+    /// Advances a running scroll_to() animation. Returns true if the node
+    /// is still animating afterwards.
+    pub fn tick_scroll_animation(&mut self, now_ns: u64) -> bool {
+        let animation = match self.scrolling.scroll_animation {
+            Some(animation) => animation,
+            None => return false,
+        };
+
+        let elapsed_ns = now_ns.saturating_sub(animation.start_time_ns);
+        if elapsed_ns >= animation.duration_ns {
+            self.scrolling.offset = animation.to;
+            self.scrolling.scroll_animation = None;
+        } else {
+            let t = ease(animation.easing, elapsed_ns as f32 / animation.duration_ns as f32);
+            self.scrolling.offset = LayerPoint::new(lerp(animation.from.x, animation.to.x, t),
+                                                   lerp(animation.from.y, animation.to.y, t));
+        }
+        self.reset_overscroll_spring();
+
+        self.scrolling.last_scroll_time_ns = now_ns;
+        self.scrolling.scroll_animation.is_some()
+    }
+
+    /// Moves the node to an offset supplied by the embedder. The offset is
+    /// not clamped to the scrollable area.
+    pub fn set_external_scroll_offset(&mut self, offset: LayerPoint) {
+        if self.scrolling.offset == offset {
+            return;
+        }
+
+        self.scrolling.offset = offset;
+        self.scrolling.bouncing_back = false;
+        self.scrolling.started_bouncing_back = false;
+        self.scrolling.scroll_animation = None;
+        self.reset_overscroll_spring();
+        self.scrolling.last_scroll_time_ns = precise_time_ns();
+    }
+
     pub fn update_transform(&mut self,
                             parent_reference_frame_transform: &LayerToWorldTransform,
                             parent_combined_viewport_rect: &ScrollLayerRect,
@@ -284,11 +367,19 @@ impl ClipScrollNode {
         self.content_size.width - self.local_viewport_rect.size.width
     }
 
-    pub fn scroll(&mut self, scroll_location: ScrollLocation, phase: ScrollEventPhase) -> bool {
+    pub fn scroll(&mut self,
+                  scroll_location: ScrollLocation,
+                  phase: ScrollEventPhase,
+                  behavior: OverscrollBehavior)
+                  -> bool {
+        let can_overscroll = behavior != OverscrollBehavior::Clamp;
         if self.scrolling.started_bouncing_back && phase == ScrollEventPhase::Move(false) {
             return false;
         }
 
+        // The user takes over from any programmatic scroll in progress.
+        self.scrolling.scroll_animation = None;
+
         let mut delta = match scroll_location {
             ScrollLocation::Delta(delta) => delta,
             ScrollLocation::Start => {
@@ -298,6 +389,8 @@ impl ClipScrollNode {
                 }
 
                 self.scrolling.offset.y = 0.0;
+                self.reset_overscroll_spring();
+                self.scrolling.last_scroll_time_ns = precise_time_ns();
                 return true;
             },
             ScrollLocation::End => {
@@ -309,12 +402,14 @@ impl ClipScrollNode {
                 }
 
                 self.scrolling.offset.y = end_pos;
+                self.reset_overscroll_spring();
+                self.scrolling.last_scroll_time_ns = precise_time_ns();
                 return true;
             }
         };
 
         let overscroll_amount = self.overscroll_amount();
-        let overscrolling = CAN_OVERSCROLL && (overscroll_amount.width != 0.0 ||
+        let overscrolling = can_overscroll && (overscroll_amount.width != 0.0 ||
                                                overscroll_amount.height != 0.0);
         if overscrolling {
             if overscroll_amount.width != 0.0 {
@@ -332,7 +427,7 @@ impl ClipScrollNode {
 
         if scrollable_width > 0. {
             self.scrolling.offset.x = self.scrolling.offset.x + delta.x;
-            if is_unscrollable || !CAN_OVERSCROLL {
+            if is_unscrollable || !can_overscroll {
                 self.scrolling.offset.x =
                     self.scrolling.offset.x.min(0.0).max(-scrollable_width).round();
             }
@@ -340,7 +435,7 @@ impl ClipScrollNode {
 
         if scrollable_height > 0. {
             self.scrolling.offset.y = self.scrolling.offset.y + delta.y;
-            if is_unscrollable || !CAN_OVERSCROLL {
+            if is_unscrollable || !can_overscroll {
                 self.scrolling.offset.y =
                     self.scrolling.offset.y.min(0.0).max(-scrollable_height).round();
             }
@@ -354,24 +449,42 @@ impl ClipScrollNode {
             self.scrolling.bouncing_back = true
         }
 
-        if CAN_OVERSCROLL {
-            self.stretch_overscroll_spring();
+        match behavior {
+            OverscrollBehavior::Bounce { stiffness, damping } => {
+                self.stretch_overscroll_spring(stiffness, damping);
+            }
+            _ => self.reset_overscroll_spring(),
         }
 
-        self.scrolling.offset != original_layer_scroll_offset ||
-            self.scrolling.started_bouncing_back
+        let scrolled = self.scrolling.offset != original_layer_scroll_offset ||
+                       self.scrolling.started_bouncing_back;
+        if scrolled {
+            self.scrolling.last_scroll_time_ns = precise_time_ns();
+        }
+        scrolled
     }
 
-    pub fn stretch_overscroll_spring(&mut self) {
+    pub fn stretch_overscroll_spring(&mut self, stiffness: f32, damping: f32) {
         let overscroll_amount = self.overscroll_amount();
+        self.scrolling.spring = Spring::at(self.scrolling.offset, stiffness, damping);
         self.scrolling.spring.coords(self.scrolling.offset,
                                      self.scrolling.offset,
                                      self.scrolling.offset + overscroll_amount);
     }
 
+    /// Leaves the spring resting at the current offset, so that ticking it
+    /// doesn't move the node back to where the spring last was.
+    fn reset_overscroll_spring(&mut self) {
+        let offset = self.scrolling.offset;
+        self.scrolling.spring.coords(offset, offset, offset);
+    }
+
     pub fn tick_scrolling_bounce_animation(&mut self) {
         let finished = self.scrolling.spring.animate();
         self.scrolling.offset = self.scrolling.spring.current();
+        if self.scrolling.bouncing_back {
+            self.scrolling.last_scroll_time_ns = precise_time_ns();
+        }
         if finished {
             self.scrolling.bouncing_back = false
         }
@@ -404,7 +517,34 @@ pub struct ScrollingState {
     pub spring: Spring,
     pub started_bouncing_back: bool,
     pub bouncing_back: bool,
-    pub should_handoff_scroll: bool
+    pub should_handoff_scroll: bool,
+    /// When the offset last changed, used to fade out idle scrollbars.
+    pub last_scroll_time_ns: u64,
+    pub scroll_animation: Option<ScrollAnimationState>,
+}
+
+/// A smooth scroll started by `ClipScrollNode::scroll_to`.
+#[derive(Copy, Clone, Debug)]
+pub struct ScrollAnimationState {
+    pub from: LayerPoint,
+    pub to: LayerPoint,
+    pub start_time_ns: u64,
+    pub duration_ns: u64,
+    pub easing: ScrollEasing,
+}
+
+fn ease(easing: ScrollEasing, t: f32) -> f32 {
+    match easing {
+        ScrollEasing::Linear => t,
+        ScrollEasing::EaseOut => 1.0 - (1.0 - t).powi(3),
+        ScrollEasing::EaseInOut => {
+            if t < 0.5 {
+                4.0 * t * t * t
+            } else {
+                1.0 - (2.0 - 2.0 * t).powi(3) / 2.0
+            }
+        }
+    }
 }
 
 impl ScrollingState {
@@ -414,7 +554,9 @@ impl ScrollingState {
             spring: Spring::at(LayerPoint::zero(), STIFFNESS, DAMPING),
             started_bouncing_back: false,
             bouncing_back: false,
-            should_handoff_scroll: false
+            should_handoff_scroll: false,
+            last_scroll_time_ns: precise_time_ns(),
+            scroll_animation: None,
         }
     }
 }
@@ -2,19 +2,23 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use clip_scroll_node::{ClipScrollNode, NodeType, ScrollingState};
+use clip_scroll_node::{ClipScrollNode, NodeType, ScrollingState, default_overscroll_behavior};
 use fnv::FnvHasher;
 use std::collections::{HashMap, HashSet};
 use std::hash::BuildHasherDefault;
-use webrender_traits::{LayerPoint, LayerRect, LayerToScrollTransform, LayerToWorldTransform};
-use webrender_traits::{PipelineId, ScrollEventPhase, ScrollLayerId, ScrollLayerRect};
+use time::precise_time_ns;
+use webrender_traits::{ExternalScrollOffset, LayerPoint, LayerRect, LayerSize};
+use webrender_traits::LayerToScrollTransform;
+use webrender_traits::{LayerToWorldTransform, OverscrollBehavior, OverscrollEvent, PipelineId};
+use webrender_traits::{ScrollAnimation, ScrollClamping, ScrollEventPhase, ScrollLayerId};
+use webrender_traits::ScrollLayerRect;
 use webrender_traits::{ScrollLayerState, ScrollLocation, WorldPoint, as_scroll_parent_rect};
 
 pub type ScrollStates = HashMap<ScrollLayerId, ScrollingState, BuildHasherDefault<FnvHasher>>;
 
 pub struct ClipScrollTree {
     pub nodes: HashMap<ScrollLayerId, ClipScrollNode, BuildHasherDefault<FnvHasher>>,
-    pub pending_scroll_offsets: HashMap<ScrollLayerId, LayerPoint>,
+    pub pending_scroll_offsets: HashMap<ScrollLayerId, (LayerPoint, ScrollClamping)>,
 
     /// The ScrollLayerId of the currently scrolling node. Used to allow the same
     /// node to scroll even if a touch operation leaves the boundaries of that node.
@@ -36,6 +40,19 @@ pub struct ClipScrollTree {
     /// A set of pipelines which should be discarded the next time this
     /// tree is drained.
     pub pipelines_to_discard: HashSet<PipelineId>,
+
+    /// Overscroll behaviors set by the embedder. Nodes without an entry
+    /// use the platform default.
+    overscroll_behaviors: HashMap<ScrollLayerId, OverscrollBehavior>,
+
+    /// The overscroll amount and bounce state last reported to the embedder
+    /// for each node that has been out of bounds.
+    reported_overscroll: HashMap<ScrollLayerId, (LayerSize, bool)>,
+
+    /// Nodes scrolled by the embedder, with the last offset it supplied.
+    /// These are reapplied whenever the tree is rebuilt, so they don't
+    /// depend on frame state being preserved.
+    external_scroll_offsets: HashMap<ScrollLayerId, LayerPoint>,
 }
 
 impl ClipScrollTree {
@@ -49,6 +66,9 @@ impl ClipScrollTree {
             topmost_scroll_layer_id: ScrollLayerId::root_scroll_layer(dummy_pipeline),
             current_reference_frame_id: 0,
             pipelines_to_discard: HashSet::new(),
+            overscroll_behaviors: HashMap::new(),
+            reported_overscroll: HashMap::new(),
+            external_scroll_offsets: HashMap::new(),
         }
     }
 
@@ -111,7 +131,14 @@ impl ClipScrollTree {
         for (id, node) in self.nodes.iter() {
             match node.node_type {
                 NodeType::Clip(_) => result.push(
-                    ScrollLayerState { id: *id, scroll_offset: node.scrolling.offset }),
+                    ScrollLayerState {
+                        id: *id,
+                        scroll_offset: node.scrolling.offset,
+                        scrollable_size: LayerSize::new(node.scrollable_width().max(0.0),
+                                                        node.scrollable_height().max(0.0)),
+                        overscroll: node.overscroll_amount(),
+                        bouncing_back: node.scrolling.bouncing_back,
+                    }),
                 _ => {},
             }
         }
@@ -128,27 +155,54 @@ impl ClipScrollTree {
             }
         }
 
+        {
+            let pipelines_to_discard = &self.pipelines_to_discard;
+            self.overscroll_behaviors.retain(|id, _| !pipelines_to_discard.contains(&id.pipeline_id()));
+            self.reported_overscroll.retain(|id, _| !pipelines_to_discard.contains(&id.pipeline_id()));
+            self.external_scroll_offsets.retain(|id, _| !pipelines_to_discard.contains(&id.pipeline_id()));
+            self.pending_scroll_offsets.retain(|id, _| !pipelines_to_discard.contains(&id.pipeline_id()));
+        }
+
         self.pipelines_to_discard.clear();
         scroll_states
     }
 
     pub fn scroll_nodes(&mut self, origin: LayerPoint, id: ScrollLayerId) -> bool {
+        self.scroll_node_to(origin, id, ScrollClamping::ToContentBounds, None)
+    }
+
+    /// Scrolls a node to `origin`. If the node doesn't exist yet, the
+    /// origin is applied without animation once it does.
+    pub fn scroll_node_to(&mut self,
+                          origin: LayerPoint,
+                          id: ScrollLayerId,
+                          clamping: ScrollClamping,
+                          animation: Option<ScrollAnimation>)
+                          -> bool {
         if id.is_reference_frame() {
             warn!("Tried to scroll a reference frame.");
             return false;
         }
 
+        if self.external_scroll_offsets.contains_key(&id) {
+            warn!("Tried to scroll externally scrolled node {:?}.", id);
+            return false;
+        }
+
         if self.nodes.is_empty() {
-            self.pending_scroll_offsets.insert(id, origin);
+            self.pending_scroll_offsets.insert(id, (origin, clamping));
             return false;
         }
 
-        let origin = LayerPoint::new(origin.x.max(0.0), origin.y.max(0.0));
+        let origin = match clamping {
+            ScrollClamping::ToContentBounds => LayerPoint::new(origin.x.max(0.0), origin.y.max(0.0)),
+            ScrollClamping::NoClamping => origin,
+        };
         if let Some(node) = self.nodes.get_mut(&id) {
-            return node.set_scroll_origin(&origin);
+            return node.scroll_to(&origin, clamping, animation);
         }
 
-        self.pending_scroll_offsets.insert(id, origin);
+        self.pending_scroll_offsets.insert(id, (origin, clamping));
         false
     }
 
@@ -222,7 +276,89 @@ impl ClipScrollTree {
             scroll_layer_id
         };
 
-        self.nodes.get_mut(&scroll_layer_id).unwrap().scroll(scroll_location, phase)
+        // The embedder's pan/zoom controller handles input for these.
+        if self.external_scroll_offsets.contains_key(&scroll_layer_id) {
+            return false;
+        }
+
+        let behavior = self.overscroll_behavior(scroll_layer_id);
+        self.nodes.get_mut(&scroll_layer_id).unwrap().scroll(scroll_location, phase, behavior)
+    }
+
+    pub fn set_externally_scrolled(&mut self, id: ScrollLayerId, externally_scrolled: bool) {
+        if id.is_reference_frame() {
+            warn!("Tried to mark reference frame {:?} as externally scrolled.", id);
+            return;
+        }
+
+        if !externally_scrolled {
+            self.external_scroll_offsets.remove(&id);
+            return;
+        }
+
+        // Until the embedder supplies an offset, keep the node where it is.
+        let current_offset = self.nodes.get(&id).map_or(LayerPoint::zero(), |node| {
+            node.scrolling.offset
+        });
+        self.external_scroll_offsets.entry(id).or_insert(current_offset);
+    }
+
+    /// Applies offsets supplied by the embedder for externally scrolled
+    /// nodes. Offsets for any other node are ignored.
+    pub fn set_external_scroll_offsets(&mut self, offsets: Vec<ExternalScrollOffset>) {
+        for external_offset in offsets {
+            match self.external_scroll_offsets.get_mut(&external_offset.id) {
+                Some(offset) => *offset = external_offset.offset,
+                None => {
+                    warn!("Ignoring scroll offset for {:?}, which is not externally scrolled.",
+                          external_offset.id);
+                    continue;
+                }
+            }
+
+            if let Some(node) = self.nodes.get_mut(&external_offset.id) {
+                node.set_external_scroll_offset(external_offset.offset);
+            }
+        }
+    }
+
+    pub fn overscroll_behavior(&self, id: ScrollLayerId) -> OverscrollBehavior {
+        self.overscroll_behaviors.get(&id).cloned().unwrap_or_else(default_overscroll_behavior)
+    }
+
+    pub fn set_overscroll_behavior(&mut self, id: ScrollLayerId, behavior: OverscrollBehavior) {
+        self.overscroll_behaviors.insert(id, behavior);
+    }
+
+    /// Returns an event for every scroll node whose overscroll amount or
+    /// bounce state changed since the last call.
+    pub fn take_overscroll_events(&mut self) -> Vec<OverscrollEvent> {
+        let mut events = Vec::new();
+        for (id, node) in &self.nodes {
+            if let NodeType::ReferenceFrame(..) = node.node_type {
+                continue;
+            }
+
+            let state = (node.overscroll_amount(), node.scrolling.bouncing_back);
+            let previous = self.reported_overscroll.get(id)
+                                                   .cloned()
+                                                   .unwrap_or((LayerSize::zero(), false));
+            if state == previous {
+                continue;
+            }
+
+            if state == (LayerSize::zero(), false) {
+                self.reported_overscroll.remove(id);
+            } else {
+                self.reported_overscroll.insert(*id, state);
+            }
+            events.push(OverscrollEvent {
+                id: *id,
+                overscroll: state.0,
+                bouncing_back: state.1,
+            });
+        }
+        events
     }
 
     pub fn update_all_node_transforms(&mut self, pan: LayerPoint) {
@@ -282,12 +418,32 @@ impl ClipScrollTree {
         }
     }
 
+    /// Returns true if `id` is `ancestor_id` or one of its descendants.
+    pub fn is_descendant_of(&self, id: ScrollLayerId, ancestor_id: ScrollLayerId) -> bool {
+        let mut current_id = Some(id);
+        while let Some(id) = current_id {
+            if id == ancestor_id {
+                return true;
+            }
+            current_id = self.nodes.get(&id).and_then(|node| node.parent);
+        }
+        false
+    }
+
     pub fn tick_scrolling_bounce_animations(&mut self) {
-        for (_, node) in &mut self.nodes {
-            node.tick_scrolling_bounce_animation()
+        let now = precise_time_ns();
+        for (id, node) in &mut self.nodes {
+            if !self.external_scroll_offsets.contains_key(id) {
+                node.tick_scrolling_bounce_animation();
+                node.tick_scroll_animation(now);
+            }
         }
     }
 
+    pub fn scroll_animations_running(&self) -> bool {
+        self.nodes.values().any(|node| node.scrolling.scroll_animation.is_some())
+    }
+
     pub fn finalize_and_apply_pending_scroll_offsets(&mut self, old_states: ScrollStates) {
         // TODO(gw): These are all independent - can be run through thread pool if it shows up
         // in the profile!
@@ -299,8 +455,11 @@ impl ClipScrollTree {
 
             node.finalize(&scrolling_state);
 
-            if let Some(pending_offset) = self.pending_scroll_offsets.remove(&scroll_layer_id) {
-                node.set_scroll_origin(&pending_offset);
+            if let Some(external_offset) = self.external_scroll_offsets.get(&scroll_layer_id) {
+                self.pending_scroll_offsets.remove(&scroll_layer_id);
+                node.set_external_scroll_offset(*external_offset);
+            } else if let Some((origin, clamping)) = self.pending_scroll_offsets.remove(&scroll_layer_id) {
+                node.scroll_to(&origin, clamping, None);
             }
         }
 
@@ -347,3 +506,55 @@ impl ClipScrollTree {
     }
 }
 
+
+#[cfg(test)]
+fn add_scroll_node(tree: &mut ClipScrollTree, id: ScrollLayerId, parent_id: ScrollLayerId) {
+    use clip_scroll_node::ClipInfo;
+    use tiling::PackedLayerIndex;
+
+    let clip_info = ClipInfo {
+        clip_sources: Vec::new(),
+        mask_cache_info: None,
+        packed_layer_index: PackedLayerIndex(0),
+        xf_rect: None,
+    };
+    let node = ClipScrollNode::new(id.pipeline_id(),
+                                   parent_id,
+                                   &LayerRect::new(LayerPoint::zero(), LayerSize::new(100.0, 100.0)),
+                                   LayerSize::new(100.0, 1000.0),
+                                   clip_info);
+    tree.add_node(node, id);
+}
+
+#[test]
+fn scroll_to_survives_other_nodes_animating() {
+    use webrender_traits::ScrollEasing;
+
+    let pipeline_id = PipelineId(0, 0);
+    let mut tree = ClipScrollTree::new();
+    let root_id = tree.add_reference_frame(&LayerRect::new(LayerPoint::zero(),
+                                                           LayerSize::new(100.0, 100.0)),
+                                           &LayerToScrollTransform::identity(),
+                                           pipeline_id,
+                                           None);
+    let node_a = ScrollLayerId::new(1, pipeline_id);
+    let node_b = ScrollLayerId::new(2, pipeline_id);
+    add_scroll_node(&mut tree, node_a, root_id);
+    add_scroll_node(&mut tree, node_b, root_id);
+
+    assert!(tree.scroll_node_to(LayerPoint::new(0.0, 100.0),
+                                node_a,
+                                ScrollClamping::ToContentBounds,
+                                None));
+    let animation = ScrollAnimation { duration_ms: 1000, easing: ScrollEasing::Linear };
+    assert!(tree.scroll_node_to(LayerPoint::new(0.0, 200.0),
+                                node_b,
+                                ScrollClamping::ToContentBounds,
+                                Some(animation)));
+
+    for _ in 0..3 {
+        tree.tick_scrolling_bounce_animations();
+        assert!(tree.scroll_animations_running());
+        assert_eq!(tree.nodes[&node_a].scrolling.offset, LayerPoint::new(0.0, -100.0));
+    }
+}
@@ -8,6 +8,7 @@ use device::{TextureFilter, VertexUsageHint, TextureTarget};
 use euclid::{Matrix4D, Point2D, Size2D, Rect};
 use internal_types::{ORTHO_NEAR_PLANE, ORTHO_FAR_PLANE, TextureSampler};
 use internal_types::{DebugFontVertex, DebugColorVertex, RenderTargetMode, PackedColor};
+use renderer::SurfaceOrigin;
 use std::f32;
 use webrender_traits::{ColorF, ImageFormat, DeviceUintSize};
 
@@ -160,16 +161,21 @@ impl DebugRenderer {
 
     pub fn render(&mut self,
                   device: &mut Device,
-                  viewport_size: &DeviceUintSize) {
+                  viewport_size: &DeviceUintSize,
+                  origin: SurfaceOrigin) {
         let _gm = GpuMarker::new(device.rc_gl(), "debug");
         device.disable_depth();
         device.set_blend(true);
         device.set_blend_mode_alpha();
 
+        let (bottom, top) = match origin {
+            SurfaceOrigin::BottomLeft => (viewport_size.height as f32, 0.0),
+            SurfaceOrigin::TopLeft => (0.0, viewport_size.height as f32),
+        };
         let projection = Matrix4D::ortho(0.0,
                                          viewport_size.width as f32,
-                                         viewport_size.height as f32,
-                                         0.0,
+                                         bottom,
+                                         top,
                                          ORTHO_NEAR_PLANE,
                                          ORTHO_FAR_PLANE);
 
@@ -2,6 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use driver_workarounds::{BufferUpdateStrategy, DriverInfo, DriverWorkarounds};
 use euclid::Matrix4D;
 use fnv::FnvHasher;
 use gleam::gl;
@@ -10,17 +11,19 @@ use internal_types::{BlurAttribute, ClearAttribute, ClipAttribute, VertexAttribu
 use internal_types::{DebugFontVertex, DebugColorVertex};
 //use notify::{self, Watcher};
 use super::shader_source;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
 use std::hash::BuildHasherDefault;
 use std::io::Read;
 use std::iter::repeat;
 use std::mem;
 use std::path::PathBuf;
+use std::ptr;
 use std::rc::Rc;
+use std::slice;
 //use std::sync::mpsc::{channel, Sender};
 //use std::thread;
-use webrender_traits::{ColorF, ImageFormat};
+use webrender_traits::{ColorF, DevicePoint, ImageFormat};
 use webrender_traits::{DeviceIntPoint, DeviceIntRect, DeviceIntSize, DeviceUintSize};
 
 #[derive(Debug, Copy, Clone)]
@@ -40,6 +43,11 @@ const SHADER_VERSION_GL: &'static str = "#version 150\n";
 
 const SHADER_VERSION_GLES: &'static str = "#version 300 es\n";
 
+const COMPUTE_SHADER_VERSION_GL: &'static str = "#version 430\n";
+
+// From EXT_disjoint_timer_query.
+const GL_GPU_DISJOINT_EXT: gl::GLuint = 0x8FBB;
+
 static SHADER_PREAMBLE: &'static str = "shared";
 
 #[repr(u32)]
@@ -203,7 +211,7 @@ impl VertexFormat {
                 instance.bind(gl);
                 let mut offset = 0;
 
-                for &attrib in [VertexAttribute::GlobalPrimId,
+                for &attrib in [VertexAttribute::GeometryAddress,
                                 VertexAttribute::PrimitiveAddress,
                                 VertexAttribute::TaskIndex,
                                 VertexAttribute::ClipTaskIndex,
@@ -330,6 +338,12 @@ impl TextureId {
     }
 
     pub fn is_valid(&self) -> bool { *self != TextureId::invalid() }
+
+    /// The GL name of the texture, for handing it to code outside of the
+    /// device.
+    pub fn name(&self) -> gl::GLuint {
+        self.name
+    }
 }
 
 impl ProgramId {
@@ -375,14 +389,28 @@ struct Texture {
     filter: TextureFilter,
     mode: RenderTargetMode,
     fbo_ids: Vec<FBOId>,
+    // The depth buffers attached to the framebuffers of a layer render
+    // target, one per layer.
+    depth_rb_ids: Vec<gl::GLuint>,
 }
 
-impl Drop for Texture {
-    fn drop(&mut self) {
+impl Texture {
+    fn delete_fbos(&mut self) {
         if !self.fbo_ids.is_empty() {
             let fbo_ids: Vec<_> = self.fbo_ids.iter().map(|&FBOId(fbo_id)| fbo_id).collect();
             self.gl.delete_framebuffers(&fbo_ids[..]);
+            self.fbo_ids.clear();
+        }
+        if !self.depth_rb_ids.is_empty() {
+            self.gl.delete_renderbuffers(&self.depth_rb_ids[..]);
+            self.depth_rb_ids.clear();
         }
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        self.delete_fbos();
         self.gl.delete_textures(&[self.id]);
     }
 }
@@ -392,6 +420,12 @@ struct Program {
     id: gl::GLuint,
     u_transform: gl::GLint,
     u_device_pixel_ratio: gl::GLint,
+    // The values last set for uTransform and uDevicePixelRatio, which
+    // the program keeps until it is relinked.
+    transform: Option<[f32; 16]>,
+    device_pixel_ratio: Option<f32>,
+    // The locations of the other uniforms that have been looked up.
+    uniform_locations: HashMap<String, gl::GLint>,
     name: String,
     vs_source: String,
     fs_source: String,
@@ -415,7 +449,7 @@ impl Program {
                 self.gl.bind_attrib_location(self.id, VertexAttribute::Color as gl::GLuint, "aColor");
                 self.gl.bind_attrib_location(self.id, VertexAttribute::ColorTexCoord as gl::GLuint, "aColorTexCoord");
 
-                self.gl.bind_attrib_location(self.id, VertexAttribute::GlobalPrimId as gl::GLuint, "aGlobalPrimId");
+                self.gl.bind_attrib_location(self.id, VertexAttribute::GeometryAddress as gl::GLuint, "aGeometryAddress");
                 self.gl.bind_attrib_location(self.id, VertexAttribute::PrimitiveAddress as gl::GLuint, "aPrimitiveAddress");
                 self.gl.bind_attrib_location(self.id, VertexAttribute::TaskIndex as gl::GLuint, "aTaskIndex");
                 self.gl.bind_attrib_location(self.id, VertexAttribute::ClipTaskIndex as gl::GLuint, "aClipTaskIndex");
@@ -462,6 +496,17 @@ impl Drop for Program {
     }
 }
 
+struct ComputeProgram {
+    gl: Rc<gl::Gl>,
+    id: gl::GLuint,
+}
+
+impl Drop for ComputeProgram {
+    fn drop(&mut self) {
+        self.gl.delete_program(self.id);
+    }
+}
+
 struct VAO {
     gl: Rc<gl::Gl>,
     id: gl::GLuint,
@@ -515,9 +560,117 @@ struct IBOId(gl::GLuint);
 #[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
 pub struct UBOId(gl::GLuint);
 
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
+struct PBOId(gl::GLuint);
+
 const MAX_EVENTS_PER_FRAME: usize = 256;
 const MAX_PROFILE_FRAMES: usize = 4;
 
+// Number of pixel buffers the upload staging area cycles through. Filling
+// the next one doesn't have to wait for the GPU to finish reading the last.
+const UPLOAD_PBO_COUNT: usize = 3;
+
+// Maximum number of deleted texture objects kept around per texture target
+// for reuse. Anything beyond this is returned to the driver.
+const MAX_FREE_TEXTURES_PER_TARGET: usize = 32;
+
+// Offsets of uploads within the staging buffer are rounded up to this.
+const UPLOAD_OFFSET_ALIGNMENT: usize = 16;
+
+/// A texture update whose pixels have been copied to the staging
+/// buffer, but not yet handed to the driver.
+struct PendingUpload {
+    texture_id: TextureId,
+    x0: gl::GLint,
+    y0: gl::GLint,
+    width: gl::GLint,
+    height: gl::GLint,
+    gl_format: gl::GLuint,
+    gl_type: gl::GLuint,
+    alignment: gl::GLint,
+    offset: usize,
+}
+
+/// Texture updates are gathered here during a frame and flushed through
+/// a single pixel buffer upload before anything reads the textures.
+struct UploadStaging {
+    pbos: Vec<PBOId>,
+    next_pbo: usize,
+    data: Vec<u8>,
+    uploads: Vec<PendingUpload>,
+}
+
+impl UploadStaging {
+    fn new() -> UploadStaging {
+        UploadStaging {
+            pbos: Vec::new(),
+            next_pbo: 0,
+            data: Vec::new(),
+            uploads: Vec::new(),
+        }
+    }
+
+    /// Copies `height` rows of `row_bytes` bytes, `stride` bytes apart in
+    /// `data`, and returns their offset in the staging buffer. The rows are
+    /// packed tightly, so the padding between them is never copied.
+    fn push(&mut self, data: &[u8], row_bytes: usize, height: usize, stride: usize) -> usize {
+        let padding = (UPLOAD_OFFSET_ALIGNMENT - self.data.len() % UPLOAD_OFFSET_ALIGNMENT) %
+                      UPLOAD_OFFSET_ALIGNMENT;
+        self.data.extend(repeat(0).take(padding));
+        let offset = self.data.len();
+        self.data.reserve(row_bytes * height);
+        for row in 0..height {
+            let start = row * stride;
+            self.data.extend_from_slice(&data[start..start + row_bytes]);
+        }
+        offset
+    }
+
+    fn next_pbo(&mut self, gl: &gl::Gl) -> PBOId {
+        if self.pbos.is_empty() {
+            self.pbos = gl.gen_buffers(UPLOAD_PBO_COUNT as gl::GLint)
+                          .into_iter()
+                          .map(PBOId)
+                          .collect();
+        }
+        let pbo = self.pbos[self.next_pbo];
+        self.next_pbo = (self.next_pbo + 1) % self.pbos.len();
+        pbo
+    }
+}
+
+/// A copy of framebuffer pixels into a pixel pack buffer, started by
+/// `Device::begin_readback`. The pixels can be mapped without stalling once
+/// the fence has signaled.
+pub struct PendingReadback {
+    pbo: PBOId,
+    fence: gl::GLsync,
+    size: usize,
+}
+
+/// What `Device::end_readback` found.
+pub enum ReadbackStatus {
+    /// The pixels, with rows from bottom to top.
+    Complete(Vec<u8>),
+    /// The GPU hasn't written the pixels yet.
+    Pending(PendingReadback),
+    /// Waiting for the GPU or mapping the pixel buffer failed. The readback
+    /// has been released.
+    Failed,
+}
+
+/// A fence after the commands of a frame, inserted with
+/// `Device::insert_fence`, which signals once the GPU has executed them.
+pub struct GpuFence(gl::GLsync);
+
+/// The filters a texture had before `Device::override_texture_filter`
+/// changed them.
+pub struct SavedTextureFilter {
+    texture_id: TextureId,
+    min_filter: gl::GLint,
+    mag_filter: gl::GLint,
+}
+
 pub trait NamedTag {
     fn get_label(&self) -> &str;
 }
@@ -530,6 +683,7 @@ pub struct GpuSample<T> {
 
 pub struct GpuFrameProfile<T> {
     gl: Rc<gl::Gl>,
+    timer_queries: bool,
     queries: Vec<gl::GLuint>,
     samples: Vec<GpuSample<T>>,
     next_query: usize,
@@ -538,29 +692,21 @@ pub struct GpuFrameProfile<T> {
 }
 
 impl<T> GpuFrameProfile<T> {
-    fn new(gl: Rc<gl::Gl>) -> GpuFrameProfile<T> {
-        match gl.get_type() {
-            gl::GlType::Gl => {
-                let queries = gl.gen_queries(MAX_EVENTS_PER_FRAME as gl::GLint);
-                GpuFrameProfile {
-                    gl: gl,
-                    queries: queries,
-                    samples: Vec::new(),
-                    next_query: 0,
-                    pending_query: 0,
-                    frame_id: FrameId(0),
-                }
-            }
-            gl::GlType::Gles => {
-                GpuFrameProfile {
-                    gl: gl,
-                    queries: Vec::new(),
-                    samples: Vec::new(),
-                    next_query: 0,
-                    pending_query: 0,
-                    frame_id: FrameId(0),
-                }
-            }
+    fn new(gl: Rc<gl::Gl>, timer_queries: bool) -> GpuFrameProfile<T> {
+        let queries = if timer_queries {
+            gl.gen_queries(MAX_EVENTS_PER_FRAME as gl::GLint)
+        } else {
+            Vec::new()
+        };
+
+        GpuFrameProfile {
+            gl: gl,
+            timer_queries: timer_queries,
+            queries: queries,
+            samples: Vec::new(),
+            next_query: 0,
+            pending_query: 0,
+            frame_id: FrameId(0),
         }
     }
 
@@ -572,29 +718,21 @@ impl<T> GpuFrameProfile<T> {
     }
 
     fn end_frame(&mut self) {
-        match self.gl.get_type() {
-            gl::GlType::Gl => {
-                if self.pending_query != 0 {
-                    self.gl.end_query(gl::TIME_ELAPSED);
-                }
-            }
-            gl::GlType::Gles => {},
+        if self.pending_query != 0 {
+            self.gl.end_query(gl::TIME_ELAPSED);
         }
     }
 
     fn add_marker(&mut self, tag: T) -> GpuMarker
     where T: NamedTag {
-        match self.gl.get_type() {
-            gl::GlType::Gl => {
-                self.add_marker_gl(tag)
-            }
-            gl::GlType::Gles => {
-                self.add_marker_gles(tag)
-            }
+        if self.timer_queries {
+            self.add_marker_timed(tag)
+        } else {
+            self.add_marker_untimed(tag)
         }
     }
 
-    fn add_marker_gl(&mut self, tag: T) -> GpuMarker
+    fn add_marker_timed(&mut self, tag: T) -> GpuMarker
     where T: NamedTag {
         if self.pending_query != 0 {
             self.gl.end_query(gl::TIME_ELAPSED);
@@ -617,7 +755,7 @@ impl<T> GpuFrameProfile<T> {
         marker
     }
 
-    fn add_marker_gles(&mut self, tag: T) -> GpuMarker
+    fn add_marker_untimed(&mut self, tag: T) -> GpuMarker
     where T: NamedTag {
         let marker = GpuMarker::new(&self.gl, tag.get_label());
         self.samples.push(GpuSample {
@@ -631,37 +769,35 @@ impl<T> GpuFrameProfile<T> {
         self.next_query > 0 && self.next_query <= MAX_EVENTS_PER_FRAME
     }
 
-    fn build_samples(&mut self) -> Vec<GpuSample<T>> {
-        match self.gl.get_type() {
-            gl::GlType::Gl => {
-                self.build_samples_gl()
-            }
-            gl::GlType::Gles => {
-                self.build_samples_gles()
+    /// Returns the samples of the frame, or `None` when the GPU reported a
+    /// disjoint event, such as a frequency change or a context switch,
+    /// that makes the timings meaningless.
+    fn build_samples(&mut self) -> Option<Vec<GpuSample<T>>> {
+        if self.timer_queries {
+            // Reading the disjoint flag also clears it. It's only reported
+            // by EXT_disjoint_timer_query, desktop GL timings stay valid.
+            let disjoint = match self.gl.get_type() {
+                gl::GlType::Gl => false,
+                gl::GlType::Gles => self.gl.get_integer_v(GL_GPU_DISJOINT_EXT) != 0,
+            };
+            if disjoint {
+                self.samples.clear();
+                return None;
             }
-        }
-    }
 
-    fn build_samples_gl(&mut self) -> Vec<GpuSample<T>> {
-        for (index, sample) in self.samples.iter_mut().enumerate() {
-            sample.time_ns = self.gl.get_query_object_ui64v(self.queries[index], gl::QUERY_RESULT)
+            for (index, sample) in self.samples.iter_mut().enumerate() {
+                sample.time_ns = self.gl.get_query_object_ui64v(self.queries[index], gl::QUERY_RESULT)
+            }
         }
 
-        mem::replace(&mut self.samples, Vec::new())
-    }
-
-    fn build_samples_gles(&mut self) -> Vec<GpuSample<T>> {
-        mem::replace(&mut self.samples, Vec::new())
+        Some(mem::replace(&mut self.samples, Vec::new()))
     }
 }
 
 impl<T> Drop for GpuFrameProfile<T> {
     fn drop(&mut self) {
-        match self.gl.get_type() {
-            gl::GlType::Gl =>  {
-                self.gl.delete_queries(&self.queries);
-            }
-            gl::GlType::Gles => {},
+        if self.timer_queries {
+            self.gl.delete_queries(&self.queries);
         }
     }
 }
@@ -672,14 +808,17 @@ pub struct GpuProfiler<T> {
 }
 
 impl<T> GpuProfiler<T> {
-    pub fn new(gl: &Rc<gl::Gl>) -> GpuProfiler<T> {
+    /// Creates a profiler that times its markers with timer queries when
+    /// `timer_queries` is set, see `Capabilities::supports_timer_queries`.
+    /// Otherwise the samples only name the markers, with zero times.
+    pub fn new(gl: &Rc<gl::Gl>, timer_queries: bool) -> GpuProfiler<T> {
         GpuProfiler {
             next_frame: 0,
             frames: [
-                      GpuFrameProfile::new(Rc::clone(&gl)),
-                      GpuFrameProfile::new(Rc::clone(&gl)),
-                      GpuFrameProfile::new(Rc::clone(&gl)),
-                      GpuFrameProfile::new(Rc::clone(&gl)),
+                      GpuFrameProfile::new(Rc::clone(&gl), timer_queries),
+                      GpuFrameProfile::new(Rc::clone(&gl), timer_queries),
+                      GpuFrameProfile::new(Rc::clone(&gl), timer_queries),
+                      GpuFrameProfile::new(Rc::clone(&gl), timer_queries),
                     ],
         }
     }
@@ -687,7 +826,8 @@ impl<T> GpuProfiler<T> {
     pub fn build_samples(&mut self) -> Option<(FrameId, Vec<GpuSample<T>>)> {
         let frame = &mut self.frames[self.next_frame];
         if frame.is_valid() {
-            Some((frame.frame_id, frame.build_samples()))
+            let frame_id = frame.frame_id;
+            frame.build_samples().map(|samples| (frame_id, samples))
         } else {
             None
         }
@@ -852,6 +992,34 @@ impl FileWatcherThread {
 pub struct Capabilities {
     pub max_ubo_size: usize,
     pub supports_multisampling: bool,
+    /// The most samples per pixel a multisampled render target can have.
+    pub max_samples: u32,
+    /// Whether glInvalidateFramebuffer is available, which is core in
+    /// OpenGL ES 3.0 and OpenGL 4.3.
+    pub supports_invalidate_framebuffer: bool,
+    /// Whether compute shaders can write to the render target textures.
+    /// They are core in OpenGL 4.3. OpenGL ES 3.1 has them too, but is
+    /// never reported as supporting them: render targets there are
+    /// allocated as BGRA_EXT, which isn't an image unit format, so the
+    /// fragment shader blur is used instead.
+    pub supports_compute_shaders: bool,
+    /// Whether GPU work can be timed with TIME_ELAPSED queries. They are
+    /// core in OpenGL 3.3, and come with EXT_disjoint_timer_query on
+    /// OpenGL ES.
+    pub supports_timer_queries: bool,
+    /// Whether the primitive shaders should read the layers and render
+    /// tasks from uniform buffers rather than data textures. Texel
+    /// fetches from float textures are slow on many mobile GPUs.
+    pub prefers_ubo_data: bool,
+}
+
+// Multisampled color and depth buffers that a render target is drawn to,
+// before being resolved into a layer of its texture.
+struct MultisampledTarget {
+    fbo: FBOId,
+    renderbuffers: Vec<gl::GLuint>,
+    size: DeviceUintSize,
+    sample_count: u32,
 }
 
 #[derive(Clone, Debug)]
@@ -870,10 +1038,14 @@ pub struct Device {
     bound_draw_fbo: FBOId,
     default_read_fbo: gl::GLuint,
     default_draw_fbo: gl::GLuint,
+    // Stands in for the default framebuffer, see `redirect_default_target`.
+    default_target_redirect: Option<FBOId>,
     device_pixel_ratio: f32,
 
     // HW or API capabilties
     capabilities: Capabilities,
+    driver_info: DriverInfo,
+    driver_workarounds: DriverWorkarounds,
 
     // debug
     inside_frame: bool,
@@ -881,7 +1053,16 @@ pub struct Device {
     // resources
     resource_override_path: Option<PathBuf>,
     textures: HashMap<TextureId, Texture, BuildHasherDefault<FnvHasher>>,
+    // Deleted texture objects without storage, keyed by GL target. These
+    // are handed out again by create_texture_ids before new GL names.
+    free_textures: HashMap<gl::GLuint, Vec<TextureId>, BuildHasherDefault<FnvHasher>>,
+    // Textures deleted during the current frame. They may still be read by
+    // commands the GPU hasn't executed yet, so they are only released once
+    // the fence inserted at the end of the frame has signaled.
+    deleted_textures: Vec<TextureId>,
+    retiring_textures: VecDeque<(gl::GLsync, Vec<TextureId>)>,
     programs: HashMap<ProgramId, Program, BuildHasherDefault<FnvHasher>>,
+    compute_programs: HashMap<ProgramId, ComputeProgram, BuildHasherDefault<FnvHasher>>,
     vaos: HashMap<VAOId, VAO, BuildHasherDefault<FnvHasher>>,
 
     // misc.
@@ -897,6 +1078,16 @@ pub struct Device {
     // Frame counter. This is used to map between CPU
     // frames and GPU frames.
     frame_id: FrameId,
+
+    upload_staging: UploadStaging,
+
+    // Pixel pack buffers of finished readbacks, kept for reuse.
+    readback_pbos: Vec<PBOId>,
+
+    // Read framebuffer that textures are attached to, to blit from them.
+    blit_fbo: Option<FBOId>,
+
+    multisampled_target: Option<MultisampledTarget>,
 }
 
 impl Device {
@@ -910,6 +1101,40 @@ impl Device {
 
         let max_ubo_size = gl.get_integer_v(gl::MAX_UNIFORM_BLOCK_SIZE) as usize;
         let max_texture_size = gl.get_integer_v(gl::MAX_TEXTURE_SIZE) as u32;
+        let max_samples = gl.get_integer_v(gl::MAX_SAMPLES) as u32;
+        let gl_version = (gl.get_integer_v(gl::MAJOR_VERSION),
+                          gl.get_integer_v(gl::MINOR_VERSION));
+        let supports_invalidate_framebuffer = match gl.get_type() {
+            gl::GlType::Gl => gl_version >= (4, 3),
+            gl::GlType::Gles => gl_version >= (3, 0),
+        };
+        let supports_timer_queries = match gl.get_type() {
+            gl::GlType::Gl => true,
+            gl::GlType::Gles => {
+                gl.get_string(gl::EXTENSIONS)
+                  .split_whitespace()
+                  .any(|extension| extension == "GL_EXT_disjoint_timer_query")
+            }
+        };
+        let supports_compute_shaders = match gl.get_type() {
+            gl::GlType::Gl => gl_version >= (4, 3),
+            // The BGRA render targets can't be bound as images.
+            gl::GlType::Gles => false,
+        };
+        let prefers_ubo_data = match gl.get_type() {
+            gl::GlType::Gl => false,
+            gl::GlType::Gles => true,
+        };
+
+        let driver_info = DriverInfo {
+            vendor: gl.get_string(gl::VENDOR),
+            renderer: gl.get_string(gl::RENDERER),
+            version: gl.get_string(gl::VERSION),
+        };
+        let driver_workarounds = DriverWorkarounds::for_driver(&driver_info);
+        if driver_workarounds != DriverWorkarounds::default() {
+            info!("Driver workarounds for {:?}: {:?}", driver_info, driver_workarounds);
+        }
 
         Device {
             gl: gl,
@@ -922,7 +1147,14 @@ impl Device {
             capabilities: Capabilities {
                 max_ubo_size: max_ubo_size,
                 supports_multisampling: false, //TODO
+                max_samples: max_samples,
+                supports_invalidate_framebuffer: supports_invalidate_framebuffer,
+                supports_compute_shaders: supports_compute_shaders,
+                supports_timer_queries: supports_timer_queries,
+                prefers_ubo_data: prefers_ubo_data,
             },
+            driver_info: driver_info,
+            driver_workarounds: driver_workarounds,
 
             bound_textures: [ TextureId::invalid(); 16 ],
             bound_program: ProgramId(0),
@@ -931,9 +1163,14 @@ impl Device {
             bound_draw_fbo: FBOId(0),
             default_read_fbo: 0,
             default_draw_fbo: 0,
+            default_target_redirect: None,
 
             textures: HashMap::with_hasher(Default::default()),
+            free_textures: HashMap::with_hasher(Default::default()),
+            deleted_textures: Vec::new(),
+            retiring_textures: VecDeque::new(),
             programs: HashMap::with_hasher(Default::default()),
+            compute_programs: HashMap::with_hasher(Default::default()),
             vaos: HashMap::with_hasher(Default::default()),
 
             shader_preamble: shader_preamble,
@@ -943,6 +1180,11 @@ impl Device {
 
             max_texture_size: max_texture_size,
             frame_id: FrameId(0),
+
+            upload_staging: UploadStaging::new(),
+            readback_pbos: Vec::new(),
+            blit_fbo: None,
+            multisampled_target: None,
         }
     }
 
@@ -950,6 +1192,32 @@ impl Device {
         &*self.gl
     }
 
+    /// Releases the GL objects owned by the device itself. Textures,
+    /// programs and VAOs are released by their owners.
+    pub fn deinit(&mut self) {
+        let pbos: Vec<gl::GLuint> = self.upload_staging.pbos.drain(..)
+                                        .chain(self.readback_pbos.drain(..))
+                                        .map(|pbo| pbo.0)
+                                        .collect();
+        if !pbos.is_empty() {
+            self.gl.delete_buffers(&pbos);
+        }
+        if let Some(fbo) = self.blit_fbo.take() {
+            self.gl.delete_framebuffers(&[fbo.0]);
+        }
+        for (fence, _) in self.retiring_textures.drain(..) {
+            self.gl.delete_sync(fence);
+        }
+        self.delete_multisampled_target();
+    }
+
+    fn delete_multisampled_target(&mut self) {
+        if let Some(target) = self.multisampled_target.take() {
+            self.gl.delete_framebuffers(&[target.fbo.0]);
+            self.gl.delete_renderbuffers(&target.renderbuffers);
+        }
+    }
+
     pub fn rc_gl(&self) -> &Rc<gl::Gl> {
         &self.gl
     }
@@ -962,6 +1230,20 @@ impl Device {
         &self.capabilities
     }
 
+    pub fn driver_info(&self) -> &DriverInfo {
+        &self.driver_info
+    }
+
+    pub fn driver_workarounds(&self) -> &DriverWorkarounds {
+        &self.driver_workarounds
+    }
+
+    /// Replaces the workarounds picked from the table of known driver
+    /// problems, for drivers the table gets wrong.
+    pub fn set_driver_workarounds(&mut self, workarounds: DriverWorkarounds) {
+        self.driver_workarounds = workarounds;
+    }
+
     pub fn compile_shader(gl: &gl::Gl,
                           name: &str,
                           source_str: &str,
@@ -971,7 +1253,11 @@ impl Device {
         debug!("compile {:?}", name);
 
         let mut s = String::new();
-        s.push_str(get_shader_version(gl));
+        if shader_type == gl::COMPUTE_SHADER {
+            s.push_str(COMPUTE_SHADER_VERSION_GL);
+        } else {
+            s.push_str(get_shader_version(gl));
+        }
         for prefix in shader_preamble {
             s.push_str(&prefix);
         }
@@ -1005,6 +1291,23 @@ impl Device {
         let default_draw_fbo = self.gl.get_integer_v(gl::DRAW_FRAMEBUFFER_BINDING);
         self.default_draw_fbo = default_draw_fbo as gl::GLuint;
 
+        // FBO state
+        self.bound_read_fbo = FBOId(self.default_read_fbo);
+        self.bound_draw_fbo = FBOId(self.default_draw_fbo);
+        self.default_target_redirect = None;
+
+        self.reset_state();
+        self.release_retired_textures();
+
+        self.frame_id
+    }
+
+    /// Puts the context back into the state the device expects, after code
+    /// outside of it has made its own bindings in the middle of a frame.
+    /// The framebuffers the device last bound are bound again.
+    pub fn reset_state(&mut self) {
+        debug_assert!(self.inside_frame);
+
         // Texture state
         for i in 0..self.bound_textures.len() {
             self.bound_textures[i] = TextureId::invalid();
@@ -1021,22 +1324,21 @@ impl Device {
         self.clear_vertex_array();
 
         // FBO state
-        self.bound_read_fbo = FBOId(self.default_read_fbo);
-        self.bound_draw_fbo = FBOId(self.default_draw_fbo);
+        self.gl.bind_framebuffer(gl::READ_FRAMEBUFFER, self.bound_read_fbo.0);
+        self.gl.bind_framebuffer(gl::DRAW_FRAMEBUFFER, self.bound_draw_fbo.0);
 
         // Pixel op state
         self.gl.pixel_store_i(gl::UNPACK_ALIGNMENT, 1);
 
         // Default is sampler 0, always
         self.gl.active_texture(gl::TEXTURE0);
-
-        self.frame_id
     }
 
     pub fn bind_texture(&mut self,
                         sampler: TextureSampler,
                         texture_id: TextureId) {
         debug_assert!(self.inside_frame);
+        self.flush_texture_uploads();
 
         let sampler_index = sampler as usize;
         if self.bound_textures[sampler_index] != texture_id {
@@ -1049,8 +1351,10 @@ impl Device {
 
     pub fn bind_read_target(&mut self, texture_id: Option<(TextureId, i32)>) {
         debug_assert!(self.inside_frame);
+        self.flush_texture_uploads();
 
-        let fbo_id = texture_id.map_or(FBOId(self.default_read_fbo), |texture_id| {
+        let default_fbo = self.default_target_redirect.unwrap_or(FBOId(self.default_read_fbo));
+        let fbo_id = texture_id.map_or(default_fbo, |texture_id| {
             self.textures.get(&texture_id.0).unwrap().fbo_ids[texture_id.1 as usize]
         });
 
@@ -1064,8 +1368,10 @@ impl Device {
                             texture_id: Option<(TextureId, i32)>,
                             dimensions: Option<DeviceUintSize>) {
         debug_assert!(self.inside_frame);
+        self.flush_texture_uploads();
 
-        let fbo_id = texture_id.map_or(FBOId(self.default_draw_fbo), |texture_id| {
+        let default_fbo = self.default_target_redirect.unwrap_or(FBOId(self.default_draw_fbo));
+        let fbo_id = texture_id.map_or(default_fbo, |texture_id| {
             self.textures.get(&texture_id.0).unwrap().fbo_ids[texture_id.1 as usize]
         });
 
@@ -1079,6 +1385,162 @@ impl Device {
         }
     }
 
+    /// Makes drawing to and reading from no target use a layer of a render
+    /// target instead of the framebuffer that was current in `begin_frame`,
+    /// until this is called again with `None`. The caller then draws the
+    /// render target to the framebuffer itself.
+    pub fn redirect_default_target(&mut self, target: Option<(TextureId, i32)>) {
+        debug_assert!(self.inside_frame);
+
+        let fbo_id = target.map(|(texture_id, layer)| {
+            self.textures.get(&texture_id).unwrap().fbo_ids[layer as usize]
+        });
+        self.default_target_redirect = fbo_id;
+    }
+
+    /// Binds multisampled color and depth buffers of `dimensions` to draw
+    /// to, with `sample_count` samples per pixel. What is drawn is copied to
+    /// a render target with `resolve_multisampled_target`.
+    pub fn bind_multisampled_draw_target(&mut self,
+                                         dimensions: DeviceUintSize,
+                                         sample_count: u32) {
+        debug_assert!(self.inside_frame);
+        debug_assert!(sample_count > 1 && sample_count <= self.capabilities.max_samples);
+        self.flush_texture_uploads();
+
+        let is_allocated = self.multisampled_target.as_ref().map_or(false, |target| {
+            target.size == dimensions && target.sample_count == sample_count
+        });
+
+        if !is_allocated {
+            self.delete_multisampled_target();
+
+            let fbo = FBOId(self.gl.gen_framebuffers(1)[0]);
+            let renderbuffers = self.gl.gen_renderbuffers(2);
+            fbo.bind(self.gl(), FBOTarget::Draw);
+
+            let attachments = [(gl::RGBA8, gl::COLOR_ATTACHMENT0),
+                               (gl::DEPTH_COMPONENT24, gl::DEPTH_ATTACHMENT)];
+            for (&renderbuffer, &(format, attachment)) in renderbuffers.iter().zip(attachments.iter()) {
+                self.gl.bind_renderbuffer(gl::RENDERBUFFER, renderbuffer);
+                self.gl.renderbuffer_storage_multisample(gl::RENDERBUFFER,
+                                                         sample_count as gl::GLsizei,
+                                                         format,
+                                                         dimensions.width as gl::GLsizei,
+                                                         dimensions.height as gl::GLsizei);
+                self.gl.framebuffer_renderbuffer(gl::DRAW_FRAMEBUFFER,
+                                                 attachment,
+                                                 gl::RENDERBUFFER,
+                                                 renderbuffer);
+            }
+
+            self.multisampled_target = Some(MultisampledTarget {
+                fbo: fbo,
+                renderbuffers: renderbuffers,
+                size: dimensions,
+                sample_count: sample_count,
+            });
+            self.bound_draw_fbo = fbo;
+        }
+
+        let fbo = self.multisampled_target.as_ref().unwrap().fbo;
+        if self.bound_draw_fbo != fbo {
+            self.bound_draw_fbo = fbo;
+            fbo.bind(self.gl(), FBOTarget::Draw);
+        }
+
+        self.gl.viewport(0, 0, dimensions.width as gl::GLint, dimensions.height as gl::GLint);
+    }
+
+    /// Averages the samples of `rect` of the multisampled buffers into a
+    /// layer of a render target, which is left bound to draw to.
+    pub fn resolve_multisampled_target(&mut self,
+                                       dest: (TextureId, i32),
+                                       rect: DeviceIntRect) {
+        debug_assert!(self.inside_frame);
+
+        let fbo = self.multisampled_target
+                      .as_ref()
+                      .expect("No multisampled target to resolve!")
+                      .fbo;
+        if self.bound_read_fbo != fbo {
+            self.bound_read_fbo = fbo;
+            fbo.bind(self.gl(), FBOTarget::Read);
+        }
+        self.bind_draw_target(Some(dest), None);
+
+        self.gl.blit_framebuffer(rect.origin.x,
+                                 rect.origin.y,
+                                 rect.origin.x + rect.size.width,
+                                 rect.origin.y + rect.size.height,
+                                 rect.origin.x,
+                                 rect.origin.y,
+                                 rect.origin.x + rect.size.width,
+                                 rect.origin.y + rect.size.height,
+                                 gl::COLOR_BUFFER_BIT,
+                                 gl::NEAREST);
+
+        // The samples are only read by the resolve.
+        self.invalidate_framebuffer(gl::READ_FRAMEBUFFER, fbo, true, true);
+    }
+
+    /// Tells the driver that the depth buffer of the bound draw target won't
+    /// be read again. Tiled GPUs can then drop it instead of writing it back
+    /// to memory.
+    pub fn invalidate_depth_target(&self) {
+        debug_assert!(self.inside_frame);
+        self.invalidate_framebuffer(gl::DRAW_FRAMEBUFFER, self.bound_draw_fbo, false, true);
+    }
+
+    /// Tells the driver that the contents of all the layers of a render
+    /// target won't be read again, until they are drawn to anew.
+    pub fn invalidate_render_target(&mut self, texture_id: TextureId) {
+        debug_assert!(self.inside_frame);
+        if !self.capabilities.supports_invalidate_framebuffer {
+            return;
+        }
+
+        let fbo_ids = self.textures.get(&texture_id).unwrap().fbo_ids.clone();
+        for fbo_id in fbo_ids {
+            fbo_id.bind(self.gl(), FBOTarget::Read);
+            self.invalidate_framebuffer(gl::READ_FRAMEBUFFER, fbo_id, true, true);
+        }
+        self.bound_read_fbo.bind(self.gl(), FBOTarget::Read);
+    }
+
+    fn invalidate_framebuffer(&self,
+                              target: gl::GLenum,
+                              fbo_id: FBOId,
+                              color: bool,
+                              depth: bool) {
+        if !self.capabilities.supports_invalidate_framebuffer {
+            return;
+        }
+
+        // The window system framebuffer names its buffers differently.
+        let (color_attachment, depth_attachment) = if fbo_id == FBOId(0) {
+            (gl::COLOR, gl::DEPTH)
+        } else {
+            (gl::COLOR_ATTACHMENT0, gl::DEPTH_ATTACHMENT)
+        };
+        let mut attachments = Vec::with_capacity(2);
+        if color {
+            attachments.push(color_attachment);
+        }
+        if depth {
+            attachments.push(depth_attachment);
+        }
+        self.gl.invalidate_framebuffer(target, &attachments);
+    }
+
+    pub fn set_viewport(&mut self, rect: DeviceIntRect) {
+        debug_assert!(self.inside_frame);
+        self.gl.viewport(rect.origin.x,
+                         rect.origin.y,
+                         rect.size.width,
+                         rect.size.height);
+    }
+
     pub fn bind_program(&mut self,
                         program_id: ProgramId,
                         projection: &Matrix4D<f32>) {
@@ -1089,18 +1551,34 @@ impl Device {
             program_id.bind(&*self.gl);
         }
 
-        let program = self.programs.get(&program_id).unwrap();
-        self.set_uniforms(program,
-                          projection,
-                          self.device_pixel_ratio);
+        let program = self.programs.get_mut(&program_id).unwrap();
+        Device::set_uniforms(&*self.gl,
+                             program,
+                             projection,
+                             self.device_pixel_ratio);
     }
 
     pub fn create_texture_ids(&mut self,
                               count: i32,
                               target: TextureTarget) -> Vec<TextureId> {
-        let id_list = self.gl.gen_textures(count);
         let mut texture_ids = Vec::new();
 
+        if let Some(free_list) = self.free_textures.get_mut(&target.to_gl_target()) {
+            while texture_ids.len() < count as usize {
+                match free_list.pop() {
+                    Some(texture_id) => texture_ids.push(texture_id),
+                    None => break,
+                }
+            }
+        }
+
+        let remaining = count - texture_ids.len() as i32;
+        if remaining <= 0 {
+            return texture_ids;
+        }
+
+        let id_list = self.gl.gen_textures(remaining);
+
         for id in id_list {
             let texture_id = TextureId {
                 name: id,
@@ -1116,6 +1594,7 @@ impl Device {
                 filter: TextureFilter::Nearest,
                 mode: RenderTargetMode::None,
                 fbo_ids: vec![],
+                depth_rb_ids: vec![],
             };
 
             debug_assert!(self.textures.contains_key(&texture_id) == false);
@@ -1167,6 +1646,36 @@ impl Device {
                               pixels);
     }
 
+    /// Sets the sampling filter of a texture owned by the embedder, and
+    /// returns the filters it had, to be put back with
+    /// `restore_texture_filter` once the frame is done drawing with it.
+    pub fn override_texture_filter(&mut self,
+                                   texture_id: TextureId,
+                                   filter: TextureFilter) -> SavedTextureFilter {
+        debug_assert!(self.inside_frame);
+        self.bind_texture(DEFAULT_TEXTURE, texture_id);
+
+        let saved = SavedTextureFilter {
+            texture_id: texture_id,
+            min_filter: self.gl.get_tex_parameter_iv(texture_id.target, gl::TEXTURE_MIN_FILTER),
+            mag_filter: self.gl.get_tex_parameter_iv(texture_id.target, gl::TEXTURE_MAG_FILTER),
+        };
+        let filter = match filter {
+            TextureFilter::Nearest => gl::NEAREST,
+            TextureFilter::Linear => gl::LINEAR,
+        };
+        self.gl.tex_parameter_i(texture_id.target, gl::TEXTURE_MAG_FILTER, filter as gl::GLint);
+        self.gl.tex_parameter_i(texture_id.target, gl::TEXTURE_MIN_FILTER, filter as gl::GLint);
+        saved
+    }
+
+    pub fn restore_texture_filter(&mut self, saved: SavedTextureFilter) {
+        debug_assert!(self.inside_frame);
+        self.bind_texture(DEFAULT_TEXTURE, saved.texture_id);
+        self.gl.tex_parameter_i(saved.texture_id.target, gl::TEXTURE_MAG_FILTER, saved.mag_filter);
+        self.gl.tex_parameter_i(saved.texture_id.target, gl::TEXTURE_MIN_FILTER, saved.min_filter);
+    }
+
     pub fn init_texture(&mut self,
                         texture_id: TextureId,
                         width: u32,
@@ -1175,16 +1684,39 @@ impl Device {
                         filter: TextureFilter,
                         mode: RenderTargetMode,
                         pixels: Option<&[u8]>) {
+        self.init_texture_with_stride(texture_id,
+                                      width,
+                                      height,
+                                      format,
+                                      filter,
+                                      mode,
+                                      None,
+                                      pixels);
+    }
+
+    /// Like `init_texture`, but the rows of `pixels` start `stride` bytes
+    /// apart rather than being tightly packed.
+    pub fn init_texture_with_stride(&mut self,
+                                    texture_id: TextureId,
+                                    width: u32,
+                                    height: u32,
+                                    format: ImageFormat,
+                                    filter: TextureFilter,
+                                    mode: RenderTargetMode,
+                                    stride: Option<u32>,
+                                    pixels: Option<&[u8]>) {
         debug_assert!(self.inside_frame);
 
-        {
+        let resized = {
             let texture = self.textures.get_mut(&texture_id).expect("Didn't find texture!");
+            let resized = texture.width != width || texture.height != height;
             texture.format = format;
             texture.width = width;
             texture.height = height;
             texture.filter = filter;
             texture.mode = mode;
-        }
+            resized
+        };
 
         let (internal_format, gl_format) = gl_texture_formats_for_image_format(self.gl(), format);
         let type_ = gl_type_for_texture_format(format);
@@ -1203,6 +1735,12 @@ impl Device {
                 self.create_fbo_for_texture_if_necessary(texture_id, None);
             }
             RenderTargetMode::LayerRenderTarget(layer_count) => {
+                // The layers and their depth buffers are only allocated
+                // along with their framebuffers, so a render target of a
+                // new size starts over.
+                if resized {
+                    self.delete_render_target_fbos(texture_id);
+                }
                 self.bind_texture(DEFAULT_TEXTURE, texture_id);
                 self.set_texture_parameters(texture_id.target, filter);
                 self.create_fbo_for_texture_if_necessary(texture_id, Some(layer_count));
@@ -1210,15 +1748,41 @@ impl Device {
             RenderTargetMode::None => {
                 self.bind_texture(DEFAULT_TEXTURE, texture_id);
                 self.set_texture_parameters(texture_id.target, filter);
+                let bpp = format.bytes_per_pixel().unwrap();
+                let expand_a8 = format == ImageFormat::A8 &&
+                                cfg!(any(target_arch="arm", target_arch="aarch64"));
+                // Padded rows are uploaded in place with UNPACK_ROW_LENGTH,
+                // unless they can't be described in whole pixels or need
+                // expanding, in which case they are repacked first.
+                let row_length = match stride {
+                    Some(stride) if stride != width * bpp && stride % bpp == 0 && !expand_a8 => {
+                        Some(stride / bpp)
+                    }
+                    _ => None,
+                };
+                let repacked_data: Vec<u8>;
+                let pixels = match (pixels, stride) {
+                    (Some(pixels), Some(stride)) if row_length.is_none() && stride != width * bpp => {
+                        repacked_data = repack_rows(pixels, width * bpp, height, stride);
+                        Some(repacked_data.as_slice())
+                    }
+                    _ => pixels,
+                };
                 let expanded_data: Vec<u8>;
-                let actual_pixels = if pixels.is_some() &&
-                                       format == ImageFormat::A8 &&
-                                       cfg!(any(target_arch="arm", target_arch="aarch64")) {
+                let actual_pixels = if pixels.is_some() && expand_a8 {
                     expanded_data = pixels.unwrap().iter().flat_map(|&byte| repeat(byte).take(4)).collect();
                     Some(expanded_data.as_slice())
                 } else {
                     pixels
                 };
+                if let Some(row_length) = row_length {
+                    self.gl.pixel_store_i(gl::UNPACK_ROW_LENGTH, row_length as gl::GLint);
+                }
+                let upload_bpp = if expand_a8 { 4 } else { bpp };
+                let alignment = unpack_alignment_for_row(row_length.unwrap_or(width) * upload_bpp);
+                if alignment != 4 {
+                    self.gl.pixel_store_i(gl::UNPACK_ALIGNMENT, alignment);
+                }
                 self.upload_texture_image(texture_id.target,
                                           width,
                                           height,
@@ -1226,14 +1790,16 @@ impl Device {
                                           gl_format,
                                           type_,
                                           actual_pixels);
+                if alignment != 4 {
+                    self.gl.pixel_store_i(gl::UNPACK_ALIGNMENT, 4);
+                }
+                if row_length.is_some() {
+                    self.gl.pixel_store_i(gl::UNPACK_ROW_LENGTH, 0 as gl::GLint);
+                }
             }
         }
     }
 
-    pub fn get_render_target_layer_count(&self, texture_id: TextureId) -> usize {
-        self.textures[&texture_id].fbo_ids.len()
-    }
-
     pub fn create_fbo_for_texture_if_necessary(&mut self,
                                                texture_id: TextureId,
                                                layer_count: Option<i32>) {
@@ -1269,7 +1835,8 @@ impl Device {
                 let new_fbos = self.gl.gen_framebuffers(needed_layer_count);
                 texture.fbo_ids.extend(new_fbos.into_iter().map(|id| FBOId(id)));
 
-                for (fbo_index, fbo_id) in texture.fbo_ids.iter().enumerate() {
+                let new_fbo_ids = texture.fbo_ids.iter().enumerate().skip(current_layer_count as usize);
+                for (fbo_index, fbo_id) in new_fbo_ids {
                     self.gl.bind_framebuffer(gl::FRAMEBUFFER, fbo_id.0);
                     self.gl.framebuffer_texture_layer(gl::FRAMEBUFFER,
                                                        gl::COLOR_ATTACHMENT0,
@@ -1279,7 +1846,6 @@ impl Device {
 
                     // TODO(gw): Share depth render buffer between FBOs to
                     //           save memory!
-                    // TODO(gw): Free these renderbuffers on exit!
                     let renderbuffer_ids = self.gl.gen_renderbuffers(1);
                     let depth_rb = renderbuffer_ids[0];
                     self.gl.bind_renderbuffer(gl::RENDERBUFFER, depth_rb);
@@ -1291,6 +1857,7 @@ impl Device {
                                                       gl::DEPTH_ATTACHMENT,
                                                       gl::RENDERBUFFER,
                                                       depth_rb);
+                    texture.depth_rb_ids.push(depth_rb);
                 }
             }
             None => {
@@ -1343,6 +1910,155 @@ impl Device {
                                   gl::LINEAR);
     }
 
+    /// Copies `src_rect` of a texture, such as a texture cache page, to
+    /// `dest_rect` of the main framebuffer, which is `framebuffer_height`
+    /// pixels high. Both rects are from the top left and of the same size,
+    /// so the texels are copied as they are.
+    pub fn blit_texture_to_framebuffer(&mut self,
+                                       texture_id: TextureId,
+                                       src_rect: DeviceIntRect,
+                                       dest_rect: DeviceIntRect,
+                                       framebuffer_height: i32) {
+        debug_assert!(self.inside_frame);
+        debug_assert!(src_rect.size == dest_rect.size);
+        self.flush_texture_uploads();
+
+        let fbo = match self.blit_fbo {
+            Some(fbo) => fbo,
+            None => {
+                let fbo = FBOId(self.gl.gen_framebuffers(1)[0]);
+                self.blit_fbo = Some(fbo);
+                fbo
+            }
+        };
+
+        fbo.bind(self.gl(), FBOTarget::Read);
+        self.gl.framebuffer_texture_2d(gl::READ_FRAMEBUFFER,
+                                       gl::COLOR_ATTACHMENT0,
+                                       texture_id.target,
+                                       texture_id.name,
+                                       0);
+
+        // The framebuffer is bottom-up, so the destination is flipped.
+        self.gl.blit_framebuffer(src_rect.origin.x,
+                                 src_rect.origin.y,
+                                 src_rect.origin.x + src_rect.size.width,
+                                 src_rect.origin.y + src_rect.size.height,
+                                 dest_rect.origin.x,
+                                 framebuffer_height - dest_rect.origin.y,
+                                 dest_rect.origin.x + dest_rect.size.width,
+                                 framebuffer_height - dest_rect.origin.y - dest_rect.size.height,
+                                 gl::COLOR_BUFFER_BIT,
+                                 gl::NEAREST);
+
+        self.bound_read_fbo.bind(self.gl(), FBOTarget::Read);
+    }
+
+    /// Copies `src_rect` of a render target to `dest_rect` of a texture that
+    /// the device didn't create, such as an external image, stretching it
+    /// to fit. Rows are copied in the order they are in, so the top row of
+    /// a render target stays the first row.
+    pub fn blit_render_target_to_texture(&mut self,
+                                         src_texture: (TextureId, i32),
+                                         src_rect: DeviceIntRect,
+                                         dest_texture: TextureId,
+                                         dest_rect: DeviceIntRect) {
+        debug_assert!(self.inside_frame);
+        self.bind_read_target(Some(src_texture));
+
+        let fbo = match self.blit_fbo {
+            Some(fbo) => fbo,
+            None => {
+                let fbo = FBOId(self.gl.gen_framebuffers(1)[0]);
+                self.blit_fbo = Some(fbo);
+                fbo
+            }
+        };
+
+        fbo.bind(self.gl(), FBOTarget::Draw);
+        self.gl.framebuffer_texture_2d(gl::DRAW_FRAMEBUFFER,
+                                       gl::COLOR_ATTACHMENT0,
+                                       dest_texture.target,
+                                       dest_texture.name,
+                                       0);
+
+        self.gl.blit_framebuffer(src_rect.origin.x,
+                                 src_rect.origin.y,
+                                 src_rect.origin.x + src_rect.size.width,
+                                 src_rect.origin.y + src_rect.size.height,
+                                 dest_rect.origin.x,
+                                 dest_rect.origin.y,
+                                 dest_rect.origin.x + dest_rect.size.width,
+                                 dest_rect.origin.y + dest_rect.size.height,
+                                 gl::COLOR_BUFFER_BIT,
+                                 gl::LINEAR);
+
+        // The texture isn't ours, so don't keep it attached.
+        self.gl.framebuffer_texture_2d(gl::DRAW_FRAMEBUFFER,
+                                       gl::COLOR_ATTACHMENT0,
+                                       dest_texture.target,
+                                       0,
+                                       0);
+        self.bound_draw_fbo.bind(self.gl(), FBOTarget::Draw);
+    }
+
+    /// Copies the part of a texture between `uv0` (the top left corner, in
+    /// texels) and `uv1` to `dest_rect` of the currently bound framebuffer,
+    /// whose origin is at the top left. This is for textures that the
+    /// device didn't create, such as external images, and is used outside
+    /// of `begin_frame` and `end_frame`, when drawing into surfaces other
+    /// than the main framebuffer.
+    pub fn blit_external_texture(&mut self,
+                                 texture_id: TextureId,
+                                 uv0: DevicePoint,
+                                 uv1: DevicePoint,
+                                 dest_rect: DeviceIntRect,
+                                 filter: TextureFilter) {
+        debug_assert!(!self.inside_frame);
+
+        let read_fbo = self.gl.get_integer_v(gl::READ_FRAMEBUFFER_BINDING) as gl::GLuint;
+        let fbo = match self.blit_fbo {
+            Some(fbo) => fbo,
+            None => {
+                let fbo = FBOId(self.gl.gen_framebuffers(1)[0]);
+                self.blit_fbo = Some(fbo);
+                fbo
+            }
+        };
+
+        fbo.bind(self.gl(), FBOTarget::Read);
+        self.gl.framebuffer_texture_2d(gl::READ_FRAMEBUFFER,
+                                       gl::COLOR_ATTACHMENT0,
+                                       texture_id.target,
+                                       texture_id.name,
+                                       0);
+
+        let filter = match filter {
+            TextureFilter::Nearest => gl::NEAREST,
+            TextureFilter::Linear => gl::LINEAR,
+        };
+
+        // The framebuffer is bottom-up, so the destination is flipped.
+        self.gl.blit_framebuffer(uv0.x as gl::GLint,
+                                 uv0.y as gl::GLint,
+                                 uv1.x as gl::GLint,
+                                 uv1.y as gl::GLint,
+                                 dest_rect.origin.x,
+                                 dest_rect.origin.y + dest_rect.size.height,
+                                 dest_rect.origin.x + dest_rect.size.width,
+                                 dest_rect.origin.y,
+                                 gl::COLOR_BUFFER_BIT,
+                                 filter);
+
+        // The texture isn't ours, so don't keep it attached.
+        self.gl.framebuffer_texture_2d(gl::READ_FRAMEBUFFER,
+                                       gl::COLOR_ATTACHMENT0,
+                                       texture_id.target,
+                                       0,
+                                       0);
+        self.gl.bind_framebuffer(gl::READ_FRAMEBUFFER, read_fbo);
+    }
+
     pub fn resize_texture(&mut self,
                           texture_id: TextureId,
                           new_width: u32,
@@ -1408,15 +2124,86 @@ impl Device {
                               type_,
                               None);
 
-        if !texture.fbo_ids.is_empty() {
-            let fbo_ids: Vec<_> = texture.fbo_ids.iter().map(|&FBOId(fbo_id)| fbo_id).collect();
-            self.gl.delete_framebuffers(&fbo_ids[..]);
-        }
-
+        texture.delete_fbos();
         texture.format = ImageFormat::Invalid;
         texture.width = 0;
         texture.height = 0;
-        texture.fbo_ids.clear();
+    }
+
+    /// Deletes the framebuffers of a layer render target, so that they are
+    /// created again with its layers.
+    fn delete_render_target_fbos(&mut self, texture_id: TextureId) {
+        let fbo_ids = {
+            let texture = self.textures.get_mut(&texture_id).unwrap();
+            let fbo_ids = texture.fbo_ids.clone();
+            texture.delete_fbos();
+            fbo_ids
+        };
+
+        // A deleted framebuffer name can be handed out again, which the
+        // bound framebuffers must not be mistaken for.
+        if fbo_ids.contains(&self.bound_read_fbo) {
+            self.bound_read_fbo = FBOId(self.default_read_fbo);
+            self.bound_read_fbo.bind(self.gl(), FBOTarget::Read);
+        }
+        if fbo_ids.contains(&self.bound_draw_fbo) {
+            self.bound_draw_fbo = FBOId(self.default_draw_fbo);
+            self.bound_draw_fbo.bind(self.gl(), FBOTarget::Draw);
+        }
+    }
+
+    /// Schedules a texture for deletion once the GPU has finished the
+    /// current frame. The id must not be used by the caller afterwards.
+    pub fn delete_texture(&mut self, texture_id: TextureId) {
+        debug_assert!(!self.deleted_textures.contains(&texture_id));
+        self.deleted_textures.push(texture_id);
+    }
+
+    /// Releases the textures deleted in frames the GPU has completed.
+    fn release_retired_textures(&mut self) {
+        loop {
+            let fence = match self.retiring_textures.front() {
+                Some(&(fence, _)) => fence,
+                None => break,
+            };
+            if self.gl.client_wait_sync(fence, 0, 0) == gl::TIMEOUT_EXPIRED {
+                break;
+            }
+            self.gl.delete_sync(fence);
+
+            let (_, texture_ids) = self.retiring_textures.pop_front().unwrap();
+            for texture_id in texture_ids {
+                self.release_texture(texture_id);
+            }
+        }
+    }
+
+    /// Frees the storage of a texture. The texture object itself is kept
+    /// for reuse by create_texture_ids.
+    fn release_texture(&mut self, texture_id: TextureId) {
+        self.deinit_texture(texture_id);
+
+        let free_list = self.free_textures
+                            .entry(texture_id.target)
+                            .or_insert_with(Vec::new);
+        debug_assert!(!free_list.contains(&texture_id));
+        if free_list.len() < MAX_FREE_TEXTURES_PER_TARGET {
+            free_list.push(texture_id);
+            return;
+        }
+
+        for bound_texture in self.bound_textures.iter_mut() {
+            if *bound_texture == texture_id {
+                *bound_texture = TextureId::invalid();
+            }
+        }
+        self.textures.remove(&texture_id);
+    }
+
+    /// Defines `WR_FEATURE_<feature>` in every shader compiled after this
+    /// call.
+    pub fn add_shader_feature(&mut self, feature: &str) {
+        self.shader_preamble = format!("#define WR_FEATURE_{}\n{}", feature, self.shader_preamble);
     }
 
     pub fn create_program(&mut self,
@@ -1456,6 +2243,9 @@ impl Device {
             id: pid,
             u_transform: -1,
             u_device_pixel_ratio: -1,
+            transform: None,
+            device_pixel_ratio: None,
+            uniform_locations: HashMap::new(),
             vs_source: get_shader_source(&vs_name, &self.resource_override_path),
             fs_source: get_shader_source(&fs_name, &self.resource_override_path),
             prefix: prefix,
@@ -1539,82 +2329,12 @@ impl Device {
 
         program.u_transform = self.gl.get_uniform_location(program.id, "uTransform");
         program.u_device_pixel_ratio = self.gl.get_uniform_location(program.id, "uDevicePixelRatio");
+        program.transform = None;
+        program.device_pixel_ratio = None;
+        program.uniform_locations.clear();
 
         program_id.bind(&*self.gl);
-        let u_color_0 = self.gl.get_uniform_location(program.id, "sColor0");
-        if u_color_0 != -1 {
-            self.gl.uniform_1i(u_color_0, TextureSampler::Color0 as i32);
-        }
-        let u_color1 = self.gl.get_uniform_location(program.id, "sColor1");
-        if u_color1 != -1 {
-            self.gl.uniform_1i(u_color1, TextureSampler::Color1 as i32);
-        }
-        let u_color_2 = self.gl.get_uniform_location(program.id, "sColor2");
-        if u_color_2 != -1 {
-            self.gl.uniform_1i(u_color_2, TextureSampler::Color2 as i32);
-        }
-        let u_noise = self.gl.get_uniform_location(program.id, "sDither");
-        if u_noise != -1 {
-            self.gl.uniform_1i(u_noise, TextureSampler::Dither as i32);
-        }
-        let u_mask = self.gl.get_uniform_location(program.id, "sMask");
-        if u_mask != -1 {
-            self.gl.uniform_1i(u_mask, TextureSampler::Mask as i32);
-        }
-
-        let u_cache_a8 = self.gl.get_uniform_location(program.id, "sCacheA8");
-        if u_cache_a8 != -1 {
-            self.gl.uniform_1i(u_cache_a8, TextureSampler::CacheA8 as i32);
-        }
-        let u_cache_rgba8 = self.gl.get_uniform_location(program.id, "sCacheRGBA8");
-        if u_cache_rgba8 != -1 {
-            self.gl.uniform_1i(u_cache_rgba8, TextureSampler::CacheRGBA8 as i32);
-        }
-
-        let u_layers = self.gl.get_uniform_location(program.id, "sLayers");
-        if u_layers != -1 {
-            self.gl.uniform_1i(u_layers, TextureSampler::Layers as i32);
-        }
-
-        let u_tasks = self.gl.get_uniform_location(program.id, "sRenderTasks");
-        if u_tasks != -1 {
-            self.gl.uniform_1i(u_tasks, TextureSampler::RenderTasks as i32);
-        }
-
-        let u_prim_geom = self.gl.get_uniform_location(program.id, "sPrimGeometry");
-        if u_prim_geom != -1 {
-            self.gl.uniform_1i(u_prim_geom, TextureSampler::Geometry as i32);
-        }
-
-        let u_data16 = self.gl.get_uniform_location(program.id, "sData16");
-        if u_data16 != -1 {
-            self.gl.uniform_1i(u_data16, TextureSampler::Data16 as i32);
-        }
-
-        let u_data32 = self.gl.get_uniform_location(program.id, "sData32");
-        if u_data32 != -1 {
-            self.gl.uniform_1i(u_data32, TextureSampler::Data32 as i32);
-        }
-
-        let u_data64 = self.gl.get_uniform_location(program.id, "sData64");
-        if u_data64 != -1 {
-            self.gl.uniform_1i(u_data64, TextureSampler::Data64 as i32);
-        }
-
-        let u_data128 = self.gl.get_uniform_location(program.id, "sData128");
-        if u_data128 != -1 {
-            self.gl.uniform_1i(u_data128, TextureSampler::Data128    as i32);
-        }
-
-        let u_resource_rects = self.gl.get_uniform_location(program.id, "sResourceRects");
-        if u_resource_rects != -1 {
-            self.gl.uniform_1i(u_resource_rects, TextureSampler::ResourceRects as i32);
-        }
-
-        let u_gradients = self.gl.get_uniform_location(program.id, "sGradients");
-        if u_gradients != -1 {
-            self.gl.uniform_1i(u_gradients, TextureSampler::Gradients as i32);
-        }
+        bind_sampler_uniforms(&*self.gl, program.id);
 
         Ok(())
     }
@@ -1656,9 +2376,126 @@ impl Device {
         }
     }*/
 
-    pub fn get_uniform_location(&self, program_id: ProgramId, name: &str) -> UniformLocation {
-        let ProgramId(program_id) = program_id;
-        UniformLocation(self.gl.get_uniform_location(program_id, name))
+    /// Compiles `<base_filename>.cs.glsl` into a compute program. Only
+    /// available when `Capabilities::supports_compute_shaders` is set.
+    pub fn create_compute_program(&mut self,
+                                  base_filename: &str,
+                                  prefix: Option<String>) -> Result<ProgramId, ShaderError> {
+        debug_assert!(self.inside_frame);
+        debug_assert!(self.capabilities.supports_compute_shaders);
+
+        let mut cs_name = String::from(base_filename);
+        cs_name.push_str(".cs");
+        let cs_source = get_shader_source(&cs_name, &self.resource_override_path);
+
+        let mut preamble = vec!["#define WR_COMPUTE_SHADER\n".to_owned()];
+        if let Some(prefix) = prefix {
+            preamble.push(prefix);
+        }
+        preamble.push(self.shader_preamble.to_owned());
+
+        let cs_id = try!{ Device::compile_shader(&*self.gl,
+                                                 base_filename,
+                                                 &cs_source,
+                                                 gl::COMPUTE_SHADER,
+                                                 &preamble) };
+
+        let pid = self.gl.create_program();
+        self.gl.attach_shader(pid, cs_id);
+        self.gl.link_program(pid);
+        self.gl.detach_shader(pid, cs_id);
+        self.gl.delete_shader(cs_id);
+        if self.gl.get_program_iv(pid, gl::LINK_STATUS) == (0 as gl::GLint) {
+            let error_log = self.gl.get_program_info_log(pid);
+            println!("Failed to link compute program: {}", error_log);
+            self.gl.delete_program(pid);
+            return Err(ShaderError::Link(error_log));
+        }
+
+        let program_id = ProgramId(pid);
+        program_id.bind(&*self.gl);
+        self.bound_program = program_id;
+        bind_sampler_uniforms(&*self.gl, pid);
+
+        self.compute_programs.insert(program_id, ComputeProgram {
+            gl: Rc::clone(&self.gl),
+            id: pid,
+        });
+
+        Ok(program_id)
+    }
+
+    pub fn bind_compute_program(&mut self, program_id: ProgramId) {
+        debug_assert!(self.inside_frame);
+        debug_assert!(self.compute_programs.contains_key(&program_id));
+
+        if self.bound_program != program_id {
+            self.bound_program = program_id;
+            program_id.bind(&*self.gl);
+        }
+    }
+
+    /// Binds a layer of an RGBA8 texture array to image `unit`, for compute
+    /// shaders to write to.
+    pub fn bind_image_layer_for_write(&mut self, unit: u32, texture_id: TextureId, layer: i32) {
+        debug_assert!(self.inside_frame);
+        self.gl.bind_image_texture(unit,
+                                   texture_id.name,
+                                   0,
+                                   false,
+                                   layer,
+                                   gl::WRITE_ONLY,
+                                   gl::RGBA8);
+    }
+
+    pub fn dispatch_compute(&mut self, groups_x: u32, groups_y: u32) {
+        debug_assert!(self.inside_frame);
+        self.gl.dispatch_compute(groups_x, groups_y, 1);
+    }
+
+    /// Makes the image writes of earlier dispatches visible to texture
+    /// fetches and to rendering.
+    pub fn compute_barrier(&mut self) {
+        debug_assert!(self.inside_frame);
+        self.gl.memory_barrier(gl::TEXTURE_FETCH_BARRIER_BIT |
+                               gl::FRAMEBUFFER_BARRIER_BIT |
+                               gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+    }
+
+    /// Looks up a uniform of a program. The locations of the programs
+    /// created with `create_program` are cached, so this is cheap to call
+    /// every frame.
+    pub fn get_uniform_location(&mut self, program_id: ProgramId, name: &str) -> UniformLocation {
+        let gl = &*self.gl;
+        match self.programs.get_mut(&program_id) {
+            Some(program) => {
+                if let Some(&location) = program.uniform_locations.get(name) {
+                    return UniformLocation(location);
+                }
+                let location = gl.get_uniform_location(program.id, name);
+                program.uniform_locations.insert(name.to_owned(), location);
+                UniformLocation(location)
+            }
+            None => UniformLocation(gl.get_uniform_location(program_id.0, name)),
+        }
+    }
+
+    pub fn set_uniform_1f(&self, uniform: UniformLocation, x: f32) {
+        debug_assert!(self.inside_frame);
+        let UniformLocation(location) = uniform;
+        self.gl.uniform_1f(location, x);
+    }
+
+    pub fn set_uniform_1i(&self, uniform: UniformLocation, x: i32) {
+        debug_assert!(self.inside_frame);
+        let UniformLocation(location) = uniform;
+        self.gl.uniform_1i(location, x);
+    }
+
+    pub fn set_uniform_4i(&self, uniform: UniformLocation, x: i32, y: i32, z: i32, w: i32) {
+        debug_assert!(self.inside_frame);
+        let UniformLocation(location) = uniform;
+        self.gl.uniform_4i(location, x, y, z, w);
     }
 
     pub fn set_uniform_2f(&self, uniform: UniformLocation, x: f32, y: f32) {
@@ -1667,32 +2504,28 @@ impl Device {
         self.gl.uniform_2f(location, x, y);
     }
 
-    fn set_uniforms(&self,
-                    program: &Program,
+    /// Sets a mat3 uniform from nine values in column major order.
+    pub fn set_uniform_matrix_3f(&self, uniform: UniformLocation, matrix: &[f32; 9]) {
+        debug_assert!(self.inside_frame);
+        let UniformLocation(location) = uniform;
+        self.gl.uniform_matrix_3fv(location, false, matrix);
+    }
+
+    // Only the uniforms that differ from the values the program already
+    // has are set.
+    fn set_uniforms(gl: &gl::Gl,
+                    program: &mut Program,
                     transform: &Matrix4D<f32>,
                     device_pixel_ratio: f32) {
-        debug_assert!(self.inside_frame);
-        self.gl.uniform_matrix_4fv(program.u_transform,
-                               false,
-                               &transform.to_row_major_array());
-        self.gl.uniform_1f(program.u_device_pixel_ratio, device_pixel_ratio);
-    }
-
-    fn update_image_for_2d_texture(&mut self,
-                                   target: gl::GLuint,
-                                   x0: gl::GLint,
-                                   y0: gl::GLint,
-                                   width: gl::GLint,
-                                   height: gl::GLint,
-                                   format: gl::GLuint,
-                                   data: &[u8]) {
-        self.gl.tex_sub_image_2d(target,
-                                  0,
-                                  x0, y0,
-                                  width, height,
-                                  format,
-                                  gl::UNSIGNED_BYTE,
-                                  data);
+        let transform = transform.to_row_major_array();
+        if program.transform != Some(transform) {
+            gl.uniform_matrix_4fv(program.u_transform, false, &transform);
+            program.transform = Some(transform);
+        }
+        if program.device_pixel_ratio != Some(device_pixel_ratio) {
+            gl.uniform_1f(program.u_device_pixel_ratio, device_pixel_ratio);
+            program.device_pixel_ratio = Some(device_pixel_ratio);
+        }
     }
 
     pub fn update_texture(&mut self,
@@ -1706,10 +2539,20 @@ impl Device {
         debug_assert!(self.inside_frame);
 
         let mut expanded_data = Vec::new();
+        let repacked_data: Vec<u8>;
+        let mut stride = stride;
+        let mut data = data;
 
-        let (gl_format, bpp, data) = match self.textures.get(&texture_id).unwrap().format {
+        let format = self.textures.get(&texture_id).unwrap().format;
+        let (gl_format, bpp, data) = match format {
             ImageFormat::A8 => {
                 if cfg!(any(target_arch="arm", target_arch="aarch64")) {
+                    // The stride is in source bytes, so drop the padding
+                    // before each texel is expanded to four.
+                    if let Some(value) = stride.take() {
+                        repacked_data = repack_rows(data, width, height, value);
+                        data = repacked_data.as_slice();
+                    }
                     for byte in data {
                         expanded_data.push(*byte);
                         expanded_data.push(*byte);
@@ -1723,7 +2566,8 @@ impl Device {
             }
             ImageFormat::RGB8 => (gl::RGB, 3, data),
             ImageFormat::RGBA8 => (get_gl_format_bgra(self.gl()), 4, data),
-            ImageFormat::Invalid | ImageFormat::RGBAF32 => unreachable!(),
+            ImageFormat::RGBAF32 => (gl::RGBA, 16, data),
+            ImageFormat::Invalid => unreachable!(),
         };
 
         let row_length = match stride {
@@ -1738,25 +2582,182 @@ impl Device {
         assert!(data.len() as u32 >= len);
         let data = &data[0..len as usize];
 
-        if let Some(..) = stride {
-            self.gl.pixel_store_i(gl::UNPACK_ROW_LENGTH, row_length as gl::GLint);
+        // The rows of the rect are copied to the staging buffer now, without
+        // the stride padding, and handed to the driver together with the rest
+        // of the frame's updates before the texture is next bound.
+        let offset = self.upload_staging.push(data,
+                                              (width * bpp) as usize,
+                                              height as usize,
+                                              (row_length * bpp) as usize);
+        self.upload_staging.uploads.push(PendingUpload {
+            texture_id: texture_id,
+            x0: x0 as gl::GLint,
+            y0: y0 as gl::GLint,
+            width: width as gl::GLint,
+            height: height as gl::GLint,
+            gl_format: gl_format,
+            gl_type: gl_type_for_texture_format(format),
+            alignment: unpack_alignment_for_row(width * bpp),
+            offset: offset,
+        });
+    }
+
+    /// Issues all texture updates queued by `update_texture` since the last
+    /// flush. The staged pixels go to the driver in one buffer upload, and
+    /// each update then reads its rows from that buffer.
+    ///
+    /// This happens implicitly whenever a texture or render target is bound,
+    /// so callers only need it to push the uploads out early.
+    pub fn flush_texture_uploads(&mut self) {
+        if self.upload_staging.uploads.is_empty() {
+            return;
         }
 
-        self.bind_texture(DEFAULT_TEXTURE, texture_id);
-        self.update_image_for_2d_texture(texture_id.target,
-                                         x0 as gl::GLint,
-                                         y0 as gl::GLint,
-                                         width as gl::GLint,
-                                         height as gl::GLint,
-                                         gl_format,
-                                         data);
+        let uploads = mem::replace(&mut self.upload_staging.uploads, Vec::new());
+        let pbo = self.upload_staging.next_pbo(&*self.gl);
+
+        self.gl.bind_buffer(gl::PIXEL_UNPACK_BUFFER, pbo.0);
+        gl::buffer_data(self.gl(), gl::PIXEL_UNPACK_BUFFER, &self.upload_staging.data, gl::STREAM_DRAW);
+
+        for upload in uploads {
+            if upload.alignment != 4 {
+                self.gl.pixel_store_i(gl::UNPACK_ALIGNMENT, upload.alignment);
+            }
+
+            self.bind_texture(DEFAULT_TEXTURE, upload.texture_id);
+            self.gl.tex_sub_image_2d_pbo(upload.texture_id.target,
+                                         0,
+                                         upload.x0,
+                                         upload.y0,
+                                         upload.width,
+                                         upload.height,
+                                         upload.gl_format,
+                                         upload.gl_type,
+                                         upload.offset);
+
+            if upload.alignment != 4 {
+                self.gl.pixel_store_i(gl::UNPACK_ALIGNMENT, 4);
+            }
+        }
+
+        self.gl.bind_buffer(gl::PIXEL_UNPACK_BUFFER, 0);
+        self.upload_staging.data.clear();
+    }
+
+    /// Starts copying `rect` of the bound read target, in GL window
+    /// coordinates, into a pixel buffer as RGBA8. Unlike `read_pixels`, this
+    /// returns without waiting for the GPU to finish drawing.
+    pub fn begin_readback(&mut self, rect: DeviceIntRect) -> PendingReadback {
+        debug_assert!(self.inside_frame);
+
+        let pbo = match self.readback_pbos.pop() {
+            Some(pbo) => pbo,
+            None => PBOId(self.gl.gen_buffers(1)[0]),
+        };
+        let size = if rect.size.width > 0 && rect.size.height > 0 {
+            (rect.size.width * rect.size.height * 4) as usize
+        } else {
+            0
+        };
 
-        // Reset row length to 0, otherwise the stride would apply to all texture uploads.
-        if let Some(..) = stride {
-            self.gl.pixel_store_i(gl::UNPACK_ROW_LENGTH, 0 as gl::GLint);
+        // An empty rect has nothing to copy, and completes with no pixels.
+        if size > 0 {
+            self.gl.bind_buffer(gl::PIXEL_PACK_BUFFER, pbo.0);
+            self.gl.buffer_data_untyped(gl::PIXEL_PACK_BUFFER,
+                                        size as gl::GLsizeiptr,
+                                        ptr::null(),
+                                        gl::STREAM_READ);
+            self.gl.read_pixels_into_pbo(rect.origin.x,
+                                         rect.origin.y,
+                                         rect.size.width,
+                                         rect.size.height,
+                                         gl::RGBA,
+                                         gl::UNSIGNED_BYTE);
+            self.gl.bind_buffer(gl::PIXEL_PACK_BUFFER, 0);
+        }
+
+        PendingReadback {
+            pbo: pbo,
+            fence: self.gl.fence_sync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0),
+            size: size,
+        }
+    }
+
+    /// Returns the pixels of a readback, or hands the readback back if the
+    /// GPU hasn't written them yet. With `wait` set, blocks until it has.
+    pub fn end_readback(&mut self,
+                        readback: PendingReadback,
+                        wait: bool) -> ReadbackStatus {
+        let timeout = if wait { gl::TIMEOUT_IGNORED } else { 0 };
+        let status = self.gl.client_wait_sync(readback.fence, gl::SYNC_FLUSH_COMMANDS_BIT, timeout);
+        match status {
+            gl::TIMEOUT_EXPIRED => return ReadbackStatus::Pending(readback),
+            gl::WAIT_FAILED => {
+                warn!("Waiting for a readback failed");
+                self.cancel_readback(readback);
+                return ReadbackStatus::Failed;
+            }
+            _ => {}
+        }
+
+        if readback.size == 0 {
+            self.cancel_readback(readback);
+            return ReadbackStatus::Complete(Vec::new());
+        }
+
+        self.gl.bind_buffer(gl::PIXEL_PACK_BUFFER, readback.pbo.0);
+        let data = self.gl.map_buffer_range(gl::PIXEL_PACK_BUFFER,
+                                            0,
+                                            readback.size as gl::GLsizeiptr,
+                                            gl::MAP_READ_BIT);
+        let pixels = if data.is_null() {
+            warn!("Mapping the pixels of a readback failed");
+            None
+        } else {
+            let pixels = unsafe {
+                slice::from_raw_parts(data as *const u8, readback.size).to_vec()
+            };
+            self.gl.unmap_buffer(gl::PIXEL_PACK_BUFFER);
+            Some(pixels)
+        };
+        self.gl.bind_buffer(gl::PIXEL_PACK_BUFFER, 0);
+
+        self.cancel_readback(readback);
+        match pixels {
+            Some(pixels) => ReadbackStatus::Complete(pixels),
+            None => ReadbackStatus::Failed,
         }
     }
 
+    /// Releases a readback whose pixels are no longer wanted.
+    pub fn cancel_readback(&mut self, readback: PendingReadback) {
+        self.gl.delete_sync(readback.fence);
+        self.readback_pbos.push(readback.pbo);
+    }
+
+    /// Inserts a fence after the commands issued so far.
+    pub fn insert_fence(&mut self) -> GpuFence {
+        GpuFence(self.gl.fence_sync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0))
+    }
+
+    /// Releases a fence once it has signaled, or hands it back if the GPU
+    /// hasn't got to it yet. With `wait` set, blocks until it has.
+    pub fn end_fence(&mut self, fence: GpuFence, wait: bool) -> Result<(), GpuFence> {
+        let timeout = if wait { gl::TIMEOUT_IGNORED } else { 0 };
+        let status = self.gl.client_wait_sync(fence.0, gl::SYNC_FLUSH_COMMANDS_BIT, timeout);
+        if status == gl::TIMEOUT_EXPIRED {
+            return Err(fence);
+        }
+
+        self.delete_fence(fence);
+        Ok(())
+    }
+
+    /// Releases a fence that is no longer waited on.
+    pub fn delete_fence(&mut self, fence: GpuFence) {
+        self.gl.delete_sync(fence.0);
+    }
+
     fn clear_vertex_array(&mut self) {
         debug_assert!(self.inside_frame);
         self.gl.bind_vertex_array(0);
@@ -1851,7 +2852,7 @@ impl Device {
         debug_assert_eq!(self.bound_vao, vao_id);
 
         vao.main_vbo_id.bind(self.gl());
-        gl::buffer_data(self.gl(), gl::ARRAY_BUFFER, &vertices, usage_hint.to_gl());
+        self.update_buffer(gl::ARRAY_BUFFER, vertices, usage_hint);
     }
 
     pub fn update_vao_instances<V>(&mut self,
@@ -1865,7 +2866,7 @@ impl Device {
         debug_assert_eq!(vao.instance_stride as usize, mem::size_of::<V>());
 
         vao.instance_vbo_id.bind(self.gl());
-        gl::buffer_data(self.gl(), gl::ARRAY_BUFFER, &instances, usage_hint.to_gl());
+        self.update_buffer(gl::ARRAY_BUFFER, instances, usage_hint);
     }
 
     pub fn update_vao_indices<I>(&mut self,
@@ -1878,7 +2879,46 @@ impl Device {
         debug_assert_eq!(self.bound_vao, vao_id);
 
         vao.ibo_id.bind(self.gl());
-        gl::buffer_data(self.gl(), gl::ELEMENT_ARRAY_BUFFER, &indices, usage_hint.to_gl());
+        self.update_buffer(gl::ELEMENT_ARRAY_BUFFER, indices, usage_hint);
+    }
+
+    // Replaces the contents of the buffer bound to `target`, the way
+    // `DriverWorkarounds::buffer_update_strategy` says.
+    fn update_buffer<T>(&self, target: gl::GLenum, data: &[T], usage_hint: VertexUsageHint) {
+        let size = (data.len() * mem::size_of::<T>()) as gl::GLsizeiptr;
+
+        match self.driver_workarounds.buffer_update_strategy {
+            BufferUpdateStrategy::BufferData => {
+                gl::buffer_data(self.gl(), target, data, usage_hint.to_gl());
+            }
+            BufferUpdateStrategy::OrphanSubData => {
+                self.gl.buffer_data_untyped(target, size, ptr::null(), usage_hint.to_gl());
+                self.gl.buffer_sub_data_untyped(target, 0, size, data.as_ptr() as *const gl::GLvoid);
+            }
+            BufferUpdateStrategy::MapUnsynchronized => {
+                self.gl.buffer_data_untyped(target, size, ptr::null(), usage_hint.to_gl());
+                if size == 0 {
+                    return;
+                }
+                // The storage was just orphaned, so nothing can be reading it.
+                let mapped = self.gl.map_buffer_range(target,
+                                                      0,
+                                                      size,
+                                                      gl::MAP_WRITE_BIT |
+                                                      gl::MAP_INVALIDATE_BUFFER_BIT |
+                                                      gl::MAP_UNSYNCHRONIZED_BIT);
+                if mapped.is_null() {
+                    self.gl.buffer_sub_data_untyped(target, 0, size, data.as_ptr() as *const gl::GLvoid);
+                    return;
+                }
+                unsafe {
+                    ptr::copy_nonoverlapping(data.as_ptr() as *const u8,
+                                             mapped as *mut u8,
+                                             size as usize);
+                }
+                self.gl.unmap_buffer(target);
+            }
+        }
     }
 
     pub fn draw_triangles_u16(&mut self, first_vertex: i32, index_count: i32) {
@@ -1916,6 +2956,13 @@ impl Device {
         self.bind_read_target(None);
 
         debug_assert!(self.inside_frame);
+
+        if !self.deleted_textures.is_empty() {
+            let fence = self.gl.fence_sync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+            let texture_ids = mem::replace(&mut self.deleted_textures, Vec::new());
+            self.retiring_textures.push_back((fence, texture_ids));
+        }
+
         self.inside_frame = false;
 
         self.gl.bind_texture(gl::TEXTURE_2D, 0);
@@ -1933,7 +2980,10 @@ impl Device {
 
     pub fn assign_ubo_binding(&self, program_id: ProgramId, name: &str, value: u32) -> u32 {
         let index = self.gl.get_uniform_block_index(program_id.0, name);
-        self.gl.uniform_block_binding(program_id.0, index, value);
+        // The block is optimized out if the shader doesn't use it.
+        if index != gl::INVALID_INDEX {
+            self.gl.uniform_block_binding(program_id.0, index, value);
+        }
         index
     }
 
@@ -1945,6 +2995,26 @@ impl Device {
         UBOId(ubo)
     }
 
+    /// Replaces the contents of `ubo` with `size` bytes, which start with
+    /// `data`, and binds it to `binding`. The size should be that of the
+    /// uniform block, since drivers may read all of it even when the data
+    /// is shorter.
+    pub fn update_ubo<T>(&self, ubo: UBOId, data: &[T], size: usize, binding: u32) {
+        let data_size = data.len() * mem::size_of::<T>();
+        debug_assert!(data_size <= size);
+
+        self.gl.bind_buffer(gl::UNIFORM_BUFFER, ubo.0);
+        self.gl.buffer_data_untyped(gl::UNIFORM_BUFFER,
+                                    size as gl::GLsizeiptr,
+                                    ptr::null(),
+                                    gl::STREAM_DRAW);
+        self.gl.buffer_sub_data_untyped(gl::UNIFORM_BUFFER,
+                                        0,
+                                        data_size as gl::GLsizeiptr,
+                                        data.as_ptr() as *const gl::GLvoid);
+        self.gl.bind_buffer_base(gl::UNIFORM_BUFFER, binding, ubo.0);
+    }
+
     pub fn reset_ubo(&self, binding: u32) {
         self.gl.bind_buffer(gl::UNIFORM_BUFFER, 0);
         self.gl.bind_buffer_base(gl::UNIFORM_BUFFER, binding, 0);
@@ -2037,6 +3107,11 @@ impl Device {
         self.gl.disable(gl::STENCIL_TEST);
     }
 
+    pub fn enable_scissor(&self, rect: DeviceIntRect) {
+        self.gl.enable(gl::SCISSOR_TEST);
+        self.gl.scissor(rect.origin.x, rect.origin.y, rect.size.width, rect.size.height);
+    }
+
     pub fn disable_scissor(&self) {
         self.gl.disable(gl::SCISSOR_TEST);
     }
@@ -2088,6 +3163,59 @@ impl Drop for Device {
     }
 }
 
+/// Points the sampler uniforms of the bound program at their texture units.
+fn bind_sampler_uniforms(gl: &gl::Gl, program_id: gl::GLuint) {
+    let u_color_0 = gl.get_uniform_location(program_id, "sColor0");
+    if u_color_0 != -1 {
+        gl.uniform_1i(u_color_0, TextureSampler::Color0 as i32);
+    }
+    let u_color1 = gl.get_uniform_location(program_id, "sColor1");
+    if u_color1 != -1 {
+        gl.uniform_1i(u_color1, TextureSampler::Color1 as i32);
+    }
+    let u_color_2 = gl.get_uniform_location(program_id, "sColor2");
+    if u_color_2 != -1 {
+        gl.uniform_1i(u_color_2, TextureSampler::Color2 as i32);
+    }
+    let u_noise = gl.get_uniform_location(program_id, "sDither");
+    if u_noise != -1 {
+        gl.uniform_1i(u_noise, TextureSampler::Dither as i32);
+    }
+    let u_mask = gl.get_uniform_location(program_id, "sMask");
+    if u_mask != -1 {
+        gl.uniform_1i(u_mask, TextureSampler::Mask as i32);
+    }
+
+    let u_cache_a8 = gl.get_uniform_location(program_id, "sCacheA8");
+    if u_cache_a8 != -1 {
+        gl.uniform_1i(u_cache_a8, TextureSampler::CacheA8 as i32);
+    }
+    let u_cache_rgba8 = gl.get_uniform_location(program_id, "sCacheRGBA8");
+    if u_cache_rgba8 != -1 {
+        gl.uniform_1i(u_cache_rgba8, TextureSampler::CacheRGBA8 as i32);
+    }
+
+    let u_layers = gl.get_uniform_location(program_id, "sLayers");
+    if u_layers != -1 {
+        gl.uniform_1i(u_layers, TextureSampler::Layers as i32);
+    }
+
+    let u_tasks = gl.get_uniform_location(program_id, "sRenderTasks");
+    if u_tasks != -1 {
+        gl.uniform_1i(u_tasks, TextureSampler::RenderTasks as i32);
+    }
+
+    let u_gpu_cache = gl.get_uniform_location(program_id, "sGpuCache");
+    if u_gpu_cache != -1 {
+        gl.uniform_1i(u_gpu_cache, TextureSampler::GpuCache as i32);
+    }
+
+    let u_gradients = gl.get_uniform_location(program_id, "sGradients");
+    if u_gradients != -1 {
+        gl.uniform_1i(u_gradients, TextureSampler::Gradients as i32);
+    }
+}
+
 fn gl_texture_formats_for_image_format(gl: &gl::Gl, format: ImageFormat) -> (gl::GLint, gl::GLuint) {
     match format {
         ImageFormat::A8 => {
@@ -2120,3 +3248,39 @@ fn gl_type_for_texture_format(format: ImageFormat) -> gl::GLuint {
     }
 }
 
+
+/// Copies `height` rows of `row_bytes` bytes each out of `data`, where
+/// consecutive rows start `stride` bytes apart, into a tightly packed buffer.
+fn repack_rows(data: &[u8], row_bytes: u32, height: u32, stride: u32) -> Vec<u8> {
+    let mut packed = Vec::with_capacity((row_bytes * height) as usize);
+    for row in 0..height {
+        let start = (row * stride) as usize;
+        packed.extend_from_slice(&data[start..start + row_bytes as usize]);
+    }
+    packed
+}
+
+/// The largest UNPACK_ALIGNMENT, up to GL's default of 4, that rows of
+/// `row_bytes` bytes satisfy. Rows of A8 and RGB8 data are often not a
+/// multiple of 4 bytes and would otherwise be read with padding GL assumes.
+fn unpack_alignment_for_row(row_bytes: u32) -> gl::GLint {
+    match row_bytes % 4 {
+        0 => 4,
+        2 => 2,
+        _ => 1,
+    }
+}
+
+#[test]
+fn unpack_alignment_matches_row_padding() {
+    assert_eq!(unpack_alignment_for_row(16), 4);
+    assert_eq!(unpack_alignment_for_row(6), 2);
+    assert_eq!(unpack_alignment_for_row(3), 1);
+    assert_eq!(unpack_alignment_for_row(1023 * 3), 1);
+}
+
+#[test]
+fn repack_rows_drops_row_padding() {
+    let data = [1, 2, 3, 0, 4, 5, 6, 0, 7, 8, 9];
+    assert_eq!(repack_rows(&data, 3, 3, 4), vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+}
@@ -0,0 +1,233 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A table of known driver problems, and the workarounds the device applies
+//! for them. The driver is identified from the GL_VENDOR, GL_RENDERER and
+//! GL_VERSION strings when the device is created.
+
+/// The ways the renderer can steer around driver problems.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Workaround {
+    /// Draw every instance of a batch with a draw call of its own.
+    DisableInstancing,
+    /// Clear whole render targets instead of only the parts that are used.
+    ForceFullClears,
+    /// Never draw to multisampled render targets.
+    DisableMultisampling,
+    /// Write the vertex and instance data of each frame in another way.
+    UseBufferUpdateStrategy(BufferUpdateStrategy),
+}
+
+/// How the vertex, instance and index data that is replaced every frame
+/// is written to its buffers. Drivers differ in which of these stalls.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BufferUpdateStrategy {
+    /// Respecify the buffer with glBufferData and the new data.
+    BufferData,
+    /// Orphan the storage with glBufferData and no data, then write the
+    /// new data with glBufferSubData.
+    OrphanSubData,
+    /// Orphan the storage, then copy the new data into it through
+    /// glMapBufferRange with GL_MAP_UNSYNCHRONIZED_BIT.
+    MapUnsynchronized,
+}
+
+impl Default for BufferUpdateStrategy {
+    fn default() -> BufferUpdateStrategy {
+        BufferUpdateStrategy::BufferData
+    }
+}
+
+/// The workarounds in effect for a device, see `Device::driver_workarounds`.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct DriverWorkarounds {
+    pub disable_instancing: bool,
+    pub force_full_clears: bool,
+    pub disable_multisampling: bool,
+    pub buffer_update_strategy: BufferUpdateStrategy,
+}
+
+impl DriverWorkarounds {
+    /// Looks up the workarounds that the known problems table lists for
+    /// the driver.
+    pub fn for_driver(info: &DriverInfo) -> DriverWorkarounds {
+        let mut workarounds = DriverWorkarounds::default();
+        let driver_version = info.driver_version();
+
+        for problem in KNOWN_DRIVER_PROBLEMS {
+            if problem.matches(info, driver_version) {
+                workarounds.apply(problem.workaround);
+            }
+        }
+
+        workarounds
+    }
+
+    pub fn apply(&mut self, workaround: Workaround) {
+        match workaround {
+            Workaround::DisableInstancing => self.disable_instancing = true,
+            Workaround::ForceFullClears => self.force_full_clears = true,
+            Workaround::DisableMultisampling => self.disable_multisampling = true,
+            Workaround::UseBufferUpdateStrategy(strategy) => self.buffer_update_strategy = strategy,
+        }
+    }
+}
+
+/// The strings a GL driver identifies itself with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DriverInfo {
+    pub vendor: String,
+    pub renderer: String,
+    pub version: String,
+}
+
+impl DriverInfo {
+    /// The major and minor driver version, which most drivers append to the
+    /// GL version in GL_VERSION, e.g. "4.5.0 NVIDIA 375.66" is driver
+    /// version 375.66 and "OpenGL ES 3.2 V@145.0 (GIT@I86b60c3)" is 145.0.
+    pub fn driver_version(&self) -> Option<(u32, u32)> {
+        self.version
+            .split_whitespace()
+            .skip_while(|word| !word.starts_with(|c: char| c.is_digit(10)))
+            .skip(1)
+            .filter_map(parse_version_number)
+            .next()
+    }
+}
+
+fn parse_version_number(word: &str) -> Option<(u32, u32)> {
+    let start = match word.find(|c: char| c.is_digit(10)) {
+        Some(start) => start,
+        None => return None,
+    };
+    let mut numbers = word[start..].split('.').map(|number| {
+        let end = number.find(|c: char| !c.is_digit(10)).unwrap_or(number.len());
+        number[..end].parse::<u32>().ok()
+    });
+
+    match (numbers.next(), numbers.next()) {
+        (Some(Some(major)), Some(Some(minor))) => Some((major, minor)),
+        _ => None,
+    }
+}
+
+struct KnownDriverProblem {
+    /// Matched case insensitively against a part of GL_VENDOR.
+    vendor: &'static str,
+    /// Matched case insensitively against a part of GL_RENDERER.
+    renderer: &'static str,
+    /// The first driver version without the problem, if there is one.
+    fixed_in: Option<(u32, u32)>,
+    workaround: Workaround,
+}
+
+impl KnownDriverProblem {
+    fn matches(&self, info: &DriverInfo, driver_version: Option<(u32, u32)>) -> bool {
+        if !info.vendor.to_lowercase().contains(self.vendor) ||
+           !info.renderer.to_lowercase().contains(self.renderer) {
+            return false;
+        }
+
+        // A driver that doesn't report its version is assumed to be affected.
+        match (self.fixed_in, driver_version) {
+            (Some(fixed_in), Some(driver_version)) => driver_version < fixed_in,
+            _ => true,
+        }
+    }
+}
+
+// Add new problems at the end, with a note on how they show up.
+static KNOWN_DRIVER_PROBLEMS: &'static [KnownDriverProblem] = &[
+    // Instanced draws of large batches render garbage or lose the context.
+    KnownDriverProblem {
+        vendor: "qualcomm",
+        renderer: "adreno (tm) 3",
+        fixed_in: None,
+        workaround: Workaround::DisableInstancing,
+    },
+    // Tiled GPUs have to load the previous contents of every tile that a
+    // scissored clear doesn't cover, which costs more than clearing it all.
+    KnownDriverProblem {
+        vendor: "arm",
+        renderer: "mali",
+        fixed_in: None,
+        workaround: Workaround::ForceFullClears,
+    },
+    KnownDriverProblem {
+        vendor: "qualcomm",
+        renderer: "adreno",
+        fixed_in: None,
+        workaround: Workaround::ForceFullClears,
+    },
+    KnownDriverProblem {
+        vendor: "imagination",
+        renderer: "powervr",
+        fixed_in: None,
+        workaround: Workaround::ForceFullClears,
+    },
+    // Software rasterizers resolve multisampled targets on the CPU, which
+    // is slower than drawing the whole frame.
+    KnownDriverProblem {
+        vendor: "",
+        renderer: "llvmpipe",
+        fixed_in: None,
+        workaround: Workaround::DisableMultisampling,
+    },
+    KnownDriverProblem {
+        vendor: "",
+        renderer: "softpipe",
+        fixed_in: None,
+        workaround: Workaround::DisableMultisampling,
+    },
+    // glBufferData with new data waits for the draws that still read the
+    // old storage, which shows up as long stalls in the instance uploads.
+    KnownDriverProblem {
+        vendor: "qualcomm",
+        renderer: "adreno",
+        fixed_in: None,
+        workaround: Workaround::UseBufferUpdateStrategy(BufferUpdateStrategy::MapUnsynchronized),
+    },
+    KnownDriverProblem {
+        vendor: "arm",
+        renderer: "mali",
+        fixed_in: None,
+        workaround: Workaround::UseBufferUpdateStrategy(BufferUpdateStrategy::OrphanSubData),
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferUpdateStrategy, DriverInfo, DriverWorkarounds};
+
+    fn driver(vendor: &str, renderer: &str, version: &str) -> DriverInfo {
+        DriverInfo {
+            vendor: vendor.to_owned(),
+            renderer: renderer.to_owned(),
+            version: version.to_owned(),
+        }
+    }
+
+    #[test]
+    fn driver_version() {
+        let version = |s| driver("", "", s).driver_version();
+        assert_eq!(version("4.5.0 NVIDIA 375.66"), Some((375, 66)));
+        assert_eq!(version("OpenGL ES 3.2 V@145.0 (GIT@I86b60c3)"), Some((145, 0)));
+        assert_eq!(version("3.0 Mesa 17.0.1"), Some((17, 0)));
+        assert_eq!(version("4.1 ATI-1.51.8"), Some((1, 51)));
+        assert_eq!(version("4.5.0"), None);
+    }
+
+    #[test]
+    fn known_problems() {
+        let adreno = driver("Qualcomm", "Adreno (TM) 330", "OpenGL ES 3.0 V@84.0");
+        let workarounds = DriverWorkarounds::for_driver(&adreno);
+        assert!(workarounds.disable_instancing);
+        assert!(workarounds.force_full_clears);
+        assert!(!workarounds.disable_multisampling);
+        assert_eq!(workarounds.buffer_update_strategy, BufferUpdateStrategy::MapUnsynchronized);
+
+        let nvidia = driver("NVIDIA Corporation", "GeForce GTX 1070/PCIe/SSE2", "4.5.0 NVIDIA 375.66");
+        assert_eq!(DriverWorkarounds::for_driver(&nvidia), DriverWorkarounds::default());
+    }
+}
@@ -9,42 +9,49 @@ use internal_types::{ANGLE_FLOAT_TO_FIXED, AxisDirection};
 use internal_types::{LowLevelFilterOp};
 use internal_types::{RendererFrame};
 use frame_builder::{FrameBuilder, FrameBuilderConfig};
+use gpu_cache::GpuCache;
 use clip_scroll_tree::{ClipScrollTree, ScrollStates};
 use profiler::TextureCacheProfileCounters;
+use render_task_cache::RenderTaskCache;
 use resource_cache::ResourceCache;
 use scene::{Scene, SceneProperties};
 use std::collections::HashMap;
 use std::hash::BuildHasherDefault;
-use tiling::{AuxiliaryListsMap, CompositeOps, PrimitiveFlags};
-use util::subtract_rect;
-use webrender_traits::{AuxiliaryLists, ClipDisplayItem, ClipRegion, ColorF, DeviceUintRect};
-use webrender_traits::{DeviceUintSize, DisplayItem, Epoch, FilterOp, ImageDisplayItem, LayerPoint};
+use tiling::{AuxiliaryListsMap, CompositeOps, PrimitiveFlags, ScrollbarPart};
+use util::{is_invertible_transform, subtract_rect};
+use webrender_traits::{AuxiliaryLists, ClipDisplayItem, ClipRegion, DeviceUintRect};
+use webrender_traits::{DeviceUintSize, DisplayItem, Epoch, ExternalScrollOffset, FilterOp};
+use webrender_traits::{ImageDisplayItem, LayerPoint};
 use webrender_traits::{LayerRect, LayerSize, LayerToScrollTransform, LayoutRect, LayoutTransform};
-use webrender_traits::{MixBlendMode, PipelineId, ScrollEventPhase, ScrollLayerId};
+use webrender_traits::{MixBlendMode, OverscrollBehavior, OverscrollEvent, PipelineId};
+use webrender_traits::{PropertyBinding, PropertyBindingId, ScrollAnimation, ScrollClamping};
+use webrender_traits::ScrollEventPhase;
+use webrender_traits::ScrollLayerId;
 use webrender_traits::{ScrollLayerState, ScrollLocation, ScrollPolicy, SpecificDisplayItem};
 use webrender_traits::{StackingContext, TileOffset, WorldPoint};
 
 #[derive(Copy, Clone, PartialEq, PartialOrd, Debug)]
 pub struct FrameId(pub u32);
 
-static DEFAULT_SCROLLBAR_COLOR: ColorF = ColorF { r: 0.3, g: 0.3, b: 0.3, a: 0.6 };
-
 struct FlattenContext<'a> {
     scene: &'a Scene,
     builder: &'a mut FrameBuilder,
     resource_cache: &'a mut ResourceCache,
+    gpu_cache: &'a mut GpuCache,
     replacements: Vec<(ScrollLayerId, ScrollLayerId)>,
 }
 
 impl<'a> FlattenContext<'a> {
     fn new(scene: &'a Scene,
            builder: &'a mut FrameBuilder,
-           resource_cache: &'a mut ResourceCache)
+           resource_cache: &'a mut ResourceCache,
+           gpu_cache: &'a mut GpuCache)
            -> FlattenContext<'a> {
         FlattenContext {
             scene: scene,
             builder: builder,
             resource_cache: resource_cache,
+            gpu_cache: gpu_cache,
             replacements: Vec::new(),
         }
     }
@@ -87,6 +94,7 @@ trait StackingContextHelpers {
     fn filter_ops_for_compositing(&self,
                                   auxiliary_lists: &AuxiliaryLists,
                                   properties: &SceneProperties) -> Vec<LowLevelFilterOp>;
+    fn bound_opacities(&self, auxiliary_lists: &AuxiliaryLists) -> Vec<(usize, PropertyBindingId)>;
 }
 
 impl StackingContextHelpers for StackingContext {
@@ -149,6 +157,40 @@ impl StackingContextHelpers for StackingContext {
         }
         filters
     }
+
+    /// The bound opacity filters, with their indices in the filters that
+    /// `filter_ops_for_compositing` returns.
+    fn bound_opacities(&self, auxiliary_lists: &AuxiliaryLists) -> Vec<(usize, PropertyBindingId)> {
+        let mut bound_opacities = vec![];
+        let mut filter_index = 0;
+        for filter in auxiliary_lists.filters(&self.filters) {
+            match *filter {
+                FilterOp::Blur(..) => {
+                    filter_index += 2;
+                    continue;
+                }
+                FilterOp::Opacity(PropertyBinding::Binding(ref key)) => {
+                    bound_opacities.push((filter_index, key.id));
+                }
+                _ => {}
+            }
+            filter_index += 1;
+        }
+        bound_opacities
+    }
+}
+
+/// Records the bindings of a stacking context that is left out of the
+/// frame, since only flattening the scene again can bring it back.
+fn add_fixed_bindings(builder: &mut FrameBuilder,
+                      stacking_context: &StackingContext,
+                      bound_opacities: &[(usize, PropertyBindingId)]) {
+    if let Some(&PropertyBinding::Binding(ref key)) = stacking_context.transform.as_ref() {
+        builder.add_fixed_binding(key.id);
+    }
+    for &(_, binding_id) in bound_opacities {
+        builder.add_fixed_binding(binding_id);
+    }
 }
 
 struct DisplayListTraversal<'a> {
@@ -222,6 +264,86 @@ fn clip_intersection(original_rect: &LayerRect,
     })
 }
 
+fn is_finite_point(point: &LayerPoint) -> bool {
+    point.x.is_finite() && point.y.is_finite()
+}
+
+fn is_valid_size(size: &LayerSize) -> bool {
+    size.width.is_finite() && size.height.is_finite() &&
+    size.width >= 0.0 && size.height >= 0.0
+}
+
+fn is_valid_rect(rect: &LayerRect) -> bool {
+    is_finite_point(&rect.origin) && is_valid_size(&rect.size)
+}
+
+fn is_valid_length(length: f32) -> bool {
+    length.is_finite() && length >= 0.0
+}
+
+/// Checks the geometry of an item before it's turned into primitives.
+/// Non-finite values would be written to the GPU data textures as they
+/// are, and degenerate image stretch sizes never finish decomposing. Glyph
+/// positions live in the auxiliary lists, so text runs drop glyphs at
+/// non-finite positions when they are prepared instead.
+fn has_valid_geometry(item: &DisplayItem) -> bool {
+    if item.item == SpecificDisplayItem::PopStackingContext {
+        return true;
+    }
+
+    if !is_valid_rect(&item.rect) || !is_valid_rect(&item.clip.main) {
+        return false;
+    }
+    if let Some(ref mask) = item.clip.image_mask {
+        if !is_valid_rect(&mask.rect) {
+            return false;
+        }
+    }
+
+    match item.item {
+        SpecificDisplayItem::Image(ref info) => {
+            is_valid_size(&info.tile_spacing) &&
+            is_valid_size(&info.stretch_size) &&
+            info.stretch_size.width > 0.0 &&
+            info.stretch_size.height > 0.0
+        }
+        SpecificDisplayItem::Gradient(ref info) => {
+            is_finite_point(&info.gradient.start_point) &&
+            is_finite_point(&info.gradient.end_point)
+        }
+        SpecificDisplayItem::RadialGradient(ref info) => {
+            is_finite_point(&info.gradient.start_center) &&
+            is_finite_point(&info.gradient.end_center) &&
+            is_valid_length(info.gradient.start_radius) &&
+            is_valid_length(info.gradient.end_radius) &&
+            info.gradient.ratio_xy.is_finite() &&
+            info.gradient.ratio_xy > 0.0
+        }
+        SpecificDisplayItem::BoxShadow(ref info) => {
+            is_valid_rect(&info.box_bounds) &&
+            is_finite_point(&info.offset) &&
+            is_valid_length(info.blur_radius) &&
+            is_valid_length(info.border_radius) &&
+            info.spread_radius.is_finite()
+        }
+        SpecificDisplayItem::Border(ref info) => {
+            is_valid_length(info.widths.left) &&
+            is_valid_length(info.widths.top) &&
+            is_valid_length(info.widths.right) &&
+            is_valid_length(info.widths.bottom)
+        }
+        SpecificDisplayItem::Outline(ref info) => {
+            is_valid_rect(&info.box_bounds) &&
+            is_valid_length(info.width) &&
+            info.offset.is_finite()
+        }
+        SpecificDisplayItem::Clip(ref info) => {
+            is_valid_size(&info.content_size)
+        }
+        _ => true,
+    }
+}
+
 impl Frame {
     pub fn new(config: FrameBuilderConfig) -> Frame {
         Frame {
@@ -234,6 +356,12 @@ impl Frame {
         }
     }
 
+    pub fn set_subpixel_aa(&mut self, enable: bool) {
+        // Deterministic output always uses grayscale text.
+        self.frame_builder_config.enable_subpixel_aa = enable &&
+                                                       !self.frame_builder_config.deterministic;
+    }
+
     pub fn reset(&mut self) -> ScrollStates {
         self.pipeline_epoch_map.clear();
 
@@ -252,6 +380,22 @@ impl Frame {
         self.clip_scroll_tree.scroll_nodes(origin, id)
     }
 
+    /// Returns true if the node moved or started animating.
+    pub fn scroll_node_to(&mut self,
+                          origin: LayerPoint,
+                          id: ScrollLayerId,
+                          clamping: ScrollClamping,
+                          animation: Option<ScrollAnimation>)
+                          -> bool {
+        // Without a clock to follow, animated scrolls jump to their target.
+        let animation = if self.frame_builder_config.deterministic {
+            None
+        } else {
+            animation
+        };
+        self.clip_scroll_tree.scroll_node_to(origin, id, clamping, animation)
+    }
+
     /// Returns true if any nodes actually changed position or false otherwise.
     pub fn scroll(&mut self,
                   scroll_location: ScrollLocation,
@@ -265,13 +409,43 @@ impl Frame {
         self.clip_scroll_tree.tick_scrolling_bounce_animations();
     }
 
+    pub fn set_overscroll_behavior(&mut self, id: ScrollLayerId, behavior: OverscrollBehavior) {
+        self.clip_scroll_tree.set_overscroll_behavior(id, behavior);
+    }
+
+    pub fn set_externally_scrolled(&mut self, id: ScrollLayerId, externally_scrolled: bool) {
+        self.clip_scroll_tree.set_externally_scrolled(id, externally_scrolled);
+    }
+
+    pub fn set_external_scroll_offsets(&mut self, offsets: Vec<ExternalScrollOffset>) {
+        self.clip_scroll_tree.set_external_scroll_offsets(offsets);
+    }
+
+    pub fn take_overscroll_events(&mut self) -> Vec<OverscrollEvent> {
+        self.clip_scroll_tree.take_overscroll_events()
+    }
+
     pub fn discard_frame_state_for_pipeline(&mut self, pipeline_id: PipelineId) {
         self.clip_scroll_tree.discard_frame_state_for_pipeline(pipeline_id);
     }
 
+    /// Forgets what was kept for a pipeline that was removed from the
+    /// scene. Without the root pipeline there is nothing left to draw, so
+    /// the frame builder is dropped too, which also lets the resources it
+    /// used expire.
+    pub fn remove_pipeline(&mut self, pipeline_id: PipelineId, is_root: bool) {
+        self.clip_scroll_tree.discard_frame_state_for_pipeline(pipeline_id);
+        self.pipeline_epoch_map.remove(&pipeline_id);
+        self.pipeline_auxiliary_lists.remove(&pipeline_id);
+        if is_root {
+            self.frame_builder = None;
+        }
+    }
+
     pub fn create(&mut self,
                   scene: &Scene,
                   resource_cache: &mut ResourceCache,
+                  gpu_cache: &mut GpuCache,
                   window_size: DeviceUintSize,
                   inner_rect: DeviceUintRect,
                   device_pixel_ratio: f32) {
@@ -309,7 +483,7 @@ impl Frame {
             }
         };
 
-        let background_color = root_pipeline.background_color.and_then(|color| {
+        let background_color = scene.background_color(root_pipeline_id).and_then(|color| {
             if color.a > 0.0 {
                 Some(color)
             } else {
@@ -317,17 +491,25 @@ impl Frame {
             }
         });
 
+        // The primitives of the new scene take the place of the old ones
+        // in the GPU cache, so the old frame builder can't be used again.
+        self.frame_builder = None;
+        gpu_cache.begin_scene();
         let mut frame_builder = FrameBuilder::new(window_size,
                                                   background_color,
                                                   self.frame_builder_config);
 
         {
-            let mut context = FlattenContext::new(scene, &mut frame_builder, resource_cache);
+            let mut context = FlattenContext::new(scene,
+                                                  &mut frame_builder,
+                                                  resource_cache,
+                                                  gpu_cache);
 
             let scroll_layer_id = context.builder.push_root(root_pipeline_id,
                                                             &root_pipeline.viewport_size,
                                                             &root_bounds.size,
-                                                            &mut self.clip_scroll_tree);
+                                                            &mut self.clip_scroll_tree,
+                                                            context.gpu_cache);
 
             context.builder.setup_viewport_offset(window_size,
                                                   inner_rect,
@@ -363,7 +545,8 @@ impl Frame {
                                              &clip_rect,
                                              &item.content_size,
                                              clip,
-                                             &mut self.clip_scroll_tree);
+                                             &mut self.clip_scroll_tree,
+                                             context.gpu_cache);
 
     }
 
@@ -382,20 +565,35 @@ impl Frame {
             return;
         }
 
-        let composition_operations = {
+        let (composition_operations, bound_opacities) = {
             let auxiliary_lists = self.pipeline_auxiliary_lists
                                       .get(&pipeline_id)
                                       .expect("No auxiliary lists?!");
-            CompositeOps::new(
+            let composition_operations = CompositeOps::new(
                 stacking_context.filter_ops_for_compositing(auxiliary_lists, &context.scene.properties),
-                stacking_context.mix_blend_mode_for_compositing())
+                stacking_context.mix_blend_mode_for_compositing());
+            (composition_operations, stacking_context.bound_opacities(auxiliary_lists))
         };
 
         if composition_operations.will_make_invisible() {
+            add_fixed_bindings(context.builder, stacking_context, &bound_opacities);
             traversal.skip_current_stacking_context();
             return;
         }
 
+        if stacking_context.transform.is_some() || stacking_context.perspective.is_some() {
+            let transform = stacking_context.transform.as_ref();
+            let transform = context.scene.properties.resolve_layout_transform(transform);
+            let perspective =
+                stacking_context.perspective.unwrap_or_else(LayoutTransform::identity);
+            if !is_invertible_transform(&transform) || !is_invertible_transform(&perspective) {
+                debug!("Skipping stacking context with degenerate transform {:?}", transform);
+                add_fixed_bindings(context.builder, stacking_context, &bound_opacities);
+                traversal.skip_current_stacking_context();
+                return;
+            }
+        }
+
         let mut scroll_layer_id =
             context.scroll_layer_id_with_replacement(context_scroll_layer_id);
 
@@ -413,13 +611,13 @@ impl Frame {
             let transform = context.scene.properties.resolve_layout_transform(transform);
             let perspective =
                 stacking_context.perspective.unwrap_or_else(LayoutTransform::identity);
-            let transform =
+            let origin_transform =
                 LayerToScrollTransform::create_translation(reference_frame_relative_offset.x,
                                                            reference_frame_relative_offset.y,
                                                            0.0)
-                                        .pre_translated(bounds.origin.x, bounds.origin.y, 0.0)
-                                        .pre_mul(&transform)
-                                        .pre_mul(&perspective);
+                                        .pre_translated(bounds.origin.x, bounds.origin.y, 0.0);
+            let transform = origin_transform.pre_mul(&transform)
+                                            .pre_mul(&perspective);
 
             let reference_frame_bounds = LayerRect::new(LayerPoint::zero(), bounds.size);
             scroll_layer_id = context.builder.push_reference_frame(Some(scroll_layer_id),
@@ -427,6 +625,15 @@ impl Frame {
                                                                    &reference_frame_bounds,
                                                                    &transform,
                                                                    &mut self.clip_scroll_tree);
+
+            // Bound transforms (e.g. WebVR poses) can be late-latched by the
+            // renderer, so remember how to rebuild this reference frame.
+            if let Some(&PropertyBinding::Binding(ref key)) = stacking_context.transform.as_ref() {
+                context.builder.add_late_latched_reference_frame(scroll_layer_id,
+                                                                 key.id,
+                                                                 origin_transform.to_untyped(),
+                                                                 perspective.to_untyped());
+            }
             context.replacements.push((context_scroll_layer_id, scroll_layer_id));
             reference_frame_relative_offset = LayerPoint::zero();
         } else {
@@ -439,22 +646,27 @@ impl Frame {
         context.builder.push_stacking_context(&reference_frame_relative_offset,
                                               pipeline_id,
                                               level == 0,
-                                              composition_operations);
+                                              composition_operations,
+                                              stacking_context.snapshot,
+                                              stacking_context.isolate,
+                                              None);
+        for &(filter_index, binding_id) in &bound_opacities {
+            context.builder.add_bound_opacity(filter_index, binding_id);
+        }
 
         // For the root pipeline, there's no need to add a full screen rectangle
         // here, as it's handled by the framebuffer clear.
         if level == 0 && context.scene.root_pipeline_id.unwrap() != pipeline_id {
-            if let Some(pipeline) = context.scene.pipeline_map.get(&pipeline_id) {
-                if let Some(bg_color) = pipeline.background_color {
-                    // Note: we don't use the original clip region here,
-                    // it's already processed by the node we just pushed.
-                    let background_rect = LayerRect::new(LayerPoint::zero(), bounds.size);
-                    context.builder.add_solid_rectangle(scroll_layer_id,
-                                                        &bounds,
-                                                        &ClipRegion::simple(&background_rect),
-                                                        &bg_color,
-                                                        PrimitiveFlags::None);
-                }
+            if let Some(bg_color) = context.scene.background_color(pipeline_id) {
+                // Note: we don't use the original clip region here,
+                // it's already processed by the node we just pushed.
+                let background_rect = LayerRect::new(LayerPoint::zero(), bounds.size);
+                context.builder.add_solid_rectangle(scroll_layer_id,
+                                                    &bounds,
+                                                    &ClipRegion::simple(&background_rect),
+                                                    &bg_color,
+                                                    PrimitiveFlags::None,
+                                                    context.gpu_cache);
             }
         }
 
@@ -465,13 +677,28 @@ impl Frame {
                            level);
 
         if level == 0 && self.frame_builder_config.enable_scrollbars {
-            let scrollbar_rect = LayerRect::new(LayerPoint::zero(), LayerSize::new(10.0, 70.0));
-            context.builder.add_solid_rectangle(
-                scroll_layer_id,
-                &scrollbar_rect,
-                &ClipRegion::simple(&scrollbar_rect),
-                &DEFAULT_SCROLLBAR_COLOR,
-                PrimitiveFlags::Scrollbar(self.clip_scroll_tree.topmost_scroll_layer_id(), 4.0));
+            let theme = self.frame_builder_config.scrollbar_theme;
+            let scrolling_node_id = self.clip_scroll_tree.topmost_scroll_layer_id();
+
+            // The rects are placed by the frame builder each time a frame
+            // is built. Tracks go first so that the thumbs draw over them.
+            let scrollbar_rect = LayerRect::new(LayerPoint::zero(),
+                                                LayerSize::new(theme.width, theme.width));
+            let parts = [
+                (ScrollbarPart::VerticalTrack, theme.track_color),
+                (ScrollbarPart::HorizontalTrack, theme.track_color),
+                (ScrollbarPart::VerticalThumb, theme.thumb_color),
+                (ScrollbarPart::HorizontalThumb, theme.thumb_color),
+            ];
+            for &(part, color) in &parts {
+                context.builder.add_solid_rectangle(
+                    scroll_layer_id,
+                    &scrollbar_rect,
+                    &ClipRegion::simple(&scrollbar_rect),
+                    &color,
+                    PrimitiveFlags::Scrollbar(scrolling_node_id, part),
+                    context.gpu_cache);
+            }
         }
 
         if stacking_context.scroll_policy == ScrollPolicy::Fixed {
@@ -536,7 +763,21 @@ impl Frame {
             &LayerRect::new(LayerPoint::zero(), iframe_rect.size),
             &iframe_stacking_context_bounds.size,
             &ClipRegion::simple(&iframe_stacking_context_bounds),
-            &mut self.clip_scroll_tree);
+            &mut self.clip_scroll_tree,
+            context.gpu_cache);
+
+        // A pipeline drawn into an external image gets a stacking context
+        // of its own around it, which isn't composited into the frame.
+        let render_target = context.scene.render_targets.get(&pipeline_id).cloned();
+        if render_target.is_some() {
+            context.builder.push_stacking_context(&LayerPoint::zero(),
+                                                  pipeline_id,
+                                                  false,
+                                                  CompositeOps::new(Vec::new(), None),
+                                                  None,
+                                                  false,
+                                                  render_target);
+        }
 
         let mut traversal = DisplayListTraversal::new_skipping_first(display_list);
         self.flatten_stacking_context(&mut traversal,
@@ -548,6 +789,10 @@ impl Frame {
                                       &iframe_stacking_context_bounds,
                                       &iframe_stacking_context);
 
+        if render_target.is_some() {
+            context.builder.pop_stacking_context();
+        }
+
         context.builder.pop_reference_frame();
     }
 
@@ -559,12 +804,40 @@ impl Frame {
                          level: i32) {
         while let Some(item) = traversal.next() {
             let scroll_layer_id = context.scroll_layer_id_with_replacement(item.scroll_layer_id);
+
+            if !has_valid_geometry(item) {
+                debug!("Dropping display item with invalid geometry {:?}", item);
+                match item.item {
+                    SpecificDisplayItem::PushStackingContext(..) => {
+                        traversal.skip_current_stacking_context();
+                    }
+                    SpecificDisplayItem::Clip(ref info) => {
+                        // Later items may be attached to this node, so it's
+                        // still defined, but with nothing visible inside.
+                        let empty_clip = ClipDisplayItem {
+                            content_size: LayerSize::zero(),
+                            id: info.id,
+                            parent_id: info.parent_id,
+                        };
+                        self.flatten_clip(context,
+                                          pipeline_id,
+                                          scroll_layer_id,
+                                          &empty_clip,
+                                          reference_frame_relative_offset,
+                                          &ClipRegion::simple(&LayerRect::zero()));
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
             match item.item {
                 SpecificDisplayItem::WebGL(ref info) => {
                     context.builder.add_webgl_rectangle(scroll_layer_id,
                                                         item.rect,
                                                         &item.clip,
-                                                        info.context_id);
+                                                        info.context_id,
+                                                        context.gpu_cache);
                 }
                 SpecificDisplayItem::Image(ref info) => {
                     let image = context.resource_cache.get_image_properties(info.image_key);
@@ -588,7 +861,9 @@ impl Frame {
                                                   None,
                                                   info.image_key,
                                                   info.image_rendering,
-                                                  None);
+                                                  info.color,
+                                                  None,
+                                                  context.gpu_cache);
                     }
                 }
                 SpecificDisplayItem::YuvImage(ref info) => {
@@ -598,7 +873,8 @@ impl Frame {
                                                   info.y_image_key,
                                                   info.u_image_key,
                                                   info.v_image_key,
-                                                  info.color_space);
+                                                  info.color_space,
+                                                  context.gpu_cache);
                 }
                 SpecificDisplayItem::Text(ref text_info) => {
                     context.builder.add_text(scroll_layer_id,
@@ -609,7 +885,8 @@ impl Frame {
                                              text_info.blur_radius,
                                              &text_info.color,
                                              text_info.glyphs,
-                                             text_info.glyph_options);
+                                             text_info.glyph_options,
+                                             context.gpu_cache);
                 }
                 SpecificDisplayItem::Rectangle(ref info) => {
                     let auxiliary_lists = self.pipeline_auxiliary_lists
@@ -625,20 +902,23 @@ impl Frame {
                                                             &opaque_rect,
                                                             &ClipRegion::simple(&item.clip.main),
                                                             &info.color,
-                                                            PrimitiveFlags::None);
+                                                            PrimitiveFlags::None,
+                                                            context.gpu_cache);
                         for transparent_rect in &results {
                             context.builder.add_solid_rectangle(scroll_layer_id,
                                                                 transparent_rect,
                                                                 &item.clip,
                                                                 &info.color,
-                                                                PrimitiveFlags::None);
+                                                                PrimitiveFlags::None,
+                                                                context.gpu_cache);
                         }
                     } else {
                         context.builder.add_solid_rectangle(scroll_layer_id,
                                                             &item.rect,
                                                             &item.clip,
                                                             &info.color,
-                                                            PrimitiveFlags::None);
+                                                            PrimitiveFlags::None,
+                                                            context.gpu_cache);
                     }
                 }
                 SpecificDisplayItem::Gradient(ref info) => {
@@ -648,7 +928,8 @@ impl Frame {
                                                  info.gradient.start_point,
                                                  info.gradient.end_point,
                                                  info.gradient.stops,
-                                                 info.gradient.extend_mode);
+                                                 info.gradient.extend_mode,
+                                                 context.gpu_cache);
                 }
                 SpecificDisplayItem::RadialGradient(ref info) => {
                     context.builder.add_radial_gradient(scroll_layer_id,
@@ -660,7 +941,8 @@ impl Frame {
                                                         info.gradient.end_radius,
                                                         info.gradient.ratio_xy,
                                                         info.gradient.stops,
-                                                        info.gradient.extend_mode);
+                                                        info.gradient.extend_mode,
+                                                        context.gpu_cache);
                 }
                 SpecificDisplayItem::BoxShadow(ref box_shadow_info) => {
                     context.builder.add_box_shadow(scroll_layer_id,
@@ -671,13 +953,22 @@ impl Frame {
                                                    box_shadow_info.blur_radius,
                                                    box_shadow_info.spread_radius,
                                                    box_shadow_info.border_radius,
-                                                   box_shadow_info.clip_mode);
+                                                   box_shadow_info.clip_mode,
+                                                   context.gpu_cache);
                 }
                 SpecificDisplayItem::Border(ref info) => {
                     context.builder.add_border(scroll_layer_id,
                                                item.rect,
                                                &item.clip,
-                                               info);
+                                               info,
+                                               context.gpu_cache);
+                }
+                SpecificDisplayItem::Outline(ref info) => {
+                    context.builder.add_outline(scroll_layer_id,
+                                                item.rect,
+                                                &item.clip,
+                                                info,
+                                                context.gpu_cache);
                 }
                 SpecificDisplayItem::PushStackingContext(ref info) => {
                     self.flatten_stacking_context(traversal,
@@ -690,11 +981,22 @@ impl Frame {
                                                   &info.stacking_context);
                 }
                 SpecificDisplayItem::Iframe(ref info) => {
-                    self.flatten_iframe(info.pipeline_id,
-                                        scroll_layer_id,
-                                        &item.rect,
-                                        context,
-                                        reference_frame_relative_offset);
+                    // Until the first display list of the pipeline arrives,
+                    // its declared background stands in for it.
+                    if context.scene.display_lists.contains_key(&info.pipeline_id) {
+                        self.flatten_iframe(info.pipeline_id,
+                                            scroll_layer_id,
+                                            &item.rect,
+                                            context,
+                                            reference_frame_relative_offset);
+                    } else if let Some(bg_color) = context.scene.background_color(info.pipeline_id) {
+                        context.builder.add_solid_rectangle(scroll_layer_id,
+                                                            &item.rect,
+                                                            &item.clip,
+                                                            &bg_color,
+                                                            PrimitiveFlags::None,
+                                                            context.gpu_cache);
+                    }
                 }
                 SpecificDisplayItem::Clip(ref info) => {
                     self.flatten_clip(context,
@@ -972,44 +1274,86 @@ impl Frame {
                                       None,
                                       info.image_key,
                                       info.image_rendering,
-                                      Some(tile_offset));
+                                      info.color,
+                                      Some(tile_offset),
+                                      context.gpu_cache);
+        }
+    }
+
+    /// Applies new values of animated properties to the current scene, so
+    /// that the next frame can be built from it without flattening it again.
+    /// Returns false, without changing anything, when the new values need
+    /// the scene to be flattened again, e.g. because they make a stacking
+    /// context visible that was left out.
+    pub fn update_animated_properties(&mut self,
+                                      old_properties: &SceneProperties,
+                                      new_properties: &SceneProperties)
+                                      -> bool {
+        let frame_builder = match self.frame_builder {
+            Some(ref mut frame_builder) => frame_builder,
+            None => return false,
+        };
+
+        let (changed_transforms, changed_floats) = old_properties.changed_bindings(new_properties);
+        if !frame_builder.can_update_bindings(&changed_transforms, &changed_floats, new_properties) {
+            return false;
         }
+
+        frame_builder.update_bindings(new_properties, &mut self.clip_scroll_tree);
+        true
     }
 
     pub fn build(&mut self,
                  resource_cache: &mut ResourceCache,
+                 gpu_cache: &mut GpuCache,
                  auxiliary_lists_map: &AuxiliaryListsMap,
                  device_pixel_ratio: f32,
                  pan: LayerPoint,
-                 texture_cache_profile: &mut TextureCacheProfileCounters)
+                 texture_cache_profile: &mut TextureCacheProfileCounters,
+                 render_task_cache: &mut RenderTaskCache)
                  -> RendererFrame {
         self.clip_scroll_tree.update_all_node_transforms(pan);
         let frame = self.build_frame(resource_cache,
+                                     gpu_cache,
                                      auxiliary_lists_map,
                                      device_pixel_ratio,
-                                     texture_cache_profile);
-        resource_cache.expire_old_resources(self.id);
+                                     texture_cache_profile,
+                                     render_task_cache);
         frame
     }
 
+    /// Frees the cached resources that the last built frame didn't use.
+    /// Returns true if any were freed.
+    pub fn expire_old_resources(&self, resource_cache: &mut ResourceCache) -> bool {
+        resource_cache.expire_old_resources(self.id)
+    }
+
     fn build_frame(&mut self,
                    resource_cache: &mut ResourceCache,
+                   gpu_cache: &mut GpuCache,
                    auxiliary_lists_map: &AuxiliaryListsMap,
                    device_pixel_ratio: f32,
-                   texture_cache_profile: &mut TextureCacheProfileCounters)
+                   texture_cache_profile: &mut TextureCacheProfileCounters,
+                   render_task_cache: &mut RenderTaskCache)
                    -> RendererFrame {
         let mut frame_builder = self.frame_builder.take();
         let frame = frame_builder.as_mut().map(|builder|
             builder.build(resource_cache,
+                          gpu_cache,
                           self.id,
                           &mut self.clip_scroll_tree,
                           auxiliary_lists_map,
                           device_pixel_ratio,
-                          texture_cache_profile)
+                          texture_cache_profile,
+                          render_task_cache)
         );
         self.frame_builder = frame_builder;
 
         let nodes_bouncing_back = self.clip_scroll_tree.collect_nodes_bouncing_back();
-        RendererFrame::new(self.pipeline_epoch_map.clone(), nodes_bouncing_back, frame)
+        let scroll_animations_running = self.clip_scroll_tree.scroll_animations_running();
+        RendererFrame::new(self.pipeline_epoch_map.clone(),
+                           nodes_bouncing_back,
+                           scroll_animations_running,
+                           frame)
     }
 }
@@ -5,35 +5,53 @@
 use app_units::Au;
 use batch_builder::BorderSideHelpers;
 use frame::FrameId;
+use gpu_cache::GpuCache;
 use gpu_store::GpuStoreAddress;
-use internal_types::{HardwareCompositeOp, SourceTexture};
+use internal_types::{HardwareCompositeOp, LowLevelFilterOp, SourceTexture};
 use mask_cache::{ClipMode, ClipSource, MaskCacheInfo, RegionMode};
 use prim_store::{BorderPrimitiveCpu, BorderPrimitiveGpu, BoxShadowPrimitiveGpu};
 use prim_store::{GradientPrimitiveCpu, GradientPrimitiveGpu, ImagePrimitiveCpu, ImagePrimitiveGpu};
 use prim_store::{ImagePrimitiveKind, PrimitiveContainer, PrimitiveGeometry, PrimitiveIndex};
+use prim_store::{PrimitiveKind, PrimitiveMetadata};
 use prim_store::{PrimitiveStore, RadialGradientPrimitiveCpu, RadialGradientPrimitiveGpu};
 use prim_store::{RectanglePrimitive, TextRunPrimitiveCpu, TextRunPrimitiveGpu};
 use prim_store::{TexelRect, YuvImagePrimitiveCpu, YuvImagePrimitiveGpu};
+use pipeline_stats::PipelineStatistics;
 use profiler::{FrameProfileCounters, TextureCacheProfileCounters};
 use render_task::{AlphaRenderItem, MaskCacheKey, MaskResult, RenderTask, RenderTaskIndex};
-use render_task::RenderTaskLocation;
+use render_task::{RenderTaskGraph, RenderTaskLocation};
+use render_task_cache::RenderTaskCache;
+use renderer::{OverlayCandidate, ScrollbarTheme, ZAllocation};
 use resource_cache::ResourceCache;
+use scene::SceneProperties;
 use clip_scroll_node::{ClipInfo, ClipScrollNode, NodeType};
 use clip_scroll_tree::ClipScrollTree;
+use fnv::FnvHasher;
 use std::{cmp, f32, i32, mem, usize};
-use euclid::SideOffsets2D;
+use std::collections::HashSet;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use euclid::{Matrix4D, SideOffsets2D};
 use tiling::StackingContextIndex;
-use tiling::{AuxiliaryListsMap, ClipScrollGroup, ClipScrollGroupIndex, CompositeOps, Frame};
+use tiling::{AuxiliaryListsMap, ClipScrollGroup, ClipScrollGroupIndex, CompositeOps};
+use tiling::{CompositorSurface, DamageItem, Frame};
+use tiling::LateLatchedLayer;
 use tiling::{PackedLayer, PackedLayerIndex, PrimitiveFlags, PrimitiveRunCmd, RenderPass};
-use tiling::{RenderTargetContext, RenderTaskCollection, ScrollbarPrimitive, StackingContext};
+use tiling::{RenderTargetContext, RenderTaskCollection, ScrollbarPart, ScrollbarPrimitive};
+use tiling::StackingContext;
+use time::precise_time_ns;
 use util::{self, pack_as_float, rect_from_points_f, subtract_rect};
-use util::{RectHelpers, TransformedRectKind};
-use webrender_traits::{BorderDetails, BorderDisplayItem, BorderSide, BorderStyle};
-use webrender_traits::{BoxShadowClipMode, ClipRegion, ColorF, DeviceIntPoint, DeviceIntRect};
-use webrender_traits::{DeviceIntSize, DeviceUintRect, DeviceUintSize, ExtendMode, FontKey};
+use util::{RectHelpers, TransformedRect, TransformedRectKind};
+use webrender_traits::{BorderDetails, BorderDisplayItem, BorderSide, BorderStyle, BorderWidths};
+use webrender_traits::{BoxShadowClipMode, ClipRegion, ColorF, ColorSpace, DeviceIntPoint, DeviceIntRect};
+use webrender_traits::{DeviceIntSize, DeviceUintRect, DeviceUintSize, ExtendMode, ExternalImageData};
+use webrender_traits::FontKey;
 use webrender_traits::{FontRenderMode, GlyphOptions, ImageKey, ImageRendering, ItemRange};
-use webrender_traits::{LayerPoint, LayerRect, LayerSize, LayerToScrollTransform, PipelineId};
-use webrender_traits::{RepeatMode, ScrollLayerId, TileOffset, WebGLContextId, YuvColorSpace};
+use webrender_traits::{LayerPoint, LayerRect, LayerSize, LayerToScrollTransform, LayoutTransform};
+use webrender_traits::{NormalBorder, OutlineDisplayItem, PipelineId};
+use webrender_traits::PropertyBindingId;
+use webrender_traits::{RepeatMode, ScrollLayerId, SnapshotId, SubpixelQuantization, TileOffset};
+use webrender_traits::WebGLContextId;
+use webrender_traits::YuvColorSpace;
 
 #[derive(Debug, Clone)]
 struct ImageBorderSegment {
@@ -83,22 +101,135 @@ impl ImageBorderSegment {
     }
 }
 
+/// A reference frame whose local transform is `pre_transform * pose * post_transform`,
+/// where the pose comes from a property binding that may be late-latched.
+struct LateLatchedReferenceFrame {
+    scroll_layer_id: ScrollLayerId,
+    binding_id: PropertyBindingId,
+    pre_transform: Matrix4D<f32>,
+    post_transform: Matrix4D<f32>,
+}
+
+/// An opacity filter of a stacking context whose amount comes from a
+/// property binding.
+struct BoundOpacity {
+    stacking_context_index: StackingContextIndex,
+    filter_index: usize,
+    binding_id: PropertyBindingId,
+}
+
 #[derive(Clone, Copy)]
 pub struct FrameBuilderConfig {
     pub enable_scrollbars: bool,
+    pub scrollbar_theme: ScrollbarTheme,
     pub enable_subpixel_aa: bool,
+    pub enable_overlay_promotion: bool,
+    pub enable_compositor_surfaces: bool,
+    pub z_allocation: ZAllocation,
+    /// The first z value that falls outside the renderer's depth range.
+    pub max_z: i32,
     pub debug: bool,
+    /// Leave out anything that depends on the clock, see
+    /// `RendererOptions::deterministic`.
+    pub deterministic: bool,
+    /// The color space frames are drawn in, see `RendererOptions::color_space`.
+    pub color_space: ColorSpace,
+    /// The most gradient data tables an angle or radial gradient can use,
+    /// see `RendererOptions::max_gradient_resolution`.
+    pub max_gradient_tables: usize,
+    /// How many of the latest alpha batches a primitive can join, see
+    /// `RendererOptions::batch_lookback_count`.
+    pub batch_lookback_count: usize,
+    /// How many subpixel positions glyphs are rasterized at, see
+    /// `RendererOptions::subpixel_quantization`.
+    pub subpixel_quantization: SubpixelQuantization,
+    /// How far past the screen content below a late-latched reference
+    /// frame is kept, see `RendererOptions::late_latch_cull_margin`.
+    pub late_latch_cull_margin: f32,
 }
 
 impl FrameBuilderConfig {
     pub fn new(enable_scrollbars: bool,
+               scrollbar_theme: ScrollbarTheme,
                enable_subpixel_aa: bool,
-               debug: bool)
+               enable_overlay_promotion: bool,
+               enable_compositor_surfaces: bool,
+               z_allocation: ZAllocation,
+               max_z: i32,
+               debug: bool,
+               deterministic: bool,
+               color_space: ColorSpace,
+               max_gradient_tables: usize,
+               batch_lookback_count: usize,
+               subpixel_quantization: SubpixelQuantization,
+               late_latch_cull_margin: f32)
                -> FrameBuilderConfig {
         FrameBuilderConfig {
             enable_scrollbars: enable_scrollbars,
+            scrollbar_theme: scrollbar_theme,
             enable_subpixel_aa: enable_subpixel_aa,
+            enable_overlay_promotion: enable_overlay_promotion,
+            enable_compositor_surfaces: enable_compositor_surfaces,
+            z_allocation: z_allocation,
+            max_z: max_z,
             debug: debug,
+            deterministic: deterministic,
+            color_space: color_space,
+            max_gradient_tables: max_gradient_tables,
+            batch_lookback_count: batch_lookback_count,
+            subpixel_quantization: subpixel_quantization,
+            late_latch_cull_margin: late_latch_cull_margin,
+        }
+    }
+}
+
+/// Hands out z values to the items of a frame in paint order.
+struct ZAllocator {
+    strategy: ZAllocation,
+    next_z: i32,
+    after_translucent: bool,
+}
+
+impl ZAllocator {
+    fn new(strategy: ZAllocation) -> ZAllocator {
+        ZAllocator {
+            strategy: strategy,
+            next_z: 0,
+            after_translucent: false,
+        }
+    }
+
+    fn alloc_opaque(&mut self) -> i32 {
+        if self.after_translucent {
+            self.next_z += 1;
+            self.after_translucent = false;
+        }
+        let z = self.next_z;
+        self.next_z += 1;
+        z
+    }
+
+    fn alloc_translucent(&mut self) -> i32 {
+        match self.strategy {
+            ZAllocation::PerItem => {
+                let z = self.next_z;
+                self.next_z += 1;
+                z
+            }
+            ZAllocation::Compact => {
+                // Translucent items don't write depth, so a run of them can
+                // share a z value as long as the next opaque item is above it.
+                self.after_translucent = true;
+                self.next_z
+            }
+        }
+    }
+
+    fn used_z_count(&self) -> i32 {
+        if self.after_translucent {
+            self.next_z + 1
+        } else {
+            self.next_z
         }
     }
 }
@@ -116,6 +247,13 @@ pub struct FrameBuilder {
 
     scrollbar_prims: Vec<ScrollbarPrimitive>,
 
+    late_latched_reference_frames: Vec<LateLatchedReferenceFrame>,
+    bound_opacities: Vec<BoundOpacity>,
+    /// Bindings that were resolved for good when the scene was flattened,
+    /// such as those of stacking contexts that were left out for being
+    /// invisible. Changing them needs the scene to be flattened again.
+    fixed_bindings: Vec<PropertyBindingId>,
+
     /// A stack of scroll nodes used during display list processing to properly
     /// parent new scroll nodes.
     reference_frame_stack: Vec<ScrollLayerId>,
@@ -131,13 +269,18 @@ impl FrameBuilder {
                config: FrameBuilderConfig) -> FrameBuilder {
         FrameBuilder {
             screen_size: screen_size,
-            background_color: background_color,
+            background_color: background_color.map(|color| {
+                color.convert_color_space(ColorSpace::Srgb, config.color_space)
+            }),
             stacking_context_store: Vec::new(),
             clip_scroll_group_store: Vec::new(),
-            prim_store: PrimitiveStore::new(),
+            prim_store: PrimitiveStore::new(config.color_space, config.max_gradient_tables),
             cmds: Vec::new(),
             packed_layers: Vec::new(),
             scrollbar_prims: Vec::new(),
+            late_latched_reference_frames: Vec::new(),
+            bound_opacities: Vec::new(),
+            fixed_bindings: Vec::new(),
             config: config,
             reference_frame_stack: Vec::new(),
             stacking_context_stack: Vec::new(),
@@ -149,7 +292,8 @@ impl FrameBuilder {
                      rect: &LayerRect,
                      clip_region: &ClipRegion,
                      extra_clip: Option<ClipSource>,
-                     container: PrimitiveContainer)
+                     container: PrimitiveContainer,
+                     gpu_cache: &mut GpuCache)
                      -> PrimitiveIndex {
         let stacking_context_index = *self.stacking_context_stack.last().unwrap();
         if !self.stacking_context_store[stacking_context_index.0]
@@ -174,13 +318,13 @@ impl FrameBuilder {
         if let Some(extra_clip) = extra_clip {
             clip_sources.push(extra_clip);
         }
-        let clip_info = MaskCacheInfo::new(&clip_sources,
-                                           &mut self.prim_store.gpu_data32);
+        let clip_info = MaskCacheInfo::new(&clip_sources, gpu_cache);
 
         let prim_index = self.prim_store.add_primitive(geometry,
                                                        clip_sources,
                                                        clip_info,
-                                                       container);
+                                                       container,
+                                                       gpu_cache);
 
         match self.cmds.last_mut().unwrap() {
             &mut PrimitiveRunCmd::PrimitiveRun(_run_prim_index, ref mut count, run_layer_id)
@@ -219,7 +363,10 @@ impl FrameBuilder {
                                  reference_frame_offset: &LayerPoint,
                                  pipeline_id: PipelineId,
                                  is_page_root: bool,
-                                 composite_ops: CompositeOps) {
+                                 composite_ops: CompositeOps,
+                                 snapshot: Option<SnapshotId>,
+                                 isolate: bool,
+                                 render_target: Option<ExternalImageData>) {
         if let Some(parent_index) = self.stacking_context_stack.last() {
             let parent_is_root = self.stacking_context_store[parent_index.0].is_page_root;
 
@@ -232,10 +379,28 @@ impl FrameBuilder {
         }
 
         let stacking_context_index = StackingContextIndex(self.stacking_context_store.len());
-        self.stacking_context_store.push(StackingContext::new(pipeline_id,
-                                                              *reference_frame_offset,
-                                                              is_page_root,
-                                                              composite_ops));
+        let mut stacking_context = StackingContext::new(pipeline_id,
+                                                        *reference_frame_offset,
+                                                        is_page_root,
+                                                        composite_ops);
+        // A snapshot is read from the render task the stacking context is
+        // drawn into on its own.
+        if snapshot.is_some() {
+            stacking_context.should_isolate = true;
+            stacking_context.snapshot = snapshot;
+        }
+        // An isolated group is the backdrop its descendants blend with,
+        // even at the root of a page.
+        if isolate {
+            stacking_context.should_isolate = true;
+        }
+        // The contents of a render target are drawn on their own, and then
+        // copied to the external image rather than composited.
+        if render_target.is_some() {
+            stacking_context.should_isolate = true;
+            stacking_context.render_target = render_target;
+        }
+        self.stacking_context_store.push(stacking_context);
         self.cmds.push(PrimitiveRunCmd::PushStackingContext(stacking_context_index));
         self.stacking_context_stack.push(stacking_context_index);
     }
@@ -257,6 +422,85 @@ impl FrameBuilder {
         new_id
     }
 
+    pub fn add_late_latched_reference_frame(&mut self,
+                                            scroll_layer_id: ScrollLayerId,
+                                            binding_id: PropertyBindingId,
+                                            pre_transform: Matrix4D<f32>,
+                                            post_transform: Matrix4D<f32>) {
+        self.late_latched_reference_frames.push(LateLatchedReferenceFrame {
+            scroll_layer_id: scroll_layer_id,
+            binding_id: binding_id,
+            pre_transform: pre_transform,
+            post_transform: post_transform,
+        });
+    }
+
+    /// Records that the filter at `filter_index` of the current stacking
+    /// context is an opacity bound to `binding_id`.
+    pub fn add_bound_opacity(&mut self, filter_index: usize, binding_id: PropertyBindingId) {
+        let stacking_context_index = *self.stacking_context_stack.last().unwrap();
+        self.bound_opacities.push(BoundOpacity {
+            stacking_context_index: stacking_context_index,
+            filter_index: filter_index,
+            binding_id: binding_id,
+        });
+    }
+
+    pub fn add_fixed_binding(&mut self, binding_id: PropertyBindingId) {
+        if !self.fixed_bindings.contains(&binding_id) {
+            self.fixed_bindings.push(binding_id);
+        }
+    }
+
+    /// Whether the changed bindings can be applied with `update_bindings`,
+    /// rather than by flattening the scene again.
+    pub fn can_update_bindings(&self,
+                               changed_transforms: &[PropertyBindingId],
+                               changed_floats: &[PropertyBindingId],
+                               properties: &SceneProperties)
+                               -> bool {
+        for id in changed_transforms.iter().chain(changed_floats.iter()) {
+            if self.fixed_bindings.contains(id) {
+                return false;
+            }
+        }
+
+        // Flattening leaves out stacking contexts with degenerate
+        // transforms, which can't be done here.
+        for id in changed_transforms {
+            if let Some(transform) = properties.get_transform(*id) {
+                if !util::is_invertible_transform(&transform) {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Applies the current values of the transforms and opacities that are
+    /// bound to properties, without flattening the scene again.
+    pub fn update_bindings(&mut self,
+                           properties: &SceneProperties,
+                           clip_scroll_tree: &mut ClipScrollTree) {
+        for frame in &self.late_latched_reference_frames {
+            let pose = properties.get_transform(frame.binding_id)
+                                 .unwrap_or_else(LayoutTransform::identity);
+            let transform = frame.pre_transform.pre_mul(&pose.to_untyped())
+                                               .pre_mul(&frame.post_transform);
+            if let Some(node) = clip_scroll_tree.nodes.get_mut(&frame.scroll_layer_id) {
+                node.node_type = NodeType::ReferenceFrame(LayerToScrollTransform::from_untyped(&transform));
+            }
+        }
+
+        for opacity in &self.bound_opacities {
+            let amount = properties.get_float(opacity.binding_id).unwrap_or(1.0);
+            let stacking_context = &mut self.stacking_context_store[opacity.stacking_context_index.0];
+            stacking_context.composite_ops.filters[opacity.filter_index] =
+                LowLevelFilterOp::Opacity(Au::from_f32_px(amount));
+        }
+    }
+
     pub fn current_reference_frame_id(&self) -> ScrollLayerId {
         *self.reference_frame_stack.last().unwrap()
     }
@@ -298,7 +542,8 @@ impl FrameBuilder {
                      pipeline_id: PipelineId,
                      viewport_size: &LayerSize,
                      content_size: &LayerSize,
-                     clip_scroll_tree: &mut ClipScrollTree)
+                     clip_scroll_tree: &mut ClipScrollTree,
+                     gpu_cache: &mut GpuCache)
                      -> ScrollLayerId {
         let viewport_rect = LayerRect::new(LayerPoint::zero(), *viewport_size);
         let identity = &LayerToScrollTransform::identity();
@@ -312,7 +557,8 @@ impl FrameBuilder {
                                    &viewport_rect,
                                    content_size,
                                    &ClipRegion::simple(&viewport_rect),
-                                   clip_scroll_tree);
+                                   clip_scroll_tree,
+                                   gpu_cache);
         topmost_scroll_layer_id
     }
 
@@ -323,9 +569,10 @@ impl FrameBuilder {
                                 local_viewport_rect: &LayerRect,
                                 content_size: &LayerSize,
                                 clip_region: &ClipRegion,
-                                clip_scroll_tree: &mut ClipScrollTree) {
+                                clip_scroll_tree: &mut ClipScrollTree,
+                                gpu_cache: &mut GpuCache) {
         let clip_info = ClipInfo::new(clip_region,
-                                      &mut self.prim_store.gpu_data32,
+                                      gpu_cache,
                                       PackedLayerIndex(self.packed_layers.len()));
         let node = ClipScrollNode::new(pipeline_id,
                                        parent_id,
@@ -346,7 +593,8 @@ impl FrameBuilder {
                                rect: &LayerRect,
                                clip_region: &ClipRegion,
                                color: &ColorF,
-                               flags: PrimitiveFlags) {
+                               flags: PrimitiveFlags,
+                               gpu_cache: &mut GpuCache) {
         if color.a == 0.0 {
             return;
         }
@@ -359,15 +607,17 @@ impl FrameBuilder {
                                             rect,
                                             clip_region,
                                             None,
-                                            PrimitiveContainer::Rectangle(prim));
+                                            PrimitiveContainer::Rectangle(prim),
+                                            gpu_cache);
 
         match flags {
             PrimitiveFlags::None => {}
-            PrimitiveFlags::Scrollbar(scroll_layer_id, border_radius) => {
+            PrimitiveFlags::Scrollbar(scroll_layer_id, part) => {
                 self.scrollbar_prims.push(ScrollbarPrimitive {
                     prim_index: prim_index,
                     scroll_layer_id: scroll_layer_id,
-                    border_radius: border_radius,
+                    part: part,
+                    color: *color,
                 });
             }
         }
@@ -393,11 +643,39 @@ impl FrameBuilder {
         }
     }
 
+    /// Adds an outline as a border with the same side all around, so it
+    /// gets the border styles and radii.
+    pub fn add_outline(&mut self,
+                       scroll_layer_id: ScrollLayerId,
+                       rect: LayerRect,
+                       clip_region: &ClipRegion,
+                       outline_item: &OutlineDisplayItem,
+                       gpu_cache: &mut GpuCache) {
+        let border_item = BorderDisplayItem {
+            widths: BorderWidths {
+                left: outline_item.width,
+                top: outline_item.width,
+                right: outline_item.width,
+                bottom: outline_item.width,
+            },
+            details: BorderDetails::Normal(NormalBorder {
+                left: outline_item.side,
+                right: outline_item.side,
+                top: outline_item.side,
+                bottom: outline_item.side,
+                radius: outline_item.radius,
+            }),
+        };
+
+        self.add_border(scroll_layer_id, rect, clip_region, &border_item, gpu_cache);
+    }
+
     pub fn add_border(&mut self,
                       scroll_layer_id: ScrollLayerId,
                       rect: LayerRect,
                       clip_region: &ClipRegion,
-                      border_item: &BorderDisplayItem) {
+                      border_item: &BorderDisplayItem,
+                      gpu_cache: &mut GpuCache) {
         let create_segments = |outset: SideOffsets2D<f32>| {
             // Calculate the modified rect as specific by border-image-outset
             let origin = LayerPoint::new(rect.origin.x - outset.left,
@@ -538,15 +816,17 @@ impl FrameBuilder {
                                    Some(segment.sub_rect),
                                    border.image_key,
                                    ImageRendering::Auto,
-                                   None);
+                                   ColorF::new(1.0, 1.0, 1.0, 1.0),
+                                   None,
+                                   gpu_cache);
                 }
             }
             BorderDetails::Normal(ref border) => {
                 let radius = &border.radius;
-                let left = &border.left;
-                let right = &border.right;
-                let top = &border.top;
-                let bottom = &border.bottom;
+                let left = &border.left.for_width(border_item.widths.left);
+                let right = &border.right.for_width(border_item.widths.right);
+                let top = &border.top.for_width(border_item.widths.top);
+                let bottom = &border.bottom.for_width(border_item.widths.bottom);
 
                 if !self.supported_style(left) || !self.supported_style(right) ||
                    !self.supported_style(top) || !self.supported_style(bottom) {
@@ -616,7 +896,8 @@ impl FrameBuilder {
                                                      rect,
                                                      clip_region,
                                                      &top_color,
-                                                     PrimitiveFlags::None);
+                                                     PrimitiveFlags::None,
+                                                     gpu_cache);
                         }
 
                         return;
@@ -658,7 +939,8 @@ impl FrameBuilder {
                                    &rect,
                                    clip_region,
                                    None,
-                                   PrimitiveContainer::Border(prim_cpu, prim_gpu));
+                                   PrimitiveContainer::Border(prim_cpu, prim_gpu),
+                                   gpu_cache);
             }
             BorderDetails::Gradient(ref border) => {
                 for segment in create_segments(border.outset) {
@@ -668,7 +950,8 @@ impl FrameBuilder {
                                       border.gradient.start_point,
                                       border.gradient.end_point,
                                       border.gradient.stops,
-                                      border.gradient.extend_mode);
+                                      border.gradient.extend_mode,
+                                      gpu_cache);
                 }
             }
             BorderDetails::RadialGradient(ref border) => {
@@ -682,7 +965,8 @@ impl FrameBuilder {
                                              border.gradient.end_radius,
                                              border.gradient.ratio_xy,
                                              border.gradient.stops,
-                                             border.gradient.extend_mode);
+                                             border.gradient.extend_mode,
+                                             gpu_cache);
                 }
             }
         }
@@ -695,7 +979,8 @@ impl FrameBuilder {
                         start_point: LayerPoint,
                         end_point: LayerPoint,
                         stops: ItemRange,
-                        extend_mode: ExtendMode) {
+                        extend_mode: ExtendMode,
+                        gpu_cache: &mut GpuCache) {
         // Fast path for clamped, axis-aligned gradients, with gradient lines intersecting all of rect:
         let aligned = extend_mode == ExtendMode::Clamp &&
                       (start_point.x == end_point.x &&
@@ -704,6 +989,10 @@ impl FrameBuilder {
                       (start_point.y == end_point.y &&
                        start_point.x.min(end_point.x) <= rect.min_x() &&
                        start_point.x.max(end_point.x) >= rect.max_x());
+        // Aligned gradients interpolate between their stops on the GPU, in
+        // the color space frames are drawn in. The others are interpolated
+        // in sRGB, as CSS expects, when their color tables are built.
+        let aligned = aligned && self.config.color_space == ColorSpace::Srgb;
         // Try to ensure that if the gradient is specified in reverse, then so long as the stops
         // are also supplied in reverse that the rendered result will be equivalent. To do this,
         // a reference orientation for the gradient line must be chosen, somewhat arbitrarily, so
@@ -715,10 +1004,13 @@ impl FrameBuilder {
                              (start_point.x == end_point.x &&
                               start_point.y > end_point.y));
 
+        let dx = end_point.x - start_point.x;
+        let dy = end_point.y - start_point.y;
         let gradient_cpu = GradientPrimitiveCpu {
             stops_range: stops,
             extend_mode: extend_mode,
             reverse_stops: reverse_stops,
+            length: (dx * dx + dy * dy).sqrt(),
             cache_dirty: true,
         };
 
@@ -748,7 +1040,8 @@ impl FrameBuilder {
                            &rect,
                            clip_region,
                            None,
-                           prim);
+                           prim,
+                           gpu_cache);
     }
 
     pub fn add_radial_gradient(&mut self,
@@ -761,10 +1054,16 @@ impl FrameBuilder {
                                end_radius: f32,
                                ratio_xy: f32,
                                stops: ItemRange,
-                               extend_mode: ExtendMode) {
+                               extend_mode: ExtendMode,
+                               gpu_cache: &mut GpuCache) {
+        // The ellipses are stretched along one of the axes by the ratio.
+        let dx = end_center.x - start_center.x;
+        let dy = end_center.y - start_center.y;
+        let length = (dx * dx + dy * dy).sqrt() + (end_radius - start_radius).abs();
         let radial_gradient_cpu = RadialGradientPrimitiveCpu {
             stops_range: stops,
             extend_mode: extend_mode,
+            length: length * ratio_xy.max(1.0 / ratio_xy),
             cache_dirty: true,
         };
 
@@ -781,7 +1080,8 @@ impl FrameBuilder {
                            &rect,
                            clip_region,
                            None,
-                           PrimitiveContainer::RadialGradient(radial_gradient_cpu, radial_gradient_gpu));
+                           PrimitiveContainer::RadialGradient(radial_gradient_cpu, radial_gradient_gpu),
+                           gpu_cache);
     }
 
     pub fn add_text(&mut self,
@@ -793,7 +1093,8 @@ impl FrameBuilder {
                     blur_radius: Au,
                     color: &ColorF,
                     glyph_range: ItemRange,
-                    glyph_options: Option<GlyphOptions>) {
+                    glyph_options: Option<GlyphOptions>,
+                    gpu_cache: &mut GpuCache) {
         if color.a == 0.0 {
             return
         }
@@ -851,7 +1152,8 @@ impl FrameBuilder {
                                &rect,
                                clip_region,
                                None,
-                               PrimitiveContainer::TextRun(prim_cpu, prim_gpu));
+                               PrimitiveContainer::TextRun(prim_cpu, prim_gpu),
+                               gpu_cache);
         }
     }
 
@@ -864,7 +1166,8 @@ impl FrameBuilder {
                           blur_radius: f32,
                           spread_radius: f32,
                           border_radius: f32,
-                          clip_mode: BoxShadowClipMode) {
+                          clip_mode: BoxShadowClipMode,
+                          gpu_cache: &mut GpuCache) {
         if color.a == 0.0 {
             return
         }
@@ -875,7 +1178,8 @@ impl FrameBuilder {
                                      &box_bounds,
                                      clip_region,
                                      color,
-                                     PrimitiveFlags::None);
+                                     PrimitiveFlags::None,
+                                     gpu_cache);
             return;
         }
 
@@ -958,7 +1262,8 @@ impl FrameBuilder {
                                              rect,
                                              clip_region,
                                              color,
-                                             PrimitiveFlags::None)
+                                             PrimitiveFlags::None,
+                                             gpu_cache)
                 }
             }
             BoxShadowKind::Shadow(rects) => {
@@ -991,7 +1296,8 @@ impl FrameBuilder {
                                    &outer_rect,
                                    clip_region,
                                    extra_clip,
-                                   PrimitiveContainer::BoxShadow(prim_gpu, rects));
+                                   PrimitiveContainer::BoxShadow(prim_gpu, rects),
+                                   gpu_cache);
             }
         }
     }
@@ -1000,12 +1306,15 @@ impl FrameBuilder {
                                scroll_layer_id: ScrollLayerId,
                                rect: LayerRect,
                                clip_region: &ClipRegion,
-                               context_id: WebGLContextId) {
+                               context_id: WebGLContextId,
+                               gpu_cache: &mut GpuCache) {
         let prim_cpu = ImagePrimitiveCpu {
             kind: ImagePrimitiveKind::WebGL(context_id),
             color_texture_id: SourceTexture::Invalid,
             resource_address: GpuStoreAddress(0),
             sub_rect: None,
+            color: ColorF::new(1.0, 1.0, 1.0, 1.0),
+            is_alpha_mask: false,
         };
 
         let prim_gpu = ImagePrimitiveGpu {
@@ -1017,7 +1326,8 @@ impl FrameBuilder {
                            &rect,
                            clip_region,
                            None,
-                           PrimitiveContainer::Image(prim_cpu, prim_gpu));
+                           PrimitiveContainer::Image(prim_cpu, prim_gpu),
+                           gpu_cache);
     }
 
     pub fn add_image(&mut self,
@@ -1029,7 +1339,9 @@ impl FrameBuilder {
                      sub_rect: Option<TexelRect>,
                      image_key: ImageKey,
                      image_rendering: ImageRendering,
-                     tile: Option<TileOffset>) {
+                     color: ColorF,
+                     tile: Option<TileOffset>,
+                     gpu_cache: &mut GpuCache) {
         let prim_cpu = ImagePrimitiveCpu {
             kind: ImagePrimitiveKind::Image(image_key,
                                             image_rendering,
@@ -1038,6 +1350,8 @@ impl FrameBuilder {
             color_texture_id: SourceTexture::Invalid,
             resource_address: GpuStoreAddress(0),
             sub_rect: sub_rect,
+            color: color,
+            is_alpha_mask: false,
         };
 
         let prim_gpu = ImagePrimitiveGpu {
@@ -1049,7 +1363,8 @@ impl FrameBuilder {
                            &rect,
                            clip_region,
                            None,
-                           PrimitiveContainer::Image(prim_cpu, prim_gpu));
+                           PrimitiveContainer::Image(prim_cpu, prim_gpu),
+                           gpu_cache);
     }
 
     pub fn add_yuv_image(&mut self,
@@ -1059,7 +1374,8 @@ impl FrameBuilder {
                          y_image_key: ImageKey,
                          u_image_key: ImageKey,
                          v_image_key: ImageKey,
-                         color_space: YuvColorSpace) {
+                         color_space: YuvColorSpace,
+                         gpu_cache: &mut GpuCache) {
 
         let prim_cpu = YuvImagePrimitiveCpu {
             yuv_key: [y_image_key, u_image_key, v_image_key],
@@ -1073,77 +1389,457 @@ impl FrameBuilder {
                            &rect,
                            clip_region,
                            None,
-                           PrimitiveContainer::YuvImage(prim_cpu, prim_gpu));
+                           PrimitiveContainer::YuvImage(prim_cpu, prim_gpu),
+                           gpu_cache);
     }
 
     /// Compute the contribution (bounding rectangles, and resources) of layers and their
     /// primitives in screen space.
     fn build_layer_screen_rects_and_cull_layers(&mut self,
                                                 screen_rect: &DeviceIntRect,
+                                                cull_rect: &DeviceIntRect,
                                                 clip_scroll_tree: &mut ClipScrollTree,
                                                 auxiliary_lists_map: &AuxiliaryListsMap,
                                                 resource_cache: &mut ResourceCache,
+                                                gpu_cache: &mut GpuCache,
                                                 profile_counters: &mut FrameProfileCounters,
                                                 device_pixel_ratio: f32) {
         profile_scope!("cull");
         LayerRectCalculationAndCullingPass::create_and_run(self,
                                                            screen_rect,
+                                                           cull_rect,
                                                            clip_scroll_tree,
                                                            auxiliary_lists_map,
                                                            resource_cache,
+                                                           gpu_cache,
                                                            profile_counters,
                                                            device_pixel_ratio);
     }
 
-    fn update_scroll_bars(&mut self, clip_scroll_tree: &ClipScrollTree) {
-        let distance_from_edge = 8.0;
+    /// Finds the device rect of every pipeline drawn into a render target,
+    /// from the bounds of its root scroll layer. Returns the screen rect
+    /// grown to contain them.
+    fn update_render_target_rects(&mut self,
+                                  screen_rect: &DeviceIntRect,
+                                  clip_scroll_tree: &ClipScrollTree,
+                                  device_pixel_ratio: f32)
+                                  -> DeviceIntRect {
+        let mut cull_rect = *screen_rect;
+        for stacking_context in &mut self.stacking_context_store {
+            if stacking_context.render_target.is_none() {
+                continue;
+            }
+
+            let scroll_layer_id = ScrollLayerId::root_scroll_layer(stacking_context.pipeline_id);
+            stacking_context.render_target_rect = match clip_scroll_tree.nodes.get(&scroll_layer_id) {
+                Some(node) => {
+                    TransformedRect::new(&node.local_viewport_rect,
+                                         &node.world_viewport_transform,
+                                         device_pixel_ratio).bounding_rect
+                }
+                None => DeviceIntRect::zero(),
+            };
+            cull_rect = cull_rect.union(&stacking_context.render_target_rect);
+        }
+        cull_rect
+    }
+
+    fn update_scroll_bars(&mut self, clip_scroll_tree: &ClipScrollTree, gpu_cache: &mut GpuCache) {
+        let theme = self.config.scrollbar_theme;
+        let now = precise_time_ns();
 
         for scrollbar_prim in &self.scrollbar_prims {
-            let mut geom = (*self.prim_store.gpu_geometry.get(GpuStoreAddress(scrollbar_prim.prim_index.0 as i32))).clone();
+            let geometry_address = self.prim_store.get_metadata(scrollbar_prim.prim_index)
+                                                  .gpu_geometry_address;
+            let mut geom = gpu_cache.get::<PrimitiveGeometry>(geometry_address).clone();
             let clip_scroll_node = &clip_scroll_tree.nodes[&scrollbar_prim.scroll_layer_id];
 
-            let scrollable_distance = clip_scroll_node.scrollable_height();
+            let opacity = if self.config.deterministic {
+                1.0
+            } else {
+                let idle_ns = now.saturating_sub(clip_scroll_node.scrolling.last_scroll_time_ns);
+                scrollbar_opacity(&theme, idle_ns)
+            };
+            let rect = match scrollbar_rect(&theme, clip_scroll_node, scrollbar_prim.part) {
+                Some(rect) if opacity > 0.0 => rect,
+                _ => {
+                    geom.local_clip_rect.size = LayerSize::zero();
+                    *gpu_cache.get_mut(geometry_address) = geom;
+                    continue;
+                }
+            };
+
+            geom.local_rect = rect;
+            geom.local_clip_rect = rect;
+
+            let clip_source = if theme.border_radius > 0.0 {
+                Some(ClipSource::Complex(rect, theme.border_radius, ClipMode::Clip))
+            } else {
+                None
+            };
+            self.prim_store.set_clip_source(scrollbar_prim.prim_index, clip_source, gpu_cache);
+
+            let mut color = scrollbar_prim.color;
+            color.a *= opacity;
+            self.prim_store.set_rectangle_color(scrollbar_prim.prim_index, color, gpu_cache);
+
+            *gpu_cache.get_mut(geometry_address) = geom;
+        }
+    }
+
+    fn build_statistics(&self, passes: &[RenderPass]) -> PipelineStatistics {
+        let mut statistics = PipelineStatistics::default();
+        statistics.total_primitives = self.prim_store.prim_count();
 
-            if scrollable_distance <= 0.0 {
-                geom.local_clip_rect.size = LayerSize::zero();
-                *self.prim_store.gpu_geometry.get_mut(GpuStoreAddress(scrollbar_prim.prim_index.0 as i32)) = geom;
+        for (prim_index, bounding_rect) in self.prim_store.cpu_bounding_rects.iter().enumerate() {
+            if bounding_rect.is_none() {
                 continue;
             }
+            let metadata = self.prim_store.get_metadata(PrimitiveIndex(prim_index));
+            statistics.visible_primitives += 1;
+            statistics.primitives.add(metadata.prim_kind);
+            if metadata.clip_task.is_some() {
+                statistics.clip_masks += 1;
+            }
+        }
+
+        statistics.passes = passes.len();
+        for pass in passes {
+            statistics.color_targets += pass.color_targets.target_count();
+            statistics.alpha_targets += pass.alpha_targets.target_count();
+            for target in &pass.color_targets.targets {
+                statistics.batches.accumulate(&target.alpha_batcher.stats);
+            }
+        }
+
+        statistics
+    }
+
+    /// Describes what each visible primitive and each composited stacking
+    /// context draws, so that the renderer can compare frames. A change in
+    /// the drawing order of otherwise unchanged items is not detected.
+    fn build_damage_items(&self,
+                          frame_id: FrameId,
+                          overlay_prims: &HashSet<PrimitiveIndex, BuildHasherDefault<FnvHasher>>,
+                          resource_cache: &ResourceCache,
+                          gpu_cache: &GpuCache) -> Vec<DamageItem> {
+        let mut damage_items = Vec::new();
+        let mut sc_stack = Vec::new();
+
+        for cmd in &self.cmds {
+            match *cmd {
+                PrimitiveRunCmd::PushStackingContext(stacking_context_index) => {
+                    sc_stack.push(stacking_context_index);
 
-            let f = -clip_scroll_node.scrolling.offset.y / scrollable_distance;
+                    // Filters and blend modes change the pixels of everything
+                    // in the stacking context.
+                    let stacking_context = &self.stacking_context_store[stacking_context_index.0];
+                    if stacking_context.is_visible && stacking_context.composite_ops.count() > 0 {
+                        let mut hasher = FnvHasher::default();
+                        stacking_context.composite_ops.filters.hash(&mut hasher);
+                        stacking_context.composite_ops.mix_blend_mode.hash(&mut hasher);
+                        damage_items.push(DamageItem {
+                            rect: stacking_context.bounding_rect,
+                            key: hasher.finish(),
+                        });
+                    }
+                }
+                PrimitiveRunCmd::PopStackingContext => {
+                    sc_stack.pop();
+                }
+                PrimitiveRunCmd::PrimitiveRun(first_prim_index, prim_count, scroll_layer_id) => {
+                    let stacking_context_index = *sc_stack.last().unwrap();
+                    let stacking_context = &self.stacking_context_store[stacking_context_index.0];
+                    if !stacking_context.is_visible {
+                        continue;
+                    }
 
-            let min_y = clip_scroll_node.local_viewport_rect.origin.y -
-                        clip_scroll_node.scrolling.offset.y +
-                        distance_from_edge;
+                    let group_index = stacking_context.clip_scroll_group(scroll_layer_id);
+                    let group = &self.clip_scroll_group_store[group_index.0];
+                    let packed_layer = &self.packed_layers[group.packed_layer_index.0];
 
-            let max_y = clip_scroll_node.local_viewport_rect.origin.y +
-                        clip_scroll_node.local_viewport_rect.size.height -
-                        clip_scroll_node.scrolling.offset.y -
-                        geom.local_rect.size.height -
-                        distance_from_edge;
+                    for i in 0..prim_count {
+                        let prim_index = PrimitiveIndex(first_prim_index.0 + i);
+                        let bounding_rect = match self.prim_store.cpu_bounding_rects[prim_index.0] {
+                            Some(rect) => rect,
+                            None => continue,
+                        };
+
+                        let mut hasher = FnvHasher::default();
+                        if overlay_prims.contains(&prim_index) {
+                            // Presented by the embedder, not drawn.
+                            u32::max_value().hash(&mut hasher);
+                        } else {
+                            for value in &packed_layer.transform.to_row_major_array() {
+                                hasher.write_u32(unsafe { mem::transmute::<f32, u32>(*value) });
+                            }
+                            if !self.prim_store.hash_primitive(prim_index,
+                                                               resource_cache,
+                                                               gpu_cache,
+                                                               &mut hasher) {
+                                frame_id.0.hash(&mut hasher);
+                            }
+                        }
 
-            geom.local_rect.origin.x = clip_scroll_node.local_viewport_rect.origin.x +
-                                       clip_scroll_node.local_viewport_rect.size.width -
-                                       geom.local_rect.size.width -
-                                       distance_from_edge;
+                        damage_items.push(DamageItem {
+                            rect: bounding_rect,
+                            key: hasher.finish(),
+                        });
+                    }
+                }
+            }
+        }
 
-            geom.local_rect.origin.y = util::lerp(min_y, max_y, f);
-            geom.local_clip_rect = geom.local_rect;
+        damage_items
+    }
 
-            let clip_source = if scrollbar_prim.border_radius > 0.0 {
-                Some(ClipSource::Complex(geom.local_rect, scrollbar_prim.border_radius, ClipMode::Clip))
-            } else {
-                None
+    /// Split the transforms of all packed layers below a late-latched reference
+    /// frame around the pose, so that the renderer can substitute a newer pose
+    /// just before uploading the layer data. Nested late-latched reference
+    /// frames are not supported; the innermost one wins.
+    fn build_late_latched_layers(&self, clip_scroll_tree: &ClipScrollTree) -> Vec<LateLatchedLayer> {
+        let mut late_latched_layers = Vec::new();
+
+        for frame in &self.late_latched_reference_frames {
+            let node = match clip_scroll_tree.nodes.get(&frame.scroll_layer_id) {
+                Some(node) => node,
+                None => continue,
+            };
+            let local_transform = match node.node_type {
+                NodeType::ReferenceFrame(ref transform) => transform.to_untyped(),
+                NodeType::Clip(_) => continue,
             };
-            self.prim_store.set_clip_source(scrollbar_prim.prim_index, clip_source);
-            *self.prim_store.gpu_geometry.get_mut(GpuStoreAddress(scrollbar_prim.prim_index.0 as i32)) = geom;
+
+            // The world transform of the reference frame is
+            // parent * pre_transform * pose * post_transform.
+            let world_transform = node.world_viewport_transform.to_untyped();
+            let (inv_local_transform, inv_world_transform) =
+                match (local_transform.inverse(), world_transform.inverse()) {
+                    (Some(inv_local), Some(inv_world)) => (inv_local, inv_world),
+                    _ => continue,
+                };
+            let pre_transform = world_transform.pre_mul(&inv_local_transform)
+                                               .pre_mul(&frame.pre_transform);
+            let post_transform = frame.post_transform.pre_mul(&inv_world_transform);
+
+            let mut add_layer = |packed_layer_index: PackedLayerIndex| {
+                let layer_transform = self.packed_layers[packed_layer_index.0].transform.to_untyped();
+                late_latched_layers.push(LateLatchedLayer {
+                    binding_id: frame.binding_id,
+                    packed_layer_index: packed_layer_index,
+                    pre_transform: pre_transform,
+                    post_transform: post_transform.pre_mul(&layer_transform),
+                });
+            };
+
+            for group in &self.clip_scroll_group_store {
+                if clip_scroll_tree.is_descendant_of(group.scroll_layer_id, frame.scroll_layer_id) {
+                    add_layer(group.packed_layer_index);
+                }
+            }
+
+            for (id, node) in &clip_scroll_tree.nodes {
+                if let NodeType::Clip(ref clip_info) = node.node_type {
+                    if clip_scroll_tree.is_descendant_of(*id, frame.scroll_layer_id) {
+                        add_layer(clip_info.packed_layer_index);
+                    }
+                }
+            }
+        }
+
+        late_latched_layers
+    }
+
+    /// Find the primitives that `is_candidate` accepts and that could be
+    /// presented outside of the main framebuffer. Those are axis-aligned,
+    /// not clipped by a mask, not affected by any filter or blend, and not
+    /// covered by any primitive drawn after them. Returns their indices, the
+    /// rects they are drawn to, and the visible parts of those rects.
+    fn find_promotable_prims<F>(&self,
+                                device_pixel_ratio: f32,
+                                gpu_cache: &GpuCache,
+                                is_candidate: F)
+                                -> Vec<(PrimitiveIndex, DeviceIntRect, DeviceIntRect)>
+                                where F: Fn(PrimitiveIndex, &PrimitiveMetadata) -> bool {
+        let mut sc_stack = Vec::new();
+        let mut composite_depth = 0;
+        let mut drawn_prims = Vec::new();
+
+        for cmd in &self.cmds {
+            match *cmd {
+                PrimitiveRunCmd::PushStackingContext(stacking_context_index) => {
+                    let stacking_context = &self.stacking_context_store[stacking_context_index.0];
+                    if stacking_context.composite_ops.count() > 0 || stacking_context.should_isolate {
+                        composite_depth += 1;
+                    }
+                    sc_stack.push(stacking_context_index);
+                }
+                PrimitiveRunCmd::PopStackingContext => {
+                    let stacking_context_index = sc_stack.pop().unwrap();
+                    let stacking_context = &self.stacking_context_store[stacking_context_index.0];
+                    if stacking_context.composite_ops.count() > 0 || stacking_context.should_isolate {
+                        composite_depth -= 1;
+                    }
+                }
+                PrimitiveRunCmd::PrimitiveRun(first_prim_index, prim_count, scroll_layer_id) => {
+                    let stacking_context_index = *sc_stack.last().unwrap();
+                    let stacking_context = &self.stacking_context_store[stacking_context_index.0];
+                    if !stacking_context.is_visible {
+                        continue;
+                    }
+
+                    let group_index = stacking_context.clip_scroll_group(scroll_layer_id);
+                    let group = &self.clip_scroll_group_store[group_index.0];
+                    let is_axis_aligned = match group.xf_rect {
+                        Some(ref xf_rect) => xf_rect.kind == TransformedRectKind::AxisAligned,
+                        None => continue,
+                    };
+
+                    for i in 0..prim_count {
+                        let prim_index = PrimitiveIndex(first_prim_index.0 + i);
+                        let bounding_rect = match self.prim_store.cpu_bounding_rects[prim_index.0] {
+                            Some(rect) => rect,
+                            None => continue,
+                        };
+
+                        let metadata = self.prim_store.get_metadata(prim_index);
+                        let is_candidate = metadata.clip_task.is_none() &&
+                                           composite_depth == 0 &&
+                                           is_axis_aligned &&
+                                           is_candidate(prim_index, metadata);
+                        drawn_prims.push((prim_index,
+                                          bounding_rect,
+                                          is_candidate,
+                                          group.packed_layer_index));
+                    }
+                }
+            }
+        }
+
+        let mut promotable_prims = Vec::new();
+
+        for (i, &(prim_index, bounding_rect, is_candidate, packed_layer_index)) in drawn_prims.iter().enumerate() {
+            if !is_candidate {
+                continue;
+            }
+
+            let is_occluded = drawn_prims[i+1..].iter().any(|&(_, rect, _, _)| {
+                rect.intersects(&bounding_rect)
+            });
+            if is_occluded {
+                continue;
+            }
+
+            let metadata = self.prim_store.get_metadata(prim_index);
+            let geometry = gpu_cache.get::<PrimitiveGeometry>(metadata.gpu_geometry_address);
+            let packed_layer = &self.packed_layers[packed_layer_index.0];
+            let xf_rect = TransformedRect::new(&geometry.local_rect,
+                                               &packed_layer.transform,
+                                               device_pixel_ratio);
+
+            promotable_prims.push((prim_index, xf_rect.bounding_rect, bounding_rect));
         }
+
+        promotable_prims
     }
 
-    fn build_render_task(&self) -> (RenderTask, usize) {
+    /// Find the video primitives that the embedder could promote to hardware
+    /// overlay planes.
+    fn find_overlay_candidates(&self,
+                               device_pixel_ratio: f32,
+                               gpu_cache: &GpuCache)
+                               -> Vec<(PrimitiveIndex, OverlayCandidate)> {
+        if !self.config.enable_overlay_promotion {
+            return Vec::new();
+        }
+
+        let prims = self.find_promotable_prims(device_pixel_ratio, gpu_cache, |_, metadata| {
+            metadata.prim_kind == PrimitiveKind::YuvImage
+        });
+
+        prims.into_iter().map(|(prim_index, rect, clip_rect)| {
+            let metadata = self.prim_store.get_metadata(prim_index);
+            let image_cpu = &self.prim_store.cpu_yuv_images[metadata.cpu_prim_index.0];
+            (prim_index, OverlayCandidate {
+                image_keys: image_cpu.yuv_key,
+                rect: rect,
+                clip_rect: clip_rect,
+            })
+        }).collect()
+    }
+
+    /// The texture a primitive draws, if it is an image whose contents
+    /// change independently of the scene (WebGL canvases and external
+    /// images such as video frames), drawn once and without tint.
+    fn frequently_updating_image(&self,
+                                 prim_index: PrimitiveIndex,
+                                 resource_cache: &ResourceCache,
+                                 gpu_cache: &GpuCache)
+                                 -> Option<(SourceTexture, ImageRendering)> {
+        let metadata = self.prim_store.get_metadata(prim_index);
+        if metadata.prim_kind != PrimitiveKind::Image {
+            return None;
+        }
+
+        let image_cpu = &self.prim_store.cpu_images[metadata.cpu_prim_index.0];
+        if image_cpu.is_alpha_mask || image_cpu.sub_rect.is_some() {
+            return None;
+        }
+
+        match image_cpu.kind {
+            ImagePrimitiveKind::Image(image_key, image_rendering, None, stretch_size) => {
+                let geometry = gpu_cache.get::<PrimitiveGeometry>(metadata.gpu_geometry_address);
+                if stretch_size != geometry.local_rect.size {
+                    return None;
+                }
+                resource_cache.get_image_properties(image_key)
+                              .external_image
+                              .map(|external_image| (SourceTexture::External(external_image), image_rendering))
+            }
+            ImagePrimitiveKind::Image(..) => None,
+            ImagePrimitiveKind::WebGL(context_id) => {
+                Some((SourceTexture::WebGL(context_id), ImageRendering::Auto))
+            }
+        }
+    }
+
+    /// Find the frequently updating images that can be drawn into compositor
+    /// surfaces of their own, see `RendererOptions::enable_compositor_surfaces`.
+    fn find_compositor_surfaces(&self,
+                                device_pixel_ratio: f32,
+                                resource_cache: &ResourceCache,
+                                gpu_cache: &GpuCache)
+                                -> Vec<(PrimitiveIndex, CompositorSurface)> {
+        if !self.config.enable_compositor_surfaces {
+            return Vec::new();
+        }
+
+        let prims = self.find_promotable_prims(device_pixel_ratio, gpu_cache, |prim_index, _| {
+            self.frequently_updating_image(prim_index, resource_cache, gpu_cache).is_some()
+        });
+
+        prims.into_iter().map(|(prim_index, rect, clip_rect)| {
+            let (texture, image_rendering) =
+                self.frequently_updating_image(prim_index, resource_cache, gpu_cache).unwrap();
+            let metadata = self.prim_store.get_metadata(prim_index);
+            let image_cpu = &self.prim_store.cpu_images[metadata.cpu_prim_index.0];
+            (prim_index, CompositorSurface {
+                texture: texture,
+                image_rendering: image_rendering,
+                resource_address: image_cpu.resource_address,
+                rect: rect,
+                clip_rect: clip_rect,
+            })
+        }).collect()
+    }
+
+    fn build_render_task(&self,
+                         overlay_prims: &HashSet<PrimitiveIndex, BuildHasherDefault<FnvHasher>>,
+                         render_task_cache: &mut RenderTaskCache)
+                         -> (RenderTask, usize) {
         profile_scope!("build_render_task");
 
-        let mut next_z = 0;
+        let mut z_allocator = ZAllocator::new(self.config.z_allocation);
         let mut next_task_index = RenderTaskIndex(0);
 
         let mut sc_stack = Vec::new();
@@ -1188,6 +1884,15 @@ impl FrameBuilder {
                         let prev_task = mem::replace(&mut current_task, new_task);
                         alpha_task_stack.push(prev_task);
                     }
+
+                    // The innermost task holds the contents before any of
+                    // the composite ops are applied.
+                    if let Some(snapshot) = stacking_context.snapshot {
+                        current_task.as_alpha_batch().snapshot = Some(snapshot);
+                    }
+                    if let Some(render_target) = stacking_context.render_target {
+                        current_task.as_alpha_batch().render_target = Some(render_target);
+                    }
                 }
                 PrimitiveRunCmd::PopStackingContext => {
                     let stacking_context_index = sc_stack.pop().unwrap();
@@ -1201,12 +1906,13 @@ impl FrameBuilder {
 
                     if composite_count == 0 && stacking_context.should_isolate {
                         let mut prev_task = alpha_task_stack.pop().unwrap();
-                        let item = AlphaRenderItem::HardwareComposite(stacking_context_index,
-                                                                      current_task.id,
-                                                                      HardwareCompositeOp::PremultipliedAlpha,
-                                                                      next_z);
-                        next_z += 1;
-                        prev_task.as_alpha_batch().alpha_items.push(item);
+                        if stacking_context.render_target.is_none() {
+                            let item = AlphaRenderItem::HardwareComposite(stacking_context_index,
+                                                                          current_task.id,
+                                                                          HardwareCompositeOp::PremultipliedAlpha,
+                                                                          z_allocator.alloc_translucent());
+                            prev_task.as_alpha_batch().alpha_items.push(item);
+                        }
                         prev_task.children.push(current_task);
                         current_task = prev_task;
                     }
@@ -1216,8 +1922,7 @@ impl FrameBuilder {
                         let item = AlphaRenderItem::Blend(stacking_context_index,
                                                           current_task.id,
                                                           *filter,
-                                                          next_z);
-                        next_z += 1;
+                                                          z_allocator.alloc_translucent());
                         prev_task.as_alpha_batch().alpha_items.push(item);
                         prev_task.children.push(current_task);
                         current_task = prev_task;
@@ -1232,8 +1937,7 @@ impl FrameBuilder {
                                                               readback_task.id,
                                                               current_task.id,
                                                               mix_blend_mode,
-                                                              next_z);
-                        next_z += 1;
+                                                              z_allocator.alloc_translucent());
                         prev_task.as_alpha_batch().alpha_items.push(item);
                         prev_task.children.push(current_task);
                         prev_task.children.push(readback_task);
@@ -1259,12 +1963,17 @@ impl FrameBuilder {
                     for i in 0..prim_count {
                         let prim_index = PrimitiveIndex(first_prim_index.0 + i);
 
+                        // Primitives promoted to overlays are composited by the embedder.
+                        if overlay_prims.contains(&prim_index) {
+                            continue;
+                        }
+
                         if self.prim_store.cpu_bounding_rects[prim_index.0].is_some() {
                             let prim_metadata = self.prim_store.get_metadata(prim_index);
 
                             // Add any dynamic render tasks needed to render this primitive
                             if let Some(ref render_task) = prim_metadata.render_task {
-                                current_task.children.push(render_task.clone());
+                                current_task.children.push(render_task_cache.request(render_task.clone()));
                             }
                             if let Some(ref clip_task) = prim_metadata.clip_task {
                                 current_task.children.push(clip_task.clone());
@@ -1275,13 +1984,14 @@ impl FrameBuilder {
                                                  !prim_metadata.is_opaque ||
                                                  needs_clipping;
 
-                            let items = if needs_blending {
-                                &mut current_task.as_alpha_batch().alpha_items
+                            let (items, z) = if needs_blending {
+                                (&mut current_task.as_alpha_batch().alpha_items,
+                                 z_allocator.alloc_translucent())
                             } else {
-                                &mut current_task.as_alpha_batch().opaque_items
+                                (&mut current_task.as_alpha_batch().opaque_items,
+                                 z_allocator.alloc_opaque())
                             };
-                            items.push(AlphaRenderItem::Primitive(group_index, prim_index, next_z));
-                            next_z += 1;
+                            items.push(AlphaRenderItem::Primitive(group_index, prim_index, z));
                         }
                     }
                 }
@@ -1289,16 +1999,23 @@ impl FrameBuilder {
         }
 
         debug_assert!(alpha_task_stack.is_empty());
+        if z_allocator.used_z_count() > self.config.max_z {
+            warn!("Frame uses {} z values, but the depth range only fits {}",
+                  z_allocator.used_z_count(),
+                  self.config.max_z);
+        }
         (current_task, next_task_index.0)
     }
 
     pub fn build(&mut self,
                  resource_cache: &mut ResourceCache,
+                 gpu_cache: &mut GpuCache,
                  frame_id: FrameId,
                  clip_scroll_tree: &mut ClipScrollTree,
                  auxiliary_lists_map: &AuxiliaryListsMap,
                  device_pixel_ratio: f32,
-                 texture_cache_profile: &mut TextureCacheProfileCounters)
+                 texture_cache_profile: &mut TextureCacheProfileCounters,
+                 render_task_cache: &mut RenderTaskCache)
                  -> Frame {
         profile_scope!("build");
 
@@ -1306,46 +2023,88 @@ impl FrameBuilder {
         profile_counters.total_primitives.set(self.prim_store.prim_count());
 
         resource_cache.begin_frame(frame_id);
+        let render_task_cache_frame = render_task_cache.begin_frame(frame_id, device_pixel_ratio);
 
         let screen_rect = DeviceIntRect::new(
             DeviceIntPoint::zero(),
             DeviceIntSize::new(self.screen_size.width as i32,
                                self.screen_size.height as i32));
 
+        // Pipelines drawn into render targets are drawn whole, even where
+        // they are off the screen, so primitives are culled against their
+        // bounds as well.
+        let cull_rect = self.update_render_target_rects(&screen_rect,
+                                                        clip_scroll_tree,
+                                                        device_pixel_ratio);
+
+        // Culling uses the poses known now, and the renderer may latch newer
+        // ones that bring content from past the edges of the screen into view.
+        let cull_rect = if self.late_latched_reference_frames.is_empty() {
+            cull_rect
+        } else {
+            let margin = self.config.late_latch_cull_margin;
+            cull_rect.inflate((screen_rect.size.width as f32 * margin).ceil() as i32,
+                              (screen_rect.size.height as f32 * margin).ceil() as i32)
+        };
+
         // Pick a size for the cache render targets to be. The main requirement is that it
         // has to be at least as large as the framebuffer size. This ensures that it will
         // always be able to allocate the worst case render task (such as a clip mask that
-        // covers the entire screen).
-        let cache_size = DeviceUintSize::new(cmp::max(1024, screen_rect.size.width as u32),
-                                             cmp::max(1024, screen_rect.size.height as u32));
+        // covers the entire screen, or a whole pipeline drawn into a render target).
+        let cache_size = DeviceUintSize::new(cmp::max(1024, cull_rect.size.width as u32),
+                                             cmp::max(1024, cull_rect.size.height as u32));
 
-        self.update_scroll_bars(clip_scroll_tree);
+        self.update_scroll_bars(clip_scroll_tree, gpu_cache);
 
         self.build_layer_screen_rects_and_cull_layers(&screen_rect,
+                                                      &cull_rect,
                                                       clip_scroll_tree,
                                                       auxiliary_lists_map,
                                                       resource_cache,
+                                                      gpu_cache,
                                                       &mut profile_counters,
                                                       device_pixel_ratio);
 
-        let (main_render_task, static_render_task_count) = self.build_render_task();
-        let mut render_tasks = RenderTaskCollection::new(static_render_task_count);
+        let late_latched_layers = self.build_late_latched_layers(clip_scroll_tree);
 
-        let mut required_pass_count = 0;
-        main_render_task.max_depth(0, &mut required_pass_count);
+        let overlay_candidates = self.find_overlay_candidates(device_pixel_ratio, gpu_cache);
+        let compositor_surfaces = self.find_compositor_surfaces(device_pixel_ratio,
+                                                                resource_cache,
+                                                                gpu_cache);
+        // Looked up for every visible primitive, so kept in a set.
+        let overlay_prims: HashSet<PrimitiveIndex, BuildHasherDefault<FnvHasher>> =
+            overlay_candidates.iter()
+                              .map(|&(prim_index, _)| prim_index)
+                              .chain(compositor_surfaces.iter().map(|&(prim_index, _)| prim_index))
+                              .collect();
+
+        let damage_items = self.build_damage_items(frame_id, &overlay_prims, resource_cache, gpu_cache);
+
+        let (main_render_task, static_render_task_count) =
+            self.build_render_task(&overlay_prims, render_task_cache);
+        let mut render_tasks = RenderTaskCollection::new(static_render_task_count);
+        let mut render_task_graph = RenderTaskGraph::new(main_render_task);
+        let required_pass_count = render_task_graph.pass_count();
 
         resource_cache.block_until_all_resources_added(texture_cache_profile);
 
         for node in clip_scroll_tree.nodes.values() {
             if let NodeType::Clip(ref clip_info) = node.node_type {
                 if let Some(ref mask_info) = clip_info.mask_cache_info {
-                    self.prim_store.resolve_clip_cache(mask_info, resource_cache);
+                    PrimitiveStore::resolve_clip_cache(mask_info, gpu_cache, resource_cache);
                 }
             }
         }
 
-        let deferred_resolves = self.prim_store.resolve_primitives(resource_cache,
-                                                                   device_pixel_ratio);
+        let mut deferred_resolves = self.prim_store.resolve_primitives(resource_cache,
+                                                                       gpu_cache,
+                                                                       device_pixel_ratio);
+        // The renderer resolves the images of compositor surfaces itself.
+        deferred_resolves.retain(|deferred_resolve| {
+            !compositor_surfaces.iter().any(|&(_, ref surface)| {
+                surface.resource_address == deferred_resolve.resource_address
+            })
+        });
 
         let mut passes = Vec::new();
 
@@ -1357,7 +2116,7 @@ impl FrameBuilder {
                                         cache_size));
         }
 
-        main_render_task.assign_to_passes(passes.len() - 1, &mut passes);
+        render_task_graph.assign_to_passes(&mut passes);
 
         for pass in &mut passes {
             let ctx = RenderTargetContext {
@@ -1365,6 +2124,12 @@ impl FrameBuilder {
                 clip_scroll_group_store: &self.clip_scroll_group_store,
                 prim_store: &self.prim_store,
                 resource_cache: resource_cache,
+                gpu_cache: gpu_cache,
+                packed_layers: &self.packed_layers,
+                late_latched_layers: &late_latched_layers,
+                device_pixel_ratio: device_pixel_ratio,
+                render_task_cache: render_task_cache,
+                batch_lookback_count: self.config.batch_lookback_count,
             };
 
             pass.build(&ctx, &mut render_tasks);
@@ -1374,6 +2139,10 @@ impl FrameBuilder {
             profile_counters.alpha_targets.add(pass.alpha_targets.target_count());
         }
 
+        render_task_graph.assign_texture_slots(&mut passes);
+
+        let statistics = self.build_statistics(&passes);
+
         resource_cache.end_frame();
 
         Frame {
@@ -1385,14 +2154,19 @@ impl FrameBuilder {
             cache_size: cache_size,
             layer_texture_data: self.packed_layers.clone(),
             render_task_data: render_tasks.render_task_data,
-            gpu_data16: self.prim_store.gpu_data16.build(),
-            gpu_data32: self.prim_store.gpu_data32.build(),
-            gpu_data64: self.prim_store.gpu_data64.build(),
-            gpu_data128: self.prim_store.gpu_data128.build(),
-            gpu_geometry: self.prim_store.gpu_geometry.build(),
+            gpu_cache_updates: gpu_cache.take_updates(),
             gpu_gradient_data: self.prim_store.gpu_gradient_data.build(),
-            gpu_resource_rects: self.prim_store.gpu_resource_rects.build(),
+            late_latched_layers: late_latched_layers,
+            overlay_candidates: overlay_candidates.into_iter()
+                                                  .map(|(_, candidate)| candidate)
+                                                  .collect(),
+            compositor_surfaces: compositor_surfaces.into_iter()
+                                                    .map(|(_, surface)| surface)
+                                                    .collect(),
+            damage_items: damage_items,
+            statistics: statistics,
             deferred_resolves: deferred_resolves,
+            render_task_cache_frame: render_task_cache_frame,
         }
     }
 
@@ -1401,9 +2175,13 @@ impl FrameBuilder {
 struct LayerRectCalculationAndCullingPass<'a> {
     frame_builder: &'a mut FrameBuilder,
     screen_rect: &'a DeviceIntRect,
+    /// The screen rect, grown to contain the pipelines drawn into render
+    /// targets.
+    cull_rect: &'a DeviceIntRect,
     clip_scroll_tree: &'a mut ClipScrollTree,
     auxiliary_lists_map: &'a AuxiliaryListsMap,
     resource_cache: &'a mut ResourceCache,
+    gpu_cache: &'a mut GpuCache,
     profile_counters: &'a mut FrameProfileCounters,
     device_pixel_ratio: f32,
     stacking_context_stack: Vec<StackingContextIndex>,
@@ -1421,18 +2199,22 @@ struct LayerRectCalculationAndCullingPass<'a> {
 impl<'a> LayerRectCalculationAndCullingPass<'a> {
     fn create_and_run(frame_builder: &'a mut FrameBuilder,
                       screen_rect: &'a DeviceIntRect,
+                      cull_rect: &'a DeviceIntRect,
                       clip_scroll_tree: &'a mut ClipScrollTree,
                       auxiliary_lists_map: &'a AuxiliaryListsMap,
                       resource_cache: &'a mut ResourceCache,
+                      gpu_cache: &'a mut GpuCache,
                       profile_counters: &'a mut FrameProfileCounters,
                       device_pixel_ratio: f32) {
 
         let mut pass = LayerRectCalculationAndCullingPass {
             frame_builder: frame_builder,
             screen_rect: screen_rect,
+            cull_rect: cull_rect,
             clip_scroll_tree: clip_scroll_tree,
             auxiliary_lists_map: auxiliary_lists_map,
             resource_cache: resource_cache,
+            gpu_cache: gpu_cache,
             profile_counters: profile_counters,
             device_pixel_ratio: device_pixel_ratio,
             stacking_context_stack: Vec::new(),
@@ -1486,7 +2268,7 @@ impl<'a> LayerRectCalculationAndCullingPass<'a> {
                 node.combined_local_viewport_rect.translate(&-node.local_viewport_rect.origin);
 
             node_clip_info.xf_rect = packed_layer.set_rect(&local_viewport_rect,
-                                                           self.screen_rect,
+                                                           self.cull_rect,
                                                            self.device_pixel_ratio);
 
             let mask_info = match node_clip_info.mask_cache_info {
@@ -1499,7 +2281,7 @@ impl<'a> LayerRectCalculationAndCullingPass<'a> {
 
             mask_info.update(&node_clip_info.clip_sources,
                              &packed_layer.transform,
-                             &mut self.frame_builder.prim_store.gpu_data32,
+                             self.gpu_cache,
                              self.device_pixel_ratio,
                              auxiliary_lists);
 
@@ -1541,7 +2323,7 @@ impl<'a> LayerRectCalculationAndCullingPass<'a> {
                      .translate(&-stacking_context.reference_frame_offset)
                      .translate(&-node.scrolling.offset);
             group.xf_rect = packed_layer.set_rect(viewport_rect,
-                                                  self.screen_rect,
+                                                  self.cull_rect,
                                                   self.device_pixel_ratio);
         }
     }
@@ -1562,19 +2344,31 @@ impl<'a> LayerRectCalculationAndCullingPass<'a> {
 
     fn handle_pop_stacking_context(&mut self) {
         let stacking_context_index = self.stacking_context_stack.pop().unwrap();
+        let clip_rect = self.clip_rect_for(stacking_context_index);
 
-        let (bounding_rect, is_visible) = {
+        let (bounding_rect, is_visible, is_render_target) = {
             let stacking_context =
                 &mut self.frame_builder.stacking_context_store[stacking_context_index.0];
-            stacking_context.bounding_rect = stacking_context.bounding_rect
-                                                             .intersection(self.screen_rect)
-                                                             .unwrap_or(DeviceIntRect::zero());
-            (stacking_context.bounding_rect.clone(), stacking_context.is_visible)
+            // A render target gets the whole pipeline, so that it maps onto
+            // the external image the same way whatever is on screen.
+            stacking_context.bounding_rect = if stacking_context.render_target.is_some() {
+                stacking_context.render_target_rect
+            } else {
+                stacking_context.bounding_rect
+                                .intersection(&clip_rect)
+                                .unwrap_or(DeviceIntRect::zero())
+            };
+            (stacking_context.bounding_rect.clone(),
+             stacking_context.is_visible,
+             stacking_context.render_target.is_some())
         };
 
         if let Some(ref mut parent_index) = self.stacking_context_stack.last_mut() {
             let parent = &mut self.frame_builder.stacking_context_store[parent_index.0];
-            parent.bounding_rect = parent.bounding_rect.union(&bounding_rect);
+            // Render targets aren't drawn into their parent.
+            if !is_render_target {
+                parent.bounding_rect = parent.bounding_rect.union(&bounding_rect);
+            }
 
             // The previous compute_stacking_context_visibility pass did not take into
             // account visibility of children, so we do that now.
@@ -1582,6 +2376,18 @@ impl<'a> LayerRectCalculationAndCullingPass<'a> {
         }
     }
 
+    /// The rect a stacking context is clipped to: the bounds of the
+    /// innermost pipeline drawn into a render target that contains it, or
+    /// the screen.
+    fn clip_rect_for(&self, stacking_context_index: StackingContextIndex) -> DeviceIntRect {
+        let store = &self.frame_builder.stacking_context_store;
+        Some(stacking_context_index).into_iter()
+                                    .chain(self.stacking_context_stack.iter().rev().cloned())
+                                    .map(|index| &store[index.0])
+                                    .find(|stacking_context| stacking_context.render_target.is_some())
+                                    .map_or(*self.screen_rect, |stacking_context| stacking_context.render_target_rect)
+    }
+
     fn handle_push_stacking_context(&mut self, stacking_context_index: StackingContextIndex) {
         self.stacking_context_stack.push(stacking_context_index);
 
@@ -1663,20 +2469,23 @@ impl<'a> LayerRectCalculationAndCullingPass<'a> {
         for i in 0..prim_count {
             let prim_index = PrimitiveIndex(prim_index.0 + i);
             if self.frame_builder.prim_store.build_bounding_rect(prim_index,
-                                                                 self.screen_rect,
+                                                                 self.cull_rect,
                                                                  &packed_layer.transform,
                                                                  &packed_layer.local_clip_rect,
-                                                                 self.device_pixel_ratio) {
+                                                                 self.device_pixel_ratio,
+                                                                 self.gpu_cache) {
                 if self.frame_builder.prim_store.prepare_prim_for_render(prim_index,
                                                                          self.resource_cache,
+                                                                         self.gpu_cache,
                                                                          &packed_layer.transform,
                                                                          self.device_pixel_ratio,
                                                                          auxiliary_lists) {
                     self.frame_builder.prim_store.build_bounding_rect(prim_index,
-                                                                      self.screen_rect,
+                                                                      self.cull_rect,
                                                                       &packed_layer.transform,
                                                                       &packed_layer.local_clip_rect,
-                                                                      self.device_pixel_ratio);
+                                                                      self.device_pixel_ratio,
+                                                                      self.gpu_cache);
                 }
 
                 // If the primitive is visible, consider culling it via clip rect(s).
@@ -1732,3 +2541,109 @@ impl<'a> LayerRectCalculationAndCullingPass<'a> {
         }
     }
 }
+
+/// Places a scrollbar track or thumb for a scroll node, or returns None if
+/// the node can't scroll along that axis. The scrollbar primitives move with
+/// the node's content, so the scroll offset is undone here.
+fn scrollbar_rect(theme: &ScrollbarTheme,
+                  node: &ClipScrollNode,
+                  part: ScrollbarPart)
+                  -> Option<LayerRect> {
+    let viewport = &node.local_viewport_rect;
+    let offset = node.scrolling.offset;
+    let has_vertical = node.scrollable_height() > 0.0;
+    let has_horizontal = node.scrollable_width() > 0.0;
+
+    // When both scrollbars are shown, they stop short of the shared corner.
+    let corner = theme.width + theme.margin;
+    let left = viewport.origin.x - offset.x;
+    let top = viewport.origin.y - offset.y;
+
+    match part {
+        ScrollbarPart::VerticalTrack | ScrollbarPart::VerticalThumb => {
+            if !has_vertical {
+                return None;
+            }
+
+            let track_length = viewport.size.height - 2.0 * theme.margin -
+                               if has_horizontal { corner } else { 0.0 };
+            if track_length <= 0.0 {
+                return None;
+            }
+
+            let x = left + viewport.size.width - theme.width - theme.margin;
+            let y = top + theme.margin;
+            if part == ScrollbarPart::VerticalTrack {
+                return Some(LayerRect::new(LayerPoint::new(x, y),
+                                           LayerSize::new(theme.width, track_length)));
+            }
+
+            let (thumb_offset, thumb_length) = thumb_extent(theme,
+                                                            track_length,
+                                                            viewport.size.height,
+                                                            node.content_size.height,
+                                                            offset.y);
+            Some(LayerRect::new(LayerPoint::new(x, y + thumb_offset),
+                                LayerSize::new(theme.width, thumb_length)))
+        }
+        ScrollbarPart::HorizontalTrack | ScrollbarPart::HorizontalThumb => {
+            if !has_horizontal {
+                return None;
+            }
+
+            let track_length = viewport.size.width - 2.0 * theme.margin -
+                               if has_vertical { corner } else { 0.0 };
+            if track_length <= 0.0 {
+                return None;
+            }
+
+            let x = left + theme.margin;
+            let y = top + viewport.size.height - theme.width - theme.margin;
+            if part == ScrollbarPart::HorizontalTrack {
+                return Some(LayerRect::new(LayerPoint::new(x, y),
+                                           LayerSize::new(track_length, theme.width)));
+            }
+
+            let (thumb_offset, thumb_length) = thumb_extent(theme,
+                                                            track_length,
+                                                            viewport.size.width,
+                                                            node.content_size.width,
+                                                            offset.x);
+            Some(LayerRect::new(LayerPoint::new(x + thumb_offset, y),
+                                LayerSize::new(thumb_length, theme.width)))
+        }
+    }
+}
+
+/// Returns the position of a thumb along its track, and its length. The
+/// thumb covers the same fraction of the track as the viewport does of the
+/// content, and stays at the ends of the track while overscrolling.
+fn thumb_extent(theme: &ScrollbarTheme,
+                track_length: f32,
+                viewport_length: f32,
+                content_length: f32,
+                scroll_offset: f32)
+                -> (f32, f32) {
+    let thumb_length = (track_length * viewport_length / content_length)
+        .max(theme.min_thumb_length)
+        .min(track_length);
+    let scrollable_length = content_length - viewport_length;
+    let f = (-scroll_offset / scrollable_length).max(0.0).min(1.0);
+    (util::lerp(0.0, track_length - thumb_length, f), thumb_length)
+}
+
+fn scrollbar_opacity(theme: &ScrollbarTheme, idle_ns: u64) -> f32 {
+    if !theme.fade_out {
+        return 1.0;
+    }
+
+    let idle_ms = (idle_ns / 1_000_000) as f32;
+    let fade_delay_ms = theme.fade_delay_ms as f32;
+    if idle_ms <= fade_delay_ms {
+        1.0
+    } else if theme.fade_duration_ms == 0 {
+        0.0
+    } else {
+        (1.0 - (idle_ms - fade_delay_ms) / theme.fade_duration_ms as f32).max(0.0)
+    }
+}
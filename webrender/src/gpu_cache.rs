@@ -0,0 +1,304 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A single GPU-resident cache for primitive data.
+//!
+//! All per-primitive data that the shaders fetch (primitive headers,
+//! geometry, clip data, glyphs, gradient stops and resource rects) is
+//! stored in one RGBAF32 texture, `GPU_CACHE_WIDTH` blocks wide. A block
+//! is one texel (four floats). Addresses handed out by the cache are block
+//! indices, and stay valid until the next scene is built.
+//!
+//! The cache is owned by the render backend and outlives the scenes. Each
+//! scene allocates from the start of the cache again, so a scene that
+//! mostly repeats the previous one writes mostly the same blocks to the
+//! same addresses. The cache tracks which rows were written to since the
+//! last call to `take_updates`, and only sends the renderer those whose
+//! contents actually changed.
+
+use gpu_store::GpuStoreAddress;
+use renderer::MAX_VERTEX_TEXTURE_WIDTH;
+use std::hash::Hasher;
+use std::mem;
+use std::slice;
+
+/// The width of the cache texture, in blocks.
+pub const GPU_CACHE_WIDTH: usize = MAX_VERTEX_TEXTURE_WIDTH;
+
+/// One texel of the GPU cache.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct GpuBlockData {
+    pub data: [f32; 4],
+}
+
+impl Default for GpuBlockData {
+    fn default() -> GpuBlockData {
+        GpuBlockData {
+            data: [0.0; 4],
+        }
+    }
+}
+
+/// Types that can be stored in the GPU cache. Implementors must be
+/// `#[repr(C)]`, made only of `f32`s, and a power-of-two number of blocks
+/// in size, so that they can be aliased with the block storage and never
+/// straddle a row of the texture.
+pub trait GpuCacheItem {}
+
+/// The number of blocks that an item of type `T` occupies.
+pub fn blocks_per_item<T: GpuCacheItem>() -> usize {
+    let block_size = mem::size_of::<GpuBlockData>();
+    debug_assert!(mem::size_of::<T>() % block_size == 0);
+    mem::size_of::<T>() / block_size
+}
+
+/// The address of the `index`-th item of an allocation of `T`s that
+/// starts at `address`.
+pub fn item_address<T: GpuCacheItem>(address: GpuStoreAddress, index: usize) -> GpuStoreAddress {
+    address + index * blocks_per_item::<T>()
+}
+
+/// A run of consecutive rows that changed, with their new contents.
+#[derive(Debug)]
+pub struct GpuCacheUpdate {
+    pub first_row: u32,
+    pub blocks: Vec<GpuBlockData>,
+}
+
+/// The changes to the GPU cache since the previous update list was taken.
+#[derive(Debug)]
+pub struct GpuCacheUpdateList {
+    /// The cache was created from scratch, so any contents that the
+    /// renderer holds from a previous cache must be discarded.
+    pub clear: bool,
+    /// The number of rows that the cache currently uses.
+    pub height: u32,
+    pub updates: Vec<GpuCacheUpdate>,
+}
+
+impl GpuCacheUpdateList {
+    pub fn new() -> GpuCacheUpdateList {
+        GpuCacheUpdateList {
+            clear: false,
+            height: 0,
+            updates: Vec::new(),
+        }
+    }
+}
+
+fn same_bits(a: &[GpuBlockData], b: &[GpuBlockData]) -> bool {
+    a.iter().zip(b).all(|(a, b)| {
+        let a: [u32; 4] = unsafe { mem::transmute(a.data) };
+        let b: [u32; 4] = unsafe { mem::transmute(b.data) };
+        a == b
+    })
+}
+
+/// The CPU side of the GPU cache.
+pub struct GpuCache {
+    // Always a whole number of rows.
+    blocks: Vec<GpuBlockData>,
+    // The blocks as the renderer last received them.
+    uploaded_blocks: Vec<GpuBlockData>,
+    next_block: usize,
+    // Rows written to since the last update list was taken, whether or not
+    // that changed them.
+    written_rows: Vec<bool>,
+    is_new: bool,
+}
+
+impl GpuCache {
+    pub fn new() -> GpuCache {
+        GpuCache {
+            blocks: Vec::new(),
+            uploaded_blocks: Vec::new(),
+            next_block: 0,
+            written_rows: Vec::new(),
+            is_new: true,
+        }
+    }
+
+    /// Starts allocating from the start of the cache again, for the
+    /// primitives of a new scene. The addresses handed out for the previous
+    /// scene must not be used after this.
+    pub fn begin_scene(&mut self) {
+        self.next_block = 0;
+    }
+
+    fn allocate_blocks(&mut self, count: usize, alignment: usize) -> GpuStoreAddress {
+        debug_assert!(alignment.is_power_of_two() && alignment <= GPU_CACHE_WIDTH);
+
+        let start = (self.next_block + alignment - 1) & !(alignment - 1);
+        let end = start + count;
+
+        let rows = (end + GPU_CACHE_WIDTH - 1) / GPU_CACHE_WIDTH;
+        if rows > self.written_rows.len() {
+            self.blocks.resize(rows * GPU_CACHE_WIDTH, GpuBlockData::default());
+            self.uploaded_blocks.resize(rows * GPU_CACHE_WIDTH, GpuBlockData::default());
+            self.written_rows.resize(rows, false);
+        }
+
+        // The blocks may still hold data of the previous scene.
+        for block in &mut self.blocks[start..end] {
+            *block = GpuBlockData::default();
+        }
+        self.mark_written(start, count);
+
+        self.next_block = end;
+        GpuStoreAddress(start as i32)
+    }
+
+    fn mark_written(&mut self, start: usize, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let first_row = start / GPU_CACHE_WIDTH;
+        let last_row = (start + count - 1) / GPU_CACHE_WIDTH;
+        for written in &mut self.written_rows[first_row..last_row + 1] {
+            *written = true;
+        }
+    }
+
+    /// Allocates space for `count` items of type `T`, initialized to zero.
+    pub fn alloc<T: GpuCacheItem>(&mut self, count: usize) -> GpuStoreAddress {
+        let blocks = blocks_per_item::<T>();
+        self.allocate_blocks(count * blocks, blocks)
+    }
+
+    pub fn push<T: GpuCacheItem>(&mut self, data: T) -> GpuStoreAddress {
+        let address = self.alloc::<T>(1);
+        *self.get_mut(address) = data;
+        address
+    }
+
+    pub fn get<T: GpuCacheItem>(&self, address: GpuStoreAddress) -> &T {
+        let blocks = blocks_per_item::<T>();
+        let start = address.0 as usize;
+        let data = &self.blocks[start..start + blocks];
+        unsafe {
+            &*(data.as_ptr() as *const T)
+        }
+    }
+
+    pub fn get_mut<T: GpuCacheItem>(&mut self, address: GpuStoreAddress) -> &mut T {
+        &mut self.get_slice_mut(address, 1)[0]
+    }
+
+    pub fn get_slice_mut<T: GpuCacheItem>(&mut self,
+                                          address: GpuStoreAddress,
+                                          count: usize) -> &mut [T] {
+        let blocks = count * blocks_per_item::<T>();
+        let start = address.0 as usize;
+        self.mark_written(start, blocks);
+        let data = &mut self.blocks[start..start + blocks];
+        unsafe {
+            slice::from_raw_parts_mut(data.as_mut_ptr() as *mut T, count)
+        }
+    }
+
+    /// The address that the next allocation would start at, if it needs no
+    /// alignment.
+    pub fn next_address(&self) -> GpuStoreAddress {
+        GpuStoreAddress(self.next_block as i32)
+    }
+
+    /// Feeds the contents of the blocks in `[start, end)` into `state`.
+    pub fn hash_blocks<H: Hasher>(&self, start: GpuStoreAddress, end: GpuStoreAddress, state: &mut H) {
+        for block in &self.blocks[start.0 as usize..end.0 as usize] {
+            let bits: [u32; 4] = unsafe { mem::transmute(block.data) };
+            for value in &bits {
+                state.write_u32(*value);
+            }
+        }
+    }
+
+    /// Collects the rows whose contents changed since the last call.
+    pub fn take_updates(&mut self) -> GpuCacheUpdateList {
+        let mut changed_rows = vec![false; self.written_rows.len()];
+        for (row, written) in self.written_rows.iter_mut().enumerate() {
+            if !*written {
+                continue;
+            }
+            *written = false;
+
+            let blocks = row * GPU_CACHE_WIDTH..(row + 1) * GPU_CACHE_WIDTH;
+            if !same_bits(&self.blocks[blocks.clone()], &self.uploaded_blocks[blocks.clone()]) {
+                self.uploaded_blocks[blocks.clone()].copy_from_slice(&self.blocks[blocks]);
+                changed_rows[row] = true;
+            }
+        }
+
+        let mut updates = Vec::new();
+        let mut row = 0;
+
+        while row < changed_rows.len() {
+            if !changed_rows[row] {
+                row += 1;
+                continue;
+            }
+
+            let first_row = row;
+            while row < changed_rows.len() && changed_rows[row] {
+                row += 1;
+            }
+
+            let blocks = self.blocks[first_row * GPU_CACHE_WIDTH..row * GPU_CACHE_WIDTH].to_vec();
+            updates.push(GpuCacheUpdate {
+                first_row: first_row as u32,
+                blocks: blocks,
+            });
+        }
+
+        let clear = self.is_new;
+        self.is_new = false;
+
+        GpuCacheUpdateList {
+            clear: clear,
+            height: self.written_rows.len() as u32,
+            updates: updates,
+        }
+    }
+}
+
+#[cfg(test)]
+#[repr(C)]
+struct TestBlock {
+    data: [f32; 4],
+}
+
+#[cfg(test)]
+impl GpuCacheItem for TestBlock {}
+
+#[test]
+fn only_changed_rows_are_uploaded() {
+    let mut cache = GpuCache::new();
+    cache.push(TestBlock { data: [1.0; 4] });
+    cache.alloc::<TestBlock>(GPU_CACHE_WIDTH - 1);
+    cache.push(TestBlock { data: [2.0; 4] });
+
+    let update_list = cache.take_updates();
+    assert!(update_list.clear);
+    assert_eq!(update_list.height, 2);
+    assert_eq!(update_list.updates.len(), 1);
+    assert_eq!(update_list.updates[0].first_row, 0);
+    assert_eq!(update_list.updates[0].blocks.len(), 2 * GPU_CACHE_WIDTH);
+
+    // A new scene that only differs in the second row.
+    cache.begin_scene();
+    cache.push(TestBlock { data: [1.0; 4] });
+    cache.alloc::<TestBlock>(GPU_CACHE_WIDTH - 1);
+    cache.push(TestBlock { data: [3.0; 4] });
+
+    let update_list = cache.take_updates();
+    assert!(!update_list.clear);
+    assert_eq!(update_list.updates.len(), 1);
+    assert_eq!(update_list.updates[0].first_row, 1);
+    assert_eq!(update_list.updates[0].blocks.len(), GPU_CACHE_WIDTH);
+
+    // Writing the same data again uploads nothing.
+    *cache.get_mut::<TestBlock>(GpuStoreAddress(0)) = TestBlock { data: [1.0; 4] };
+    assert_eq!(cache.get::<TestBlock>(GpuStoreAddress(0)).data, [1.0; 4]);
+    assert!(cache.take_updates().updates.is_empty());
+}
@@ -37,9 +37,11 @@ pub trait GpuStoreLayout {
 
     fn texel_size() -> usize {
         match Self::image_format() {
+            ImageFormat::A8 => 1,
+            ImageFormat::RGB8 => 3,
             ImageFormat::RGBA8 => 4,
             ImageFormat::RGBAF32 => 16,
-            _ => unreachable!(),
+            ImageFormat::Invalid => unreachable!(),
         }
     }
 
@@ -77,12 +79,6 @@ impl<T: Clone + Default, L: GpuStoreLayout> GpuStore<T, L> {
         }
     }
 
-    pub fn push<E>(&mut self, data: E) -> GpuStoreAddress where T: From<E> {
-        let address = GpuStoreAddress(self.data.len() as i32);
-        self.data.push(T::from(data));
-        address
-    }
-
     // TODO(gw): Change this to do incremental updates, which means
     // there is no need to copy all this data during every scroll!
     pub fn build(&self) -> Vec<T> {
@@ -116,7 +112,7 @@ impl<T: Clone + Default, L: GpuStoreLayout> GpuStore<T, L> {
         GpuStoreAddress(self.data.len() as i32)
     }
 
-    pub fn get(&mut self, address: GpuStoreAddress) -> &T {
+    pub fn get(&self, address: GpuStoreAddress) -> &T {
         &self.data[address.0 as usize]
     }
 
@@ -124,11 +120,9 @@ impl<T: Clone + Default, L: GpuStoreLayout> GpuStore<T, L> {
         &mut self.data[address.0 as usize]
     }
 
-    pub fn get_slice_mut(&mut self,
-                         address: GpuStoreAddress,
-                         count: usize) -> &mut [T] {
-        let offset = address.0 as usize;
-        &mut self.data[offset..offset + count]
+    pub fn get_slice_mut(&mut self, address: GpuStoreAddress, count: usize) -> &mut [T] {
+        let start = address.0 as usize;
+        &mut self.data[start..start + count]
     }
 
     // TODO(gw): Implement incremental updates of
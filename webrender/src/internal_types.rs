@@ -6,6 +6,7 @@ use app_units::Au;
 use device::TextureFilter;
 use euclid::{TypedPoint2D, UnknownUnit};
 use fnv::FnvHasher;
+use gleam::gl;
 use profiler::BackendProfileCounters;
 use std::collections::{HashMap, HashSet};
 use std::f32;
@@ -15,11 +16,11 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use tiling;
 use renderer::BlendMode;
-use webrender_traits::{Epoch, ColorF, PipelineId};
+use webrender_traits::{Epoch, ColorF, FrameToken, PipelineId};
 use webrender_traits::{ImageFormat, NativeFontHandle};
 use webrender_traits::{ExternalImageData, ExternalImageId, ScrollLayerId};
-use webrender_traits::{ImageData};
-use webrender_traits::{DeviceUintRect};
+use webrender_traits::{BackendQueryId, BackendQueryResponse, ImageData};
+use webrender_traits::{DebugCommand, DeviceIntSize, DeviceUintRect, MemoryPressure, WebGLContextId};
 
 // An ID for a texture that is owned by the
 // texture cache module. This can include atlases
@@ -44,10 +45,16 @@ pub enum SourceTexture {
     Invalid,
     TextureCache(CacheTextureId),
     External(ExternalImageData),
-    #[cfg_attr(not(feature = "webgl"), allow(dead_code))]
-    /// This is actually a gl::GLuint, with the shared texture id between the
-    /// main context and the WebGL context.
-    WebGL(u32),
+    /// The texture shared with a WebGL context. The renderer resolves it
+    /// to the context's current texture, without the external image
+    /// handler.
+    WebGL(WebGLContextId),
+}
+
+/// Whether an action of a `MemoryPressurePolicy` that starts at `threshold`
+/// should be taken at `level`.
+pub fn memory_pressure_reached(threshold: Option<MemoryPressure>, level: MemoryPressure) -> bool {
+    threshold.map_or(false, |threshold| level >= threshold)
 }
 
 const COLOR_FLOAT_TO_FIXED: f32 = 255.0;
@@ -71,14 +78,9 @@ pub enum TextureSampler {
     Mask,
     CacheA8,
     CacheRGBA8,
-    Data16,
-    Data32,
-    Data64,
-    Data128,
+    GpuCache,
     Layers,
     RenderTasks,
-    Geometry,
-    ResourceRects,
     Gradients,
     Dither,
 }
@@ -121,7 +123,7 @@ pub enum VertexAttribute {
     Color,
     ColorTexCoord,
     // instance-frequency primitive attributes
-    GlobalPrimId,
+    GeometryAddress,
     PrimitiveAddress,
     TaskIndex,
     ClipTaskIndex,
@@ -274,6 +276,7 @@ pub enum TextureUpdateOp {
       filter: TextureFilter,
       mode: RenderTargetMode,
       data: Option<ImageData>,
+      stride: Option<u32>,
     },
     Update {
         page_pos_x: u32,    // the texture page position which we want to upload
@@ -331,26 +334,62 @@ pub struct RendererFrame {
     pub pipeline_epoch_map: HashMap<PipelineId, Epoch, BuildHasherDefault<FnvHasher>>,
     /// The layers that are currently affected by the over-scrolling animation.
     pub layers_bouncing_back: HashSet<ScrollLayerId, BuildHasherDefault<FnvHasher>>,
+    /// True while a smooth scroll started through the API is in progress.
+    pub scroll_animations_running: bool,
+    /// Pipelines removed since the previous frame was published.
+    pub removed_pipelines: Vec<PipelineId>,
 
     pub frame: Option<tiling::Frame>,
+
+    /// Fences inserted into the WebGL contexts after their last commands,
+    /// which the renderer waits on before sampling the shared textures.
+    pub webgl_fences: Vec<WebGLFence>,
 }
 
+/// A GL sync object created on a WebGL context and handed over to the
+/// renderer thread.
+pub struct WebGLFence(pub gl::GLsync);
+
+// The sync object is only an opaque handle. It's created on the backend
+// thread and waited upon / deleted by the renderer thread, which shares
+// the same GL share group.
+unsafe impl Send for WebGLFence {}
+
 impl RendererFrame {
     pub fn new(pipeline_epoch_map: HashMap<PipelineId, Epoch, BuildHasherDefault<FnvHasher>>,
                layers_bouncing_back: HashSet<ScrollLayerId, BuildHasherDefault<FnvHasher>>,
+               scroll_animations_running: bool,
                frame: Option<tiling::Frame>)
                -> RendererFrame {
         RendererFrame {
             pipeline_epoch_map: pipeline_epoch_map,
             layers_bouncing_back: layers_bouncing_back,
+            scroll_animations_running: scroll_animations_running,
+            removed_pipelines: Vec::new(),
             frame: frame,
+            webgl_fences: Vec::new(),
         }
     }
 }
 
 pub enum ResultMsg {
     RefreshShader(PathBuf),
-    NewFrame(RendererFrame, TextureUpdateList, ExternalImageUpdateList, BackendProfileCounters),
+    /// The shared texture of a WebGL context was created or changed. This is
+    /// the gl::GLuint of the texture and its size.
+    UpdateWebGLTexture(WebGLContextId, u32, DeviceIntSize),
+    /// A WebGL context was deleted, along with its shared texture.
+    DeleteWebGLTexture(WebGLContextId),
+    SetMemoryPressure(MemoryPressure),
+    /// A debugging command for the renderer from `RenderApi::send_debug_cmd`.
+    DebugCommand(DebugCommand),
+    /// The answer to a query sent with `Renderer::query_backend`.
+    BackendQueryResponse(BackendQueryId, BackendQueryResponse),
+    /// A built frame, with the token it was requested with, if any.
+    NewFrame(RendererFrame,
+             TextureUpdateList,
+             ExternalImageUpdateList,
+             BackendProfileCounters,
+             Option<FrameToken>),
 }
 
 #[repr(u32)]
@@ -0,0 +1,107 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Bookkeeping of the API resources an embedder has created and not yet
+//! deleted. In debug builds, the backend records where each image, font
+//! and pipeline was created, warns when the number of live resources of a
+//! kind grows suspiciously large, and lists whatever is still alive at
+//! shutdown. Without it, leaked keys only show up as slow memory growth.
+
+use backtrace::Backtrace;
+use fnv::FnvHasher;
+use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
+use webrender_traits::{FontKey, ImageKey, PipelineId};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TrackedResource {
+    Image(ImageKey),
+    /// An image key registered for an external image.
+    ExternalImage(ImageKey),
+    Font(FontKey),
+    Pipeline(PipelineId),
+}
+
+impl TrackedResource {
+    fn kind_index(&self) -> usize {
+        match *self {
+            TrackedResource::Image(..) => 0,
+            TrackedResource::ExternalImage(..) => 1,
+            TrackedResource::Font(..) => 2,
+            TrackedResource::Pipeline(..) => 3,
+        }
+    }
+}
+
+const KIND_NAMES: [&'static str; 4] = ["images", "external images", "fonts", "pipelines"];
+
+// The number of live resources of each kind past which a warning is logged.
+// The threshold doubles every time it is reached, so a steady leak is
+// reported a handful of times rather than once per resource.
+const INITIAL_THRESHOLDS: [usize; 4] = [8192, 1024, 512, 256];
+
+pub struct LeakDetector {
+    enabled: bool,
+    live: HashMap<TrackedResource, Backtrace, BuildHasherDefault<FnvHasher>>,
+    counts: [usize; 4],
+    thresholds: [usize; 4],
+}
+
+impl LeakDetector {
+    /// Creates a detector that only tracks resources in debug builds, as
+    /// capturing a backtrace for every resource is too slow otherwise.
+    pub fn new() -> LeakDetector {
+        LeakDetector {
+            enabled: cfg!(debug_assertions),
+            live: HashMap::with_hasher(Default::default()),
+            counts: [0; 4],
+            thresholds: INITIAL_THRESHOLDS,
+        }
+    }
+
+    pub fn add(&mut self, resource: TrackedResource) {
+        if !self.enabled || self.live.contains_key(&resource) {
+            return;
+        }
+
+        // Symbols are only resolved for the resources that get reported.
+        self.live.insert(resource, Backtrace::new_unresolved());
+
+        let index = resource.kind_index();
+        self.counts[index] += 1;
+        if self.counts[index] >= self.thresholds[index] {
+            warn!("{} live {}, which may be a leak. Last created at:\n{:?}",
+                  self.counts[index],
+                  KIND_NAMES[index],
+                  resolved(&self.live[&resource]));
+            self.thresholds[index] *= 2;
+        }
+    }
+
+    pub fn remove(&mut self, resource: TrackedResource) {
+        if self.live.remove(&resource).is_some() {
+            self.counts[resource.kind_index()] -= 1;
+        }
+    }
+
+    /// Logs every resource that is still alive, along with where it was
+    /// created.
+    pub fn report_live_resources(&mut self) {
+        if self.live.is_empty() {
+            return;
+        }
+
+        warn!("{} resources still alive at shutdown", self.live.len());
+        for (resource, backtrace) in self.live.drain() {
+            warn!("{:?} created at:\n{:?}", resource, resolved(&backtrace));
+        }
+        self.counts = [0; 4];
+    }
+}
+
+fn resolved(backtrace: &Backtrace) -> Backtrace {
+    let mut backtrace = backtrace.clone();
+    backtrace.resolve();
+    backtrace
+}
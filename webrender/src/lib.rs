@@ -52,18 +52,25 @@ mod debug_colors;
 mod debug_font_data;
 mod debug_render;
 mod device;
+mod driver_workarounds;
 mod frame;
 mod frame_builder;
 mod freelist;
 mod geometry;
+mod gpu_cache;
 mod gpu_store;
 mod internal_types;
+mod leak_detector;
 mod mask_cache;
+mod pipeline_stats;
 mod prim_store;
 mod profiler;
 mod record;
+#[cfg(feature = "reftest")]
+mod reftest;
 mod render_backend;
 mod render_task;
+mod render_task_cache;
 mod resource_cache;
 mod scene;
 mod spring;
@@ -83,6 +90,8 @@ mod shader_source {
 }
 
 pub use record::{ApiRecordingReceiver, BinaryRecorder, WEBRENDER_RECORDING_HEADER};
+#[cfg(feature = "reftest")]
+pub use reftest::{ReftestFuzz, ReftestHarness, ReftestImage, ReftestResult};
 
 mod platform {
     #[cfg(target_os="macos")]
@@ -120,6 +129,7 @@ extern crate freetype;
 extern crate dwrote;
 
 extern crate app_units;
+extern crate backtrace;
 extern crate bincode;
 extern crate euclid;
 extern crate fnv;
@@ -136,5 +146,17 @@ extern crate threadpool;
 #[cfg(any(target_os="macos", target_os="windows"))]
 extern crate gamma_lut;
 
+pub use device::{Device, FrameId, ProgramId, ShaderError, TextureFilter, TextureId};
+pub use device::{GpuFence, PendingReadback, TextureTarget, VAOId, VertexFormat};
+pub use driver_workarounds::{BufferUpdateStrategy, DriverInfo, DriverWorkarounds, Workaround};
+pub use internal_types::{RenderTargetMode, TextureSampler};
+pub use pipeline_stats::{BatchStatistics, PipelineStatistics, PrimitiveCounts, StatisticsFormat};
 pub use renderer::{ExternalImage, ExternalImageSource, ExternalImageHandler};
-pub use renderer::{Renderer, RendererOptions};
+pub use renderer::{NativeCompositor, NativeSurfaceId, OverlayCandidate, RenderView};
+pub use renderer::{PostProcessHandler, ReadbackHandler, ReadbackId};
+pub use renderer::{FrameCompleteHandler, RenderedFrameId};
+pub use renderer::{DebugFlags, RENDER_TARGET_DBG, RENDER_TARGET_DUMP};
+pub use renderer::{RenderResult, Renderer, RendererOptions, RendererOptionsBuilder, RendererOptionsError};
+pub use renderer::{EdgeAaMode, MemoryPressurePolicy, RendererWarning, ScrollbarTheme, ZAllocation};
+pub use renderer::{HdrOutput, HdrOutputFormat, OutputColorTransform, SurfaceOrigin, SurfaceTransform};
+pub use texture_cache::{TextureAllocatorConfig, TextureAllocatorKind, TextureAllocatorStats};
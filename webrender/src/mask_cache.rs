@@ -2,10 +2,10 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use gpu_cache::{GpuCache, item_address};
 use gpu_store::GpuStoreAddress;
 use prim_store::{ClipData, GpuBlock32, PrimitiveStore};
 use prim_store::{CLIP_DATA_GPU_SIZE, MASK_DATA_GPU_SIZE};
-use renderer::VertexDataStore;
 use util::{MatrixHelpers, TransformedRect};
 use webrender_traits::{AuxiliaryLists, BorderRadius, ClipRegion, ComplexClipRegion, ImageMask};
 use webrender_traits::{DeviceIntRect, LayerToWorldTransform};
@@ -111,7 +111,7 @@ impl MaskCacheInfo {
     /// Create a new mask cache info. It allocates the GPU store data but leaves
     /// it unitialized for the following `update()` call to deal with.
     pub fn new(clips: &[ClipSource],
-               clip_store: &mut VertexDataStore<GpuBlock32>)
+               gpu_cache: &mut GpuCache)
                -> Option<MaskCacheInfo> {
         if clips.is_empty() {
             return None;
@@ -130,7 +130,7 @@ impl MaskCacheInfo {
                 &ClipSource::Region(ref region, region_mode) => {
                     if let Some(info) = region.image_mask {
                         debug_assert!(image.is_none());     // TODO(gw): Support >1 image mask!
-                        image = Some((info, clip_store.alloc(MASK_DATA_GPU_SIZE)));
+                        image = Some((info, gpu_cache.alloc::<GpuBlock32>(MASK_DATA_GPU_SIZE)));
                     }
 
                     clip_count += region.complex.length;
@@ -143,7 +143,7 @@ impl MaskCacheInfo {
 
         let clip_range = ClipAddressRange {
             start: if clip_count > 0 {
-                clip_store.alloc(CLIP_DATA_GPU_SIZE * clip_count)
+                gpu_cache.alloc::<GpuBlock32>(CLIP_DATA_GPU_SIZE * clip_count)
             } else {
                 GpuStoreAddress(0)
             },
@@ -162,7 +162,7 @@ impl MaskCacheInfo {
     pub fn update(&mut self,
                   sources: &[ClipSource],
                   transform: &LayerToWorldTransform,
-                  clip_store: &mut VertexDataStore<GpuBlock32>,
+                  gpu_cache: &mut GpuCache,
                   device_pixel_ratio: f32,
                   aux_lists: &AuxiliaryLists) {
         let is_aligned = transform.can_losslessly_transform_and_perspective_project_a_2d_rect();
@@ -187,10 +187,11 @@ impl MaskCacheInfo {
                             has_clip_out = true;
                         }
                         debug_assert!(self.effective_clip_count < self.clip_range.item_count);
-                        let address = self.clip_range.start + self.effective_clip_count * CLIP_DATA_GPU_SIZE;
+                        let address = item_address::<GpuBlock32>(self.clip_range.start,
+                                                                 self.effective_clip_count * CLIP_DATA_GPU_SIZE);
                         self.effective_clip_count += 1;
 
-                        let slice = clip_store.get_slice_mut(address, CLIP_DATA_GPU_SIZE);
+                        let slice = gpu_cache.get_slice_mut::<GpuBlock32>(address, CLIP_DATA_GPU_SIZE);
                         let data = ClipData::uniform(rect, radius, mode);
                         PrimitiveStore::populate_clip_data(slice, data);
                         local_rect = local_rect.and_then(|r| r.intersection(&rect));
@@ -212,18 +213,21 @@ impl MaskCacheInfo {
                         if !self.is_aligned && region_mode == RegionMode::IncludeRect {
                             // we have an extra clip rect coming from the transformed layer
                             debug_assert!(self.effective_clip_count < self.clip_range.item_count);
-                            let address = self.clip_range.start + self.effective_clip_count * CLIP_DATA_GPU_SIZE;
+                            let address = item_address::<GpuBlock32>(self.clip_range.start,
+                                                                     self.effective_clip_count * CLIP_DATA_GPU_SIZE);
                             self.effective_clip_count += 1;
 
-                            let slice = clip_store.get_slice_mut(address, CLIP_DATA_GPU_SIZE);
+                            let slice = gpu_cache.get_slice_mut::<GpuBlock32>(address, CLIP_DATA_GPU_SIZE);
                             PrimitiveStore::populate_clip_data(slice, ClipData::uniform(region.main, 0.0, ClipMode::Clip));
                         }
 
                         debug_assert!(self.effective_clip_count + clips.len() <= self.clip_range.item_count);
-                        let address = self.clip_range.start + self.effective_clip_count * CLIP_DATA_GPU_SIZE;
+                        let address = item_address::<GpuBlock32>(self.clip_range.start,
+                                                                 self.effective_clip_count * CLIP_DATA_GPU_SIZE);
                         self.effective_clip_count += clips.len();
 
-                        let slice = clip_store.get_slice_mut(address, CLIP_DATA_GPU_SIZE * clips.len());
+                        let slice = gpu_cache.get_slice_mut::<GpuBlock32>(address,
+                                                                           CLIP_DATA_GPU_SIZE * clips.len());
                         for (clip, chunk) in clips.iter().zip(slice.chunks_mut(CLIP_DATA_GPU_SIZE)) {
                             let data = ClipData::from_clip_region(clip);
                             PrimitiveStore::populate_clip_data(chunk, data);
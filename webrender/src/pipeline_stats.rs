@@ -0,0 +1,213 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Structural statistics about the frames that the frame builder produces,
+//! such as how many primitives of each kind are drawn and why they end up
+//! in separate batches. These explain why a page renders slowly, where
+//! timings only tell that it does.
+
+use prim_store::PrimitiveKind;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// The visible primitives of a frame, by kind.
+#[derive(Clone, Debug, Default)]
+pub struct PrimitiveCounts {
+    pub rectangles: usize,
+    pub text_runs: usize,
+    pub images: usize,
+    pub yuv_images: usize,
+    pub borders: usize,
+    pub aligned_gradients: usize,
+    pub angle_gradients: usize,
+    pub radial_gradients: usize,
+    pub box_shadows: usize,
+}
+
+impl PrimitiveCounts {
+    pub fn add(&mut self, kind: PrimitiveKind) {
+        let count = match kind {
+            PrimitiveKind::Rectangle => &mut self.rectangles,
+            PrimitiveKind::TextRun => &mut self.text_runs,
+            PrimitiveKind::Image => &mut self.images,
+            PrimitiveKind::YuvImage => &mut self.yuv_images,
+            PrimitiveKind::Border => &mut self.borders,
+            PrimitiveKind::AlignedGradient => &mut self.aligned_gradients,
+            PrimitiveKind::AngleGradient => &mut self.angle_gradients,
+            PrimitiveKind::RadialGradient => &mut self.radial_gradients,
+            PrimitiveKind::BoxShadow => &mut self.box_shadows,
+        };
+        *count += 1;
+    }
+}
+
+/// Why an item didn't go into an existing batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatchBreakReason {
+    /// Mix-blend composites always get a batch of their own.
+    Composite,
+    /// The item overlaps an item of an incompatible batch that is drawn
+    /// before it.
+    Overlap,
+    /// There is a compatible batch, but further back than the batcher looks.
+    LookbackLimit,
+    /// The item needs a different shader than the previous batch.
+    Kind,
+    /// The item needs a different clipping or transform variant of the
+    /// shader than the previous batch.
+    Flags,
+    /// The item needs a different blend mode than the previous batch.
+    BlendMode,
+    /// The item samples from different textures than the previous batch.
+    Textures,
+}
+
+/// The batches of a frame, and why items were split into them.
+#[derive(Clone, Debug, Default)]
+pub struct BatchStatistics {
+    pub alpha_batches: usize,
+    pub opaque_batches: usize,
+    /// Opaque images that are copied to the framebuffer instead of drawn.
+    pub image_blits: usize,
+    pub composite_breaks: usize,
+    pub overlap_breaks: usize,
+    pub lookback_breaks: usize,
+    pub kind_breaks: usize,
+    pub flags_breaks: usize,
+    pub blend_mode_breaks: usize,
+    pub texture_breaks: usize,
+}
+
+impl BatchStatistics {
+    pub fn add_break(&mut self, reason: BatchBreakReason) {
+        let count = match reason {
+            BatchBreakReason::Composite => &mut self.composite_breaks,
+            BatchBreakReason::Overlap => &mut self.overlap_breaks,
+            BatchBreakReason::LookbackLimit => &mut self.lookback_breaks,
+            BatchBreakReason::Kind => &mut self.kind_breaks,
+            BatchBreakReason::Flags => &mut self.flags_breaks,
+            BatchBreakReason::BlendMode => &mut self.blend_mode_breaks,
+            BatchBreakReason::Textures => &mut self.texture_breaks,
+        };
+        *count += 1;
+    }
+
+    pub fn accumulate(&mut self, other: &BatchStatistics) {
+        self.alpha_batches += other.alpha_batches;
+        self.opaque_batches += other.opaque_batches;
+        self.image_blits += other.image_blits;
+        self.composite_breaks += other.composite_breaks;
+        self.overlap_breaks += other.overlap_breaks;
+        self.lookback_breaks += other.lookback_breaks;
+        self.kind_breaks += other.kind_breaks;
+        self.flags_breaks += other.flags_breaks;
+        self.blend_mode_breaks += other.blend_mode_breaks;
+        self.texture_breaks += other.texture_breaks;
+    }
+}
+
+/// Statistics about the structure of a built frame.
+#[derive(Clone, Debug, Default)]
+pub struct PipelineStatistics {
+    pub total_primitives: usize,
+    pub visible_primitives: usize,
+    pub primitives: PrimitiveCounts,
+    /// Visible primitives that are drawn through a clip mask.
+    pub clip_masks: usize,
+    pub passes: usize,
+    pub color_targets: usize,
+    pub alpha_targets: usize,
+    pub batches: BatchStatistics,
+}
+
+impl PipelineStatistics {
+    /// The statistics as (name, value) pairs, in the order of the CSV columns.
+    pub fn fields(&self) -> Vec<(&'static str, usize)> {
+        vec![
+            ("total_primitives", self.total_primitives),
+            ("visible_primitives", self.visible_primitives),
+            ("rectangles", self.primitives.rectangles),
+            ("text_runs", self.primitives.text_runs),
+            ("images", self.primitives.images),
+            ("yuv_images", self.primitives.yuv_images),
+            ("borders", self.primitives.borders),
+            ("aligned_gradients", self.primitives.aligned_gradients),
+            ("angle_gradients", self.primitives.angle_gradients),
+            ("radial_gradients", self.primitives.radial_gradients),
+            ("box_shadows", self.primitives.box_shadows),
+            ("clip_masks", self.clip_masks),
+            ("passes", self.passes),
+            ("color_targets", self.color_targets),
+            ("alpha_targets", self.alpha_targets),
+            ("alpha_batches", self.batches.alpha_batches),
+            ("opaque_batches", self.batches.opaque_batches),
+            ("image_blits", self.batches.image_blits),
+            ("composite_breaks", self.batches.composite_breaks),
+            ("overlap_breaks", self.batches.overlap_breaks),
+            ("lookback_breaks", self.batches.lookback_breaks),
+            ("kind_breaks", self.batches.kind_breaks),
+            ("flags_breaks", self.batches.flags_breaks),
+            ("blend_mode_breaks", self.batches.blend_mode_breaks),
+            ("texture_breaks", self.batches.texture_breaks),
+        ]
+    }
+}
+
+/// The file format of a statistics dump.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatisticsFormat {
+    /// A header line, then one line of comma-separated values per frame.
+    Csv,
+    /// One JSON object per line and frame.
+    Json,
+}
+
+/// Writes the statistics of every rendered frame to a file.
+pub struct StatisticsDump {
+    file: File,
+    format: StatisticsFormat,
+    frame_count: usize,
+}
+
+impl StatisticsDump {
+    pub fn new(path: &Path, format: StatisticsFormat) -> io::Result<StatisticsDump> {
+        let mut file = try!(File::create(path));
+
+        if format == StatisticsFormat::Csv {
+            let names: Vec<&str> = PipelineStatistics::default().fields()
+                                                                .iter()
+                                                                .map(|&(name, _)| name)
+                                                                .collect();
+            try!(writeln!(file, "frame,{}", names.join(",")));
+        }
+
+        Ok(StatisticsDump {
+            file: file,
+            format: format,
+            frame_count: 0,
+        })
+    }
+
+    pub fn write(&mut self, statistics: &PipelineStatistics) -> io::Result<()> {
+        let frame_index = self.frame_count;
+        self.frame_count += 1;
+
+        let fields = statistics.fields();
+        match self.format {
+            StatisticsFormat::Csv => {
+                let values: Vec<String> = fields.iter()
+                                                .map(|&(_, value)| value.to_string())
+                                                .collect();
+                writeln!(self.file, "{},{}", frame_index, values.join(","))
+            }
+            StatisticsFormat::Json => {
+                let values: Vec<String> = fields.iter()
+                                                .map(|&(name, value)| format!("\"{}\":{}", name, value))
+                                                .collect();
+                writeln!(self.file, "{{\"frame\":{},{}}}", frame_index, values.join(","))
+            }
+        }
+    }
+}
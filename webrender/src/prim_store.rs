@@ -4,16 +4,19 @@
 
 use app_units::Au;
 use euclid::{Point2D, Size2D};
+use gpu_cache::{GpuCache, GpuCacheItem, item_address};
 use gpu_store::GpuStoreAddress;
 use internal_types::{SourceTexture, PackedTexel};
 use mask_cache::{ClipMode, ClipSource, MaskCacheInfo};
-use renderer::{VertexDataStore, GradientDataStore};
+use renderer::GradientDataStore;
 use render_task::{RenderTask, RenderTaskLocation};
 use resource_cache::{CacheItem, ImageProperties, ResourceCache};
+use std::hash::{Hash, Hasher};
 use std::mem;
 use std::usize;
 use util::TransformedRect;
-use webrender_traits::{AuxiliaryLists, ColorF, ImageKey, ImageRendering, YuvColorSpace};
+use webrender_traits::{AuxiliaryLists, ColorF, ColorSpace, ImageFormat, ImageKey, ImageRendering};
+use webrender_traits::YuvColorSpace;
 use webrender_traits::{ClipRegion, ComplexClipRegion, ItemRange, GlyphKey};
 use webrender_traits::{FontKey, FontRenderMode, WebGLContextId};
 use webrender_traits::{device_length, DeviceIntRect, DeviceIntSize};
@@ -22,6 +25,7 @@ use webrender_traits::{LayerRect, LayerSize, LayerPoint};
 use webrender_traits::{LayerToWorldTransform, GlyphInstance, GlyphOptions};
 use webrender_traits::{ExtendMode, GradientStop, TileOffset};
 
+/// The sizes of clip and image mask data, in GpuBlock32 units.
 pub const CLIP_DATA_GPU_SIZE: usize = 5;
 pub const MASK_DATA_GPU_SIZE: usize = 1;
 
@@ -31,6 +35,7 @@ pub const MASK_DATA_GPU_SIZE: usize = 1;
 /// the UVs in the vertex shader means nothing needs to be
 /// updated on the CPU when the texture size changes.
 #[derive(Copy, Clone, Debug)]
+#[repr(C)]
 pub struct TexelRect {
     pub uv0: DevicePoint,
     pub uv1: DevicePoint,
@@ -45,6 +50,8 @@ impl Default for TexelRect {
     }
 }
 
+impl GpuCacheItem for TexelRect {}
+
 impl TexelRect {
     pub fn new(u0: u32, v0: u32, u1: u32, v1: u32) -> TexelRect {
         TexelRect {
@@ -65,6 +72,7 @@ impl TexelRect {
 pub struct DeferredResolve {
     pub resource_address: GpuStoreAddress,
     pub image_properties: ImageProperties,
+    pub image_rendering: ImageRendering,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
@@ -94,11 +102,14 @@ pub enum PrimitiveKind {
 
 /// Geometry description for simple rectangular primitives, uploaded to the GPU.
 #[derive(Debug, Clone)]
+#[repr(C)]
 pub struct PrimitiveGeometry {
     pub local_rect: LayerRect,
     pub local_clip_rect: LayerRect,
 }
 
+impl GpuCacheItem for PrimitiveGeometry {}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum PrimitiveCacheKey {
     BoxShadow(BoxShadowPrimitiveCacheKey),
@@ -116,6 +127,7 @@ pub struct PrimitiveMetadata {
     pub gpu_prim_index: GpuStoreAddress,
     pub gpu_data_address: GpuStoreAddress,
     pub gpu_data_count: i32,
+    pub gpu_geometry_address: GpuStoreAddress,
     // An optional render task that is a dependency of
     // drawing this primitive. For instance, box shadows
     // use this to draw a portion of the box shadow to
@@ -146,6 +158,9 @@ pub struct ImagePrimitiveCpu {
     pub color_texture_id: SourceTexture,
     pub resource_address: GpuStoreAddress,
     pub sub_rect: Option<TexelRect>,
+    pub color: ColorF,
+    // Set for A8 images, which are drawn in `color` with the image as coverage.
+    pub is_alpha_mask: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -155,6 +170,8 @@ pub struct ImagePrimitiveGpu {
     pub tile_spacing: LayerSize,
 }
 
+impl GpuCacheItem for ImagePrimitiveGpu {}
+
 #[derive(Debug)]
 pub struct YuvImagePrimitiveCpu {
     pub yuv_key: [ImageKey; 3],
@@ -239,6 +256,8 @@ pub struct GradientPrimitiveCpu {
     pub stops_range: ItemRange,
     pub extend_mode: ExtendMode,
     pub reverse_stops: bool,
+    // The distance between the start and end points, in layout pixels.
+    pub length: f32,
     pub cache_dirty: bool,
 }
 
@@ -257,12 +276,22 @@ pub struct RadialGradientPrimitiveGpu {
 pub struct RadialGradientPrimitiveCpu {
     pub stops_range: ItemRange,
     pub extend_mode: ExtendMode,
+    // The distance the gradient spans from the start circle to the end
+    // circle, in layout pixels.
+    pub length: f32,
     pub cache_dirty: bool,
 }
 
-// The number of entries in a gradient data table.
+// The number of entries in a gradient data table. Long gradients are given
+// several consecutive tables, which the shaders read as a single one.
 pub const GRADIENT_DATA_RESOLUTION: usize = 128;
 
+// The number of device pixels covered by an entry of a gradient with hard
+// stops, and of one without. Stops are placed on entry boundaries, so this
+// is how far they can be moved.
+const HARD_STOP_PIXELS_PER_ENTRY: f32 = 1.0;
+const SOFT_STOP_PIXELS_PER_ENTRY: f32 = 4.0;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 // An entry in a gradient data table representing a segment of the gradient color space.
@@ -302,9 +331,29 @@ impl Clone for GradientData {
     }
 }
 
+// Packs a color that was interpolated in sRGB in the color space frames are drawn in.
+fn pack_gradient_color(color: &ColorF, color_space: ColorSpace) -> (PackedTexel, PackedTexel) {
+    let color = if color_space == ColorSpace::Srgb {
+        *color
+    } else {
+        let color = color.convert_color_space(ColorSpace::Srgb, color_space);
+        ColorF::new(color.r.max(0.0).min(1.0),
+                    color.g.max(0.0).min(1.0),
+                    color.b.max(0.0).min(1.0),
+                    color.a)
+    };
+    (PackedTexel::high_bytes(&color), PackedTexel::low_bytes(&color))
+}
+
 impl GradientData {
     // Generate a color ramp between the start and end indexes from a start color to an end color.
-    fn fill_colors(&mut self, start_idx: usize, end_idx: usize, start_color: &ColorF, end_color: &ColorF) -> usize {
+    // The indexes run through all of the tables.
+    fn fill_colors(tables: &mut [GradientData],
+                   start_idx: usize,
+                   end_idx: usize,
+                   start_color: &ColorF,
+                   end_color: &ColorF,
+                   color_space: ColorSpace) -> usize {
         if start_idx >= end_idx {
             return start_idx;
         }
@@ -317,13 +366,13 @@ impl GradientData {
         let step_a = (end_color.a - start_color.a) * inv_steps;
 
         let mut cur_color = *start_color;
-        let mut cur_color_high = PackedTexel::high_bytes(&cur_color);
-        let mut cur_color_low = PackedTexel::low_bytes(&cur_color);
+        let (mut cur_color_high, mut cur_color_low) = pack_gradient_color(&cur_color, color_space);
 
         // Walk the ramp writing start and end colors for each entry.
         for index in start_idx..end_idx {
-            let high_byte_entry = &mut self.colors_high[index];
-            let low_byte_entry = &mut self.colors_low[index];
+            let table = &mut tables[index / GRADIENT_DATA_RESOLUTION];
+            let high_byte_entry = &mut table.colors_high[index % GRADIENT_DATA_RESOLUTION];
+            let low_byte_entry = &mut table.colors_low[index % GRADIENT_DATA_RESOLUTION];
 
             high_byte_entry.start_color = cur_color_high;
             low_byte_entry.start_color = cur_color_low;
@@ -331,8 +380,9 @@ impl GradientData {
             cur_color.g += step_g;
             cur_color.b += step_b;
             cur_color.a += step_a;
-            cur_color_high = PackedTexel::high_bytes(&cur_color);
-            cur_color_low = PackedTexel::low_bytes(&cur_color);
+            let (color_high, color_low) = pack_gradient_color(&cur_color, color_space);
+            cur_color_high = color_high;
+            cur_color_low = color_low;
             high_byte_entry.end_color = cur_color_high;
             low_byte_entry.end_color = cur_color_low;
         }
@@ -342,12 +392,33 @@ impl GradientData {
 
     // Compute an entry index based on a gradient stop offset.
     #[inline]
-    fn get_index(offset: f32) -> usize {
-        (offset.max(0.0).min(1.0) * GRADIENT_DATA_RESOLUTION as f32).round() as usize
+    fn get_index(offset: f32, entry_count: usize) -> usize {
+        (offset.max(0.0).min(1.0) * entry_count as f32).round() as usize
+    }
+
+    // The number of tables that a gradient spanning `device_length` pixels is given,
+    // up to `max_tables`. Gradients with stops less than a pixel apart get the most
+    // entries, as the edges of hard stops are the first to show where they moved.
+    fn table_count(src_stops: &[GradientStop], device_length: f32, max_tables: usize) -> usize {
+        let has_hard_stops = src_stops.windows(2).any(|stops| {
+            (stops[1].offset - stops[0].offset) * device_length < 1.0
+        });
+        let pixels_per_entry = if has_hard_stops {
+            HARD_STOP_PIXELS_PER_ENTRY
+        } else {
+            SOFT_STOP_PIXELS_PER_ENTRY
+        };
+        let table_count = device_length / pixels_per_entry / GRADIENT_DATA_RESOLUTION as f32;
+        table_count.ceil().max(1.0).min(max_tables as f32) as usize
     }
 
     // Build the gradient data from the supplied stops, reversing them if necessary.
-    fn build(&mut self, src_stops: &[GradientStop], reverse_stops: bool) {
+    // The colors are interpolated in sRGB, and stored in `color_space`.
+    fn build(tables: &mut [GradientData],
+             src_stops: &[GradientStop],
+             reverse_stops: bool,
+             color_space: ColorSpace) {
+        let entry_count = tables.len() * GRADIENT_DATA_RESOLUTION;
         let mut cur_idx = 0usize;
         let mut cur_color = if let Some(src) = src_stops.first() {
             src.color
@@ -359,20 +430,20 @@ impl GradientData {
             // If the gradient is reversed, then ensure the stops are processed in reverse order
             // and that the offsets are inverted.
             for src in src_stops.iter().rev() {
-                cur_idx = self.fill_colors(cur_idx, Self::get_index(1.0 - src.offset),
-                                           &cur_color, &src.color);
+                cur_idx = Self::fill_colors(tables, cur_idx, Self::get_index(1.0 - src.offset, entry_count),
+                                            &cur_color, &src.color, color_space);
                 cur_color = src.color;
             }
         } else {
             for src in src_stops {
-                cur_idx = self.fill_colors(cur_idx, Self::get_index(src.offset),
-                                           &cur_color, &src.color);
+                cur_idx = Self::fill_colors(tables, cur_idx, Self::get_index(src.offset, entry_count),
+                                            &cur_color, &src.color, color_space);
                 cur_color = src.color;
             }
         }
 
         // Fill out any remaining entries in the gradient.
-        self.fill_colors(cur_idx, GRADIENT_DATA_RESOLUTION, &cur_color, &cur_color);
+        Self::fill_colors(tables, cur_idx, entry_count, &cur_color, &cur_color, color_space);
     }
 }
 
@@ -550,6 +621,48 @@ pub enum PrimitiveContainer {
     BoxShadow(BoxShadowPrimitiveGpu, Vec<LayerRect>),
 }
 
+impl PrimitiveContainer {
+    /// Converts the colors of the primitive from the sRGB of display lists
+    /// to `color_space`. Gradient stops are converted when the gradient is
+    /// prepared for rendering.
+    fn convert_colors(&mut self, color_space: ColorSpace) {
+        if color_space == ColorSpace::Srgb {
+            return;
+        }
+
+        let convert = |color: ColorF| color.convert_color_space(ColorSpace::Srgb, color_space);
+        match *self {
+            PrimitiveContainer::Rectangle(ref mut rect) => {
+                rect.color = convert(rect.color);
+            }
+            PrimitiveContainer::TextRun(ref mut text_cpu, ref mut text_gpu) => {
+                // Glyphs are cached by their color in 8 bits per channel.
+                let color = convert(text_cpu.color);
+                text_cpu.color = ColorF::new(color.r.max(0.0).min(1.0),
+                                             color.g.max(0.0).min(1.0),
+                                             color.b.max(0.0).min(1.0),
+                                             color.a);
+                text_gpu.color = convert(text_gpu.color);
+            }
+            PrimitiveContainer::Image(ref mut image_cpu, _) => {
+                image_cpu.color = convert(image_cpu.color);
+            }
+            PrimitiveContainer::Border(_, ref mut border_gpu) => {
+                for color in &mut border_gpu.colors {
+                    *color = convert(*color);
+                }
+            }
+            PrimitiveContainer::BoxShadow(ref mut box_shadow_gpu, _) => {
+                box_shadow_gpu.color = convert(box_shadow_gpu.color);
+            }
+            PrimitiveContainer::YuvImage(..) |
+            PrimitiveContainer::AlignedGradient(..) |
+            PrimitiveContainer::AngleGradient(..) |
+            PrimitiveContainer::RadialGradient(..) => {}
+        }
+    }
+}
+
 pub struct PrimitiveStore {
     // CPU side information only
     pub cpu_bounding_rects: Vec<Option<DeviceIntRect>>,
@@ -561,23 +674,20 @@ pub struct PrimitiveStore {
     pub cpu_metadata: Vec<PrimitiveMetadata>,
     pub cpu_borders: Vec<BorderPrimitiveCpu>,
 
-    // Gets uploaded directly to GPU via vertex texture
-    pub gpu_geometry: VertexDataStore<PrimitiveGeometry>,
-    pub gpu_data16: VertexDataStore<GpuBlock16>,
-    pub gpu_data32: VertexDataStore<GpuBlock32>,
-    pub gpu_data64: VertexDataStore<GpuBlock64>,
-    pub gpu_data128: VertexDataStore<GpuBlock128>,
+    // The primitive data, geometry, clip data and resolved resource
+    // rects are kept in the GpuCache of the render backend.
     pub gpu_gradient_data: GradientDataStore,
 
-    // Resolved resource rects.
-    pub gpu_resource_rects: VertexDataStore<TexelRect>,
-
     // General
     prims_to_resolve: Vec<PrimitiveIndex>,
+    // The color space frames are drawn in.
+    color_space: ColorSpace,
+    // The most gradient data tables a gradient can be given.
+    max_gradient_tables: usize,
 }
 
 impl PrimitiveStore {
-    pub fn new() -> PrimitiveStore {
+    pub fn new(color_space: ColorSpace, max_gradient_tables: usize) -> PrimitiveStore {
         PrimitiveStore {
             cpu_metadata: Vec::new(),
             cpu_bounding_rects: Vec::new(),
@@ -587,14 +697,10 @@ impl PrimitiveStore {
             cpu_gradients: Vec::new(),
             cpu_radial_gradients: Vec::new(),
             cpu_borders: Vec::new(),
-            gpu_geometry: VertexDataStore::new(),
-            gpu_data16: VertexDataStore::new(),
-            gpu_data32: VertexDataStore::new(),
-            gpu_data64: VertexDataStore::new(),
-            gpu_data128: VertexDataStore::new(),
             gpu_gradient_data: GradientDataStore::new(),
-            gpu_resource_rects: VertexDataStore::new(),
             prims_to_resolve: Vec::new(),
+            color_space: color_space,
+            max_gradient_tables: max_gradient_tables,
         }
     }
 
@@ -610,15 +716,17 @@ impl PrimitiveStore {
                          geometry: PrimitiveGeometry,
                          clips: Vec<ClipSource>,
                          clip_info: Option<MaskCacheInfo>,
-                         container: PrimitiveContainer) -> PrimitiveIndex {
+                         mut container: PrimitiveContainer,
+                         gpu_cache: &mut GpuCache) -> PrimitiveIndex {
+        container.convert_colors(self.color_space);
         let prim_index = self.cpu_metadata.len();
         self.cpu_bounding_rects.push(None);
-        self.gpu_geometry.push(geometry);
+        let geometry_address = gpu_cache.push(geometry);
 
         let metadata = match container {
             PrimitiveContainer::Rectangle(rect) => {
                 let is_opaque = rect.color.a == 1.0;
-                let gpu_address = self.gpu_data16.push(rect);
+                let gpu_address = gpu_cache.push(GpuBlock16::from(rect));
 
                 let metadata = PrimitiveMetadata {
                     is_opaque: is_opaque,
@@ -629,6 +737,7 @@ impl PrimitiveStore {
                     gpu_prim_index: gpu_address,
                     gpu_data_address: GpuStoreAddress(0),
                     gpu_data_count: 0,
+                    gpu_geometry_address: geometry_address,
                     render_task: None,
                     clip_task: None,
                 };
@@ -636,9 +745,9 @@ impl PrimitiveStore {
                 metadata
             }
             PrimitiveContainer::TextRun(mut text_cpu, text_gpu) => {
-                let gpu_address = self.gpu_data16.push(text_gpu);
-                let gpu_glyphs_address = self.gpu_data16.alloc(text_cpu.glyph_range.length);
-                text_cpu.resource_address = self.gpu_resource_rects.alloc(text_cpu.glyph_range.length);
+                let gpu_address = gpu_cache.push(GpuBlock16::from(text_gpu));
+                let gpu_glyphs_address = gpu_cache.alloc::<GpuBlock16>(text_cpu.glyph_range.length);
+                text_cpu.resource_address = gpu_cache.alloc::<TexelRect>(text_cpu.glyph_range.length);
 
                 let metadata = PrimitiveMetadata {
                     is_opaque: false,
@@ -649,6 +758,7 @@ impl PrimitiveStore {
                     gpu_prim_index: gpu_address,
                     gpu_data_address: gpu_glyphs_address,
                     gpu_data_count: text_cpu.glyph_range.length as i32,
+                    gpu_geometry_address: geometry_address,
                     render_task: None,
                     clip_task: None,
                 };
@@ -657,9 +767,11 @@ impl PrimitiveStore {
                 metadata
             }
             PrimitiveContainer::Image(mut image_cpu, image_gpu) => {
-                image_cpu.resource_address = self.gpu_resource_rects.alloc(1);
+                image_cpu.resource_address = gpu_cache.alloc::<TexelRect>(1);
 
-                let gpu_address = self.gpu_data16.push(image_gpu);
+                // The color follows the image data, see fetch_image().
+                let gpu_address = gpu_cache.push(GpuBlock16::from(image_gpu));
+                gpu_cache.push(GpuBlock16::from(image_cpu.color));
 
                 let metadata = PrimitiveMetadata {
                     is_opaque: false,
@@ -670,6 +782,7 @@ impl PrimitiveStore {
                     gpu_prim_index: gpu_address,
                     gpu_data_address: GpuStoreAddress(0),
                     gpu_data_count: 0,
+                    gpu_geometry_address: geometry_address,
                     render_task: None,
                     clip_task: None,
                 };
@@ -678,9 +791,9 @@ impl PrimitiveStore {
                 metadata
             }
             PrimitiveContainer::YuvImage(mut image_cpu, image_gpu) => {
-                image_cpu.yuv_resource_address = self.gpu_resource_rects.alloc(3);
+                image_cpu.yuv_resource_address = gpu_cache.alloc::<TexelRect>(3);
 
-                let gpu_address = self.gpu_data16.push(image_gpu);
+                let gpu_address = gpu_cache.push(GpuBlock16::from(image_gpu));
 
                 let metadata = PrimitiveMetadata {
                     is_opaque: true,
@@ -691,6 +804,7 @@ impl PrimitiveStore {
                     gpu_prim_index: gpu_address,
                     gpu_data_address: GpuStoreAddress(0),
                     gpu_data_count: 0,
+                    gpu_geometry_address: geometry_address,
                     render_task: None,
                     clip_task: None,
                 };
@@ -699,7 +813,7 @@ impl PrimitiveStore {
                 metadata
             }
             PrimitiveContainer::Border(border_cpu, border_gpu) => {
-                let gpu_address = self.gpu_data128.push(border_gpu);
+                let gpu_address = gpu_cache.push(GpuBlock128::from(border_gpu));
 
                 let metadata = PrimitiveMetadata {
                     is_opaque: false,
@@ -710,6 +824,7 @@ impl PrimitiveStore {
                     gpu_prim_index: gpu_address,
                     gpu_data_address: GpuStoreAddress(0),
                     gpu_data_count: 0,
+                    gpu_geometry_address: geometry_address,
                     render_task: None,
                     clip_task: None,
                 };
@@ -718,8 +833,8 @@ impl PrimitiveStore {
                 metadata
             }
             PrimitiveContainer::AlignedGradient(gradient_cpu, gradient_gpu) => {
-                let gpu_address = self.gpu_data32.push(gradient_gpu);
-                let gpu_stops_address = self.gpu_data32.alloc(gradient_cpu.stops_range.length);
+                let gpu_address = gpu_cache.push(GpuBlock32::from(gradient_gpu));
+                let gpu_stops_address = gpu_cache.alloc::<GpuBlock32>(gradient_cpu.stops_range.length);
 
                 let metadata = PrimitiveMetadata {
                     // TODO: calculate if the gradient is actually opaque
@@ -731,6 +846,7 @@ impl PrimitiveStore {
                     gpu_prim_index: gpu_address,
                     gpu_data_address: gpu_stops_address,
                     gpu_data_count: gradient_cpu.stops_range.length as i32,
+                    gpu_geometry_address: geometry_address,
                     render_task: None,
                     clip_task: None,
                 };
@@ -739,7 +855,7 @@ impl PrimitiveStore {
                 metadata
             }
             PrimitiveContainer::AngleGradient(gradient_cpu, gradient_gpu) => {
-                let gpu_address = self.gpu_data32.push(gradient_gpu);
+                let gpu_address = gpu_cache.push(GpuBlock32::from(gradient_gpu));
                 let gpu_gradient_address = self.gpu_gradient_data.alloc(1);
 
                 let metadata = PrimitiveMetadata {
@@ -752,6 +868,7 @@ impl PrimitiveStore {
                     gpu_prim_index: gpu_address,
                     gpu_data_address: gpu_gradient_address,
                     gpu_data_count: 1,
+                    gpu_geometry_address: geometry_address,
                     render_task: None,
                     clip_task: None,
                 };
@@ -760,7 +877,7 @@ impl PrimitiveStore {
                 metadata
             }
             PrimitiveContainer::RadialGradient(radial_gradient_cpu, radial_gradient_gpu) => {
-                let gpu_address = self.gpu_data32.push(radial_gradient_gpu);
+                let gpu_address = gpu_cache.push(GpuBlock32::from(radial_gradient_gpu));
                 let gpu_gradient_address = self.gpu_gradient_data.alloc(1);
 
                 let metadata = PrimitiveMetadata {
@@ -773,6 +890,7 @@ impl PrimitiveStore {
                     gpu_prim_index: gpu_address,
                     gpu_data_address: gpu_gradient_address,
                     gpu_data_count: 1,
+                    gpu_geometry_address: geometry_address,
                     render_task: None,
                     clip_task: None,
                 };
@@ -806,8 +924,8 @@ impl PrimitiveStore {
                                                              cache_size,
                                                              PrimitiveIndex(prim_index));
 
-                let gpu_prim_address = self.gpu_data64.push(box_shadow_gpu);
-                let gpu_data_address = self.gpu_data16.get_next_address();
+                let gpu_prim_address = gpu_cache.push(GpuBlock64::from(box_shadow_gpu));
+                let gpu_data_address = gpu_cache.alloc::<GpuBlock16>(instance_rects.len());
 
                 let metadata = PrimitiveMetadata {
                     is_opaque: false,
@@ -818,12 +936,15 @@ impl PrimitiveStore {
                     gpu_prim_index: gpu_prim_address,
                     gpu_data_address: gpu_data_address,
                     gpu_data_count: instance_rects.len() as i32,
+                    gpu_geometry_address: geometry_address,
                     render_task: Some(render_task),
                     clip_task: None,
                 };
 
-                for rect in instance_rects {
-                    self.gpu_data16.push(InstanceRect {
+                let dest_rects = gpu_cache.get_slice_mut::<GpuBlock16>(gpu_data_address,
+                                                                        instance_rects.len());
+                for (rect, dest) in instance_rects.into_iter().zip(dest_rects.iter_mut()) {
+                    *dest = GpuBlock16::from(InstanceRect {
                         rect: rect,
                     });
                 }
@@ -837,12 +958,12 @@ impl PrimitiveStore {
         PrimitiveIndex(prim_index)
     }
 
-    fn resolve_clip_cache_internal(gpu_data32: &mut VertexDataStore<GpuBlock32>,
-                                   clip_info: &MaskCacheInfo,
-                                   resource_cache: &ResourceCache) {
+    pub fn resolve_clip_cache(clip_info: &MaskCacheInfo,
+                              gpu_cache: &mut GpuCache,
+                              resource_cache: &ResourceCache) {
         if let Some((ref mask, gpu_address)) = clip_info.image {
             let cache_item = resource_cache.get_cached_image(mask.image, ImageRendering::Auto, None);
-            let mask_data = gpu_data32.get_slice_mut(gpu_address, MASK_DATA_GPU_SIZE);
+            let mask_data = gpu_cache.get_slice_mut::<GpuBlock32>(gpu_address, MASK_DATA_GPU_SIZE);
             mask_data[0] = GpuBlock32::from(ImageMaskData {
                 uv_rect: DeviceRect::new(cache_item.uv0,
                                          DeviceSize::new(cache_item.uv1.x - cache_item.uv0.x,
@@ -852,12 +973,6 @@ impl PrimitiveStore {
         }
     }
 
-    pub fn resolve_clip_cache(&mut self,
-                              clip_info: &MaskCacheInfo,
-                              resource_cache: &ResourceCache) {
-        Self::resolve_clip_cache_internal(&mut self.gpu_data32, clip_info, resource_cache)
-    }
-
     fn resolve_image(resource_cache: &ResourceCache,
                      deferred_resolves: &mut Vec<DeferredResolve>,
                      image_key: ImageKey,
@@ -876,6 +991,7 @@ impl PrimitiveStore {
                 deferred_resolves.push(DeferredResolve {
                     image_properties: image_properties,
                     resource_address: image_uv_address,
+                    image_rendering: image_rendering,
                 });
 
                 (SourceTexture::External(external_image), None)
@@ -889,6 +1005,7 @@ impl PrimitiveStore {
 
     pub fn resolve_primitives(&mut self,
                               resource_cache: &ResourceCache,
+                              gpu_cache: &mut GpuCache,
                               device_pixel_ratio: f32) -> Vec<DeferredResolve> {
         profile_scope!("resolve_primitives");
         let mut deferred_resolves = Vec::new();
@@ -896,7 +1013,7 @@ impl PrimitiveStore {
         for prim_index in self.prims_to_resolve.drain(..) {
             let metadata = &mut self.cpu_metadata[prim_index.0];
             if let Some(ref clip_info) = metadata.clip_cache_info {
-                Self::resolve_clip_cache_internal(&mut self.gpu_data32, clip_info, resource_cache);
+                Self::resolve_clip_cache(clip_info, gpu_cache, resource_cache);
             }
 
             match metadata.prim_kind {
@@ -911,8 +1028,8 @@ impl PrimitiveStore {
 
                     let font_size_dp = text.logical_font_size.scale_by(device_pixel_ratio);
 
-                    let dest_rects = self.gpu_resource_rects.get_slice_mut(text.resource_address,
-                                                                           text.glyph_range.length);
+                    let dest_rects = gpu_cache.get_slice_mut::<TexelRect>(text.resource_address,
+                                                                          text.glyph_range.length);
 
                     let texture_id = resource_cache.get_glyphs(text.font_key,
                                                                font_size_dp,
@@ -942,13 +1059,15 @@ impl PrimitiveStore {
                                                           tile_offset)
                         }
                         ImagePrimitiveKind::WebGL(context_id) => {
+                            // The render thread resolves the context to the
+                            // texture it currently shares.
                             let cache_item = resource_cache.get_webgl_texture(&context_id);
                             (cache_item.texture_id, Some(cache_item))
                         }
                     };
 
                     if let Some(cache_item) = cache_item {
-                        let resource_rect = self.gpu_resource_rects.get_mut(image_cpu.resource_address);
+                        let resource_rect = gpu_cache.get_mut::<TexelRect>(image_cpu.resource_address);
                         match image_cpu.sub_rect {
                             Some(sub_rect) => {
                                 resource_rect.uv0.x = cache_item.uv0.x + sub_rect.uv0.x;
@@ -972,7 +1091,8 @@ impl PrimitiveStore {
                         if image_cpu.yuv_texture_id[channel] == SourceTexture::Invalid {
                             // Check if an external image that needs to be resolved
                             // by the render thread.
-                            let resource_address = image_cpu.yuv_resource_address + channel as i32;
+                            let resource_address = item_address::<TexelRect>(image_cpu.yuv_resource_address,
+                                                                             channel);
 
                             let (texture_id, cache_item) =
                                 PrimitiveStore::resolve_image(resource_cache,
@@ -985,7 +1105,7 @@ impl PrimitiveStore {
                             image_cpu.yuv_texture_id[channel] = texture_id;
                             // uv coordinates
                             if let Some(cache_item) = cache_item {
-                                let resource_rect = self.gpu_resource_rects.get_mut(image_cpu.yuv_resource_address + channel as i32);
+                                let resource_rect = gpu_cache.get_mut::<TexelRect>(resource_address);
                                 resource_rect.uv0 = cache_item.uv0;
                                 resource_rect.uv1 = cache_item.uv1;
                             }
@@ -998,7 +1118,22 @@ impl PrimitiveStore {
         deferred_resolves
     }
 
-    pub fn set_clip_source(&mut self, index: PrimitiveIndex, source: Option<ClipSource>) {
+    pub fn set_rectangle_color(&mut self,
+                               index: PrimitiveIndex,
+                               color: ColorF,
+                               gpu_cache: &mut GpuCache) {
+        let metadata = &mut self.cpu_metadata[index.0];
+        debug_assert!(metadata.prim_kind == PrimitiveKind::Rectangle);
+        metadata.is_opaque = color.a == 1.0;
+        *gpu_cache.get_mut::<GpuBlock16>(metadata.gpu_prim_index) = GpuBlock16::from(RectanglePrimitive {
+            color: color.convert_color_space(ColorSpace::Srgb, self.color_space),
+        });
+    }
+
+    pub fn set_clip_source(&mut self,
+                           index: PrimitiveIndex,
+                           source: Option<ClipSource>,
+                           gpu_cache: &mut GpuCache) {
         let metadata = &mut self.cpu_metadata[index.0];
         metadata.clips = match source {
             Some(source) => {
@@ -1006,7 +1141,7 @@ impl PrimitiveStore {
                     ClipSource::Complex(rect, radius, _) => (rect, radius > 0.0),
                     ClipSource::Region(ref region, _) => (region.main, region.is_complex()),
                 };
-                self.gpu_geometry.get_mut(GpuStoreAddress(index.0 as i32))
+                gpu_cache.get_mut::<PrimitiveGeometry>(metadata.gpu_geometry_address)
                     .local_clip_rect = rect;
                 if is_complex {
                     metadata.clip_cache_info = None; //CLIP TODO: re-use the existing GPU allocation
@@ -1023,6 +1158,70 @@ impl PrimitiveStore {
         &self.cpu_metadata[index.0]
     }
 
+    /// Feeds everything that decides how a primitive looks, apart from its
+    /// position on screen, into `state`. Returns false if the primitive may
+    /// look different in every frame, as external and WebGL images do,
+    /// since their contents change behind the resource cache's back.
+    pub fn hash_primitive<H: Hasher>(&self,
+                                     prim_index: PrimitiveIndex,
+                                     resource_cache: &ResourceCache,
+                                     gpu_cache: &GpuCache,
+                                     state: &mut H) -> bool {
+        let metadata = &self.cpu_metadata[prim_index.0];
+        (metadata.prim_kind as u32).hash(state);
+
+        // All the GPU cache blocks of a primitive are allocated together, in
+        // add_primitive(), starting with its geometry.
+        let end_address = match self.cpu_metadata.get(prim_index.0 + 1) {
+            Some(next_metadata) => next_metadata.gpu_geometry_address,
+            None => gpu_cache.next_address(),
+        };
+        gpu_cache.hash_blocks(metadata.gpu_geometry_address, end_address, state);
+
+        match metadata.prim_kind {
+            PrimitiveKind::Image => {
+                let image_cpu = &self.cpu_images[metadata.cpu_prim_index.0];
+                match image_cpu.kind {
+                    ImagePrimitiveKind::Image(image_key, _, _, _) => {
+                        let image_properties = resource_cache.get_image_properties(image_key);
+                        if image_properties.external_image.is_some() {
+                            return false;
+                        }
+                        image_properties.epoch.hash(state);
+                    }
+                    ImagePrimitiveKind::WebGL(..) => return false,
+                }
+            }
+            PrimitiveKind::YuvImage => {
+                let image_cpu = &self.cpu_yuv_images[metadata.cpu_prim_index.0];
+                for image_key in &image_cpu.yuv_key {
+                    let image_properties = resource_cache.get_image_properties(*image_key);
+                    if image_properties.external_image.is_some() {
+                        return false;
+                    }
+                    image_properties.epoch.hash(state);
+                }
+            }
+            PrimitiveKind::AngleGradient | PrimitiveKind::RadialGradient => {
+                for table_index in 0..metadata.gpu_data_count as usize {
+                    let gradient = self.gpu_gradient_data.get(metadata.gpu_data_address + table_index);
+                    for entry in gradient.colors_high.iter().chain(gradient.colors_low.iter()) {
+                        for texel in &[entry.start_color, entry.end_color] {
+                            state.write(&[texel.b, texel.g, texel.r, texel.a]);
+                        }
+                    }
+                }
+            }
+            PrimitiveKind::Rectangle |
+            PrimitiveKind::TextRun |
+            PrimitiveKind::Border |
+            PrimitiveKind::AlignedGradient |
+            PrimitiveKind::BoxShadow => {}
+        }
+
+        true
+    }
+
     pub fn prim_count(&self) -> usize {
         self.cpu_metadata.len()
     }
@@ -1032,8 +1231,10 @@ impl PrimitiveStore {
                                screen_rect: &DeviceIntRect,
                                layer_transform: &LayerToWorldTransform,
                                layer_combined_local_clip_rect: &LayerRect,
-                               device_pixel_ratio: f32) -> bool {
-        let geom = &self.gpu_geometry.get(GpuStoreAddress(prim_index.0 as i32));
+                               device_pixel_ratio: f32,
+                               gpu_cache: &GpuCache) -> bool {
+        let geometry_address = self.cpu_metadata[prim_index.0].gpu_geometry_address;
+        let geom = gpu_cache.get::<PrimitiveGeometry>(geometry_address);
 
         let bounding_rect = geom.local_rect
                                 .intersection(&geom.local_clip_rect)
@@ -1053,6 +1254,7 @@ impl PrimitiveStore {
     pub fn prepare_prim_for_render(&mut self,
                                    prim_index: PrimitiveIndex,
                                    resource_cache: &mut ResourceCache,
+                                   gpu_cache: &mut GpuCache,
                                    layer_transform: &LayerToWorldTransform,
                                    device_pixel_ratio: f32,
                                    auxiliary_lists: &AuxiliaryLists) -> bool {
@@ -1064,7 +1266,7 @@ impl PrimitiveStore {
         if let Some(ref mut clip_info) = metadata.clip_cache_info {
             clip_info.update(&metadata.clips,
                              layer_transform,
-                             &mut self.gpu_data32,
+                             gpu_cache,
                              device_pixel_ratio,
                              auxiliary_lists);
             for clip in &metadata.clips {
@@ -1087,7 +1289,7 @@ impl PrimitiveStore {
                 // the patch, in order to prevent bilinear filter artifacts as
                 // the patch is clamped / mirrored across the box shadow rect.
                 let box_shadow_gpu: &BoxShadowPrimitiveGpu = unsafe {
-                    mem::transmute(self.gpu_data64.get(metadata.gpu_prim_index))
+                    mem::transmute(gpu_cache.get::<GpuBlock64>(metadata.gpu_prim_index))
                 };
                 let edge_size = box_shadow_gpu.edge_size.ceil() * device_pixel_ratio;
                 let edge_size = edge_size as i32 + 2;   // Account for bilinear filtering
@@ -1109,22 +1311,38 @@ impl PrimitiveStore {
                     debug_assert!(metadata.gpu_data_count == text.glyph_range.length as i32);
                     debug_assert!(text.glyph_instances.is_empty());
 
-                    let dest_glyphs = self.gpu_data16.get_slice_mut(metadata.gpu_data_address,
-                                                                    text.glyph_range.length);
+                    // Glyphs at non-finite positions are dropped, like items
+                    // with such geometry are while flattening.
+                    let src_glyphs: Vec<&GlyphInstance> = src_glyphs.iter()
+                                                                    .filter(|src| src.point.x.is_finite() &&
+                                                                                  src.point.y.is_finite())
+                                                                    .collect();
+
+                    let dest_glyphs = gpu_cache.get_slice_mut::<GpuBlock16>(metadata.gpu_data_address,
+                                                                            text.glyph_range.length);
+                    // The index and offset are set for each glyph below.
                     let mut glyph_key = GlyphKey::new(text.font_key,
                                                       font_size_dp,
                                                       text.color,
-                                                      src_glyphs[0].index,
-                                                      src_glyphs[0].point,
+                                                      0,
+                                                      Point2D::zero(),
                                                       text.render_mode);
                     let mut local_rect = LayerRect::zero();
                     let mut actual_glyph_count = 0;
 
-                    for src in src_glyphs {
+                    // Look up the dimensions of new glyphs in parallel first.
+                    let subpixel_quantization = resource_cache.subpixel_quantization();
+                    let glyph_keys: Vec<GlyphKey> = src_glyphs.iter().map(|src| {
                         glyph_key.index = src.index;
-                        glyph_key.subpixel_point.set_offset(src.point, text.render_mode);
-
-                        let dimensions = match resource_cache.get_glyph_dimensions(&glyph_key) {
+                        glyph_key.subpixel_point.set_quantized_offset(src.point,
+                                                                      text.render_mode,
+                                                                      subpixel_quantization);
+                        glyph_key.clone()
+                    }).collect();
+                    resource_cache.request_glyph_dimensions(&glyph_keys);
+
+                    for (src, glyph_key) in src_glyphs.iter().zip(&glyph_keys) {
+                        let dimensions = match resource_cache.get_glyph_dimensions(glyph_key) {
                             None => continue,
                             Some(dimensions) => dimensions,
                         };
@@ -1180,7 +1398,8 @@ impl PrimitiveStore {
 
                     metadata.gpu_data_count = actual_glyph_count as i32;
                     metadata.render_task = render_task;
-                    self.gpu_geometry.get_mut(GpuStoreAddress(prim_index.0 as i32)).local_rect = local_rect;
+                    gpu_cache.get_mut::<PrimitiveGeometry>(metadata.gpu_geometry_address)
+                        .local_rect = local_rect;
                 }
 
                 resource_cache.request_glyphs(text.font_key,
@@ -1203,7 +1422,9 @@ impl PrimitiveStore {
                         // right now, but if we introduce a cache for images for some other
                         // reason then we might as well cache this with it.
                         let image_properties = resource_cache.get_image_properties(image_key);
+                        image_cpu.is_alpha_mask = image_properties.descriptor.format == ImageFormat::A8;
                         metadata.is_opaque = image_properties.descriptor.is_opaque &&
+                                             !image_cpu.is_alpha_mask &&
                                              tile_spacing.width == 0.0 &&
                                              tile_spacing.height == 0.0;
                     }
@@ -1227,13 +1448,13 @@ impl PrimitiveStore {
                     let src_stops = auxiliary_lists.gradient_stops(&gradient.stops_range);
 
                     debug_assert!(metadata.gpu_data_count == gradient.stops_range.length as i32);
-                    let dest_stops = self.gpu_data32.get_slice_mut(metadata.gpu_data_address,
-                                                                   gradient.stops_range.length);
+                    let dest_stops = gpu_cache.get_slice_mut::<GpuBlock32>(metadata.gpu_data_address,
+                                                                           gradient.stops_range.length);
 
                     for (src, dest) in src_stops.iter().zip(dest_stops.iter_mut()) {
                         *dest = GpuBlock32::from(GradientStopGpu {
                             offset: src.offset,
-                            color: src.color,
+                            color: src.color.convert_color_space(ColorSpace::Srgb, self.color_space),
                             padding: [0.0; 3],
                         });
                     }
@@ -1245,8 +1466,16 @@ impl PrimitiveStore {
                 let gradient = &mut self.cpu_gradients[metadata.cpu_prim_index.0];
                 if gradient.cache_dirty {
                     let src_stops = auxiliary_lists.gradient_stops(&gradient.stops_range);
-                    let dest_gradient = self.gpu_gradient_data.get_mut(metadata.gpu_data_address);
-                    dest_gradient.build(src_stops, gradient.reverse_stops);
+                    let table_count = GradientData::table_count(src_stops,
+                                                                gradient.length * device_pixel_ratio,
+                                                                self.max_gradient_tables);
+                    if table_count != metadata.gpu_data_count as usize {
+                        metadata.gpu_data_address = self.gpu_gradient_data.alloc(table_count);
+                        metadata.gpu_data_count = table_count as i32;
+                    }
+                    let dest_tables = self.gpu_gradient_data.get_slice_mut(metadata.gpu_data_address,
+                                                                           table_count);
+                    GradientData::build(dest_tables, src_stops, gradient.reverse_stops, self.color_space);
                     gradient.cache_dirty = false;
                 }
             }
@@ -1254,8 +1483,16 @@ impl PrimitiveStore {
                 let gradient = &mut self.cpu_radial_gradients[metadata.cpu_prim_index.0];
                 if gradient.cache_dirty {
                     let src_stops = auxiliary_lists.gradient_stops(&gradient.stops_range);
-                    let dest_gradient = self.gpu_gradient_data.get_mut(metadata.gpu_data_address);
-                    dest_gradient.build(src_stops, false);
+                    let table_count = GradientData::table_count(src_stops,
+                                                                gradient.length * device_pixel_ratio,
+                                                                self.max_gradient_tables);
+                    if table_count != metadata.gpu_data_count as usize {
+                        metadata.gpu_data_address = self.gpu_gradient_data.alloc(table_count);
+                        metadata.gpu_data_count = table_count as i32;
+                    }
+                    let dest_tables = self.gpu_gradient_data.get_slice_mut(metadata.gpu_data_address,
+                                                                           table_count);
+                    GradientData::build(dest_tables, src_stops, false, self.color_space);
                     gradient.cache_dirty = false;
                 }
             }
@@ -1275,6 +1512,8 @@ pub struct GpuBlock16 {
     data: [f32; 4],
 }
 
+impl GpuCacheItem for GpuBlock16 {}
+
 impl Default for GpuBlock16 {
     fn default() -> GpuBlock16 {
         GpuBlock16 {
@@ -1307,6 +1546,14 @@ impl From<InstanceRect> for GpuBlock16 {
     }
 }
 
+impl From<ColorF> for GpuBlock16 {
+    fn from(data: ColorF) -> GpuBlock16 {
+        unsafe {
+            mem::transmute::<ColorF, GpuBlock16>(data)
+        }
+    }
+}
+
 impl From<ImagePrimitiveGpu> for GpuBlock16 {
     fn from(data: ImagePrimitiveGpu) -> GpuBlock16 {
         unsafe {
@@ -1329,6 +1576,8 @@ pub struct GpuBlock32 {
     data: [f32; 8],
 }
 
+impl GpuCacheItem for GpuBlock32 {}
+
 impl Default for GpuBlock32 {
     fn default() -> GpuBlock32 {
         GpuBlock32 {
@@ -1399,6 +1648,8 @@ pub struct GpuBlock64 {
     data: [f32; 16],
 }
 
+impl GpuCacheItem for GpuBlock64 {}
+
 impl Default for GpuBlock64 {
     fn default() -> GpuBlock64 {
         GpuBlock64 {
@@ -1421,6 +1672,8 @@ pub struct GpuBlock128 {
     data: [f32; 32],
 }
 
+impl GpuCacheItem for GpuBlock128 {}
+
 impl Default for GpuBlock128 {
     fn default() -> GpuBlock128 {
         GpuBlock128 {
@@ -73,8 +73,13 @@ pub fn should_record_msg(msg: &ApiMsg) -> bool {
         &ApiMsg::DeleteImage(..) |
         &ApiMsg::SetDisplayList(..) |
         &ApiMsg::SetRootPipeline(..) |
+        &ApiMsg::SetPipelineBackgroundColor(..) |
+        &ApiMsg::SetPipelineRenderTarget(..) |
         &ApiMsg::Scroll(..) |
         &ApiMsg::TickScrollingBounce |
+        &ApiMsg::SetOverscrollBehavior(..) |
+        &ApiMsg::ScrollNodeWithId(..) |
+        &ApiMsg::SetExternallyScrolled(..) |
         &ApiMsg::WebGLCommand(..) =>
             true,
         _ => false
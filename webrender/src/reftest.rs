@@ -0,0 +1,358 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A harness for reference tests. It renders display lists with a headless
+//! GL context, and compares the output against reference images.
+
+use gleam::gl;
+use renderer::{InitError, Renderer, RendererOptions, SurfaceOrigin};
+use std::cmp;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use util::write_pam;
+use webrender_traits::{AuxiliaryLists, AuxiliaryListsDescriptor, BuiltDisplayList};
+use webrender_traits::{BuiltDisplayListDescriptor, ColorF, DeviceUintPoint, DeviceUintRect};
+use webrender_traits::{DeviceUintSize, Epoch, LayoutSize, PipelineId, RenderApi};
+
+/// How far the rendered image may be from the reference before the test
+/// fails.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReftestFuzz {
+    /// The largest difference allowed in any channel of a pixel.
+    pub max_difference: u8,
+    /// The number of pixels that may differ by up to `max_difference`.
+    pub num_differences: usize,
+}
+
+impl ReftestFuzz {
+    /// Requires the images to match exactly.
+    pub fn exact() -> ReftestFuzz {
+        ReftestFuzz {
+            max_difference: 0,
+            num_differences: 0,
+        }
+    }
+}
+
+impl Default for ReftestFuzz {
+    fn default() -> ReftestFuzz {
+        ReftestFuzz::exact()
+    }
+}
+
+/// The outcome of comparing a rendered image with its reference.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReftestResult {
+    /// The images match within the fuzz.
+    Pass,
+    /// The images differ by more than the fuzz allows. Holds the largest
+    /// channel difference and the number of pixels that differ.
+    Fail(u8, usize),
+    /// The images have different sizes, so they can't be compared.
+    SizeMismatch(DeviceUintSize, DeviceUintSize),
+}
+
+impl ReftestResult {
+    pub fn is_pass(&self) -> bool {
+        *self == ReftestResult::Pass
+    }
+}
+
+impl fmt::Display for ReftestResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReftestResult::Pass => write!(f, "pass"),
+            ReftestResult::Fail(max_difference, num_differences) => {
+                write!(f, "fail: {} pixels differ, by up to {}", num_differences, max_difference)
+            }
+            ReftestResult::SizeMismatch(test_size, reference_size) => {
+                write!(f,
+                       "fail: image is {}x{}, but the reference is {}x{}",
+                       test_size.width,
+                       test_size.height,
+                       reference_size.width,
+                       reference_size.height)
+            }
+        }
+    }
+}
+
+/// An RGBA8 image, with the rows in top to bottom order.
+#[derive(Clone, Debug)]
+pub struct ReftestImage {
+    pub size: DeviceUintSize,
+    pub data: Vec<u8>,
+}
+
+impl ReftestImage {
+    /// Compares this image with `reference`, allowing the differences
+    /// described by `fuzz`.
+    pub fn compare(&self, reference: &ReftestImage, fuzz: &ReftestFuzz) -> ReftestResult {
+        if self.size != reference.size {
+            return ReftestResult::SizeMismatch(self.size, reference.size);
+        }
+
+        let mut max_difference = 0;
+        let mut num_differences = 0;
+        for (pixel, reference_pixel) in self.data.chunks(4).zip(reference.data.chunks(4)) {
+            let difference = pixel_difference(pixel, reference_pixel);
+            if difference > 0 {
+                max_difference = cmp::max(max_difference, difference);
+                num_differences += 1;
+            }
+        }
+
+        if max_difference <= fuzz.max_difference && num_differences <= fuzz.num_differences {
+            ReftestResult::Pass
+        } else {
+            ReftestResult::Fail(max_difference, num_differences)
+        }
+    }
+
+    /// Creates an image that shows where this image differs from
+    /// `reference`. Differing pixels are opaque red, and the rest of the
+    /// reference is drawn in dimmed grayscale for context. The images must
+    /// be the same size.
+    pub fn create_diff_image(&self, reference: &ReftestImage) -> ReftestImage {
+        assert_eq!(self.size, reference.size);
+
+        let mut data = Vec::with_capacity(self.data.len());
+        for (pixel, reference_pixel) in self.data.chunks(4).zip(reference.data.chunks(4)) {
+            if pixel_difference(pixel, reference_pixel) > 0 {
+                data.extend_from_slice(&[255, 0, 0, 255]);
+            } else {
+                let luma = (reference_pixel[0] as u32 * 2 +
+                            reference_pixel[1] as u32 * 5 +
+                            reference_pixel[2] as u32) / 8;
+                let dimmed = (luma / 4) as u8;
+                data.extend_from_slice(&[dimmed, dimmed, dimmed, 255]);
+            }
+        }
+
+        ReftestImage {
+            size: self.size,
+            data: data,
+        }
+    }
+
+    /// Saves the image as a PAM file.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        write_pam(path, &self.data, &self.size, 4)
+    }
+}
+
+fn pixel_difference(pixel: &[u8], reference_pixel: &[u8]) -> u8 {
+    pixel.iter()
+         .zip(reference_pixel)
+         .map(|(a, b)| if a > b { a - b } else { b - a })
+         .max()
+         .unwrap_or(0)
+}
+
+/// Renders display lists headlessly for reference tests.
+///
+/// The renderer is always created with `RendererOptions::synchronous_backend`,
+/// `RendererOptions::deterministic` and
+/// `RendererOptions::single_threaded_workers`, so each `render` call returns
+/// the finished frame, and the output doesn't depend on timing.
+pub struct ReftestHarness {
+    renderer: Renderer,
+    api: RenderApi,
+    framebuffer: gl::GLuint,
+    size: DeviceUintSize,
+    device_pixel_ratio: f32,
+    framebuffer_origin: SurfaceOrigin,
+    epoch: Epoch,
+    diff_output_path: Option<PathBuf>,
+}
+
+impl ReftestHarness {
+    /// Creates a harness that renders into the current framebuffer of `gl`,
+    /// which must be at least `size` pixels.
+    pub fn new(gl: Rc<gl::Gl>,
+               mut options: RendererOptions,
+               size: DeviceUintSize) -> Result<ReftestHarness, InitError> {
+        options.synchronous_backend = true;
+        options.deterministic = true;
+        options.single_threaded_workers = true;
+        let device_pixel_ratio = options.device_pixel_ratio;
+        let framebuffer_origin = options.framebuffer_origin;
+        let framebuffer = gl.get_integer_v(gl::DRAW_FRAMEBUFFER_BINDING) as gl::GLuint;
+
+        let (renderer, sender) = try!(Renderer::new(gl, options));
+        let api = sender.create_api();
+        api.set_window_parameters(size,
+                                  DeviceUintRect::new(DeviceUintPoint::zero(), size),
+                                  device_pixel_ratio);
+
+        Ok(ReftestHarness {
+            renderer: renderer,
+            api: api,
+            framebuffer: framebuffer,
+            size: size,
+            device_pixel_ratio: device_pixel_ratio,
+            framebuffer_origin: framebuffer_origin,
+            epoch: Epoch(0),
+            diff_output_path: None,
+        })
+    }
+
+    /// The API of the renderer, for adding the fonts and images that the
+    /// display lists use.
+    pub fn api(&self) -> &RenderApi {
+        &self.api
+    }
+
+    /// Sets the directory that failing comparisons write the test,
+    /// reference and diff images to. Nothing is written with `None`.
+    pub fn set_diff_output_path(&mut self, path: Option<PathBuf>) {
+        self.diff_output_path = path;
+    }
+
+    /// Renders a display list as the root pipeline, and reads back the
+    /// result.
+    pub fn render(&mut self,
+                  background_color: Option<ColorF>,
+                  display_list: (PipelineId, BuiltDisplayList, AuxiliaryLists))
+                  -> ReftestImage {
+        let pipeline_id = display_list.0;
+        let viewport_size = LayoutSize::new(self.size.width as f32 / self.device_pixel_ratio,
+                                            self.size.height as f32 / self.device_pixel_ratio);
+
+        self.epoch.0 += 1;
+        self.api.set_root_pipeline(pipeline_id);
+        self.api.set_display_list(background_color,
+                                  self.epoch,
+                                  viewport_size,
+                                  display_list,
+                                  false);
+        self.api.generate_frame(None, None);
+
+        self.renderer.update_and_build_frame();
+        // The renderer draws into whichever framebuffer is bound when the
+        // frame starts.
+        self.renderer.gl().bind_framebuffer(gl::DRAW_FRAMEBUFFER, self.framebuffer);
+        self.renderer.render(self.size);
+
+        self.read_framebuffer()
+    }
+
+    /// Renders a display list that was serialized with
+    /// `BuiltDisplayList::into_data` and `AuxiliaryLists::into_data`.
+    pub fn render_serialized(&mut self,
+                             background_color: Option<ColorF>,
+                             pipeline_id: PipelineId,
+                             (display_list_data, display_list_descriptor): (Vec<u8>, BuiltDisplayListDescriptor),
+                             (auxiliary_lists_data, auxiliary_lists_descriptor): (Vec<u8>, AuxiliaryListsDescriptor))
+                             -> ReftestImage {
+        let display_list = BuiltDisplayList::from_data(display_list_data, display_list_descriptor);
+        let auxiliary_lists = AuxiliaryLists::from_data(auxiliary_lists_data,
+                                                        auxiliary_lists_descriptor);
+        self.render(background_color, (pipeline_id, display_list, auxiliary_lists))
+    }
+
+    /// Compares `test` with `reference`. If they differ by more than
+    /// `fuzz`, and a diff output path is set, the images and a diff image
+    /// are written there, prefixed with `name`.
+    pub fn check(&self,
+                 name: &str,
+                 test: &ReftestImage,
+                 reference: &ReftestImage,
+                 fuzz: &ReftestFuzz) -> ReftestResult {
+        let result = test.compare(reference, fuzz);
+        if let (&ReftestResult::Fail(..), Some(path)) = (&result, self.diff_output_path.as_ref()) {
+            let diff = test.create_diff_image(reference);
+            for &(kind, image) in &[("test", test), ("reference", reference), ("diff", &diff)] {
+                let file_path = path.join(format!("{}-{}.pam", name, kind));
+                if let Err(err) = image.save(&file_path) {
+                    warn!("Failed to save reftest image to {:?}: {}", file_path, err);
+                }
+            }
+        }
+        result
+    }
+
+    /// Reads back the framebuffer, with the rows flipped to top to bottom
+    /// order when the framebuffer starts at the bottom.
+    fn read_framebuffer(&self) -> ReftestImage {
+        let gl = self.renderer.gl();
+        gl.bind_framebuffer(gl::READ_FRAMEBUFFER, self.framebuffer);
+        let pixels = gl.read_pixels(0,
+                                    0,
+                                    self.size.width as gl::GLsizei,
+                                    self.size.height as gl::GLsizei,
+                                    gl::RGBA,
+                                    gl::UNSIGNED_BYTE);
+
+        let data = match self.framebuffer_origin {
+            SurfaceOrigin::BottomLeft => {
+                let stride = self.size.width as usize * 4;
+                pixels.chunks(stride).rev().flat_map(|row| row.iter().cloned()).collect()
+            }
+            SurfaceOrigin::TopLeft => pixels,
+        };
+
+        ReftestImage {
+            size: self.size,
+            data: data,
+        }
+    }
+
+    pub fn deinit(self) {
+        self.renderer.deinit();
+    }
+}
+
+#[cfg(test)]
+fn test_image(width: u32, height: u32, value: u8) -> ReftestImage {
+    ReftestImage {
+        size: DeviceUintSize::new(width, height),
+        data: vec![value; width as usize * height as usize * 4],
+    }
+}
+
+#[test]
+fn identical_images_match_exactly() {
+    let image = test_image(4, 4, 128);
+    assert_eq!(image.compare(&image.clone(), &ReftestFuzz::exact()), ReftestResult::Pass);
+}
+
+#[test]
+fn fuzz_allows_small_differences() {
+    let reference = test_image(4, 4, 128);
+    let mut image = reference.clone();
+    image.data[0] = 130;
+    image.data[6] = 125;
+
+    assert_eq!(image.compare(&reference, &ReftestFuzz::exact()), ReftestResult::Fail(3, 2));
+    let fuzz = ReftestFuzz { max_difference: 3, num_differences: 2 };
+    assert_eq!(image.compare(&reference, &fuzz), ReftestResult::Pass);
+}
+
+#[test]
+fn differences_beyond_the_fuzz_fail() {
+    let reference = test_image(4, 4, 128);
+    let mut image = reference.clone();
+    image.data[0] = 140;
+    image.data[4] = 129;
+    image.data[8] = 129;
+
+    // Too large a difference in one pixel.
+    let fuzz = ReftestFuzz { max_difference: 4, num_differences: 3 };
+    assert_eq!(image.compare(&reference, &fuzz), ReftestResult::Fail(12, 3));
+    // Too many differing pixels.
+    let fuzz = ReftestFuzz { max_difference: 12, num_differences: 2 };
+    assert_eq!(image.compare(&reference, &fuzz), ReftestResult::Fail(12, 3));
+}
+
+#[test]
+fn images_of_different_sizes_dont_match() {
+    let image = test_image(4, 4, 0);
+    let reference = test_image(4, 2, 0);
+    let fuzz = ReftestFuzz { max_difference: 255, num_differences: 16 };
+    assert_eq!(image.compare(&reference, &fuzz),
+               ReftestResult::SizeMismatch(DeviceUintSize::new(4, 4), DeviceUintSize::new(4, 2)));
+}
@@ -4,23 +4,32 @@
 
 use frame::Frame;
 use frame_builder::FrameBuilderConfig;
-use internal_types::{FontTemplate, SourceTexture, ResultMsg, RendererFrame};
+use gpu_cache::GpuCache;
+use internal_types::{FontTemplate, ResultMsg, RendererFrame, WebGLFence};
+use internal_types::memory_pressure_reached;
+use leak_detector::{LeakDetector, TrackedResource};
 use profiler::{BackendProfileCounters, TextureCacheProfileCounters};
 use record::ApiRecordingReceiver;
+use render_task_cache::RenderTaskCache;
+use renderer::MemoryPressurePolicy;
 use resource_cache::ResourceCache;
-use scene::Scene;
+use scene::{Scene, SceneProperties};
 use std::collections::HashMap;
+use std::mem;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicUsize;
 use std::sync::mpsc::Sender;
 use texture_cache::TextureCache;
 use thread_profiler::register_thread_with_profiler;
 use threadpool::ThreadPool;
 use webgl_types::{GLContextHandleWrapper, GLContextWrapper};
-use webrender_traits::{DeviceIntPoint, DeviceUintPoint, DeviceUintRect, DeviceUintSize, LayerPoint};
-use webrender_traits::{ApiMsg, AuxiliaryLists, BuiltDisplayList, IdNamespace, ImageData};
-use webrender_traits::{PipelineId, RenderNotifier, RenderDispatcher, WebGLCommand, WebGLContextId};
+use webrender_traits::{DeviceIntPoint, DeviceUintRect, DeviceUintSize, LayerPoint};
+use webrender_traits::{ApiMsg, AuxiliaryLists, BuiltDisplayList, DebugCommand, IdNamespace, ImageData};
+use webrender_traits::{ExternalImageData, ExternalImageType};
+use webrender_traits::{BackendQuery, BackendQueryResponse, MemoryPressure, ScenePipelineInfo};
+use webrender_traits::{PipelineId, RenderNotifier, RenderDispatcher, WebGLContextId};
 use webrender_traits::channel::{PayloadSenderHelperMethods, PayloadReceiverHelperMethods, PayloadReceiver, PayloadSender, MsgReceiver};
-use webrender_traits::{BlobImageRenderer, VRCompositorCommand, VRCompositorHandler};
+use webrender_traits::{BlobImageRenderer, FrameToken, VRCompositorCommand, VRCompositorHandler};
 #[cfg(feature = "webgl")]
 use offscreen_gl_context::GLContextDispatcher;
 #[cfg(not(feature = "webgl"))]
@@ -29,7 +38,10 @@ use webgl_types::GLContextDispatcher;
 /// The render backend is responsible for transforming high level display lists into
 /// GPU-friendly work which is then submitted to the renderer in the form of a frame::Frame.
 ///
-/// The render backend operates on its own thread.
+/// The render backend normally operates on its own thread. With
+/// `RendererOptions::synchronous_backend` it is owned by the renderer instead,
+/// which pumps it from `Renderer::update_and_build_frame`, and from the
+/// `RenderApi` calls that wait for a reply.
 pub struct RenderBackend {
     api_rx: MsgReceiver<ApiMsg>,
     payload_rx: PayloadReceiver,
@@ -46,20 +58,45 @@ pub struct RenderBackend {
     next_namespace_id: IdNamespace,
 
     resource_cache: ResourceCache,
+    /// The data the shaders fetch for primitives. It outlives the scenes,
+    /// so that what a new scene has in common with the old one isn't
+    /// uploaded again.
+    gpu_cache: GpuCache,
 
     scene: Scene,
     frame: Frame,
 
+    /// What some render tasks drew in recent frames, for the next ones to
+    /// reuse.
+    render_task_cache: RenderTaskCache,
+
     notifier: Arc<Mutex<Option<Box<RenderNotifier>>>>,
     webrender_context_handle: Option<GLContextHandleWrapper>,
     webgl_contexts: HashMap<WebGLContextId, GLContextWrapper>,
     current_bound_webgl_context_id: Option<WebGLContextId>,
+    /// Fences inserted at the last scene build, to be handed to the
+    /// renderer with the next published frame.
+    pending_webgl_fences: Vec<WebGLFence>,
     recorder: Option<Box<ApiRecordingReceiver>>,
     main_thread_dispatcher: Arc<Mutex<Option<Box<RenderDispatcher>>>>,
 
     next_webgl_id: usize,
 
-    vr_compositor_handler: Arc<Mutex<Option<Box<VRCompositorHandler>>>>
+    vr_compositor_handler: Arc<Mutex<Option<Box<VRCompositorHandler>>>>,
+
+    /// Check incoming display lists before using them.
+    validate_display_lists: bool,
+
+    /// Pipelines removed since the last published frame. The renderer is
+    /// told about them with the next frame.
+    removed_pipelines: Vec<PipelineId>,
+
+    /// Keeps track of the live images, fonts and pipelines in debug builds.
+    leak_detector: LeakDetector,
+
+    /// The last level reported to `RenderApi::set_memory_pressure`.
+    memory_pressure: MemoryPressure,
+    memory_pressure_policy: MemoryPressurePolicy,
 }
 
 impl RenderBackend {
@@ -78,11 +115,16 @@ impl RenderBackend {
                main_thread_dispatcher: Arc<Mutex<Option<Box<RenderDispatcher>>>>,
                blob_image_renderer: Option<Box<BlobImageRenderer>>,
                vr_compositor_handler: Arc<Mutex<Option<Box<VRCompositorHandler>>>>,
-               initial_window_size: DeviceUintSize) -> RenderBackend {
+               validate_display_lists: bool,
+               memory_pressure_policy: MemoryPressurePolicy,
+               rendered_frame_index: Arc<AtomicUsize>) -> RenderBackend {
 
-        let resource_cache = ResourceCache::new(texture_cache, workers, blob_image_renderer, enable_aa);
-
-        register_thread_with_profiler("Backend".to_string());
+        let resource_cache = ResourceCache::new(texture_cache,
+                                                workers,
+                                                blob_image_renderer,
+                                                enable_aa,
+                                                config.color_space,
+                                                config.subpixel_quantization);
 
         RenderBackend {
             api_rx: api_rx,
@@ -94,23 +136,33 @@ impl RenderBackend {
             pinch_zoom_factor: 1.0,
             pan: DeviceIntPoint::zero(),
             resource_cache: resource_cache,
+            gpu_cache: GpuCache::new(),
             scene: Scene::new(),
             frame: Frame::new(config),
+            render_task_cache: RenderTaskCache::new(rendered_frame_index),
             next_namespace_id: IdNamespace(1),
             notifier: notifier,
             webrender_context_handle: webrender_context_handle,
             webgl_contexts: HashMap::new(),
             current_bound_webgl_context_id: None,
+            pending_webgl_fences: Vec::new(),
             recorder: recorder,
             main_thread_dispatcher: main_thread_dispatcher,
             next_webgl_id: 0,
             vr_compositor_handler: vr_compositor_handler,
-            window_size: initial_window_size,
-            inner_rect: DeviceUintRect::new(DeviceUintPoint::zero(), initial_window_size),
+            // Nothing is drawn until the embedder sets the window parameters.
+            window_size: DeviceUintSize::zero(),
+            inner_rect: DeviceUintRect::zero(),
+            validate_display_lists: validate_display_lists,
+            removed_pipelines: Vec::new(),
+            leak_detector: LeakDetector::new(),
+            memory_pressure: MemoryPressure::Normal,
+            memory_pressure_policy: memory_pressure_policy,
         }
     }
 
     pub fn run(&mut self, mut profile_counters: BackendProfileCounters) {
+        register_thread_with_profiler("Backend".to_string());
         let mut frame_counter: u32 = 0;
 
         loop {
@@ -118,306 +170,506 @@ impl RenderBackend {
             profile_scope!("handle_msg");
             match msg {
                 Ok(msg) => {
-                    if let Some(ref mut r) = self.recorder {
-                        r.write_msg(frame_counter, &msg);
+                    if !self.handle_msg(msg, &mut profile_counters, &mut frame_counter) {
+                        break;
                     }
-                    match msg {
-                        ApiMsg::AddRawFont(id, bytes) => {
-                            profile_counters.font_templates.inc(bytes.len());
-                            self.resource_cache
-                                .add_font_template(id, FontTemplate::Raw(Arc::new(bytes)));
-                        }
-                        ApiMsg::AddNativeFont(id, native_font_handle) => {
-                            self.resource_cache
-                                .add_font_template(id, FontTemplate::Native(native_font_handle));
-                        }
-                        ApiMsg::DeleteFont(id) => {
-                            self.resource_cache.delete_font_template(id);
-                        }
-                        ApiMsg::GetGlyphDimensions(glyph_keys, tx) => {
-                            let mut glyph_dimensions = Vec::with_capacity(glyph_keys.len());
-                            for glyph_key in &glyph_keys {
-                                let glyph_dim = self.resource_cache.get_glyph_dimensions(glyph_key);
-                                glyph_dimensions.push(glyph_dim);
-                            };
-                            tx.send(glyph_dimensions).unwrap();
-                        }
-                        ApiMsg::AddImage(id, descriptor, data, tiling) => {
-                            if let ImageData::Raw(ref bytes) = data {
-                                profile_counters.image_templates.inc(bytes.len());
-                            }
-                            self.resource_cache.add_image_template(id, descriptor, data, tiling);
-                        }
-                        ApiMsg::UpdateImage(id, descriptor, bytes, dirty_rect) => {
-                            self.resource_cache.update_image_template(id, descriptor, bytes, dirty_rect);
-                        }
-                        ApiMsg::DeleteImage(id) => {
-                            self.resource_cache.delete_image_template(id);
-                        }
-                        ApiMsg::SetPageZoom(factor) => {
-                            self.page_zoom_factor = factor.get();
-                        }
-                        ApiMsg::SetPinchZoom(factor) => {
-                            self.pinch_zoom_factor = factor.get();
-                        }
-                        ApiMsg::SetPan(pan) => {
-                            self.pan = pan;
-                        }
-                        ApiMsg::SetWindowParameters(window_size, inner_rect) => {
-                            self.window_size = window_size;
-                            self.inner_rect = inner_rect;
-                        }
-                        ApiMsg::CloneApi(sender) => {
-                            let result = self.next_namespace_id;
+                }
+                Err(..) => {
+                    self.leak_detector.report_live_resources();
+                    let notifier = self.notifier.lock();
+                    notifier.unwrap()
+                            .as_mut()
+                            .unwrap()
+                            .shut_down();
+                    break;
+                }
+            }
+        }
+    }
 
-                            let IdNamespace(id_namespace) = self.next_namespace_id;
-                            self.next_namespace_id = IdNamespace(id_namespace + 1);
+    /// Processes every message that is currently queued, without blocking.
+    /// Used instead of `run` when the backend lives on the renderer's thread.
+    /// Returns false once the backend has been shut down.
+    pub fn process_pending_messages(&mut self,
+                                    profile_counters: &mut BackendProfileCounters,
+                                    frame_counter: &mut u32)
+                                    -> bool {
+        while let Ok(msg) = self.api_rx.try_recv() {
+            if !self.handle_msg(msg, profile_counters, frame_counter) {
+                return false;
+            }
+        }
+        true
+    }
 
-                            sender.send(result).unwrap();
-                        }
-                        ApiMsg::SetDisplayList(background_color,
-                                               epoch,
-                                               pipeline_id,
-                                               viewport_size,
-                                               display_list_descriptor,
-                                               auxiliary_lists_descriptor,
-                                               preserve_frame_state) => {
-                            profile_scope!("SetDisplayList");
-                            let mut leftover_auxiliary_data = vec![];
-                            let mut auxiliary_data;
-                            loop {
-                                auxiliary_data = self.payload_rx.recv_payload().unwrap();
-                                {
-                                    if auxiliary_data.epoch == epoch &&
-                                       auxiliary_data.pipeline_id == pipeline_id {
-                                        break
-                                    }
-                                }
-                                leftover_auxiliary_data.push(auxiliary_data)
-                            }
-                            for leftover_auxiliary_data in leftover_auxiliary_data {
-                                self.payload_tx.send_payload(leftover_auxiliary_data).unwrap()
-                            }
-                            if let Some(ref mut r) = self.recorder {
-                                r.write_payload(frame_counter, &auxiliary_data.to_data());
-                            }
+    /// Processes a single API message. Returns false once the backend has
+    /// been shut down.
+    fn handle_msg(&mut self,
+                  msg: ApiMsg,
+                  profile_counters: &mut BackendProfileCounters,
+                  frame_counter: &mut u32)
+                  -> bool {
+        if let Some(ref mut r) = self.recorder {
+            r.write_msg(*frame_counter, &msg);
+        }
+        match msg {
+            ApiMsg::AddRawFont(id, bytes) => {
+                profile_counters.font_templates.inc(bytes.len());
+                self.leak_detector.add(TrackedResource::Font(id));
+                self.resource_cache
+                    .add_font_template(id, FontTemplate::Raw(Arc::new(bytes)));
+            }
+            ApiMsg::AddNativeFont(id, native_font_handle) => {
+                self.leak_detector.add(TrackedResource::Font(id));
+                self.resource_cache
+                    .add_font_template(id, FontTemplate::Native(native_font_handle));
+            }
+            ApiMsg::DeleteFont(id) => {
+                self.leak_detector.remove(TrackedResource::Font(id));
+                self.resource_cache.delete_font_template(id);
+            }
+            ApiMsg::GetGlyphDimensions(glyph_keys, tx) => {
+                let mut glyph_dimensions = Vec::with_capacity(glyph_keys.len());
+                for glyph_key in &glyph_keys {
+                    let glyph_dim = self.resource_cache.get_glyph_dimensions(glyph_key);
+                    glyph_dimensions.push(glyph_dim);
+                };
+                tx.send(glyph_dimensions).unwrap();
+            }
+            ApiMsg::AddImage(id, descriptor, data, tiling) => {
+                if let ImageData::Raw(ref bytes) = data {
+                    profile_counters.image_templates.inc(bytes.len());
+                }
+                self.leak_detector.add(match data {
+                    ImageData::External(..) => TrackedResource::ExternalImage(id),
+                    _ => TrackedResource::Image(id),
+                });
+                self.resource_cache.add_image_template(id, descriptor, data, tiling);
+            }
+            ApiMsg::UpdateImage(id, descriptor, bytes, dirty_rect) => {
+                self.resource_cache.update_image_template(id, descriptor, bytes, dirty_rect);
+            }
+            ApiMsg::DeleteImage(id) => {
+                self.leak_detector.remove(TrackedResource::Image(id));
+                self.leak_detector.remove(TrackedResource::ExternalImage(id));
+                self.resource_cache.delete_image_template(id);
+            }
+            ApiMsg::SetPageZoom(factor) => {
+                self.page_zoom_factor = factor.get();
+            }
+            ApiMsg::SetSubpixelAA(enable) => {
+                // Text runs pick their render mode when the scene
+                // is flattened, so rebuild it.
+                self.frame.set_subpixel_aa(enable);
+                self.build_scene();
+            }
+            ApiMsg::SetPinchZoom(factor) => {
+                self.pinch_zoom_factor = factor.get();
+            }
+            ApiMsg::SetPan(pan) => {
+                self.pan = pan;
+            }
+            ApiMsg::SetWindowParameters(window_size, inner_rect, device_pixel_ratio) => {
+                self.window_size = window_size;
+                self.inner_rect = inner_rect;
+                self.hidpi_factor = device_pixel_ratio;
+            }
+            ApiMsg::CloneApi(sender) => {
+                let result = self.next_namespace_id;
 
-                            let built_display_list =
-                                BuiltDisplayList::from_data(auxiliary_data.display_list_data,
-                                                            display_list_descriptor);
-                            let auxiliary_lists =
-                                AuxiliaryLists::from_data(auxiliary_data.auxiliary_lists_data,
-                                                          auxiliary_lists_descriptor);
+                let IdNamespace(id_namespace) = self.next_namespace_id;
+                self.next_namespace_id = IdNamespace(id_namespace + 1);
 
-                            if !preserve_frame_state {
-                                self.discard_frame_state_for_pipeline(pipeline_id);
-                            }
-                            profile_counters.total_time.profile(|| {
-                                self.scene.set_display_list(pipeline_id,
-                                                            epoch,
-                                                            built_display_list,
-                                                            background_color,
-                                                            viewport_size,
-                                                            auxiliary_lists);
-                                self.build_scene();
-                            })
+                sender.send(result).unwrap();
+            }
+            ApiMsg::SetDisplayList(background_color,
+                                   epoch,
+                                   pipeline_id,
+                                   viewport_size,
+                                   display_list_descriptor,
+                                   auxiliary_lists_descriptor,
+                                   preserve_frame_state) => {
+                profile_scope!("SetDisplayList");
+                let mut leftover_auxiliary_data = vec![];
+                let mut auxiliary_data;
+                loop {
+                    auxiliary_data = self.payload_rx.recv_payload().unwrap();
+                    {
+                        if auxiliary_data.epoch == epoch &&
+                           auxiliary_data.pipeline_id == pipeline_id {
+                            break
                         }
-                        ApiMsg::SetRootPipeline(pipeline_id) => {
-                            profile_scope!("SetRootPipeline");
-                            self.scene.set_root_pipeline_id(pipeline_id);
+                    }
+                    leftover_auxiliary_data.push(auxiliary_data)
+                }
+                for leftover_auxiliary_data in leftover_auxiliary_data {
+                    self.payload_tx.send_payload(leftover_auxiliary_data).unwrap()
+                }
+                if let Some(ref mut r) = self.recorder {
+                    r.write_payload(*frame_counter, &auxiliary_data.to_data());
+                }
 
-                            if self.scene.display_lists.get(&pipeline_id).is_none() {
-                                continue;
-                            }
+                let built_display_list =
+                    BuiltDisplayList::from_data(auxiliary_data.display_list_data,
+                                                display_list_descriptor);
+                let auxiliary_lists =
+                    AuxiliaryLists::from_data(auxiliary_data.auxiliary_lists_data,
+                                              auxiliary_lists_descriptor);
+
+                if self.validate_display_lists {
+                    if let Err(error) = built_display_list.validate(pipeline_id, &auxiliary_lists) {
+                        warn!("Rejected display list for {:?} at {:?}: {}", pipeline_id, epoch, error);
+                        let mut notifier = self.notifier.lock();
+                        notifier.as_mut().unwrap().as_mut().unwrap()
+                                .display_list_rejected(pipeline_id, epoch, error);
+                        return true;
+                    }
+                }
 
-                            profile_counters.total_time.profile(|| {
-                                self.build_scene();
-                            })
-                        }
-                        ApiMsg::Scroll(delta, cursor, move_phase) => {
-                            profile_scope!("Scroll");
-                            let frame = {
-                                let counters = &mut profile_counters.texture_cache;
-                                profile_counters.total_time.profile(|| {
-                                    if self.frame.scroll(delta, cursor, move_phase) {
-                                        Some(self.render(counters))
-                                    } else {
-                                        None
-                                    }
-                                })
-                            };
-
-                            match frame {
-                                Some(frame) => {
-                                    self.publish_frame(frame, &mut profile_counters);
-                                    self.notify_compositor_of_new_scroll_frame(true)
-                                }
-                                None => self.notify_compositor_of_new_scroll_frame(false),
-                            }
-                        }
-                        ApiMsg::ScrollLayerWithId(origin, id) => {
-                            profile_scope!("ScrollLayerWithScrollId");
-                            let frame = {
-                                let counters = &mut profile_counters.texture_cache;
-                                profile_counters.total_time.profile(|| {
-                                    if self.frame.scroll_nodes(origin, id) {
-                                        Some(self.render(counters))
-                                    } else {
-                                        None
-                                    }
-                                })
-                            };
-
-                            match frame {
-                                Some(frame) => {
-                                    self.publish_frame(frame, &mut profile_counters);
-                                    self.notify_compositor_of_new_scroll_frame(true)
-                                }
-                                None => self.notify_compositor_of_new_scroll_frame(false),
-                            }
+                if !preserve_frame_state {
+                    self.discard_frame_state_for_pipeline(pipeline_id);
+                }
+                self.leak_detector.add(TrackedResource::Pipeline(pipeline_id));
+                profile_counters.total_time.profile(|| {
+                    self.scene.set_display_list(pipeline_id,
+                                                epoch,
+                                                built_display_list,
+                                                background_color,
+                                                viewport_size,
+                                                auxiliary_lists);
+                    self.build_scene();
+                })
+            }
+            ApiMsg::SetRootPipeline(pipeline_id) => {
+                profile_scope!("SetRootPipeline");
+                self.scene.set_root_pipeline_id(pipeline_id);
 
+                if self.scene.display_lists.get(&pipeline_id).is_none() {
+                    return true;
+                }
+
+                profile_counters.total_time.profile(|| {
+                    self.build_scene();
+                })
+            }
+            ApiMsg::SetPipelineBackgroundColor(pipeline_id, background_color) => {
+                profile_scope!("SetPipelineBackgroundColor");
+                self.scene.set_background_color(pipeline_id, background_color);
+
+                profile_counters.total_time.profile(|| {
+                    self.build_scene();
+                })
+            }
+            ApiMsg::RemovePipeline(pipeline_id) => {
+                profile_scope!("RemovePipeline");
+                self.leak_detector.remove(TrackedResource::Pipeline(pipeline_id));
+                if !self.scene.remove_pipeline(pipeline_id) {
+                    return true;
+                }
+
+                let is_root = self.scene.root_pipeline_id == Some(pipeline_id);
+                self.frame.remove_pipeline(pipeline_id, is_root);
+                self.removed_pipelines.push(pipeline_id);
+
+                profile_counters.total_time.profile(|| {
+                    self.build_scene();
+                })
+            }
+            ApiMsg::SetPipelineRenderTarget(pipeline_id, target) => {
+                profile_scope!("SetPipelineRenderTarget");
+                // Pipelines can only be copied to textures.
+                let target = match target {
+                    Some(ExternalImageData { image_type: ExternalImageType::ExternalBuffer, id }) => {
+                        warn!("Ignored render target {:?} for {:?}, as it isn't a texture", id, pipeline_id);
+                        None
+                    }
+                    target => target,
+                };
+                self.scene.set_render_target(pipeline_id, target);
+
+                profile_counters.total_time.profile(|| {
+                    self.build_scene();
+                })
+            }
+            ApiMsg::Scroll(delta, cursor, move_phase) => {
+                profile_scope!("Scroll");
+                let frame = {
+                    let counters = &mut profile_counters.texture_cache;
+                    profile_counters.total_time.profile(|| {
+                        if self.frame.scroll(delta, cursor, move_phase) {
+                            Some(self.render(counters))
+                        } else {
+                            None
                         }
-                        ApiMsg::TickScrollingBounce => {
-                            profile_scope!("TickScrollingBounce");
-                            let frame = {
-                                let counters = &mut profile_counters.texture_cache;
-                                profile_counters.total_time.profile(|| {
-                                    self.frame.tick_scrolling_bounce_animations();
-                                    self.render(counters)
-                                })
-                            };
-
-                            self.publish_frame_and_notify_compositor(frame, &mut profile_counters);
-                        }
-                        ApiMsg::TranslatePointToLayerSpace(..) => {
-                            panic!("unused api - remove from webrender_traits");
-                        }
-                        ApiMsg::GetScrollLayerState(tx) => {
-                            profile_scope!("GetScrollLayerState");
-                            tx.send(self.frame.get_scroll_node_state())
-                              .unwrap()
-                        }
-                        ApiMsg::RequestWebGLContext(size, attributes, tx) => {
-                            if let Some(ref wrapper) = self.webrender_context_handle {
-                                let dispatcher: Option<Box<GLContextDispatcher>> = if cfg!(target_os = "windows") {
-                                    Some(Box::new(WebRenderGLDispatcher {
-                                        dispatcher: Arc::clone(&self.main_thread_dispatcher)
-                                    }))
-                                } else {
-                                    None
-                                };
-
-                                let result = wrapper.new_context(size, attributes, dispatcher);
-
-                                match result {
-                                    Ok(ctx) => {
-                                        let id = WebGLContextId(self.next_webgl_id);
-                                        self.next_webgl_id += 1;
-
-                                        let (real_size, texture_id, limits) = ctx.get_info();
-
-                                        self.webgl_contexts.insert(id, ctx);
-
-                                        self.resource_cache
-                                            .add_webgl_texture(id, SourceTexture::WebGL(texture_id),
-                                                               real_size);
-
-                                        tx.send(Ok((id, limits))).unwrap();
-                                    },
-                                    Err(msg) => {
-                                        tx.send(Err(msg.to_owned())).unwrap();
-                                    }
-                                }
-                            } else {
-                                tx.send(Err("Not implemented yet".to_owned())).unwrap();
-                            }
-                        }
-                        ApiMsg::ResizeWebGLContext(context_id, size) => {
-                            let ctx = self.webgl_contexts.get_mut(&context_id).unwrap();
-                            ctx.make_current();
-                            match ctx.resize(&size) {
-                                Ok(_) => {
-                                    // Update webgl texture size. Texture id may change too.
-                                    let (real_size, texture_id, _) = ctx.get_info();
-                                    self.resource_cache
-                                        .update_webgl_texture(context_id, SourceTexture::WebGL(texture_id),
-                                                              real_size);
-                                },
-                                Err(msg) => {
-                                    error!("Error resizing WebGLContext: {}", msg);
-                                }
-                            }
-                        }
-                        ApiMsg::WebGLCommand(context_id, command) => {
-                            // TODO: Buffer the commands and only apply them here if they need to
-                            // be synchronous.
-                            let ctx = &self.webgl_contexts[&context_id];
-                            ctx.make_current();
-                            ctx.apply_command(command);
-                            self.current_bound_webgl_context_id = Some(context_id);
-                        },
+                    })
+                };
 
-                        ApiMsg::VRCompositorCommand(context_id, command) => {
-                            self.handle_vr_compositor_command(context_id, command);
+                match frame {
+                    Some(frame) => {
+                        self.publish_frame(frame, None, profile_counters);
+                        self.notify_compositor_of_new_scroll_frame(true)
+                    }
+                    None => self.notify_compositor_of_new_scroll_frame(false),
+                }
+                self.notify_overscroll_changes();
+            }
+            ApiMsg::ScrollLayerWithId(origin, id) => {
+                profile_scope!("ScrollLayerWithScrollId");
+                let frame = {
+                    let counters = &mut profile_counters.texture_cache;
+                    profile_counters.total_time.profile(|| {
+                        if self.frame.scroll_nodes(origin, id) {
+                            Some(self.render(counters))
+                        } else {
+                            None
                         }
-                        ApiMsg::GenerateFrame(property_bindings) => {
-                            profile_scope!("GenerateFrame");
-
-                            // Ideally, when there are property bindings present,
-                            // we won't need to rebuild the entire frame here.
-                            // However, to avoid conflicts with the ongoing work to
-                            // refactor how scroll roots + transforms work, this
-                            // just rebuilds the frame if there are animated property
-                            // bindings present for now.
-                            // TODO(gw): Once the scrolling / reference frame changes
-                            //           are completed, optimize the internals of
-                            //           animated properties to not require a full
-                            //           rebuild of the frame!
-                            if let Some(property_bindings) = property_bindings {
-                                self.scene.properties.set_properties(property_bindings);
-                                profile_counters.total_time.profile(|| {
-                                    self.build_scene();
-                                });
-                            }
+                    })
+                };
 
-                            let frame = {
-                                let counters = &mut profile_counters.texture_cache;
-                                profile_counters.total_time.profile(|| {
-                                    self.render(counters)
-                                })
-                            };
-                            if self.scene.root_pipeline_id.is_some() {
-                                self.publish_frame_and_notify_compositor(frame, &mut profile_counters);
-                                frame_counter += 1;
-                            }
+                match frame {
+                    Some(frame) => {
+                        self.publish_frame(frame, None, profile_counters);
+                        self.notify_compositor_of_new_scroll_frame(true)
+                    }
+                    None => self.notify_compositor_of_new_scroll_frame(false),
+                }
+                self.notify_overscroll_changes();
+
+            }
+            ApiMsg::ScrollNodeWithId(origin, id, clamping, animation) => {
+                profile_scope!("ScrollNodeWithId");
+                let frame = {
+                    let counters = &mut profile_counters.texture_cache;
+                    profile_counters.total_time.profile(|| {
+                        if self.frame.scroll_node_to(origin, id, clamping, animation) {
+                            Some(self.render(counters))
+                        } else {
+                            None
                         }
-                        ApiMsg::ExternalEvent(evt) => {
-                            let notifier = self.notifier.lock();
-                            notifier.unwrap()
-                                    .as_mut()
-                                    .unwrap()
-                                    .external_event(evt);
+                    })
+                };
+
+                match frame {
+                    Some(frame) => {
+                        self.publish_frame(frame, None, profile_counters);
+                        self.notify_compositor_of_new_scroll_frame(true)
+                    }
+                    None => self.notify_compositor_of_new_scroll_frame(false),
+                }
+                self.notify_overscroll_changes();
+            }
+            ApiMsg::TickScrollingBounce => {
+                profile_scope!("TickScrollingBounce");
+                let frame = {
+                    let counters = &mut profile_counters.texture_cache;
+                    profile_counters.total_time.profile(|| {
+                        self.frame.tick_scrolling_bounce_animations();
+                        self.render(counters)
+                    })
+                };
+
+                self.publish_frame_and_notify_compositor(frame, None, profile_counters);
+                self.notify_overscroll_changes();
+            }
+            ApiMsg::SetOverscrollBehavior(id, behavior) => {
+                self.frame.set_overscroll_behavior(id, behavior);
+            }
+            ApiMsg::SetExternallyScrolled(id, externally_scrolled) => {
+                self.frame.set_externally_scrolled(id, externally_scrolled);
+            }
+            ApiMsg::TranslatePointToLayerSpace(..) => {
+                panic!("unused api - remove from webrender_traits");
+            }
+            ApiMsg::GetScrollLayerState(tx) => {
+                profile_scope!("GetScrollLayerState");
+                tx.send(self.frame.get_scroll_node_state())
+                  .unwrap()
+            }
+            ApiMsg::RequestWebGLContext(size, attributes, tx) => {
+                if let Some(ref wrapper) = self.webrender_context_handle {
+                    let dispatcher: Option<Box<GLContextDispatcher>> = if cfg!(target_os = "windows") {
+                        Some(Box::new(WebRenderGLDispatcher {
+                            dispatcher: Arc::clone(&self.main_thread_dispatcher)
+                        }))
+                    } else {
+                        None
+                    };
+
+                    let result = wrapper.new_context(size, attributes, dispatcher);
+
+                    match result {
+                        Ok(ctx) => {
+                            let id = WebGLContextId(self.next_webgl_id);
+                            self.next_webgl_id += 1;
+
+                            let (real_size, texture_id, limits) = ctx.get_info();
+
+                            self.webgl_contexts.insert(id, ctx);
+
+                            self.resource_cache.add_webgl_texture(id, real_size);
+                            let msg = ResultMsg::UpdateWebGLTexture(id, texture_id, real_size);
+                            self.result_tx.send(msg).unwrap();
+
+                            tx.send(Ok((id, limits))).unwrap();
+                        },
+                        Err(msg) => {
+                            tx.send(Err(msg.to_owned())).unwrap();
                         }
-                        ApiMsg::ShutDown => {
-                            let notifier = self.notifier.lock();
-                            notifier.unwrap()
-                                    .as_mut()
-                                    .unwrap()
-                                    .shut_down();
-                            break;
+                    }
+                } else {
+                    tx.send(Err("Not implemented yet".to_owned())).unwrap();
+                }
+            }
+            ApiMsg::ResizeWebGLContext(context_id, size) => {
+                let ctx = self.webgl_contexts.get_mut(&context_id).unwrap();
+                ctx.make_current();
+                match ctx.resize(&size) {
+                    Ok(_) => {
+                        // Update webgl texture size. Texture id may change too.
+                        let (real_size, texture_id, _) = ctx.get_info();
+                        self.resource_cache.update_webgl_texture(context_id, real_size);
+                        let msg = ResultMsg::UpdateWebGLTexture(context_id, texture_id, real_size);
+                        self.result_tx.send(msg).unwrap();
+                    },
+                    Err(msg) => {
+                        error!("Error resizing WebGLContext: {}", msg);
+                    }
+                }
+            }
+            ApiMsg::DeleteWebGLContext(context_id) => {
+                // Dropping the context destroys its texture, so the renderer
+                // forgets it in the same message.
+                if self.webgl_contexts.remove(&context_id).is_some() {
+                    if self.current_bound_webgl_context_id == Some(context_id) {
+                        self.current_bound_webgl_context_id = None;
+                    }
+                    self.resource_cache.delete_webgl_texture(context_id);
+                    self.result_tx.send(ResultMsg::DeleteWebGLTexture(context_id)).unwrap();
+                }
+            }
+            ApiMsg::WebGLCommand(context_id, command) => {
+                // TODO: Buffer the commands and only apply them here if they need to
+                // be synchronous.
+                let ctx = &self.webgl_contexts[&context_id];
+                ctx.make_current();
+                ctx.apply_command(command);
+                self.current_bound_webgl_context_id = Some(context_id);
+            },
+
+            ApiMsg::VRCompositorCommand(context_id, command) => {
+                self.handle_vr_compositor_command(context_id, command);
+            }
+            ApiMsg::GenerateFrame(property_bindings, token) => {
+                profile_scope!("GenerateFrame");
+
+                // Ideally, when there are property bindings present,
+                // we won't need to rebuild the entire frame here.
+                // However, to avoid conflicts with the ongoing work to
+                // refactor how scroll roots + transforms work, this
+                // just rebuilds the frame if there are animated property
+                // bindings present for now.
+                // TODO(gw): Once the scrolling / reference frame changes
+                //           are completed, optimize the internals of
+                //           animated properties to not require a full
+                //           rebuild of the frame!
+                if let Some(mut property_bindings) = property_bindings {
+                    // External scroll offsets only move nodes of the existing
+                    // clip scroll tree, and the transforms are recomputed by
+                    // render() below, so they alone don't need a rebuild.
+                    let scroll_offsets = mem::replace(&mut property_bindings.scroll_offsets,
+                                                      Vec::new());
+                    let only_scroll_offsets = !scroll_offsets.is_empty() &&
+                                              property_bindings.transforms.is_empty() &&
+                                              property_bindings.floats.is_empty();
+                    self.frame.set_external_scroll_offsets(scroll_offsets);
+
+                    if !only_scroll_offsets {
+                        // Bound transforms and opacities of the current scene
+                        // are updated in place where possible. This keeps
+                        // animations smooth while the embedder is still busy
+                        // building the next display list.
+                        let mut properties = SceneProperties::new();
+                        properties.set_properties(property_bindings);
+                        let updated = self.frame.update_animated_properties(&self.scene.properties,
+                                                                            &properties);
+                        self.scene.properties = properties;
+
+                        if !updated {
+                            profile_counters.total_time.profile(|| {
+                                self.build_scene();
+                            });
                         }
                     }
                 }
-                Err(..) => {
-                    let notifier = self.notifier.lock();
-                    notifier.unwrap()
-                            .as_mut()
-                            .unwrap()
-                            .shut_down();
-                    break;
+
+                let frame = {
+                    let counters = &mut profile_counters.texture_cache;
+                    profile_counters.total_time.profile(|| {
+                        self.render(counters)
+                    })
+                };
+                if self.scene.root_pipeline_id.is_some() {
+                    self.publish_frame_and_notify_compositor(frame, token, profile_counters);
+                    *frame_counter += 1;
+                }
+            }
+            ApiMsg::ExternalEvent(evt) => {
+                let notifier = self.notifier.lock();
+                notifier.unwrap()
+                        .as_mut()
+                        .unwrap()
+                        .external_event(evt);
+            }
+            ApiMsg::SetMemoryPressure(level) => {
+                if level != self.memory_pressure {
+                    self.memory_pressure = level;
+                    self.result_tx.send(ResultMsg::SetMemoryPressure(level)).unwrap();
+                    self.release_memory();
+                }
+            }
+            ApiMsg::DebugCommand(cmd) => {
+                match cmd {
+                    DebugCommand::ClearCaches => {
+                        self.resource_cache.release_memory(true, true);
+                    }
+                    DebugCommand::SetFlags(..) |
+                    DebugCommand::EnableProfiler(..) |
+                    DebugCommand::SaveCpuProfile(..) => {
+                        self.result_tx.send(ResultMsg::DebugCommand(cmd)).unwrap();
+                    }
                 }
             }
+            ApiMsg::BackendQuery(id, query) => {
+                let response = match query {
+                    BackendQuery::ResourceCacheStats => {
+                        BackendQueryResponse::ResourceCacheStats(self.resource_cache.stats())
+                    }
+                    BackendQuery::ScenePipelines => {
+                        let pipelines = self.scene.pipeline_map.values().map(|pipeline| {
+                            ScenePipelineInfo {
+                                pipeline_id: pipeline.pipeline_id,
+                                epoch: pipeline.epoch,
+                                display_item_count: self.scene.display_lists
+                                                              .get(&pipeline.pipeline_id)
+                                                              .map_or(0, |items| items.len()),
+                            }
+                        }).collect();
+                        BackendQueryResponse::ScenePipelines(pipelines)
+                    }
+                };
+                self.result_tx.send(ResultMsg::BackendQueryResponse(id, response)).unwrap();
+            }
+            ApiMsg::ShutDown => {
+                self.leak_detector.report_live_resources();
+                let notifier = self.notifier.lock();
+                notifier.unwrap()
+                        .as_mut()
+                        .unwrap()
+                        .shut_down();
+                return false;
+            }
         }
+        true
     }
 
     fn discard_frame_state_for_pipeline(&mut self, pipeline_id: PipelineId) {
@@ -435,22 +687,22 @@ impl RenderBackend {
             self.current_bound_webgl_context_id = None;
         }
 
-        // When running in OSMesa mode with texture sharing,
-        // a flush is required on any GL contexts to ensure
-        // that read-back from the shared texture returns
-        // valid data! This should be fine to have run on all
-        // implementations - a single flush for each webgl
-        // context at the start of a render frame should
-        // incur minimal cost.
+        // Insert a fence in each WebGL context rather than relying on
+        // a flush (or worse, a finish) to synchronize with the renderer.
+        // The renderer waits on these fences on the GPU before sampling
+        // the shared textures, so neither thread blocks. Inserting the
+        // fence also flushes the context, which OSMesa texture sharing
+        // relies on for read-back to return valid data.
         for (_, webgl_context) in &self.webgl_contexts {
             webgl_context.make_current();
-            webgl_context.apply_command(WebGLCommand::Flush);
+            self.pending_webgl_fences.push(WebGLFence(webgl_context.insert_fence()));
             webgl_context.unbind();
         }
 
         let accumulated_scale_factor = self.accumulated_scale_factor();
         self.frame.create(&self.scene,
                           &mut self.resource_cache,
+                          &mut self.gpu_cache,
                           self.window_size,
                           self.inner_rect,
                           accumulated_scale_factor);
@@ -462,35 +714,68 @@ impl RenderBackend {
         let accumulated_scale_factor = self.accumulated_scale_factor();
         let pan = LayerPoint::new(self.pan.x as f32 / accumulated_scale_factor,
                                   self.pan.y as f32 / accumulated_scale_factor);
-        let frame = self.frame.build(&mut self.resource_cache,
-                                     &self.scene.pipeline_auxiliary_lists,
-                                     accumulated_scale_factor,
-                                     pan,
-                                     texture_cache_profile);
+        let mut frame = self.frame.build(&mut self.resource_cache,
+                                         &mut self.gpu_cache,
+                                         &self.scene.pipeline_auxiliary_lists,
+                                         accumulated_scale_factor,
+                                         pan,
+                                         texture_cache_profile,
+                                         &mut self.render_task_cache);
+        // Expired items can leave texture cache pages empty, so release
+        // again while the pressure lasts.
+        if self.frame.expire_old_resources(&mut self.resource_cache) &&
+           self.memory_pressure != MemoryPressure::Normal {
+            self.release_memory();
+        }
+        frame.webgl_fences.extend(self.pending_webgl_fences.drain(..));
+        self.notify_missing_glyphs();
         frame
     }
 
+    /// Releases what the memory pressure policy says to at the current
+    /// level. This happens when the level changes, and after frames that
+    /// freed cached items while memory is short.
+    fn release_memory(&mut self) {
+        let policy = self.memory_pressure_policy;
+        let drop_glyph_dimensions = memory_pressure_reached(policy.drop_glyph_dimensions,
+                                                            self.memory_pressure);
+        let free_empty_pages = memory_pressure_reached(policy.free_empty_cache_pages,
+                                                       self.memory_pressure);
+        self.resource_cache.release_memory(drop_glyph_dimensions, free_empty_pages);
+    }
+
     fn publish_frame(&mut self,
-                     frame: RendererFrame,
+                     mut frame: RendererFrame,
+                     token: Option<FrameToken>,
                      profile_counters: &mut BackendProfileCounters) {
+        frame.removed_pipelines = mem::replace(&mut self.removed_pipelines, Vec::new());
         let pending_update = self.resource_cache.pending_updates();
         let pending_external_image_update = self.resource_cache.pending_external_image_updates();
-        let msg = ResultMsg::NewFrame(frame, pending_update, pending_external_image_update, profile_counters.clone());
+        let msg = ResultMsg::NewFrame(frame,
+                                      pending_update,
+                                      pending_external_image_update,
+                                      profile_counters.clone(),
+                                      token);
         self.result_tx.send(msg).unwrap();
         profile_counters.reset();
     }
 
     fn publish_frame_and_notify_compositor(&mut self,
                                            frame: RendererFrame,
+                                           token: Option<FrameToken>,
                                            profile_counters: &mut BackendProfileCounters) {
-        self.publish_frame(frame, profile_counters);
+        self.publish_frame(frame, token, profile_counters);
 
         // TODO(gw): This is kindof bogus to have to lock the notifier
         //           each time it's used. This is due to some nastiness
         //           in initialization order for Servo. Perhaps find a
         //           cleaner way to do this, or use the OnceMutex on crates.io?
         let mut notifier = self.notifier.lock();
-        notifier.as_mut().unwrap().as_mut().unwrap().new_frame_ready();
+        let notifier = notifier.as_mut().unwrap().as_mut().unwrap();
+        notifier.new_frame_ready();
+        if let Some(token) = token {
+            notifier.generated_frame_ready(token);
+        }
     }
 
     fn notify_compositor_of_new_scroll_frame(&mut self, composite_needed: bool) {
@@ -502,13 +787,60 @@ impl RenderBackend {
         notifier.as_mut().unwrap().as_mut().unwrap().new_scroll_frame_ready(composite_needed);
     }
 
+    fn notify_overscroll_changes(&mut self) {
+        let events = self.frame.take_overscroll_events();
+        if events.is_empty() {
+            return;
+        }
+
+        let mut notifier = self.notifier.lock();
+        notifier.as_mut().unwrap().as_mut().unwrap().overscroll_changed(events);
+    }
+
+    fn notify_missing_glyphs(&mut self) {
+        let missing_glyphs = self.resource_cache.take_missing_glyphs();
+        if missing_glyphs.is_empty() {
+            return;
+        }
+
+        let mut notifier = self.notifier.lock();
+        let notifier = notifier.as_mut().unwrap().as_mut().unwrap();
+        for (font_key, glyph_indices) in missing_glyphs {
+            notifier.glyphs_missing(font_key, glyph_indices);
+        }
+    }
+
     fn handle_vr_compositor_command(&mut self, ctx_id: WebGLContextId, cmd: VRCompositorCommand) {
         let texture = match cmd {
             VRCompositorCommand::SubmitFrame(..) => {
-                    match self.resource_cache.get_webgl_texture(&ctx_id).texture_id {
-                        SourceTexture::WebGL(texture_id) => Some(texture_id),
-                        _=> None
+                let ctx = match self.webgl_contexts.get(&ctx_id) {
+                    Some(ctx) => ctx,
+                    None => {
+                        warn!("Dropped a VR frame from deleted WebGL context {:?}", ctx_id);
+                        return;
                     }
+                };
+                let (_, texture_id, _) = ctx.get_info();
+                Some(texture_id)
+            },
+            VRCompositorCommand::SubmitMultiviewFrame(_, texture_array_id) => {
+                let ctx = match self.webgl_contexts.get(&ctx_id) {
+                    Some(ctx) => ctx,
+                    None => {
+                        warn!("Dropped a VR frame from deleted WebGL context {:?}", ctx_id);
+                        return;
+                    }
+                };
+                // The WebGL content draws both eyes into the layers of a
+                // texture array, which is handed on as it is. Without the
+                // extension the array can't have been drawn in one pass.
+                ctx.make_current();
+                self.current_bound_webgl_context_id = Some(ctx_id);
+                if !ctx.supports_extension("GL_OVR_multiview2") {
+                    warn!("Dropped a multiview VR frame from {:?}, which lacks OVR_multiview2", ctx_id);
+                    return;
+                }
+                Some(texture_array_id)
             },
             _ => None
         };
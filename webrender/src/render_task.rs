@@ -9,10 +9,16 @@ use std::{cmp, f32, i32, mem, usize};
 use tiling::{ClipScrollGroupIndex, PackedLayerIndex, RenderPass, RenderTargetIndex};
 use tiling::{RenderTargetKind, StackingContextIndex};
 use webrender_traits::{DeviceIntLength, DeviceIntPoint, DeviceIntRect, DeviceIntSize};
-use webrender_traits::{MixBlendMode, ScrollLayerId};
+use webrender_traits::{ExternalImageData, MixBlendMode, ScrollLayerId, SnapshotId};
 
 const FLOATS_PER_RENDER_TASK_INFO: usize = 12;
 
+/// The largest radius, in device pixels, that is blurred at the size of
+/// the primitive. The cost of a blur pass grows with its radius, so larger
+/// blurs are applied to a copy of the primitive that is halved in size
+/// until the radius fits, and scaled back up when drawn.
+pub const MAX_BLUR_RADIUS: i32 = 32;
+
 #[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
 pub struct RenderTaskIndex(pub usize);
 
@@ -26,6 +32,9 @@ pub enum RenderTaskKey {
     VerticalBlur(i32, PrimitiveIndex),
     /// Apply a horizontal blur pass of given radius for this primitive.
     HorizontalBlur(i32, PrimitiveIndex),
+    /// Halve the size of this primitive the given number of times, for
+    /// a blur.
+    Downscale(i32, PrimitiveIndex),
     /// Allocate a block of space in target for framebuffer copy.
     CopyFramebuffer(StackingContextIndex),
 }
@@ -57,12 +66,28 @@ pub enum AlphaRenderItem {
     HardwareComposite(StackingContextIndex, RenderTaskId, HardwareCompositeOp, i32),
 }
 
+impl AlphaRenderItem {
+    pub fn z_sort_index(&self) -> i32 {
+        match *self {
+            AlphaRenderItem::Primitive(_, _, z) |
+            AlphaRenderItem::Blend(_, _, _, z) |
+            AlphaRenderItem::Composite(_, _, _, _, z) |
+            AlphaRenderItem::HardwareComposite(_, _, _, z) => z,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AlphaRenderTask {
     screen_origin: DeviceIntPoint,
     pub opaque_items: Vec<AlphaRenderItem>,
     pub alpha_items: Vec<AlphaRenderItem>,
     pub isolate_clear: bool,
+    /// The contents of the task are read back once drawn.
+    pub snapshot: Option<SnapshotId>,
+    /// The contents of the task are copied to an external image once
+    /// drawn.
+    pub render_target: Option<ExternalImageData>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -126,8 +151,10 @@ pub enum RenderTaskKind {
     Alpha(AlphaRenderTask),
     CachePrimitive(PrimitiveIndex),
     CacheMask(CacheMaskTask),
-    VerticalBlur(DeviceIntLength, PrimitiveIndex),
-    HorizontalBlur(DeviceIntLength, PrimitiveIndex),
+    /// The blur passes and downscales, with the task they read from.
+    VerticalBlur(DeviceIntLength, RenderTaskKey),
+    HorizontalBlur(DeviceIntLength, RenderTaskKey),
+    Downscale(RenderTaskKey),
     Readback(DeviceIntRect),
 }
 
@@ -156,6 +183,8 @@ impl RenderTask {
                 alpha_items: Vec::new(),
                 opaque_items: Vec::new(),
                 isolate_clear: isolate_clear,
+                snapshot: None,
+                render_target: None,
             }),
         }
     }
@@ -270,6 +299,9 @@ impl RenderTask {
     //    PrimitiveCacheTask: Draw the text run.
     //           ^
     //           |
+    //    DownscaleTask: Halve the size of the text run, once per task, for
+    //           ^       blur radii larger than MAX_BLUR_RADIUS.
+    //           |
     //    VerticalBlurTask: Apply the separable vertical blur to the primitive.
     //           ^
     //           |
@@ -281,25 +313,47 @@ impl RenderTask {
                     size: DeviceIntSize,
                     blur_radius: DeviceIntLength,
                     prim_index: PrimitiveIndex) -> RenderTask {
-        let prim_cache_task = RenderTask::new_prim_cache(key,
-                                                         size,
-                                                         prim_index);
+        let mut src_task = RenderTask::new_prim_cache(key,
+                                                      size,
+                                                      prim_index);
+        let mut src_key = RenderTaskKey::CachePrimitive(key);
+
+        // Each downscale halves the blur radius along with the size, and
+        // the bilinear filter averages the texels that are merged.
+        let mut size = size;
+        let mut radius = blur_radius.0;
+        let mut scale_count = 0;
+        while radius > MAX_BLUR_RADIUS {
+            scale_count += 1;
+            size = DeviceIntSize::new((size.width + 1) / 2, (size.height + 1) / 2);
+            radius = (radius + 1) / 2;
+
+            let scale_key = RenderTaskKey::Downscale(scale_count, prim_index);
+            src_task = RenderTask {
+                id: RenderTaskId::Dynamic(scale_key),
+                children: vec![src_task],
+                location: RenderTaskLocation::Dynamic(None, size),
+                kind: RenderTaskKind::Downscale(src_key),
+            };
+            src_key = scale_key;
+        }
 
-        let blur_target_size = size + DeviceIntSize::new(2 * blur_radius.0,
-                                                         2 * blur_radius.0);
+        let blur_target_size = size + DeviceIntSize::new(2 * radius, 2 * radius);
+        let radius = DeviceIntLength::new(radius);
 
+        let blur_key_v = RenderTaskKey::VerticalBlur(blur_radius.0, prim_index);
         let blur_task_v = RenderTask {
-            id: RenderTaskId::Dynamic(RenderTaskKey::VerticalBlur(blur_radius.0, prim_index)),
-            children: vec![prim_cache_task],
+            id: RenderTaskId::Dynamic(blur_key_v),
+            children: vec![src_task],
             location: RenderTaskLocation::Dynamic(None, blur_target_size),
-            kind: RenderTaskKind::VerticalBlur(blur_radius, prim_index),
+            kind: RenderTaskKind::VerticalBlur(radius, src_key),
         };
 
         let blur_task_h = RenderTask {
             id: RenderTaskId::Dynamic(RenderTaskKey::HorizontalBlur(blur_radius.0, prim_index)),
             children: vec![blur_task_v],
             location: RenderTaskLocation::Dynamic(None, blur_target_size),
-            kind: RenderTaskKind::HorizontalBlur(blur_radius, prim_index),
+            kind: RenderTaskKind::HorizontalBlur(radius, blur_key_v),
         };
 
         blur_task_h
@@ -311,6 +365,7 @@ impl RenderTask {
             RenderTaskKind::CachePrimitive(..) |
             RenderTaskKind::CacheMask(..) |
             RenderTaskKind::VerticalBlur(..) |
+            RenderTaskKind::Downscale(..) |
             RenderTaskKind::Readback(..) |
             RenderTaskKind::HorizontalBlur(..) => unreachable!(),
         }
@@ -349,7 +404,8 @@ impl RenderTask {
                     ],
                 }
             }
-            RenderTaskKind::CachePrimitive(..) => {
+            RenderTaskKind::CachePrimitive(..) |
+            RenderTaskKind::Downscale(..) => {
                 RenderTaskData {
                     data: [
                         target_rect.origin.x as f32,
@@ -437,26 +493,6 @@ impl RenderTask {
         }
     }
 
-    pub fn assign_to_passes(mut self, pass_index: usize, passes: &mut Vec<RenderPass>) {
-        for child in self.children.drain(..) {
-            child.assign_to_passes(pass_index - 1,
-                                   passes);
-        }
-
-        // Sanity check - can be relaxed if needed
-        match self.location {
-            RenderTaskLocation::Fixed => {
-                debug_assert!(pass_index == passes.len() - 1);
-            }
-            RenderTaskLocation::Dynamic(..) => {
-                debug_assert!(pass_index < passes.len() - 1);
-            }
-        }
-
-        let pass = &mut passes[pass_index];
-        pass.add_render_task(self);
-    }
-
     pub fn max_depth(&self, depth: usize, max_depth: &mut usize) {
         let depth = depth + 1;
         *max_depth = cmp::max(*max_depth, depth);
@@ -470,9 +506,122 @@ impl RenderTask {
             RenderTaskKind::Alpha(..) |
             RenderTaskKind::CachePrimitive(..) |
             RenderTaskKind::VerticalBlur(..) |
+            RenderTaskKind::Downscale(..) |
             RenderTaskKind::Readback(..) |
             RenderTaskKind::HorizontalBlur(..) => RenderTargetKind::Color,
             RenderTaskKind::CacheMask(..) => RenderTargetKind::Alpha,
         }
     }
 }
+
+struct RenderTaskNode {
+    // Moved into its pass by `assign_to_passes`.
+    task: Option<RenderTask>,
+    pass_index: usize,
+    target_kind: RenderTargetKind,
+    // The task that reads the output of this one.
+    parent: Option<usize>,
+}
+
+/// The render tasks of a frame, flattened into a dependency graph.
+///
+/// Each task is scheduled in the pass right before the task that reads its
+/// output, so its output only has to stay alive until that pass. Passes
+/// whose outputs are never alive at the same time share a render target
+/// texture, which is tracked as a texture slot on each pass.
+pub struct RenderTaskGraph {
+    nodes: Vec<RenderTaskNode>,
+    pass_count: usize,
+}
+
+impl RenderTaskGraph {
+    pub fn new(root: RenderTask) -> RenderTaskGraph {
+        let mut pass_count = 0;
+        root.max_depth(0, &mut pass_count);
+
+        let mut graph = RenderTaskGraph {
+            nodes: Vec::new(),
+            pass_count: pass_count,
+        };
+        graph.add_task(root, None, pass_count - 1);
+        graph
+    }
+
+    fn add_task(&mut self, mut task: RenderTask, parent: Option<usize>, pass_index: usize) {
+        // Sanity check - can be relaxed if needed
+        match task.location {
+            RenderTaskLocation::Fixed => {
+                debug_assert!(pass_index == self.pass_count - 1);
+            }
+            RenderTaskLocation::Dynamic(..) => {
+                debug_assert!(pass_index < self.pass_count - 1);
+            }
+        }
+
+        let index = self.nodes.len();
+        let children = mem::replace(&mut task.children, Vec::new());
+        self.nodes.push(RenderTaskNode {
+            target_kind: task.target_kind(),
+            task: Some(task),
+            pass_index: pass_index,
+            parent: parent,
+        });
+
+        for child in children {
+            self.add_task(child, Some(index), pass_index - 1);
+        }
+    }
+
+    pub fn pass_count(&self) -> usize {
+        self.pass_count
+    }
+
+    pub fn assign_to_passes(&mut self, passes: &mut [RenderPass]) {
+        for node in &mut self.nodes {
+            let task = node.task.take().expect("Render task was already assigned!");
+            passes[node.pass_index].add_render_task(task);
+        }
+    }
+
+    // The last pass that reads the targets of the given kind drawn in a pass.
+    fn last_read_pass(&self, pass_index: usize, kind: RenderTargetKind) -> usize {
+        self.nodes.iter()
+                  .filter(|node| node.pass_index == pass_index && node.target_kind == kind)
+                  .filter_map(|node| node.parent)
+                  .map(|parent| self.nodes[parent].pass_index)
+                  .max()
+                  .unwrap_or(pass_index)
+    }
+
+    /// Gives each pass that draws to render targets a texture slot per target
+    /// kind. A slot is reused as soon as the last pass reading it is done, so
+    /// the number of textures follows the overlap of target lifetimes rather
+    /// than the number of passes. Must be called once the passes are built.
+    pub fn assign_texture_slots(&self, passes: &mut [RenderPass]) {
+        for &kind in &[RenderTargetKind::Color, RenderTargetKind::Alpha] {
+            // The last pass that reads each slot.
+            let mut slot_last_reads: Vec<usize> = Vec::new();
+
+            for (pass_index, pass) in passes.iter_mut().enumerate() {
+                if !pass.needs_render_target_kind(kind) {
+                    continue;
+                }
+
+                let last_read = self.last_read_pass(pass_index, kind);
+                let free_slot = slot_last_reads.iter().position(|&last| last < pass_index);
+                let slot = match free_slot {
+                    Some(slot) => {
+                        slot_last_reads[slot] = last_read;
+                        slot
+                    }
+                    None => {
+                        slot_last_reads.push(last_read);
+                        slot_last_reads.len() - 1
+                    }
+                };
+
+                pass.set_texture_slot(kind, slot);
+            }
+        }
+    }
+}
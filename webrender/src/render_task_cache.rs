@@ -0,0 +1,223 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Keeps what some render tasks drew in a texture of the renderer, so that
+//! the following frames that need the same task can copy it from there
+//! rather than drawing it again. A task is first drawn as usual and saved,
+//! and is then restored for as long as every frame asks for it.
+//!
+//! The renderer only draws the most recent frame it has been sent, so a
+//! save is only trusted once the renderer reports having drawn the frame
+//! that made it.
+
+use fnv::FnvHasher;
+use frame::FrameId;
+use internal_types::CacheTextureId;
+use prim_store::PrimitiveCacheKey;
+use render_task::{RenderTask, RenderTaskId, RenderTaskKey, RenderTaskLocation};
+use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use texture_cache::TexturePage;
+use webrender_traits::{DeviceIntPoint, DeviceIntRect, DeviceIntSize, DeviceUintRect, DeviceUintSize};
+
+/// The width and height of the texture that render tasks are kept in.
+pub const RENDER_TASK_CACHE_SIZE: u32 = 1024;
+
+enum EntryState {
+    /// Saved by these frames, none of which the renderer has reported
+    /// drawing yet.
+    Pending(Vec<usize>),
+    /// In the texture.
+    Valid,
+}
+
+struct RenderTaskCacheEntry {
+    rect: DeviceUintRect,
+    state: EntryState,
+    /// Whether the frame being built uses the entry.
+    used: bool,
+}
+
+pub struct RenderTaskCache {
+    page: TexturePage,
+    entries: HashMap<RenderTaskKey, RenderTaskCacheEntry, BuildHasherDefault<FnvHasher>>,
+    /// The number of frames built so far.
+    frame_index: usize,
+    /// The frame the renderer last drew, stored by the renderer.
+    rendered_frame_index: Arc<AtomicUsize>,
+    /// The scene and device pixel ratio that the entries were drawn for.
+    /// Text shadows are keyed by primitive index, which only identifies
+    /// a primitive within a scene.
+    scene_id: Option<FrameId>,
+    device_pixel_ratio: f32,
+    /// Where the tasks of the frame being built are copied from.
+    restores: HashMap<RenderTaskKey, DeviceIntRect, BuildHasherDefault<FnvHasher>>,
+    /// Where the tasks of the frame being built are copied to.
+    saves: HashMap<RenderTaskKey, DeviceIntRect, BuildHasherDefault<FnvHasher>>,
+}
+
+impl RenderTaskCache {
+    pub fn new(rendered_frame_index: Arc<AtomicUsize>) -> RenderTaskCache {
+        let size = DeviceUintSize::new(RENDER_TASK_CACHE_SIZE, RENDER_TASK_CACHE_SIZE);
+        RenderTaskCache {
+            page: TexturePage::new(CacheTextureId(0), size),
+            entries: HashMap::with_hasher(Default::default()),
+            frame_index: 0,
+            rendered_frame_index: rendered_frame_index,
+            scene_id: None,
+            device_pixel_ratio: 0.0,
+            restores: HashMap::with_hasher(Default::default()),
+            saves: HashMap::with_hasher(Default::default()),
+        }
+    }
+
+    /// Starts a frame of `scene_id`, and returns its index, which the
+    /// renderer reports back once it has drawn the frame.
+    pub fn begin_frame(&mut self, scene_id: FrameId, device_pixel_ratio: f32) -> usize {
+        self.frame_index += 1;
+        self.restores.clear();
+        self.saves.clear();
+
+        if self.scene_id != Some(scene_id) || self.device_pixel_ratio != device_pixel_ratio {
+            self.entries.clear();
+            self.page.clear();
+            self.scene_id = Some(scene_id);
+            self.device_pixel_ratio = device_pixel_ratio;
+            return self.frame_index;
+        }
+
+        // A pending entry is in the texture if the renderer drew one of the
+        // frames that saved it. Once it has drawn a later frame instead,
+        // the saves may have been skipped.
+        let rendered_frame_index = self.rendered_frame_index.load(Ordering::Acquire);
+        let mut evicted = Vec::new();
+        for (key, entry) in &mut self.entries {
+            let drawn = match entry.state {
+                EntryState::Pending(ref frames) if frames.contains(&rendered_frame_index) => Some(true),
+                EntryState::Pending(ref frames) if frames.iter().all(|&frame| frame < rendered_frame_index) => Some(false),
+                EntryState::Pending(..) |
+                EntryState::Valid => None,
+            };
+            match drawn {
+                Some(true) => entry.state = EntryState::Valid,
+                Some(false) => evicted.push(*key),
+                None => {}
+            }
+
+            // Only tasks that consecutive frames need are kept.
+            if !entry.used {
+                evicted.push(*key);
+            }
+            entry.used = false;
+        }
+
+        for key in evicted {
+            if let Some(entry) = self.entries.remove(&key) {
+                self.page.free(&entry.rect);
+            }
+        }
+
+        self.frame_index
+    }
+
+    /// Returns the task to draw for `task` in the frame being built, which
+    /// is a copy without children if the cache has what it draws.
+    pub fn request(&mut self, task: RenderTask) -> RenderTask {
+        let cacheable = match (task.id, &task.location) {
+            (RenderTaskId::Dynamic(key), &RenderTaskLocation::Dynamic(None, size)) => {
+                if is_cacheable(&key) && size.width > 0 && size.height > 0 {
+                    Some((key, DeviceUintSize::new(size.width as u32, size.height as u32)))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        let (key, size) = match cacheable {
+            Some(cacheable) => cacheable,
+            None => return task,
+        };
+
+        let frame_index = self.frame_index;
+        let mut resized = false;
+        if let Some(entry) = self.entries.get_mut(&key) {
+            if entry.rect.size == size {
+                entry.used = true;
+                let rect = to_device_int_rect(&entry.rect);
+                match entry.state {
+                    EntryState::Valid => {
+                        self.restores.insert(key, rect);
+                        return RenderTask {
+                            children: Vec::new(),
+                            ..task
+                        };
+                    }
+                    EntryState::Pending(ref mut frames) => {
+                        if !frames.contains(&frame_index) {
+                            frames.push(frame_index);
+                        }
+                        self.saves.insert(key, rect);
+                        return task;
+                    }
+                }
+            }
+            resized = true;
+        }
+        if resized {
+            let entry = self.entries.remove(&key).unwrap();
+            self.page.free(&entry.rect);
+        }
+
+        let origin = match self.page.allocate(&size) {
+            Some(origin) => Some(origin),
+            None if self.page.coalesce(false) => self.page.allocate(&size),
+            None => None,
+        };
+        if let Some(origin) = origin {
+            let rect = DeviceUintRect::new(origin, size);
+            self.saves.insert(key, to_device_int_rect(&rect));
+            self.entries.insert(key, RenderTaskCacheEntry {
+                rect: rect,
+                state: EntryState::Pending(vec![frame_index]),
+                used: true,
+            });
+        }
+        task
+    }
+
+    /// The rect of the cache texture that the task is copied from, if the
+    /// frame being built restores it.
+    pub fn restore_rect(&self, key: &RenderTaskKey) -> Option<DeviceIntRect> {
+        self.restores.get(key).cloned()
+    }
+
+    /// The rect of the cache texture that the task is copied to once it is
+    /// drawn, if the frame being built saves it.
+    pub fn save_rect(&self, key: &RenderTaskKey) -> Option<DeviceIntRect> {
+        self.saves.get(key).cloned()
+    }
+}
+
+// Box shadows and text shadows are drawn untransformed, so what they draw
+// only depends on the primitive. Masks are drawn in screen space, and the
+// text runs, downscales and vertical blurs of text shadows are only read
+// by the next task of the chain.
+fn is_cacheable(key: &RenderTaskKey) -> bool {
+    match *key {
+        RenderTaskKey::CachePrimitive(PrimitiveCacheKey::BoxShadow(..)) |
+        RenderTaskKey::HorizontalBlur(..) => true,
+        RenderTaskKey::CachePrimitive(PrimitiveCacheKey::TextShadow(..)) |
+        RenderTaskKey::CacheMask(..) |
+        RenderTaskKey::VerticalBlur(..) |
+        RenderTaskKey::Downscale(..) |
+        RenderTaskKey::CopyFramebuffer(..) => false,
+    }
+}
+
+fn to_device_int_rect(rect: &DeviceUintRect) -> DeviceIntRect {
+    DeviceIntRect::new(DeviceIntPoint::new(rect.origin.x as i32, rect.origin.y as i32),
+                       DeviceIntSize::new(rect.size.width as i32, rect.size.height as i32))
+}
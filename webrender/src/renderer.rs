@@ -13,51 +13,80 @@ use debug_colors;
 use debug_render::DebugRenderer;
 use device::{DepthFunction, Device, FrameId, ProgramId, TextureId, VertexFormat, GpuMarker, GpuProfiler};
 use device::{GpuSample, TextureFilter, VAOId, VertexUsageHint, FileWatcherHandler, TextureTarget, ShaderError};
+use device::{GpuFence, PendingReadback, ReadbackStatus, SavedTextureFilter, UBOId, UniformLocation};
+use driver_workarounds::{BufferUpdateStrategy, DriverWorkarounds, Workaround};
 use euclid::Matrix4D;
 use fnv::FnvHasher;
 use frame_builder::FrameBuilderConfig;
 use gleam::gl;
-use gpu_store::{GpuStore, GpuStoreLayout};
+use gpu_cache::{GPU_CACHE_WIDTH, GpuBlockData, GpuCacheUpdateList};
+use gpu_store::{GpuStore, GpuStoreAddress, GpuStoreLayout};
 use internal_types::{CacheTextureId, RendererFrame, ResultMsg, TextureUpdateOp};
 use internal_types::{ExternalImageUpdateList, TextureUpdateList, PackedVertex, RenderTargetMode};
 use internal_types::{ORTHO_NEAR_PLANE, ORTHO_FAR_PLANE, SourceTexture};
-use internal_types::{BatchTextures, TextureSampler};
-use prim_store::GradientData;
+use internal_types::{BatchTextures, TextureSampler, DebugFontVertex, PackedColor};
+use internal_types::memory_pressure_reached;
+use prim_store::{GRADIENT_DATA_RESOLUTION, GradientData};
+use pipeline_stats::{PipelineStatistics, StatisticsDump, StatisticsFormat};
+use resource_cache::texture_filter_for_rendering;
 use profiler::{Profiler, BackendProfileCounters};
 use profiler::{GpuProfileTag, RendererProfileTimers, RendererProfileCounters};
 use record::ApiRecordingReceiver;
 use render_backend::RenderBackend;
-use render_task::RenderTaskData;
+use render_task::{MAX_BLUR_RADIUS, RenderTaskData};
+use render_task_cache::RENDER_TASK_CACHE_SIZE;
 use std;
+use std::cell::RefCell;
 use std::cmp;
 use std::collections::{HashMap, VecDeque};
 use std::f32;
+use std::fmt;
+use std::fs;
 use std::hash::BuildHasherDefault;
+use std::io;
 use std::marker::PhantomData;
 use std::mem;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::slice;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread;
-use texture_cache::TextureCache;
+use texture_cache::{TextureAllocatorConfig, TextureCache};
 use threadpool::ThreadPool;
-use tiling::{AlphaBatchKind, BlurCommand, Frame, PrimitiveBatch, PrimitiveBatchData, RenderTarget};
+use tiling::{AlphaBatchKind, BlurCommand, BlurDirection, Frame, PrimitiveBatch, PrimitiveBatchData};
 use tiling::{AlphaRenderTarget, CacheClipInstance, PrimitiveInstance, ColorRenderTarget, RenderTargetKind};
+use tiling::{CompositorSurface, DamageItem, PackedLayer, RenderPass, RenderTarget};
 use time::precise_time_ns;
 use thread_profiler::{register_thread_with_profiler, write_profile};
-use util::TransformedRectKind;
+use util::{TransformedRectKind, write_pam};
 use webgl_types::GLContextHandleWrapper;
-use webrender_traits::{ColorF, Epoch, PipelineId, RenderNotifier, RenderDispatcher};
-use webrender_traits::{ExternalImageId, ExternalImageType, ImageData, ImageFormat, RenderApiSender};
+use webrender_traits::{ApiMsg, ColorF, ColorSpace, DebugCommand, Epoch, FrameToken, PipelineId};
+use webrender_traits::{BackendQuery, BackendQueryId, BackendQueryResponse, RenderNotifier, RenderDispatcher};
+use webrender_traits::{ExternalImageData, ExternalImageId, ExternalImageType, ImageData, ImageFormat};
+use webrender_traits::{RenderApiSender, register_synchronous_backend};
 use webrender_traits::{DeviceIntRect, DevicePoint, DeviceIntPoint, DeviceIntSize, DeviceUintSize};
-use webrender_traits::{ImageDescriptor, BlobImageRenderer};
-use webrender_traits::channel;
+use webrender_traits::{DeviceUintPoint, DeviceUintRect, SubpixelQuantization};
+use webrender_traits::{ImageDescriptor, ImageKey, BlobImageRenderer, MemoryPressure, SnapshotId};
+use webrender_traits::WebGLContextId;
+use webrender_traits::channel::{self, MsgSender};
 use webrender_traits::VRCompositorHandler;
+use webrender_traits::{LayoutTransform, PropertyBindingId, PropertyBindingKey};
 
 pub const GPU_DATA_TEXTURE_POOL: usize = 5;
 pub const MAX_VERTEX_TEXTURE_WIDTH: usize = 1024;
 
+/// Damage is merged down to at most this many rects.
+const MAX_DAMAGE_RECTS: usize = 4;
+/// Back buffers drawn longer ago than this many frames are redrawn in full.
+const MAX_BUFFER_AGE: u32 = 4;
+/// The smallest width or height of the render targets of cache passes.
+const MIN_RENDER_TARGET_SIZE: u32 = 256;
+/// Past this many changed items, the damage is merged into a single rect
+/// right away, rather than searching for the best rects to merge.
+const MAX_DAMAGE_RECTS_TO_MERGE: usize = 64;
+
 const GPU_TAG_CACHE_BOX_SHADOW: GpuProfileTag = GpuProfileTag { label: "C_BoxShadow", color: debug_colors::BLACK };
 const GPU_TAG_CACHE_CLIP: GpuProfileTag = GpuProfileTag { label: "C_Clip", color: debug_colors::PURPLE };
 const GPU_TAG_CACHE_TEXT_RUN: GpuProfileTag = GpuProfileTag { label: "C_TextRun", color: debug_colors::MISTYROSE };
@@ -66,7 +95,9 @@ const GPU_TAG_SETUP_TARGET: GpuProfileTag = GpuProfileTag { label: "Target", col
 const GPU_TAG_PRIM_RECT: GpuProfileTag = GpuProfileTag { label: "Rect", color: debug_colors::RED };
 const GPU_TAG_PRIM_IMAGE: GpuProfileTag = GpuProfileTag { label: "Image", color: debug_colors::GREEN };
 const GPU_TAG_PRIM_IMAGE_RECT: GpuProfileTag = GpuProfileTag { label: "ImageRect", color: debug_colors::GREENYELLOW };
+const GPU_TAG_PRIM_ALPHA_IMAGE: GpuProfileTag = GpuProfileTag { label: "AlphaImage", color: debug_colors::LIMEGREEN };
 const GPU_TAG_PRIM_YUV_IMAGE: GpuProfileTag = GpuProfileTag { label: "YuvImage", color: debug_colors::DARKGREEN };
+const GPU_TAG_PRIM_YUV_IMAGE_RECT: GpuProfileTag = GpuProfileTag { label: "YuvImageRect", color: debug_colors::FORESTGREEN };
 const GPU_TAG_PRIM_BLEND: GpuProfileTag = GpuProfileTag { label: "Blend", color: debug_colors::LIGHTBLUE };
 const GPU_TAG_PRIM_HW_COMPOSITE: GpuProfileTag = GpuProfileTag { label: "HwComposite", color: debug_colors::DODGERBLUE };
 const GPU_TAG_PRIM_COMPOSITE: GpuProfileTag = GpuProfileTag { label: "Composite", color: debug_colors::MAGENTA };
@@ -77,7 +108,24 @@ const GPU_TAG_PRIM_RADIAL_GRADIENT: GpuProfileTag = GpuProfileTag { label: "Radi
 const GPU_TAG_PRIM_BOX_SHADOW: GpuProfileTag = GpuProfileTag { label: "BoxShadow", color: debug_colors::CYAN };
 const GPU_TAG_PRIM_BORDER: GpuProfileTag = GpuProfileTag { label: "Border", color: debug_colors::ORANGE };
 const GPU_TAG_PRIM_CACHE_IMAGE: GpuProfileTag = GpuProfileTag { label: "CacheImage", color: debug_colors::SILVER };
+const GPU_TAG_BLIT: GpuProfileTag = GpuProfileTag { label: "Blit", color: debug_colors::DARKSEAGREEN };
 const GPU_TAG_BLUR: GpuProfileTag = GpuProfileTag { label: "Blur", color: debug_colors::VIOLET };
+const GPU_TAG_OUTPUT: GpuProfileTag = GpuProfileTag { label: "Output", color: debug_colors::GOLD };
+const GPU_TAG_POST_PROCESS: GpuProfileTag = GpuProfileTag { label: "PostProcess", color: debug_colors::ORCHID };
+
+bitflags! {
+    /// Debugging aids that can be switched on and off while running, see
+    /// `Renderer::set_debug_flags`.
+    pub flags DebugFlags: u32 {
+        /// Draws the intermediate render targets along the top of the
+        /// framebuffer.
+        const RENDER_TARGET_DBG  = 1 << 0,
+        /// Saves each layer of the intermediate render targets of every
+        /// frame, and the finished frame, as PAM images, see
+        /// `Renderer::set_render_target_dump_path`.
+        const RENDER_TARGET_DUMP = 1 << 1,
+    }
+}
 
 #[derive(Debug, Copy, Clone)]
 pub enum RendererKind {
@@ -85,6 +133,318 @@ pub enum RendererKind {
     OSMesa,
 }
 
+/// How primitives are assigned z values for the depth test that lets
+/// opaque primitives occlude whatever is painted below them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ZAllocation {
+    /// Every item gets its own z value, in paint order.
+    PerItem,
+    /// Consecutive translucent items share a z value, since they never
+    /// write depth. Only opaque items need a z value of their own, which
+    /// stretches the depth range a lot further for scenes with many
+    /// translucent items.
+    Compact,
+}
+
+/// How the edges of transformed primitives are antialiased.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EdgeAaMode {
+    /// Approximate the coverage from the local-space distance to the edge.
+    /// Cheap, but edges get blurry when scaled up and alias when scaled
+    /// down or seen at glancing angles.
+    Fast,
+    /// Compute the coverage from the distance to each edge in device
+    /// pixels. A few more instructions per fragment, but edges stay one
+    /// pixel wide at any scale, and rotations by multiples of 90 degrees
+    /// are as crisp as axis-aligned content.
+    HighQuality,
+}
+
+/// How the frame is rotated onto the framebuffer, clockwise, for displays
+/// that are mounted rotated. With a rotation by 90 or 270 degrees the frame
+/// is laid out with the width and height of the framebuffer swapped.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SurfaceTransform {
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl SurfaceTransform {
+    /// The size of the framebuffer for a frame of `size`, or the other way
+    /// around.
+    pub fn transform_size(&self, size: DeviceUintSize) -> DeviceUintSize {
+        match *self {
+            SurfaceTransform::Identity |
+            SurfaceTransform::Rotate180 => size,
+            SurfaceTransform::Rotate90 |
+            SurfaceTransform::Rotate270 => DeviceUintSize::new(size.height, size.width),
+        }
+    }
+
+    /// Maps `rect` of a frame of `size` to where it ends up in the
+    /// framebuffer. Both have the origin at the top left.
+    pub fn transform_rect(&self, rect: &DeviceIntRect, size: DeviceUintSize) -> DeviceIntRect {
+        let (width, height) = (size.width as i32, size.height as i32);
+        match *self {
+            SurfaceTransform::Identity => *rect,
+            SurfaceTransform::Rotate90 => {
+                DeviceIntRect::new(DeviceIntPoint::new(height - rect.max_y(), rect.origin.x),
+                                   DeviceIntSize::new(rect.size.height, rect.size.width))
+            }
+            SurfaceTransform::Rotate180 => {
+                DeviceIntRect::new(DeviceIntPoint::new(width - rect.max_x(), height - rect.max_y()),
+                                   rect.size)
+            }
+            SurfaceTransform::Rotate270 => {
+                DeviceIntRect::new(DeviceIntPoint::new(rect.origin.y, width - rect.max_x()),
+                                   DeviceIntSize::new(rect.size.height, rect.size.width))
+            }
+        }
+    }
+
+    /// The transform from a frame of `size` to the framebuffer, applied
+    /// before the projection.
+    fn to_matrix(&self, size: DeviceUintSize) -> Matrix4D<f32> {
+        let (width, height) = (size.width as f32, size.height as f32);
+        match *self {
+            SurfaceTransform::Identity => Matrix4D::identity(),
+            SurfaceTransform::Rotate90 => Matrix4D::row_major(0.0, 1.0, 0.0, 0.0,
+                                                              -1.0, 0.0, 0.0, 0.0,
+                                                              0.0, 0.0, 1.0, 0.0,
+                                                              height, 0.0, 0.0, 1.0),
+            SurfaceTransform::Rotate180 => Matrix4D::row_major(-1.0, 0.0, 0.0, 0.0,
+                                                               0.0, -1.0, 0.0, 0.0,
+                                                               0.0, 0.0, 1.0, 0.0,
+                                                               width, height, 0.0, 1.0),
+            SurfaceTransform::Rotate270 => Matrix4D::row_major(0.0, -1.0, 0.0, 0.0,
+                                                               1.0, 0.0, 0.0, 0.0,
+                                                               0.0, 0.0, 1.0, 0.0,
+                                                               0.0, width, 0.0, 1.0),
+        }
+    }
+
+    /// Rotates RGBA8 pixels read from the framebuffer, with rows from top
+    /// to bottom, back into the orientation of a frame `size` large.
+    fn untransform_pixels(&self, pixels: Vec<u8>, size: DeviceUintSize) -> Vec<u8> {
+        if *self == SurfaceTransform::Identity {
+            return pixels;
+        }
+
+        let (width, height) = (size.width as usize, size.height as usize);
+        let surface_width = self.transform_size(size).width as usize;
+        let mut result = Vec::with_capacity(pixels.len());
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = match *self {
+                    SurfaceTransform::Identity => (x, y),
+                    SurfaceTransform::Rotate90 => (height - 1 - y, x),
+                    SurfaceTransform::Rotate180 => (width - 1 - x, height - 1 - y),
+                    SurfaceTransform::Rotate270 => (y, width - 1 - x),
+                };
+                let offset = (sy * surface_width + sx) * 4;
+                result.extend_from_slice(&pixels[offset .. offset + 4]);
+            }
+        }
+        result
+    }
+}
+
+/// Where the first row of a render target's pixels is shown. GL's default
+/// framebuffer starts at the bottom, while D3D render targets, and FBOs
+/// that wrap them, start at the top.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SurfaceOrigin {
+    BottomLeft,
+    TopLeft,
+}
+
+impl SurfaceOrigin {
+    /// Converts `rect`, with the origin at the top left of a surface
+    /// `height` pixels tall, to GL window coordinates.
+    fn to_gl_rect(&self, rect: &DeviceIntRect, height: i32) -> DeviceIntRect {
+        match *self {
+            SurfaceOrigin::BottomLeft => {
+                DeviceIntRect::new(DeviceIntPoint::new(rect.origin.x, height - rect.max_y()),
+                                   rect.size)
+            }
+            SurfaceOrigin::TopLeft => *rect,
+        }
+    }
+}
+
+/// A conversion from the sRGB that frames are drawn in to the color space
+/// of the display, applied to the whole frame as the last step of drawing
+/// it, see `Renderer::set_output_color_transform`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputColorTransform {
+    /// Decodes sRGB to linear light, maps it to the linear RGB of the
+    /// display with `matrix`, given in column major order, and encodes the
+    /// result with a power curve of `gamma`.
+    Matrix {
+        matrix: [f32; 9],
+        gamma: f32,
+    },
+    /// A table of `size` entries along each axis, from 2 to 64, that maps
+    /// sRGB colors to display colors. `data` holds an RGB8 entry for each,
+    /// with red changing fastest and blue slowest. Colors between entries
+    /// are interpolated.
+    Lut {
+        size: u32,
+        data: Vec<u8>,
+    },
+}
+
+impl OutputColorTransform {
+    fn is_valid(&self) -> bool {
+        match *self {
+            OutputColorTransform::Matrix { ref matrix, gamma } => {
+                matrix.iter().all(|value| value.is_finite()) &&
+                gamma.is_finite() && gamma > 0.0
+            }
+            OutputColorTransform::Lut { size, ref data } => {
+                size >= 2 && size <= 64 &&
+                data.len() == (size * size * size * 3) as usize
+            }
+        }
+    }
+}
+
+/// The encoding of an HDR swapchain, see `HdrOutput`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HdrOutputFormat {
+    /// Linear light with sRGB primaries, where 1.0 is 80 nits, for an
+    /// RGBA16F swapchain.
+    ScRgbLinear,
+    /// PQ encoded light with BT.2020 primaries, for an RGB10A2 swapchain.
+    Hdr10,
+}
+
+impl HdrOutputFormat {
+    /// The matrix, in column major order, from linear RGB in `color_space`
+    /// to linear RGB with the primaries of the format.
+    fn primaries_matrix(&self, color_space: ColorSpace) -> [f32; 9] {
+        match (*self, color_space) {
+            (HdrOutputFormat::ScRgbLinear, ColorSpace::Srgb) => {
+                [1.0, 0.0, 0.0,
+                 0.0, 1.0, 0.0,
+                 0.0, 0.0, 1.0]
+            }
+            (HdrOutputFormat::ScRgbLinear, ColorSpace::DisplayP3) => {
+                [1.2249401, -0.0420569, -0.0196376,
+                 -0.2249404, 1.0420571, -0.0786361,
+                 0.0, 0.0, 1.0982735]
+            }
+            (HdrOutputFormat::Hdr10, ColorSpace::Srgb) => {
+                [0.6274040, 0.0690970, 0.0163916,
+                 0.3292820, 0.9195400, 0.0880132,
+                 0.0433136, 0.0113612, 0.8955950]
+            }
+            (HdrOutputFormat::Hdr10, ColorSpace::DisplayP3) => {
+                [0.7538330, 0.0457438, -0.0012103,
+                 0.1985974, 0.9417772, 0.0176017,
+                 0.0475696, 0.0124789, 0.9836086]
+            }
+        }
+    }
+}
+
+/// Draws frames for an HDR swapchain, which the embedder creates in the
+/// matching format. Content is mapped so that 1.0 in the frame, the white
+/// of SDR content, shows at `sdr_white_level`. The frame is drawn into a
+/// floating point texture first, so HDR external images that sample above
+/// 1.0 keep their range, and light above a knee below `max_luminance` is
+/// rolled off to fit the display instead of clipping.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HdrOutput {
+    pub format: HdrOutputFormat,
+    /// The luminance of SDR white, in nits.
+    pub sdr_white_level: f32,
+    /// The peak luminance of the display, in nits.
+    pub max_luminance: f32,
+}
+
+impl HdrOutput {
+    fn is_valid(&self) -> bool {
+        self.sdr_white_level.is_finite() && self.sdr_white_level > 0.0 &&
+        self.max_luminance.is_finite() && self.max_luminance > 0.0
+    }
+
+    /// Light above this is compressed, it's kept above SDR white so that
+    /// UI isn't dimmed.
+    fn tone_map_knee(&self) -> f32 {
+        (0.75 * self.max_luminance).max(self.sdr_white_level).min(self.max_luminance)
+    }
+}
+
+/// What webrender releases while `RenderApi::set_memory_pressure` reports
+/// memory to be short. Each action is taken from the given level up, or
+/// never when it's `None`.
+#[derive(Debug, Copy, Clone)]
+pub struct MemoryPressurePolicy {
+    /// Texture cache pages that no longer hold any items are deleted,
+    /// rather than kept for later items. This happens when the level
+    /// changes, and after frames that free cached items.
+    pub free_empty_cache_pages: Option<MemoryPressure>,
+    /// Cached glyph metrics are dropped when the level changes, and after
+    /// frames that free cached items. They are looked up again when needed.
+    pub drop_glyph_dimensions: Option<MemoryPressure>,
+    /// Render targets the last frame didn't use are deleted.
+    pub trim_render_targets: Option<MemoryPressure>,
+    /// All render targets are deleted after each frame, and created again
+    /// for the next one.
+    pub release_render_targets: Option<MemoryPressure>,
+}
+
+impl Default for MemoryPressurePolicy {
+    fn default() -> MemoryPressurePolicy {
+        MemoryPressurePolicy {
+            free_empty_cache_pages: Some(MemoryPressure::Moderate),
+            drop_glyph_dimensions: Some(MemoryPressure::Moderate),
+            trim_render_targets: Some(MemoryPressure::Moderate),
+            release_render_targets: Some(MemoryPressure::Critical),
+        }
+    }
+}
+
+/// The look of the scrollbars drawn when `enable_scrollbars` is set.
+/// Lengths are in layer pixels.
+#[derive(Debug, Copy, Clone)]
+pub struct ScrollbarTheme {
+    pub thumb_color: ColorF,
+    pub track_color: ColorF,
+    /// The thickness of a scrollbar.
+    pub width: f32,
+    /// The gap between a scrollbar and the edges of the viewport.
+    pub margin: f32,
+    pub border_radius: f32,
+    /// Thumbs are never shorter than this, however long the content is.
+    pub min_thumb_length: f32,
+    /// When set, scrollbars stay visible for `fade_delay_ms` after the last
+    /// scroll and then fade out over `fade_duration_ms`. The fade is only
+    /// seen if the embedder keeps generating frames while it runs.
+    pub fade_out: bool,
+    pub fade_delay_ms: u32,
+    pub fade_duration_ms: u32,
+}
+
+impl Default for ScrollbarTheme {
+    fn default() -> ScrollbarTheme {
+        ScrollbarTheme {
+            thumb_color: ColorF::new(0.3, 0.3, 0.3, 0.6),
+            track_color: ColorF::new(0.0, 0.0, 0.0, 0.0),
+            width: 10.0,
+            margin: 8.0,
+            border_radius: 4.0,
+            min_thumb_length: 20.0,
+            fade_out: false,
+            fade_delay_ms: 1000,
+            fade_duration_ms: 300,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct GpuProfile {
     pub frame_id: FrameId,
@@ -133,8 +493,13 @@ pub enum BlendMode {
     Subpixel(ColorF),
 }
 
+/// A texture of per-frame data. The contents of the previous upload are
+/// kept on the CPU, so that only the rows that changed are uploaded again.
 struct GpuDataTexture<L> {
     id: TextureId,
+    // What the texture holds, and its height in rows.
+    contents: Vec<u8>,
+    rows: usize,
     layout: PhantomData<L>,
 }
 
@@ -144,6 +509,8 @@ impl<L: GpuStoreLayout> GpuDataTexture<L> {
 
         GpuDataTexture {
             id: id,
+            contents: Vec::new(),
+            rows: 0,
             layout: PhantomData,
         }
     }
@@ -173,13 +540,55 @@ impl<L: GpuStoreLayout> GpuDataTexture<L> {
             data.len() * rows_per_item
         };
 
-        device.init_texture(self.id,
-                            L::texture_width::<T>() as u32,
-                            height as u32,
-                            L::image_format(),
-                            L::texture_filter(),
-                            RenderTargetMode::None,
-                            Some(unsafe { mem::transmute(data.as_slice()) } ));
+        let bytes = unsafe {
+            slice::from_raw_parts(data.as_ptr() as *const u8,
+                                  data.len() * mem::size_of::<T>())
+        };
+        let width = L::texture_width::<T>();
+
+        // The texture only needs new storage when it grows. Rows past the
+        // end of the data are left as they are, since nothing reads them.
+        if height > self.rows {
+            device.init_texture(self.id,
+                                width as u32,
+                                height as u32,
+                                L::image_format(),
+                                L::texture_filter(),
+                                RenderTargetMode::None,
+                                Some(bytes));
+            self.contents.clear();
+            self.contents.extend_from_slice(bytes);
+            self.rows = height;
+            return;
+        }
+
+        let row_size = width * L::texel_size();
+        let mut row = 0;
+        while row < height {
+            if self.row(row, row_size) == &bytes[row * row_size..(row + 1) * row_size] {
+                row += 1;
+                continue;
+            }
+
+            let first_row = row;
+            while row < height && self.row(row, row_size) != &bytes[row * row_size..(row + 1) * row_size] {
+                row += 1;
+            }
+
+            let changed = &bytes[first_row * row_size..row * row_size];
+            device.update_texture(self.id,
+                                  0,
+                                  first_row as u32,
+                                  width as u32,
+                                  (row - first_row) as u32,
+                                  None,
+                                  changed);
+            self.contents[first_row * row_size..row * row_size].copy_from_slice(changed);
+        }
+    }
+
+    fn row(&self, row: usize, row_size: usize) -> &[u8] {
+        &self.contents[row * row_size..(row + 1) * row_size]
     }
 }
 
@@ -200,8 +609,10 @@ impl GpuStoreLayout for VertexDataTextureLayout {
 }
 
 type VertexDataTexture = GpuDataTexture<VertexDataTextureLayout>;
-pub type VertexDataStore<T> = GpuStore<T, VertexDataTextureLayout>;
 
+// Each gradient data table takes two rows, one for the high bytes of its
+// colors and one for the low bytes. Gradients with several tables use
+// consecutive ones.
 pub struct GradientDataTextureLayout {}
 
 impl GpuStoreLayout for GradientDataTextureLayout {
@@ -225,15 +636,80 @@ const TRANSFORM_FEATURE: &'static str = "TRANSFORM";
 const SUBPIXEL_AA_FEATURE: &'static str = "SUBPIXEL_AA";
 const CLIP_FEATURE: &'static str = "CLIP";
 const TEXTURE_RECT_FEATURE: &'static str = "TEXTURE_RECT";
+const ALPHA_MASK_FEATURE: &'static str = "ALPHA_MASK";
+const DITHERING_FEATURE: &'static str = "DITHERING";
+const HIGH_QUALITY_EDGE_AA_FEATURE: &'static str = "HIGH_QUALITY_EDGE_AA";
+const FAST_BLUR_FEATURE: &'static str = "FAST_BLUR";
+
+/// The texture cache page size limit of `RendererOptions::low_quality_profile`.
+const LOW_QUALITY_MAX_TEXTURE_SIZE: u32 = 2048;
+
+/// The texture cache page size limit of `RendererOptions::software_profile`.
+/// Software rasterizers spend time on every texel of a page they clear or
+/// upload, used or not.
+const SOFTWARE_MAX_TEXTURE_SIZE: u32 = 1024;
+
+/// How many of the latest alpha batches a primitive can join by default.
+const DEFAULT_BATCH_LOOKBACK_COUNT: usize = 10;
+
+/// The batch lookback of `RendererOptions::software_profile`. A software
+/// rasterizer pays much more for each draw call than for the batching
+/// work that saves it.
+const SOFTWARE_BATCH_LOOKBACK_COUNT: usize = 50;
+
+/// The number of pixels of a row or column that each work group of the
+/// compute blur shader blurs.
+const COMPUTE_BLUR_GROUP_SIZE: u32 = 128;
+
+const COLOR_LUT_FEATURE: &'static str = "COLOR_LUT";
+const HDR_OUTPUT_FEATURE: &'static str = "HDR_OUTPUT";
+const HDR10_FEATURE: &'static str = "HDR10";
+
+/// The binding points of the uniform blocks that hold the layers and
+/// render tasks in the UBO variants of the shaders.
+const LAYERS_UBO_BINDING: u32 = 0;
+const RENDER_TASKS_UBO_BINDING: u32 = 1;
+
+/// How many layers and render tasks fit in the uniform blocks of the UBO
+/// variants of the shaders, see `Capabilities::prefers_ubo_data`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct UboDataCapacity {
+    layers: usize,
+    render_tasks: usize,
+}
+
+impl UboDataCapacity {
+    fn new(max_ubo_size: usize) -> UboDataCapacity {
+        UboDataCapacity {
+            layers: get_ubo_max_len::<PackedLayer>(max_ubo_size),
+            render_tasks: get_ubo_max_len::<RenderTaskData>(max_ubo_size),
+        }
+    }
+
+    fn fits(&self, frame: &Frame) -> bool {
+        frame.layer_texture_data.len() <= self.layers &&
+        frame.render_task_data.len() <= self.render_tasks
+    }
+
+    fn prefix(&self) -> String {
+        format!("#define WR_MAX_UBO_LAYERS {}\n#define WR_MAX_UBO_RENDER_TASKS {}\n",
+                self.layers,
+                self.render_tasks)
+    }
+}
 
 enum ShaderKind {
     Primitive,
     Cache(VertexFormat),
     ClipCache,
+    Output,
 }
 
 struct LazilyCompiledShader {
     id: Option<ProgramId>,
+    // The variant that reads the layers and render tasks from uniform
+    // buffers. Output shaders don't read them, and have none.
+    ubo_id: Option<ProgramId>,
     name: &'static str,
     kind: ShaderKind,
     features: Vec<&'static str>,
@@ -247,43 +723,89 @@ impl LazilyCompiledShader {
            precache: bool) -> Result<LazilyCompiledShader, ShaderError> {
         let mut shader = LazilyCompiledShader {
             id: None,
+            ubo_id: None,
             name: name,
             kind: kind,
             features: features.to_vec(),
         };
 
         if precache {
-            try!{ shader.get(device) };
+            try!{ shader.get(device, None) };
         }
 
         Ok(shader)
     }
 
-    fn get(&mut self, device: &mut Device) -> Result<ProgramId, ShaderError> {
-        if self.id.is_none() {
+    /// Gets the program, compiling it on first use. The UBO variant is
+    /// returned when `ubo_data` is set.
+    fn get(&mut self,
+           device: &mut Device,
+           ubo_data: Option<UboDataCapacity>) -> Result<ProgramId, ShaderError> {
+        let ubo_data = match self.kind {
+            ShaderKind::Output => None,
+            _ => ubo_data,
+        };
+        let slot = if ubo_data.is_some() { &mut self.ubo_id } else { &mut self.id };
+
+        if slot.is_none() {
             let id = try!{
                 match self.kind {
                     ShaderKind::Primitive => {
                         create_prim_shader(self.name,
                                            device,
                                            &self.features,
-                                           VertexFormat::Triangles)
+                                           VertexFormat::Triangles,
+                                           ubo_data)
                     }
                     ShaderKind::Cache(format) => {
                         create_prim_shader(self.name,
                                            device,
                                            &self.features,
-                                           format)
+                                           format,
+                                           ubo_data)
                     }
                     ShaderKind::ClipCache => {
-                        create_clip_shader(self.name, device)
+                        create_clip_shader(self.name, device, ubo_data)
+                    }
+                    ShaderKind::Output => {
+                        create_output_shader(self.name, device, &self.features)
                     }
                 }
             };
-            self.id = Some(id);
+            *slot = Some(id);
         }
 
-        Ok(self.id.unwrap())
+        Ok(slot.unwrap())
+    }
+}
+
+/// The compute shader that blurs render tasks on hardware that supports
+/// it, see `RendererOptions::compute_blur`.
+#[derive(Clone, Copy)]
+struct ComputeBlurShader {
+    program: ProgramId,
+    u_task_rect: UniformLocation,
+    u_source_rect: UniformLocation,
+    u_source_layer: UniformLocation,
+    u_blur_radius: UniformLocation,
+    u_blur_direction: UniformLocation,
+}
+
+impl ComputeBlurShader {
+    fn new(device: &mut Device) -> Result<ComputeBlurShader, ShaderError> {
+        let prefix = format!("#define WR_BLUR_GROUP_SIZE {}\n#define WR_MAX_BLUR_RADIUS {}\n",
+                             COMPUTE_BLUR_GROUP_SIZE,
+                             MAX_BLUR_RADIUS);
+        let program = try!{ device.create_compute_program("cs_blur_compute", Some(prefix)) };
+
+        Ok(ComputeBlurShader {
+            program: program,
+            u_task_rect: device.get_uniform_location(program, "uTaskRect"),
+            u_source_rect: device.get_uniform_location(program, "uSourceRect"),
+            u_source_layer: device.get_uniform_location(program, "uSourceLayer"),
+            u_blur_radius: device.get_uniform_location(program, "uBlurRadius"),
+            u_blur_direction: device.get_uniform_location(program, "uBlurDirection"),
+        })
     }
 }
 
@@ -305,7 +827,7 @@ impl FileWatcherHandler for FileWatcher {
     }
 }
 
-fn _get_ubo_max_len<T>(max_ubo_size: usize) -> usize {
+fn get_ubo_max_len<T>(max_ubo_size: usize) -> usize {
     let item_size = mem::size_of::<T>();
     let max_items = max_ubo_size / item_size;
 
@@ -348,10 +870,11 @@ impl PrimitiveShader {
 
     fn get(&mut self,
            device: &mut Device,
-           transform_kind: TransformedRectKind) -> Result<ProgramId, ShaderError> {
+           transform_kind: TransformedRectKind,
+           ubo_data: Option<UboDataCapacity>) -> Result<ProgramId, ShaderError> {
         match transform_kind {
-            TransformedRectKind::AxisAligned => self.simple.get(device),
-            TransformedRectKind::Complex => self.transform.get(device),
+            TransformedRectKind::AxisAligned => self.simple.get(device, ubo_data),
+            TransformedRectKind::Complex => self.transform.get(device, ubo_data),
         }
     }
 }
@@ -359,7 +882,8 @@ impl PrimitiveShader {
 fn create_prim_shader(name: &'static str,
                       device: &mut Device,
                       features: &[&'static str],
-                      vertex_format: VertexFormat) -> Result<ProgramId, ShaderError> {
+                      vertex_format: VertexFormat,
+                      ubo_data: Option<UboDataCapacity>) -> Result<ProgramId, ShaderError> {
     let mut prefix = format!("#define WR_MAX_VERTEX_TEXTURE_WIDTH {}\n",
                               MAX_VERTEX_TEXTURE_WIDTH);
 
@@ -369,71 +893,261 @@ fn create_prim_shader(name: &'static str,
 
     debug!("PrimShader {}", name);
 
-    let includes = &["prim_shared"];
-    device.create_program_with_prefix(name, includes, Some(prefix), vertex_format)
+    match ubo_data {
+        Some(capacity) => {
+            prefix.push_str(&capacity.prefix());
+            let program = try!{
+                device.create_program_with_prefix(name, &["prim_shared_ubo"], Some(prefix), vertex_format)
+            };
+            assign_ubo_data_bindings(device, program);
+            Ok(program)
+        }
+        None => {
+            device.create_program_with_prefix(name, &["prim_shared"], Some(prefix), vertex_format)
+        }
+    }
 }
 
-fn create_clip_shader(name: &'static str, device: &mut Device) -> Result<ProgramId, ShaderError> {
-    let prefix = format!("#define WR_MAX_VERTEX_TEXTURE_WIDTH {}\n
-                          #define WR_FEATURE_TRANSFORM",
-                          MAX_VERTEX_TEXTURE_WIDTH);
+fn create_clip_shader(name: &'static str,
+                      device: &mut Device,
+                      ubo_data: Option<UboDataCapacity>) -> Result<ProgramId, ShaderError> {
+    let mut prefix = format!("#define WR_MAX_VERTEX_TEXTURE_WIDTH {}\n
+                              #define WR_FEATURE_TRANSFORM\n",
+                              MAX_VERTEX_TEXTURE_WIDTH);
 
     debug!("ClipShader {}", name);
 
-    let includes = &["prim_shared", "clip_shared"];
-    device.create_program_with_prefix(name, includes, Some(prefix), VertexFormat::Clip)
+    match ubo_data {
+        Some(capacity) => {
+            prefix.push_str(&capacity.prefix());
+            let includes = &["prim_shared_ubo", "clip_shared"];
+            let program = try!{
+                device.create_program_with_prefix(name, includes, Some(prefix), VertexFormat::Clip)
+            };
+            assign_ubo_data_bindings(device, program);
+            Ok(program)
+        }
+        None => {
+            let includes = &["prim_shared", "clip_shared"];
+            device.create_program_with_prefix(name, includes, Some(prefix), VertexFormat::Clip)
+        }
+    }
+}
+
+fn assign_ubo_data_bindings(device: &mut Device, program: ProgramId) {
+    device.assign_ubo_binding(program, "Layers", LAYERS_UBO_BINDING);
+    device.assign_ubo_binding(program, "RenderTasks", RENDER_TASKS_UBO_BINDING);
+}
+
+fn create_output_shader(name: &'static str,
+                        device: &mut Device,
+                        features: &[&'static str]) -> Result<ProgramId, ShaderError> {
+    let mut prefix = String::new();
+    for feature in features {
+        prefix.push_str(&format!("#define WR_FEATURE_{}\n", feature));
+    }
+
+    debug!("OutputShader {}", name);
+
+    device.create_program_with_prefix(name, &["shared_other"], Some(prefix), VertexFormat::DebugFont)
+}
+
+/// The renderer's side of the GPU cache. The updates that come with each
+/// frame are applied to a CPU copy as soon as the frame arrives, so none are
+/// lost when a frame is replaced before it is drawn. Only the rows that
+/// changed are uploaded before drawing.
+struct GpuCacheTexture {
+    id: TextureId,
+    blocks: Vec<GpuBlockData>,
+    dirty_rows: Vec<bool>,
+    // The height of the texture storage, in rows.
+    texture_rows: u32,
+}
+
+impl GpuCacheTexture {
+    fn new(device: &mut Device) -> GpuCacheTexture {
+        let id = device.create_texture_ids(1, TextureTarget::Default)[0];
+
+        GpuCacheTexture {
+            id: id,
+            blocks: Vec::new(),
+            dirty_rows: Vec::new(),
+            texture_rows: 0,
+        }
+    }
+
+    fn apply_updates(&mut self, update_list: GpuCacheUpdateList) {
+        if update_list.clear {
+            self.blocks.clear();
+            self.dirty_rows.clear();
+        }
+
+        let rows = update_list.height as usize;
+        if rows > self.dirty_rows.len() {
+            self.blocks.resize(rows * GPU_CACHE_WIDTH, GpuBlockData::default());
+            self.dirty_rows.resize(rows, true);
+        }
+
+        for update in update_list.updates {
+            let first_row = update.first_row as usize;
+            let row_count = update.blocks.len() / GPU_CACHE_WIDTH;
+            let start = first_row * GPU_CACHE_WIDTH;
+            self.blocks[start..start + update.blocks.len()].copy_from_slice(&update.blocks);
+            for dirty in &mut self.dirty_rows[first_row..first_row + row_count] {
+                *dirty = true;
+            }
+        }
+    }
+
+    fn set_resource_rect(&mut self, address: GpuStoreAddress, uv0: DevicePoint, uv1: DevicePoint) {
+        let index = address.0 as usize;
+        self.blocks[index].data = [uv0.x, uv0.y, uv1.x, uv1.y];
+        self.dirty_rows[index / GPU_CACHE_WIDTH] = true;
+    }
+
+    fn rows_as_bytes(&self, first_row: usize, row_count: usize) -> &[u8] {
+        let blocks = &self.blocks[first_row * GPU_CACHE_WIDTH..(first_row + row_count) * GPU_CACHE_WIDTH];
+        unsafe {
+            slice::from_raw_parts(blocks.as_ptr() as *const u8,
+                                  blocks.len() * mem::size_of::<GpuBlockData>())
+        }
+    }
+
+    /// Uploads the rows that changed since the last call, reallocating
+    /// the texture if the cache has grown, and binds it.
+    fn update_and_bind(&mut self, device: &mut Device) {
+        let rows = self.dirty_rows.len();
+
+        if rows as u32 > self.texture_rows {
+            device.init_texture(self.id,
+                                GPU_CACHE_WIDTH as u32,
+                                rows as u32,
+                                ImageFormat::RGBAF32,
+                                TextureFilter::Nearest,
+                                RenderTargetMode::None,
+                                Some(self.rows_as_bytes(0, rows)));
+            self.texture_rows = rows as u32;
+            for dirty in &mut self.dirty_rows {
+                *dirty = false;
+            }
+        } else {
+            let mut row = 0;
+            while row < rows {
+                if !self.dirty_rows[row] {
+                    row += 1;
+                    continue;
+                }
+
+                let first_row = row;
+                while row < rows && self.dirty_rows[row] {
+                    self.dirty_rows[row] = false;
+                    row += 1;
+                }
+
+                device.update_texture(self.id,
+                                      0,
+                                      first_row as u32,
+                                      GPU_CACHE_WIDTH as u32,
+                                      (row - first_row) as u32,
+                                      None,
+                                      self.rows_as_bytes(first_row, row - first_row));
+            }
+        }
+
+        device.bind_texture(TextureSampler::GpuCache, self.id);
+    }
 }
 
 struct GpuDataTextures {
     layer_texture: VertexDataTexture,
     render_task_texture: VertexDataTexture,
-    prim_geom_texture: VertexDataTexture,
-    data16_texture: VertexDataTexture,
-    data32_texture: VertexDataTexture,
-    data64_texture: VertexDataTexture,
-    data128_texture: VertexDataTexture,
-    resource_rects_texture: VertexDataTexture,
     gradient_data_texture: GradientDataTexture,
+    // The uniform buffers the layers and render tasks are uploaded to
+    // instead, when the UBO variants of the shaders are used.
+    layer_ubo: Option<UBOId>,
+    render_task_ubo: Option<UBOId>,
 }
 
 impl GpuDataTextures {
-    fn new(device: &mut Device) -> GpuDataTextures {
+    fn new(device: &mut Device, ubo_data: Option<UboDataCapacity>) -> GpuDataTextures {
+        let (layer_ubo, render_task_ubo) = match ubo_data {
+            Some(..) => {
+                (Some(device.create_ubo::<PackedLayer>(&[], LAYERS_UBO_BINDING)),
+                 Some(device.create_ubo::<RenderTaskData>(&[], RENDER_TASKS_UBO_BINDING)))
+            }
+            None => (None, None),
+        };
+
         GpuDataTextures {
             layer_texture: VertexDataTexture::new(device),
             render_task_texture: VertexDataTexture::new(device),
-            prim_geom_texture: VertexDataTexture::new(device),
-            data16_texture: VertexDataTexture::new(device),
-            data32_texture: VertexDataTexture::new(device),
-            data64_texture: VertexDataTexture::new(device),
-            data128_texture: VertexDataTexture::new(device),
-            resource_rects_texture: VertexDataTexture::new(device),
             gradient_data_texture: GradientDataTexture::new(device),
+            layer_ubo: layer_ubo,
+            render_task_ubo: render_task_ubo,
         }
     }
 
-    fn init_frame(&mut self, device: &mut Device, frame: &mut Frame) {
-        self.data16_texture.init(device, &mut frame.gpu_data16);
-        self.data32_texture.init(device, &mut frame.gpu_data32);
-        self.data64_texture.init(device, &mut frame.gpu_data64);
-        self.data128_texture.init(device, &mut frame.gpu_data128);
-        self.prim_geom_texture.init(device, &mut frame.gpu_geometry);
-        self.resource_rects_texture.init(device, &mut frame.gpu_resource_rects);
-        self.layer_texture.init(device, &mut frame.layer_texture_data);
-        self.render_task_texture.init(device, &mut frame.render_task_data);
-        self.gradient_data_texture.init(device, &mut frame.gpu_gradient_data);
+    /// Uploads the data of the frame. The layers and render tasks go to
+    /// the uniform buffers when they fit in `ubo_data`, and the capacity is
+    /// returned so the UBO variants of the shaders are drawn with.
+    fn init_frame(&mut self,
+                  device: &mut Device,
+                  frame: &mut Frame,
+                  late_latched_poses: &HashMap<PropertyBindingId, LayoutTransform>,
+                  ubo_data: Option<UboDataCapacity>) -> Option<UboDataCapacity> {
+        // Patch in the most recent poses right before the layer data is
+        // uploaded, so that they are picked up even when re-rendering a frame.
+        for layer in &frame.late_latched_layers {
+            if let Some(pose) = late_latched_poses.get(&layer.binding_id) {
+                layer.patch(&mut frame.layer_texture_data, &pose.to_untyped());
+            }
+        }
+
+        let ubo_data = ubo_data.and_then(|capacity| {
+            if capacity.fits(frame) { Some(capacity) } else { None }
+        });
+
+        match (ubo_data, self.layer_ubo, self.render_task_ubo) {
+            (Some(capacity), Some(layer_ubo), Some(render_task_ubo)) => {
+                device.update_ubo(layer_ubo,
+                                  &frame.layer_texture_data,
+                                  capacity.layers * mem::size_of::<PackedLayer>(),
+                                  LAYERS_UBO_BINDING);
+                device.update_ubo(render_task_ubo,
+                                  &frame.render_task_data,
+                                  capacity.render_tasks * mem::size_of::<RenderTaskData>(),
+                                  RENDER_TASKS_UBO_BINDING);
+            }
+            _ => {
+                self.layer_texture.init(device, &mut frame.layer_texture_data);
+                self.render_task_texture.init(device, &mut frame.render_task_data);
 
-        device.bind_texture(TextureSampler::Layers, self.layer_texture.id);
-        device.bind_texture(TextureSampler::RenderTasks, self.render_task_texture.id);
-        device.bind_texture(TextureSampler::Geometry, self.prim_geom_texture.id);
-        device.bind_texture(TextureSampler::Data16, self.data16_texture.id);
-        device.bind_texture(TextureSampler::Data32, self.data32_texture.id);
-        device.bind_texture(TextureSampler::Data64, self.data64_texture.id);
-        device.bind_texture(TextureSampler::Data128, self.data128_texture.id);
-        device.bind_texture(TextureSampler::ResourceRects, self.resource_rects_texture.id);
+                device.bind_texture(TextureSampler::Layers, self.layer_texture.id);
+                device.bind_texture(TextureSampler::RenderTasks, self.render_task_texture.id);
+            }
+        }
+
+        self.gradient_data_texture.init(device, &mut frame.gpu_gradient_data);
         device.bind_texture(TextureSampler::Gradients, self.gradient_data_texture.id);
+
+        ubo_data
+    }
+
+    fn deinit(&mut self, device: &mut Device) {
+        for ubo in self.layer_ubo.take().into_iter().chain(self.render_task_ubo.take()) {
+            device.delete_buffer(ubo);
+        }
     }
 }
 
+/// What the previously rendered frame drew, to find the damage of the next.
+struct DamageState {
+    framebuffer_size: DeviceUintSize,
+    device_pixel_ratio: f32,
+    background_color: Option<ColorF>,
+    items: Vec<DamageItem>,
+}
+
 /// The renderer is responsible for submitting to the GPU the work prepared by the
 /// RenderBackend.
 pub struct Renderer {
@@ -449,6 +1163,7 @@ pub struct Renderer {
     cs_box_shadow: LazilyCompiledShader,
     cs_text_run: LazilyCompiledShader,
     cs_blur: LazilyCompiledShader,
+    cs_blur_compute: Option<ComputeBlurShader>,
     /// These are "cache clip shaders". These shaders are used to
     /// draw clip instances into the cached clip mask. The results
     /// of these shaders are also used by the primitive shaders.
@@ -468,7 +1183,9 @@ pub struct Renderer {
     ps_text_run_subpixel: PrimitiveShader,
     ps_image: PrimitiveShader,
     ps_image_rect: PrimitiveShader,
+    ps_image_alpha: PrimitiveShader,
     ps_yuv_image: PrimitiveShader,
+    ps_yuv_image_rect: PrimitiveShader,
     ps_border: PrimitiveShader,
     ps_gradient: PrimitiveShader,
     ps_angle_gradient: PrimitiveShader,
@@ -480,14 +1197,52 @@ pub struct Renderer {
     ps_hw_composite: LazilyCompiledShader,
     ps_composite: LazilyCompiledShader,
 
+    ps_output_matrix: LazilyCompiledShader,
+    ps_output_lut: LazilyCompiledShader,
+    ps_output_hdr: LazilyCompiledShader,
+    ps_output_hdr10: LazilyCompiledShader,
+
+    debug_alpha_target: LazilyCompiledShader,
+
     notifier: Arc<Mutex<Option<Box<RenderNotifier>>>>,
 
     enable_profiler: bool,
     max_recorded_profiles: usize,
     clear_framebuffer: bool,
     clear_color: ColorF,
+    transparent_framebuffer: bool,
+    // Kept to check `set_subpixel_aa_enabled` against.
+    enable_aa: bool,
+    deterministic: bool,
+    intermediate_sample_count: u32,
+    surface_transform: SurfaceTransform,
+    framebuffer_origin: SurfaceOrigin,
+    /// The origin of the target that the current `render` draws into.
+    output_origin: SurfaceOrigin,
+    output_color_transform: Option<OutputColorTransform>,
+    hdr_output: Option<HdrOutput>,
+    color_space: ColorSpace,
+    /// The last level reported to `RenderApi::set_memory_pressure`.
+    memory_pressure: MemoryPressure,
+    memory_pressure_policy: MemoryPressurePolicy,
+    /// The framebuffer is drawn into this, at the size and in the format
+    /// it was last drawn with, while an output color transform, HDR output
+    /// or post-process handler is set.
+    output_texture: Option<(TextureId, DeviceUintSize, ImageFormat)>,
+    post_process_handler: Option<Box<PostProcessHandler>>,
+    /// The post-process handler draws into this when the output color
+    /// transform or HDR mapping still has to be applied after it.
+    post_process_texture: Option<(TextureId, DeviceUintSize, ImageFormat)>,
+    output_lut_texture_id: Option<TextureId>,
+    /// Set when the table of a `OutputColorTransform::Lut` needs uploading.
+    output_lut_dirty: bool,
+    output_vao_id: VAOId,
+    ortho_near_plane: f32,
+    ortho_far_plane: f32,
     debug: DebugRenderer,
-    render_target_debug: bool,
+    debug_flags: DebugFlags,
+    render_target_dump_path: PathBuf,
+    render_target_dump_count: usize,
     backend_profile_counters: BackendProfileCounters,
     profile_counters: RendererProfileCounters,
     profiler: Profiler,
@@ -503,35 +1258,75 @@ pub struct Renderer {
 
     gdt_index: usize,
     gpu_data_textures: [GpuDataTextures; GPU_DATA_TEXTURE_POOL],
+    // How many layers and render tasks the UBO variants of the shaders
+    // hold, if they are preferred over the data textures.
+    ubo_data_capacity: Option<UboDataCapacity>,
+    // The capacity the current frame's data was uploaded with, when it
+    // fit in the uniform buffers.
+    frame_ubo_data: Option<UboDataCapacity>,
+    gpu_cache_texture: GpuCacheTexture,
 
     pipeline_epoch_map: HashMap<PipelineId, Epoch, BuildHasherDefault<FnvHasher>>,
+    /// The token of the most recent frame requested with one.
+    frame_token: Option<FrameToken>,
     /// Used to dispatch functions to the main thread's event loop.
     /// Required to allow GLContext sharing in some implementations like WGL.
     main_thread_dispatcher: Arc<Mutex<Option<Box<RenderDispatcher>>>>,
 
     /// A vector for fast resolves of texture cache IDs to
     /// native texture IDs. This maps to a free-list managed
-    /// by the backend thread / texture cache. We free the
-    /// texture memory associated with a TextureId when its
-    /// texture cache ID is freed by the texture cache, but
-    /// reuse the TextureId when the texture caches's free
-    /// list reuses the texture cache ID. This saves having to
-    /// use a hashmap, and allows a flat vector for performance.
-    cache_texture_id_map: Vec<TextureId>,
+    /// by the backend thread / texture cache, which hands out
+    /// the lowest free IDs first. A texture is deleted when its
+    /// texture cache ID is freed, and the slots freed at the end
+    /// are dropped. This saves having to use a hashmap, and allows
+    /// a flat vector for performance.
+    cache_texture_id_map: Vec<Option<TextureId>>,
 
     /// A special 1x1 dummy cache texture used for shaders that expect to work
     /// with the cache but are actually running in the first pass
     /// when no target is yet provided as a cache texture input.
     dummy_cache_texture_id: TextureId,
 
+    /// Holds what some render tasks drew in recent frames, for the render
+    /// task cache of the backend. Created when first needed.
+    task_cache_texture: Option<TextureId>,
+    /// The last frame drawn, as numbered by the render task cache.
+    task_cache_frame: Arc<AtomicUsize>,
+
     dither_matrix_texture_id: TextureId,
 
     /// Optional trait object that allows the client
     /// application to provide external buffers for image data.
     external_image_handler: Option<Box<ExternalImageHandler>>,
 
+    /// Receives the pixels of readbacks requested with `request_readback`.
+    readback_handler: Option<Box<ReadbackHandler>>,
+    next_readback_id: u32,
+    /// Readbacks to start once the next frame has been drawn.
+    requested_readbacks: Vec<(ReadbackId, DeviceUintRect)>,
+    /// Readbacks waiting for the GPU to write their pixels.
+    pending_readbacks: Vec<(ReadbackId, DeviceUintRect, SurfaceOrigin, PendingReadback)>,
+    /// Readbacks of stacking context snapshots, which are already in top
+    /// to bottom order.
+    pending_snapshots: Vec<(SnapshotId, DeviceUintSize, PendingReadback)>,
+
+    /// Told when the GPU has finished the frames that `render` composites.
+    frame_complete_handler: Option<Box<FrameCompleteHandler>>,
+    /// The id of the next frame that `render` composites.
+    next_rendered_frame_id: u64,
+    /// Fences after the composited frames the GPU may still be drawing,
+    /// oldest first.
+    pending_frame_fences: VecDeque<(RenderedFrameId, GpuFence)>,
+
     /// Map of external image IDs to native textures.
     external_images: HashMap<ExternalImageId, TextureId, BuildHasherDefault<FnvHasher>>,
+    /// The embedder's filters of the native textures resolved this frame,
+    /// in the order they were changed.
+    external_texture_filters: Vec<SavedTextureFilter>,
+
+    /// Textures shared with WebGL contexts, and their sizes. These are
+    /// resolved like external images, but don't go through the handler.
+    webgl_textures: HashMap<WebGLContextId, (u32, DeviceIntSize), BuildHasherDefault<FnvHasher>>,
 
     // Optional trait object that handles WebVR commands.
     // Some WebVR commands such as SubmitFrame must be synced with the WebGL render thread.
@@ -541,14 +1336,105 @@ pub struct Renderer {
     /// via get_frame_profiles().
     cpu_profiles: VecDeque<CpuProfile>,
     gpu_profiles: VecDeque<GpuProfile>,
+
+    /// Optional OS compositor that owns the surface the frame is drawn
+    /// into, instead of the default framebuffer.
+    native_compositor: Option<Box<NativeCompositor>>,
+    native_surface: Option<(NativeSurfaceId, DeviceUintSize)>,
+    /// The part of the native surface bound for the frame being drawn, when
+    /// the rest of it still shows the previous frame.
+    native_dirty_rect: Option<DeviceIntRect>,
+    /// Set when a new frame arrives or the way frames are drawn changes,
+    /// so that the native surface is only redrawn, and with
+    /// `skip_unchanged_frames` the framebuffer only drawn, when its content
+    /// may have changed.
+    frame_dirty: bool,
+    skip_unchanged_frames: bool,
+    /// The framebuffer size of the last `render` that drew a frame.
+    last_framebuffer_size: Option<DeviceUintSize>,
+    /// The surfaces that promoted images are drawn into, in the order of
+    /// the frame's compositor surfaces, and their sizes.
+    compositor_surfaces: Vec<(NativeSurfaceId, DeviceUintSize)>,
+    next_native_surface_id: u64,
+
+    damage_state: Option<DamageState>,
+    statistics_dump: Option<StatisticsDump>,
+    /// The parts of the framebuffer that the last call to render() changed.
+    damage_rects: Vec<DeviceIntRect>,
+    /// The damage of the last few rendered frames, most recent first, to
+    /// find what a back buffer of a given age is missing.
+    damage_history: VecDeque<Vec<DeviceIntRect>>,
+    /// The age of the back buffer the next `render` draws into, see
+    /// `set_buffer_age`.
+    buffer_age: Option<u32>,
+    /// The part of the framebuffer, in GL coordinates, that the frame being
+    /// drawn is limited to, when the rest of the back buffer is up to date.
+    repaint_rect: Option<DeviceIntRect>,
+    /// The scissor rect of the color target being drawn, if any.
+    scissor_rect: Option<DeviceIntRect>,
+
+    /// Poses supplied by the embedder right before rendering, which override
+    /// the value of the matching transform binding at frame build time.
+    late_latched_poses: HashMap<PropertyBindingId, LayoutTransform>,
+
+    /// The scale and its origin that the framebuffer pass is drawn with,
+    /// see `set_composite_zoom`.
+    composite_zoom: Option<(f32, DevicePoint)>,
+
+    /// Views the framebuffer pass is drawn into when rendering in stereo.
+    /// Empty for regular rendering.
+    views: Vec<RenderView>,
+
+    /// The video primitives promoted to overlay planes in the last
+    /// rendered frame.
+    overlay_candidates: Vec<OverlayCandidate>,
+
+    /// Used to forward option changes that the backend thread needs to see.
+    api_tx: MsgSender<ApiMsg>,
+    next_backend_query_id: u32,
+    backend_query_responses: Vec<(BackendQueryId, BackendQueryResponse)>,
+
+    /// The backend, when it runs on this thread rather than its own. It is
+    /// shared with the function that `RenderApi` calls run before they
+    /// wait for a reply.
+    inline_backend: Option<Rc<RefCell<InlineBackend>>>,
+
+    /// Warnings not yet collected by `take_warnings`.
+    warnings: Vec<RendererWarning>,
+}
+
+/// A render backend driven by `Renderer::update_and_build_frame`, along
+/// with the state its thread would otherwise keep.
+struct InlineBackend {
+    backend: RenderBackend,
+    profile_counters: BackendProfileCounters,
+    frame_counter: u32,
+    shut_down: bool,
+}
+
+impl InlineBackend {
+    /// Processes every queued API message. Returns false once the backend
+    /// has been shut down.
+    fn process_pending_messages(&mut self) -> bool {
+        if !self.shut_down {
+            self.shut_down = !self.backend.process_pending_messages(&mut self.profile_counters,
+                                                                    &mut self.frame_counter);
+        }
+        !self.shut_down
+    }
 }
 
 #[derive(Debug)]
 pub enum InitError {
+    Options(RendererOptionsError),
     Shader(ShaderError),
     Thread(std::io::Error),
 }
 
+impl From<RendererOptionsError> for InitError {
+    fn from(err: RendererOptionsError) -> Self { InitError::Options(err) }
+}
+
 impl From<ShaderError> for InitError {
     fn from(err: ShaderError) -> Self { InitError::Shader(err) }
 }
@@ -575,10 +1461,16 @@ impl Renderer {
     /// };
     /// let (renderer, sender) = Renderer::new(opts);
     /// ```
+    ///
+    /// The window parameters must be set with
+    /// `RenderApi::set_window_parameters` before the first frame is
+    /// generated.
+    ///
     /// [rendereroptions]: struct.RendererOptions.html
     pub fn new(gl: Rc<gl::Gl>,
-               mut options: RendererOptions,
-               initial_window_size: DeviceUintSize) -> Result<(Renderer, RenderApiSender), InitError> {
+               mut options: RendererOptions) -> Result<(Renderer, RenderApiSender), InitError> {
+        try!{ options.validate() };
+
         let (api_tx, api_rx) = try!{ channel::msg_channel() };
         let (payload_tx, payload_rx) = try!{ channel::payload_channel() };
         let (result_tx, result_rx) = channel();
@@ -595,6 +1487,20 @@ impl Renderer {
         let mut device = Device::new(gl,
                                      options.resource_override_path.clone(),
                                      Box::new(file_watch_handler));
+        if let Some(workarounds) = options.driver_workarounds {
+            device.set_driver_workarounds(workarounds);
+        }
+        if let Some(strategy) = options.buffer_update_strategy {
+            let mut workarounds = *device.driver_workarounds();
+            workarounds.apply(Workaround::UseBufferUpdateStrategy(strategy));
+            device.set_driver_workarounds(workarounds);
+        }
+        if options.enable_dithering && !options.deterministic {
+            device.add_shader_feature(DITHERING_FEATURE);
+        }
+        if options.edge_aa_mode == EdgeAaMode::HighQuality {
+            device.add_shader_feature(HIGH_QUALITY_EDGE_AA_FEATURE);
+        }
         // device-pixel ratio doesn't matter here - we are just creating resources.
         device.begin_frame(1.0);
 
@@ -614,13 +1520,26 @@ impl Renderer {
                                       options.precache_shaders)
         };
 
+        let blur_features = if options.fast_blur { vec![FAST_BLUR_FEATURE] } else { vec![] };
         let cs_blur = try!{
             LazilyCompiledShader::new(ShaderKind::Cache(VertexFormat::Blur),
                                      "cs_blur",
-                                      &[],
+                                      &blur_features,
                                       &mut device,
                                       options.precache_shaders)
         };
+        let cs_blur_compute = if options.compute_blur &&
+                                 device.get_capabilities().supports_compute_shaders {
+            match ComputeBlurShader::new(&mut device) {
+                Ok(shader) => Some(shader),
+                Err(err) => {
+                    warn!("Failed to create the compute blur shader, using cs_blur: {:?}", err);
+                    None
+                }
+            }
+        } else {
+            None
+        };
 
         let cs_clip_rectangle = try!{
             LazilyCompiledShader::new(ShaderKind::ClipCache,
@@ -680,6 +1599,13 @@ impl Renderer {
                                  options.precache_shaders)
         };
 
+        let ps_image_alpha = try!{
+            PrimitiveShader::new("ps_image",
+                                 &mut device,
+                                 &[ ALPHA_MASK_FEATURE ],
+                                 options.precache_shaders)
+        };
+
         let ps_yuv_image = try!{
             PrimitiveShader::new("ps_yuv_image",
                                  &mut device,
@@ -687,6 +1613,13 @@ impl Renderer {
                                  options.precache_shaders)
         };
 
+        let ps_yuv_image_rect = try!{
+            PrimitiveShader::new("ps_yuv_image",
+                                 &mut device,
+                                 &[ TEXTURE_RECT_FEATURE ],
+                                 options.precache_shaders)
+        };
+
         let ps_border = try!{
             PrimitiveShader::new("ps_border",
                                  &mut device,
@@ -745,6 +1678,46 @@ impl Renderer {
                                       options.precache_shaders)
         };
 
+        let ps_output_matrix = try!{
+            LazilyCompiledShader::new(ShaderKind::Output,
+                                      "output_color",
+                                      &[],
+                                      &mut device,
+                                      options.precache_shaders)
+        };
+
+        let ps_output_lut = try!{
+            LazilyCompiledShader::new(ShaderKind::Output,
+                                      "output_color",
+                                      &[COLOR_LUT_FEATURE],
+                                      &mut device,
+                                      options.precache_shaders)
+        };
+
+        let ps_output_hdr = try!{
+            LazilyCompiledShader::new(ShaderKind::Output,
+                                      "output_color",
+                                      &[HDR_OUTPUT_FEATURE],
+                                      &mut device,
+                                      options.precache_shaders)
+        };
+
+        let ps_output_hdr10 = try!{
+            LazilyCompiledShader::new(ShaderKind::Output,
+                                      "output_color",
+                                      &[HDR_OUTPUT_FEATURE, HDR10_FEATURE],
+                                      &mut device,
+                                      options.precache_shaders)
+        };
+
+        let debug_alpha_target = try!{
+            LazilyCompiledShader::new(ShaderKind::Output,
+                                      "debug_target",
+                                      &[],
+                                      &mut device,
+                                      options.precache_shaders)
+        };
+
         let ps_hw_composite = try!{
             LazilyCompiledShader::new(ShaderKind::Primitive,
                                      "ps_hardware_composite",
@@ -753,10 +1726,19 @@ impl Renderer {
                                      options.precache_shaders)
         };
 
+        let intermediate_sample_count = if device.driver_workarounds().disable_multisampling {
+            1
+        } else {
+            cmp::min(options.intermediate_sample_count,
+                     device.get_capabilities().max_samples)
+        };
+
         let device_max_size = device.max_texture_size();
         let max_texture_size = cmp::min(device_max_size, options.max_texture_size.unwrap_or(device_max_size));
 
         let mut texture_cache = TextureCache::new(max_texture_size);
+        texture_cache.set_deterministic(options.deterministic);
+        texture_cache.set_allocator_config(options.texture_allocator);
         let mut backend_profile_counters = BackendProfileCounters::new();
 
         let white_pixels: Vec<u8> = vec![
@@ -816,13 +1798,19 @@ impl Renderer {
 
         let debug_renderer = DebugRenderer::new(&mut device);
 
+        let ubo_data_capacity = if device.get_capabilities().prefers_ubo_data {
+            Some(UboDataCapacity::new(device.get_capabilities().max_ubo_size))
+        } else {
+            None
+        };
         let gpu_data_textures = [
-            GpuDataTextures::new(&mut device),
-            GpuDataTextures::new(&mut device),
-            GpuDataTextures::new(&mut device),
-            GpuDataTextures::new(&mut device),
-            GpuDataTextures::new(&mut device),
+            GpuDataTextures::new(&mut device, ubo_data_capacity),
+            GpuDataTextures::new(&mut device, ubo_data_capacity),
+            GpuDataTextures::new(&mut device, ubo_data_capacity),
+            GpuDataTextures::new(&mut device, ubo_data_capacity),
+            GpuDataTextures::new(&mut device, ubo_data_capacity),
         ];
+        let gpu_cache_texture = GpuCacheTexture::new(&mut device);
 
         let x0 = 0.0;
         let y0 = 0.0;
@@ -854,6 +1842,20 @@ impl Renderer {
         let blur_vao_id = device.create_vao_with_new_instances(VertexFormat::Blur, mem::size_of::<BlurCommand>() as i32, prim_vao_id);
         let clip_vao_id = device.create_vao_with_new_instances(VertexFormat::Clip, mem::size_of::<CacheClipInstance>() as i32, prim_vao_id);
 
+        // A unit quad, scaled to the framebuffer by the projection.
+        let output_color = PackedColor::from_color(&ColorF::new(1.0, 1.0, 1.0, 1.0));
+        let output_indices: [u32; 6] = [ 0, 1, 2, 2, 1, 3 ];
+        let output_vertices = [
+            DebugFontVertex::new(x0, y0, 0.0, 0.0, output_color),
+            DebugFontVertex::new(x1, y0, 1.0, 0.0, output_color),
+            DebugFontVertex::new(x0, y1, 0.0, 1.0, output_color),
+            DebugFontVertex::new(x1, y1, 1.0, 1.0, output_color),
+        ];
+        let output_vao_id = device.create_vao(VertexFormat::DebugFont, 32);
+        device.bind_vao(output_vao_id);
+        device.update_vao_indices(output_vao_id, &output_indices, VertexUsageHint::Static);
+        device.update_vao_main_vertices(output_vao_id, &output_vertices, VertexUsageHint::Static);
+
         device.end_frame();
 
         let main_thread_dispatcher = Arc::new(Mutex::new(None));
@@ -863,6 +1865,9 @@ impl Renderer {
         let vr_compositor = Arc::new(Mutex::new(None));
         let backend_vr_compositor = Arc::clone(&vr_compositor);
 
+        let task_cache_frame = Arc::new(AtomicUsize::new(0));
+        let backend_task_cache_frame = Arc::clone(&task_cache_frame);
+
         // We need a reference to the webrender context from the render backend in order to share
         // texture ids
         let context_handle = match options.renderer_kind {
@@ -871,40 +1876,104 @@ impl Renderer {
         };
 
         let config = FrameBuilderConfig::new(options.enable_scrollbars,
-                                             options.enable_subpixel_aa,
-                                             options.debug);
+                                             options.scrollbar_theme,
+                                             options.enable_subpixel_aa && !options.deterministic,
+                                             options.enable_overlay_promotion,
+                                             options.enable_compositor_surfaces &&
+                                                 options.native_compositor.is_some(),
+                                             options.z_allocation,
+                                             options.ortho_far_plane as i32,
+                                             options.debug,
+                                             options.deterministic,
+                                             options.color_space,
+                                             options.max_gradient_resolution / GRADIENT_DATA_RESOLUTION,
+                                             options.batch_lookback_count,
+                                             options.subpixel_quantization,
+                                             options.late_latch_cull_margin);
 
         let (device_pixel_ratio, enable_aa) = (options.device_pixel_ratio, options.enable_aa);
-        let render_target_debug = options.render_target_debug;
+        let mut debug_flags = DebugFlags::empty();
+        if options.render_target_debug {
+            debug_flags.insert(RENDER_TARGET_DBG);
+        }
         let payload_tx_for_backend = payload_tx.clone();
         let recorder = options.recorder;
-        let workers = options.workers.take().unwrap_or_else(||{
-            // TODO(gw): Use a heuristic to select best # of worker threads.
-            Arc::new(Mutex::new(ThreadPool::new_with_name("WebRender:Worker".to_string(), 4)))
-        });
-
+        let validate_display_lists = options.validate_display_lists;
+        let memory_pressure_policy = options.memory_pressure_policy;
+        let workers = if options.single_threaded_workers {
+            // A pool of one thread runs the jobs in the order they were
+            // queued, and all glyphs come from the same font context.
+            Arc::new(Mutex::new(ThreadPool::new_with_name("WebRender:Worker".to_string(), 1)))
+        } else {
+            options.workers.take().unwrap_or_else(||{
+                // TODO(gw): Use a heuristic to select best # of worker threads.
+                Arc::new(Mutex::new(ThreadPool::new_with_name("WebRender:Worker".to_string(), 4)))
+            })
+        };
+
         let blob_image_renderer = options.blob_image_renderer.take();
-        try!{ thread::Builder::new().name("RenderBackend".to_string()).spawn(move || {
-            let mut backend = RenderBackend::new(api_rx,
-                                                 payload_rx,
-                                                 payload_tx_for_backend,
-                                                 result_tx,
-                                                 device_pixel_ratio,
-                                                 texture_cache,
-                                                 enable_aa,
-                                                 workers,
-                                                 backend_notifier,
-                                                 context_handle,
-                                                 config,
-                                                 recorder,
-                                                 backend_main_thread_dispatcher,
-                                                 blob_image_renderer,
-                                                 backend_vr_compositor,
-                                                 initial_window_size);
-            backend.run(backend_profile_counters);
-        })};
-
-        let gpu_profile = GpuProfiler::new(device.rc_gl());
+        // An inline backend would make WebGL contexts current on the
+        // renderer's thread, so WebGL isn't available in that mode.
+        let context_handle = if options.synchronous_backend {
+            None
+        } else {
+            context_handle
+        };
+        let create_backend = move || {
+            RenderBackend::new(api_rx,
+                               payload_rx,
+                               payload_tx_for_backend,
+                               result_tx,
+                               device_pixel_ratio,
+                               texture_cache,
+                               enable_aa,
+                               workers,
+                               backend_notifier,
+                               context_handle,
+                               config,
+                               recorder,
+                               backend_main_thread_dispatcher,
+                               blob_image_renderer,
+                               backend_vr_compositor,
+                               validate_display_lists,
+                               memory_pressure_policy,
+                               backend_task_cache_frame)
+        };
+
+        let inline_backend = if options.synchronous_backend {
+            let inline_backend = Rc::new(RefCell::new(InlineBackend {
+                backend: create_backend(),
+                profile_counters: backend_profile_counters,
+                frame_counter: 0,
+                shut_down: false,
+            }));
+            // Lets the API calls that wait for a reply run the backend
+            // first, instead of blocking until the next
+            // `update_and_build_frame()`.
+            let weak_backend = Rc::downgrade(&inline_backend);
+            register_synchronous_backend(Box::new(move || {
+                match weak_backend.upgrade() {
+                    Some(inline_backend) => {
+                        match inline_backend.try_borrow_mut() {
+                            Ok(mut inline_backend) => inline_backend.process_pending_messages(),
+                            // Already processing messages further up the stack.
+                            Err(..) => true,
+                        }
+                    }
+                    None => false,
+                }
+            }));
+            Some(inline_backend)
+        } else {
+            try!{ thread::Builder::new().name("RenderBackend".to_string()).spawn(move || {
+                let mut backend = create_backend();
+                backend.run(backend_profile_counters);
+            })};
+            None
+        };
+
+        let timer_queries = device.get_capabilities().supports_timer_queries;
+        let gpu_profile = GpuProfiler::new(device.rc_gl(), timer_queries);
 
         let renderer = Renderer {
             result_rx: result_rx,
@@ -915,6 +1984,7 @@ impl Renderer {
             cs_box_shadow: cs_box_shadow,
             cs_text_run: cs_text_run,
             cs_blur: cs_blur,
+            cs_blur_compute: cs_blur_compute,
             cs_clip_rectangle: cs_clip_rectangle,
             cs_clip_image: cs_clip_image,
             ps_rectangle: ps_rectangle,
@@ -923,7 +1993,9 @@ impl Renderer {
             ps_text_run_subpixel: ps_text_run_subpixel,
             ps_image: ps_image,
             ps_image_rect: ps_image_rect,
+            ps_image_alpha: ps_image_alpha,
             ps_yuv_image: ps_yuv_image,
+            ps_yuv_image_rect: ps_yuv_image_rect,
             ps_border: ps_border,
             ps_box_shadow: ps_box_shadow,
             ps_gradient: ps_gradient,
@@ -933,16 +2005,44 @@ impl Renderer {
             ps_blend: ps_blend,
             ps_hw_composite: ps_hw_composite,
             ps_composite: ps_composite,
+            ps_output_matrix: ps_output_matrix,
+            ps_output_lut: ps_output_lut,
+            ps_output_hdr: ps_output_hdr,
+            ps_output_hdr10: ps_output_hdr10,
+            debug_alpha_target: debug_alpha_target,
             notifier: notifier,
             debug: debug_renderer,
-            render_target_debug: render_target_debug,
+            debug_flags: debug_flags,
+            render_target_dump_path: PathBuf::from("render_targets"),
+            render_target_dump_count: 0,
             backend_profile_counters: BackendProfileCounters::new(),
             profile_counters: RendererProfileCounters::new(),
             profiler: Profiler::new(),
             enable_profiler: options.enable_profiler,
             max_recorded_profiles: options.max_recorded_profiles,
             clear_framebuffer: options.clear_framebuffer,
-            clear_color: options.clear_color,
+            intermediate_sample_count: intermediate_sample_count,
+            surface_transform: options.surface_transform,
+            framebuffer_origin: options.framebuffer_origin,
+            output_origin: options.framebuffer_origin,
+            output_color_transform: options.output_color_transform.take(),
+            hdr_output: options.hdr_output,
+            color_space: options.color_space,
+            memory_pressure: MemoryPressure::Normal,
+            memory_pressure_policy: options.memory_pressure_policy,
+            output_texture: None,
+            post_process_handler: None,
+            post_process_texture: None,
+            output_lut_texture_id: None,
+            output_lut_dirty: true,
+            output_vao_id: output_vao_id,
+            clear_color: options.clear_color.convert_color_space(ColorSpace::Srgb,
+                                                                 options.color_space),
+            transparent_framebuffer: options.transparent_framebuffer,
+            enable_aa: options.enable_aa,
+            deterministic: options.deterministic,
+            ortho_near_plane: options.ortho_near_plane,
+            ortho_far_plane: options.ortho_far_plane,
             last_time: 0,
             color_render_targets: Vec::new(),
             alpha_render_targets: Vec::new(),
@@ -952,16 +2052,56 @@ impl Renderer {
             clip_vao_id: clip_vao_id,
             gdt_index: 0,
             gpu_data_textures: gpu_data_textures,
+            ubo_data_capacity: ubo_data_capacity,
+            frame_ubo_data: None,
+            gpu_cache_texture: gpu_cache_texture,
             pipeline_epoch_map: HashMap::with_hasher(Default::default()),
+            frame_token: None,
             main_thread_dispatcher: main_thread_dispatcher,
             cache_texture_id_map: Vec::new(),
             dummy_cache_texture_id: dummy_cache_texture_id,
+            task_cache_texture: None,
+            task_cache_frame: task_cache_frame,
             dither_matrix_texture_id: dither_matrix_texture_id,
             external_image_handler: None,
+            readback_handler: None,
+            next_readback_id: 0,
+            requested_readbacks: Vec::new(),
+            pending_readbacks: Vec::new(),
+            pending_snapshots: Vec::new(),
+            frame_complete_handler: None,
+            next_rendered_frame_id: 0,
+            pending_frame_fences: VecDeque::new(),
             external_images: HashMap::with_hasher(Default::default()),
+            external_texture_filters: Vec::new(),
+            webgl_textures: HashMap::with_hasher(Default::default()),
             vr_compositor_handler: vr_compositor,
             cpu_profiles: VecDeque::new(),
             gpu_profiles: VecDeque::new(),
+            native_compositor: options.native_compositor.take(),
+            native_surface: None,
+            native_dirty_rect: None,
+            frame_dirty: true,
+            skip_unchanged_frames: options.skip_unchanged_frames,
+            last_framebuffer_size: None,
+            compositor_surfaces: Vec::new(),
+            next_native_surface_id: 0,
+            damage_state: None,
+            statistics_dump: None,
+            damage_rects: Vec::new(),
+            damage_history: VecDeque::new(),
+            buffer_age: None,
+            repaint_rect: None,
+            scissor_rect: None,
+            late_latched_poses: HashMap::new(),
+            composite_zoom: None,
+            views: Vec::new(),
+            overlay_candidates: Vec::new(),
+            api_tx: api_tx.clone(),
+            next_backend_query_id: 0,
+            backend_query_responses: Vec::new(),
+            inline_backend: inline_backend,
+            warnings: Vec::new(),
         };
 
         let sender = RenderApiSender::new(api_tx, payload_tx);
@@ -972,6 +2112,60 @@ impl Renderer {
         self.device.gl()
     }
 
+    /// Sets the origin of the framebuffer that the following calls to
+    /// `render` draw into, for embedders that switch between targets with
+    /// different conventions.
+    pub fn set_framebuffer_origin(&mut self, origin: SurfaceOrigin) {
+        self.framebuffer_origin = origin;
+        self.frame_dirty = true;
+    }
+
+    /// Sets the color transform applied to each frame as the last step of
+    /// drawing it, or removes it with `None`. Embedders call this when the
+    /// window moves to a display with another color profile; it takes effect
+    /// with the next `render`.
+    pub fn set_output_color_transform(&mut self,
+                                      transform: Option<OutputColorTransform>)
+                                      -> Result<(), RendererOptionsError> {
+        if let Some(ref transform) = transform {
+            if !transform.is_valid() {
+                return Err(RendererOptionsError::InvalidOutputColorTransform);
+            }
+            if self.hdr_output.is_some() {
+                return Err(RendererOptionsError::HdrOutputWithColorTransform);
+            }
+        }
+        self.output_color_transform = transform;
+        self.output_lut_dirty = true;
+        self.frame_dirty = true;
+        Ok(())
+    }
+
+    /// Switches between drawing for an HDR swapchain and drawing for an
+    /// SDR one with `None`. The embedder recreates its swapchain in the
+    /// matching format; this takes effect with the next `render`.
+    pub fn set_hdr_output(&mut self,
+                          hdr_output: Option<HdrOutput>)
+                          -> Result<(), RendererOptionsError> {
+        if let Some(ref hdr_output) = hdr_output {
+            if !hdr_output.is_valid() {
+                return Err(RendererOptionsError::InvalidHdrOutput);
+            }
+            if self.output_color_transform.is_some() {
+                return Err(RendererOptionsError::HdrOutputWithColorTransform);
+            }
+        }
+        self.hdr_output = hdr_output;
+        self.frame_dirty = true;
+        Ok(())
+    }
+
+    /// Returns the driver workarounds in effect, see
+    /// `RendererOptions::driver_workarounds`.
+    pub fn get_driver_workarounds(&self) -> DriverWorkarounds {
+        *self.device.driver_workarounds()
+    }
+
     /// Sets the new RenderNotifier.
     ///
     /// The RenderNotifier will be called when processing e.g. of a (scrolling) frame is done,
@@ -998,17 +2192,70 @@ impl Renderer {
         *handler_arc = Some(creator);
     }
 
+    /// Late-latches the value of a transform binding, typically a WebVR pose.
+    ///
+    /// The pose is applied by the renderer to the layers of the current frame
+    /// just before they are uploaded to the GPU, without a round trip through
+    /// the render backend. It stays in effect until it is replaced.
+    pub fn set_late_latched_pose(&mut self,
+                                 key: PropertyBindingKey<LayoutTransform>,
+                                 pose: LayoutTransform) {
+        self.late_latched_poses.insert(key.id, pose);
+        self.frame_dirty = true;
+    }
+
+    /// Scales the current frame by `scale` around `origin` when drawing it
+    /// to the framebuffer, without rebuilding it, e.g. to follow a pinch
+    /// gesture while the content is rendered at the new resolution with
+    /// `RenderApi::set_pinch_zoom`. The origin is in device pixels from
+    /// the top left of the frame. The zoom stays in effect, also for the
+    /// frames that arrive later, until it's reset with `None`.
+    ///
+    /// Parts of the page outside of the frame aren't drawn when zooming
+    /// out. Images promoted to compositor surfaces and overlay candidates
+    /// aren't scaled.
+    pub fn set_composite_zoom(&mut self, zoom: Option<(f32, DevicePoint)>) {
+        self.composite_zoom = zoom;
+        // The whole framebuffer changes when the zoom does.
+        self.damage_state = None;
+        self.frame_dirty = true;
+    }
+
     /// Returns the Epoch of the current frame in a pipeline.
     pub fn current_epoch(&self, pipeline_id: PipelineId) -> Option<Epoch> {
         self.pipeline_epoch_map.get(&pipeline_id).cloned()
     }
 
+    /// Returns the token passed to `RenderApi::generate_frame` for the most
+    /// recent frame received by the renderer that was requested with one.
+    pub fn current_frame_token(&self) -> Option<FrameToken> {
+        self.frame_token
+    }
+
     /// Returns a HashMap containing the pipeline ids that have been received by the renderer and
     /// their respective epochs since the last time the method was called.
     pub fn flush_rendered_epochs(&mut self) -> HashMap<PipelineId, Epoch, BuildHasherDefault<FnvHasher>> {
         return mem::replace(&mut self.pipeline_epoch_map, HashMap::with_hasher(Default::default()));
     }
 
+    /// Runs the inline backend over every API message sent so far, then
+    /// processes its results as `update()` does. Frames requested with
+    /// `generate_frame()` are ready to render once this returns.
+    ///
+    /// Only does work when the renderer was created with
+    /// `RendererOptions::synchronous_backend`. Otherwise it is the same as
+    /// calling `update()`.
+    pub fn update_and_build_frame(&mut self) {
+        let running = match self.inline_backend {
+            Some(ref inline) => inline.borrow_mut().process_pending_messages(),
+            None => true,
+        };
+        if !running {
+            self.inline_backend = None;
+        }
+        self.update();
+    }
+
     /// Processes the result queue.
     ///
     /// Should be called before `render()`, as texture cache updates are done here.
@@ -1018,7 +2265,11 @@ impl Renderer {
         // Pull any pending results and return the most recent.
         while let Ok(msg) = self.result_rx.try_recv() {
             match msg {
-                ResultMsg::NewFrame(frame, texture_update_list, external_image_update_list, profile_counters) => {
+                ResultMsg::NewFrame(mut frame,
+                                    texture_update_list,
+                                    external_image_update_list,
+                                    profile_counters,
+                                    token) => {
                     self.pending_texture_updates.push(texture_update_list);
 
                     // When a new frame is ready, we could start to update all pending external image requests here.
@@ -1031,33 +2282,75 @@ impl Renderer {
                     for (pipeline_id, epoch) in &frame.pipeline_epoch_map {
                         self.pipeline_epoch_map.insert(*pipeline_id, *epoch);
                     }
+                    for pipeline_id in &frame.removed_pipelines {
+                        self.pipeline_epoch_map.remove(pipeline_id);
+                    }
+
+                    if let Some(ref mut tiling_frame) = frame.frame {
+                        let updates = mem::replace(&mut tiling_frame.gpu_cache_updates,
+                                                   GpuCacheUpdateList::new());
+                        self.gpu_cache_texture.apply_updates(updates);
+                    }
+
+                    // Carry over any fences from a frame that was never rendered.
+                    if let Some(old_frame) = self.current_frame.take() {
+                        frame.webgl_fences.extend(old_frame.webgl_fences);
+                    }
+
+                    // Frames built for scrolling keep the token of the frame
+                    // they were derived from.
+                    if token.is_some() {
+                        self.frame_token = token;
+                    }
 
                     self.current_frame = Some(frame);
+                    self.frame_dirty = true;
+                }
+                ResultMsg::UpdateWebGLTexture(id, texture_id, size) => {
+                    self.webgl_textures.insert(id, (texture_id, size));
+                    self.frame_dirty = true;
+                }
+                ResultMsg::DeleteWebGLTexture(id) => {
+                    self.webgl_textures.remove(&id);
                 }
                 ResultMsg::RefreshShader(path) => {
                     self.pending_shader_updates.push(path);
                 }
+                ResultMsg::SetMemoryPressure(level) => {
+                    self.memory_pressure = level;
+                }
+                ResultMsg::DebugCommand(cmd) => {
+                    self.handle_debug_command(cmd);
+                }
+                ResultMsg::BackendQueryResponse(id, response) => {
+                    self.backend_query_responses.push((id, response));
+                }
             }
         }
     }
 
     // Get the real (OpenGL) texture ID for a given source texture.
     // For a texture cache texture, the IDs are stored in a vector
-    // map for fast access. For WebGL textures, the native texture ID
-    // is stored inline. When we add support for external textures,
-    // we will add a callback here that is able to ask the caller
-    // for the image data.
+    // map for fast access. External textures are resolved at the
+    // start of each frame, and WebGL contexts to the texture they share.
     fn resolve_source_texture(&mut self, texture_id: &SourceTexture) -> TextureId {
         match *texture_id {
             SourceTexture::Invalid => TextureId::invalid(),
-            SourceTexture::WebGL(id) => TextureId::new(id, TextureTarget::Default),
+            // A context deleted after the frame was built draws nothing.
+            SourceTexture::WebGL(context_id) => {
+                self.webgl_textures
+                    .get(&context_id)
+                    .map_or(TextureId::invalid(), |&(texture_id, _)| {
+                        TextureId::new(texture_id, TextureTarget::Default)
+                    })
+            }
             SourceTexture::External(external_image) => {
                 *self.external_images
                      .get(&external_image.id)
                      .expect("BUG: External image should be resolved by now!")
             }
             SourceTexture::TextureCache(index) => {
-                self.cache_texture_id_map[index.0]
+                self.cache_texture_id(index)
             }
         }
     }
@@ -1067,6 +2360,143 @@ impl Renderer {
         self.external_image_handler = Some(handler);
     }
 
+    /// Set a callback for receiving the pixels of readbacks.
+    pub fn set_readback_handler(&mut self, handler: Box<ReadbackHandler>) {
+        self.readback_handler = Some(handler);
+    }
+
+    /// Set a callback for finding out when the GPU has finished the frames
+    /// that `render()` composites.
+    pub fn set_frame_complete_handler(&mut self, handler: Box<FrameCompleteHandler>) {
+        self.frame_complete_handler = Some(handler);
+    }
+
+    /// The id of the last frame that `render()` composited, which is handed
+    /// to the `FrameCompleteHandler` once the GPU has finished it.
+    pub fn last_rendered_frame_id(&self) -> Option<RenderedFrameId> {
+        match self.next_rendered_frame_id {
+            0 => None,
+            next_id => Some(RenderedFrameId(next_id - 1)),
+        }
+    }
+
+    /// Tells the frame complete handler about the composited frames that
+    /// the GPU has finished. `render()` does this too, so this only needs to
+    /// be called to find out sooner, or, with `wait` set, to block until the
+    /// GPU has finished all of them.
+    pub fn poll_completed_frames(&mut self, wait: bool) {
+        while let Some((id, fence)) = self.pending_frame_fences.pop_front() {
+            match self.device.end_fence(fence, wait) {
+                Ok(()) => {
+                    self.frame_complete_handler.as_mut().unwrap().frame_complete(id);
+                }
+                Err(fence) => {
+                    self.pending_frame_fences.push_front((id, fence));
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Sets the effect drawn over the whole frame before it reaches the
+    /// framebuffer, or removes it with `None`. Takes effect with the next
+    /// `render`.
+    pub fn set_post_process_handler(&mut self, handler: Option<Box<PostProcessHandler>>) {
+        self.post_process_handler = handler;
+        self.frame_dirty = true;
+    }
+
+    /// Asks for the pixels of `rect` of the framebuffer, in device pixels
+    /// from the top left, as drawn by the next `render()`. The copy is made
+    /// on the GPU without waiting for it to finish drawing, and the pixels
+    /// are handed to the `ReadbackHandler` by a later `render()` or
+    /// `poll_readbacks()` call, once they have arrived. The rect is in the
+    /// orientation of the frame, before the `surface_transform`.
+    pub fn request_readback(&mut self, rect: DeviceUintRect) -> ReadbackId {
+        let id = ReadbackId(self.next_readback_id);
+        self.next_readback_id += 1;
+        self.requested_readbacks.push((id, rect));
+        id
+    }
+
+    /// Hands the pixels of the readbacks that have finished to the readback
+    /// handler. `render()` does this too, so this only needs to be called to
+    /// collect them sooner, or, with `wait` set, to block until all readbacks
+    /// that were started have finished.
+    pub fn poll_readbacks(&mut self, wait: bool) {
+        let pending_readbacks = mem::replace(&mut self.pending_readbacks, Vec::new());
+        for (id, rect, origin, readback) in pending_readbacks {
+            match self.device.end_readback(readback, wait) {
+                ReadbackStatus::Complete(pixels) => {
+                    if self.readback_handler.is_none() {
+                        self.add_warning(RendererWarning::MissingReadbackHandler);
+                        continue;
+                    }
+                    let pixels = match origin {
+                        SurfaceOrigin::BottomLeft => {
+                            let surface_size = self.surface_transform.transform_size(rect.size);
+                            flip_rows(&pixels, surface_size.width as usize * 4)
+                        }
+                        SurfaceOrigin::TopLeft => pixels,
+                    };
+                    let pixels = self.surface_transform.untransform_pixels(pixels, rect.size);
+                    self.readback_handler.as_mut().unwrap().readback_complete(id, rect, pixels);
+                }
+                ReadbackStatus::Pending(readback) => {
+                    self.pending_readbacks.push((id, rect, origin, readback));
+                }
+                ReadbackStatus::Failed => {
+                    self.add_warning(RendererWarning::ReadbackFailed);
+                }
+            }
+        }
+
+        let pending_snapshots = mem::replace(&mut self.pending_snapshots, Vec::new());
+        for (id, size, readback) in pending_snapshots {
+            match self.device.end_readback(readback, wait) {
+                ReadbackStatus::Complete(pixels) => {
+                    if self.readback_handler.is_none() {
+                        self.add_warning(RendererWarning::MissingReadbackHandler);
+                        continue;
+                    }
+                    self.readback_handler.as_mut().unwrap().snapshot_complete(id, size, pixels);
+                }
+                ReadbackStatus::Pending(readback) => {
+                    self.pending_snapshots.push((id, size, readback));
+                }
+                ReadbackStatus::Failed => {
+                    self.add_warning(RendererWarning::ReadbackFailed);
+                }
+            }
+        }
+    }
+
+    fn begin_requested_readbacks(&mut self, framebuffer_size: &DeviceUintSize) {
+        if self.requested_readbacks.is_empty() {
+            return;
+        }
+
+        let layout_size = self.surface_transform.transform_size(*framebuffer_size);
+        let layout_rect = DeviceUintRect::new(DeviceUintPoint::zero(), layout_size);
+        self.device.bind_read_target(None);
+
+        for (id, rect) in self.requested_readbacks.drain(..) {
+            // Parts of the rect outside of the framebuffer are left out, and
+            // the handler is told about the rect that was actually read.
+            let rect = rect.intersection(&layout_rect).unwrap_or(DeviceUintRect::zero());
+            let surface_rect = DeviceIntRect::new(
+                DeviceIntPoint::new(rect.origin.x as i32, rect.origin.y as i32),
+                DeviceIntSize::new(rect.size.width as i32, rect.size.height as i32));
+            let surface_rect = self.surface_transform.transform_rect(&surface_rect, layout_size);
+
+            let gl_rect = self.output_origin.to_gl_rect(&surface_rect,
+                                                        framebuffer_size.height as i32);
+
+            let readback = self.device.begin_readback(gl_rect);
+            self.pending_readbacks.push((id, rect, self.output_origin, readback));
+        }
+    }
+
     /// Retrieve (and clear) the current list of recorded frame profiles.
     pub fn get_frame_profiles(&mut self) -> (Vec<CpuProfile>, Vec<GpuProfile>) {
         let cpu_profiles = self.cpu_profiles.drain(..).collect();
@@ -1074,14 +2504,89 @@ impl Renderer {
         (cpu_profiles, gpu_profiles)
     }
 
+    /// Returns the video primitives that were left out of the last rendered
+    /// frame, for the embedder to present in hardware overlay planes.
+    ///
+    /// Always empty unless `RendererOptions::enable_overlay_promotion` is set.
+    pub fn get_overlay_candidates(&self) -> &[OverlayCandidate] {
+        &self.overlay_candidates
+    }
+
+    /// Renders the current frame once per view, e.g. into the left and
+    /// right halves of the framebuffer for stereo output.
+    ///
+    /// The frame is only built once. Intermediate render targets are shared
+    /// between the views, and only the final composite is repeated with each
+    /// view's transform and viewport.
+    pub fn render_views(&mut self,
+                        framebuffer_size: DeviceUintSize,
+                        views: &[RenderView]) -> RenderResult {
+        self.views = views.to_vec();
+        // The views themselves aren't compared with the last ones.
+        self.frame_dirty = true;
+        let result = self.render(framebuffer_size);
+        self.views.clear();
+        result
+    }
+
     /// Renders the current frame.
     ///
     /// A Frame is supplied by calling [`set_display_list()`][newframe].
     /// [newframe]: ../../webrender_traits/struct.RenderApi.html#method.set_display_list
-    pub fn render(&mut self, framebuffer_size: DeviceUintSize) {
+    pub fn render(&mut self, framebuffer_size: DeviceUintSize) -> RenderResult {
         profile_scope!("render");
 
+        self.poll_readbacks(false);
+        self.poll_completed_frames(false);
+        self.damage_rects.clear();
+        let buffer_age = self.buffer_age.take();
+
+        // A minimized window or collapsed panel has no pixels to draw into,
+        // and the projection and render targets can't be set up for an
+        // empty framebuffer. Keep the current frame, and any texture updates
+        // queued with it, until the framebuffer has a size again.
+        if framebuffer_size.width == 0 || framebuffer_size.height == 0 {
+            return RenderResult::Skipped;
+        }
+
+        if self.skip_unchanged_frames && !self.needs_composite(framebuffer_size) {
+            return RenderResult::Skipped;
+        }
+        let mut result = RenderResult::Skipped;
+
+        // When rendering into a native compositor surface, the OS keeps
+        // presenting the previous content, so there's nothing to do unless
+        // the frame changed (the profiler overlay updates every frame).
+        // Promoted images are drawn into their own surfaces on every call,
+        // since their contents change without a new frame being built, and
+        // only changing them doesn't need the main surface to be redrawn.
+        let use_native_surface = self.native_compositor.is_some() &&
+                                 self.current_frame.is_some();
+        self.output_origin = self.framebuffer_origin;
+        if use_native_surface {
+            self.update_compositor_surfaces();
+
+            let damage_rects = if self.frame_dirty {
+                self.find_current_damage(framebuffer_size)
+            } else {
+                Vec::new()
+            };
+            if damage_rects.is_empty() && !self.enable_profiler {
+                self.frame_dirty = false;
+                self.native_compositor.as_mut().unwrap().commit();
+                return RenderResult::Skipped;
+            }
+            self.bind_native_surface(framebuffer_size, &damage_rects);
+        }
+
         if let Some(mut frame) = self.current_frame.take() {
+            // Make the GPU wait until the WebGL contexts are done
+            // producing the textures this frame samples from.
+            for fence in frame.webgl_fences.drain(..) {
+                self.device.gl().wait_sync(fence.0, 0, gl::TIMEOUT_IGNORED);
+                self.device.gl().delete_sync(fence.0);
+            }
+
             if let Some(ref mut frame) = frame.frame {
                 let mut profile_timers = RendererProfileTimers::new();
 
@@ -1097,6 +2602,15 @@ impl Renderer {
                     profile_timers.gpu_samples = samples;
                 }
 
+                let damage_rects = self.find_damage(frame, framebuffer_size);
+                self.repaint_rect = self.find_repaint_rect(buffer_age,
+                                                           &damage_rects,
+                                                           framebuffer_size);
+                if let Some(dirty_rect) = self.native_dirty_rect.take() {
+                    self.repaint_rect = Some(self.output_origin.to_gl_rect(&dirty_rect,
+                                                                           framebuffer_size.height as i32));
+                }
+
                 let cpu_frame_id = profile_timers.cpu_time.profile(|| {
                     let cpu_frame_id = self.device.begin_frame(frame.device_pixel_ratio);
                     self.gpu_profile.begin_frame(cpu_frame_id);
@@ -1111,7 +2625,11 @@ impl Renderer {
                         self.update_texture_cache();
                     }
 
+                    self.begin_output_pass(&framebuffer_size);
                     self.draw_tile_frame(frame, &framebuffer_size);
+                    self.task_cache_frame.store(frame.render_task_cache_frame, Ordering::Release);
+                    self.end_output_pass(&framebuffer_size);
+                    self.begin_requested_readbacks(&framebuffer_size);
 
                     self.gpu_profile.end_frame();
                     cpu_frame_id
@@ -1145,14 +2663,523 @@ impl Renderer {
 
                 let debug_size = DeviceUintSize::new(framebuffer_size.width as u32,
                                                      framebuffer_size.height as u32);
-                self.debug.render(&mut self.device, &debug_size);
+                self.debug.render(&mut self.device, &debug_size, self.output_origin);
                 self.device.end_frame();
                 self.last_time = current_time;
+
+                // Fences are only inserted while there's a handler to tell.
+                let rendered_frame_id = RenderedFrameId(self.next_rendered_frame_id);
+                self.next_rendered_frame_id += 1;
+                if self.frame_complete_handler.is_some() {
+                    let fence = self.device.insert_fence();
+                    self.pending_frame_fences.push_back((rendered_frame_id, fence));
+                }
+
+                self.repaint_rect = None;
+                self.update_damage(frame, framebuffer_size, damage_rects);
+                self.frame_dirty = false;
+                self.last_framebuffer_size = Some(framebuffer_size);
+                result = RenderResult::Composited;
+
+                if let Some(ref mut statistics_dump) = self.statistics_dump {
+                    if let Err(err) = statistics_dump.write(&frame.statistics) {
+                        warn!("Failed to write pipeline statistics: {}", err);
+                    }
+                }
             }
 
             // Restore frame - avoid borrow checker!
             self.current_frame = Some(frame);
         }
+
+        if use_native_surface {
+            self.unbind_native_surface();
+        }
+        result
+    }
+
+    /// Whether drawing the current frame could give anything other than
+    /// what the framebuffer got from the last `render`. Frames that sample
+    /// external images are always drawn, as their contents can change
+    /// without a new frame, and so are frames whose pixels were asked for
+    /// or that a post-process handler may draw differently.
+    fn needs_composite(&self, framebuffer_size: DeviceUintSize) -> bool {
+        let frame = match self.current_frame {
+            Some(RendererFrame { frame: Some(ref frame), .. }) => frame,
+            _ => return true,
+        };
+        self.frame_dirty ||
+        self.last_framebuffer_size != Some(framebuffer_size) ||
+        self.enable_profiler ||
+        self.native_compositor.is_some() ||
+        self.post_process_handler.is_some() ||
+        !self.requested_readbacks.is_empty() ||
+        !frame.deferred_resolves.is_empty()
+    }
+
+    /// Returns the parts of the framebuffer that the last call to `render`
+    /// changed, in device pixels with the origin at the top left. This is
+    /// empty if nothing changed. The whole framebuffer is damaged by the
+    /// first frame, by a resize, and while the profiler or the render target
+    /// debug view is shown. The rects are in framebuffer space, after the
+    /// `surface_transform`.
+    ///
+    /// These are the rects to pass to `eglSwapBuffersWithDamageKHR`, or as
+    /// the dirty rects of `IDXGISwapChain1::Present1`, since the rest of the
+    /// window shows the same content as before. The damage is relative to
+    /// the previously rendered frame; a back buffer of another age is made
+    /// up to date by `render` when `set_buffer_age` is called first.
+    pub fn get_damage_rects(&self) -> &[DeviceIntRect] {
+        &self.damage_rects
+    }
+
+    /// Tells the renderer how many frames ago the back buffer that the next
+    /// `render` draws into was last drawn, as reported by
+    /// `EGL_EXT_buffer_age` or `GLX_EXT_buffer_age`, with 1 for the buffer
+    /// drawn by the previous `render`. The renderer then only redraws the
+    /// parts of the framebuffer that changed since, and leaves the rest of
+    /// the buffer as it is. An age of 0 means the contents are undefined.
+    ///
+    /// This applies to the next `render` only. Without it, or with an age
+    /// the renderer no longer has the damage for, the whole framebuffer
+    /// is drawn. So is a frame drawn through an output pass, a native
+    /// compositor surface or several views.
+    pub fn set_buffer_age(&mut self, age: u32) {
+        self.buffer_age = Some(age);
+    }
+
+    /// The part of the framebuffer, in GL coordinates, that a back buffer
+    /// of `buffer_age` needs redrawn, if it isn't all of it.
+    fn find_repaint_rect(&self,
+                         buffer_age: Option<u32>,
+                         damage_rects: &[DeviceIntRect],
+                         framebuffer_size: DeviceUintSize) -> Option<DeviceIntRect> {
+        let buffer_age = match buffer_age {
+            Some(age) if age > 0 && age <= MAX_BUFFER_AGE => age as usize,
+            _ => return None,
+        };
+        if buffer_age - 1 > self.damage_history.len() ||
+           self.has_output_color_pass() ||
+           self.post_process_handler.is_some() ||
+           self.native_compositor.is_some() ||
+           !self.views.is_empty() {
+            return None;
+        }
+
+        // The buffer misses the changes of the frames drawn since it was
+        // last presented, on top of those of this frame.
+        let missed_damage = self.damage_history.iter()
+                                               .take(buffer_age - 1)
+                                               .flat_map(|rects| rects.iter());
+        let repaint_rect = damage_rects.iter()
+                                       .chain(missed_damage)
+                                       .fold(DeviceIntRect::zero(), |union, rect| union.union(rect));
+        Some(self.output_origin.to_gl_rect(&repaint_rect, framebuffer_size.height as i32))
+    }
+
+    /// The damage that drawing `frame` causes, relative to the previously
+    /// rendered frame.
+    fn find_damage(&self, frame: &Frame, framebuffer_size: DeviceUintSize) -> Vec<DeviceIntRect> {
+        // The damage is found in layout space, and then transformed.
+        let layout_size = self.surface_transform.transform_size(framebuffer_size);
+        let layout_rect = DeviceIntRect::new(DeviceIntPoint::zero(),
+                                             DeviceIntSize::new(layout_size.width as i32,
+                                                                layout_size.height as i32));
+
+        // Overlays are drawn over the whole frame, and late-latched poses
+        // can move layers without the frame knowing.
+        let full_damage = self.enable_profiler ||
+                          self.composite_zoom.is_some() ||
+                          self.debug_flags.contains(RENDER_TARGET_DBG) ||
+                          !frame.late_latched_layers.is_empty();
+
+        let damage_rects = match self.damage_state {
+            Some(ref previous) if !full_damage &&
+                                  previous.framebuffer_size == framebuffer_size &&
+                                  previous.device_pixel_ratio == frame.device_pixel_ratio &&
+                                  previous.background_color == frame.background_color => {
+                find_damage_rects(&previous.items, &frame.damage_items, &layout_rect)
+            }
+            _ => vec![layout_rect],
+        };
+
+        damage_rects.iter()
+                    .map(|rect| self.surface_transform.transform_rect(rect, layout_size))
+                    .collect()
+    }
+
+    fn find_current_damage(&self, framebuffer_size: DeviceUintSize) -> Vec<DeviceIntRect> {
+        match self.current_frame {
+            Some(RendererFrame { frame: Some(ref frame), .. }) => {
+                self.find_damage(frame, framebuffer_size)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn update_damage(&mut self,
+                     frame: &Frame,
+                     framebuffer_size: DeviceUintSize,
+                     damage_rects: Vec<DeviceIntRect>) {
+        self.damage_history.push_front(damage_rects.clone());
+        self.damage_history.truncate(MAX_BUFFER_AGE as usize - 1);
+        self.damage_rects = damage_rects;
+        self.damage_state = Some(DamageState {
+            framebuffer_size: framebuffer_size,
+            device_pixel_ratio: frame.device_pixel_ratio,
+            background_color: frame.background_color,
+            items: frame.damage_items.clone(),
+        });
+    }
+
+    /// Draws the images of the current frame that were promoted to
+    /// compositor surfaces, and places the surfaces over the main one.
+    fn update_compositor_surfaces(&mut self) {
+        let surfaces: Vec<CompositorSurface> = match self.current_frame {
+            Some(RendererFrame { frame: Some(ref frame), .. }) => frame.compositor_surfaces.clone(),
+            _ => Vec::new(),
+        };
+
+        // The WebGL contexts have to be done with their textures first.
+        if let Some(ref mut frame) = self.current_frame {
+            for fence in frame.webgl_fences.drain(..) {
+                self.device.gl().wait_sync(fence.0, 0, gl::TIMEOUT_IGNORED);
+                self.device.gl().delete_sync(fence.0);
+            }
+        }
+
+        while self.compositor_surfaces.len() > surfaces.len() {
+            let (surface_id, _) = self.compositor_surfaces.pop().unwrap();
+            self.native_compositor.as_mut().unwrap().destroy_surface(surface_id);
+        }
+
+        for (index, surface) in surfaces.iter().enumerate() {
+            let size = DeviceUintSize::new(surface.rect.size.width as u32,
+                                           surface.rect.size.height as u32);
+
+            // Surfaces are reused in order, and recreated when the size of
+            // their image changes.
+            let existing_surface = self.compositor_surfaces.get(index).cloned();
+            let surface_id = match existing_surface {
+                Some((surface_id, surface_size)) if surface_size == size => surface_id,
+                existing_surface => {
+                    let compositor = self.native_compositor.as_mut().unwrap();
+                    if let Some((old_surface_id, _)) = existing_surface {
+                        compositor.destroy_surface(old_surface_id);
+                    }
+                    let surface_id = NativeSurfaceId(self.next_native_surface_id);
+                    self.next_native_surface_id += 1;
+                    compositor.create_surface(surface_id, size);
+                    if index < self.compositor_surfaces.len() {
+                        self.compositor_surfaces[index] = (surface_id, size);
+                    } else {
+                        self.compositor_surfaces.push((surface_id, size));
+                    }
+                    surface_id
+                }
+            };
+
+            // Resolved the same way as in update_deferred_resolves(), but
+            // the image is only locked while it's being copied.
+            let (texture_id, uv0, uv1, locked_image) = match surface.texture {
+                SourceTexture::WebGL(context_id) => {
+                    let (texture_id, size) = match self.webgl_textures.get(&context_id) {
+                        Some(&webgl_texture) => webgl_texture,
+                        None => continue,
+                    };
+                    // WebGL content is drawn upside down.
+                    (TextureId::new(texture_id, TextureTarget::Default),
+                     DevicePoint::new(0.0, size.height as f32),
+                     DevicePoint::new(size.width as f32, 0.0),
+                     None)
+                }
+                SourceTexture::External(ext_image) => {
+                    let texture_target = match ext_image.image_type {
+                        ExternalImageType::Texture2DHandle => TextureTarget::Default,
+                        ExternalImageType::TextureRectHandle => TextureTarget::Rect,
+                        ExternalImageType::ExternalBuffer => unreachable!(),
+                    };
+                    if self.external_image_handler.is_none() {
+                        self.add_warning(RendererWarning::MissingExternalImageHandler(ext_image.id));
+                        continue;
+                    }
+                    let image = self.external_image_handler.as_mut().unwrap().lock(ext_image.id);
+                    let texture_id = match image.source {
+                        ExternalImageSource::NativeTexture(texture_id) => TextureId::new(texture_id, texture_target),
+                        _ => panic!("No native texture found."),
+                    };
+                    (texture_id,
+                     DevicePoint::new(image.u0, image.v0),
+                     DevicePoint::new(image.u1, image.v1),
+                     Some(ext_image.id))
+                }
+                SourceTexture::TextureCache(..) | SourceTexture::Invalid => unreachable!(),
+            };
+
+            self.native_compositor.as_mut().unwrap()
+                .bind(surface_id, DeviceUintRect::new(DeviceUintPoint::zero(), size));
+            self.device.blit_external_texture(texture_id,
+                                              uv0,
+                                              uv1,
+                                              DeviceIntRect::new(DeviceIntPoint::zero(), surface.rect.size),
+                                              texture_filter_for_rendering(surface.image_rendering));
+
+            let compositor = self.native_compositor.as_mut().unwrap();
+            compositor.unbind();
+            compositor.position_surface(surface_id, surface.rect, surface.clip_rect);
+
+            if let Some(id) = locked_image {
+                self.external_image_handler.as_mut().unwrap().unlock(id);
+            }
+        }
+    }
+
+    /// Copies a pipeline drawn in `rect` of a render target to the external
+    /// image the embedder set as its render target.
+    fn copy_to_render_target(&mut self,
+                             src_texture: (TextureId, i32),
+                             rect: DeviceIntRect,
+                             render_target: ExternalImageData) {
+        // The backend drops render targets that aren't textures, but the
+        // copy is skipped rather than trusting that.
+        let texture_target = match render_target.image_type {
+            ExternalImageType::Texture2DHandle => TextureTarget::Default,
+            ExternalImageType::TextureRectHandle => TextureTarget::Rect,
+            ExternalImageType::ExternalBuffer => {
+                self.add_warning(RendererWarning::InvalidRenderTarget(render_target.id));
+                return;
+            }
+        };
+
+        if self.external_image_handler.is_none() {
+            self.add_warning(RendererWarning::MissingExternalImageHandler(render_target.id));
+            return;
+        }
+
+        let target = {
+            let image = self.external_image_handler.as_mut().unwrap().lock(render_target.id);
+            match image.source {
+                ExternalImageSource::NativeTexture(texture_id) => {
+                    let dest_rect = DeviceIntRect::new(DeviceIntPoint::new(image.u0 as i32, image.v0 as i32),
+                                                       DeviceIntSize::new((image.u1 - image.u0) as i32,
+                                                                          (image.v1 - image.v0) as i32));
+                    Some((TextureId::new(texture_id, texture_target), dest_rect))
+                }
+                _ => None,
+            }
+        };
+
+        match target {
+            Some((texture_id, dest_rect)) => {
+                self.device.blit_render_target_to_texture(src_texture, rect, texture_id, dest_rect);
+            }
+            None => self.add_warning(RendererWarning::InvalidRenderTarget(render_target.id)),
+        }
+        self.external_image_handler.as_mut().unwrap().unlock(render_target.id);
+    }
+
+    fn has_output_color_pass(&self) -> bool {
+        self.output_color_transform.is_some() || self.hdr_output.is_some()
+    }
+
+    /// Makes the following draws to the framebuffer go to the output
+    /// texture instead, when an output color transform, HDR output or
+    /// post-process handler is set.
+    fn begin_output_pass(&mut self, framebuffer_size: &DeviceUintSize) {
+        if !self.has_output_color_pass() && self.post_process_handler.is_none() {
+            return;
+        }
+
+        // HDR external images can draw values above 1, which an RGBA8
+        // texture would clip.
+        let format = if self.hdr_output.is_some() {
+            ImageFormat::RGBAF32
+        } else {
+            ImageFormat::RGBA8
+        };
+
+        let texture_id = prepare_output_texture(&mut self.device,
+                                                &mut self.output_texture,
+                                                framebuffer_size,
+                                                format);
+
+        self.device.redirect_default_target(Some((texture_id, 0)));
+        self.device.bind_draw_target(None, Some(*framebuffer_size));
+    }
+
+    /// Draws the output texture to the framebuffer through the post-process
+    /// handler and the output color transform or HDR mapping, in that
+    /// order. The texture has the size and orientation of the framebuffer,
+    /// so each pixel stays where it is.
+    fn end_output_pass(&mut self, framebuffer_size: &DeviceUintSize) {
+        let (mut texture_id, format) = match self.output_texture {
+            Some((texture_id, _, format)) if self.has_output_color_pass() ||
+                                             self.post_process_handler.is_some() => {
+                (texture_id, format)
+            }
+            _ => return,
+        };
+
+        self.device.redirect_default_target(None);
+
+        if self.post_process_handler.is_some() {
+            let _gm = self.gpu_profile.add_marker(GPU_TAG_POST_PROCESS);
+
+            let target = if self.has_output_color_pass() {
+                let target_id = prepare_output_texture(&mut self.device,
+                                                       &mut self.post_process_texture,
+                                                       framebuffer_size,
+                                                       format);
+                Some((target_id, 0))
+            } else {
+                None
+            };
+            self.device.bind_draw_target(target, Some(*framebuffer_size));
+            self.post_process_handler
+                .as_mut()
+                .unwrap()
+                .post_process(texture_id.name(), *framebuffer_size, format);
+            self.device.reset_state();
+
+            match target {
+                Some((target_id, _)) => texture_id = target_id,
+                None => return,
+            }
+        }
+
+        let _gm = self.gpu_profile.add_marker(GPU_TAG_OUTPUT);
+
+        self.device.bind_draw_target(None, Some(*framebuffer_size));
+        self.device.disable_depth();
+        self.device.disable_scissor();
+        self.device.set_blend(false);
+
+        let projection = Matrix4D::ortho(0.0,
+                                         1.0,
+                                         0.0,
+                                         1.0,
+                                         ORTHO_NEAR_PLANE,
+                                         ORTHO_FAR_PLANE);
+
+        match self.output_color_transform {
+            Some(OutputColorTransform::Matrix { ref matrix, gamma }) => {
+                let shader = self.ps_output_matrix.get(&mut self.device, None).unwrap();
+                self.device.bind_program(shader, &projection);
+                let u_color_matrix = self.device.get_uniform_location(shader, "uColorMatrix");
+                self.device.set_uniform_matrix_3f(u_color_matrix, matrix);
+                let u_output_gamma = self.device.get_uniform_location(shader, "uOutputGamma");
+                self.device.set_uniform_1f(u_output_gamma, gamma);
+            }
+            Some(OutputColorTransform::Lut { size, ref data }) => {
+                let lut_texture_id = match self.output_lut_texture_id {
+                    Some(texture_id) => texture_id,
+                    None => {
+                        let texture_id = self.device.create_texture_ids(1, TextureTarget::Default)[0];
+                        self.output_lut_texture_id = Some(texture_id);
+                        texture_id
+                    }
+                };
+                // The blue slices of the table are stacked vertically, so
+                // the entries are uploaded in the order they're given.
+                if self.output_lut_dirty {
+                    self.device.init_texture(lut_texture_id,
+                                             size,
+                                             size * size,
+                                             ImageFormat::RGB8,
+                                             TextureFilter::Linear,
+                                             RenderTargetMode::None,
+                                             Some(&data[..]));
+                    self.output_lut_dirty = false;
+                }
+
+                let shader = self.ps_output_lut.get(&mut self.device, None).unwrap();
+                self.device.bind_program(shader, &projection);
+                let u_lut_size = self.device.get_uniform_location(shader, "uLutSize");
+                self.device.set_uniform_1f(u_lut_size, size as f32);
+                self.device.bind_texture(TextureSampler::Color0, lut_texture_id);
+            }
+            None => {
+                // HDR output is set, the two are exclusive.
+                let hdr_output = self.hdr_output.unwrap();
+                let shader = match hdr_output.format {
+                    HdrOutputFormat::ScRgbLinear => self.ps_output_hdr.get(&mut self.device, None).unwrap(),
+                    HdrOutputFormat::Hdr10 => self.ps_output_hdr10.get(&mut self.device, None).unwrap(),
+                };
+                self.device.bind_program(shader, &projection);
+                let primaries = hdr_output.format.primaries_matrix(self.color_space);
+                let u_output_primaries = self.device.get_uniform_location(shader, "uOutputPrimaries");
+                self.device.set_uniform_matrix_3f(u_output_primaries, &primaries);
+                let u_sdr_white_level = self.device.get_uniform_location(shader, "uSdrWhiteLevel");
+                self.device.set_uniform_1f(u_sdr_white_level, hdr_output.sdr_white_level);
+                let u_tone_map_knee = self.device.get_uniform_location(shader, "uToneMapKnee");
+                self.device.set_uniform_1f(u_tone_map_knee, hdr_output.tone_map_knee());
+                let u_max_luminance = self.device.get_uniform_location(shader, "uMaxLuminance");
+                self.device.set_uniform_1f(u_max_luminance, hdr_output.max_luminance);
+            }
+        }
+
+        self.device.bind_texture(TextureSampler::CacheRGBA8, texture_id);
+        self.device.bind_vao(self.output_vao_id);
+        self.device.draw_triangles_u32(0, 6);
+        self.profile_counters.draw_calls.inc();
+    }
+
+    /// Binds the native surface for drawing. Only the union of
+    /// `damage_rects` is marked dirty, as the surface keeps what the
+    /// previous frame drew, unless the surface is new or the frame has to
+    /// be drawn whole.
+    fn bind_native_surface(&mut self,
+                           framebuffer_size: DeviceUintSize,
+                           damage_rects: &[DeviceIntRect]) {
+        let full_rect = DeviceIntRect::new(DeviceIntPoint::zero(),
+                                           DeviceIntSize::new(framebuffer_size.width as i32,
+                                                              framebuffer_size.height as i32));
+        let draws_whole_frame = self.has_output_color_pass() ||
+                                self.post_process_handler.is_some() ||
+                                !self.views.is_empty();
+        let damage_rect = damage_rects.iter()
+                                      .fold(DeviceIntRect::zero(), |union, rect| union.union(rect))
+                                      .intersection(&full_rect);
+        let compositor = self.native_compositor.as_mut().unwrap();
+
+        // (Re)create the surface if the window size changed.
+        let (surface_id, dirty_rect) = match self.native_surface {
+            Some((surface_id, size)) if size == framebuffer_size && !draws_whole_frame => {
+                (surface_id, damage_rect.unwrap_or(full_rect))
+            }
+            Some((surface_id, size)) if size == framebuffer_size => (surface_id, full_rect),
+            previous_surface => {
+                if let Some((old_surface_id, _)) = previous_surface {
+                    compositor.destroy_surface(old_surface_id);
+                }
+                let surface_id = NativeSurfaceId(self.next_native_surface_id);
+                self.next_native_surface_id += 1;
+                compositor.create_surface(surface_id, framebuffer_size);
+                self.native_surface = Some((surface_id, framebuffer_size));
+                (surface_id, full_rect)
+            }
+        };
+
+        // The compositor makes the surface's framebuffer current, which
+        // the device then picks up as the default target in begin_frame().
+        compositor.bind(surface_id,
+                        DeviceUintRect::new(DeviceUintPoint::new(dirty_rect.origin.x as u32,
+                                                                 dirty_rect.origin.y as u32),
+                                            DeviceUintSize::new(dirty_rect.size.width as u32,
+                                                                dirty_rect.size.height as u32)));
+        self.output_origin = compositor.surface_origin(surface_id);
+        self.native_dirty_rect = if dirty_rect == full_rect {
+            None
+        } else {
+            Some(dirty_rect)
+        };
+    }
+
+    fn unbind_native_surface(&mut self) {
+        let compositor = self.native_compositor.as_mut().unwrap();
+        compositor.unbind();
+        compositor.commit();
+        self.frame_dirty = false;
     }
 
     pub fn layers_are_bouncing_back(&self) -> bool {
@@ -1162,6 +3189,17 @@ impl Renderer {
         }
     }
 
+    /// Returns true while a scroll started with an animation through
+    /// `RenderApi::scroll_node_with_id` hasn't reached its destination.
+    /// The embedder should keep calling `tick_scrolling_bounce_animations`
+    /// each frame until this is false.
+    pub fn scroll_animations_running(&self) -> bool {
+        match self.current_frame {
+            None => false,
+            Some(ref current_frame) => current_frame.scroll_animations_running,
+        }
+    }
+
 /*
     fn update_shaders(&mut self) {
         let update_uniforms = !self.pending_shader_updates.is_empty();
@@ -1177,49 +3215,75 @@ impl Renderer {
     }
 */
 
+    fn cache_texture_id(&self, id: CacheTextureId) -> TextureId {
+        self.cache_texture_id_map[id.0].expect("BUG: unknown cache texture")
+    }
+
     fn update_texture_cache(&mut self) {
         let _gm = GpuMarker::new(self.device.rc_gl(), "texture cache update");
         let mut pending_texture_updates = mem::replace(&mut self.pending_texture_updates, vec![]);
         for update_list in pending_texture_updates.drain(..) {
             for update in update_list.updates {
                 match update.op {
-                    TextureUpdateOp::Create { width, height, format, filter, mode, data } => {
+                    TextureUpdateOp::Create { width, height, format, filter, mode, data, stride } => {
                         let CacheTextureId(cache_texture_index) = update.id;
-                        if self.cache_texture_id_map.len() == cache_texture_index {
-                            // Create a new native texture, as requested by the texture cache.
-                            let texture_id = self.device
-                                                 .create_texture_ids(1, TextureTarget::Default)[0];
-                            self.cache_texture_id_map.push(texture_id);
+                        if self.cache_texture_id_map.len() <= cache_texture_index {
+                            self.cache_texture_id_map.resize(cache_texture_index + 1, None);
                         }
-                        let texture_id = self.cache_texture_id_map[cache_texture_index];
+                        let texture_id = match self.cache_texture_id_map[cache_texture_index] {
+                            Some(texture_id) => texture_id,
+                            None => {
+                                // Create a new native texture, as requested by the texture cache.
+                                let texture_id = self.device
+                                                     .create_texture_ids(1, TextureTarget::Default)[0];
+                                self.cache_texture_id_map[cache_texture_index] = Some(texture_id);
+                                texture_id
+                            }
+                        };
 
                         if let Some(image) = data {
                             match image {
                                 ImageData::Raw(raw) => {
-                                    self.device.init_texture(texture_id,
-                                                             width,
-                                                             height,
-                                                             format,
-                                                             filter,
-                                                             mode,
-                                                             Some(raw.as_slice()));
+                                    self.device.init_texture_with_stride(texture_id,
+                                                                         width,
+                                                                         height,
+                                                                         format,
+                                                                         filter,
+                                                                         mode,
+                                                                         stride,
+                                                                         Some(raw.as_slice()));
                                 }
                                 ImageData::External(ext_image) => {
                                     match ext_image.image_type {
+                                        ExternalImageType::ExternalBuffer if self.external_image_handler.is_none() => {
+                                            // Leave a transparent placeholder rather than
+                                            // uninitialized texture memory.
+                                            self.add_warning(RendererWarning::MissingExternalImageHandler(ext_image.id));
+                                            let bpp = format.bytes_per_pixel().unwrap_or(4);
+                                            let placeholder = vec![0; (width * height * bpp) as usize];
+                                            self.device.init_texture(texture_id,
+                                                                     width,
+                                                                     height,
+                                                                     format,
+                                                                     filter,
+                                                                     mode,
+                                                                     Some(placeholder.as_slice()));
+                                        }
                                         ExternalImageType::ExternalBuffer => {
                                             let handler = self.external_image_handler
                                                               .as_mut()
-                                                              .expect("Found external image, but no handler set!");
+                                                              .unwrap();
 
                                             match handler.lock(ext_image.id).source {
                                                 ExternalImageSource::RawData(raw) => {
-                                                    self.device.init_texture(texture_id,
-                                                                             width,
-                                                                             height,
-                                                                             format,
-                                                                             filter,
-                                                                             mode,
-                                                                             Some(raw));
+                                                    self.device.init_texture_with_stride(texture_id,
+                                                                                         width,
+                                                                                         height,
+                                                                                         format,
+                                                                                         filter,
+                                                                                         mode,
+                                                                                         stride,
+                                                                                         Some(raw));
                                                 }
                                                 _ => panic!("No external buffer found"),
                                             };
@@ -1245,7 +3309,7 @@ impl Renderer {
                         }
                     }
                     TextureUpdateOp::Grow { width, height, format, filter, mode } => {
-                        let texture_id = self.cache_texture_id_map[update.id.0];
+                        let texture_id = self.cache_texture_id(update.id);
                         self.device.resize_texture(texture_id,
                                                    width,
                                                    height,
@@ -1254,19 +3318,23 @@ impl Renderer {
                                                    mode);
                     }
                     TextureUpdateOp::Update { page_pos_x, page_pos_y, width, height, data, stride, offset } => {
-                        let texture_id = self.cache_texture_id_map[update.id.0];
+                        let texture_id = self.cache_texture_id(update.id);
                         self.device.update_texture(texture_id,
                                                    page_pos_x,
                                                    page_pos_y,
                                                    width, height, stride,
                                                    &data[offset as usize..]);
                     }
+                    TextureUpdateOp::UpdateForExternalBuffer { id, .. } if self.external_image_handler.is_none() => {
+                        // Keep the previous contents of the region.
+                        self.add_warning(RendererWarning::MissingExternalImageHandler(id));
+                    }
                     TextureUpdateOp::UpdateForExternalBuffer { rect, id, stride } => {
+                        let cached_id = self.cache_texture_id(update.id);
                         let handler = self.external_image_handler
                                           .as_mut()
-                                          .expect("Found external image, but no handler set!");
+                                          .unwrap();
                         let device = &mut self.device;
-                        let cached_id = self.cache_texture_id_map[update.id.0];
 
                         match handler.lock(id).source {
                             ExternalImageSource::RawData(data) => {
@@ -1282,8 +3350,13 @@ impl Renderer {
                         handler.unlock(id);
                     }
                     TextureUpdateOp::Free => {
-                        let texture_id = self.cache_texture_id_map[update.id.0];
-                        self.device.deinit_texture(texture_id);
+                        let texture_id = self.cache_texture_id_map[update.id.0]
+                                             .take()
+                                             .expect("BUG: freeing an unknown cache texture");
+                        self.device.delete_texture(texture_id);
+                        while let Some(&None) = self.cache_texture_id_map.last() {
+                            self.cache_texture_id_map.pop();
+                        }
                     }
                 }
             }
@@ -1307,10 +3380,17 @@ impl Renderer {
         // TODO: this probably isn't the best place for this.
         self.device.bind_texture(TextureSampler::Dither, self.dither_matrix_texture_id);
 
-        self.device.update_vao_instances(vao, data, VertexUsageHint::Stream);
-        self.device.draw_indexed_triangles_instanced_u16(6, data.len() as i32);
+        let instances_per_draw = if self.device.driver_workarounds().disable_instancing {
+            1
+        } else {
+            cmp::max(data.len(), 1)
+        };
+        for instances in data.chunks(instances_per_draw) {
+            self.device.update_vao_instances(vao, instances, VertexUsageHint::Stream);
+            self.device.draw_indexed_triangles_instanced_u16(6, instances.len() as i32);
+            self.profile_counters.draw_calls.inc();
+        }
         self.profile_counters.vertices.add(6 * data.len());
-        self.profile_counters.draw_calls.inc();
     }
 
     fn submit_batch(&mut self,
@@ -1319,7 +3399,8 @@ impl Renderer {
                     render_task_data: &Vec<RenderTaskData>,
                     cache_texture: TextureId,
                     render_target: Option<(TextureId, i32)>,
-                    target_dimensions: DeviceUintSize) {
+                    target_dimensions: DeviceUintSize,
+                    allow_blits: bool) {
         let transform_kind = batch.key.flags.transform_kind();
         let needs_clipping = batch.key.flags.needs_clipping();
         debug_assert!(!needs_clipping ||
@@ -1330,63 +3411,72 @@ impl Renderer {
             PrimitiveBatchData::Instances(ref data) => {
                 let (marker, shader) = match batch.key.kind {
                     AlphaBatchKind::Composite => unreachable!(),
+                    AlphaBatchKind::Blit => unreachable!(),
                     AlphaBatchKind::HardwareComposite => {
-                        let shader = self.ps_hw_composite.get(&mut self.device);
+                        let shader = self.ps_hw_composite.get(&mut self.device, self.frame_ubo_data);
                         (GPU_TAG_PRIM_HW_COMPOSITE, shader)
                     }
                     AlphaBatchKind::Blend => {
-                        let shader = self.ps_blend.get(&mut self.device);
+                        let shader = self.ps_blend.get(&mut self.device, self.frame_ubo_data);
                         (GPU_TAG_PRIM_BLEND, shader)
                     }
                     AlphaBatchKind::Rectangle => {
                         let shader = if needs_clipping {
-                            self.ps_rectangle_clip.get(&mut self.device, transform_kind)
+                            self.ps_rectangle_clip.get(&mut self.device, transform_kind, self.frame_ubo_data)
                         } else {
-                            self.ps_rectangle.get(&mut self.device, transform_kind)
+                            self.ps_rectangle.get(&mut self.device, transform_kind, self.frame_ubo_data)
                         };
                         (GPU_TAG_PRIM_RECT, shader)
                     }
                     AlphaBatchKind::TextRun => {
                         let shader = match batch.key.blend_mode {
-                            BlendMode::Subpixel(..) => self.ps_text_run_subpixel.get(&mut self.device, transform_kind),
-                            BlendMode::Alpha | BlendMode::PremultipliedAlpha | BlendMode::None => self.ps_text_run.get(&mut self.device, transform_kind),
+                            BlendMode::Subpixel(..) => self.ps_text_run_subpixel.get(&mut self.device, transform_kind, self.frame_ubo_data),
+                            BlendMode::Alpha | BlendMode::PremultipliedAlpha | BlendMode::None => self.ps_text_run.get(&mut self.device, transform_kind, self.frame_ubo_data),
                         };
                         (GPU_TAG_PRIM_TEXT_RUN, shader)
                     }
                     AlphaBatchKind::Image => {
-                        let shader = self.ps_image.get(&mut self.device, transform_kind);
+                        let shader = self.ps_image.get(&mut self.device, transform_kind, self.frame_ubo_data);
                         (GPU_TAG_PRIM_IMAGE, shader)
                     }
                     AlphaBatchKind::ImageRect => {
-                        let shader = self.ps_image_rect.get(&mut self.device, transform_kind);
+                        let shader = self.ps_image_rect.get(&mut self.device, transform_kind, self.frame_ubo_data);
                         (GPU_TAG_PRIM_IMAGE_RECT, shader)
                     }
+                    AlphaBatchKind::AlphaImage => {
+                        let shader = self.ps_image_alpha.get(&mut self.device, transform_kind, self.frame_ubo_data);
+                        (GPU_TAG_PRIM_ALPHA_IMAGE, shader)
+                    }
                     AlphaBatchKind::YuvImage => {
-                        let shader = self.ps_yuv_image.get(&mut self.device, transform_kind);
+                        let shader = self.ps_yuv_image.get(&mut self.device, transform_kind, self.frame_ubo_data);
                         (GPU_TAG_PRIM_YUV_IMAGE, shader)
                     }
+                    AlphaBatchKind::YuvImageRect => {
+                        let shader = self.ps_yuv_image_rect.get(&mut self.device, transform_kind, self.frame_ubo_data);
+                        (GPU_TAG_PRIM_YUV_IMAGE_RECT, shader)
+                    }
                     AlphaBatchKind::Border => {
-                        let shader = self.ps_border.get(&mut self.device, transform_kind);
+                        let shader = self.ps_border.get(&mut self.device, transform_kind, self.frame_ubo_data);
                         (GPU_TAG_PRIM_BORDER, shader)
                     }
                     AlphaBatchKind::AlignedGradient => {
-                        let shader = self.ps_gradient.get(&mut self.device, transform_kind);
+                        let shader = self.ps_gradient.get(&mut self.device, transform_kind, self.frame_ubo_data);
                         (GPU_TAG_PRIM_GRADIENT, shader)
                     }
                     AlphaBatchKind::AngleGradient => {
-                        let shader = self.ps_angle_gradient.get(&mut self.device, transform_kind);
+                        let shader = self.ps_angle_gradient.get(&mut self.device, transform_kind, self.frame_ubo_data);
                         (GPU_TAG_PRIM_ANGLE_GRADIENT, shader)
                     }
                     AlphaBatchKind::RadialGradient => {
-                        let shader = self.ps_radial_gradient.get(&mut self.device, transform_kind);
+                        let shader = self.ps_radial_gradient.get(&mut self.device, transform_kind, self.frame_ubo_data);
                         (GPU_TAG_PRIM_RADIAL_GRADIENT, shader)
                     }
                     AlphaBatchKind::BoxShadow => {
-                        let shader = self.ps_box_shadow.get(&mut self.device, transform_kind);
+                        let shader = self.ps_box_shadow.get(&mut self.device, transform_kind, self.frame_ubo_data);
                         (GPU_TAG_PRIM_BOX_SHADOW, shader)
                     }
                     AlphaBatchKind::CacheImage => {
-                        let shader = self.ps_cache_image.get(&mut self.device, transform_kind);
+                        let shader = self.ps_cache_image.get(&mut self.device, transform_kind, self.frame_ubo_data);
                         (GPU_TAG_PRIM_CACHE_IMAGE, shader)
                     }
                 };
@@ -1401,10 +3491,31 @@ impl Renderer {
                                           &batch.key.textures,
                                           projection);
             }
+            PrimitiveBatchData::Blit(ref blit) => {
+                let _gm = self.gpu_profile.add_marker(GPU_TAG_BLIT);
+
+                if allow_blits {
+                    debug_assert!(render_target.is_none());
+                    let texture_id = self.resolve_source_texture(&batch.key.textures.colors[0]);
+                    self.device.blit_texture_to_framebuffer(texture_id,
+                                                            blit.src_rect,
+                                                            blit.dest_rect,
+                                                            target_dimensions.height as i32);
+                } else {
+                    // Views with their own projection need the image drawn.
+                    let shader = self.ps_image.get(&mut self.device, transform_kind, self.frame_ubo_data).unwrap();
+                    let vao = self.prim_vao_id;
+                    self.draw_instanced_batch(&[blit.instance.clone()],
+                                              vao,
+                                              shader,
+                                              &batch.key.textures,
+                                              projection);
+                }
+            }
             PrimitiveBatchData::Composite(ref instance) => {
                 let _gm = self.gpu_profile.add_marker(GPU_TAG_PRIM_COMPOSITE);
                 let vao = self.prim_vao_id;
-                let shader = self.ps_composite.get(&mut self.device).unwrap();
+                let shader = self.ps_composite.get(&mut self.device, self.frame_ubo_data).unwrap();
 
                 // TODO(gw): This code branch is all a bit hacky. We rely
                 // on pulling specific values from the render target data
@@ -1442,20 +3553,46 @@ impl Renderer {
                 let mut dest = DeviceIntRect::new(DeviceIntPoint::new(dest_x as i32, dest_y as i32),
                                                   DeviceIntSize::new(width as i32, height as i32));
 
-                // Need to invert the y coordinates and flip the image vertically when
-                // reading back from the framebuffer.
+                // Cache targets have their first row at the top of the frame.
+                // When reading back from the framebuffer, the backdrop has to
+                // be found through the surface transform, and flipped
+                // wherever the framebuffer's rows or columns run the other
+                // way.
                 if render_target.is_none() {
-                    src.origin.y = target_dimensions.height as i32 - src.size.height - src.origin.y;
-                    dest.origin.y += dest.size.height;
-                    dest.size.height = -dest.size.height;
+                    let layout_size = self.surface_transform.transform_size(target_dimensions);
+                    src = self.surface_transform.transform_rect(&src, layout_size);
+                    src = self.output_origin.to_gl_rect(&src, target_dimensions.height as i32);
+
+                    let rotated_180 = self.surface_transform == SurfaceTransform::Rotate180;
+                    let bottom_up = self.output_origin == SurfaceOrigin::BottomLeft;
+                    if bottom_up != rotated_180 {
+                        dest.origin.y += dest.size.height;
+                        dest.size.height = -dest.size.height;
+                    }
+                    match self.surface_transform {
+                        SurfaceTransform::Identity => {}
+                        SurfaceTransform::Rotate180 => {
+                            dest.origin.x += dest.size.width;
+                            dest.size.width = -dest.size.width;
+                        }
+                        SurfaceTransform::Rotate90 |
+                        SurfaceTransform::Rotate270 => {
+                            self.add_warning(RendererWarning::TransposedBackdrop);
+                        }
+                    }
                 }
 
+                // The backdrop is copied whole, even outside the scissor.
+                self.device.disable_scissor();
                 self.device.blit_render_target(render_target,
                                                Some(src),
                                                dest);
 
                 // Restore draw target to current pass render target + layer.
                 self.device.bind_draw_target(render_target, Some(target_dimensions));
+                if let Some(scissor_rect) = self.scissor_rect {
+                    self.device.enable_scissor(scissor_rect);
+                }
 
                 self.draw_instanced_batch(&[instance.clone()],
                                           vao,
@@ -1473,10 +3610,25 @@ impl Renderer {
                          color_cache_texture: TextureId,
                          clear_color: Option<[f32; 4]>,
                          render_task_data: &Vec<RenderTaskData>,
-                         projection: &Matrix4D<f32>) {
+                         projection: &Matrix4D<f32>,
+                         viewport: Option<DeviceIntRect>) {
+        // Cache targets with transformed or clipped primitives can be drawn
+        // multisampled, and resolved into their texture at the end.
+        let sample_count = match render_target {
+            Some(..) if target.needs_multisampling => self.intermediate_sample_count,
+            _ => 1,
+        };
+
         {
             let _gm = self.gpu_profile.add_marker(GPU_TAG_SETUP_TARGET);
-            self.device.bind_draw_target(render_target, Some(target_size));
+            if sample_count > 1 {
+                self.device.bind_multisampled_draw_target(target_size, sample_count);
+            } else {
+                self.device.bind_draw_target(render_target, Some(target_size));
+            }
+            if let Some(viewport) = viewport {
+                self.device.set_viewport(viewport);
+            }
             self.device.disable_depth();
             self.device.enable_depth_write();
             self.device.set_blend(false);
@@ -1488,12 +3640,23 @@ impl Renderer {
                     // GPUs that I have tested with. It's possible it may be a
                     // performance penalty on other GPU types - we should test this
                     // and consider different code paths.
-                    self.device.clear_target_rect(clear_color,
-                                                  Some(1.0),
-                                                  target.used_rect());
+                    if self.device.driver_workarounds().force_full_clears {
+                        self.device.clear_target(clear_color, Some(1.0));
+                    } else {
+                        self.device.clear_target_rect(clear_color,
+                                                      Some(1.0),
+                                                      target.used_rect());
+                    }
                 }
                 None => {
-                    self.device.clear_target(clear_color, Some(1.0));
+                    match viewport.or(self.repaint_rect) {
+                        Some(rect) => {
+                            self.device.clear_target_rect(clear_color, Some(1.0), rect);
+                        }
+                        None => {
+                            self.device.clear_target(clear_color, Some(1.0));
+                        }
+                    }
                 }
             }
 
@@ -1504,9 +3667,30 @@ impl Renderer {
                                               *isolate_clear);
             }
 
+            // Cache targets are only drawn to within their used rect, and
+            // the rest of the back buffer may already show this frame.
+            self.scissor_rect = match render_target {
+                Some(..) => Some(target.used_rect()),
+                None => self.repaint_rect,
+            };
+            if let Some(scissor_rect) = self.scissor_rect {
+                self.device.enable_scissor(scissor_rect);
+            }
+
             self.device.disable_depth_write();
         }
 
+        // Halve the size of the primitives for large blurs, letting the
+        // linear filter of the blit average their texels.
+        if !target.downscales.is_empty() {
+            let _gm = self.gpu_profile.add_marker(GPU_TAG_BLUR);
+            for &(src_rect, src_target_index, task_rect) in &target.downscales {
+                self.device.blit_render_target(Some((color_cache_texture, src_target_index.0 as i32)),
+                                               Some(src_rect),
+                                               task_rect);
+            }
+        }
+
         // Draw any blurs for this target.
         // Blurs are rendered as a standard 2-pass
         // separable implementation.
@@ -1515,21 +3699,63 @@ impl Renderer {
         //           blur radii with fixed weights.
         if !target.vertical_blurs.is_empty() || !target.horizontal_blurs.is_empty() {
             let _gm = self.gpu_profile.add_marker(GPU_TAG_BLUR);
-            let vao = self.blur_vao_id;
 
-            self.device.set_blend(false);
-            let shader = self.cs_blur.get(&mut self.device).unwrap();
+            // The compute shader writes straight into the texture, so it
+            // can't be used when the target is drawn multisampled and
+            // resolved afterwards.
+            let compute_blur = match (self.cs_blur_compute, render_target) {
+                (Some(shader), Some(texture_layer)) if sample_count == 1 => {
+                    Some((shader, texture_layer))
+                }
+                _ => None,
+            };
 
-            self.draw_instanced_batch(&target.vertical_blurs,
-                                      vao,
-                                      shader,
-                                      &BatchTextures::no_texture(),
-                                      &projection);
-            self.draw_instanced_batch(&target.horizontal_blurs,
-                                      vao,
-                                      shader,
-                                      &BatchTextures::no_texture(),
-                                      &projection);
+            if let Some((shader, (texture_id, layer))) = compute_blur {
+                self.device.bind_compute_program(shader.program);
+                self.device.bind_image_layer_for_write(0, texture_id, layer);
+                for blur in target.vertical_blurs.iter().chain(&target.horizontal_blurs) {
+                    let task = &render_task_data[blur.task_id as usize].data;
+                    let source = &render_task_data[blur.src_task_id as usize].data;
+                    let (along, across) = if blur.blur_direction == BlurDirection::Horizontal as i32 {
+                        (task[2] as u32, task[3] as u32)
+                    } else {
+                        (task[3] as u32, task[2] as u32)
+                    };
+
+                    self.device.set_uniform_4i(shader.u_task_rect,
+                                               task[0] as i32,
+                                               task[1] as i32,
+                                               task[2] as i32,
+                                               task[3] as i32);
+                    self.device.set_uniform_4i(shader.u_source_rect,
+                                               source[0] as i32,
+                                               source[1] as i32,
+                                               source[2] as i32,
+                                               source[3] as i32);
+                    self.device.set_uniform_1i(shader.u_source_layer, source[4] as i32);
+                    self.device.set_uniform_1i(shader.u_blur_radius, task[5] as i32);
+                    self.device.set_uniform_1i(shader.u_blur_direction, blur.blur_direction);
+                    self.device.dispatch_compute((along + COMPUTE_BLUR_GROUP_SIZE - 1) / COMPUTE_BLUR_GROUP_SIZE,
+                                                 across);
+                }
+                self.device.compute_barrier();
+            } else {
+                let vao = self.blur_vao_id;
+
+                self.device.set_blend(false);
+                let shader = self.cs_blur.get(&mut self.device, self.frame_ubo_data).unwrap();
+
+                self.draw_instanced_batch(&target.vertical_blurs,
+                                          vao,
+                                          shader,
+                                          &BatchTextures::no_texture(),
+                                          &projection);
+                self.draw_instanced_batch(&target.horizontal_blurs,
+                                          vao,
+                                          shader,
+                                          &BatchTextures::no_texture(),
+                                          &projection);
+            }
         }
 
         // Draw any box-shadow caches for this target.
@@ -1537,7 +3763,7 @@ impl Renderer {
             self.device.set_blend(false);
             let _gm = self.gpu_profile.add_marker(GPU_TAG_CACHE_BOX_SHADOW);
             let vao = self.prim_vao_id;
-            let shader = self.cs_box_shadow.get(&mut self.device).unwrap();
+            let shader = self.cs_box_shadow.get(&mut self.device, self.frame_ubo_data).unwrap();
             self.draw_instanced_batch(&target.box_shadow_cache_prims,
                                       vao,
                                       shader,
@@ -1557,7 +3783,7 @@ impl Renderer {
 
             let _gm = self.gpu_profile.add_marker(GPU_TAG_CACHE_TEXT_RUN);
             let vao = self.prim_vao_id;
-            let shader = self.cs_text_run.get(&mut self.device).unwrap();
+            let shader = self.cs_text_run.get(&mut self.device, self.frame_ubo_data).unwrap();
 
             self.draw_instanced_batch(&target.text_run_cache_prims,
                                       vao,
@@ -1574,13 +3800,21 @@ impl Renderer {
         self.device.enable_depth();
         self.device.enable_depth_write();
 
+        // Opaque images are only blitted to the framebuffer when it isn't
+        // split into views or zoomed.
+        let allow_blits = viewport.is_none() &&
+                          self.composite_zoom.is_none() &&
+                          self.surface_transform == SurfaceTransform::Identity &&
+                          self.output_origin == SurfaceOrigin::BottomLeft;
+
         for batch in &target.alpha_batcher.opaque_batches {
             self.submit_batch(batch,
                               &projection,
                               render_task_data,
                               color_cache_texture,
                               render_target,
-                              target_size);
+                              target_size,
+                              allow_blits);
         }
 
         self.device.disable_depth_write();
@@ -1612,11 +3846,74 @@ impl Renderer {
                               render_task_data,
                               color_cache_texture,
                               render_target,
-                              target_size);
+                              target_size,
+                              allow_blits);
         }
 
         self.device.disable_depth();
         self.device.set_blend(false);
+        self.device.disable_scissor();
+        self.scissor_rect = None;
+
+        // Depth is cleared before each target is drawn, so it's never
+        // needed afterwards. The framebuffer's is left to the embedder.
+        if render_target.is_some() {
+            self.device.invalidate_depth_target();
+        }
+
+        if sample_count > 1 {
+            self.device.resolve_multisampled_target(render_target.unwrap(),
+                                                    target.used_rect());
+        }
+
+        if let Some(render_target) = render_target {
+            self.update_task_cache(render_target, target);
+        }
+    }
+
+    /// Copies the tasks of a cache target that the render task cache has
+    /// into the target, and the ones it keeps from the target.
+    fn update_task_cache(&mut self,
+                         render_target: (TextureId, i32),
+                         target: &ColorRenderTarget) {
+        if target.task_cache_restores.is_empty() && target.task_cache_saves.is_empty() {
+            return;
+        }
+
+        let _gm = self.gpu_profile.add_marker(GPU_TAG_BLIT);
+        let cache_texture_id = match self.task_cache_texture {
+            Some(texture_id) => texture_id,
+            None => {
+                let texture_id = self.device.create_texture_ids(1, TextureTarget::Default)[0];
+                self.device.init_texture(texture_id,
+                                         RENDER_TASK_CACHE_SIZE,
+                                         RENDER_TASK_CACHE_SIZE,
+                                         ImageFormat::RGBA8,
+                                         TextureFilter::Linear,
+                                         RenderTargetMode::SimpleRenderTarget,
+                                         None);
+                self.task_cache_texture = Some(texture_id);
+                texture_id
+            }
+        };
+
+        if !target.task_cache_restores.is_empty() {
+            self.device.bind_draw_target(Some(render_target), None);
+            for &(cache_rect, task_rect) in &target.task_cache_restores {
+                self.device.blit_render_target(Some((cache_texture_id, 0)),
+                                               Some(cache_rect),
+                                               task_rect);
+            }
+        }
+
+        if !target.task_cache_saves.is_empty() {
+            self.device.bind_draw_target(Some((cache_texture_id, 0)), None);
+            for &(task_rect, cache_rect) in &target.task_cache_saves {
+                self.device.blit_render_target(Some(render_target),
+                                               Some(task_rect),
+                                               cache_rect);
+            }
+        }
     }
 
     fn draw_alpha_target(&mut self,
@@ -1636,9 +3933,14 @@ impl Renderer {
             // performance penalty on other GPU types - we should test this
             // and consider different code paths.
             let clear_color = [1.0, 1.0, 1.0, 0.0];
-            self.device.clear_target_rect(Some(clear_color),
-                                          None,
-                                          target.used_rect());
+            if self.device.driver_workarounds().force_full_clears {
+                self.device.clear_target(Some(clear_color), None);
+            } else {
+                self.device.clear_target_rect(Some(clear_color),
+                                              None,
+                                              target.used_rect());
+            }
+            self.device.enable_scissor(target.used_rect());
         }
 
         // Draw the clip items into the tiled alpha mask.
@@ -1652,7 +3954,7 @@ impl Renderer {
             // draw rounded cornered rectangles
             if !target.clip_batcher.rectangles.is_empty() {
                 let _gm2 = GpuMarker::new(self.device.rc_gl(), "clip rectangles");
-                let shader = self.cs_clip_rectangle.get(&mut self.device).unwrap();
+                let shader = self.cs_clip_rectangle.get(&mut self.device, self.frame_ubo_data).unwrap();
                 self.draw_instanced_batch(&target.clip_batcher.rectangles,
                                           vao,
                                           shader,
@@ -1664,7 +3966,7 @@ impl Renderer {
                 let _gm2 = GpuMarker::new(self.device.rc_gl(), "clip images");
                 let texture_id = self.resolve_source_texture(mask_texture_id);
                 self.device.bind_texture(TextureSampler::Mask, texture_id);
-                let shader = self.cs_clip_image.get(&mut self.device).unwrap();
+                let shader = self.cs_clip_image.get(&mut self.device, self.frame_ubo_data).unwrap();
                 self.draw_instanced_batch(items,
                                           vao,
                                           shader,
@@ -1672,6 +3974,8 @@ impl Renderer {
                                           &projection);
             }
         }
+
+        self.device.disable_scissor();
     }
 
     fn update_deferred_resolves(&mut self, frame: &mut Frame) {
@@ -1679,64 +3983,103 @@ impl Renderer {
         // resolves, and use a callback to get the UV rect for this
         // custom item. Then we patch the resource_rects structure
         // here before it's uploaded to the GPU.
-        if !frame.deferred_resolves.is_empty() {
-            let handler = self.external_image_handler
-                              .as_mut()
-                              .expect("Found external image, but no handler set!");
-
-            for deferred_resolve in &frame.deferred_resolves {
-                GpuMarker::fire(self.device.gl(), "deferred resolve");
-                let props = &deferred_resolve.image_properties;
-                let ext_image = props.external_image
-                                     .expect("BUG: Deferred resolves must be external images!");
-                let image = handler.lock(ext_image.id);
-                let texture_target = match ext_image.image_type {
-                    ExternalImageType::Texture2DHandle => TextureTarget::Default,
-                    ExternalImageType::TextureRectHandle => TextureTarget::Rect,
-                    _ => {
-                        panic!("{:?} is not a suitable image type in update_deferred_resolves().",
-                            ext_image.image_type);
-                    }
-                };
+        for deferred_resolve in &frame.deferred_resolves {
+            GpuMarker::fire(self.device.gl(), "deferred resolve");
+            let props = &deferred_resolve.image_properties;
+            let ext_image = props.external_image
+                                 .expect("BUG: Deferred resolves must be external images!");
+            let texture_target = match ext_image.image_type {
+                ExternalImageType::Texture2DHandle => TextureTarget::Default,
+                ExternalImageType::TextureRectHandle => TextureTarget::Rect,
+                _ => {
+                    panic!("{:?} is not a suitable image type in update_deferred_resolves().",
+                        ext_image.image_type);
+                }
+            };
 
-                let texture_id = match image.source {
-                    ExternalImageSource::NativeTexture(texture_id) => TextureId::new(texture_id, texture_target),
-                    _ => panic!("No native texture found."),
-                };
+            let (texture_id, uv0, uv1) = match self.external_image_handler {
+                None => {
+                    // Sample from the null texture, which reads as black.
+                    // The invalid id tells unlock_external_images() that
+                    // nothing was locked.
+                    self.add_warning(RendererWarning::MissingExternalImageHandler(ext_image.id));
+                    (TextureId::invalid(), DevicePoint::zero(), DevicePoint::zero())
+                }
+                Some(ref mut handler) => {
+                    let image = handler.lock(ext_image.id);
+                    let texture_id = match image.source {
+                        ExternalImageSource::NativeTexture(texture_id) => TextureId::new(texture_id, texture_target),
+                        _ => panic!("No native texture found."),
+                    };
+                    // Native textures don't go through the texture cache, so
+                    // their filter is set here, and put back once the frame
+                    // is drawn. If several items draw the same texture with
+                    // different renderings, the last wins.
+                    let saved = self.device.override_texture_filter(texture_id,
+                                                                    texture_filter_for_rendering(deferred_resolve.image_rendering));
+                    self.external_texture_filters.push(saved);
+                    (texture_id,
+                     DevicePoint::new(image.u0, image.v0),
+                     DevicePoint::new(image.u1, image.v1))
+                }
+            };
 
-                self.external_images.insert(ext_image.id, texture_id);
-                let resource_rect_index = deferred_resolve.resource_address.0 as usize;
-                let resource_rect = &mut frame.gpu_resource_rects[resource_rect_index];
-                resource_rect.uv0 = DevicePoint::new(image.u0, image.v0);
-                resource_rect.uv1 = DevicePoint::new(image.u1, image.v1);
-            }
+            self.external_images.insert(ext_image.id, texture_id);
+            self.gpu_cache_texture.set_resource_rect(deferred_resolve.resource_address, uv0, uv1);
         }
     }
 
     fn unlock_external_images(&mut self) {
-        if !self.external_images.is_empty() {
+        // Restored newest first, so the filter the embedder set wins when
+        // a texture was changed more than once.
+        while let Some(saved) = self.external_texture_filters.pop() {
+            self.device.restore_texture_filter(saved);
+        }
+
+        for (external_id, texture_id) in self.external_images.drain() {
+            if !texture_id.is_valid() {
+                continue;
+            }
+
             let handler = self.external_image_handler
                               .as_mut()
                               .expect("Found external image, but no handler set!");
-
-            for (external_id, _) in self.external_images.drain() {
-                handler.unlock(external_id);
-            }
+            handler.unlock(external_id);
         }
     }
 
     fn release_external_images(&mut self, mut pending_external_image_updates: ExternalImageUpdateList) {
-        if !pending_external_image_updates.is_empty() {
-            let handler = self.external_image_handler
-                              .as_mut()
-                              .expect("found external image updates, but no handler set!");
+        if pending_external_image_updates.is_empty() {
+            return;
+        }
 
+        if self.external_image_handler.is_none() {
             for external_id in pending_external_image_updates.drain(..) {
-                handler.release(external_id);
+                self.add_warning(RendererWarning::MissingExternalImageHandler(external_id));
             }
+            return;
+        }
+
+        let handler = self.external_image_handler.as_mut().unwrap();
+        for external_id in pending_external_image_updates.drain(..) {
+            handler.release(external_id);
+        }
+    }
+
+    fn add_warning(&mut self, warning: RendererWarning) {
+        if !self.warnings.contains(&warning) {
+            warn!("{}", warning);
+            self.warnings.push(warning);
         }
     }
 
+    /// Returns the problems the renderer has worked around since the last
+    /// call, such as external images used without an
+    /// `ExternalImageHandler`. Each distinct warning is reported once.
+    pub fn take_warnings(&mut self) -> Vec<RendererWarning> {
+        mem::replace(&mut self.warnings, Vec::new())
+    }
+
     fn draw_tile_frame(&mut self,
                        frame: &mut Frame,
                        framebuffer_size: &DeviceUintSize) {
@@ -1746,149 +4089,334 @@ impl Renderer {
         // Some tests use a restricted viewport smaller than the main screen size.
         // Ensure we clear the framebuffer in these tests.
         // TODO(gw): Find a better solution for this?
-        let needs_clear = frame.window_size.width < framebuffer_size.width ||
-                          frame.window_size.height < framebuffer_size.height;
+        let layout_size = self.surface_transform.transform_size(*framebuffer_size);
+        let needs_clear = frame.window_size.width < layout_size.width ||
+                          frame.window_size.height < layout_size.height;
 
         self.device.disable_depth_write();
         self.device.disable_stencil();
         self.device.set_blend(false);
 
         if frame.passes.is_empty() {
-            self.device.clear_target(Some(self.clear_color.to_array()), Some(1.0));
+            if self.clear_framebuffer || needs_clear {
+                let clear_color = self.framebuffer_clear_color(self.clear_color);
+                self.device.clear_target(Some(clear_color), Some(1.0));
+            }
         } else {
-            // Assign render targets to the passes.
+            // Assign render targets to the passes. Passes that share a
+            // texture slot in the render task graph share a texture.
             for pass in &mut frame.passes {
                 debug_assert!(pass.color_texture_id.is_none());
                 debug_assert!(pass.alpha_texture_id.is_none());
 
-                if pass.needs_render_target_kind(RenderTargetKind::Color) {
-                    pass.color_texture_id = Some(self.color_render_targets
-                                                     .pop()
-                                                     .unwrap_or_else(|| {
-                                                         self.device
-                                                             .create_texture_ids(1, TextureTarget::Array)[0]
-                                                      }));
+                if let Some(slot) = pass.color_texture_slot {
+                    while self.color_render_targets.len() <= slot {
+                        let texture_id = self.device.create_texture_ids(1, TextureTarget::Array)[0];
+                        self.color_render_targets.push(texture_id);
+                    }
+                    pass.color_texture_id = Some(self.color_render_targets[slot]);
                 }
 
-                if pass.needs_render_target_kind(RenderTargetKind::Alpha) {
-                    pass.alpha_texture_id = Some(self.alpha_render_targets
-                                                     .pop()
-                                                     .unwrap_or_else(|| {
-                                                         self.device
-                                                             .create_texture_ids(1, TextureTarget::Array)[0]
-                                                      }));
+                if let Some(slot) = pass.alpha_texture_slot {
+                    while self.alpha_render_targets.len() <= slot {
+                        let texture_id = self.device.create_texture_ids(1, TextureTarget::Array)[0];
+                        self.alpha_render_targets.push(texture_id);
+                    }
+                    pass.alpha_texture_id = Some(self.alpha_render_targets[slot]);
                 }
             }
 
-            // Init textures and render targets to match this scene.
+            // Each texture is sized for the largest part that the passes
+            // sharing it draw to, rather than for the whole cache.
+            let mut color_target_sizes = vec![DeviceUintSize::zero(); self.color_render_targets.len()];
+            let mut alpha_target_sizes = vec![DeviceUintSize::zero(); self.alpha_render_targets.len()];
             for pass in &frame.passes {
-                if let Some(texture_id) = pass.color_texture_id {
-                    let target_count = pass.required_target_count(RenderTargetKind::Color);
-                    self.device.init_texture(texture_id,
-                                             frame.cache_size.width as u32,
-                                             frame.cache_size.height as u32,
-                                             ImageFormat::RGBA8,
-                                             TextureFilter::Linear,
-                                             RenderTargetMode::LayerRenderTarget(target_count as i32),
-                                             None);
+                if let Some(slot) = pass.color_texture_slot {
+                    color_target_sizes[slot] = max_size(color_target_sizes[slot],
+                                                        pass.color_targets.used_size());
                 }
-                if let Some(texture_id) = pass.alpha_texture_id {
-                    let target_count = pass.required_target_count(RenderTargetKind::Alpha);
-                    self.device.init_texture(texture_id,
-                                             frame.cache_size.width as u32,
-                                             frame.cache_size.height as u32,
-                                             ImageFormat::A8,
-                                             TextureFilter::Nearest,
-                                             RenderTargetMode::LayerRenderTarget(target_count as i32),
-                                             None);
+                if let Some(slot) = pass.alpha_texture_slot {
+                    alpha_target_sizes[slot] = max_size(alpha_target_sizes[slot],
+                                                        pass.alpha_targets.used_size());
                 }
             }
+            for size in color_target_sizes.iter_mut().chain(alpha_target_sizes.iter_mut()) {
+                *size = render_target_bucket_size(*size, frame.cache_size);
+            }
 
             // TODO(gw): This is a hack / workaround for #728.
             // We should find a better way to implement these updates rather
             // than wasting this extra memory, but for now it removes a large
             // number of driver stalls.
-            self.gpu_data_textures[self.gdt_index].init_frame(&mut self.device, frame);
+            self.frame_ubo_data =
+                self.gpu_data_textures[self.gdt_index].init_frame(&mut self.device,
+                                                                  frame,
+                                                                  &self.late_latched_poses,
+                                                                  self.ubo_data_capacity);
             self.gdt_index = (self.gdt_index + 1) % GPU_DATA_TEXTURE_POOL;
+            self.gpu_cache_texture.update_and_bind(&mut self.device);
 
             let mut src_color_id = self.dummy_cache_texture_id;
             let mut src_alpha_id = self.dummy_cache_texture_id;
 
-            for pass in &mut frame.passes {
+            let mut debug_targets = Vec::new();
+            let dump_render_targets = self.debug_flags.contains(RENDER_TARGET_DUMP);
+            if dump_render_targets {
+                if let Err(err) = fs::create_dir_all(&self.render_target_dump_path) {
+                    warn!("Failed to create {:?}: {}", self.render_target_dump_path, err);
+                }
+            }
+
+            for (pass_index, pass) in frame.passes.iter_mut().enumerate() {
                 let size;
                 let clear_color;
                 let projection;
 
                 if pass.is_framebuffer {
                     clear_color = if self.clear_framebuffer || needs_clear {
-                        Some(frame.background_color.map_or(self.clear_color.to_array(), |color| {
-                            color.to_array()
-                        }))
+                        let color = frame.background_color.unwrap_or(self.clear_color);
+                        Some(self.framebuffer_clear_color(color))
                     } else {
                         None
                     };
                     size = framebuffer_size;
+                    // Each stereo view covers only part of the framebuffer,
+                    // with the frame laid out at the window size.
+                    let projection_size = if self.views.is_empty() {
+                        layout_size
+                    } else {
+                        frame.window_size
+                    };
+                    let surface_size = self.surface_transform.transform_size(projection_size);
+                    let (bottom, top) = match self.output_origin {
+                        SurfaceOrigin::BottomLeft => (surface_size.height as f32, 0.0),
+                        SurfaceOrigin::TopLeft => (0.0, surface_size.height as f32),
+                    };
                     projection = Matrix4D::ortho(0.0,
-                                                 size.width as f32,
-                                                 size.height as f32,
-                                                 0.0,
-                                                 ORTHO_NEAR_PLANE,
-                                                 ORTHO_FAR_PLANE)
+                                                 surface_size.width as f32,
+                                                 bottom,
+                                                 top,
+                                                 self.ortho_near_plane,
+                                                 self.ortho_far_plane)
+                        .pre_mul(&self.surface_transform.to_matrix(projection_size))
+                        .pre_mul(&self.composite_zoom_matrix())
                 } else {
-                    size = &frame.cache_size;
+                    size = match pass.color_texture_slot {
+                        Some(slot) => &color_target_sizes[slot],
+                        None => &frame.cache_size,
+                    };
                     clear_color = Some([1.0, 1.0, 1.0, 0.0]);
                     projection = Matrix4D::ortho(0.0,
                                                  size.width as f32,
                                                  0.0,
                                                  size.height as f32,
-                                                 ORTHO_NEAR_PLANE,
-                                                 ORTHO_FAR_PLANE);
+                                                 self.ortho_near_plane,
+                                                 self.ortho_far_plane);
+                }
+
+                // A shared texture is only initialized for this pass once
+                // the previous passes that drew to it have been read.
+                if let Some(texture_id) = pass.color_texture_id {
+                    let target_count = pass.required_target_count(RenderTargetKind::Color);
+                    self.device.init_texture(texture_id,
+                                             size.width,
+                                             size.height,
+                                             ImageFormat::RGBA8,
+                                             TextureFilter::Linear,
+                                             RenderTargetMode::LayerRenderTarget(target_count as i32),
+                                             None);
+                }
+                let alpha_size = pass.alpha_texture_slot.map_or(frame.cache_size, |slot| {
+                    alpha_target_sizes[slot]
+                });
+                if let Some(texture_id) = pass.alpha_texture_id {
+                    let target_count = pass.required_target_count(RenderTargetKind::Alpha);
+                    self.device.init_texture(texture_id,
+                                             alpha_size.width,
+                                             alpha_size.height,
+                                             ImageFormat::A8,
+                                             TextureFilter::Nearest,
+                                             RenderTargetMode::LayerRenderTarget(target_count as i32),
+                                             None);
                 }
 
                 self.device.bind_texture(TextureSampler::CacheA8, src_alpha_id);
                 self.device.bind_texture(TextureSampler::CacheRGBA8, src_color_id);
 
+                let alpha_projection = Matrix4D::ortho(0.0,
+                                                       alpha_size.width as f32,
+                                                       0.0,
+                                                       alpha_size.height as f32,
+                                                       self.ortho_near_plane,
+                                                       self.ortho_far_plane);
                 for (target_index, target) in pass.alpha_targets.targets.iter().enumerate() {
                     self.draw_alpha_target((pass.alpha_texture_id.unwrap(), target_index as i32),
                                            target,
-                                           *size,
-                                           &projection);
+                                           alpha_size,
+                                           &alpha_projection);
                 }
 
                 for (target_index, target) in pass.color_targets.targets.iter().enumerate() {
                     let render_target = pass.color_texture_id.map(|texture_id| {
                         (texture_id, target_index as i32)
                     });
+
+                    // In stereo mode, the framebuffer target is drawn once per
+                    // view, reusing the cache targets of the previous passes.
+                    if pass.is_framebuffer && !self.views.is_empty() {
+                        let views = self.views.clone();
+                        for view in &views {
+                            let view_projection = projection.pre_mul(&view.transform);
+                            let viewport = DeviceIntRect::new(
+                                DeviceIntPoint::new(view.viewport.origin.x as i32,
+                                                    view.viewport.origin.y as i32),
+                                DeviceIntSize::new(view.viewport.size.width as i32,
+                                                   view.viewport.size.height as i32));
+                            self.draw_color_target(render_target,
+                                                   target,
+                                                   *size,
+                                                   src_color_id,
+                                                   clear_color,
+                                                   &frame.render_task_data,
+                                                   &view_projection,
+                                                   Some(viewport));
+                        }
+                        continue;
+                    }
+
                     self.draw_color_target(render_target,
                                            target,
                                            *size,
                                            src_color_id,
                                            clear_color,
                                            &frame.render_task_data,
-                                           &projection);
+                                           &projection,
+                                           None);
+
+                }
+
+                if let Some(texture_id) = pass.color_texture_id {
+                    for (target_index, target) in pass.color_targets.targets.iter().enumerate() {
+                        if target.snapshots.is_empty() {
+                            continue;
+                        }
+                        self.device.bind_read_target(Some((texture_id, target_index as i32)));
+                        for &(id, rect) in &target.snapshots {
+                            let readback = self.device.begin_readback(rect);
+                            let size = DeviceUintSize::new(rect.size.width as u32,
+                                                           rect.size.height as u32);
+                            self.pending_snapshots.push((id, size, readback));
+                        }
+                    }
+                }
 
+                if let Some(texture_id) = pass.color_texture_id {
+                    for (target_index, target) in pass.color_targets.targets.iter().enumerate() {
+                        for &(render_target, rect) in &target.render_target_copies {
+                            self.copy_to_render_target((texture_id, target_index as i32),
+                                                       rect,
+                                                       render_target);
+                        }
+                    }
                 }
 
-                src_color_id = pass.color_texture_id.unwrap_or(self.dummy_cache_texture_id);
-                src_alpha_id = pass.alpha_texture_id.unwrap_or(self.dummy_cache_texture_id);
+                if dump_render_targets {
+                    self.dump_render_targets(pass_index, pass);
+                }
 
-                // Return the texture IDs to the pool for next frame.
-                if let Some(texture_id) = pass.color_texture_id.take() {
-                    self.color_render_targets.push(texture_id);
+                if self.debug_flags.contains(RENDER_TARGET_DBG) {
+                    add_debug_targets(&mut debug_targets,
+                                      pass.color_texture_id,
+                                      pass.color_targets.targets.len(),
+                                      RenderTargetKind::Color,
+                                      pass_index);
+                    add_debug_targets(&mut debug_targets,
+                                      pass.alpha_texture_id,
+                                      pass.alpha_targets.targets.len(),
+                                      RenderTargetKind::Alpha,
+                                      pass_index);
                 }
-                if let Some(texture_id) = pass.alpha_texture_id.take() {
-                    self.alpha_render_targets.push(texture_id);
+
+                // The targets of the previous pass have been read by this
+                // one, and nothing else reads them, unless they are shown
+                // for debugging.
+                if !self.debug_flags.contains(RENDER_TARGET_DBG) {
+                    for &texture_id in &[src_color_id, src_alpha_id] {
+                        if texture_id != self.dummy_cache_texture_id {
+                            self.device.invalidate_render_target(texture_id);
+                        }
+                    }
+                }
+
+                src_color_id = pass.color_texture_id.take().unwrap_or(self.dummy_cache_texture_id);
+                src_alpha_id = pass.alpha_texture_id.take().unwrap_or(self.dummy_cache_texture_id);
+            }
+
+            // Punch transparent holes where the overlay planes show through.
+            if !frame.overlay_candidates.is_empty() {
+                self.device.bind_draw_target(None, Some(*framebuffer_size));
+                let surface_transform = self.surface_transform;
+                let output_origin = self.output_origin;
+                for candidate in &frame.overlay_candidates {
+                    let rect = surface_transform.transform_rect(&candidate.clip_rect, layout_size);
+                    let gl_rect = output_origin.to_gl_rect(&rect, framebuffer_size.height as i32);
+                    self.device.clear_target_rect(Some([0.0, 0.0, 0.0, 0.0]), None, gl_rect);
                 }
             }
+            self.overlay_candidates = frame.overlay_candidates.clone();
 
-            self.color_render_targets.reverse();
-            self.alpha_render_targets.reverse();
-            self.draw_render_target_debug(framebuffer_size);
+            if dump_render_targets {
+                self.dump_framebuffer(framebuffer_size);
+                self.render_target_dump_count += 1;
+            }
+            self.draw_render_target_debug(framebuffer_size, &frame.cache_size, &debug_targets);
         }
 
+        self.release_render_targets(frame);
         self.unlock_external_images();
     }
 
+    /// The transform of `set_composite_zoom`, in layout space.
+    fn composite_zoom_matrix(&self) -> Matrix4D<f32> {
+        match self.composite_zoom {
+            Some((scale, origin)) => {
+                Matrix4D::identity().pre_translated(origin.x, origin.y, 0.0)
+                                    .pre_scaled(scale, scale, 1.0)
+                                    .pre_translated(-origin.x, -origin.y, 0.0)
+            }
+            None => Matrix4D::identity(),
+        }
+    }
+
+    /// Deletes the render targets that the memory pressure policy asks to
+    /// give back. They are created again when a later frame needs them.
+    fn release_render_targets(&mut self, frame: &Frame) {
+        let policy = self.memory_pressure_policy;
+        let (color_count, alpha_count) =
+            if memory_pressure_reached(policy.release_render_targets, self.memory_pressure) {
+                (0, 0)
+            } else if memory_pressure_reached(policy.trim_render_targets, self.memory_pressure) {
+                let color_count = frame.passes.iter()
+                                              .filter_map(|pass| pass.color_texture_slot)
+                                              .max()
+                                              .map_or(0, |slot| slot + 1);
+                let alpha_count = frame.passes.iter()
+                                              .filter_map(|pass| pass.alpha_texture_slot)
+                                              .max()
+                                              .map_or(0, |slot| slot + 1);
+                (color_count, alpha_count)
+            } else {
+                return;
+            };
+
+        for texture_id in self.color_render_targets.drain(color_count..) {
+            self.device.delete_texture(texture_id);
+        }
+        for texture_id in self.alpha_render_targets.drain(alpha_count..) {
+            self.device.delete_texture(texture_id);
+        }
+    }
+
     pub fn debug_renderer<'a>(&'a mut self) -> &'a mut DebugRenderer {
         &mut self.debug
     }
@@ -1899,44 +4427,299 @@ impl Renderer {
 
     pub fn set_profiler_enabled(&mut self, enabled: bool) {
         self.enable_profiler = enabled;
+        self.frame_dirty = true;
+    }
+
+    pub fn set_max_recorded_profiles(&mut self, count: usize) {
+        self.max_recorded_profiles = count;
+        while self.cpu_profiles.len() > count {
+            self.cpu_profiles.pop_front();
+        }
+        while self.gpu_profiles.len() > count {
+            self.gpu_profiles.pop_front();
+        }
+    }
+
+    pub fn set_clear_framebuffer(&mut self, enabled: bool) {
+        self.clear_framebuffer = enabled;
+        self.frame_dirty = true;
+    }
+
+    pub fn set_clear_color(&mut self, color: ColorF) {
+        self.clear_color = color;
+        self.frame_dirty = true;
+    }
+
+    // The framebuffer holds premultiplied colors when it is composited
+    // with alpha, so translucent clear colors need premultiplying first.
+    fn framebuffer_clear_color(&self, color: ColorF) -> [f32; 4] {
+        if self.transparent_framebuffer {
+            color.premultiplied().to_array()
+        } else {
+            color.to_array()
+        }
+    }
+
+    pub fn set_render_target_debug(&mut self, enabled: bool) {
+        if enabled {
+            self.debug_flags.insert(RENDER_TARGET_DBG);
+        } else {
+            self.debug_flags.remove(RENDER_TARGET_DBG);
+        }
+        self.frame_dirty = true;
+    }
+
+    pub fn get_debug_flags(&self) -> DebugFlags {
+        self.debug_flags
+    }
+
+    pub fn set_debug_flags(&mut self, flags: DebugFlags) {
+        self.debug_flags = flags;
+        self.frame_dirty = true;
+    }
+
+    /// Returns structural statistics about the current frame, such as the
+    /// number of primitives of each kind, and why primitives that could
+    /// have been drawn together ended up in separate batches.
+    pub fn get_pipeline_statistics(&self) -> Option<&PipelineStatistics> {
+        match self.current_frame {
+            Some(RendererFrame { frame: Some(ref frame), .. }) => Some(&frame.statistics),
+            _ => None,
+        }
+    }
+
+    /// Writes the pipeline statistics of every frame rendered from now on to
+    /// the file at `path`, replacing its contents. Passing `None` stops.
+    pub fn set_pipeline_statistics_dump(&mut self,
+                                        path: Option<&Path>,
+                                        format: StatisticsFormat)
+                                        -> io::Result<()> {
+        self.statistics_dump = match path {
+            Some(path) => Some(try!(StatisticsDump::new(path, format))),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Sets the directory that `RENDER_TARGET_DUMP` saves render targets
+    /// to. It defaults to `render_targets` in the working directory, and
+    /// is created when needed. Files are named after the frame, the pass
+    /// and the target, as in `frame3_pass1_alpha0.pam`, or `frame3_final.pam`
+    /// for the framebuffer, with frames counted from when dumping was first
+    /// enabled. With `RendererOptions::deterministic` and
+    /// `RendererOptions::single_threaded_workers`, replaying the same
+    /// recording writes the same files, so they can be diffed against
+    /// golden copies.
+    pub fn set_render_target_dump_path(&mut self, path: PathBuf) {
+        self.render_target_dump_path = path;
+    }
+
+    /// Enables or disables subpixel AA for text. This requires the scene to
+    /// be rebuilt on the backend thread, so it only shows up once the next
+    /// frame has been generated.
+    ///
+    /// Enabling it fails, leaving the setting as it was, where
+    /// `RendererOptions::validate` would reject `enable_subpixel_aa`. With
+    /// `RendererOptions::deterministic`, text stays grayscale.
+    pub fn set_subpixel_aa_enabled(&mut self, enabled: bool) -> Result<(), RendererOptionsError> {
+        if enabled {
+            try!(validate_subpixel_aa(self.enable_aa, self.transparent_framebuffer));
+        }
+        self.api_tx.send(ApiMsg::SetSubpixelAA(enabled && !self.deterministic)).unwrap();
+        Ok(())
     }
 
     pub fn save_cpu_profile(&self, filename: &str) {
         write_profile(filename);
     }
 
+    /// Asks the render backend for data. The response arrives with the
+    /// messages that `update` receives, and is returned by
+    /// `take_backend_query_responses` with the id returned here.
+    pub fn query_backend(&mut self, query: BackendQuery) -> BackendQueryId {
+        let id = BackendQueryId(self.next_backend_query_id);
+        self.next_backend_query_id += 1;
+        self.api_tx.send(ApiMsg::BackendQuery(id, query)).unwrap();
+        id
+    }
+
+    /// Returns the responses to `query_backend` received so far.
+    pub fn take_backend_query_responses(&mut self) -> Vec<(BackendQueryId, BackendQueryResponse)> {
+        mem::replace(&mut self.backend_query_responses, Vec::new())
+    }
+
+    fn handle_debug_command(&mut self, cmd: DebugCommand) {
+        match cmd {
+            DebugCommand::SetFlags(bits) => {
+                self.set_debug_flags(DebugFlags::from_bits_truncate(bits));
+            }
+            DebugCommand::EnableProfiler(enabled) => {
+                self.set_profiler_enabled(enabled);
+            }
+            DebugCommand::SaveCpuProfile(filename) => {
+                self.save_cpu_profile(&filename);
+            }
+            DebugCommand::ClearCaches => {
+                // The backend owns the caches.
+            }
+        }
+    }
+
+    fn dump_render_targets(&mut self, pass_index: usize, pass: &RenderPass) {
+        let color_targets = pass.color_texture_id.map(|texture_id| {
+            ("color", texture_id, pass.color_targets.targets.len())
+        });
+        let alpha_targets = pass.alpha_texture_id.map(|texture_id| {
+            ("alpha", texture_id, pass.alpha_targets.targets.len())
+        });
+
+        for (kind, texture_id, target_count) in color_targets.into_iter().chain(alpha_targets) {
+            let target_size = self.device.get_texture_dimensions(texture_id);
+            for target_index in 0..target_count {
+                // Alpha targets are read as RGBA too, since not every GL
+                // can read back single channel targets.
+                self.device.bind_read_target(Some((texture_id, target_index as i32)));
+                let pixels = self.device.gl().read_pixels(0,
+                                                          0,
+                                                          target_size.width as gl::GLsizei,
+                                                          target_size.height as gl::GLsizei,
+                                                          gl::RGBA,
+                                                          gl::UNSIGNED_BYTE);
+
+                let file_name = format!("frame{}_pass{}_{}{}.pam",
+                                        self.render_target_dump_count,
+                                        pass_index,
+                                        kind,
+                                        target_index);
+                let path = self.render_target_dump_path.join(file_name);
+                // Cache targets are drawn without flipping, so the rows are
+                // already in top to bottom order.
+                let channels = if kind == "color" { 4 } else { 1 };
+                if let Err(err) = write_pam(&path, &pixels, &target_size, channels) {
+                    warn!("Failed to save render target to {:?}: {}", path, err);
+                }
+            }
+        }
+    }
+
+    /// Saves the finished frame next to its render targets, so regressions
+    /// can be traced back to the pass that introduced them. It's read before
+    /// the debug overlays are drawn.
+    fn dump_framebuffer(&mut self, framebuffer_size: &DeviceUintSize) {
+        self.device.bind_read_target(None);
+        let pixels = self.device.gl().read_pixels(0,
+                                                  0,
+                                                  framebuffer_size.width as gl::GLsizei,
+                                                  framebuffer_size.height as gl::GLsizei,
+                                                  gl::RGBA,
+                                                  gl::UNSIGNED_BYTE);
+        let pixels = match self.output_origin {
+            SurfaceOrigin::BottomLeft => {
+                let stride = framebuffer_size.width as usize * 4;
+                pixels.chunks(stride).rev().flat_map(|row| row.iter().cloned()).collect()
+            }
+            SurfaceOrigin::TopLeft => pixels,
+        };
+
+        let file_name = format!("frame{}_final.pam", self.render_target_dump_count);
+        let path = self.render_target_dump_path.join(file_name);
+        if let Err(err) = write_pam(&path, &pixels, framebuffer_size, 4) {
+            warn!("Failed to save framebuffer to {:?}: {}", path, err);
+        }
+    }
+
+    /// Shows the render targets that the cache passes drew into, in rows
+    /// scaled to fit the framebuffer. Alpha targets are shown in false
+    /// color, since their coverage is hard to read in a single channel.
     fn draw_render_target_debug(&mut self,
-                                framebuffer_size: &DeviceUintSize) {
-        if self.render_target_debug {
-            // TODO(gw): Make the layout of the render targets a bit more sophisticated.
-            // Right now, it just draws them in one row at the bottom of the screen,
-            // with a fixed size.
-            let rt_debug_x0 = 16;
-            let rt_debug_y0 = 16;
-            let rt_debug_spacing = 16;
-            let rt_debug_size = 512;
-            let mut current_target = 0;
-
-            for texture_id in self.color_render_targets.iter().chain(self.alpha_render_targets.iter()) {
-                let layer_count = self.device.get_render_target_layer_count(*texture_id);
-                for layer_index in 0..layer_count {
-                    let x0 = rt_debug_x0 + (rt_debug_spacing + rt_debug_size) * current_target;
-                    let y0 = rt_debug_y0;
-
-                    // If we have more targets than fit on one row in screen, just early exit.
-                    if x0 > framebuffer_size.width as i32 {
-                        return;
-                    }
+                                framebuffer_size: &DeviceUintSize,
+                                target_size: &DeviceUintSize,
+                                debug_targets: &[DebugTarget]) {
+        if debug_targets.is_empty() {
+            return;
+        }
 
-                    let dest_rect = DeviceIntRect::new(DeviceIntPoint::new(x0, y0),
-                                                       DeviceIntSize::new(rt_debug_size, rt_debug_size));
-                    self.device.blit_render_target(Some((*texture_id, layer_index as i32)),
+        let spacing = 16.0;
+        let label_height = self.debug.line_height();
+        let (columns, width, height) = layout_debug_targets(debug_targets.len(),
+                                                            framebuffer_size,
+                                                            target_size,
+                                                            spacing,
+                                                            label_height);
+        if width < 1.0 || height < 1.0 {
+            return;
+        }
+
+        self.device.bind_draw_target(None, Some(*framebuffer_size));
+        self.device.disable_depth();
+        self.device.disable_scissor();
+        self.device.set_blend(false);
+
+        let projection = Matrix4D::ortho(0.0,
+                                         framebuffer_size.width as f32,
+                                         0.0,
+                                         framebuffer_size.height as f32,
+                                         ORTHO_NEAR_PLANE,
+                                         ORTHO_FAR_PLANE);
+        let label_color = ColorF::new(1.0, 1.0, 0.0, 1.0);
+
+        for (i, target) in debug_targets.iter().enumerate() {
+            let column = i % columns;
+            let row = i / columns;
+            let x0 = (spacing + (width + spacing) * column as f32) as i32;
+            let y0 = (spacing + label_height + (height + spacing + label_height) * row as f32) as i32;
+
+            // Textures are only as large as what was drawn into them, and
+            // are shown at the scale of the whole cache.
+            let texture_size = self.device.get_texture_dimensions(target.texture_id);
+            let w = (width * texture_size.width as f32 / target_size.width as f32) as i32;
+            let h = (height * texture_size.height as f32 / target_size.height as f32) as i32;
+
+            // The labels are drawn by the debug renderer, from the top of
+            // the screen. Keep the targets upright whichever row of the
+            // framebuffer is shown at the top.
+            let bottom = framebuffer_size.height as i32 - y0 - h;
+            let dest_rect = match self.output_origin {
+                SurfaceOrigin::BottomLeft => {
+                    DeviceIntRect::new(DeviceIntPoint::new(x0, bottom),
+                                       DeviceIntSize::new(w, h))
+                }
+                SurfaceOrigin::TopLeft => {
+                    DeviceIntRect::new(DeviceIntPoint::new(x0, framebuffer_size.height as i32 - bottom),
+                                       DeviceIntSize::new(w, -h))
+                }
+            };
+
+            match target.kind {
+                RenderTargetKind::Color => {
+                    self.device.blit_render_target(Some((target.texture_id, target.layer)),
                                                    None,
                                                    dest_rect);
-
-                    current_target += 1;
+                }
+                RenderTargetKind::Alpha => {
+                    let transform = projection.pre_translated(dest_rect.origin.x as f32,
+                                                              dest_rect.origin.y as f32,
+                                                              0.0)
+                                              .pre_scaled(dest_rect.size.width as f32,
+                                                          dest_rect.size.height as f32,
+                                                          1.0);
+                    let shader = self.debug_alpha_target.get(&mut self.device, None).unwrap();
+                    self.device.bind_program(shader, &transform);
+                    let u_layer = self.device.get_uniform_location(shader, "uLayer");
+                    self.device.set_uniform_1f(u_layer, target.layer as f32);
+                    self.device.bind_texture(TextureSampler::CacheA8, target.texture_id);
+                    self.device.bind_vao(self.output_vao_id);
+                    self.device.draw_triangles_u32(0, 6);
                 }
             }
+
+            let label = format!("pass {} {:?} {} ({}x{})",
+                                target.pass_index,
+                                target.kind,
+                                target.layer,
+                                texture_size.width,
+                                texture_size.height);
+            self.debug.add_text(x0 as f32, y0 as f32 - 4.0, &label, &label_color);
         }
     }
 
@@ -1945,7 +4728,42 @@ impl Renderer {
         //Note: this is a fake frame, only needed because texture deletion is require to happen inside a frame
         self.device.begin_frame(1.0);
         self.device.deinit_texture(self.dummy_cache_texture_id);
+        if let Some(texture_id) = self.task_cache_texture.take() {
+            self.device.deinit_texture(texture_id);
+        }
+        self.device.deinit_texture(self.gpu_cache_texture.id);
+        if let Some((texture_id, _, _)) = self.post_process_texture.take() {
+            self.device.deinit_texture(texture_id);
+        }
+        if let Some((texture_id, _, _)) = self.output_texture.take() {
+            self.device.deinit_texture(texture_id);
+        }
+        if let Some(texture_id) = self.output_lut_texture_id.take() {
+            self.device.deinit_texture(texture_id);
+        }
+        for (_, _, _, readback) in self.pending_readbacks.drain(..) {
+            self.device.cancel_readback(readback);
+        }
+        for (_, _, readback) in self.pending_snapshots.drain(..) {
+            self.device.cancel_readback(readback);
+        }
+        for (_, fence) in self.pending_frame_fences.drain(..) {
+            self.device.delete_fence(fence);
+        }
+        for gpu_data_textures in &mut self.gpu_data_textures {
+            gpu_data_textures.deinit(&mut self.device);
+        }
         self.device.end_frame();
+        self.device.deinit();
+
+        if let Some(mut compositor) = self.native_compositor.take() {
+            if let Some((surface_id, _)) = self.native_surface.take() {
+                compositor.destroy_surface(surface_id);
+            }
+            for (surface_id, _) in self.compositor_surfaces.drain(..) {
+                compositor.destroy_surface(surface_id);
+            }
+        }
     }
 }
 
@@ -1987,6 +4805,307 @@ pub trait ExternalImageHandler {
     fn release(&mut self, key: ExternalImageId);
 }
 
+/// Identifies a readback requested with `Renderer::request_readback`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ReadbackId(pub u32);
+
+/// Receives the pixels of readbacks once the GPU has produced them.
+pub trait ReadbackHandler {
+    /// `pixels` are RGBA8, with rows from the top of `rect` to the bottom.
+    /// `rect` is the requested rect clipped to the framebuffer. Both are in
+    /// the orientation of the frame, before the `surface_transform`.
+    fn readback_complete(&mut self, id: ReadbackId, rect: DeviceUintRect, pixels: Vec<u8>);
+    /// Receives the contents of a stacking context pushed with
+    /// `DisplayListBuilder::push_snapshot_stacking_context`, as drawn by a
+    /// `render()`. `pixels` are premultiplied RGBA8, with rows from the
+    /// top to the bottom of the visible part of the stacking context.
+    fn snapshot_complete(&mut self, _id: SnapshotId, _size: DeviceUintSize, _pixels: Vec<u8>) {
+    }
+}
+
+/// Identifies a frame composited by `Renderer::render`. They count up from
+/// zero, one for each call that returns `RenderResult::Composited`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct RenderedFrameId(pub u64);
+
+/// Is told when the GPU has finished drawing composited frames, e.g. to
+/// hand them to an external compositor or to pace video, without having to
+/// call `glFinish` or guess how long the GPU takes.
+pub trait FrameCompleteHandler {
+    /// The GPU has executed all the commands of frame `id`, including the
+    /// copies to external images it makes. Frames complete in order.
+    fn frame_complete(&mut self, id: RenderedFrameId);
+}
+
+/// An effect the embedder draws over the whole frame on its way to the
+/// framebuffer, e.g. color inversion for accessibility, a night mode or a
+/// magnifier.
+pub trait PostProcessHandler {
+    /// Draw the frame from `texture` into the bound framebuffer, which has
+    /// the viewport set to cover `size`. `texture` is the GL name of a
+    /// `TEXTURE_2D_ARRAY` whose first layer holds the frame, premultiplied,
+    /// in the size and orientation of the framebuffer. It's RGBA8, or
+    /// RGBA32F with `format` `RGBAF32` when HDR output is set. Any GL
+    /// state may be changed, webrender rebinds what it needs afterwards.
+    fn post_process(&mut self, texture: u32, size: DeviceUintSize, format: ImageFormat);
+}
+
+/// Makes `texture` a render target of `size` and `format`, creating or
+/// reallocating it as needed, and returns it.
+fn prepare_output_texture(device: &mut Device,
+                          texture: &mut Option<(TextureId, DeviceUintSize, ImageFormat)>,
+                          size: &DeviceUintSize,
+                          format: ImageFormat)
+                          -> TextureId {
+    match *texture {
+        Some((texture_id, texture_size, texture_format))
+            if texture_size == *size && texture_format == format => texture_id,
+        previous_texture => {
+            let texture_id = match previous_texture {
+                Some((texture_id, _, _)) => texture_id,
+                None => device.create_texture_ids(1, TextureTarget::Array)[0],
+            };
+            device.init_texture(texture_id,
+                                size.width,
+                                size.height,
+                                format,
+                                TextureFilter::Nearest,
+                                RenderTargetMode::LayerRenderTarget(1),
+                                None);
+            // Frames that don't clear the framebuffer expect to draw
+            // over something defined.
+            device.bind_draw_target(Some((texture_id, 0)), Some(*size));
+            device.clear_target(Some([0.0, 0.0, 0.0, 0.0]), None);
+            *texture = Some((texture_id, *size, format));
+            texture_id
+        }
+    }
+}
+
+/// A layer of a render target texture, as shown by the render target
+/// debug overlay.
+struct DebugTarget {
+    texture_id: TextureId,
+    layer: i32,
+    kind: RenderTargetKind,
+    pass_index: usize,
+}
+
+fn max_size(a: DeviceUintSize, b: DeviceUintSize) -> DeviceUintSize {
+    DeviceUintSize::new(cmp::max(a.width, b.width), cmp::max(a.height, b.height))
+}
+
+/// Rounds the size of a render target texture up to a power of two, at
+/// most `max_size`, so that it keeps its size while what is drawn into it
+/// changes a little from frame to frame.
+fn render_target_bucket_size(used_size: DeviceUintSize, max_size: DeviceUintSize) -> DeviceUintSize {
+    let bucket = |used: u32, max: u32| {
+        cmp::min(cmp::max(used, MIN_RENDER_TARGET_SIZE).next_power_of_two(), max)
+    };
+    DeviceUintSize::new(bucket(used_size.width, max_size.width),
+                        bucket(used_size.height, max_size.height))
+}
+
+/// Adds the targets a pass drew into a texture. Textures are shared
+/// between passes, so the targets of earlier passes that used the same
+/// texture are no longer there to show.
+fn add_debug_targets(debug_targets: &mut Vec<DebugTarget>,
+                     texture_id: Option<TextureId>,
+                     target_count: usize,
+                     kind: RenderTargetKind,
+                     pass_index: usize) {
+    let texture_id = match texture_id {
+        Some(texture_id) => texture_id,
+        None => return,
+    };
+    debug_targets.retain(|target| target.texture_id != texture_id);
+    for layer in 0..target_count {
+        debug_targets.push(DebugTarget {
+            texture_id: texture_id,
+            layer: layer as i32,
+            kind: kind,
+            pass_index: pass_index,
+        });
+    }
+}
+
+/// Picks the number of columns that shows `count` targets of `target_size`
+/// the largest in `framebuffer_size`, with room for a label above each.
+/// Returns the columns and the size of each target on the screen, which
+/// is at most 512 pixels wide.
+fn layout_debug_targets(count: usize,
+                        framebuffer_size: &DeviceUintSize,
+                        target_size: &DeviceUintSize,
+                        spacing: f32,
+                        label_height: f32) -> (usize, f32, f32) {
+    let max_width = 512.0;
+    let aspect = target_size.height as f32 / cmp::max(target_size.width, 1) as f32;
+
+    let mut best = (1, 0.0);
+    for columns in 1..count + 1 {
+        let rows = (count + columns - 1) / columns;
+        let width = (framebuffer_size.width as f32 - spacing * (columns + 1) as f32) /
+                    columns as f32;
+        let height = (framebuffer_size.height as f32 - spacing -
+                      (spacing + label_height) * rows as f32) / rows as f32;
+        let width = width.min(height / aspect).min(max_width);
+        if width > best.1 {
+            best = (columns, width);
+        }
+    }
+
+    let (columns, width) = best;
+    (columns, width.floor(), (width * aspect).floor())
+}
+
+/// Reverses the order of the rows of an image.
+fn flip_rows(pixels: &[u8], row_bytes: usize) -> Vec<u8> {
+    let mut flipped = Vec::with_capacity(pixels.len());
+    if row_bytes > 0 {
+        for row in pixels.chunks(row_bytes).rev() {
+            flipped.extend_from_slice(row);
+        }
+    }
+    flipped
+}
+
+/// Finds the rects of the items that only one of the two frames draws, and
+/// merges them down to at most `MAX_DAMAGE_RECTS` rects.
+fn find_damage_rects(old_items: &[DamageItem],
+                     new_items: &[DamageItem],
+                     framebuffer_rect: &DeviceIntRect) -> Vec<DeviceIntRect> {
+    let item_key = |item: &DamageItem| {
+        (item.rect.origin.x, item.rect.origin.y,
+         item.rect.size.width, item.rect.size.height,
+         item.key)
+    };
+
+    let mut counts = HashMap::new();
+    for item in new_items {
+        *counts.entry(item_key(item)).or_insert(0) += 1;
+    }
+    for item in old_items {
+        *counts.entry(item_key(item)).or_insert(0) -= 1;
+    }
+
+    let mut keys: Vec<_> = counts.iter()
+                                 .filter(|&(_, count)| *count != 0)
+                                 .map(|(key, _)| *key)
+                                 .collect();
+    keys.sort();
+
+    let mut rects: Vec<DeviceIntRect> = keys.iter().filter_map(|&(x, y, width, height, _)| {
+        DeviceIntRect::new(DeviceIntPoint::new(x, y), DeviceIntSize::new(width, height))
+            .intersection(framebuffer_rect)
+    }).collect();
+
+    if rects.len() > MAX_DAMAGE_RECTS_TO_MERGE {
+        let union = rects.iter().fold(rects[0], |union, rect| union.union(rect));
+        return vec![union];
+    }
+
+    // Repeatedly merge the two rects whose union adds the least area.
+    let area = |rect: &DeviceIntRect| rect.size.width as i64 * rect.size.height as i64;
+    while rects.len() > MAX_DAMAGE_RECTS {
+        let mut best = (0, 1, i64::max_value());
+        for i in 0..rects.len() {
+            for j in i+1..rects.len() {
+                let added_area = area(&rects[i].union(&rects[j])) - area(&rects[i]) - area(&rects[j]);
+                if added_area < best.2 {
+                    best = (i, j, added_area);
+                }
+            }
+        }
+        let (i, j, _) = best;
+        let merged = rects[i].union(&rects[j]);
+        rects[i] = merged;
+        rects.swap_remove(j);
+    }
+
+    rects
+}
+
+/// A video primitive that was promoted to a hardware overlay plane. Rects are
+/// in device pixels, with the origin at the top left of the framebuffer.
+#[derive(Clone, Debug)]
+pub struct OverlayCandidate {
+    /// The Y, U and V planes of the video.
+    pub image_keys: [ImageKey; 3],
+    /// The rect the whole video is drawn to.
+    pub rect: DeviceIntRect,
+    /// The visible part of `rect`, after clipping.
+    pub clip_rect: DeviceIntRect,
+}
+
+/// Whether a call to `Renderer::render` drew into the framebuffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderResult {
+    /// The framebuffer was drawn, and should be presented.
+    Composited,
+    /// Nothing was drawn, because there is no frame yet, the framebuffer
+    /// is empty, or with `RendererOptions::skip_unchanged_frames` nothing
+    /// changed since the last composite. There's nothing new to present.
+    Skipped,
+}
+
+/// One of the views that `Renderer::render_views` draws the frame into.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderView {
+    /// The part of the framebuffer covered by this view, in framebuffer
+    /// coordinates (i.e. with the origin at the bottom left).
+    pub viewport: DeviceUintRect,
+    /// A transform applied to the frame (in device pixels, laid out at the
+    /// window size) before it's projected into the viewport.
+    pub transform: Matrix4D<f32>,
+}
+
+impl RenderView {
+    pub fn new(viewport: DeviceUintRect) -> RenderView {
+        RenderView {
+            viewport: viewport,
+            transform: Matrix4D::identity(),
+        }
+    }
+}
+
+/// Identifies a surface allocated by a `NativeCompositor`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct NativeSurfaceId(pub u64);
+
+/// The interface an application can implement to have webrender draw into
+/// surfaces owned by the OS compositor (e.g. DirectComposition visuals on
+/// Windows or CoreAnimation layers on macOS) instead of the default framebuffer.
+/// The surface is only redrawn when a new frame has been built, so the OS can
+/// keep presenting the previous content while nothing changes.
+pub trait NativeCompositor {
+    /// Allocate a new surface of the given size.
+    fn create_surface(&mut self, id: NativeSurfaceId, size: DeviceUintSize);
+    /// Release a surface previously allocated by create_surface().
+    fn destroy_surface(&mut self, id: NativeSurfaceId);
+    /// Bind the framebuffer of a surface on the current GL context, so that
+    /// the following draw calls go to it. Only the dirty rect is updated.
+    fn bind(&mut self, id: NativeSurfaceId, dirty_rect: DeviceUintRect);
+    /// Called once WR is done drawing into the currently bound surface.
+    fn unbind(&mut self);
+    /// Where the first row of the surface's pixels is shown.
+    fn surface_origin(&self, _id: NativeSurfaceId) -> SurfaceOrigin {
+        SurfaceOrigin::BottomLeft
+    }
+    /// Place a surface that a promoted image was drawn into above the main
+    /// surface, see `RendererOptions::enable_compositor_surfaces`. `rect`
+    /// is where the whole surface goes, and `clip_rect` the part of it that
+    /// is visible, in device pixels with the origin at the top left of the
+    /// main surface. Compositors that leave `enable_compositor_surfaces`
+    /// off never get this call, so it does nothing by default.
+    fn position_surface(&mut self,
+                        _id: NativeSurfaceId,
+                        _rect: DeviceIntRect,
+                        _clip_rect: DeviceIntRect) {
+    }
+    /// Present the updated surfaces in the OS visual tree.
+    fn commit(&mut self);
+}
+
 pub struct RendererOptions {
     pub device_pixel_ratio: f32,
     pub resource_override_path: Option<PathBuf>,
@@ -1995,6 +5114,7 @@ pub struct RendererOptions {
     pub max_recorded_profiles: usize,
     pub debug: bool,
     pub enable_scrollbars: bool,
+    pub scrollbar_theme: ScrollbarTheme,
     pub precache_shaders: bool,
     pub renderer_kind: RendererKind,
     pub enable_subpixel_aa: bool,
@@ -2005,6 +5125,147 @@ pub struct RendererOptions {
     pub workers: Option<Arc<Mutex<ThreadPool>>>,
     pub blob_image_renderer: Option<Box<BlobImageRenderer>>,
     pub recorder: Option<Box<ApiRecordingReceiver>>,
+    pub native_compositor: Option<Box<NativeCompositor>>,
+    /// When enabled, unoccluded axis-aligned video primitives are not drawn.
+    /// A transparent hole is left in their place instead, and the embedder
+    /// is expected to present them in overlay planes, see
+    /// `Renderer::get_overlay_candidates`.
+    pub enable_overlay_promotion: bool,
+    /// When enabled along with a `native_compositor`, WebGL canvases and
+    /// external images (e.g. video frames) that are unoccluded and
+    /// axis-aligned are drawn into compositor surfaces of their own. These
+    /// are updated on every call to `render`, without redrawing the rest of
+    /// the frame.
+    pub enable_compositor_surfaces: bool,
+    /// Set when the framebuffer is composited with alpha by the window
+    /// system, e.g. for translucent popups. Clear and background colors are
+    /// then written premultiplied, and with a fully transparent
+    /// `clear_color` anything not covered by content shows through.
+    /// Subpixel AA can't be used, since it needs an opaque destination.
+    pub transparent_framebuffer: bool,
+    /// The near and far planes of the orthographic projection used to draw
+    /// each pass. Primitives get increasing integer z values in paint order,
+    /// starting at zero, and anything at or beyond the far plane is clipped,
+    /// so `ortho_far_plane` bounds the number of z values a frame can use.
+    /// A wider range spreads the same depth buffer bits over more values,
+    /// which makes adjacent z values harder to tell apart. With a 24 bit
+    /// depth buffer the default range still separates every integer.
+    pub ortho_near_plane: f32,
+    pub ortho_far_plane: f32,
+    /// How z values are handed out to primitives, see `ZAllocation`.
+    pub z_allocation: ZAllocation,
+    /// Runs the render backend on the renderer's thread instead of spawning
+    /// one for it. The embedder then drives it by calling
+    /// `Renderer::update_and_build_frame()` before each `render()`, which
+    /// makes frame production deterministic for tests and fuzzing. API
+    /// messages are still queued on the usual channels, and WebGL contexts
+    /// are not supported in this mode. API calls that wait for a reply,
+    /// such as `create_api()`, run the backend themselves when they are
+    /// made on the renderer's thread. From other threads they wait for the
+    /// next `update_and_build_frame()`.
+    pub synchronous_backend: bool,
+    /// Makes `render` return `RenderResult::Skipped` without drawing when
+    /// the framebuffer would get the same pixels as from the last call,
+    /// for embedders that render on every vsync. The framebuffer is then
+    /// left as it is, so this only suits embedders that skip presenting
+    /// too, or that present a buffer that is preserved between frames.
+    pub skip_unchanged_frames: bool,
+    /// Checks every display list before it's used, see
+    /// `BuiltDisplayList::validate`. Malformed lists are dropped and
+    /// reported through `RenderNotifier::display_list_rejected` instead of
+    /// failing somewhere in frame building. Enabled by default in debug
+    /// builds.
+    pub validate_display_lists: bool,
+    /// Makes the output depend only on the API messages received, so the
+    /// same display lists render to identical pixels on every run, as
+    /// reftests and fuzzers need. Gradients and blurs are not dithered,
+    /// text is always grayscale antialiased, scrollbars don't fade,
+    /// animated scrolls jump straight to their target and the texture
+    /// cache allocator isn't cut short by a timeout. Bounce animations
+    /// still advance one step per `tick_scrolling_bounce_animations()`.
+    pub deterministic: bool,
+    /// Runs the worker jobs, such as glyph rasterization, one at a time in
+    /// the order they were requested, on a private single thread pool
+    /// that replaces `workers`. Slower, but makes captures and fuzzer
+    /// crashes reproduce reliably.
+    pub single_threaded_workers: bool,
+    /// How images and glyphs are packed into the texture cache. Shelf
+    /// packing with size classes suits glyph-heavy content, where the
+    /// default guillotine packing tends to fragment.
+    pub texture_allocator: TextureAllocatorConfig,
+    /// The number of samples per pixel of the intermediate render targets
+    /// that transformed or clipped content is drawn into, which smooths
+    /// its edges once composited. Values of 1 and below disable
+    /// multisampling, and the count is capped at what the GPU supports.
+    pub intermediate_sample_count: u32,
+    /// Replaces the driver workarounds that are otherwise picked from a
+    /// table of known driver problems when the renderer is created.
+    pub driver_workarounds: Option<DriverWorkarounds>,
+    /// Replaces the way the vertex and instance data of each frame is
+    /// written to its buffers, which is otherwise picked by the driver
+    /// workarounds.
+    pub buffer_update_strategy: Option<BufferUpdateStrategy>,
+    /// How the edges of transformed primitives are antialiased, see
+    /// `EdgeAaMode`.
+    pub edge_aa_mode: EdgeAaMode,
+    /// Dithers gradients and blurs to hide banding. Never done in
+    /// deterministic mode.
+    pub enable_dithering: bool,
+    /// Blurs with half the texture fetches, by letting bilinear filtering
+    /// combine pairs of taps. Edges between colors come out slightly
+    /// softer.
+    pub fast_blur: bool,
+    /// Blurs with a compute shader, which fetches each source texel once
+    /// for the pixels around it, where OpenGL 4.3 compute shaders are
+    /// available. This has no effect on OpenGL ES, see
+    /// `Capabilities::supports_compute_shaders`. `fast_blur` only applies
+    /// to the fragment shader blurs used otherwise.
+    pub compute_blur: bool,
+    /// Rotates the frame onto the framebuffer as part of drawing it, see
+    /// `SurfaceTransform`. Damage rects are reported in framebuffer space,
+    /// while readbacks, overlay candidates and compositor surfaces keep the
+    /// orientation of the frame.
+    pub surface_transform: SurfaceTransform,
+    /// The origin of the framebuffer that is bound when `render` is called,
+    /// see `Renderer::set_framebuffer_origin`. The surfaces of a
+    /// `NativeCompositor` report their own origin.
+    pub framebuffer_origin: SurfaceOrigin,
+    /// Converts frames to the color space of the display after drawing
+    /// them, see `Renderer::set_output_color_transform`.
+    pub output_color_transform: Option<OutputColorTransform>,
+    /// The color space frames are drawn in, which should be that of the
+    /// framebuffer. Colors, which display lists give in sRGB, and images
+    /// tagged with another color space are converted to it. Gradients are
+    /// still interpolated in sRGB. Blending happens on the encoded values
+    /// either way, as both color spaces share the sRGB transfer function.
+    pub color_space: ColorSpace,
+    /// The most entries the color table of an angle or radial gradient can
+    /// have, rounded down to a multiple of 128. Stops are moved to the
+    /// nearest entry, so long gradients are given more of them, and the
+    /// most when they have hard stops.
+    pub max_gradient_resolution: usize,
+    /// Draws frames for an HDR swapchain instead of an SDR one, see
+    /// `Renderer::set_hdr_output`. Can't be combined with an output color
+    /// transform.
+    pub hdr_output: Option<HdrOutput>,
+    /// What is released while memory is short, see
+    /// `RenderApi::set_memory_pressure`.
+    pub memory_pressure_policy: MemoryPressurePolicy,
+    /// How many of the latest alpha batches are searched for one that a
+    /// primitive can join. Searching further back gives fewer, larger
+    /// batches, for more CPU time spent batching. Must be non-zero.
+    pub batch_lookback_count: usize,
+    /// How many subpixel positions per pixel glyphs are rasterized at with
+    /// subpixel AA. Fewer positions share glyph cache entries between more
+    /// glyphs, e.g. of animated text, for slightly less exact placement.
+    pub subpixel_quantization: SubpixelQuantization,
+    /// How far past each edge of the screen primitives below a reference
+    /// frame whose pose is set with `Renderer::set_late_latched_pose` are
+    /// kept, as a fraction of the screen size. Frames are culled with the
+    /// pose known when they were built, so this leaves room for the
+    /// latched pose to turn content into view. Must be finite and not
+    /// negative.
+    pub late_latch_cull_margin: f32,
 }
 
 impl Default for RendererOptions {
@@ -2017,6 +5278,7 @@ impl Default for RendererOptions {
             max_recorded_profiles: 0,
             debug: false,
             enable_scrollbars: false,
+            scrollbar_theme: ScrollbarTheme::default(),
             precache_shaders: false,
             renderer_kind: RendererKind::Native,
             enable_subpixel_aa: false,
@@ -2027,6 +5289,536 @@ impl Default for RendererOptions {
             workers: None,
             blob_image_renderer: None,
             recorder: None,
+            enable_overlay_promotion: false,
+            enable_compositor_surfaces: false,
+            native_compositor: None,
+            transparent_framebuffer: false,
+            ortho_near_plane: ORTHO_NEAR_PLANE,
+            ortho_far_plane: ORTHO_FAR_PLANE,
+            z_allocation: ZAllocation::PerItem,
+            synchronous_backend: false,
+            skip_unchanged_frames: false,
+            validate_display_lists: cfg!(debug_assertions),
+            deterministic: false,
+            single_threaded_workers: false,
+            texture_allocator: TextureAllocatorConfig::default(),
+            intermediate_sample_count: 1,
+            driver_workarounds: None,
+            buffer_update_strategy: None,
+            edge_aa_mode: EdgeAaMode::Fast,
+            enable_dithering: true,
+            fast_blur: false,
+            compute_blur: true,
+            surface_transform: SurfaceTransform::Identity,
+            framebuffer_origin: SurfaceOrigin::BottomLeft,
+            output_color_transform: None,
+            color_space: ColorSpace::Srgb,
+            max_gradient_resolution: 1024,
+            hdr_output: None,
+            memory_pressure_policy: MemoryPressurePolicy::default(),
+            batch_lookback_count: DEFAULT_BATCH_LOOKBACK_COUNT,
+            subpixel_quantization: SubpixelQuantization::default(),
+            late_latch_cull_margin: 0.25,
+        }
+    }
+}
+
+impl RendererOptions {
+    /// Options for weak GPUs, trading quality for speed and memory: a
+    /// smaller texture cache, grayscale text, no dithering or
+    /// multisampling, cheaper blurs and edge AA, coarser gradients, shaders
+    /// compiled only when first used, and render targets released as soon
+    /// as frames stop needing them.
+    pub fn low_quality_profile() -> RendererOptions {
+        let mut memory_pressure_policy = MemoryPressurePolicy::default();
+        memory_pressure_policy.free_empty_cache_pages = Some(MemoryPressure::Normal);
+        memory_pressure_policy.trim_render_targets = Some(MemoryPressure::Normal);
+
+        RendererOptions {
+            max_texture_size: Some(LOW_QUALITY_MAX_TEXTURE_SIZE),
+            enable_subpixel_aa: false,
+            enable_dithering: false,
+            fast_blur: true,
+            precache_shaders: false,
+            edge_aa_mode: EdgeAaMode::Fast,
+            intermediate_sample_count: 1,
+            max_gradient_resolution: GRADIENT_DATA_RESOLUTION,
+            memory_pressure_policy: memory_pressure_policy,
+            ..RendererOptions::default()
+        }
+    }
+
+    /// Options for software rasterizers such as OSMesa, e.g. on headless
+    /// servers and VMs without a GPU: small texture cache pages, no
+    /// dithering or multisampling, cheap edge AA, and alpha batches that
+    /// are searched further back so that frames take fewer draw calls.
+    pub fn software_profile() -> RendererOptions {
+        RendererOptions {
+            renderer_kind: RendererKind::OSMesa,
+            max_texture_size: Some(SOFTWARE_MAX_TEXTURE_SIZE),
+            enable_dithering: false,
+            edge_aa_mode: EdgeAaMode::Fast,
+            intermediate_sample_count: 1,
+            batch_lookback_count: SOFTWARE_BATCH_LOOKBACK_COUNT,
+            ..RendererOptions::default()
+        }
+    }
+
+    /// Checks that the options are consistent with each other.
+    pub fn validate(&self) -> Result<(), RendererOptionsError> {
+        if !self.device_pixel_ratio.is_finite() || self.device_pixel_ratio <= 0.0 {
+            return Err(RendererOptionsError::InvalidDevicePixelRatio(self.device_pixel_ratio));
+        }
+        if self.enable_subpixel_aa {
+            try!(validate_subpixel_aa(self.enable_aa, self.transparent_framebuffer));
+        }
+        if !self.ortho_near_plane.is_finite() ||
+           !self.ortho_far_plane.is_finite() ||
+           self.ortho_near_plane >= 0.0 ||
+           self.ortho_far_plane <= 0.0 {
+            return Err(RendererOptionsError::InvalidDepthRange(self.ortho_near_plane,
+                                                               self.ortho_far_plane));
+        }
+        if self.max_texture_size == Some(0) {
+            return Err(RendererOptionsError::InvalidMaxTextureSize(0));
+        }
+        if self.batch_lookback_count == 0 {
+            return Err(RendererOptionsError::InvalidBatchLookbackCount);
+        }
+        if self.max_gradient_resolution < GRADIENT_DATA_RESOLUTION {
+            return Err(RendererOptionsError::InvalidMaxGradientResolution(self.max_gradient_resolution));
+        }
+        if !self.late_latch_cull_margin.is_finite() || self.late_latch_cull_margin < 0.0 {
+            return Err(RendererOptionsError::InvalidLateLatchCullMargin(self.late_latch_cull_margin));
+        }
+        if let RendererKind::OSMesa = self.renderer_kind {
+            if self.native_compositor.is_some() {
+                return Err(RendererOptionsError::NativeCompositorWithOSMesa);
+            }
+        }
+        if let Some(ref transform) = self.output_color_transform {
+            if !transform.is_valid() {
+                return Err(RendererOptionsError::InvalidOutputColorTransform);
+            }
+        }
+        if let Some(ref hdr_output) = self.hdr_output {
+            if !hdr_output.is_valid() {
+                return Err(RendererOptionsError::InvalidHdrOutput);
+            }
+            if self.output_color_transform.is_some() {
+                return Err(RendererOptionsError::HdrOutputWithColorTransform);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A problem the renderer worked around instead of failing, returned by
+/// `Renderer::take_warnings`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RendererWarning {
+    /// An external image was used without a handler being set with
+    /// `Renderer::set_external_image_handler`. A placeholder was drawn
+    /// instead, or nothing for a pipeline's render target.
+    MissingExternalImageHandler(ExternalImageId),
+    /// A readback finished without a handler being set with
+    /// `Renderer::set_readback_handler`. Its pixels were dropped.
+    MissingReadbackHandler,
+    /// The GPU failed to finish a readback or snapshot, or its pixels
+    /// couldn't be mapped. The handler wasn't called for it.
+    ReadbackFailed,
+    /// A mix-blend-mode stacking context drawn straight to a framebuffer
+    /// rotated by 90 or 270 degrees needed its backdrop, which can't be
+    /// copied out of the framebuffer without also transposing it. The
+    /// blend used a transposed backdrop.
+    TransposedBackdrop,
+    /// The external image set as a pipeline's render target wasn't a
+    /// native texture. The pipeline wasn't copied to it.
+    InvalidRenderTarget(ExternalImageId),
+}
+
+impl fmt::Display for RendererWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RendererWarning::MissingExternalImageHandler(id) => {
+                write!(f, "external image {:?} used, but no external image handler is set", id)
+            }
+            RendererWarning::MissingReadbackHandler => {
+                write!(f, "readback finished, but no readback handler is set")
+            }
+            RendererWarning::ReadbackFailed => {
+                write!(f, "readback failed on the GPU")
+            }
+            RendererWarning::TransposedBackdrop => {
+                write!(f, "mix-blend-mode backdrop read from a framebuffer rotated by 90 or 270 degrees")
+            }
+            RendererWarning::InvalidRenderTarget(id) => {
+                write!(f, "render target {:?} is not a native texture", id)
+            }
+        }
+    }
+}
+
+/// Whether subpixel AA can be enabled along with the other options.
+fn validate_subpixel_aa(enable_aa: bool, transparent_framebuffer: bool) -> Result<(), RendererOptionsError> {
+    if !enable_aa {
+        return Err(RendererOptionsError::SubpixelAaWithoutAa);
+    }
+    if transparent_framebuffer {
+        return Err(RendererOptionsError::SubpixelAaWithTransparentFramebuffer);
+    }
+    Ok(())
+}
+
+/// An invalid combination of `RendererOptions`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RendererOptionsError {
+    /// The device pixel ratio must be finite and positive.
+    InvalidDevicePixelRatio(f32),
+    /// Subpixel AA can't be enabled when AA is disabled.
+    SubpixelAaWithoutAa,
+    /// The depth range must be finite and contain zero.
+    InvalidDepthRange(f32, f32),
+    /// The maximum texture size must be non-zero.
+    InvalidMaxTextureSize(u32),
+    /// Gradients can't have fewer than 128 entries.
+    InvalidMaxGradientResolution(usize),
+    /// Primitives must be able to join at least the latest batch.
+    InvalidBatchLookbackCount,
+    /// OSMesa renders offscreen, so it can't draw into native compositor surfaces.
+    NativeCompositorWithOSMesa,
+    /// Subpixel AA needs an opaque framebuffer to blend against.
+    SubpixelAaWithTransparentFramebuffer,
+    /// The output color transform must have a positive gamma, or a table
+    /// with as many entries as its size says.
+    InvalidOutputColorTransform,
+    /// The SDR white level and peak luminance of HDR output must be
+    /// positive.
+    InvalidHdrOutput,
+    /// HDR output does its own color conversion.
+    HdrOutputWithColorTransform,
+    /// The late-latch cull margin must be finite and not negative.
+    InvalidLateLatchCullMargin(f32),
+}
+
+impl fmt::Display for RendererOptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RendererOptionsError::InvalidDevicePixelRatio(ratio) => {
+                write!(f, "invalid device pixel ratio {}", ratio)
+            }
+            RendererOptionsError::SubpixelAaWithoutAa => {
+                write!(f, "subpixel AA requires AA to be enabled")
+            }
+            RendererOptionsError::InvalidDepthRange(near, far) => {
+                write!(f, "invalid depth range {}..{}", near, far)
+            }
+            RendererOptionsError::InvalidMaxTextureSize(size) => {
+                write!(f, "invalid max texture size {}", size)
+            }
+            RendererOptionsError::InvalidMaxGradientResolution(resolution) => {
+                write!(f, "invalid max gradient resolution {}", resolution)
+            }
+            RendererOptionsError::InvalidBatchLookbackCount => {
+                write!(f, "the batch lookback count must be non-zero")
+            }
+            RendererOptionsError::NativeCompositorWithOSMesa => {
+                write!(f, "a native compositor can't be used with the OSMesa renderer")
+            }
+            RendererOptionsError::SubpixelAaWithTransparentFramebuffer => {
+                write!(f, "subpixel AA can't be used with a transparent framebuffer")
+            }
+            RendererOptionsError::InvalidOutputColorTransform => {
+                write!(f, "invalid output color transform")
+            }
+            RendererOptionsError::InvalidHdrOutput => {
+                write!(f, "invalid HDR output luminance")
+            }
+            RendererOptionsError::HdrOutputWithColorTransform => {
+                write!(f, "HDR output can't be used with an output color transform")
+            }
+            RendererOptionsError::InvalidLateLatchCullMargin(margin) => {
+                write!(f, "invalid late-latch cull margin {}", margin)
+            }
+        }
+    }
+}
+
+/// Builds `RendererOptions`, starting from the defaults, and validates
+/// them on `build()`.
+///
+/// ```rust,ignore
+/// let options = webrender::RendererOptionsBuilder::new()
+///     .device_pixel_ratio(2.0)
+///     .enable_subpixel_aa(true)
+///     .build()
+///     .unwrap();
+/// ```
+pub struct RendererOptionsBuilder {
+    options: RendererOptions,
+}
+
+impl RendererOptionsBuilder {
+    pub fn new() -> RendererOptionsBuilder {
+        RendererOptionsBuilder {
+            options: RendererOptions::default(),
         }
     }
+
+    /// Starts from `RendererOptions::low_quality_profile` instead of the
+    /// defaults.
+    pub fn low_quality_profile() -> RendererOptionsBuilder {
+        RendererOptionsBuilder {
+            options: RendererOptions::low_quality_profile(),
+        }
+    }
+
+    /// Starts from `RendererOptions::software_profile` instead of the
+    /// defaults.
+    pub fn software_profile() -> RendererOptionsBuilder {
+        RendererOptionsBuilder {
+            options: RendererOptions::software_profile(),
+        }
+    }
+
+    pub fn device_pixel_ratio(mut self, device_pixel_ratio: f32) -> RendererOptionsBuilder {
+        self.options.device_pixel_ratio = device_pixel_ratio;
+        self
+    }
+
+    pub fn resource_override_path(mut self, path: PathBuf) -> RendererOptionsBuilder {
+        self.options.resource_override_path = Some(path);
+        self
+    }
+
+    pub fn enable_aa(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.enable_aa = enable;
+        self
+    }
+
+    pub fn enable_profiler(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.enable_profiler = enable;
+        self
+    }
+
+    pub fn max_recorded_profiles(mut self, count: usize) -> RendererOptionsBuilder {
+        self.options.max_recorded_profiles = count;
+        self
+    }
+
+    pub fn debug(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.debug = enable;
+        self
+    }
+
+    pub fn enable_scrollbars(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.enable_scrollbars = enable;
+        self
+    }
+
+    pub fn precache_shaders(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.precache_shaders = enable;
+        self
+    }
+
+    pub fn renderer_kind(mut self, kind: RendererKind) -> RendererOptionsBuilder {
+        self.options.renderer_kind = kind;
+        self
+    }
+
+    pub fn enable_subpixel_aa(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.enable_subpixel_aa = enable;
+        self
+    }
+
+    pub fn clear_framebuffer(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.clear_framebuffer = enable;
+        self
+    }
+
+    pub fn clear_color(mut self, color: ColorF) -> RendererOptionsBuilder {
+        self.options.clear_color = color;
+        self
+    }
+
+    pub fn render_target_debug(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.render_target_debug = enable;
+        self
+    }
+
+    pub fn max_texture_size(mut self, size: u32) -> RendererOptionsBuilder {
+        self.options.max_texture_size = Some(size);
+        self
+    }
+
+    pub fn workers(mut self, workers: Arc<Mutex<ThreadPool>>) -> RendererOptionsBuilder {
+        self.options.workers = Some(workers);
+        self
+    }
+
+    pub fn blob_image_renderer(mut self, renderer: Box<BlobImageRenderer>) -> RendererOptionsBuilder {
+        self.options.blob_image_renderer = Some(renderer);
+        self
+    }
+
+    pub fn recorder(mut self, recorder: Box<ApiRecordingReceiver>) -> RendererOptionsBuilder {
+        self.options.recorder = Some(recorder);
+        self
+    }
+
+    pub fn native_compositor(mut self, compositor: Box<NativeCompositor>) -> RendererOptionsBuilder {
+        self.options.native_compositor = Some(compositor);
+        self
+    }
+
+    pub fn enable_overlay_promotion(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.enable_overlay_promotion = enable;
+        self
+    }
+
+    pub fn enable_compositor_surfaces(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.enable_compositor_surfaces = enable;
+        self
+    }
+
+    pub fn intermediate_sample_count(mut self, sample_count: u32) -> RendererOptionsBuilder {
+        self.options.intermediate_sample_count = sample_count;
+        self
+    }
+
+    pub fn driver_workarounds(mut self, workarounds: DriverWorkarounds) -> RendererOptionsBuilder {
+        self.options.driver_workarounds = Some(workarounds);
+        self
+    }
+
+    pub fn buffer_update_strategy(mut self, strategy: BufferUpdateStrategy) -> RendererOptionsBuilder {
+        self.options.buffer_update_strategy = Some(strategy);
+        self
+    }
+
+    pub fn edge_aa_mode(mut self, mode: EdgeAaMode) -> RendererOptionsBuilder {
+        self.options.edge_aa_mode = mode;
+        self
+    }
+
+    pub fn enable_dithering(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.enable_dithering = enable;
+        self
+    }
+
+    pub fn fast_blur(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.fast_blur = enable;
+        self
+    }
+
+    pub fn compute_blur(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.compute_blur = enable;
+        self
+    }
+
+    pub fn surface_transform(mut self, transform: SurfaceTransform) -> RendererOptionsBuilder {
+        self.options.surface_transform = transform;
+        self
+    }
+
+    pub fn framebuffer_origin(mut self, origin: SurfaceOrigin) -> RendererOptionsBuilder {
+        self.options.framebuffer_origin = origin;
+        self
+    }
+
+    pub fn output_color_transform(mut self, transform: OutputColorTransform) -> RendererOptionsBuilder {
+        self.options.output_color_transform = Some(transform);
+        self
+    }
+
+    pub fn color_space(mut self, color_space: ColorSpace) -> RendererOptionsBuilder {
+        self.options.color_space = color_space;
+        self
+    }
+
+    pub fn max_gradient_resolution(mut self, resolution: usize) -> RendererOptionsBuilder {
+        self.options.max_gradient_resolution = resolution;
+        self
+    }
+
+    pub fn batch_lookback_count(mut self, count: usize) -> RendererOptionsBuilder {
+        self.options.batch_lookback_count = count;
+        self
+    }
+
+    pub fn subpixel_quantization(mut self, quantization: SubpixelQuantization) -> RendererOptionsBuilder {
+        self.options.subpixel_quantization = quantization;
+        self
+    }
+
+    pub fn late_latch_cull_margin(mut self, margin: f32) -> RendererOptionsBuilder {
+        self.options.late_latch_cull_margin = margin;
+        self
+    }
+
+    pub fn hdr_output(mut self, hdr_output: HdrOutput) -> RendererOptionsBuilder {
+        self.options.hdr_output = Some(hdr_output);
+        self
+    }
+
+    pub fn memory_pressure_policy(mut self, policy: MemoryPressurePolicy) -> RendererOptionsBuilder {
+        self.options.memory_pressure_policy = policy;
+        self
+    }
+
+    pub fn transparent_framebuffer(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.transparent_framebuffer = enable;
+        self
+    }
+
+    pub fn depth_range(mut self, near: f32, far: f32) -> RendererOptionsBuilder {
+        self.options.ortho_near_plane = near;
+        self.options.ortho_far_plane = far;
+        self
+    }
+
+    pub fn scrollbar_theme(mut self, theme: ScrollbarTheme) -> RendererOptionsBuilder {
+        self.options.scrollbar_theme = theme;
+        self
+    }
+
+    pub fn z_allocation(mut self, z_allocation: ZAllocation) -> RendererOptionsBuilder {
+        self.options.z_allocation = z_allocation;
+        self
+    }
+
+    pub fn validate_display_lists(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.validate_display_lists = enable;
+        self
+    }
+
+    pub fn deterministic(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.deterministic = enable;
+        self
+    }
+
+    pub fn single_threaded_workers(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.single_threaded_workers = enable;
+        self
+    }
+
+    pub fn texture_allocator(mut self, config: TextureAllocatorConfig) -> RendererOptionsBuilder {
+        self.options.texture_allocator = config;
+        self
+    }
+
+    pub fn synchronous_backend(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.synchronous_backend = enable;
+        self
+    }
+
+    pub fn skip_unchanged_frames(mut self, enable: bool) -> RendererOptionsBuilder {
+        self.options.skip_unchanged_frames = enable;
+        self
+    }
+
+    pub fn build(self) -> Result<RendererOptions, RendererOptionsError> {
+        try!{ self.options.validate() };
+        Ok(self.options)
+    }
 }
@@ -10,6 +10,7 @@ use internal_types::{ExternalImageUpdateList, FontTemplate, SourceTexture, Textu
 use platform::font::{FontContext, RasterizedGlyph};
 use profiler::TextureCacheProfileCounters;
 use std::cell::RefCell;
+use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::Entry::{self, Occupied, Vacant};
 use std::fmt::Debug;
@@ -24,9 +25,10 @@ use thread_profiler::register_thread_with_profiler;
 use webrender_traits::{Epoch, FontKey, GlyphKey, ImageKey, ImageFormat, ImageRendering};
 use webrender_traits::{FontRenderMode, ImageData, GlyphDimensions, WebGLContextId};
 use webrender_traits::{DevicePoint, DeviceIntSize, DeviceUintRect, ImageDescriptor, ColorF};
-use webrender_traits::{GlyphOptions, GlyphInstance, TileOffset, TileSize};
+use webrender_traits::{DeviceUintPoint, DeviceUintSize};
+use webrender_traits::{GlyphOptions, GlyphInstance, SubpixelQuantization, TileOffset, TileSize};
 use webrender_traits::{BlobImageRenderer, BlobImageDescriptor, BlobImageError};
-use webrender_traits::{ExternalImageData, ExternalImageType};
+use webrender_traits::{ExternalImageData, ExternalImageType, ColorSpace, ResourceCacheStats};
 use threadpool::ThreadPool;
 use euclid::Point2D;
 
@@ -44,6 +46,9 @@ enum GlyphCacheMsg {
     AddFont(FontKey, FontTemplate),
     /// Request glyphs for a text run.
     RequestGlyphs(FontKey, Au, ColorF, Vec<GlyphInstance>, FontRenderMode, Option<GlyphOptions>),
+    /// Look up the dimensions of some glyphs on the workers. Each worker
+    /// sends the dimensions of its share of the glyphs to the sender.
+    RequestGlyphDimensions(Vec<GlyphKey>, Sender<Vec<(GlyphKey, Option<GlyphDimensions>)>>),
     // Remove an existing font.
     DeleteFont(FontKey),
     /// Finished requesting glyphs. Reply with new glyphs.
@@ -69,6 +74,10 @@ pub struct CacheItem {
     pub texture_id: SourceTexture,
     pub uv0: DevicePoint,
     pub uv1: DevicePoint,
+    /// Texels per image pixel. Below 1.0 when the image was too big for a
+    /// texture and had to be downscaled, see `downscale_image`. The UVs
+    /// always cover the whole image.
+    pub scale: f32,
 }
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug, Ord, PartialOrd)]
@@ -85,20 +94,140 @@ impl RenderedGlyphKey {
                index: u32,
                point: Point2D<f32>,
                render_mode: FontRenderMode,
-               glyph_options: Option<GlyphOptions>) -> RenderedGlyphKey {
+               glyph_options: Option<GlyphOptions>,
+               subpixel_quantization: SubpixelQuantization) -> RenderedGlyphKey {
+        let mut key = GlyphKey::new(font_key, size, color, index,
+                                    point, render_mode);
+        key.subpixel_point.set_quantized_offset(point, render_mode, subpixel_quantization);
+
         RenderedGlyphKey {
-            key: GlyphKey::new(font_key, size, color, index,
-                               point, render_mode),
+            key: key,
             render_mode: render_mode,
             glyph_options: glyph_options,
         }
     }
 }
 
+/// The sampling used for images drawn with `rendering`. Both crisp-edges
+/// and pixelated ask for the image not to be smoothed when it is scaled,
+/// so both are nearest filtered.
+pub fn texture_filter_for_rendering(rendering: ImageRendering) -> TextureFilter {
+    match rendering {
+        ImageRendering::Auto => TextureFilter::Linear,
+        ImageRendering::CrispEdges | ImageRendering::Pixelated => TextureFilter::Nearest,
+    }
+}
+
+/// Shrinks an image by the smallest integer factor that makes it fit in a
+/// `max_size` texture, averaging each block of pixels. Only 8 bit formats
+/// with their data in memory can be downscaled. Returns the new descriptor,
+/// the packed pixels and the applied scale.
+fn downscale_image(descriptor: &ImageDescriptor,
+                   data: &ImageData,
+                   max_size: u32)
+                   -> Option<(ImageDescriptor, Vec<u8>, f32)> {
+    let bytes = match *data {
+        ImageData::Raw(ref bytes) => bytes,
+        ImageData::Blob(..) | ImageData::External(..) => return None,
+    };
+    let bpp = match descriptor.format {
+        ImageFormat::A8 | ImageFormat::RGB8 | ImageFormat::RGBA8 => {
+            descriptor.format.bytes_per_pixel().unwrap() as usize
+        }
+        ImageFormat::Invalid | ImageFormat::RGBAF32 => return None,
+    };
+    if max_size == 0 || descriptor.width == 0 || descriptor.height == 0 {
+        return None;
+    }
+
+    let stride = descriptor.compute_stride() as usize;
+    let offset = descriptor.offset as usize;
+    let required_len = offset + (descriptor.height as usize - 1) * stride +
+                       descriptor.width as usize * bpp;
+    if bytes.len() < required_len {
+        return None;
+    }
+
+    let factor = (cmp::max(descriptor.width, descriptor.height) + max_size - 1) / max_size;
+    let width = (descriptor.width + factor - 1) / factor;
+    let height = (descriptor.height + factor - 1) / factor;
+
+    let mut pixels = vec![0; width as usize * height as usize * bpp];
+    let mut sums = vec![0u32; bpp];
+    for y in 0..height {
+        let y0 = y * factor;
+        let y1 = cmp::min(y0 + factor, descriptor.height);
+        for x in 0..width {
+            let x0 = x * factor;
+            let x1 = cmp::min(x0 + factor, descriptor.width);
+
+            for sum in sums.iter_mut() {
+                *sum = 0;
+            }
+            for src_y in y0..y1 {
+                let row = offset + src_y as usize * stride;
+                for src_x in x0..x1 {
+                    let src = row + src_x as usize * bpp;
+                    for channel in 0..bpp {
+                        sums[channel] += bytes[src + channel] as u32;
+                    }
+                }
+            }
+
+            let count = (x1 - x0) * (y1 - y0);
+            let dst = (y as usize * width as usize + x as usize) * bpp;
+            for channel in 0..bpp {
+                pixels[dst + channel] = ((sums[channel] + count / 2) / count) as u8;
+            }
+        }
+    }
+
+    let descriptor = ImageDescriptor {
+        width: width,
+        height: height,
+        stride: None,
+        offset: 0,
+        format: descriptor.format,
+        is_opaque: descriptor.is_opaque,
+        color_space: descriptor.color_space,
+    };
+
+    Some((descriptor, pixels, 1.0 / factor as f32))
+}
+
+/// Converts the pixels of an RGBA8 or RGB8 image from the color space in
+/// its descriptor to `to`, in place.
+fn convert_color_space(descriptor: &ImageDescriptor, bytes: &mut [u8], to: ColorSpace) {
+    // RGBA8 images are stored as BGRA.
+    let (bpp, red, blue) = match descriptor.format {
+        ImageFormat::RGBA8 => (4, 2, 0),
+        ImageFormat::RGB8 => (3, 0, 2),
+        _ => return,
+    };
+    let stride = descriptor.stride.unwrap_or(descriptor.width * bpp as u32) as usize;
+    let to_byte = |value: f32| (value.max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+
+    for y in 0..descriptor.height as usize {
+        let row_start = descriptor.offset as usize + y * stride;
+        let row = &mut bytes[row_start .. row_start + descriptor.width as usize * bpp];
+        for pixel in row.chunks_mut(bpp) {
+            let rgb = descriptor.color_space.convert_rgb(to, [pixel[red] as f32 / 255.0,
+                                                               pixel[1] as f32 / 255.0,
+                                                               pixel[blue] as f32 / 255.0]);
+            pixel[red] = to_byte(rgb[0]);
+            pixel[1] = to_byte(rgb[1]);
+            pixel[blue] = to_byte(rgb[2]);
+        }
+    }
+}
+
 pub struct ImageProperties {
     pub descriptor: ImageDescriptor,
     pub external_image: Option<ExternalImageData>,
     pub tiling: Option<TileSize>,
+    /// Bumped each time the image is updated. WebGL images don't have one,
+    /// since their contents change without the resource cache knowing.
+    pub epoch: Epoch,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -119,6 +248,7 @@ struct ImageResource {
 struct CachedImageInfo {
     texture_cache_id: TextureCacheItemId,
     epoch: Epoch,
+    scale: f32,
 }
 
 pub struct ResourceClassCache<K,V> {
@@ -162,13 +292,14 @@ impl<K,V> ResourceClassCache<K,V> where K: Clone + Hash + Eq + Debug, V: Resourc
         self.last_access_times.insert((*key).clone(), frame);
     }
 
-    fn expire_old_resources(&mut self, texture_cache: &mut TextureCache, frame_id: FrameId) {
+    fn expire_old_resources(&mut self, texture_cache: &mut TextureCache, frame_id: FrameId) -> bool {
         let mut resources_to_destroy = vec![];
         for (key, this_frame_id) in &self.last_access_times {
             if *this_frame_id < frame_id {
                 resources_to_destroy.push((*key).clone())
             }
         }
+        let expired_any = !resources_to_destroy.is_empty();
         for key in resources_to_destroy {
             let resource =
                 self.resources
@@ -179,6 +310,7 @@ impl<K,V> ResourceClassCache<K,V> where K: Clone + Hash + Eq + Debug, V: Resourc
                 texture_cache.free(texture_cache_item_id)
             }
         }
+        expired_any
     }
 }
 
@@ -195,7 +327,6 @@ struct GlyphRasterJob {
 }
 
 struct WebGLTexture {
-    id: SourceTexture,
     size: DeviceIntSize,
 }
 
@@ -203,12 +334,14 @@ pub struct ResourceCache {
     cached_glyphs: Option<GlyphCache>,
     cached_images: ResourceClassCache<ImageRequest, CachedImageInfo>,
 
-    // TODO(pcwalton): Figure out the lifecycle of these.
     webgl_textures: HashMap<WebGLContextId, WebGLTexture, BuildHasherDefault<FnvHasher>>,
 
     font_templates: HashMap<FontKey, FontTemplate, BuildHasherDefault<FnvHasher>>,
     image_templates: HashMap<ImageKey, ImageResource, BuildHasherDefault<FnvHasher>>,
     enable_aa: bool,
+    /// The color space frames are drawn in, which images are converted to.
+    color_space: ColorSpace,
+    subpixel_quantization: SubpixelQuantization,
     state: State,
     current_frame_id: FrameId,
 
@@ -217,20 +350,28 @@ pub struct ResourceCache {
     // TODO(gw): We should expire (parts of) this cache semi-regularly!
     cached_glyph_dimensions: HashMap<GlyphKey, Option<GlyphDimensions>, BuildHasherDefault<FnvHasher>>,
     pending_image_requests: Vec<ImageRequest>,
+    // Glyphs that the fonts don't have, since the last time they were taken.
+    missing_glyphs: Vec<(FontKey, u32)>,
     glyph_cache_tx: Sender<GlyphCacheMsg>,
     glyph_cache_result_queue: Receiver<GlyphCacheResultMsg>,
     pending_external_image_update_list: ExternalImageUpdateList,
 
     blob_image_renderer: Option<Box<BlobImageRenderer>>,
     blob_image_requests: HashSet<ImageRequest>,
+    // The same requests in the order they were made, so that they get
+    // resolved and placed in the texture cache in a repeatable order.
+    pending_blob_image_requests: Vec<ImageRequest>,
 }
 
 impl ResourceCache {
     pub fn new(texture_cache: TextureCache,
                workers: Arc<Mutex<ThreadPool>>,
                blob_image_renderer: Option<Box<BlobImageRenderer>>,
-               enable_aa: bool) -> ResourceCache {
-        let (glyph_cache_tx, glyph_cache_result_queue) = spawn_glyph_cache_thread(workers);
+               enable_aa: bool,
+               color_space: ColorSpace,
+               subpixel_quantization: SubpixelQuantization) -> ResourceCache {
+        let (glyph_cache_tx, glyph_cache_result_queue) = spawn_glyph_cache_thread(workers,
+                                                                                  subpixel_quantization);
 
         ResourceCache {
             cached_glyphs: Some(ResourceClassCache::new()),
@@ -242,14 +383,18 @@ impl ResourceCache {
             texture_cache: texture_cache,
             state: State::Idle,
             enable_aa: enable_aa,
+            color_space: color_space,
+            subpixel_quantization: subpixel_quantization,
             current_frame_id: FrameId(0),
             pending_image_requests: Vec::new(),
+            missing_glyphs: Vec::new(),
             glyph_cache_tx: glyph_cache_tx,
             glyph_cache_result_queue: glyph_cache_result_queue,
             pending_external_image_update_list: ExternalImageUpdateList::new(),
 
             blob_image_renderer: blob_image_renderer,
             blob_image_requests: HashSet::new(),
+            pending_blob_image_requests: Vec::new(),
         }
     }
 
@@ -257,6 +402,27 @@ impl ResourceCache {
         self.texture_cache.max_texture_size()
     }
 
+    /// Converts images in another color space than frames are drawn in.
+    /// Blob and external images are drawn as they are.
+    fn convert_image_data(&self,
+                          mut descriptor: ImageDescriptor,
+                          data: ImageData) -> (ImageDescriptor, ImageData) {
+        if descriptor.color_space == self.color_space {
+            return (descriptor, data);
+        }
+
+        let data = match data {
+            ImageData::Raw(bytes) => {
+                let mut bytes = (*bytes).clone();
+                convert_color_space(&descriptor, &mut bytes, self.color_space);
+                ImageData::new(bytes)
+            }
+            data => return (descriptor, data),
+        };
+        descriptor.color_space = self.color_space;
+        (descriptor, data)
+    }
+
     fn should_tile(&self, descriptor: &ImageDescriptor, data: &ImageData) -> bool {
         let limit = self.max_texture_size();
         let size_check = descriptor.width > limit || descriptor.height > limit;
@@ -292,6 +458,7 @@ impl ResourceCache {
                               descriptor: ImageDescriptor,
                               data: ImageData,
                               mut tiling: Option<TileSize>) {
+        let (descriptor, data) = self.convert_image_data(descriptor, data);
         if tiling.is_none() && self.should_tile(&descriptor, &data) {
             // We aren't going to be able to upload a texture this big, so tile it, even
             // if tiling was not requested.
@@ -314,6 +481,7 @@ impl ResourceCache {
                                  descriptor: ImageDescriptor,
                                  data: ImageData,
                                  dirty_rect: Option<DeviceUintRect>) {
+        let (descriptor, data) = self.convert_image_data(descriptor, data);
         let resource = if let Some(image) = self.image_templates.get(&image_key) {
             assert!(image.descriptor.width == descriptor.width);
             assert!(image.descriptor.height == descriptor.height);
@@ -368,21 +536,23 @@ impl ResourceCache {
         println!("Delete the non-exist key:{:?}", image_key);
     }
 
-    pub fn add_webgl_texture(&mut self, id: WebGLContextId, texture_id: SourceTexture, size: DeviceIntSize) {
+    /// The renderer resolves WebGL contexts to the texture they currently
+    /// share, so only their size is kept here.
+    pub fn add_webgl_texture(&mut self, id: WebGLContextId, size: DeviceIntSize) {
         self.webgl_textures.insert(id, WebGLTexture {
-            id: texture_id,
             size: size,
         });
     }
 
-    pub fn update_webgl_texture(&mut self, id: WebGLContextId, texture_id: SourceTexture, size: DeviceIntSize) {
+    pub fn update_webgl_texture(&mut self, id: WebGLContextId, size: DeviceIntSize) {
         let webgl_texture = self.webgl_textures.get_mut(&id).unwrap();
-
-        // Update new texture id and size
-        webgl_texture.id = texture_id;
         webgl_texture.size = size;
     }
 
+    pub fn delete_webgl_texture(&mut self, id: WebGLContextId) {
+        self.webgl_textures.remove(&id);
+    }
+
     pub fn request_image(&mut self,
                          key: ImageKey,
                          rendering: ImageRendering,
@@ -403,7 +573,8 @@ impl ResourceCache {
                     None => false,
                 };
 
-                if !same_epoch && self.blob_image_requests.insert(request) {
+                if !same_epoch && self.blob_image_requests.insert(request.clone()) {
+                    self.pending_blob_image_requests.push(request);
                     renderer.request_blob_image(
                         key,
                         Arc::clone(&data),
@@ -469,11 +640,14 @@ impl ResourceCache {
                                                   0,
                                                   Point2D::new(0.0, 0.0),
                                                   render_mode,
-                                                  glyph_options);
+                                                  glyph_options,
+                                                  self.subpixel_quantization);
         let mut texture_id = None;
         for (loop_index, glyph_instance) in glyph_instances.iter().enumerate() {
             glyph_key.key.index = glyph_instance.index;
-            glyph_key.key.subpixel_point.set_offset(glyph_instance.point, render_mode);
+            glyph_key.key.subpixel_point.set_quantized_offset(glyph_instance.point,
+                                                              render_mode,
+                                                              self.subpixel_quantization);
 
             let image_id = cache.get(&glyph_key, self.current_frame_id);
             let cache_item = image_id.map(|image_id| self.texture_cache.get(image_id));
@@ -492,6 +666,41 @@ impl ResourceCache {
         texture_id.map_or(SourceTexture::Invalid, SourceTexture::TextureCache)
     }
 
+    /// Looks up the dimensions of the glyphs that aren't cached yet, in
+    /// parallel on the workers, so that `get_glyph_dimensions` finds them
+    /// without asking the font backend one glyph at a time.
+    pub fn request_glyph_dimensions(&mut self, glyph_keys: &[GlyphKey]) {
+        let mut missing_keys: Vec<GlyphKey> = glyph_keys.iter()
+                                                        .filter(|key| !self.cached_glyph_dimensions.contains_key(key))
+                                                        .cloned()
+                                                        .collect();
+        missing_keys.sort();
+        missing_keys.dedup();
+
+        // Not worth the round trip for a glyph or two.
+        if missing_keys.len() < 2 {
+            return;
+        }
+
+        let mut pending_count = missing_keys.len();
+        let (dimensions_tx, dimensions_rx) = channel();
+        let msg = GlyphCacheMsg::RequestGlyphDimensions(missing_keys, dimensions_tx);
+        self.glyph_cache_tx.send(msg).unwrap();
+
+        while pending_count > 0 {
+            let dimensions = dimensions_rx.recv()
+                                          .expect("BUG: Should be glyph dimensions pending!");
+            pending_count -= dimensions.len();
+            self.cached_glyph_dimensions.extend(dimensions);
+        }
+    }
+
+    /// How many subpixel positions glyphs are rasterized at, see
+    /// `RendererOptions::subpixel_quantization`.
+    pub fn subpixel_quantization(&self) -> SubpixelQuantization {
+        self.subpixel_quantization
+    }
+
     pub fn get_glyph_dimensions(&mut self, glyph_key: &GlyphKey) -> Option<GlyphDimensions> {
         match self.cached_glyph_dimensions.entry(glyph_key.clone()) {
             Occupied(entry) => *entry.get(),
@@ -538,6 +747,7 @@ impl ResourceCache {
                                   item.pixel_rect.top_left.y as f32),
             uv1: DevicePoint::new(item.pixel_rect.bottom_right.x as f32,
                                   item.pixel_rect.bottom_right.y as f32),
+            scale: image_info.scale,
         }
     }
 
@@ -563,24 +773,60 @@ impl ResourceCache {
             descriptor: image_template.descriptor,
             external_image: external_image,
             tiling: image_template.tiling,
+            epoch: image_template.epoch,
         }
     }
 
-    #[inline]
+    /// WebGL content is drawn upside down, hence the flipped UVs. A context
+    /// that was deleted while the scene still draws it resolves to an
+    /// invalid texture, which draws nothing.
     pub fn get_webgl_texture(&self, context_id: &WebGLContextId) -> CacheItem {
-        let webgl_texture = &self.webgl_textures[context_id];
-        CacheItem {
-            texture_id: webgl_texture.id,
-            uv0: DevicePoint::new(0.0, webgl_texture.size.height as f32),
-            uv1: DevicePoint::new(webgl_texture.size.width as f32, 0.0),
+        match self.webgl_textures.get(context_id) {
+            Some(webgl_texture) => CacheItem {
+                texture_id: SourceTexture::WebGL(*context_id),
+                uv0: DevicePoint::new(0.0, webgl_texture.size.height as f32),
+                uv1: DevicePoint::new(webgl_texture.size.width as f32, 0.0),
+                scale: 1.0,
+            },
+            None => CacheItem {
+                texture_id: SourceTexture::Invalid,
+                uv0: DevicePoint::zero(),
+                uv1: DevicePoint::zero(),
+                scale: 1.0,
+            },
+        }
+    }
+
+    /// Counts the templates and cached items currently held.
+    pub fn stats(&self) -> ResourceCacheStats {
+        ResourceCacheStats {
+            font_templates: self.font_templates.len(),
+            image_templates: self.image_templates.len(),
+            cached_images: self.cached_images.resources.len(),
+            cached_glyphs: self.cached_glyphs.as_ref().map_or(0, |cache| cache.resources.len()),
+        }
+    }
+
+    /// Drops the cached glyph metrics and deletes the texture cache pages
+    /// that are empty, as asked for.
+    pub fn release_memory(&mut self, drop_glyph_dimensions: bool, free_empty_pages: bool) {
+        if drop_glyph_dimensions {
+            self.cached_glyph_dimensions.clear();
+        }
+        if free_empty_pages {
+            self.texture_cache.free_empty_pages();
         }
     }
 
-    pub fn expire_old_resources(&mut self, frame_id: FrameId) {
-        self.cached_images.expire_old_resources(&mut self.texture_cache, frame_id);
+    /// Frees the cached items that weren't used since `frame_id`. Returns
+    /// true if there were any.
+    pub fn expire_old_resources(&mut self, frame_id: FrameId) -> bool {
+        let expired_images = self.cached_images.expire_old_resources(&mut self.texture_cache,
+                                                                     frame_id);
 
         let cached_glyphs = self.cached_glyphs.as_mut().unwrap();
-        cached_glyphs.expire_old_resources(&mut self.texture_cache, frame_id);
+        let expired_glyphs = cached_glyphs.expire_old_resources(&mut self.texture_cache, frame_id);
+        expired_images || expired_glyphs
     }
 
     pub fn begin_frame(&mut self, frame_id: FrameId) {
@@ -591,6 +837,23 @@ impl ResourceCache {
         self.glyph_cache_tx.send(GlyphCacheMsg::BeginFrame(frame_id, glyph_cache)).ok();
     }
 
+    /// Returns the glyphs that were missing from their fonts since the
+    /// last call, grouped by font.
+    pub fn take_missing_glyphs(&mut self) -> Vec<(FontKey, Vec<u32>)> {
+        let mut missing_glyphs = mem::replace(&mut self.missing_glyphs, Vec::new());
+        missing_glyphs.sort();
+        missing_glyphs.dedup();
+
+        let mut fonts: Vec<(FontKey, Vec<u32>)> = Vec::new();
+        for (font_key, index) in missing_glyphs {
+            if fonts.last().map_or(true, |&(last_key, _)| last_key != font_key) {
+                fonts.push((font_key, Vec::new()));
+            }
+            fonts.last_mut().unwrap().1.push(index);
+        }
+        fonts
+    }
+
     pub fn block_until_all_resources_added(&mut self,
                                            texture_cache_profile: &mut TextureCacheProfileCounters) {
         profile_scope!("block_until_all_resources_added");
@@ -616,6 +879,12 @@ impl ResourceCache {
                 GlyphCacheResultMsg::EndFrame(mut cache, glyph_jobs) => {
                     // Add any newly rasterized glyphs to the texture cache.
                     for job in glyph_jobs {
+                        // A glyph the font can't load, or its .notdef glyph,
+                        // would be drawn as a blank space or as tofu.
+                        if job.result.is_none() || job.key.key.index == 0 {
+                            self.missing_glyphs.push((job.key.key.font_key, job.key.key.index));
+                        }
+
                         let image_id = job.result.and_then(|glyph| {
                             if glyph.width > 0 && glyph.height > 0 {
                                 let image_id = self.texture_cache.new_item_id();
@@ -627,6 +896,7 @@ impl ResourceCache {
                                                               format: ImageFormat::RGBA8,
                                                               is_opaque: false,
                                                               offset: 0,
+                                                              color_space: ColorSpace::Srgb,
                                                           },
                                                           TextureFilter::Linear,
                                                           ImageData::Raw(Arc::new(glyph.bytes)),
@@ -651,9 +921,10 @@ impl ResourceCache {
             self.finalize_image_request(request, None, texture_cache_profile);
         }
 
-        let mut blob_image_requests = mem::replace(&mut self.blob_image_requests, HashSet::new());
+        self.blob_image_requests.clear();
+        let mut blob_image_requests = mem::replace(&mut self.pending_blob_image_requests, Vec::new());
         if self.blob_image_renderer.is_some() {
-            for request in blob_image_requests.drain() {
+            for request in blob_image_requests.drain(..) {
                 match self.blob_image_renderer.as_mut().unwrap()
                                                 .resolve_blob_image(request.key) {
                     Ok(image) => {
@@ -680,6 +951,13 @@ impl ResourceCache {
                 }
             }
         }
+
+        // Every cached copy that is in use has been brought up to date by
+        // now. Copies that weren't requested may miss several updates, so
+        // they get a full upload when they are next requested.
+        for image_template in self.image_templates.values_mut() {
+            image_template.dirty_rect = None;
+        }
     }
 
     fn update_texture_cache(&mut self,
@@ -691,6 +969,7 @@ impl ResourceCache {
             image_template.data.clone()
         });
 
+        let mut dirty_rect = image_template.dirty_rect;
         let descriptor = if let Some(tile) = request.tile {
             let tile_size = image_template.tiling.unwrap() as u32;
             let image_descriptor = &image_template.descriptor;
@@ -714,6 +993,21 @@ impl ResourceCache {
             let offset = image_descriptor.offset + tile.y as u32 * tile_size * stride
                                                  + tile.x as u32 * tile_size * bpp;
 
+            // The dirty rect is in image space. Clip it to this tile and make
+            // it relative to the tile, so untouched tiles skip the upload.
+            if let Some(rect) = image_template.dirty_rect {
+                let tile_rect = DeviceUintRect::new(
+                    DeviceUintPoint::new(tile.x as u32 * tile_size, tile.y as u32 * tile_size),
+                    DeviceUintSize::new(actual_width, actual_height));
+                dirty_rect = Some(match rect.intersection(&tile_rect) {
+                    Some(rect) => DeviceUintRect::new(
+                        DeviceUintPoint::new(rect.origin.x - tile_rect.origin.x,
+                                             rect.origin.y - tile_rect.origin.y),
+                        rect.size),
+                    None => DeviceUintRect::zero(),
+                });
+            }
+
             ImageDescriptor {
                 width: actual_width,
                 height: actual_height,
@@ -721,36 +1015,65 @@ impl ResourceCache {
                 offset: offset,
                 format: image_descriptor.format,
                 is_opaque: image_descriptor.is_opaque,
+                color_space: image_descriptor.color_space,
             }
         } else {
             image_template.descriptor.clone()
         };
 
+        // Images that can't be split into tiles here, e.g. when used as a
+        // clip mask, are shrunk to fit in a texture instead.
+        let max_texture_size = self.texture_cache.max_texture_size();
+        let oversized = descriptor.width > max_texture_size ||
+                        descriptor.height > max_texture_size;
+        let (descriptor, image_data, scale) = if oversized {
+            match downscale_image(&descriptor, &image_data, max_texture_size) {
+                Some((descriptor, bytes, scale)) => {
+                    debug!("Downscaled image {:?} by {} to fit in a texture", request.key, scale);
+                    dirty_rect = None;
+                    (descriptor, ImageData::new(bytes), scale)
+                }
+                None => {
+                    warn!("Image {:?} is too big for a texture and can't be downscaled", request.key);
+                    (descriptor, image_data, 1.0)
+                }
+            }
+        } else {
+            (descriptor, image_data, 1.0)
+        };
+
         match self.cached_images.entry(request.clone(), self.current_frame_id) {
             Occupied(entry) => {
                 let image_id = entry.get().texture_cache_id;
 
                 if entry.get().epoch != image_template.epoch {
-                    self.texture_cache.update(image_id,
-                                              descriptor,
-                                              image_data,
-                                              image_template.dirty_rect);
+                    // The dirty rect only covers the latest update, so it
+                    // can't be used if this copy missed any before it.
+                    if entry.get().epoch.0 + 1 != image_template.epoch.0 {
+                        dirty_rect = None;
+                    }
+                    let needs_upload = dirty_rect.map_or(true, |rect| {
+                        rect.size.width > 0 && rect.size.height > 0
+                    });
+                    if needs_upload {
+                        self.texture_cache.update(image_id,
+                                                  descriptor,
+                                                  image_data,
+                                                  dirty_rect);
+                    }
 
                     // Update the cached epoch
                     *entry.into_mut() = CachedImageInfo {
                         texture_cache_id: image_id,
                         epoch: image_template.epoch,
+                        scale: scale,
                     };
-                    image_template.dirty_rect = None;
                 }
             }
             Vacant(entry) => {
                 let image_id = self.texture_cache.new_item_id();
 
-                let filter = match request.rendering {
-                    ImageRendering::Pixelated => TextureFilter::Nearest,
-                    ImageRendering::Auto | ImageRendering::CrispEdges => TextureFilter::Linear,
-                };
+                let filter = texture_filter_for_rendering(request.rendering);
 
                 self.texture_cache.insert(image_id,
                                           descriptor,
@@ -761,6 +1084,7 @@ impl ResourceCache {
                 entry.insert(CachedImageInfo {
                     texture_cache_id: image_id,
                     epoch: image_template.epoch,
+                    scale: scale,
                 });
             }
         }
@@ -827,7 +1151,9 @@ impl Resource for CachedImageInfo {
     }
 }
 
-fn spawn_glyph_cache_thread(workers: Arc<Mutex<ThreadPool>>) -> (Sender<GlyphCacheMsg>, Receiver<GlyphCacheResultMsg>) {
+fn spawn_glyph_cache_thread(workers: Arc<Mutex<ThreadPool>>,
+                            subpixel_quantization: SubpixelQuantization)
+                            -> (Sender<GlyphCacheMsg>, Receiver<GlyphCacheResultMsg>) {
     let worker_count = {
         workers.lock().unwrap().max_count()
     };
@@ -935,7 +1261,8 @@ fn spawn_glyph_cache_thread(workers: Arc<Mutex<ThreadPool>>) -> (Sender<GlyphCac
                                                               glyph_instance.index,
                                                               glyph_instance.point,
                                                               render_mode,
-                                                              glyph_options);
+                                                              glyph_options,
+                                                              subpixel_quantization);
 
                         glyph_cache.mark_as_needed(&glyph_key, current_frame_id);
                         if !glyph_cache.contains_key(&glyph_key) &&
@@ -955,6 +1282,29 @@ fn spawn_glyph_cache_thread(workers: Arc<Mutex<ThreadPool>>) -> (Sender<GlyphCac
                         }
                     }
                 }
+                GlyphCacheMsg::RequestGlyphDimensions(glyph_keys, dimensions_tx) => {
+                    profile_scope!("RequestGlyphDimensions");
+
+                    // Split the glyphs evenly between the workers. The
+                    // fonts were added to every worker by AddFont, which
+                    // came through this thread earlier.
+                    let chunk_size = (glyph_keys.len() + worker_count - 1) / worker_count;
+                    for chunk in glyph_keys.chunks(cmp::max(chunk_size, 1)) {
+                        let chunk = chunk.to_vec();
+                        let dimensions_tx = dimensions_tx.clone();
+                        workers.lock().unwrap().execute(move || {
+                            profile_scope!("glyph_dimensions");
+                            FONT_CONTEXT.with(move |font_context| {
+                                let mut font_context = font_context.borrow_mut();
+                                let dimensions = chunk.into_iter().map(|glyph_key| {
+                                    let dimensions = font_context.get_glyph_dimensions(&glyph_key);
+                                    (glyph_key, dimensions)
+                                }).collect();
+                                dimensions_tx.send(dimensions).unwrap();
+                            });
+                        });
+                    }
+                }
                 GlyphCacheMsg::EndFrame => {
                     profile_scope!("EndFrame");
 
@@ -989,3 +1339,60 @@ fn spawn_glyph_cache_thread(workers: Arc<Mutex<ThreadPool>>) -> (Sender<GlyphCac
 
     (msg_tx, result_rx)
 }
+
+#[cfg(test)]
+fn run_frame(resource_cache: &mut ResourceCache,
+             frame_id: u32,
+             requested_key: Option<ImageKey>) -> TextureUpdateList {
+    let mut texture_cache_profile = TextureCacheProfileCounters::new();
+    resource_cache.begin_frame(FrameId(frame_id));
+    if let Some(key) = requested_key {
+        resource_cache.request_image(key, ImageRendering::Auto, None);
+    }
+    resource_cache.block_until_all_resources_added(&mut texture_cache_profile);
+    resource_cache.end_frame();
+    resource_cache.pending_updates()
+}
+
+#[test]
+fn images_that_missed_updates_are_uploaded_whole() {
+    use internal_types::TextureUpdateOp;
+
+    let workers = Arc::new(Mutex::new(ThreadPool::new(1)));
+    let mut resource_cache = ResourceCache::new(TextureCache::new(2048),
+                                                workers,
+                                                None,
+                                                true,
+                                                ColorSpace::Srgb,
+                                                SubpixelQuantization::Pixels);
+    let key = ImageKey::new(0, 1);
+    let descriptor = ImageDescriptor::new(4, 4, ImageFormat::RGBA8, true);
+    let dirty_rect = |x, y| DeviceUintRect::new(DeviceUintPoint::new(x, y),
+                                                DeviceUintSize::new(1, 1));
+    let uploaded_size = |updates: TextureUpdateList| {
+        updates.updates.iter().filter_map(|update| match update.op {
+            TextureUpdateOp::Update { width, height, .. } => Some((width, height)),
+            _ => None,
+        }).collect::<Vec<_>>()
+    };
+
+    resource_cache.add_image_template(key, descriptor, ImageData::new(vec![0; 64]), None);
+    run_frame(&mut resource_cache, 1, Some(key));
+
+    // Two updates in frames that don't use the image.
+    resource_cache.update_image_template(key, descriptor, ImageData::new(vec![1; 64]),
+                                         Some(dirty_rect(0, 0)));
+    assert!(uploaded_size(run_frame(&mut resource_cache, 2, None)).is_empty());
+    resource_cache.update_image_template(key, descriptor, ImageData::new(vec![2; 64]),
+                                         Some(dirty_rect(3, 3)));
+    assert!(uploaded_size(run_frame(&mut resource_cache, 3, None)).is_empty());
+
+    let updates = run_frame(&mut resource_cache, 4, Some(key));
+    assert_eq!(uploaded_size(updates), vec![(4, 4)]);
+
+    // A copy that is only one update behind still gets a partial upload.
+    resource_cache.update_image_template(key, descriptor, ImageData::new(vec![3; 64]),
+                                         Some(dirty_rect(1, 1)));
+    let updates = run_frame(&mut resource_cache, 5, Some(key));
+    assert_eq!(uploaded_size(updates), vec![(1, 1)]);
+}
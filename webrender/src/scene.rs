@@ -7,7 +7,7 @@ use std::collections::HashMap;
 use std::hash::BuildHasherDefault;
 use tiling::AuxiliaryListsMap;
 use webrender_traits::{AuxiliaryLists, BuiltDisplayList, PipelineId, Epoch, ColorF};
-use webrender_traits::{DisplayItem, DynamicProperties, LayerSize, LayoutTransform};
+use webrender_traits::{DisplayItem, DynamicProperties, ExternalImageData, LayerSize, LayoutTransform};
 use webrender_traits::{PropertyBinding, PropertyBindingId};
 
 /// Stores a map of the animated property bindings for the current display list. These
@@ -40,6 +40,46 @@ impl SceneProperties {
         }
     }
 
+    /// The bindings whose values differ from those in `other`, as
+    /// (transforms, floats). Bindings that only one of them has a value
+    /// for count as changed.
+    pub fn changed_bindings(&self, other: &SceneProperties)
+                            -> (Vec<PropertyBindingId>, Vec<PropertyBindingId>) {
+        let mut transforms = Vec::new();
+        for (id, value) in &self.transform_properties {
+            if other.transform_properties.get(id) != Some(value) {
+                transforms.push(*id);
+            }
+        }
+        for id in other.transform_properties.keys() {
+            if !self.transform_properties.contains_key(id) {
+                transforms.push(*id);
+            }
+        }
+
+        let mut floats = Vec::new();
+        for (id, value) in &self.float_properties {
+            if other.float_properties.get(id) != Some(value) {
+                floats.push(*id);
+            }
+        }
+        for id in other.float_properties.keys() {
+            if !self.float_properties.contains_key(id) {
+                floats.push(*id);
+            }
+        }
+
+        (transforms, floats)
+    }
+
+    pub fn get_transform(&self, id: PropertyBindingId) -> Option<LayoutTransform> {
+        self.transform_properties.get(&id).cloned()
+    }
+
+    pub fn get_float(&self, id: PropertyBindingId) -> Option<f32> {
+        self.float_properties.get(&id).cloned()
+    }
+
     /// Get the current value for a transform property.
     pub fn resolve_layout_transform(&self,
                                     property: Option<&PropertyBinding<LayoutTransform>>)
@@ -86,7 +126,6 @@ pub struct ScenePipeline {
     pub pipeline_id: PipelineId,
     pub epoch: Epoch,
     pub viewport_size: LayerSize,
-    pub background_color: Option<ColorF>,
 }
 
 /// A complete representation of the layout bundling visible pipelines together.
@@ -95,6 +134,13 @@ pub struct Scene {
     pub pipeline_map: HashMap<PipelineId, ScenePipeline, BuildHasherDefault<FnvHasher>>,
     pub pipeline_auxiliary_lists: AuxiliaryListsMap,
     pub display_lists: HashMap<PipelineId, Vec<DisplayItem>, BuildHasherDefault<FnvHasher>>,
+    /// Set along with display lists, or ahead of them so that iframes
+    /// waiting for their first display list have a background. `None`
+    /// marks a transparent pipeline.
+    pub background_colors: HashMap<PipelineId, Option<ColorF>, BuildHasherDefault<FnvHasher>>,
+    /// The external images that pipelines are drawn into instead of their
+    /// iframes.
+    pub render_targets: HashMap<PipelineId, ExternalImageData, BuildHasherDefault<FnvHasher>>,
     pub properties: SceneProperties,
 }
 
@@ -105,6 +151,8 @@ impl Scene {
             pipeline_map: HashMap::with_hasher(Default::default()),
             pipeline_auxiliary_lists: HashMap::with_hasher(Default::default()),
             display_lists: HashMap::with_hasher(Default::default()),
+            background_colors: HashMap::with_hasher(Default::default()),
+            render_targets: HashMap::with_hasher(Default::default()),
             properties: SceneProperties::new(),
         }
     }
@@ -127,9 +175,43 @@ impl Scene {
             pipeline_id: pipeline_id,
             epoch: epoch,
             viewport_size: viewport_size,
-            background_color: background_color,
         };
 
         self.pipeline_map.insert(pipeline_id, new_pipeline);
+        self.background_colors.insert(pipeline_id, background_color);
+    }
+
+    pub fn set_background_color(&mut self,
+                                pipeline_id: PipelineId,
+                                background_color: Option<ColorF>) {
+        self.background_colors.insert(pipeline_id, background_color);
+    }
+
+    /// Returns the background of a pipeline, `None` if it's transparent or
+    /// was never given one.
+    pub fn background_color(&self, pipeline_id: PipelineId) -> Option<ColorF> {
+        self.background_colors.get(&pipeline_id).and_then(|color| *color)
+    }
+
+    pub fn set_render_target(&mut self,
+                             pipeline_id: PipelineId,
+                             target: Option<ExternalImageData>) {
+        match target {
+            Some(target) => {
+                self.render_targets.insert(pipeline_id, target);
+            }
+            None => {
+                self.render_targets.remove(&pipeline_id);
+            }
+        }
+    }
+
+    /// Returns false if the pipeline was unknown.
+    pub fn remove_pipeline(&mut self, pipeline_id: PipelineId) -> bool {
+        self.pipeline_auxiliary_lists.remove(&pipeline_id);
+        self.render_targets.remove(&pipeline_id);
+        self.display_lists.remove(&pipeline_id);
+        let had_background = self.background_colors.remove(&pipeline_id).is_some();
+        self.pipeline_map.remove(&pipeline_id).is_some() || had_background
     }
 }
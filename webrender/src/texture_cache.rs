@@ -41,6 +41,10 @@ const MINIMUM_MEDIUM_RECT_SIZE: u32 = 16;
 /// "large" within the free list.
 const MINIMUM_LARGE_RECT_SIZE: u32 = 32;
 
+/// The largest width and height of items that go in the small item pages, when size classes are
+/// enabled. Most glyphs fit.
+const MAXIMUM_SMALL_ITEM_SIZE: u32 = 64;
+
 /// The amount of time in milliseconds we give ourselves to coalesce rects before giving up.
 const COALESCING_TIMEOUT: u64 = 100;
 
@@ -50,6 +54,246 @@ const COALESCING_TIMEOUT_CHECKING_INTERVAL: usize = 256;
 
 pub type TextureCacheItemId = FreeListItemId;
 
+/// Selects how items are packed into texture cache pages, see
+/// `TextureAllocatorConfig`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureAllocatorKind {
+    /// Guillotine packing, see `GuillotineAllocator`. Copes well with any mix
+    /// of sizes, but frees leave strips behind that have to be coalesced.
+    Guillotine,
+    /// Shelf packing, see `ShelfAllocator`. Cheap, and doesn't fragment when
+    /// items have similar heights, as glyphs do, at the cost of padding
+    /// items up to the height of their shelf.
+    Shelf,
+}
+
+/// How the texture cache allocates space in its pages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureAllocatorConfig {
+    pub kind: TextureAllocatorKind,
+    /// Keeps small items, such as glyphs, in separate pages from larger
+    /// images. Otherwise a few long-lived glyphs can pin down a page that
+    /// images are constantly being allocated in and freed from.
+    pub size_classes: bool,
+}
+
+impl Default for TextureAllocatorConfig {
+    fn default() -> TextureAllocatorConfig {
+        TextureAllocatorConfig {
+            kind: TextureAllocatorKind::Guillotine,
+            size_classes: false,
+        }
+    }
+}
+
+/// How well the texture cache pages are packed.
+///
+/// The shelf allocator pads items to the height of their shelf, and the
+/// padding counts as neither allocated nor free, so `allocated_area` and
+/// `free_area` don't necessarily add up to `total_area`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TextureAllocatorStats {
+    pub pages: usize,
+    pub allocations: usize,
+    pub total_area: u64,
+    pub allocated_area: u64,
+    pub free_area: u64,
+    /// The area of the largest free rect in any page.
+    pub largest_free_area: u64,
+}
+
+impl TextureAllocatorStats {
+    /// The area that is neither allocated nor available for allocation.
+    pub fn wasted_area(&self) -> u64 {
+        self.total_area - self.allocated_area - self.free_area
+    }
+
+    /// The fraction of the free area that lies outside the largest free rect.
+    /// Zero when all the free space is in one piece, approaching one as it
+    /// gets scattered into small holes.
+    pub fn fragmentation(&self) -> f32 {
+        if self.free_area == 0 {
+            return 0.0
+        }
+        1.0 - self.largest_free_area as f32 / self.free_area as f32
+    }
+
+    fn add(&mut self, other: &TextureAllocatorStats) {
+        self.pages += other.pages;
+        self.allocations += other.allocations;
+        self.total_area += other.total_area;
+        self.allocated_area += other.allocated_area;
+        self.free_area += other.free_area;
+        self.largest_free_area = cmp::max(self.largest_free_area, other.largest_free_area);
+    }
+
+    fn add_free_rect(&mut self, size: &DeviceUintSize) {
+        let area = size.width as u64 * size.height as u64;
+        self.free_area += area;
+        self.largest_free_area = cmp::max(self.largest_free_area, area);
+    }
+}
+
+/// Which items a page accepts when size classes are enabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PageSizeClass {
+    Any,
+    Small,
+    Large,
+}
+
+impl PageSizeClass {
+    fn for_size(size: &DeviceUintSize, size_classes: bool) -> PageSizeClass {
+        if !size_classes {
+            PageSizeClass::Any
+        } else if size.width <= MAXIMUM_SMALL_ITEM_SIZE && size.height <= MAXIMUM_SMALL_ITEM_SIZE {
+            PageSizeClass::Small
+        } else {
+            PageSizeClass::Large
+        }
+    }
+}
+
+enum PageAllocator {
+    Guillotine(GuillotineAllocator),
+    Shelf(ShelfAllocator),
+}
+
+/// A texture atlas, and the allocator that hands out space in it.
+pub struct TexturePage {
+    texture_id: CacheTextureId,
+    texture_size: DeviceUintSize,
+    allocator: PageAllocator,
+    size_class: PageSizeClass,
+    allocations: u32,
+    allocated_area: u64,
+}
+
+impl TexturePage {
+    pub fn new(texture_id: CacheTextureId, texture_size: DeviceUintSize) -> TexturePage {
+        TexturePage::with_allocator(texture_id,
+                                    texture_size,
+                                    TextureAllocatorKind::Guillotine,
+                                    PageSizeClass::Any)
+    }
+
+    fn with_allocator(texture_id: CacheTextureId,
+                      texture_size: DeviceUintSize,
+                      kind: TextureAllocatorKind,
+                      size_class: PageSizeClass)
+                      -> TexturePage {
+        let allocator = match kind {
+            TextureAllocatorKind::Guillotine => {
+                PageAllocator::Guillotine(GuillotineAllocator::new(&texture_size))
+            }
+            TextureAllocatorKind::Shelf => {
+                PageAllocator::Shelf(ShelfAllocator::new(&texture_size))
+            }
+        };
+        TexturePage {
+            texture_id: texture_id,
+            texture_size: texture_size,
+            allocator: allocator,
+            size_class: size_class,
+            allocations: 0,
+            allocated_area: 0,
+        }
+    }
+
+    pub fn can_allocate(&self, requested_dimensions: &DeviceUintSize) -> bool {
+        match self.allocator {
+            PageAllocator::Guillotine(ref allocator) => allocator.can_allocate(requested_dimensions),
+            PageAllocator::Shelf(ref allocator) => allocator.can_allocate(requested_dimensions),
+        }
+    }
+
+    pub fn allocate(&mut self,
+                    requested_dimensions: &DeviceUintSize) -> Option<DeviceUintPoint> {
+        let location = match self.allocator {
+            PageAllocator::Guillotine(ref mut allocator) => allocator.allocate(requested_dimensions),
+            PageAllocator::Shelf(ref mut allocator) => allocator.allocate(requested_dimensions),
+        };
+
+        if location.is_some() {
+            self.allocations += 1;
+            self.allocated_area += requested_dimensions.width as u64 *
+                                   requested_dimensions.height as u64;
+        }
+
+        location
+    }
+
+    pub fn coalesce(&mut self, time_limited: bool) -> bool {
+        match self.allocator {
+            PageAllocator::Guillotine(ref mut allocator) => allocator.coalesce(time_limited),
+            // Shelves merge free spans as soon as they're freed.
+            PageAllocator::Shelf(..) => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        let texture_size = self.texture_size;
+        match self.allocator {
+            PageAllocator::Guillotine(ref mut allocator) => allocator.clear(&texture_size),
+            PageAllocator::Shelf(ref mut allocator) => allocator.clear(&texture_size),
+        }
+        self.allocations = 0;
+        self.allocated_area = 0;
+    }
+
+    pub fn free(&mut self, rect: &DeviceUintRect) {
+        debug_assert!(self.allocations > 0);
+        self.allocations -= 1;
+        self.allocated_area -= rect.size.width as u64 * rect.size.height as u64;
+        if self.allocations == 0 {
+            self.clear();
+            return
+        }
+
+        match self.allocator {
+            PageAllocator::Guillotine(ref mut allocator) => allocator.free(rect),
+            PageAllocator::Shelf(ref mut allocator) => allocator.free(rect),
+        }
+    }
+
+    fn grow(&mut self, new_texture_size: DeviceUintSize) {
+        assert!(new_texture_size.width >= self.texture_size.width);
+        assert!(new_texture_size.height >= self.texture_size.height);
+
+        let texture_size = self.texture_size;
+        match self.allocator {
+            PageAllocator::Guillotine(ref mut allocator) => {
+                allocator.grow(&texture_size, &new_texture_size)
+            }
+            PageAllocator::Shelf(ref mut allocator) => {
+                allocator.grow(&texture_size, &new_texture_size)
+            }
+        }
+
+        self.texture_size = new_texture_size
+    }
+
+    fn can_grow(&self, max_size: u32) -> bool {
+        self.texture_size.width < max_size || self.texture_size.height < max_size
+    }
+
+    fn stats(&self) -> TextureAllocatorStats {
+        let mut stats = TextureAllocatorStats {
+            pages: 1,
+            allocations: self.allocations as usize,
+            total_area: self.texture_size.width as u64 * self.texture_size.height as u64,
+            allocated_area: self.allocated_area,
+            free_area: 0,
+            largest_free_area: 0,
+        };
+        match self.allocator {
+            PageAllocator::Guillotine(ref allocator) => allocator.add_free_rects(&mut stats),
+            PageAllocator::Shelf(ref allocator) => allocator.add_free_rects(&mut stats),
+        }
+        stats
+    }
+}
+
 /// A texture allocator using the guillotine algorithm with the rectangle merge improvement. See
 /// sections 2.2 and 2.2.5 in "A Thousand Ways to Pack the Bin - A Practical Approach to Two-
 /// Dimensional Rectangle Bin Packing":
@@ -58,25 +302,19 @@ pub type TextureCacheItemId = FreeListItemId;
 ///
 /// This approach was chosen because of its simplicity, good performance, and easy support for
 /// dynamic texture deallocation.
-pub struct TexturePage {
-    texture_id: CacheTextureId,
-    texture_size: DeviceUintSize,
+struct GuillotineAllocator {
     free_list: FreeRectList,
-    allocations: u32,
     dirty: bool,
 }
 
-impl TexturePage {
-    pub fn new(texture_id: CacheTextureId, texture_size: DeviceUintSize) -> TexturePage {
-        let mut page = TexturePage {
-            texture_id: texture_id,
-            texture_size: texture_size,
+impl GuillotineAllocator {
+    fn new(texture_size: &DeviceUintSize) -> GuillotineAllocator {
+        let mut allocator = GuillotineAllocator {
             free_list: FreeRectList::new(),
-            allocations: 0,
             dirty: false,
         };
-        page.clear();
-        page
+        allocator.clear(texture_size);
+        allocator
     }
 
     fn find_index_of_best_rect_in_bin(&self, bin: FreeListBin, requested_dimensions: &DeviceUintSize)
@@ -111,12 +349,12 @@ impl TexturePage {
         None
     }
 
-    pub fn can_allocate(&self, requested_dimensions: &DeviceUintSize) -> bool {
+    fn can_allocate(&self, requested_dimensions: &DeviceUintSize) -> bool {
         self.find_index_of_best_rect(requested_dimensions).is_some()
     }
 
-    pub fn allocate(&mut self,
-                    requested_dimensions: &DeviceUintSize) -> Option<DeviceUintPoint> {
+    fn allocate(&mut self,
+                requested_dimensions: &DeviceUintSize) -> Option<DeviceUintPoint> {
         let index = match self.find_index_of_best_rect(requested_dimensions) {
             None => return None,
             Some(index) => index,
@@ -166,21 +404,22 @@ impl TexturePage {
             self.dirty = true
         }
 
-        // Bump the allocation counter.
-        self.allocations += 1;
-
         // Return the result.
         Some(chosen_rect.origin)
     }
 
     #[inline(never)]
-    pub fn coalesce(&mut self) -> bool {
+    fn coalesce(&mut self, time_limited: bool) -> bool {
         if !self.dirty {
             return false
         }
 
         // Iterate to a fixed point or until a timeout is reached.
-        let deadline = time::precise_time_ns() + COALESCING_TIMEOUT;
+        let deadline = if time_limited {
+            time::precise_time_ns() + COALESCING_TIMEOUT * 1000000
+        } else {
+            ::std::u64::MAX
+        };
         let mut free_list = mem::replace(&mut self.free_list, FreeRectList::new()).into_vec();
         let mut changed = false;
 
@@ -215,7 +454,6 @@ impl TexturePage {
                         free_list[work_index].union(&free_list[candidate_index]);
                     free_list[candidate_index].size.width = 0
                 }
-                new_free_list.push(free_list[work_index])
             }
             new_free_list.push(free_list[work_index])
         }
@@ -263,39 +501,28 @@ impl TexturePage {
         changed
     }
 
-    pub fn clear(&mut self) {
+    fn clear(&mut self, texture_size: &DeviceUintSize) {
         self.free_list = FreeRectList::new();
         self.free_list.push(&DeviceUintRect::new(
             DeviceUintPoint::zero(),
-            self.texture_size));
-        self.allocations = 0;
+            *texture_size));
         self.dirty = false;
     }
 
     fn free(&mut self, rect: &DeviceUintRect) {
-        debug_assert!(self.allocations > 0);
-        self.allocations -= 1;
-        if self.allocations == 0 {
-            self.clear();
-            return
-        }
-
         self.free_list.push(rect);
         self.dirty = true
     }
 
-    fn grow(&mut self, new_texture_size: DeviceUintSize) {
-        assert!(new_texture_size.width >= self.texture_size.width);
-        assert!(new_texture_size.height >= self.texture_size.height);
-
+    fn grow(&mut self, texture_size: &DeviceUintSize, new_texture_size: &DeviceUintSize) {
         let new_rects = [
-            DeviceUintRect::new(DeviceUintPoint::new(self.texture_size.width, 0),
-                                DeviceUintSize::new(new_texture_size.width - self.texture_size.width,
+            DeviceUintRect::new(DeviceUintPoint::new(texture_size.width, 0),
+                                DeviceUintSize::new(new_texture_size.width - texture_size.width,
                                                     new_texture_size.height)),
 
-            DeviceUintRect::new(DeviceUintPoint::new(0, self.texture_size.height),
-                                DeviceUintSize::new(self.texture_size.width,
-                                                    new_texture_size.height - self.texture_size.height)),
+            DeviceUintRect::new(DeviceUintPoint::new(0, texture_size.height),
+                                DeviceUintSize::new(texture_size.width,
+                                                    new_texture_size.height - texture_size.height)),
         ];
 
         for rect in &new_rects {
@@ -303,12 +530,206 @@ impl TexturePage {
                 self.free_list.push(rect);
             }
         }
+    }
 
-        self.texture_size = new_texture_size
+    fn add_free_rects(&self, stats: &mut TextureAllocatorStats) {
+        for &bin in &[FreeListBin::Small, FreeListBin::Medium, FreeListBin::Large] {
+            for rect in self.free_list.iter(bin) {
+                stats.add_free_rect(&rect.size);
+            }
+        }
     }
+}
 
-    fn can_grow(&self, max_size: u32) -> bool {
-        self.texture_size.width < max_size || self.texture_size.height < max_size
+/// Rounds item heights up so that items of similar heights share a shelf.
+/// Finer steps for small items keep the padding below glyphs low.
+fn shelf_height_for(height: u32) -> u32 {
+    let granularity = if height <= 32 {
+        4
+    } else if height <= 128 {
+        16
+    } else {
+        32
+    };
+    (height + granularity - 1) / granularity * granularity
+}
+
+/// A row of items of similar heights.
+struct Shelf {
+    y: u32,
+    height: u32,
+    /// The unused parts of the shelf as (x, width) pairs, sorted by x. Spans
+    /// that touch are always merged.
+    free_spans: Vec<(u32, u32)>,
+}
+
+impl Shelf {
+    fn has_span(&self, width: u32) -> bool {
+        self.free_spans.iter().any(|&(_, span_width)| span_width >= width)
+    }
+
+    fn is_empty(&self, texture_width: u32) -> bool {
+        self.free_spans.len() == 1 && self.free_spans[0] == (0, texture_width)
+    }
+
+    fn allocate(&mut self, width: u32) -> Option<u32> {
+        let index = self.free_spans.iter().position(|&(_, span_width)| span_width >= width);
+        let index = match index {
+            Some(index) => index,
+            None => return None,
+        };
+
+        let (x, span_width) = self.free_spans[index];
+        if span_width == width {
+            self.free_spans.remove(index);
+        } else {
+            self.free_spans[index] = (x + width, span_width - width);
+        }
+        Some(x)
+    }
+
+    fn free(&mut self, x: u32, width: u32) {
+        let index = self.free_spans
+                        .iter()
+                        .position(|&(span_x, _)| span_x > x)
+                        .unwrap_or(self.free_spans.len());
+        self.free_spans.insert(index, (x, width));
+
+        // Merge with the following span, then with the preceding one.
+        if index + 1 < self.free_spans.len() && x + width == self.free_spans[index + 1].0 {
+            let next_width = self.free_spans[index + 1].1;
+            self.free_spans[index].1 += next_width;
+            self.free_spans.remove(index + 1);
+        }
+        if index > 0 {
+            let (previous_x, previous_width) = self.free_spans[index - 1];
+            if previous_x + previous_width == x {
+                let width = self.free_spans[index].1;
+                self.free_spans[index - 1].1 += width;
+                self.free_spans.remove(index);
+            }
+        }
+    }
+}
+
+/// A texture allocator that places items side by side in shelves, which are
+/// stacked from the top of the page. See section 2.1 of the paper referenced
+/// by `GuillotineAllocator`.
+///
+/// Items go in the shelf with the lowest height that has room for them, and a
+/// new shelf is started when none does, as long as the best existing one
+/// isn't much taller than the item. Freed space is merged straight away, and
+/// empty shelves at the bottom of the page are released.
+struct ShelfAllocator {
+    texture_size: DeviceUintSize,
+    shelves: Vec<Shelf>,
+}
+
+impl ShelfAllocator {
+    fn new(texture_size: &DeviceUintSize) -> ShelfAllocator {
+        ShelfAllocator {
+            texture_size: *texture_size,
+            shelves: Vec::new(),
+        }
+    }
+
+    fn next_shelf_y(&self) -> u32 {
+        self.shelves.last().map_or(0, |shelf| shelf.y + shelf.height)
+    }
+
+    fn can_add_shelf(&self, requested_dimensions: &DeviceUintSize) -> bool {
+        requested_dimensions.width <= self.texture_size.width &&
+            requested_dimensions.height <= self.texture_size.height - self.next_shelf_y()
+    }
+
+    fn add_shelf(&mut self, requested_dimensions: &DeviceUintSize) -> usize {
+        let y = self.next_shelf_y();
+        let height = cmp::min(shelf_height_for(requested_dimensions.height),
+                              self.texture_size.height - y);
+        self.shelves.push(Shelf {
+            y: y,
+            height: height,
+            free_spans: vec![(0, self.texture_size.width)],
+        });
+        self.shelves.len() - 1
+    }
+
+    fn find_shelf(&self, requested_dimensions: &DeviceUintSize) -> Option<usize> {
+        let mut best_index_and_height = None;
+        for (index, shelf) in self.shelves.iter().enumerate() {
+            if shelf.height < requested_dimensions.height ||
+                    !shelf.has_span(requested_dimensions.width) {
+                continue
+            }
+            match best_index_and_height {
+                Some((_, best_height)) if best_height <= shelf.height => {}
+                _ => best_index_and_height = Some((index, shelf.height)),
+            }
+        }
+        best_index_and_height.map(|(index, _)| index)
+    }
+
+    fn can_allocate(&self, requested_dimensions: &DeviceUintSize) -> bool {
+        self.find_shelf(requested_dimensions).is_some() ||
+            self.can_add_shelf(requested_dimensions)
+    }
+
+    fn allocate(&mut self,
+                requested_dimensions: &DeviceUintSize) -> Option<DeviceUintPoint> {
+        let shelf_height = shelf_height_for(requested_dimensions.height);
+        let best_index = self.find_shelf(requested_dimensions);
+
+        // Prefer starting a new shelf to padding the item out to more than
+        // twice its height.
+        let index = match best_index {
+            Some(index) if self.shelves[index].height <= shelf_height * 2 => index,
+            _ if self.can_add_shelf(requested_dimensions) => self.add_shelf(requested_dimensions),
+            Some(index) => index,
+            None => return None,
+        };
+
+        let shelf = &mut self.shelves[index];
+        let y = shelf.y;
+        shelf.allocate(requested_dimensions.width).map(|x| DeviceUintPoint::new(x, y))
+    }
+
+    fn clear(&mut self, texture_size: &DeviceUintSize) {
+        self.texture_size = *texture_size;
+        self.shelves.clear();
+    }
+
+    fn free(&mut self, rect: &DeviceUintRect) {
+        let index = self.shelves
+                        .iter()
+                        .position(|shelf| shelf.y == rect.origin.y)
+                        .expect("Freed a rect that isn't in any shelf");
+        self.shelves[index].free(rect.origin.x, rect.size.width);
+
+        let texture_width = self.texture_size.width;
+        while self.shelves.last().map_or(false, |shelf| shelf.is_empty(texture_width)) {
+            self.shelves.pop();
+        }
+    }
+
+    fn grow(&mut self, texture_size: &DeviceUintSize, new_texture_size: &DeviceUintSize) {
+        if new_texture_size.width > texture_size.width {
+            for shelf in &mut self.shelves {
+                shelf.free(texture_size.width, new_texture_size.width - texture_size.width);
+            }
+        }
+        self.texture_size = *new_texture_size;
+    }
+
+    fn add_free_rects(&self, stats: &mut TextureAllocatorStats) {
+        for shelf in &self.shelves {
+            for &(_, width) in &shelf.free_spans {
+                stats.add_free_rect(&DeviceUintSize::new(width, shelf.height));
+            }
+        }
+        let unused_height = self.texture_size.height - self.next_shelf_y();
+        if unused_height > 0 {
+            stats.add_free_rect(&DeviceUintSize::new(self.texture_size.width, unused_height));
+        }
     }
 }
 
@@ -513,7 +934,16 @@ impl CacheTextureIdList {
     }
 
     fn free(&mut self, id: CacheTextureId) {
+        debug_assert!(id.0 < self.next_id && !self.free_list.contains(&id.0));
+        // The free list is kept in descending order, so the lowest IDs are
+        // reused first. IDs freed at the top are given back, which lets
+        // the renderer's table of cache textures shrink.
         self.free_list.push(id.0);
+        self.free_list.sort_by(|a, b| b.cmp(a));
+        while self.free_list.first() == Some(&(self.next_id - 1)) {
+            self.free_list.remove(0);
+            self.next_id -= 1;
+        }
     }
 }
 
@@ -524,6 +954,8 @@ pub struct TextureCache {
     arena: TextureCacheArena,
     pending_updates: TextureUpdateList,
     max_texture_size: u32,
+    deterministic: bool,
+    allocator_config: TextureAllocatorConfig,
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -551,9 +983,33 @@ impl TextureCache {
             pending_updates: TextureUpdateList::new(),
             arena: TextureCacheArena::new(),
             max_texture_size: max_texture_size,
+            deterministic: false,
+            allocator_config: TextureAllocatorConfig::default(),
         }
     }
 
+    /// Stops coalescing from giving up after a timeout, so that where items
+    /// are placed doesn't depend on how fast the machine is.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Only affects pages created after the call.
+    pub fn set_allocator_config(&mut self, config: TextureAllocatorConfig) {
+        self.allocator_config = config;
+    }
+
+    /// Measures how well the texture pages are packed. Standalone textures
+    /// aren't included.
+    pub fn allocator_stats(&self) -> TextureAllocatorStats {
+        let mut stats = TextureAllocatorStats::default();
+        for page in self.arena.pages_a8.iter().chain(self.arena.pages_rgb8.iter())
+                                             .chain(self.arena.pages_rgba8.iter()) {
+            stats.add(&page.stats());
+        }
+        stats
+    }
+
     pub fn max_texture_size(&self) -> u32 {
         self.max_texture_size
     }
@@ -614,14 +1070,19 @@ impl TextureCache {
         assert!(requested_size.width <= self.max_texture_size);
         assert!(requested_size.height <= self.max_texture_size);
 
+        let size_class = PageSizeClass::for_size(&requested_size, self.allocator_config.size_classes);
+
         let mut page_id = None; //using ID here to please the borrow checker
         for (i, page) in page_list.iter_mut().enumerate() {
+            if page.size_class != size_class {
+                continue;
+            }
             if page.can_allocate(&requested_size) {
                 page_id = Some(i);
                 break;
             }
             // try to coalesce it
-            if page.coalesce() && page.can_allocate(&requested_size) {
+            if page.coalesce(!self.deterministic) && page.can_allocate(&requested_size) {
                 page_id = Some(i);
                 break;
             }
@@ -684,7 +1145,10 @@ impl TextureCache {
                 let free_texture_level = free_texture_levels.pop().unwrap();
                 let texture_id = free_texture_level.texture_id;
 
-                let page = TexturePage::new(texture_id, texture_size);
+                let page = TexturePage::with_allocator(texture_id,
+                                                       texture_size,
+                                                       self.allocator_config.kind,
+                                                       size_class);
                 page_list.push(page);
                 page_list.last_mut().unwrap()
             },
@@ -725,7 +1189,8 @@ impl TextureCache {
                 match dirty_rect {
                     Some(dirty) => {
                         let stride = descriptor.compute_stride();
-                        let offset = descriptor.offset + dirty.origin.y * stride + dirty.origin.x;
+                        let bpp = descriptor.format.bytes_per_pixel().unwrap();
+                        let offset = descriptor.offset + dirty.origin.y * stride + dirty.origin.x * bpp;
                         TextureUpdateOp::Update {
                             page_pos_x: existing_item.allocated_rect.origin.x + dirty.origin.x,
                             page_pos_y: existing_item.allocated_rect.origin.y + dirty.origin.y,
@@ -843,6 +1308,7 @@ impl TextureCache {
                                         filter: filter,
                                         mode: RenderTargetMode::None,
                                         data: Some(data),
+                                        stride: stride,
                                     },
                                 };
 
@@ -860,6 +1326,7 @@ impl TextureCache {
                                 filter: filter,
                                 mode: RenderTargetMode::None,
                                 data: Some(data),
+                                stride: stride,
                             },
                         };
 
@@ -874,6 +1341,14 @@ impl TextureCache {
         self.items.get(id)
     }
 
+    /// Deletes the texture pages that no longer hold any items. Pages are
+    /// otherwise kept once created, for later items to go into.
+    pub fn free_empty_pages(&mut self) {
+        free_empty_pages(&mut self.arena.pages_a8, &mut self.cache_id_list, &mut self.pending_updates);
+        free_empty_pages(&mut self.arena.pages_rgb8, &mut self.cache_id_list, &mut self.pending_updates);
+        free_empty_pages(&mut self.arena.pages_rgba8, &mut self.cache_id_list, &mut self.pending_updates);
+    }
+
     pub fn free(&mut self, id: TextureCacheItemId) {
         let item = self.items.free(id);
         match self.arena.texture_page_for_id(item.texture_id) {
@@ -891,6 +1366,22 @@ impl TextureCache {
     }
 }
 
+fn free_empty_pages(pages: &mut Vec<TexturePage>,
+                    cache_id_list: &mut CacheTextureIdList,
+                    pending_updates: &mut TextureUpdateList) {
+    pages.retain(|page| {
+        if page.allocations > 0 {
+            return true;
+        }
+        pending_updates.push(TextureUpdate {
+            id: page.texture_id,
+            op: TextureUpdateOp::Free,
+        });
+        cache_id_list.free(page.texture_id);
+        false
+    });
+}
+
 fn texture_create_op(texture_size: DeviceUintSize, format: ImageFormat, mode: RenderTargetMode)
                      -> TextureUpdateOp {
     TextureUpdateOp::Create {
@@ -900,6 +1391,7 @@ fn texture_create_op(texture_size: DeviceUintSize, format: ImageFormat, mode: Re
         filter: TextureFilter::Linear,
         mode: mode,
         data: None,
+        stride: None,
     }
 }
 
@@ -937,3 +1429,124 @@ fn initial_texture_size(max_texture_size: u32) -> DeviceUintSize {
     let initial_size = cmp::min(max_texture_size, INITIAL_TEXTURE_SIZE);
     DeviceUintSize::new(initial_size, initial_size)
 }
+
+#[cfg(test)]
+fn allocate_mixed_items(page: &mut TexturePage) -> Vec<DeviceUintRect> {
+    // A fixed pseudo-random mix of glyph and image sizes.
+    let mut seed: u32 = 1;
+    let mut rects = Vec::new();
+    for _ in 0..300 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let size = if seed % 4 == 0 {
+            DeviceUintSize::new(32 + (seed >> 8) % 96, 32 + (seed >> 16) % 96)
+        } else {
+            DeviceUintSize::new(4 + (seed >> 8) % 16, 8 + (seed >> 16) % 16)
+        };
+        if let Some(origin) = page.allocate(&size) {
+            rects.push(DeviceUintRect::new(origin, size));
+        }
+    }
+    rects
+}
+
+#[test]
+fn allocator_stats_cover_the_page() {
+    for &kind in &[TextureAllocatorKind::Guillotine, TextureAllocatorKind::Shelf] {
+        let texture_size = DeviceUintSize::new(512, 512);
+        let mut page = TexturePage::with_allocator(CacheTextureId(0),
+                                                   texture_size,
+                                                   kind,
+                                                   PageSizeClass::Any);
+        let rects = allocate_mixed_items(&mut page);
+        let mut live_area = 0;
+        for (i, rect) in rects.iter().enumerate() {
+            if i % 3 == 0 {
+                page.free(rect);
+            } else {
+                live_area += rect.size.width as u64 * rect.size.height as u64;
+            }
+        }
+        page.coalesce(false);
+
+        let stats = page.stats();
+        assert_eq!(stats.pages, 1);
+        assert_eq!(stats.allocations, rects.len() - (rects.len() + 2) / 3);
+        assert_eq!(stats.total_area, 512 * 512);
+        assert_eq!(stats.allocated_area, live_area);
+        assert!(stats.allocated_area + stats.free_area <= stats.total_area);
+        if kind == TextureAllocatorKind::Guillotine {
+            assert_eq!(stats.wasted_area(), 0);
+        }
+        assert!(stats.fragmentation() > 0.0 && stats.fragmentation() < 1.0);
+
+        for rect in rects.iter().enumerate().filter(|&(i, _)| i % 3 != 0).map(|(_, rect)| rect) {
+            page.free(rect);
+        }
+        let stats = page.stats();
+        assert_eq!(stats.free_area, stats.total_area);
+        assert_eq!(stats.fragmentation(), 0.0);
+    }
+}
+
+#[test]
+fn cache_texture_ids_are_reused_lowest_first() {
+    let mut ids = CacheTextureIdList::new();
+    let allocated: Vec<_> = (0..4).map(|_| ids.allocate()).collect();
+    assert_eq!(allocated, vec![CacheTextureId(0), CacheTextureId(1),
+                               CacheTextureId(2), CacheTextureId(3)]);
+
+    ids.free(CacheTextureId(2));
+    ids.free(CacheTextureId(0));
+    assert_eq!(ids.allocate(), CacheTextureId(0));
+
+    // Freeing the top ID gives back the free ones below it too.
+    ids.free(CacheTextureId(3));
+    assert_eq!(ids.next_id, 2);
+    assert!(ids.free_list.is_empty());
+    assert_eq!(ids.allocate(), CacheTextureId(2));
+}
+
+#[test]
+fn shelf_allocator_reuses_freed_space() {
+    let mut page = TexturePage::with_allocator(CacheTextureId(0),
+                                               DeviceUintSize::new(256, 256),
+                                               TextureAllocatorKind::Shelf,
+                                               PageSizeClass::Any);
+    let glyph_size = DeviceUintSize::new(10, 14);
+    let mut rects = Vec::new();
+    while let Some(origin) = page.allocate(&glyph_size) {
+        rects.push(DeviceUintRect::new(origin, glyph_size));
+    }
+    // 25 glyphs per 16 pixel high shelf.
+    assert_eq!(rects.len(), 25 * 16);
+
+    for rect in rects.iter().enumerate().filter(|&(i, _)| i % 2 == 0).map(|(_, rect)| rect) {
+        page.free(rect);
+    }
+    for _ in 0..rects.len() / 2 {
+        assert!(page.allocate(&glyph_size).is_some());
+    }
+    assert!(!page.can_allocate(&glyph_size));
+}
+
+#[test]
+fn size_classes_separate_glyphs_from_images() {
+    let mut texture_cache = TextureCache::new(2048);
+    texture_cache.set_allocator_config(TextureAllocatorConfig {
+        kind: TextureAllocatorKind::Shelf,
+        size_classes: true,
+    });
+    let mut profile = TextureCacheProfileCounters::new();
+
+    let glyph = texture_cache.new_item_id();
+    let glyph = texture_cache.allocate(glyph, 12, 16, ImageFormat::RGBA8,
+                                       TextureFilter::Linear, &mut profile);
+    let image = texture_cache.new_item_id();
+    let image = texture_cache.allocate(image, 200, 100, ImageFormat::RGBA8,
+                                       TextureFilter::Linear, &mut profile);
+    assert!(glyph.item.texture_id != image.item.texture_id);
+
+    let stats = texture_cache.allocator_stats();
+    assert_eq!(stats.pages, 2);
+    assert_eq!(stats.allocated_area, 12 * 16 + 200 * 100);
+}
@@ -4,31 +4,36 @@
 
 use app_units::Au;
 use device::TextureId;
+use euclid::Matrix4D;
 use fnv::FnvHasher;
+use gpu_cache::{GpuCache, GpuCacheUpdateList, item_address};
 use gpu_store::GpuStoreAddress;
 use internal_types::{ANGLE_FLOAT_TO_FIXED, BatchTextures, CacheTextureId, LowLevelFilterOp};
 use internal_types::SourceTexture;
 use mask_cache::MaskCacheInfo;
-use prim_store::{CLIP_DATA_GPU_SIZE, DeferredResolve, GpuBlock128, GpuBlock16, GpuBlock32};
-use prim_store::{GpuBlock64, GradientData, PrimitiveCacheKey, PrimitiveGeometry, PrimitiveIndex};
-use prim_store::{PrimitiveKind, PrimitiveMetadata, PrimitiveStore, TexelRect};
+use prim_store::{CLIP_DATA_GPU_SIZE, DeferredResolve, GpuBlock32, GradientData, ImagePrimitiveGpu};
+use prim_store::{PrimitiveGeometry, PrimitiveIndex, PrimitiveKind, PrimitiveMetadata};
+use prim_store::{PrimitiveStore, TexelRect};
+use pipeline_stats::{BatchBreakReason, BatchStatistics, PipelineStatistics};
 use profiler::FrameProfileCounters;
 use render_task::{AlphaRenderItem, MaskGeometryKind, MaskSegment, RenderTask, RenderTaskData};
 use render_task::{RenderTaskId, RenderTaskIndex, RenderTaskKey, RenderTaskKind};
 use render_task::RenderTaskLocation;
-use renderer::BlendMode;
+use render_task_cache::RenderTaskCache;
+use renderer::{BlendMode, OverlayCandidate};
 use resource_cache::ResourceCache;
-use std::{f32, i32, mem, usize};
+use std::{cmp, f32, i32, mem, usize};
 use std::collections::HashMap;
 use std::hash::BuildHasherDefault;
 use texture_cache::TexturePage;
-use util::{TransformedRect, TransformedRectKind};
+use util::{MatrixHelpers, TransformedRect, TransformedRectKind};
 use webrender_traits::{AuxiliaryLists, ColorF, DeviceIntPoint, DeviceIntRect};
 use webrender_traits::{DeviceIntSize, DeviceUintPoint};
 use webrender_traits::{DeviceUintSize, FontRenderMode, ImageRendering, LayerPoint, LayerRect};
-use webrender_traits::{LayerToWorldTransform, MixBlendMode, PipelineId, ScrollLayerId};
+use webrender_traits::{LayerToWorldTransform, MixBlendMode, PipelineId, PropertyBindingId};
+use webrender_traits::{ScrollLayerId, SnapshotId};
 use webrender_traits::{WorldPoint4D, WorldToLayerTransform};
-use webrender_traits::{ExternalImageType};
+use webrender_traits::{ExternalImageData, ExternalImageType};
 
 // Special sentinel value recognized by the shader. It is considered to be
 // a dummy task that doesn't mask out anything.
@@ -84,12 +89,34 @@ impl AlphaBatchHelpers for PrimitiveStore {
                             }
                         }
                     }
+                    _ if image_cpu.is_alpha_mask => {
+                        AlphaBatchKind::AlphaImage
+                    }
                     _ => {
                         AlphaBatchKind::Image
                     }
                 }
             }
-            PrimitiveKind::YuvImage => AlphaBatchKind::YuvImage,
+            PrimitiveKind::YuvImage => {
+                let image_cpu = &self.cpu_yuv_images[metadata.cpu_prim_index.0];
+
+                // All the planes are expected to share the same kind of texture,
+                // so the shader is selected from the Y plane.
+                match image_cpu.yuv_texture_id[0] {
+                    SourceTexture::External(ext_image) => {
+                        match ext_image.image_type {
+                            ExternalImageType::Texture2DHandle => AlphaBatchKind::YuvImage,
+                            ExternalImageType::TextureRectHandle => AlphaBatchKind::YuvImageRect,
+                            _ => {
+                                panic!("Non-texture handle type should be handled in other way.");
+                            }
+                        }
+                    }
+                    _ => {
+                        AlphaBatchKind::YuvImage
+                    }
+                }
+            }
             PrimitiveKind::Rectangle => AlphaBatchKind::Rectangle,
             PrimitiveKind::AlignedGradient => AlphaBatchKind::AlignedGradient,
             PrimitiveKind::AngleGradient => AlphaBatchKind::AngleGradient,
@@ -194,7 +221,7 @@ impl AlphaBatchHelpers for PrimitiveStore {
         match batch.data {
             PrimitiveBatchData::Instances(ref mut data) => {
                 data.push(PrimitiveInstance {
-                    global_prim_id: -1,
+                    geometry_address: GpuStoreAddress(-1),
                     prim_address: GpuStoreAddress(0),
                     task_index: task_index.0 as i32,
                     clip_task_index: -1,
@@ -219,7 +246,7 @@ impl AlphaBatchHelpers for PrimitiveStore {
         match batch.data {
             PrimitiveBatchData::Instances(ref mut data) => {
                 data.push(PrimitiveInstance {
-                    global_prim_id: -1,
+                    geometry_address: GpuStoreAddress(-1),
                     prim_address: GpuStoreAddress(0),
                     task_index: task_index.0 as i32,
                     clip_task_index: -1,
@@ -243,7 +270,7 @@ impl AlphaBatchHelpers for PrimitiveStore {
                          z_sort_index: i32) {
         let metadata = self.get_metadata(prim_index);
         let packed_layer_index = packed_layer_index.0 as i32;
-        let global_prim_id = prim_index.0 as i32;
+        let geometry_address = metadata.gpu_geometry_address;
         let prim_address = metadata.gpu_prim_index;
         let clip_task_index = match metadata.clip_task {
             Some(ref clip_task) => {
@@ -259,17 +286,19 @@ impl AlphaBatchHelpers for PrimitiveStore {
 
         match &mut batch.data {
             &mut PrimitiveBatchData::Composite(..) => unreachable!(),
+            &mut PrimitiveBatchData::Blit(..) => unreachable!(),
             &mut PrimitiveBatchData::Instances(ref mut data) => {
                 match batch.key.kind {
                     AlphaBatchKind::Composite => unreachable!(),
                     AlphaBatchKind::HardwareComposite => unreachable!(),
                     AlphaBatchKind::Blend => unreachable!(),
+                    AlphaBatchKind::Blit => unreachable!(),
                     AlphaBatchKind::Rectangle => {
                         data.push(PrimitiveInstance {
                             task_index: task_index,
                             clip_task_index: clip_task_index,
                             layer_index: packed_layer_index,
-                            global_prim_id: global_prim_id,
+                            geometry_address: geometry_address,
                             prim_address: prim_address,
                             sub_index: 0,
                             user_data: [0, 0],
@@ -284,7 +313,7 @@ impl AlphaBatchHelpers for PrimitiveStore {
                                 task_index: task_index,
                                 clip_task_index: clip_task_index,
                                 layer_index: packed_layer_index,
-                                global_prim_id: global_prim_id,
+                                geometry_address: geometry_address,
                                 prim_address: prim_address,
                                 sub_index: metadata.gpu_data_address.0 + glyph_index,
                                 user_data: [ text_cpu.resource_address.0 + glyph_index, 0 ],
@@ -293,28 +322,30 @@ impl AlphaBatchHelpers for PrimitiveStore {
                         }
                     }
                     AlphaBatchKind::Image |
-                    AlphaBatchKind::ImageRect => {
+                    AlphaBatchKind::ImageRect |
+                    AlphaBatchKind::AlphaImage => {
                         let image_cpu = &self.cpu_images[metadata.cpu_prim_index.0];
 
                         data.push(PrimitiveInstance {
                             task_index: task_index,
                             clip_task_index: clip_task_index,
                             layer_index: packed_layer_index,
-                            global_prim_id: global_prim_id,
+                            geometry_address: geometry_address,
                             prim_address: prim_address,
                             sub_index: 0,
                             user_data: [ image_cpu.resource_address.0, 0 ],
                             z_sort_index: z_sort_index,
                         });
                     }
-                    AlphaBatchKind::YuvImage => {
+                    AlphaBatchKind::YuvImage |
+                    AlphaBatchKind::YuvImageRect => {
                         let image_yuv_cpu = &self.cpu_yuv_images[metadata.cpu_prim_index.0];
 
                         data.push(PrimitiveInstance {
                             task_index: task_index,
                             clip_task_index: clip_task_index,
                             layer_index: packed_layer_index,
-                            global_prim_id: global_prim_id,
+                            geometry_address: geometry_address,
                             prim_address: prim_address,
                             sub_index: 0,
                             user_data: [ image_yuv_cpu.yuv_resource_address.0, 0 ],
@@ -327,7 +358,7 @@ impl AlphaBatchHelpers for PrimitiveStore {
                                 task_index: task_index,
                                 clip_task_index: clip_task_index,
                                 layer_index: packed_layer_index,
-                                global_prim_id: global_prim_id,
+                                geometry_address: geometry_address,
                                 prim_address: prim_address,
                                 sub_index: border_segment,
                                 user_data: [ 0, 0 ],
@@ -341,9 +372,10 @@ impl AlphaBatchHelpers for PrimitiveStore {
                                 task_index: task_index,
                                 clip_task_index: clip_task_index,
                                 layer_index: packed_layer_index,
-                                global_prim_id: global_prim_id,
+                                geometry_address: geometry_address,
                                 prim_address: prim_address,
-                                sub_index: metadata.gpu_data_address.0 + part_index,
+                                sub_index: item_address::<GpuBlock32>(metadata.gpu_data_address,
+                                                                      part_index as usize).0,
                                 user_data: [ 0, 0 ],
                                 z_sort_index: z_sort_index,
                             });
@@ -354,7 +386,7 @@ impl AlphaBatchHelpers for PrimitiveStore {
                             task_index: task_index,
                             clip_task_index: clip_task_index,
                             layer_index: packed_layer_index,
-                            global_prim_id: global_prim_id,
+                            geometry_address: geometry_address,
                             prim_address: prim_address,
                             sub_index: metadata.gpu_data_address.0,
                             user_data: [ metadata.gpu_data_count, 0 ],
@@ -366,7 +398,7 @@ impl AlphaBatchHelpers for PrimitiveStore {
                             task_index: task_index,
                             clip_task_index: clip_task_index,
                             layer_index: packed_layer_index,
-                            global_prim_id: global_prim_id,
+                            geometry_address: geometry_address,
                             prim_address: prim_address,
                             sub_index: metadata.gpu_data_address.0,
                             user_data: [ metadata.gpu_data_count, 0 ],
@@ -383,7 +415,7 @@ impl AlphaBatchHelpers for PrimitiveStore {
                                 task_index: task_index,
                                 clip_task_index: clip_task_index,
                                 layer_index: packed_layer_index,
-                                global_prim_id: global_prim_id,
+                                geometry_address: geometry_address,
                                 prim_address: prim_address,
                                 sub_index: metadata.gpu_data_address.0 + rect_index,
                                 user_data: [ cache_task_index.0 as i32, 0 ],
@@ -404,7 +436,7 @@ impl AlphaBatchHelpers for PrimitiveStore {
                             task_index: task_index,
                             clip_task_index: clip_task_index,
                             layer_index: packed_layer_index,
-                            global_prim_id: global_prim_id,
+                            geometry_address: geometry_address,
                             prim_address: prim_address,
                             sub_index: 0,
                             user_data: [ cache_task_index.0 as i32, 0 ],
@@ -417,11 +449,21 @@ impl AlphaBatchHelpers for PrimitiveStore {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ScrollbarPart {
+    VerticalTrack,
+    VerticalThumb,
+    HorizontalTrack,
+    HorizontalThumb,
+}
+
 #[derive(Debug)]
 pub struct ScrollbarPrimitive {
     pub scroll_layer_id: ScrollLayerId,
     pub prim_index: PrimitiveIndex,
-    pub border_radius: f32,
+    pub part: ScrollbarPart,
+    /// The color before any fading is applied.
+    pub color: ColorF,
 }
 
 #[derive(Debug)]
@@ -434,7 +476,7 @@ pub enum PrimitiveRunCmd {
 #[derive(Debug, Copy, Clone)]
 pub enum PrimitiveFlags {
     None,
-    Scrollbar(ScrollLayerId, f32)
+    Scrollbar(ScrollLayerId, ScrollbarPart)
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -446,6 +488,7 @@ pub struct RenderPassIndex(isize);
 struct DynamicTaskInfo {
     index: RenderTaskIndex,
     rect: DeviceIntRect,
+    target_index: RenderTargetIndex,
 }
 
 pub struct RenderTaskCollection {
@@ -471,13 +514,17 @@ impl RenderTaskCollection {
                 let index = RenderTaskIndex(self.render_task_data.len());
                 let key = (key, pass);
                 debug_assert!(self.dynamic_tasks.contains_key(&key) == false);
+                let (rect, target_index) = match task.location {
+                    RenderTaskLocation::Fixed => panic!("Dynamic tasks should not have fixed locations!"),
+                    RenderTaskLocation::Dynamic(Some((origin, target_index)), size) => {
+                        (DeviceIntRect::new(origin, size), target_index)
+                    }
+                    RenderTaskLocation::Dynamic(None, _) => panic!("Expect the task to be already allocated here"),
+                };
                 self.dynamic_tasks.insert(key, DynamicTaskInfo {
                     index: index,
-                    rect: match task.location {
-                        RenderTaskLocation::Fixed => panic!("Dynamic tasks should not have fixed locations!"),
-                        RenderTaskLocation::Dynamic(Some((origin, _)), size) => DeviceIntRect::new(origin, size),
-                        RenderTaskLocation::Dynamic(None, _) => panic!("Expect the task to be already allocated here"),
-                    },
+                    rect: rect,
+                    target_index: target_index,
                 });
                 self.render_task_data.push(task.write_task_data());
                 index
@@ -491,6 +538,13 @@ impl RenderTaskCollection {
                           .map(|task| &task.rect)
     }
 
+    fn get_dynamic_location(&self,
+                            pass_index: RenderPassIndex,
+                            key: RenderTaskKey) -> (DeviceIntRect, RenderTargetIndex) {
+        let task = &self.dynamic_tasks[&(key, pass_index)];
+        (task.rect, task.target_index)
+    }
+
     fn get_static_task_index(&self, id: &RenderTaskId) -> RenderTaskIndex {
         match id {
             &RenderTaskId::Static(index) => index,
@@ -508,25 +562,163 @@ impl RenderTaskCollection {
     }
 }
 
-impl Default for PrimitiveGeometry {
-    fn default() -> PrimitiveGeometry {
-        PrimitiveGeometry {
-            local_rect: unsafe { mem::uninitialized() },
-            local_clip_rect: unsafe { mem::uninitialized() },
-        }
-    }
-}
-
 struct AlphaBatchTask {
     task_id: RenderTaskId,
     opaque_items: Vec<AlphaRenderItem>,
     alpha_items: Vec<AlphaRenderItem>,
+    // Set for the task that is drawn to the framebuffer, which opaque
+    // images can be copied to with a blit.
+    allow_blits: bool,
+}
+
+/// Returns the texels and batch textures of an opaque image that is drawn
+/// without any scaling, clipping or tinting, at whole device pixels, so
+/// that it can be copied to the target instead of being drawn as a quad.
+fn get_image_blit(ctx: &RenderTargetContext,
+                  task_index: RenderTaskIndex,
+                  clip_scroll_group_index: ClipScrollGroupIndex,
+                  prim_index: PrimitiveIndex,
+                  z_sort_index: i32)
+                  -> Option<(BatchTextures, ImageBlit)> {
+    let metadata = ctx.prim_store.get_metadata(prim_index);
+    if metadata.prim_kind != PrimitiveKind::Image ||
+       !metadata.is_opaque ||
+       metadata.clip_task.is_some() {
+        return None;
+    }
+
+    let image_cpu = &ctx.prim_store.cpu_images[metadata.cpu_prim_index.0];
+    match image_cpu.color_texture_id {
+        SourceTexture::TextureCache(..) if image_cpu.color == ColorF::new(1.0, 1.0, 1.0, 1.0) => {}
+        _ => return None,
+    }
+
+    // Layers that the renderer transforms again don't end up where the
+    // frame builder expects.
+    let group = &ctx.clip_scroll_group_store[clip_scroll_group_index.0];
+    if ctx.late_latched_layers.iter().any(|layer| layer.packed_layer_index == group.packed_layer_index) {
+        return None;
+    }
+
+    // A blit can't mirror the image either.
+    let transform = &ctx.packed_layers[group.packed_layer_index.0].transform;
+    if !transform.can_losslessly_transform_a_2d_rect() ||
+       transform.m11 <= 0.0 ||
+       transform.m22 <= 0.0 {
+        return None;
+    }
+
+    // Only images that aren't repeated map one texel to one device pixel.
+    let geometry = ctx.gpu_cache.get::<PrimitiveGeometry>(metadata.gpu_geometry_address);
+    let image_gpu = ctx.gpu_cache.get::<ImagePrimitiveGpu>(metadata.gpu_prim_index);
+    if image_gpu.stretch_size != geometry.local_rect.size {
+        return None;
+    }
+
+    let world_rect = transform.transform_rect(&geometry.local_rect);
+    let device_pixel_ratio = ctx.device_pixel_ratio;
+    let dest_rect = match device_rect(world_rect.origin.x * device_pixel_ratio,
+                                      world_rect.origin.y * device_pixel_ratio,
+                                      world_rect.max_x() * device_pixel_ratio,
+                                      world_rect.max_y() * device_pixel_ratio) {
+        Some(rect) => rect,
+        None => return None,
+    };
+
+    // The image must be entirely visible, since a blit doesn't clip.
+    if ctx.prim_store.cpu_bounding_rects[prim_index.0] != Some(dest_rect) {
+        return None;
+    }
+
+    let texel_rect = ctx.gpu_cache.get::<TexelRect>(image_cpu.resource_address);
+    let src_rect = match device_rect(texel_rect.uv0.x,
+                                     texel_rect.uv0.y,
+                                     texel_rect.uv1.x,
+                                     texel_rect.uv1.y) {
+        Some(rect) if rect.size == dest_rect.size => rect,
+        _ => return None,
+    };
+
+    let textures = BatchTextures {
+        colors: ctx.prim_store.get_color_textures(metadata),
+    };
+
+    Some((textures, ImageBlit {
+        src_rect: src_rect,
+        dest_rect: dest_rect,
+        instance: PrimitiveInstance {
+            task_index: task_index.0 as i32,
+            clip_task_index: OPAQUE_TASK_INDEX.0 as i32,
+            layer_index: group.packed_layer_index.0 as i32,
+            geometry_address: metadata.gpu_geometry_address,
+            prim_address: metadata.gpu_prim_index,
+            sub_index: 0,
+            user_data: [ image_cpu.resource_address.0, 0 ],
+            z_sort_index: z_sort_index,
+        },
+    }))
+}
+
+/// The rect between two corners, if they are at whole pixels.
+fn device_rect(x0: f32, y0: f32, x1: f32, y1: f32) -> Option<DeviceIntRect> {
+    let corners = [x0, y0, x1, y1];
+    if corners.iter().any(|value| (value - value.round()).abs() > 0.001) {
+        return None;
+    }
+
+    let origin = DeviceIntPoint::new(x0.round() as i32, y0.round() as i32);
+    let size = DeviceIntSize::new(x1.round() as i32 - origin.x,
+                                  y1.round() as i32 - origin.y);
+    Some(DeviceIntRect::new(origin, size))
+}
+
+/// Finds the opaque items of a task that can be copied to the target with a
+/// blit. The blits are done after the opaque batches, between the alpha
+/// batches, so an image is only blitted if no opaque item in front of it
+/// overlaps it. Returns the blits from back to front.
+fn find_image_blits(ctx: &RenderTargetContext,
+                    task_index: RenderTaskIndex,
+                    opaque_items: &[AlphaRenderItem])
+                    -> Vec<(PrimitiveIndex, BatchTextures, ImageBlit)> {
+    let mut blits = Vec::new();
+
+    for item in opaque_items {
+        let (clip_scroll_group_index, prim_index, z) = match *item {
+            AlphaRenderItem::Primitive(clip_scroll_group_index, prim_index, z) => {
+                (clip_scroll_group_index, prim_index, z)
+            }
+            _ => continue,
+        };
+
+        let (textures, blit) = match get_image_blit(ctx, task_index, clip_scroll_group_index, prim_index, z) {
+            Some(blit) => blit,
+            None => continue,
+        };
+
+        let is_covered = opaque_items.iter().any(|other| {
+            match *other {
+                AlphaRenderItem::Primitive(_, other_prim_index, other_z) if other_z > z => {
+                    let bounding_rect = &ctx.prim_store.cpu_bounding_rects[other_prim_index.0];
+                    bounding_rect.as_ref().unwrap().intersects(&blit.dest_rect)
+                }
+                _ => false,
+            }
+        });
+
+        if !is_covered {
+            blits.push((prim_index, textures, blit));
+        }
+    }
+
+    blits.sort_by_key(|&(_, _, ref blit)| blit.instance.z_sort_index);
+    blits
 }
 
 /// Encapsulates the logic of building batches for items that are blended.
 pub struct AlphaBatcher {
     pub alpha_batches: Vec<PrimitiveBatch>,
     pub opaque_batches: Vec<PrimitiveBatch>,
+    pub stats: BatchStatistics,
     tasks: Vec<AlphaBatchTask>,
 }
 
@@ -535,6 +727,7 @@ impl AlphaBatcher {
         AlphaBatcher {
             alpha_batches: Vec::new(),
             opaque_batches: Vec::new(),
+            stats: BatchStatistics::default(),
             tasks: Vec::new(),
         }
     }
@@ -554,7 +747,27 @@ impl AlphaBatcher {
             let task_index = render_tasks.get_static_task_index(&task.task_id);
             let mut existing_opaque_batch_index = 0;
 
+            let mut blits = if task.allow_blits {
+                find_image_blits(ctx, task_index, &task.opaque_items)
+            } else {
+                Vec::new()
+            };
+            let blit_prims: Vec<PrimitiveIndex> = blits.iter()
+                                                       .map(|&(prim_index, _, _)| prim_index)
+                                                       .collect();
+            self.stats.image_blits += blits.len();
+            blits.reverse();
+
             for item in &task.alpha_items {
+                // Blits go in between the alpha batches, in the same order
+                // as the alpha items.
+                while blits.last().map_or(false, |&(_, _, ref blit)| {
+                    blit.instance.z_sort_index < item.z_sort_index()
+                }) {
+                    let (prim_index, textures, blit) = blits.pop().unwrap();
+                    alpha_batches.push(PrimitiveBatch::new_blit(prim_index, textures, blit));
+                }
+
                 let (batch_key, item_bounding_rect) = match item {
                     &AlphaRenderItem::Blend(stacking_context_index, ..) => {
                         let stacking_context =
@@ -588,6 +801,9 @@ impl AlphaBatcher {
                                                                   render_tasks.get_static_task_index(&src_id),
                                                                   info,
                                                                   z);
+                        if !alpha_batches.is_empty() {
+                            self.stats.add_break(BatchBreakReason::Composite);
+                        }
                         alpha_batches.push(batch);
                         continue;
                     }
@@ -623,11 +839,21 @@ impl AlphaBatcher {
                     }
                 };
 
+                // Hardware composites are few, and are usually far apart
+                // in the batch list, with unrelated content in between.
+                // They look back over all the batches, so that the ones
+                // whose destinations nothing else overlaps share a draw.
+                let lookback_count = match item {
+                    &AlphaRenderItem::HardwareComposite(..) => alpha_batches.len(),
+                    _ => ctx.batch_lookback_count,
+                };
+
                 let mut alpha_batch_index = None;
+                let mut is_overlapping = false;
                 'outer: for (batch_index, batch) in alpha_batches.iter()
                                                          .enumerate()
                                                          .rev()
-                                                         .take(10) {
+                                                         .take(lookback_count) {
                     if batch.key.is_compatible_with(&batch_key) {
                         alpha_batch_index = Some(batch_index);
                         break;
@@ -648,12 +874,27 @@ impl AlphaBatcher {
                         };
 
                         if intersects {
+                            is_overlapping = true;
                             break 'outer;
                         }
                     }
                 }
 
                 if alpha_batch_index.is_none() {
+                    let break_reason = if is_overlapping {
+                        Some(BatchBreakReason::Overlap)
+                    } else if alpha_batches.iter()
+                                           .rev()
+                                           .skip(lookback_count)
+                                           .any(|batch| batch.key.is_compatible_with(&batch_key)) {
+                        Some(BatchBreakReason::LookbackLimit)
+                    } else {
+                        alpha_batches.last().map(|batch| batch.key.break_reason(&batch_key))
+                    };
+                    if let Some(break_reason) = break_reason {
+                        self.stats.add_break(break_reason);
+                    }
+
                     let new_batch = match item {
                         &AlphaRenderItem::Composite(..) => unreachable!(),
                         &AlphaRenderItem::HardwareComposite(..) => {
@@ -705,11 +946,18 @@ impl AlphaBatcher {
                 }
             }
 
+            while let Some((prim_index, textures, blit)) = blits.pop() {
+                alpha_batches.push(PrimitiveBatch::new_blit(prim_index, textures, blit));
+            }
+
             for item in task.opaque_items.iter().rev() {
                 let batch_key = match item {
                     &AlphaRenderItem::Composite(..) => unreachable!(),
                     &AlphaRenderItem::Blend(..) => unreachable!(),
                     &AlphaRenderItem::HardwareComposite(..) => unreachable!(),
+                    &AlphaRenderItem::Primitive(_, prim_index, _) if blit_prims.contains(&prim_index) => {
+                        continue;
+                    }
                     &AlphaRenderItem::Primitive(clip_scroll_group_index, prim_index, _) => {
                         let group = &ctx.clip_scroll_group_store[clip_scroll_group_index.0];
                         let transform_kind = group.xf_rect.as_ref().unwrap().kind;
@@ -747,6 +995,10 @@ impl AlphaBatcher {
                 }
 
                 if existing_opaque_batch_index == opaque_batches.len() {
+                    if let Some(batch) = opaque_batches.last() {
+                        self.stats.add_break(batch.key.break_reason(&batch_key));
+                    }
+
                     let new_batch = match item {
                         &AlphaRenderItem::Composite(..) => unreachable!(),
                         &AlphaRenderItem::Blend(..) => unreachable!(),
@@ -781,6 +1033,8 @@ impl AlphaBatcher {
             }
         }
 
+        self.stats.alpha_batches += alpha_batches.len();
+        self.stats.opaque_batches += opaque_batches.len();
         self.alpha_batches.extend(alpha_batches.into_iter());
         self.opaque_batches.extend(opaque_batches.into_iter());
     }
@@ -818,7 +1072,8 @@ impl ClipBatcher {
             };
 
             for clip_index in 0..info.effective_clip_count as usize {
-                let offset = info.clip_range.start.0 + ((CLIP_DATA_GPU_SIZE * clip_index) as i32);
+                let offset = item_address::<GpuBlock32>(info.clip_range.start,
+                                                        CLIP_DATA_GPU_SIZE * clip_index).0;
                 match geometry_kind {
                     MaskGeometryKind::Default => {
                         self.rectangles.push(CacheClipInstance {
@@ -872,6 +1127,12 @@ pub struct RenderTargetContext<'a> {
     pub clip_scroll_group_store: &'a [ClipScrollGroup],
     pub prim_store: &'a PrimitiveStore,
     pub resource_cache: &'a ResourceCache,
+    pub gpu_cache: &'a GpuCache,
+    pub packed_layers: &'a [PackedLayer],
+    pub late_latched_layers: &'a [LateLatchedLayer],
+    pub device_pixel_ratio: f32,
+    pub render_task_cache: &'a RenderTaskCache,
+    pub batch_lookback_count: usize,
 }
 
 struct TextureAllocator {
@@ -929,7 +1190,7 @@ pub trait RenderTarget {
     fn used_rect(&self) -> DeviceIntRect;
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum RenderTargetKind {
     Color,   // RGBA32
     Alpha,   // R8
@@ -957,6 +1218,16 @@ impl<T: RenderTarget> RenderTargetList<T> {
         self.targets.len()
     }
 
+    /// The size from the origin that covers what is drawn into each of
+    /// the targets.
+    pub fn used_size(&self) -> DeviceUintSize {
+        self.targets.iter().fold(DeviceUintSize::zero(), |size, target| {
+            let rect = target.used_rect();
+            DeviceUintSize::new(cmp::max(size.width, rect.max_x() as u32),
+                                cmp::max(size.height, rect.max_y() as u32))
+        })
+    }
+
     fn build(&mut self,
              ctx: &RenderTargetContext,
              render_tasks: &mut RenderTaskCollection,
@@ -1012,11 +1283,54 @@ pub struct ColorRenderTarget {
     // List of blur operations to apply for this render target.
     pub vertical_blurs: Vec<BlurCommand>,
     pub horizontal_blurs: Vec<BlurCommand>,
+    // Tasks to downscale from the previous pass, as the rect and layer of
+    // the source task in its target and the rect of the task.
+    pub downscales: Vec<(DeviceIntRect, RenderTargetIndex, DeviceIntRect)>,
     pub readbacks: Vec<DeviceIntRect>,
     pub isolate_clears: Vec<DeviceIntRect>,
+    // Stacking context snapshots to read back once the target is drawn.
+    pub snapshots: Vec<(SnapshotId, DeviceIntRect)>,
+    // Stacking contexts to copy to external images once the target is drawn.
+    pub render_target_copies: Vec<(ExternalImageData, DeviceIntRect)>,
+    // Tasks to copy from the render task cache rather than draw, as the
+    // rect in the cache texture and the rect in the target.
+    pub task_cache_restores: Vec<(DeviceIntRect, DeviceIntRect)>,
+    // Tasks to copy into the render task cache once they are drawn, as
+    // the rect in the target and the rect in the cache texture.
+    pub task_cache_saves: Vec<(DeviceIntRect, DeviceIntRect)>,
+    // Set when the target has primitives with edges that aren't aligned
+    // to pixels, which it can be multisampled for.
+    pub needs_multisampling: bool,
     allocator: TextureAllocator,
 }
 
+impl ColorRenderTarget {
+    fn needs_multisampling(&self) -> bool {
+        let batches = self.alpha_batcher.opaque_batches.iter()
+                          .chain(self.alpha_batcher.alpha_batches.iter());
+        let mut needs_multisampling = false;
+
+        for batch in batches {
+            match batch.key.kind {
+                // Composites read back from the target, which would miss
+                // what is drawn to the multisampled buffer.
+                AlphaBatchKind::Composite => return false,
+                AlphaBatchKind::HardwareComposite |
+                AlphaBatchKind::Blend |
+                AlphaBatchKind::Blit => {}
+                _ => {
+                    if !batch.key.flags.contains(AXIS_ALIGNED) ||
+                       batch.key.flags.contains(NEEDS_CLIPPING) {
+                        needs_multisampling = true;
+                    }
+                }
+            }
+        }
+
+        needs_multisampling
+    }
+}
+
 impl RenderTarget for ColorRenderTarget {
     fn allocate(&mut self, size: DeviceUintSize) -> Option<DeviceUintPoint> {
         self.allocator.allocate(&size)
@@ -1030,8 +1344,14 @@ impl RenderTarget for ColorRenderTarget {
             text_run_textures: BatchTextures::no_texture(),
             vertical_blurs: Vec::new(),
             horizontal_blurs: Vec::new(),
+            downscales: Vec::new(),
             readbacks: Vec::new(),
             isolate_clears: Vec::new(),
+            snapshots: Vec::new(),
+            render_target_copies: Vec::new(),
+            task_cache_restores: Vec::new(),
+            task_cache_saves: Vec::new(),
+            needs_multisampling: false,
             allocator: TextureAllocator::new(size),
         }
     }
@@ -1047,6 +1367,7 @@ impl RenderTarget for ColorRenderTarget {
         self.alpha_batcher.build(ctx,
                                  render_tasks,
                                  child_pass_index);
+        self.needs_multisampling = self.needs_multisampling();
     }
 
     fn add_task(&mut self,
@@ -1054,12 +1375,33 @@ impl RenderTarget for ColorRenderTarget {
                 ctx: &RenderTargetContext,
                 render_tasks: &RenderTaskCollection,
                 pass_index: RenderPassIndex) {
+        let cached_task = match (task.id, &task.location) {
+            (RenderTaskId::Dynamic(key), &RenderTaskLocation::Dynamic(Some((origin, _)), size)) => {
+                Some((key, DeviceIntRect::new(origin, size)))
+            }
+            _ => None,
+        };
+        if let Some((key, task_rect)) = cached_task {
+            if let Some(cache_rect) = ctx.render_task_cache.restore_rect(&key) {
+                self.task_cache_restores.push((cache_rect, task_rect));
+                return;
+            }
+            if let Some(cache_rect) = ctx.render_task_cache.save_rect(&key) {
+                self.task_cache_saves.push((task_rect, cache_rect));
+            }
+        }
+
         match task.kind {
             RenderTaskKind::Alpha(info) => {
+                let allow_blits = match task.location {
+                    RenderTaskLocation::Fixed => true,
+                    RenderTaskLocation::Dynamic(..) => false,
+                };
                 self.alpha_batcher.add_task(AlphaBatchTask {
                     task_id: task.id,
                     opaque_items: info.opaque_items,
                     alpha_items: info.alpha_items,
+                    allow_blits: allow_blits,
                 });
 
                 if info.isolate_clear {
@@ -1071,16 +1413,30 @@ impl RenderTarget for ColorRenderTarget {
                     };
                     self.isolate_clears.push(location);
                 }
+
+                if let Some(snapshot) = info.snapshot {
+                    let location = match task.location {
+                        RenderTaskLocation::Dynamic(origin, size) => {
+                            DeviceIntRect::new(origin.unwrap().0, size)
+                        }
+                        RenderTaskLocation::Fixed => panic!()
+                    };
+                    self.snapshots.push((snapshot, location));
+                }
+
+                // Render targets are always isolated, so their tasks are
+                // allocated in a target rather than drawn to the framebuffer.
+                if let (Some(render_target), RenderTaskLocation::Dynamic(origin, size)) =
+                        (info.render_target, task.location) {
+                    let location = DeviceIntRect::new(origin.unwrap().0, size);
+                    self.render_target_copies.push((render_target, location));
+                }
             }
-            RenderTaskKind::VerticalBlur(_, prim_index) => {
-                // Find the child render task that we are applying
-                // a vertical blur on.
-                // TODO(gw): Consider a simpler way for render tasks to find
-                //           their child tasks than having to construct the
-                //           correct id here.
+            RenderTaskKind::VerticalBlur(_, src_key) => {
+                // The child render task that we are applying a vertical
+                // blur on is in the previous pass.
                 let child_pass_index = RenderPassIndex(pass_index.0 - 1);
-                let task_key = RenderTaskKey::CachePrimitive(PrimitiveCacheKey::TextShadow(prim_index));
-                let src_id = RenderTaskId::Dynamic(task_key);
+                let src_id = RenderTaskId::Dynamic(src_key);
                 self.vertical_blurs.push(BlurCommand {
                     task_id: render_tasks.get_task_index(&task.id, pass_index).0 as i32,
                     src_task_id: render_tasks.get_task_index(&src_id, child_pass_index).0 as i32,
@@ -1088,11 +1444,9 @@ impl RenderTarget for ColorRenderTarget {
                     padding: 0,
                 });
             }
-            RenderTaskKind::HorizontalBlur(blur_radius, prim_index) => {
-                // Find the child render task that we are applying
-                // a horizontal blur on.
+            RenderTaskKind::HorizontalBlur(_, src_key) => {
                 let child_pass_index = RenderPassIndex(pass_index.0 - 1);
-                let src_id = RenderTaskId::Dynamic(RenderTaskKey::VerticalBlur(blur_radius.0, prim_index));
+                let src_id = RenderTaskId::Dynamic(src_key);
                 self.horizontal_blurs.push(BlurCommand {
                     task_id: render_tasks.get_task_index(&task.id, pass_index).0 as i32,
                     src_task_id: render_tasks.get_task_index(&src_id, child_pass_index).0 as i32,
@@ -1100,13 +1454,25 @@ impl RenderTarget for ColorRenderTarget {
                     padding: 0,
                 });
             }
+            RenderTaskKind::Downscale(src_key) => {
+                let child_pass_index = RenderPassIndex(pass_index.0 - 1);
+                let (src_rect, src_target_index) = render_tasks.get_dynamic_location(child_pass_index,
+                                                                                     src_key);
+                let task_rect = match task.location {
+                    RenderTaskLocation::Dynamic(origin, size) => {
+                        DeviceIntRect::new(origin.unwrap().0, size)
+                    }
+                    RenderTaskLocation::Fixed => panic!()
+                };
+                self.downscales.push((src_rect, src_target_index, task_rect));
+            }
             RenderTaskKind::CachePrimitive(prim_index) => {
                 let prim_metadata = ctx.prim_store.get_metadata(prim_index);
 
                 match prim_metadata.prim_kind {
                     PrimitiveKind::BoxShadow => {
                         self.box_shadow_cache_prims.push(PrimitiveInstance {
-                            global_prim_id: prim_index.0 as i32,
+                            geometry_address: prim_metadata.gpu_geometry_address,
                             prim_address: prim_metadata.gpu_prim_index,
                             task_index: render_tasks.get_task_index(&task.id, pass_index).0 as i32,
                             clip_task_index: 0,
@@ -1135,7 +1501,7 @@ impl RenderTarget for ColorRenderTarget {
 
                         for glyph_index in 0..prim_metadata.gpu_data_count {
                             self.text_run_cache_prims.push(PrimitiveInstance {
-                                global_prim_id: prim_index.0 as i32,
+                                geometry_address: prim_metadata.gpu_geometry_address,
                                 prim_address: prim_metadata.gpu_prim_index,
                                 task_index: render_tasks.get_task_index(&task.id, pass_index).0 as i32,
                                 clip_task_index: 0,
@@ -1192,6 +1558,7 @@ impl RenderTarget for AlphaRenderTarget {
             RenderTaskKind::Alpha(..) |
             RenderTaskKind::VerticalBlur(..) |
             RenderTaskKind::HorizontalBlur(..) |
+            RenderTaskKind::Downscale(..) |
             RenderTaskKind::CachePrimitive(..) |
             RenderTaskKind::Readback(..) => {
                 panic!("Should not be added to alpha target!");
@@ -1220,6 +1587,10 @@ pub struct RenderPass {
     pub alpha_targets: RenderTargetList<AlphaRenderTarget>,
     pub color_texture_id: Option<TextureId>,
     pub alpha_texture_id: Option<TextureId>,
+    // The render target textures that this pass draws to, shared with
+    // other passes whose targets are not alive at the same time.
+    pub color_texture_slot: Option<usize>,
+    pub alpha_texture_slot: Option<usize>,
 }
 
 impl RenderPass {
@@ -1232,6 +1603,15 @@ impl RenderPass {
             tasks: vec![],
             color_texture_id: None,
             alpha_texture_id: None,
+            color_texture_slot: None,
+            alpha_texture_slot: None,
+        }
+    }
+
+    pub fn set_texture_slot(&mut self, kind: RenderTargetKind, slot: usize) {
+        match kind {
+            RenderTargetKind::Color => self.color_texture_slot = Some(slot),
+            RenderTargetKind::Alpha => self.alpha_texture_slot = Some(slot),
         }
     }
 
@@ -1331,13 +1711,16 @@ pub enum AlphaBatchKind {
     TextRun,
     Image,
     ImageRect,
+    AlphaImage,
     YuvImage,
+    YuvImageRect,
     Border,
     AlignedGradient,
     AngleGradient,
     RadialGradient,
     BoxShadow,
     CacheImage,
+    Blit,
 }
 
 bitflags! {
@@ -1390,6 +1773,19 @@ impl AlphaBatchKey {
             textures_compatible(self.textures.colors[1], other.textures.colors[1]) &&
             textures_compatible(self.textures.colors[2], other.textures.colors[2])
     }
+
+    /// Why an item with the key `other` can't be added to this batch.
+    fn break_reason(&self, other: &AlphaBatchKey) -> BatchBreakReason {
+        if self.kind != other.kind {
+            BatchBreakReason::Kind
+        } else if self.flags != other.flags {
+            BatchBreakReason::Flags
+        } else if self.blend_mode != other.blend_mode {
+            BatchBreakReason::BlendMode
+        } else {
+            BatchBreakReason::Textures
+        }
+    }
 }
 
 #[repr(C)]
@@ -1407,9 +1803,9 @@ fn textures_compatible(t1: SourceTexture, t2: SourceTexture) -> bool {
 // All Packed Primitives below must be 16 byte aligned.
 #[derive(Debug)]
 pub struct BlurCommand {
-    task_id: i32,
-    src_task_id: i32,
-    blur_direction: i32,
+    pub task_id: i32,
+    pub src_task_id: i32,
+    pub blur_direction: i32,
     padding: i32,
 }
 
@@ -1426,7 +1822,7 @@ pub struct CacheClipInstance {
 
 #[derive(Debug, Clone)]
 pub struct PrimitiveInstance {
-    global_prim_id: i32,
+    geometry_address: GpuStoreAddress,
     prim_address: GpuStoreAddress,
     pub task_index: i32,
     clip_task_index: i32,
@@ -1436,10 +1832,23 @@ pub struct PrimitiveInstance {
     pub user_data: [i32; 2],
 }
 
+/// An opaque image that is copied to the framebuffer with a blit, instead
+/// of being drawn as a quad.
+#[derive(Debug)]
+pub struct ImageBlit {
+    /// The texels to copy, from the top left of the texture.
+    pub src_rect: DeviceIntRect,
+    /// Where to copy them to, in device pixels from the top left.
+    pub dest_rect: DeviceIntRect,
+    /// The image as a quad, for when the framebuffer can't be blitted to.
+    pub instance: PrimitiveInstance,
+}
+
 #[derive(Debug)]
 pub enum PrimitiveBatchData {
     Instances(Vec<PrimitiveInstance>),
     Composite(PrimitiveInstance),
+    Blit(ImageBlit),
 }
 
 #[derive(Debug)]
@@ -1462,7 +1871,9 @@ impl PrimitiveBatch {
             AlphaBatchKind::TextRun |
             AlphaBatchKind::Image |
             AlphaBatchKind::ImageRect |
+            AlphaBatchKind::AlphaImage |
             AlphaBatchKind::YuvImage |
+            AlphaBatchKind::YuvImageRect |
             AlphaBatchKind::Border |
             AlphaBatchKind::AlignedGradient |
             AlphaBatchKind::AngleGradient |
@@ -1473,7 +1884,8 @@ impl PrimitiveBatch {
             AlphaBatchKind::CacheImage => {
                 PrimitiveBatchData::Instances(Vec::new())
             }
-            AlphaBatchKind::Composite => unreachable!(),
+            AlphaBatchKind::Composite |
+            AlphaBatchKind::Blit => unreachable!(),
         };
 
         PrimitiveBatch {
@@ -1490,7 +1902,7 @@ impl PrimitiveBatch {
                      mode: MixBlendMode,
                      z_sort_index: i32) -> PrimitiveBatch {
         let data = PrimitiveBatchData::Composite(PrimitiveInstance {
-            global_prim_id: -1,
+            geometry_address: GpuStoreAddress(-1),
             prim_address: GpuStoreAddress(0),
             task_index: task_index.0 as i32,
             clip_task_index: -1,
@@ -1511,6 +1923,21 @@ impl PrimitiveBatch {
             items: vec![PrimitiveBatchItem::StackingContext(stacking_context_index)],
         }
     }
+
+    fn new_blit(prim_index: PrimitiveIndex,
+                textures: BatchTextures,
+                blit: ImageBlit) -> PrimitiveBatch {
+        let key = AlphaBatchKey::new(AlphaBatchKind::Blit,
+                                     AXIS_ALIGNED,
+                                     BlendMode::None,
+                                     textures);
+
+        PrimitiveBatch {
+            key: key,
+            data: PrimitiveBatchData::Blit(blit),
+            items: vec![PrimitiveBatchItem::Primitive(prim_index)],
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
@@ -1543,6 +1970,17 @@ pub struct StackingContext {
     // when to isolate a mix-blend-mode composite.
     pub is_page_root: bool,
 
+    // Read back the contents of the stacking context after drawing them.
+    pub snapshot: Option<SnapshotId>,
+
+    // Copy the contents of the stacking context to an external image after
+    // drawing them, instead of compositing them into the parent.
+    pub render_target: Option<ExternalImageData>,
+
+    // The device rect of the whole pipeline drawn into `render_target`,
+    // which isn't clipped to the screen.
+    pub render_target_rect: DeviceIntRect,
+
     // Wehther or not this stacking context has any visible components, calculated
     // based on the size and position of all children and how they are clipped.
     pub is_visible: bool,
@@ -1562,6 +2000,9 @@ impl StackingContext {
             clip_scroll_groups: Vec::new(),
             should_isolate: false,
             is_page_root: is_page_root,
+            snapshot: None,
+            render_target: None,
+            render_target_rect: DeviceIntRect::zero(),
             is_visible: false,
         }
     }
@@ -1650,6 +2091,57 @@ impl PackedLayer {
     }
 }
 
+/// Something drawn into the framebuffer, used to find the parts of the
+/// framebuffer that changed between two frames. Items with the same rect
+/// and key draw the same pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DamageItem {
+    pub rect: DeviceIntRect,
+    pub key: u64,
+}
+
+/// A frequently updating image that is left out of the frame, for the
+/// renderer to draw into a compositor surface of its own. Rects are in
+/// device pixels, with the origin at the top left of the framebuffer.
+#[derive(Debug, Clone)]
+pub struct CompositorSurface {
+    /// The external image or WebGL context drawn into the surface.
+    pub texture: SourceTexture,
+    pub image_rendering: ImageRendering,
+    /// Where the primitive would have read the UV rect of the image.
+    pub resource_address: GpuStoreAddress,
+    /// The rect the whole image is drawn to.
+    pub rect: DeviceIntRect,
+    /// The visible part of `rect`, after clipping.
+    pub clip_rect: DeviceIntRect,
+}
+
+/// A packed layer whose transform depends on a pose that may be late-latched
+/// by the renderer. The final transform is `pre_transform * pose * post_transform`,
+/// which lets the renderer substitute a more recent pose just before the layer
+/// data is uploaded, without rebuilding the frame.
+#[derive(Debug, Clone)]
+pub struct LateLatchedLayer {
+    pub binding_id: PropertyBindingId,
+    pub packed_layer_index: PackedLayerIndex,
+    pub pre_transform: Matrix4D<f32>,
+    pub post_transform: Matrix4D<f32>,
+}
+
+impl LateLatchedLayer {
+    pub fn patch(&self, layers: &mut [PackedLayer], pose: &Matrix4D<f32>) {
+        let transform = self.pre_transform
+                            .pre_mul(pose)
+                            .pre_mul(&self.post_transform);
+        let layer = &mut layers[self.packed_layer_index.0];
+        // A non-invertible pose leaves the layer with the transform it was built with.
+        if let Some(inv_transform) = transform.inverse() {
+            layer.transform = LayerToWorldTransform::from_untyped(&transform);
+            layer.inv_transform = WorldToLayerTransform::from_untyped(&inv_transform);
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CompositeOps {
     // Requires only a single texture as input (e.g. most filters)
@@ -1694,18 +2186,37 @@ pub struct Frame {
 
     pub layer_texture_data: Vec<PackedLayer>,
     pub render_task_data: Vec<RenderTaskData>,
-    pub gpu_data16: Vec<GpuBlock16>,
-    pub gpu_data32: Vec<GpuBlock32>,
-    pub gpu_data64: Vec<GpuBlock64>,
-    pub gpu_data128: Vec<GpuBlock128>,
-    pub gpu_geometry: Vec<PrimitiveGeometry>,
     pub gpu_gradient_data: Vec<GradientData>,
-    pub gpu_resource_rects: Vec<TexelRect>,
+
+    // Rows of the GPU cache that changed since the previous frame.
+    pub gpu_cache_updates: GpuCacheUpdateList,
+
+    // Layers that the renderer may patch with a late-latched
+    // pose right before uploading the layer texture.
+    pub late_latched_layers: Vec<LateLatchedLayer>,
+
+    // Video primitives that were left out of the frame, for the
+    // embedder to present in hardware overlay planes instead.
+    pub overlay_candidates: Vec<OverlayCandidate>,
+
+    // Images that are left out of the frame, to be drawn into compositor
+    // surfaces of their own.
+    pub compositor_surfaces: Vec<CompositorSurface>,
+
+    // What the frame draws into the framebuffer, for the renderer to
+    // work out which parts of it changed since the previous frame.
+    pub damage_items: Vec<DamageItem>,
+
+    pub statistics: PipelineStatistics,
 
     // List of textures that we don't know about yet
     // from the backend thread. The render thread
     // will use a callback to resolve these and
     // patch the data structures.
     pub deferred_resolves: Vec<DeferredResolve>,
+
+    // The index of the frame in the render task cache, for the renderer
+    // to report back once the frame is drawn.
+    pub render_task_cache_frame: usize,
 }
 
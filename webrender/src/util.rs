@@ -4,8 +4,12 @@
 
 use euclid::{Point2D, Rect, Size2D};
 use euclid::{TypedRect, TypedPoint2D, TypedSize2D, TypedPoint4D, TypedMatrix4D};
-use webrender_traits::{DeviceIntRect, DeviceIntPoint, DeviceIntSize};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use webrender_traits::{DeviceIntRect, DeviceIntPoint, DeviceIntSize, DeviceUintSize};
 use webrender_traits::{LayerRect, WorldPoint4D, LayerPoint4D, LayerToWorldTransform};
+use webrender_traits::LayoutTransform;
 use num_traits::Zero;
 
 // TODO: Implement these in euclid!
@@ -269,3 +273,29 @@ impl TransformedRect {
 pub fn pack_as_float(value: u32) -> f32 {
     value as f32 + 0.5
 }
+
+/// Non-invertible transforms flatten their contents onto a line or a point,
+/// so there is nothing to draw.
+pub fn is_invertible_transform(transform: &LayoutTransform) -> bool {
+    transform.to_row_major_array().iter().all(|value| value.is_finite()) &&
+    transform.inverse().is_some()
+}
+
+/// Saves RGBA8 pixels as a PAM image, keeping the first `channels` channels
+/// of each pixel. The rows are expected in top to bottom order.
+pub fn write_pam(path: &Path, pixels: &[u8], size: &DeviceUintSize, channels: usize) -> io::Result<()> {
+    let tuple_type = if channels == 4 { "RGB_ALPHA" } else { "GRAYSCALE" };
+    let mut file = try!(File::create(path));
+    try!(write!(file,
+                "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL 255\nTUPLTYPE {}\nENDHDR\n",
+                size.width,
+                size.height,
+                channels,
+                tuple_type));
+
+    let mut data = Vec::with_capacity(pixels.len() / 4 * channels);
+    for pixel in pixels.chunks(4) {
+        data.extend_from_slice(&pixel[..channels]);
+    }
+    file.write_all(&data)
+}
@@ -7,6 +7,7 @@
 //! The API surface provided here should be roughly the same to the one provided
 //! in webgl_types, modulo completely compiled-out stuff.
 
+use gleam::gl;
 use webrender_traits::DeviceIntSize;
 use webrender_traits::{GLContextAttributes, GLLimits};
 use webrender_traits::WebGLCommand;
@@ -45,6 +46,14 @@ impl GLContextWrapper {
         unreachable!()
     }
 
+    pub fn insert_fence(&self) -> gl::GLsync {
+        unreachable!()
+    }
+
+    pub fn supports_extension(&self, _: &str) -> bool {
+        unreachable!()
+    }
+
     pub fn get_info(&self) -> (DeviceIntSize, u32, GLLimits) {
         unreachable!()
     }
@@ -92,6 +92,34 @@ impl GLContextWrapper {
         }
     }
 
+    /// Insert a fence after the commands issued so far, and flush so
+    /// that the fence becomes visible to the other contexts of the share group.
+    pub fn insert_fence(&self) -> gl::GLsync {
+        match *self {
+            GLContextWrapper::Native(ref ctx) => {
+                let fence = ctx.gl().fence_sync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+                ctx.gl().flush();
+                fence
+            }
+            GLContextWrapper::OSMesa(ref ctx) => {
+                let fence = ctx.gl().fence_sync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+                ctx.gl().flush();
+                fence
+            }
+        }
+    }
+
+    /// Whether the context advertises the GL extension `name`. The context
+    /// must be current.
+    pub fn supports_extension(&self, name: &str) -> bool {
+        let gl = match *self {
+            GLContextWrapper::Native(ref ctx) => ctx.gl(),
+            GLContextWrapper::OSMesa(ref ctx) => ctx.gl(),
+        };
+        let count = gl.get_integer_v(gl::NUM_EXTENSIONS);
+        (0..count as gl::GLuint).any(|index| gl.get_string_i(gl::EXTENSIONS, index) == name)
+    }
+
     pub fn get_info(&self) -> (DeviceIntSize, u32, GLLimits) {
         match *self {
             GLContextWrapper::Native(ref ctx) => {
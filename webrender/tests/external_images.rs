@@ -0,0 +1,81 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+// The headless context comes from offscreen_gl_context.
+#![cfg(feature = "webgl")]
+
+extern crate euclid;
+extern crate gleam;
+extern crate offscreen_gl_context;
+extern crate webrender;
+extern crate webrender_traits;
+
+mod headless;
+
+use headless::HeadlessContext;
+use webrender::{Renderer, RendererOptions, RendererWarning};
+use webrender_traits::{ColorF, DeviceUintPoint, DeviceUintRect, DeviceUintSize, DisplayListBuilder};
+use webrender_traits::{Epoch, ExternalImageData, ExternalImageId, ExternalImageType};
+use webrender_traits::{ImageData, ImageDescriptor, ImageFormat, ImageRendering, LayoutPoint};
+use webrender_traits::{LayoutRect, LayoutSize, MixBlendMode, PipelineId, ScrollPolicy};
+
+#[test]
+fn external_image_without_handler_warns() {
+    let size = DeviceUintSize::new(64, 64);
+    let context = HeadlessContext::new(size);
+    let options = RendererOptions {
+        synchronous_backend: true,
+        ..Default::default()
+    };
+    let (mut renderer, sender) = Renderer::new(context.gl(), options).unwrap();
+    let api = sender.create_api();
+    api.set_window_parameters(size, DeviceUintRect::new(DeviceUintPoint::zero(), size), 1.0);
+
+    let external_id = ExternalImageId(1);
+    let image_key = api.generate_image_key();
+    api.add_image(image_key,
+                  ImageDescriptor::new(32, 32, ImageFormat::RGBA8, true),
+                  ImageData::External(ExternalImageData {
+                      id: external_id,
+                      image_type: ExternalImageType::Texture2DHandle,
+                  }),
+                  None);
+
+    let pipeline_id = PipelineId(0, 0);
+    let viewport_size = LayoutSize::new(size.width as f32, size.height as f32);
+    let bounds = LayoutRect::new(LayoutPoint::zero(), viewport_size);
+    let mut builder = DisplayListBuilder::new(pipeline_id);
+    builder.push_stacking_context(ScrollPolicy::Scrollable,
+                                  bounds,
+                                  0,
+                                  None,
+                                  None,
+                                  MixBlendMode::Normal,
+                                  Vec::new());
+    let image_rect = LayoutRect::new(LayoutPoint::new(16.0, 16.0), LayoutSize::new(32.0, 32.0));
+    let clip = builder.new_clip_region(&image_rect, Vec::new(), None);
+    builder.push_image(image_rect,
+                       clip,
+                       image_rect.size,
+                       LayoutSize::zero(),
+                       ImageRendering::Auto,
+                       image_key);
+    builder.pop_stacking_context();
+
+    api.set_root_pipeline(pipeline_id);
+    api.set_display_list(Some(ColorF::new(1.0, 1.0, 1.0, 1.0)),
+                         Epoch(0),
+                         viewport_size,
+                         builder.finalize(),
+                         false);
+    api.generate_frame(None, None);
+
+    renderer.update_and_build_frame();
+    renderer.render(size);
+
+    let warnings = renderer.take_warnings();
+    assert!(warnings.contains(&RendererWarning::MissingExternalImageHandler(external_id)));
+
+    renderer.deinit();
+}
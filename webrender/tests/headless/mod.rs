@@ -0,0 +1,71 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! An OSMesa context for the tests that need to render, with a framebuffer
+//! of the requested size bound for drawing.
+
+use euclid::Size2D;
+use gleam::gl;
+use offscreen_gl_context::{ColorAttachmentType, GLContext, GLContextAttributes};
+use offscreen_gl_context::{NativeGLContextMethods, OSMesaContext};
+use std::rc::Rc;
+use webrender_traits::DeviceUintSize;
+
+pub struct HeadlessContext {
+    _context: GLContext<OSMesaContext>,
+    gl: Rc<gl::Gl>,
+    framebuffer: gl::GLuint,
+    renderbuffers: Vec<gl::GLuint>,
+}
+
+impl HeadlessContext {
+    pub fn new(size: DeviceUintSize) -> HeadlessContext {
+        let context = GLContext::<OSMesaContext>::new(Size2D::new(size.width as i32,
+                                                                  size.height as i32),
+                                                      GLContextAttributes::default(),
+                                                      ColorAttachmentType::Renderbuffer,
+                                                      gl::GlType::default(),
+                                                      None)
+            .expect("Failed to create an OSMesa context");
+        context.make_current().unwrap();
+
+        let gl = unsafe {
+            gl::GlFns::load_with(|symbol| OSMesaContext::get_proc_address(symbol) as *const _)
+        };
+
+        // The renderer draws into whatever framebuffer is bound, and needs a
+        // depth buffer along with the color buffer.
+        let framebuffer = gl.gen_framebuffers(1)[0];
+        let renderbuffers = gl.gen_renderbuffers(2);
+        gl.bind_framebuffer(gl::FRAMEBUFFER, framebuffer);
+        for &(renderbuffer, format, attachment) in &[(renderbuffers[0], gl::RGBA8, gl::COLOR_ATTACHMENT0),
+                                                     (renderbuffers[1], gl::DEPTH24_STENCIL8, gl::DEPTH_STENCIL_ATTACHMENT)] {
+            gl.bind_renderbuffer(gl::RENDERBUFFER, renderbuffer);
+            gl.renderbuffer_storage(gl::RENDERBUFFER,
+                                    format,
+                                    size.width as gl::GLsizei,
+                                    size.height as gl::GLsizei);
+            gl.framebuffer_renderbuffer(gl::FRAMEBUFFER, attachment, gl::RENDERBUFFER, renderbuffer);
+        }
+        assert_eq!(gl.check_frame_buffer_status(gl::FRAMEBUFFER), gl::FRAMEBUFFER_COMPLETE);
+
+        HeadlessContext {
+            _context: context,
+            gl: gl,
+            framebuffer: framebuffer,
+            renderbuffers: renderbuffers,
+        }
+    }
+
+    pub fn gl(&self) -> Rc<gl::Gl> {
+        self.gl.clone()
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        self.gl.delete_framebuffers(&[self.framebuffer]);
+        self.gl.delete_renderbuffers(&self.renderbuffers);
+    }
+}
@@ -0,0 +1,68 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+// The headless context comes from offscreen_gl_context.
+#![cfg(all(feature = "reftest", feature = "webgl"))]
+
+extern crate euclid;
+extern crate gleam;
+extern crate offscreen_gl_context;
+extern crate webrender;
+extern crate webrender_traits;
+
+mod headless;
+
+use headless::HeadlessContext;
+use webrender::{ReftestFuzz, ReftestHarness, RendererOptions};
+use webrender_traits::{AuxiliaryLists, BuiltDisplayList, ColorF, DeviceUintSize};
+use webrender_traits::{DisplayListBuilder, LayoutPoint, LayoutRect, LayoutSize};
+use webrender_traits::{MixBlendMode, PipelineId, ScrollPolicy};
+
+fn build_display_list<F>(size: DeviceUintSize, push_items: F)
+                         -> (PipelineId, BuiltDisplayList, AuxiliaryLists)
+                         where F: FnOnce(&mut DisplayListBuilder) {
+    let mut builder = DisplayListBuilder::new(PipelineId(0, 0));
+    let bounds = LayoutRect::new(LayoutPoint::zero(),
+                                 LayoutSize::new(size.width as f32, size.height as f32));
+    builder.push_stacking_context(ScrollPolicy::Scrollable,
+                                  bounds,
+                                  0,
+                                  None,
+                                  None,
+                                  MixBlendMode::Normal,
+                                  Vec::new());
+    push_items(&mut builder);
+    builder.pop_stacking_context();
+    builder.finalize()
+}
+
+fn push_rect(builder: &mut DisplayListBuilder, x: f32, y: f32, width: f32, height: f32) {
+    let rect = LayoutRect::new(LayoutPoint::new(x, y), LayoutSize::new(width, height));
+    let clip = builder.new_clip_region(&rect, Vec::new(), None);
+    builder.push_rect(rect, clip, ColorF::new(0.0, 0.5, 1.0, 1.0));
+}
+
+#[test]
+fn split_rect_matches_whole_rect() {
+    let size = DeviceUintSize::new(64, 64);
+    let context = HeadlessContext::new(size);
+    let mut harness = ReftestHarness::new(context.gl(), RendererOptions::default(), size).unwrap();
+    let background = Some(ColorF::new(1.0, 1.0, 1.0, 1.0));
+
+    let test = harness.render(background, build_display_list(size, |builder| {
+        push_rect(builder, 16.0, 16.0, 16.0, 32.0);
+        push_rect(builder, 32.0, 16.0, 16.0, 32.0);
+    }));
+    let reference = harness.render(background, build_display_list(size, |builder| {
+        push_rect(builder, 16.0, 16.0, 32.0, 32.0);
+    }));
+    let blank = harness.render(background, build_display_list(size, |_| {}));
+
+    let fuzz = ReftestFuzz::exact();
+    assert!(harness.check("split-rect", &test, &reference, &fuzz).is_pass());
+    // Guards against both renders coming out empty.
+    assert!(!harness.check("split-rect-blank", &reference, &blank, &fuzz).is_pass());
+
+    harness.deinit();
+}
@@ -0,0 +1,38 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+// The headless context comes from offscreen_gl_context.
+#![cfg(feature = "webgl")]
+
+extern crate euclid;
+extern crate gleam;
+extern crate offscreen_gl_context;
+extern crate webrender;
+extern crate webrender_traits;
+
+mod headless;
+
+use headless::HeadlessContext;
+use webrender::{Renderer, RendererOptions};
+use webrender_traits::DeviceUintSize;
+
+#[test]
+fn reply_queries_run_the_inline_backend() {
+    let context = HeadlessContext::new(DeviceUintSize::new(64, 64));
+    let options = RendererOptions {
+        synchronous_backend: true,
+        ..Default::default()
+    };
+    let (mut renderer, sender) = Renderer::new(context.gl(), options).unwrap();
+
+    // Both of these wait for the backend to reply, which only happens if
+    // they run it themselves.
+    let api = sender.create_api();
+    let other_api = sender.create_api();
+    assert!(api.id_namespace.0 != other_api.id_namespace.0);
+    assert!(api.get_scroll_node_state().is_empty());
+
+    renderer.update_and_build_frame();
+    renderer.deinit();
+}
@@ -5,19 +5,22 @@
 use channel::{self, MsgSender, Payload, PayloadSenderHelperMethods, PayloadSender};
 #[cfg(feature = "webgl")]
 use offscreen_gl_context::{GLContextAttributes, GLLimits};
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::fmt;
 use std::marker::PhantomData;
+use std::mem;
 use {AuxiliaryLists, AuxiliaryListsDescriptor, BuiltDisplayList, BuiltDisplayListDescriptor};
-use {ColorF, DeviceIntPoint, DeviceIntSize, DeviceUintRect, DeviceUintSize, FontKey};
-use {GlyphDimensions, GlyphKey, ImageData, ImageDescriptor, ImageKey, LayoutPoint, LayoutSize};
-use {LayoutTransform, NativeFontHandle, ScrollLayerId, WorldPoint};
+use DisplayListError;
+use {ColorF, DeviceIntPoint, DeviceIntSize, DeviceUintRect, DeviceUintSize, ExternalImageData};
+use {FontKey, GlyphDimensions, GlyphKey, ImageData, ImageDescriptor, ImageKey, LayoutPoint};
+use {LayoutSize, LayoutTransform, NativeFontHandle, ScrollLayerId, WorldPoint};
 #[cfg(feature = "webgl")]
 use {WebGLCommand, WebGLContextId};
 
 pub type TileSize = u16;
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum ApiMsg {
     AddRawFont(FontKey, Vec<u8>),
     AddNativeFont(FontKey, NativeFontHandle),
@@ -43,25 +46,48 @@ pub enum ApiMsg {
                    AuxiliaryListsDescriptor,
                    bool),
     SetPageZoom(ZoomFactor),
+    /// Enables or disables subpixel AA for text. Takes effect when the
+    /// scene is next built.
+    SetSubpixelAA(bool),
     SetPinchZoom(ZoomFactor),
     SetPan(DeviceIntPoint),
     SetRootPipeline(PipelineId),
-    SetWindowParameters(DeviceUintSize, DeviceUintRect),
+    /// Sets a pipeline's background without a new display list.
+    SetPipelineBackgroundColor(PipelineId, Option<ColorF>),
+    /// Drops a pipeline's display list and everything kept for it.
+    RemovePipeline(PipelineId),
+    /// Draws a pipeline into an external image instead of its iframe, see
+    /// `RenderApi::set_pipeline_render_target`.
+    SetPipelineRenderTarget(PipelineId, Option<ExternalImageData>),
+    SetWindowParameters(DeviceUintSize, DeviceUintRect, f32),
     Scroll(ScrollLocation, WorldPoint, ScrollEventPhase),
     ScrollLayerWithId(LayoutPoint, ScrollLayerId),
+    ScrollNodeWithId(LayoutPoint, ScrollLayerId, ScrollClamping, Option<ScrollAnimation>),
+    /// Changes how a scroll node behaves when scrolled past its bounds.
+    SetOverscrollBehavior(ScrollLayerId, OverscrollBehavior),
+    /// Hands the scroll offset of a node to the embedder, see
+    /// `RenderApi::set_externally_scrolled`.
+    SetExternallyScrolled(ScrollLayerId, bool),
     TickScrollingBounce,
     TranslatePointToLayerSpace(WorldPoint, MsgSender<(LayoutPoint, PipelineId)>),
     GetScrollLayerState(MsgSender<Vec<ScrollLayerState>>),
     RequestWebGLContext(DeviceIntSize, GLContextAttributes, MsgSender<Result<(WebGLContextId, GLLimits), String>>),
     ResizeWebGLContext(WebGLContextId, DeviceIntSize),
+    DeleteWebGLContext(WebGLContextId),
     WebGLCommand(WebGLContextId, WebGLCommand),
-    GenerateFrame(Option<DynamicProperties>),
+    GenerateFrame(Option<DynamicProperties>, Option<FrameToken>),
     // WebVR commands that must be called in the WebGL render thread.
     VRCompositorCommand(WebGLContextId, VRCompositorCommand),
     /// An opaque handle that must be passed to the render notifier. It is used by Gecko
     /// to forward gecko-specific messages to the render thread preserving the ordering
     /// within the other messages.
     ExternalEvent(ExternalEvent),
+    /// Tells webrender how short of memory the system is.
+    SetMemoryPressure(MemoryPressure),
+    /// Drives the debugging aids of webrender, see `DebugCommand`.
+    DebugCommand(DebugCommand),
+    /// Asks the render backend for data, which it answers with the same id.
+    BackendQuery(BackendQueryId, BackendQuery),
     ShutDown,
 }
 
@@ -78,19 +104,30 @@ impl fmt::Debug for ApiMsg {
             &ApiMsg::CloneApi(..) => { write!(f, "ApiMsg::CloneApi") }
             &ApiMsg::SetDisplayList(..) => { write!(f, "ApiMsg::SetDisplayList") }
             &ApiMsg::SetRootPipeline(..) => { write!(f, "ApiMsg::SetRootPipeline") }
+            &ApiMsg::SetPipelineBackgroundColor(..) => { write!(f, "ApiMsg::SetPipelineBackgroundColor") }
+            &ApiMsg::RemovePipeline(..) => { write!(f, "ApiMsg::RemovePipeline") }
+            &ApiMsg::SetPipelineRenderTarget(..) => { write!(f, "ApiMsg::SetPipelineRenderTarget") }
             &ApiMsg::Scroll(..) => { write!(f, "ApiMsg::Scroll") }
             &ApiMsg::ScrollLayerWithId(..) => { write!(f, "ApiMsg::ScrollLayerWithId") }
+            &ApiMsg::ScrollNodeWithId(..) => { write!(f, "ApiMsg::ScrollNodeWithId") }
+            &ApiMsg::SetOverscrollBehavior(..) => { write!(f, "ApiMsg::SetOverscrollBehavior") }
+            &ApiMsg::SetExternallyScrolled(..) => { write!(f, "ApiMsg::SetExternallyScrolled") }
             &ApiMsg::TickScrollingBounce => { write!(f, "ApiMsg::TickScrollingBounce") }
             &ApiMsg::TranslatePointToLayerSpace(..) => { write!(f, "ApiMsg::TranslatePointToLayerSpace") }
             &ApiMsg::GetScrollLayerState(..) => { write!(f, "ApiMsg::GetScrollLayerState") }
             &ApiMsg::RequestWebGLContext(..) => { write!(f, "ApiMsg::RequestWebGLContext") }
             &ApiMsg::ResizeWebGLContext(..) => { write!(f, "ApiMsg::ResizeWebGLContext") }
+            &ApiMsg::DeleteWebGLContext(..) => { write!(f, "ApiMsg::DeleteWebGLContext") }
             &ApiMsg::WebGLCommand(..) => { write!(f, "ApiMsg::WebGLCommand") }
             &ApiMsg::GenerateFrame(..) => { write!(f, "ApiMsg::GenerateFrame") }
             &ApiMsg::VRCompositorCommand(..) => { write!(f, "ApiMsg::VRCompositorCommand") }
             &ApiMsg::ExternalEvent(..) => { write!(f, "ApiMsg::ExternalEvent") }
+            &ApiMsg::SetMemoryPressure(..) => { write!(f, "ApiMsg::SetMemoryPressure") }
+            &ApiMsg::DebugCommand(..) => { write!(f, "ApiMsg::DebugCommand") }
+            &ApiMsg::BackendQuery(..) => { write!(f, "ApiMsg::BackendQuery") }
             &ApiMsg::ShutDown => { write!(f, "ApiMsg::ShutDown") }
             &ApiMsg::SetPageZoom(..) => { write!(f, "ApiMsg::SetPageZoom") }
+            &ApiMsg::SetSubpixelAA(..) => { write!(f, "ApiMsg::SetSubpixelAA") }
             &ApiMsg::SetPinchZoom(..) => { write!(f, "ApiMsg::SetPinchZoom") }
             &ApiMsg::SetPan(..) => { write!(f, "ApiMsg::SetPan") }
             &ApiMsg::SetWindowParameters(..) => { write!(f, "ApiMsg::SetWindowParameters") }
@@ -99,42 +136,57 @@ impl fmt::Debug for ApiMsg {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct Epoch(pub u32);
 
+/// Identifies a frame requested with `generate_frame`, chosen by the
+/// embedder. It is handed back once the frame is built.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct FrameToken(pub u64);
+
 #[cfg(not(feature = "webgl"))]
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct WebGLContextId(pub usize);
 
 #[cfg(not(feature = "webgl"))]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct GLContextAttributes([u8; 0]);
 
 #[cfg(not(feature = "webgl"))]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct GLLimits([u8; 0]);
 
 #[cfg(not(feature = "webgl"))]
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum WebGLCommand {
     Flush,
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct PipelineId(pub u32, pub u32);
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct IdNamespace(pub u32);
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ResourceId(pub u32);
 
 /// An opaque pointer-sized value.
 #[repr(C)]
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ExternalEvent {
     raw: usize,
 }
@@ -148,7 +200,39 @@ impl ExternalEvent {
 }
 
 
-#[derive(Clone, Deserialize, Serialize)]
+thread_local! {
+    static SYNCHRONOUS_BACKENDS: RefCell<Vec<Box<FnMut() -> bool>>> = RefCell::new(Vec::new());
+}
+
+/// Registers a function that processes the queued messages of a render
+/// backend which runs on this thread, rather than on its own.
+///
+/// Calls that wait for a reply from the backend run every registered
+/// function before blocking, as nothing would answer them otherwise. The
+/// function returns false once its backend is gone, which unregisters it.
+pub fn register_synchronous_backend(process_messages: Box<FnMut() -> bool>) {
+    SYNCHRONOUS_BACKENDS.with(|backends| backends.borrow_mut().push(process_messages));
+}
+
+fn run_synchronous_backends() {
+    SYNCHRONOUS_BACKENDS.with(|backends| {
+        // Taken out of the cell while they run, so that a backend can be
+        // registered from within one.
+        let running = mem::replace(&mut *backends.borrow_mut(), Vec::new());
+        let mut remaining = Vec::with_capacity(running.len());
+        for mut process_messages in running {
+            if process_messages() {
+                remaining.push(process_messages);
+            }
+        }
+        let mut backends = backends.borrow_mut();
+        remaining.extend(backends.drain(..));
+        *backends = remaining;
+    });
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct RenderApiSender {
     api_sender: MsgSender<ApiMsg>,
     payload_sender: PayloadSender,
@@ -172,6 +256,7 @@ impl RenderApiSender {
         let (sync_tx, sync_rx) = channel::msg_channel().unwrap();
         let msg = ApiMsg::CloneApi(sync_tx);
         api_sender.send(msg).unwrap();
+        run_synchronous_backends();
         RenderApi {
             api_sender: api_sender.clone(),
             payload_sender: payload_sender.clone(),
@@ -223,6 +308,7 @@ impl RenderApi {
         let (tx, rx) = channel::msg_channel().unwrap();
         let msg = ApiMsg::GetGlyphDimensions(glyph_keys, tx);
         self.api_sender.send(msg).unwrap();
+        run_synchronous_backends();
         rx.recv().unwrap()
     }
 
@@ -277,6 +363,41 @@ impl RenderApi {
         self.api_sender.send(msg).unwrap();
     }
 
+    /// Sets the background color of a pipeline, or makes it transparent
+    /// with `None`, without sending a new display list. Declaring it when
+    /// an iframe is created makes it draw in that color, instead of not at
+    /// all, until its first display list arrives. `set_display_list` sets
+    /// it again.
+    pub fn set_pipeline_background_color(&self,
+                                         pipeline_id: PipelineId,
+                                         background_color: Option<ColorF>) {
+        let msg = ApiMsg::SetPipelineBackgroundColor(pipeline_id, background_color);
+        self.api_sender.send(msg).unwrap();
+    }
+
+    /// Removes a pipeline, e.g. when an iframe is destroyed. Its display
+    /// list, scroll state and epoch are dropped. Texture cache entries that
+    /// only it used are freed with the next frame. Image and font keys
+    /// belong to the API, and still have to be deleted separately.
+    pub fn remove_pipeline(&self, pipeline_id: PipelineId) {
+        let msg = ApiMsg::RemovePipeline(pipeline_id);
+        self.api_sender.send(msg).unwrap();
+    }
+
+    /// Makes the iframes of a pipeline draw into a texture that the embedder
+    /// provides, instead of into the frame, e.g. to map the pipeline onto
+    /// 3D geometry. The texture is locked with the renderer's
+    /// `ExternalImageHandler` after each frame that draws the pipeline, and
+    /// the part of the pipeline that is on screen is stretched over the
+    /// locked rect, with the top row first. `image_type` must be a texture
+    /// handle. `None` composites the pipeline into the frame again.
+    pub fn set_pipeline_render_target(&self,
+                                      pipeline_id: PipelineId,
+                                      target: Option<ExternalImageData>) {
+        let msg = ApiMsg::SetPipelineRenderTarget(pipeline_id, target);
+        self.api_sender.send(msg).unwrap();
+    }
+
     /// Supplies a new frame to WebRender.
     ///
     /// Non-blocking, it notifies a worker process which processes the display list.
@@ -337,6 +458,20 @@ impl RenderApi {
         self.api_sender.send(msg).unwrap();
     }
 
+    /// Scrolls the node `id` so that `origin` is at the top left of its
+    /// viewport. With an animation, the node moves there smoothly over the
+    /// following frames, which are produced by calling
+    /// tick_scrolling_bounce_animations() while the renderer reports
+    /// scroll animations running. A user scroll of the node cancels it.
+    pub fn scroll_node_with_id(&self,
+                               origin: LayoutPoint,
+                               id: ScrollLayerId,
+                               clamping: ScrollClamping,
+                               animation: Option<ScrollAnimation>) {
+        let msg = ApiMsg::ScrollNodeWithId(origin, id, clamping, animation);
+        self.api_sender.send(msg).unwrap();
+    }
+
     pub fn set_page_zoom(&self, page_zoom: ZoomFactor) {
         let msg = ApiMsg::SetPageZoom(page_zoom);
         self.api_sender.send(msg).unwrap();
@@ -352,10 +487,33 @@ impl RenderApi {
         self.api_sender.send(msg).unwrap();
     }
 
+    /// Sets the size of the framebuffer, the rect of it that the document
+    /// is drawn to, and the device pixel ratio. This must be sent before
+    /// the first frame is generated, and again whenever the window is
+    /// resized or moved to a screen of a different density.
     pub fn set_window_parameters(&self,
                                  window_size: DeviceUintSize,
-                                 inner_rect: DeviceUintRect) {
-        let msg = ApiMsg::SetWindowParameters(window_size, inner_rect);
+                                 inner_rect: DeviceUintRect,
+                                 device_pixel_ratio: f32) {
+        let msg = ApiMsg::SetWindowParameters(window_size, inner_rect, device_pixel_ratio);
+        self.api_sender.send(msg).unwrap();
+    }
+
+    /// Sets how the scroll node `id` reacts to being scrolled past its
+    /// bounds. The behavior is kept across display lists until the node's
+    /// pipeline is discarded.
+    pub fn set_overscroll_behavior(&self, id: ScrollLayerId, behavior: OverscrollBehavior) {
+        let msg = ApiMsg::SetOverscrollBehavior(id, behavior);
+        self.api_sender.send(msg).unwrap();
+    }
+
+    /// Marks the scroll node `id` as scrolled by the embedder, typically by
+    /// an async pan/zoom controller. Scroll events and scroll_layer_with_id()
+    /// no longer move the node. Instead its offset is taken as-is from the
+    /// `scroll_offsets` passed to generate_frame(), without clamping, so the
+    /// controller is also in charge of any overscroll.
+    pub fn set_externally_scrolled(&self, id: ScrollLayerId, externally_scrolled: bool) {
+        let msg = ApiMsg::SetExternallyScrolled(id, externally_scrolled);
         self.api_sender.send(msg).unwrap();
     }
 
@@ -370,13 +528,18 @@ impl RenderApi {
         let (tx, rx) = channel::msg_channel().unwrap();
         let msg = ApiMsg::TranslatePointToLayerSpace(*point, tx);
         self.api_sender.send(msg).unwrap();
+        run_synchronous_backends();
         rx.recv().unwrap()
     }
 
-    pub fn get_scroll_layer_state(&self) -> Vec<ScrollLayerState> {
+    /// Returns the id, scrollable size and offset of every scroll node of
+    /// the current frame, e.g. to draw scrollbars or to save the scroll
+    /// positions of a session.
+    pub fn get_scroll_node_state(&self) -> Vec<ScrollLayerState> {
         let (tx, rx) = channel::msg_channel().unwrap();
         let msg = ApiMsg::GetScrollLayerState(tx);
         self.api_sender.send(msg).unwrap();
+        run_synchronous_backends();
         rx.recv().unwrap()
     }
 
@@ -385,6 +548,7 @@ impl RenderApi {
         let (tx, rx) = channel::msg_channel().unwrap();
         let msg = ApiMsg::RequestWebGLContext(*size, attributes, tx);
         self.api_sender.send(msg).unwrap();
+        run_synchronous_backends();
         rx.recv().unwrap()
     }
 
@@ -393,6 +557,13 @@ impl RenderApi {
         self.api_sender.send(msg).unwrap();
     }
 
+    /// Destroys the context and its texture. Images still drawing it
+    /// render nothing.
+    pub fn delete_webgl_context(&self, context_id: WebGLContextId) {
+        let msg = ApiMsg::DeleteWebGLContext(context_id);
+        self.api_sender.send(msg).unwrap();
+    }
+
     pub fn send_webgl_command(&self, context_id: WebGLContextId, command: WebGLCommand) {
         let msg = ApiMsg::WebGLCommand(context_id, command);
         self.api_sender.send(msg).unwrap();
@@ -400,9 +571,13 @@ impl RenderApi {
 
     /// Generate a new frame. Optionally, supply a list of animated
     /// property bindings that should be used to resolve bindings
-    /// in the current display list.
-    pub fn generate_frame(&self, property_bindings: Option<DynamicProperties>) {
-        let msg = ApiMsg::GenerateFrame(property_bindings);
+    /// in the current display list, and a token that is passed to
+    /// `RenderNotifier::generated_frame_ready` once the frame is built,
+    /// to match it with the inputs it was generated from.
+    pub fn generate_frame(&self,
+                          property_bindings: Option<DynamicProperties>,
+                          token: Option<FrameToken>) {
+        let msg = ApiMsg::GenerateFrame(property_bindings, token);
         self.api_sender.send(msg).unwrap();
     }
 
@@ -416,6 +591,24 @@ impl RenderApi {
         self.api_sender.send(msg).unwrap();
     }
 
+    /// Reports a change in how short of memory the system is, typically
+    /// forwarded from an OS notification. Webrender releases memory as the
+    /// renderer's `MemoryPressurePolicy` says for as long as the level is
+    /// above `MemoryPressure::Normal`, and caches grow back as needed once
+    /// it returns to it.
+    pub fn set_memory_pressure(&self, level: MemoryPressure) {
+        let msg = ApiMsg::SetMemoryPressure(level);
+        self.api_sender.send(msg).unwrap();
+    }
+
+    /// Sends a debugging command to the render backend, which forwards it
+    /// to the renderer if needed. This lets debugging tools drive webrender
+    /// without access to the `Renderer`.
+    pub fn send_debug_cmd(&self, cmd: DebugCommand) {
+        let msg = ApiMsg::DebugCommand(cmd);
+        self.api_sender.send(msg).unwrap();
+    }
+
     pub fn shut_down(&self) {
         self.api_sender.send(ApiMsg::ShutDown).unwrap();
     }
@@ -442,7 +635,8 @@ impl RenderApi {
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum ScrollEventPhase {
     /// The user started scrolling.
     Start,
@@ -453,13 +647,142 @@ pub enum ScrollEventPhase {
     End,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ScrollLayerState {
     pub id: ScrollLayerId,
     pub scroll_offset: LayoutPoint,
+    /// How far the node can be scrolled on each axis, which is the size of
+    /// its content less the size of its viewport.
+    pub scrollable_size: LayoutSize,
+    /// How far the node is scrolled past its bounds on each axis, pointing
+    /// back towards the valid range. Zero when the node is in bounds.
+    pub overscroll: LayoutSize,
+    /// True while the node is animating back into bounds.
+    pub bouncing_back: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum ScrollClamping {
+    /// Keep the new origin within the scrollable area.
+    ToContentBounds,
+    /// Use the origin as given, which can leave the node overscrolled.
+    NoClamping,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum ScrollEasing {
+    Linear,
+    EaseOut,
+    EaseInOut,
+}
+
+/// How a programmatic scroll moves to its destination.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct ScrollAnimation {
+    pub duration_ms: u32,
+    pub easing: ScrollEasing,
+}
+
+/// Controls what happens when a scroll node is scrolled past its bounds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum OverscrollBehavior {
+    /// The node stops at its bounds.
+    Clamp,
+    /// The node follows the scroll past its bounds with increasing
+    /// resistance, then springs back once the gesture ends. A damping of
+    /// 1.0 means the spring settles without bouncing.
+    Bounce { stiffness: f32, damping: f32 },
+}
+
+/// How short of memory the system is, from `RenderApi::set_memory_pressure`.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum MemoryPressure {
+    Normal,
+    /// Memory is getting low. Memory that is cheap to get back should be
+    /// released.
+    Moderate,
+    /// The process is about to be killed, or the system to start swapping.
+    Critical,
+}
+
+/// A debugging command sent with `RenderApi::send_debug_cmd`. The commands
+/// for the renderer are applied the next time it receives its messages.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum DebugCommand {
+    /// Sets the debug flags of the renderer, as the bits of
+    /// `webrender::renderer::DebugFlags`. Unknown bits are ignored.
+    SetFlags(u32),
+    /// Shows or hides the profiler overlay.
+    EnableProfiler(bool),
+    /// Saves the CPU profile recorded so far to a file.
+    SaveCpuProfile(String),
+    /// Releases the memory held by the resource caches that can be
+    /// rebuilt, regardless of the memory pressure.
+    ClearCaches,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+/// Identifies a query of the render backend, to match it with its response.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct BackendQueryId(pub u32);
+
+/// The data that can be asked from the render backend, see
+/// `Renderer::query_backend`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum BackendQuery {
+    /// How many resources the resource cache holds.
+    ResourceCacheStats,
+    /// The pipelines of the scene.
+    ScenePipelines,
+}
+
+/// The answer of the render backend to a `BackendQuery` of the same kind.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum BackendQueryResponse {
+    ResourceCacheStats(ResourceCacheStats),
+    ScenePipelines(Vec<ScenePipelineInfo>),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct ResourceCacheStats {
+    pub font_templates: usize,
+    pub image_templates: usize,
+    /// Images and glyphs that are rasterized in the texture cache.
+    pub cached_images: usize,
+    pub cached_glyphs: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct ScenePipelineInfo {
+    pub pipeline_id: PipelineId,
+    /// The epoch of the current display list of the pipeline.
+    pub epoch: Epoch,
+    pub display_item_count: usize,
+}
+
+/// Sent to `RenderNotifier::overscroll_changed` when the overscroll state
+/// of a scroll node changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct OverscrollEvent {
+    pub id: ScrollLayerId,
+    pub overscroll: LayoutSize,
+    pub bouncing_back: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum ScrollLocation {
     /// Scroll by a certain amount.
     Delta(LayoutPoint),
@@ -470,7 +793,8 @@ pub enum ScrollLocation {
 }
 
 /// Represents a zoom factor.
-#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ZoomFactor(f32);
 
 impl ZoomFactor {
@@ -485,7 +809,8 @@ impl ZoomFactor {
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct PropertyBindingId {
     namespace: u32,
     uid: u32,
@@ -503,7 +828,8 @@ impl PropertyBindingId {
 /// A unique key that is used for connecting animated property
 /// values to bindings in the display list.
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct PropertyBindingKey<T> {
     pub id: PropertyBindingId,
     _phantom: PhantomData<T>,
@@ -531,7 +857,8 @@ impl<T> PropertyBindingKey<T> {
 /// A binding property can either be a specific value
 /// (the normal, non-animated case) or point to a binding location
 /// to fetch the current value from.
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum PropertyBinding<T> {
     Value(T),
     Binding(PropertyBindingKey<T>),
@@ -551,7 +878,8 @@ impl<T> From<PropertyBindingKey<T>> for PropertyBinding<T> {
 
 /// The current value of an animated property. This is
 /// supplied by the calling code.
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct PropertyValue<T> {
     pub key: PropertyBindingKey<T>,
     pub value: T,
@@ -560,32 +888,73 @@ pub struct PropertyValue<T> {
 /// When using generate_frame(), a list of PropertyValue structures
 /// can optionally be supplied to provide the current value of any
 /// animated properties.
-#[derive(Clone, Deserialize, Serialize, Debug)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct DynamicProperties {
     pub transforms: Vec<PropertyValue<LayoutTransform>>,
     pub floats: Vec<PropertyValue<f32>>,
+    /// Offsets for scroll nodes marked with `set_externally_scrolled`.
+    /// Unlike the other properties, these stay in effect until replaced.
+    pub scroll_offsets: Vec<ExternalScrollOffset>,
+}
+
+/// The scroll offset of an externally scrolled node, in the same convention
+/// as `ScrollLayerState::scroll_offset`.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct ExternalScrollOffset {
+    pub id: ScrollLayerId,
+    pub offset: LayoutPoint,
 }
 
 pub type VRCompositorId = u64;
 
 // WebVR commands that must be called in the WebGL render thread.
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum VRCompositorCommand {
     Create(VRCompositorId),
     SyncPoses(VRCompositorId, f64, f64, MsgSender<Result<Vec<u8>,()>>),
     SubmitFrame(VRCompositorId, [f32; 4], [f32; 4]),
+    /// Submit both eyes rendered in a single OVR_multiview2 pass. The u32 is the
+    /// name of a texture array owned by the WebGL context, where layer 0 holds
+    /// the left eye and layer 1 the right eye.
+    ///
+    /// The WebGL content does the multiview rendering. WebRender only checks
+    /// that the context supports OVR_multiview2, and passes the array on to
+    /// the `VRCompositorHandler`. Frames from other contexts are dropped.
+    SubmitMultiviewFrame(VRCompositorId, u32),
     Release(VRCompositorId)
 }
 
 // Trait object that handles WebVR commands.
-// Receives the texture_id associated to the WebGLContext.
+// Receives the texture_id associated to the WebGLContext, or the
+// texture array for multiview frames.
 pub trait VRCompositorHandler: Send {
     fn handle(&mut self, command: VRCompositorCommand, texture_id: Option<u32>);
 }
 
 pub trait RenderNotifier: Send {
     fn new_frame_ready(&mut self);
+    /// Called after `new_frame_ready` for a frame requested with a token
+    /// passed to `RenderApi::generate_frame`.
+    fn generated_frame_ready(&mut self, _token: FrameToken) {}
     fn new_scroll_frame_ready(&mut self, composite_needed: bool);
+    /// Called after a scroll or bounce tick changes how far any scroll
+    /// node is past its bounds, including when it comes back to rest.
+    fn overscroll_changed(&mut self, _events: Vec<OverscrollEvent>) {}
+    /// Called instead of building a frame when display list validation is
+    /// enabled and the display list sent for `pipeline_id` at `epoch` is
+    /// malformed. The pipeline keeps its previous display list.
+    fn display_list_rejected(&mut self,
+                             _pipeline_id: PipelineId,
+                             _epoch: Epoch,
+                             _error: DisplayListError) {}
+    /// Called after a frame is built with the indices of any glyphs of
+    /// `font_key` that it asked for but the font doesn't have, so the
+    /// embedder can fall back to another font and resend the display list.
+    /// Each glyph is only reported the first time it is rasterized.
+    fn glyphs_missing(&mut self, _font_key: FontKey, _glyph_indices: Vec<u32>) {}
     fn external_event(&mut self, _evt: ExternalEvent) { unimplemented!() }
     fn shut_down(&mut self) {}
 }
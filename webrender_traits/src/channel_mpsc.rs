@@ -2,9 +2,9 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use serde::{Deserialize, Serialize};
+#[cfg(feature = "serialization")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::io::{Error, ErrorKind};
-use serde::{Deserializer, Serializer};
 
 use std::sync::mpsc;
 
@@ -38,6 +38,12 @@ impl<T> MsgReceiver<T> {
         use std::error::Error;
         self.rx.recv().map_err(|e| io::Error::new(ErrorKind::Other, e.description()))
     }
+
+    pub fn try_recv(&self) -> Result<T, Error> {
+        use std::io;
+        use std::error::Error;
+        self.rx.try_recv().map_err(|e| io::Error::new(ErrorKind::Other, e.description()))
+    }
 }
 
 #[derive(Clone)]
@@ -71,18 +77,21 @@ pub fn msg_channel<T>() -> Result<(MsgSender<T>, MsgReceiver<T>), Error> {
 /// that the replay tool is writing.
 ///
 
+#[cfg(feature = "serialization")]
 impl<T> Serialize for MsgReceiver<T> {
     fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
         unreachable!();
     }
 }
 
+#[cfg(feature = "serialization")]
 impl<T> Serialize for MsgSender<T> {
     fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
         unreachable!();
     }
 }
 
+#[cfg(feature = "serialization")]
 impl<T> Deserialize for MsgReceiver<T> {
     fn deserialize<D>(_: D) -> Result<MsgReceiver<T>, D::Error>
                       where D: Deserializer {
@@ -90,6 +99,7 @@ impl<T> Deserialize for MsgReceiver<T> {
     }
 }
 
+#[cfg(feature = "serialization")]
 impl<T> Deserialize for MsgSender<T> {
     fn deserialize<D>(_: D) -> Result<MsgSender<T>, D::Error>
                       where D: Deserializer {
@@ -3,7 +3,8 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ColorF {
     pub r: f32,
     pub g: f32,
@@ -13,7 +14,8 @@ pub struct ColorF {
 known_heap_size!(0, ColorF);
 
 #[repr(C)]
-#[derive(Clone, Copy, Hash, Eq, Debug, Deserialize, PartialEq, PartialOrd, Ord, Serialize)]
+#[derive(Clone, Copy, Hash, Eq, Debug, PartialEq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ColorU {
     pub r: u8,
     pub g: u8,
@@ -62,3 +64,89 @@ impl ColorU {
     }
 }
 
+
+/// The RGB color spaces that colors and images can be given in, and that
+/// frames can be drawn in. Both use the sRGB transfer function and white
+/// point, and only differ in their primaries.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum ColorSpace {
+    Srgb = 0,
+    DisplayP3 = 1,
+}
+
+// Converts linear sRGB to linear Display P3, row by row.
+const SRGB_TO_DISPLAY_P3: [[f32; 3]; 3] = [
+    [0.8224621, 0.1775380, 0.0000000],
+    [0.0331941, 0.9668058, 0.0000000],
+    [0.0170827, 0.0723974, 0.9105199],
+];
+
+// Converts linear Display P3 to linear sRGB, row by row.
+const DISPLAY_P3_TO_SRGB: [[f32; 3]; 3] = [
+    [ 1.2249401, -0.2249404, 0.0000000],
+    [-0.0420569,  1.0420571, 0.0000000],
+    [-0.0196376, -0.0786361, 1.0982735],
+];
+
+impl ColorSpace {
+    /// Converts gamma encoded RGB values from this color space to `to`.
+    /// Colors outside of the gamut of `to` get values below 0 or above 1,
+    /// and convert back to what they were.
+    pub fn convert_rgb(self, to: ColorSpace, rgb: [f32; 3]) -> [f32; 3] {
+        let matrix = match (self, to) {
+            (ColorSpace::Srgb, ColorSpace::DisplayP3) => &SRGB_TO_DISPLAY_P3,
+            (ColorSpace::DisplayP3, ColorSpace::Srgb) => &DISPLAY_P3_TO_SRGB,
+            _ => return rgb,
+        };
+
+        let linear = [
+            srgb_to_linear(rgb[0]),
+            srgb_to_linear(rgb[1]),
+            srgb_to_linear(rgb[2]),
+        ];
+        let mut result = [0.0; 3];
+        for (value, row) in result.iter_mut().zip(matrix.iter()) {
+            *value = linear_to_srgb(row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2]);
+        }
+        result
+    }
+}
+
+// The sRGB transfer function, extended to negative values by symmetry.
+fn srgb_to_linear(value: f32) -> f32 {
+    let magnitude = value.abs();
+    let linear = if magnitude <= 0.04045 {
+        magnitude / 12.92
+    } else {
+        ((magnitude + 0.055) / 1.055).powf(2.4)
+    };
+    if value < 0.0 { -linear } else { linear }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    let magnitude = value.abs();
+    let encoded = if magnitude <= 0.0031308 {
+        magnitude * 12.92
+    } else {
+        1.055 * magnitude.powf(1.0 / 2.4) - 0.055
+    };
+    if value < 0.0 { -encoded } else { encoded }
+}
+
+impl ColorF {
+    /// Converts a color given in `from` to `to`. Display lists take colors
+    /// in sRGB, so colors in other color spaces are converted to sRGB with
+    /// this before being added, keeping the parts of them that sRGB can't
+    /// show for frames drawn in a wider color space.
+    pub fn convert_color_space(&self, from: ColorSpace, to: ColorSpace) -> ColorF {
+        let rgb = from.convert_rgb(to, [self.r, self.g, self.b]);
+        ColorF {
+            r: rgb[0],
+            g: rgb[1],
+            b: rgb[2],
+            a: self.a,
+        }
+    }
+}
@@ -9,7 +9,8 @@ use {ColorF, FontKey, ImageKey, PipelineId, WebGLContextId};
 use {LayoutPoint, LayoutRect, LayoutSize, LayoutTransform};
 use {PropertyBinding};
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct DisplayItem {
     pub item: SpecificDisplayItem,
     pub rect: LayoutRect,
@@ -17,7 +18,8 @@ pub struct DisplayItem {
     pub scroll_layer_id: ScrollLayerId,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum SpecificDisplayItem {
     Clip(ClipDisplayItem),
     Rectangle(RectangleDisplayItem),
@@ -27,6 +29,7 @@ pub enum SpecificDisplayItem {
     WebGL(WebGLDisplayItem),
     Border(BorderDisplayItem),
     BoxShadow(BoxShadowDisplayItem),
+    Outline(OutlineDisplayItem),
     Gradient(GradientDisplayItem),
     RadialGradient(RadialGradientDisplayItem),
     Iframe(IframeDisplayItem),
@@ -35,25 +38,29 @@ pub enum SpecificDisplayItem {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ItemRange {
     pub start: usize,
     pub length: usize,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ClipDisplayItem {
     pub content_size: LayoutSize,
     pub id: ScrollLayerId,
     pub parent_id: ScrollLayerId,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct RectangleDisplayItem {
     pub color: ColorF,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct TextDisplayItem {
     pub glyphs: ItemRange,
     pub font_key: FontKey,
@@ -63,20 +70,23 @@ pub struct TextDisplayItem {
     pub glyph_options: Option<GlyphOptions>,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Hash, Eq, PartialEq, PartialOrd, Ord, Serialize)]
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct GlyphOptions {
     // These are currently only used on windows for dwrite fonts.
     pub use_embedded_bitmap: bool,
     pub force_gdi_rendering: bool,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct WebGLDisplayItem {
     pub context_id: WebGLContextId,
 }
 
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct NormalBorder {
     pub left: BorderSide,
     pub right: BorderSide,
@@ -86,7 +96,8 @@ pub struct NormalBorder {
 }
 
 #[repr(u32)]
-#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum RepeatMode {
     Stretch,
     Repeat,
@@ -94,14 +105,16 @@ pub enum RepeatMode {
     Space,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct NinePatchDescriptor {
     pub width: u32,
     pub height: u32,
     pub slice: SideOffsets2D<u32>,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ImageBorder {
     pub image_key: ImageKey,
     pub patch: NinePatchDescriptor,
@@ -110,19 +123,22 @@ pub struct ImageBorder {
     pub repeat_vertical: RepeatMode,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct GradientBorder {
     pub gradient: Gradient,
     pub outset: SideOffsets2D<f32>,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct RadialGradientBorder {
     pub gradient: RadialGradient,
     pub outset: SideOffsets2D<f32>,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum BorderDetails {
     Normal(NormalBorder),
     Image(ImageBorder),
@@ -130,13 +146,15 @@ pub enum BorderDetails {
     RadialGradient(RadialGradientBorder),
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct BorderDisplayItem {
     pub widths: BorderWidths,
     pub details: BorderDetails,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct BorderRadius {
     pub top_left: LayoutSize,
     pub top_right: LayoutSize,
@@ -144,7 +162,8 @@ pub struct BorderRadius {
     pub bottom_right: LayoutSize,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct BorderWidths {
     pub left: f32,
     pub top: f32,
@@ -153,14 +172,16 @@ pub struct BorderWidths {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct BorderSide {
     pub color: ColorF,
     pub style: BorderStyle,
 }
 
 #[repr(u32)]
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum BorderStyle {
     None    = 0,
     Solid   = 1,
@@ -175,14 +196,16 @@ pub enum BorderStyle {
 }
 
 #[repr(u32)]
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum BoxShadowClipMode {
     None    = 0,
     Outset  = 1,
     Inset   = 2,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct BoxShadowDisplayItem {
     pub box_bounds: LayoutRect,
     pub offset: LayoutPoint,
@@ -193,14 +216,32 @@ pub struct BoxShadowDisplayItem {
     pub clip_mode: BoxShadowClipMode,
 }
 
+/// A ring of uniform width and style around a box, like a CSS outline. The
+/// rect of the item is the outer edge of the ring.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct OutlineDisplayItem {
+    /// The box the outline is drawn around.
+    pub box_bounds: LayoutRect,
+    pub width: f32,
+    /// The gap between the box and the inner edge of the ring, which
+    /// draws the ring inside the box when negative.
+    pub offset: f32,
+    pub side: BorderSide,
+    /// The radii of the outer edge of the ring.
+    pub radius: BorderRadius,
+}
+
 #[repr(u32)]
-#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Serialize, Deserialize, Ord, PartialOrd)]
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum ExtendMode {
     Clamp,
     Repeat,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct Gradient {
     pub start_point: LayoutPoint,
     pub end_point: LayoutPoint,
@@ -208,20 +249,23 @@ pub struct Gradient {
     pub extend_mode: ExtendMode,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct GradientDisplayItem {
     pub gradient: Gradient,
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct GradientStop {
     pub offset: f32,
     pub color: ColorF,
 }
 known_heap_size!(0, GradientStop);
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct RadialGradient {
     pub start_center: LayoutPoint,
     pub start_radius: f32,
@@ -232,17 +276,20 @@ pub struct RadialGradient {
     pub extend_mode: ExtendMode,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct RadialGradientDisplayItem {
     pub gradient: RadialGradient,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct PushStackingContextDisplayItem {
     pub stacking_context: StackingContext,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct StackingContext {
     pub scroll_policy: ScrollPolicy,
     pub z_index: i32,
@@ -250,10 +297,24 @@ pub struct StackingContext {
     pub perspective: Option<LayoutTransform>,
     pub mix_blend_mode: MixBlendMode,
     pub filters: ItemRange,
+    /// When set, the contents of the stacking context are read back after
+    /// each frame that draws them, see
+    /// `DisplayListBuilder::push_snapshot_stacking_context`.
+    pub snapshot: Option<SnapshotId>,
+    /// When set, the stacking context is drawn as an isolated group, so
+    /// the blend modes of its descendants blend against its contents
+    /// only, see `DisplayListBuilder::push_isolated_stacking_context`.
+    pub isolate: bool,
 }
 
+/// Identifies the snapshots of a stacking context, chosen by the embedder.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub struct SnapshotId(pub u32);
+
 #[repr(u32)]
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum ScrollPolicy {
     Scrollable  = 0,
     Fixed       = 1,
@@ -262,7 +323,8 @@ pub enum ScrollPolicy {
 known_heap_size!(0, ScrollPolicy);
 
 #[repr(u32)]
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum MixBlendMode {
     Normal      = 0,
     Multiply    = 1,
@@ -282,7 +344,8 @@ pub enum MixBlendMode {
     Luminosity  = 15,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum FilterOp {
     Blur(Au),
     Brightness(f32),
@@ -295,29 +358,39 @@ pub enum FilterOp {
     Sepia(f32),
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct IframeDisplayItem {
     pub pipeline_id: PipelineId,
 }
 
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ImageDisplayItem {
     pub image_key: ImageKey,
     pub stretch_size: LayoutSize,
     pub tile_spacing: LayoutSize,
     pub image_rendering: ImageRendering,
+    /// The color A8 images are drawn in, with the image as its coverage.
+    /// Other formats ignore it.
+    pub color: ColorF,
 }
 
 #[repr(u32)]
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum ImageRendering {
+    /// Smoothed with a linear filter when scaled.
     Auto        = 0,
+    /// Sampled with the nearest texel, like `Pixelated`.
     CrispEdges  = 1,
+    /// Sampled with the nearest texel.
     Pixelated   = 2,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct YuvImageDisplayItem {
     pub y_image_key: ImageKey,
     pub u_image_key: ImageKey,
@@ -326,27 +399,32 @@ pub struct YuvImageDisplayItem {
 }
 
 #[repr(u32)]
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum YuvColorSpace {
     Rec601 = 1, // The values must match the ones in prim_shared.glsl
     Rec709 = 2,
+    Rec2020 = 3,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ImageMask {
     pub image: ImageKey,
     pub rect: LayoutRect,
     pub repeat: bool,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ClipRegion {
     pub main: LayoutRect,
     pub complex: ItemRange,
     pub image_mask: Option<ImageMask>,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ComplexClipRegion {
     /// The boundaries of the rectangle.
     pub rect: LayoutRect,
@@ -370,6 +448,8 @@ impl StackingContext {
             perspective: perspective,
             mix_blend_mode: mix_blend_mode,
             filters: auxiliary_lists_builder.add_filters(&filters),
+            snapshot: None,
+            isolate: false,
         }
     }
 }
@@ -474,6 +554,10 @@ impl ColorF {
         }
     }
 
+    pub fn premultiplied(&self) -> ColorF {
+        self.scale_rgb(self.a)
+    }
+
     pub fn to_array(&self) -> [f32; 4] {
         [self.r, self.g, self.b, self.a]
     }
@@ -507,7 +591,8 @@ impl ComplexClipRegion {
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum ScrollLayerId {
     Clip(u64, PipelineId),
     ClipExternalId(u64, PipelineId),
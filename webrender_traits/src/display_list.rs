@@ -3,19 +3,25 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use app_units::Au;
+use std::collections::HashSet;
+use std::fmt;
 use std::mem;
 use std::slice;
-use {BorderDetails, BorderDisplayItem, BorderWidths, BoxShadowClipMode, BoxShadowDisplayItem};
+use {BorderDetails, BorderDisplayItem, BorderRadius, BorderSide, BorderWidths};
+use {BoxShadowClipMode, BoxShadowDisplayItem};
 use {ClipDisplayItem, ClipRegion, ColorF, ComplexClipRegion, DisplayItem, ExtendMode, FilterOp};
 use {FontKey, GlyphInstance, GlyphOptions, Gradient, GradientDisplayItem, GradientStop};
 use {IframeDisplayItem, ImageDisplayItem, ImageKey, ImageMask, ImageRendering, ItemRange};
 use {LayoutPoint, LayoutRect, LayoutSize, LayoutTransform, MixBlendMode, PipelineId};
+use OutlineDisplayItem;
 use {PropertyBinding, PushStackingContextDisplayItem, RadialGradient, RadialGradientDisplayItem};
-use {RectangleDisplayItem, ScrollLayerId, ScrollPolicy, SpecificDisplayItem, StackingContext};
+use {RectangleDisplayItem, ScrollLayerId, ScrollPolicy, SnapshotId, SpecificDisplayItem};
+use StackingContext;
 use {TextDisplayItem, WebGLContextId, WebGLDisplayItem, YuvColorSpace};
 use YuvImageDisplayItem;
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct AuxiliaryLists {
     /// The concatenation of: gradient stops, complex clip regions, filters, and glyph instances,
     /// in that order.
@@ -28,7 +34,8 @@ pub struct AuxiliaryLists {
 /// Auxiliary lists consist of some number of gradient stops, complex clip regions, filters, and
 /// glyph instances, in that order.
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct AuxiliaryListsDescriptor {
     gradient_stops_size: usize,
     complex_clip_regions_size: usize,
@@ -37,7 +44,8 @@ pub struct AuxiliaryListsDescriptor {
 }
 
 /// A display list.
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct BuiltDisplayList {
     data: Vec<u8>,
     descriptor: BuiltDisplayListDescriptor,
@@ -48,7 +56,8 @@ pub struct BuiltDisplayList {
 /// A display list consists of some number of display list items, followed by a number of display
 /// items.
 #[repr(C)]
-#[derive(Copy, Clone, Deserialize, Serialize)]
+#[derive(Copy, Clone)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct BuiltDisplayListDescriptor {
     /// The size in bytes of the display list items in this display list.
     display_list_items_size: usize,
@@ -92,6 +101,186 @@ impl BuiltDisplayList {
         }
     }
 
+    /// Checks that the display list is well formed for `pipeline_id`: the
+    /// data matches the descriptor, stacking contexts are balanced, every
+    /// clip is defined before it's used, all rects have finite,
+    /// non-negative sizes, and the ranges items take from
+    /// `auxiliary_lists` are in bounds.
+    pub fn validate(&self,
+                    pipeline_id: PipelineId,
+                    auxiliary_lists: &AuxiliaryLists)
+                    -> Result<(), DisplayListError> {
+        let items_size = self.descriptor.display_list_items_size;
+        if items_size > self.data.len() || items_size % mem::size_of::<DisplayItem>() != 0 {
+            return Err(DisplayListError::InvalidDataSize {
+                data_size: self.data.len(),
+                descriptor_size: items_size,
+            });
+        }
+        let auxiliary_size = auxiliary_lists.descriptor.size();
+        if auxiliary_size > auxiliary_lists.data.len() {
+            return Err(DisplayListError::InvalidAuxiliaryDataSize {
+                data_size: auxiliary_lists.data.len(),
+                descriptor_size: auxiliary_size,
+            });
+        }
+
+        let auxiliary_descriptor = &auxiliary_lists.descriptor;
+        let gradient_stop_count =
+            auxiliary_descriptor.gradient_stops_size / mem::size_of::<GradientStop>();
+        let complex_clip_region_count =
+            auxiliary_descriptor.complex_clip_regions_size / mem::size_of::<ComplexClipRegion>();
+        let filter_count = auxiliary_descriptor.filters_size / mem::size_of::<FilterOp>();
+        let glyph_instance_count =
+            auxiliary_descriptor.glyph_instances_size / mem::size_of::<GlyphInstance>();
+
+        let mut defined_clips = HashSet::new();
+        defined_clips.insert(ScrollLayerId::root_scroll_layer(pipeline_id));
+        defined_clips.insert(ScrollLayerId::root_reference_frame(pipeline_id));
+        let mut stacking_context_depth = 0;
+
+        for (index, item) in self.all_display_items().iter().enumerate() {
+            if !defined_clips.contains(&item.scroll_layer_id) {
+                return Err(DisplayListError::UndefinedClip(index, item.scroll_layer_id));
+            }
+            if !is_valid_rect(&item.rect) {
+                return Err(DisplayListError::InvalidRect(index, item.rect));
+            }
+            if !is_valid_rect(&item.clip.main) {
+                return Err(DisplayListError::InvalidRect(index, item.clip.main));
+            }
+            try!(check_auxiliary_range(index, &item.clip.complex, complex_clip_region_count));
+
+            match item.item {
+                SpecificDisplayItem::Text(ref info) => {
+                    try!(check_auxiliary_range(index, &info.glyphs, glyph_instance_count));
+                }
+                SpecificDisplayItem::Gradient(ref info) => {
+                    try!(check_auxiliary_range(index, &info.gradient.stops, gradient_stop_count));
+                }
+                SpecificDisplayItem::RadialGradient(ref info) => {
+                    try!(check_auxiliary_range(index, &info.gradient.stops, gradient_stop_count));
+                }
+                SpecificDisplayItem::Border(ref info) => {
+                    match info.details {
+                        BorderDetails::Gradient(ref border) => {
+                            try!(check_auxiliary_range(index,
+                                                       &border.gradient.stops,
+                                                       gradient_stop_count));
+                        }
+                        BorderDetails::RadialGradient(ref border) => {
+                            try!(check_auxiliary_range(index,
+                                                       &border.gradient.stops,
+                                                       gradient_stop_count));
+                        }
+                        BorderDetails::Normal(..) | BorderDetails::Image(..) => {}
+                    }
+                }
+                SpecificDisplayItem::Clip(ref info) => {
+                    if !defined_clips.contains(&info.parent_id) {
+                        return Err(DisplayListError::UndefinedClip(index, info.parent_id));
+                    }
+                    if !is_valid_size(&info.content_size) {
+                        return Err(DisplayListError::InvalidContentSize(index, info.content_size));
+                    }
+                    defined_clips.insert(info.id);
+                }
+                SpecificDisplayItem::PushStackingContext(ref info) => {
+                    try!(check_auxiliary_range(index,
+                                               &info.stacking_context.filters,
+                                               filter_count));
+                    stacking_context_depth += 1;
+                }
+                SpecificDisplayItem::PopStackingContext => {
+                    if stacking_context_depth == 0 {
+                        return Err(DisplayListError::UnbalancedPopStackingContext(index));
+                    }
+                    stacking_context_depth -= 1;
+                }
+                _ => {}
+            }
+        }
+
+        if stacking_context_depth != 0 {
+            return Err(DisplayListError::UnclosedStackingContexts(stacking_context_depth));
+        }
+
+        Ok(())
+    }
+}
+
+fn check_auxiliary_range(index: usize, range: &ItemRange, count: usize)
+                         -> Result<(), DisplayListError> {
+    match range.start.checked_add(range.length) {
+        Some(end) if end <= count => Ok(()),
+        _ => Err(DisplayListError::InvalidAuxiliaryRange(index, *range)),
+    }
+}
+
+fn is_valid_size(size: &LayoutSize) -> bool {
+    size.width.is_finite() && size.height.is_finite() &&
+    size.width >= 0.0 && size.height >= 0.0
+}
+
+fn is_valid_rect(rect: &LayoutRect) -> bool {
+    rect.origin.x.is_finite() && rect.origin.y.is_finite() && is_valid_size(&rect.size)
+}
+
+/// Why `BuiltDisplayList::validate` rejected a display list. Item indices
+/// count from the start of the list.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DisplayListError {
+    /// The item data doesn't match the size in the descriptor.
+    InvalidDataSize { data_size: usize, descriptor_size: usize },
+    /// The auxiliary lists are smaller than their descriptor says.
+    InvalidAuxiliaryDataSize { data_size: usize, descriptor_size: usize },
+    /// A stacking context was popped without a matching push.
+    UnbalancedPopStackingContext(usize),
+    /// The list ended with this many stacking contexts still pushed.
+    UnclosedStackingContexts(usize),
+    /// An item refers to a clip that wasn't defined earlier in the list.
+    UndefinedClip(usize, ScrollLayerId),
+    /// An item rect or clip rect is negative or not finite.
+    InvalidRect(usize, LayoutRect),
+    /// A clip's content size is negative or not finite.
+    InvalidContentSize(usize, LayoutSize),
+    /// An item refers to glyphs, gradient stops, complex clips or filters
+    /// past the end of the auxiliary lists.
+    InvalidAuxiliaryRange(usize, ItemRange),
+}
+
+impl fmt::Display for DisplayListError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DisplayListError::InvalidDataSize { data_size, descriptor_size } => {
+                write!(f, "display list descriptor expects {} bytes of items, but {} bytes were sent",
+                       descriptor_size, data_size)
+            }
+            DisplayListError::InvalidAuxiliaryDataSize { data_size, descriptor_size } => {
+                write!(f, "auxiliary lists descriptor expects {} bytes, but {} bytes were sent",
+                       descriptor_size, data_size)
+            }
+            DisplayListError::UnbalancedPopStackingContext(index) => {
+                write!(f, "item {} pops a stacking context that was never pushed", index)
+            }
+            DisplayListError::UnclosedStackingContexts(count) => {
+                write!(f, "{} stacking contexts are never popped", count)
+            }
+            DisplayListError::UndefinedClip(index, id) => {
+                write!(f, "item {} refers to undefined clip {:?}", index, id)
+            }
+            DisplayListError::InvalidRect(index, rect) => {
+                write!(f, "item {} has an invalid rect {:?}", index, rect)
+            }
+            DisplayListError::InvalidContentSize(index, size) => {
+                write!(f, "clip item {} has an invalid content size {:?}", index, size)
+            }
+            DisplayListError::InvalidAuxiliaryRange(index, range) => {
+                write!(f, "item {} refers to auxiliary items {}..{}, which are out of range",
+                       index, range.start, range.start.saturating_add(range.length))
+            }
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -158,11 +347,31 @@ impl DisplayListBuilder {
                       tile_spacing: LayoutSize,
                       image_rendering: ImageRendering,
                       key: ImageKey) {
+        self.push_image_with_color(rect,
+                                   clip,
+                                   stretch_size,
+                                   tile_spacing,
+                                   image_rendering,
+                                   key,
+                                   ColorF::new(1.0, 1.0, 1.0, 1.0));
+    }
+
+    /// Pushes an image that is drawn in `color` when it is an A8 image, for
+    /// masks, signed distance fields and monochrome icons.
+    pub fn push_image_with_color(&mut self,
+                                 rect: LayoutRect,
+                                 clip: ClipRegion,
+                                 stretch_size: LayoutSize,
+                                 tile_spacing: LayoutSize,
+                                 image_rendering: ImageRendering,
+                                 key: ImageKey,
+                                 color: ColorF) {
         let item = SpecificDisplayItem::Image(ImageDisplayItem {
             image_key: key,
             stretch_size: stretch_size,
             tile_spacing: tile_spacing,
             image_rendering: image_rendering,
+            color: color,
         });
 
         self.push_item(item, rect, clip);
@@ -343,13 +552,21 @@ impl DisplayListBuilder {
                                           end_center: LayoutPoint,
                                           end_radius: f32,
                                           ratio_xy: f32,
-                                          stops: Vec<GradientStop>,
+                                          mut stops: Vec<GradientStop>,
                                           extend_mode: ExtendMode) -> RadialGradient {
+        let (start_offset,
+             end_offset) = DisplayListBuilder::normalize_stops(&mut stops, extend_mode);
+
+        // The gradient is drawn on the circles interpolated between the start and
+        // end circles, so the normalized ones are found along the same line.
+        let center_delta = end_center - start_center;
+        let radius_delta = end_radius - start_radius;
+
         RadialGradient {
-            start_center: start_center,
-            start_radius: start_radius,
-            end_center: end_center,
-            end_radius: end_radius,
+            start_center: start_center + center_delta * start_offset,
+            start_radius: start_radius + radius_delta * start_offset,
+            end_center: start_center + center_delta * end_offset,
+            end_radius: start_radius + radius_delta * end_offset,
             ratio_xy: ratio_xy,
             stops: self.auxiliary_lists_builder.add_gradient_stops(&stops),
             extend_mode: extend_mode,
@@ -392,6 +609,26 @@ impl DisplayListBuilder {
         self.push_item(item, rect, clip);
     }
 
+    /// Draws an outline around `box_bounds`, `offset` away from it.
+    pub fn push_outline(&mut self,
+                        box_bounds: LayoutRect,
+                        clip: ClipRegion,
+                        width: f32,
+                        offset: f32,
+                        side: BorderSide,
+                        radius: BorderRadius) {
+        let rect = box_bounds.inflate(offset + width, offset + width);
+        let item = SpecificDisplayItem::Outline(OutlineDisplayItem {
+            box_bounds: box_bounds,
+            width: width,
+            offset: offset,
+            side: side,
+            radius: radius,
+        });
+
+        self.push_item(item, rect, clip);
+    }
+
     pub fn push_gradient(&mut self,
                          rect: LayoutRect,
                          clip: ClipRegion,
@@ -422,15 +659,70 @@ impl DisplayListBuilder {
                                  perspective: Option<LayoutTransform>,
                                  mix_blend_mode: MixBlendMode,
                                  filters: Vec<FilterOp>) {
+        let stacking_context = StackingContext::new(scroll_policy,
+                                                    z_index,
+                                                    transform,
+                                                    perspective,
+                                                    mix_blend_mode,
+                                                    filters,
+                                                    &mut self.auxiliary_lists_builder);
+        self.push_stacking_context_item(stacking_context, bounds);
+    }
+
+    /// Pushes a stacking context whose contents are read back after each
+    /// frame that draws them, e.g. for element screenshots, drag images or
+    /// tab previews. The pixels cover the visible part of the stacking
+    /// context, before its own filters and blend mode are applied, and are
+    /// handed to the renderer's `ReadbackHandler` as `snapshot_id`.
+    pub fn push_snapshot_stacking_context(&mut self,
+                                          scroll_policy: ScrollPolicy,
+                                          bounds: LayoutRect,
+                                          z_index: i32,
+                                          transform: Option<PropertyBinding<LayoutTransform>>,
+                                          perspective: Option<LayoutTransform>,
+                                          mix_blend_mode: MixBlendMode,
+                                          filters: Vec<FilterOp>,
+                                          snapshot_id: SnapshotId) {
+        let mut stacking_context = StackingContext::new(scroll_policy,
+                                                        z_index,
+                                                        transform,
+                                                        perspective,
+                                                        mix_blend_mode,
+                                                        filters,
+                                                        &mut self.auxiliary_lists_builder);
+        stacking_context.snapshot = Some(snapshot_id);
+        self.push_stacking_context_item(stacking_context, bounds);
+    }
+
+    /// Pushes a stacking context with `isolation: isolate`. Its contents
+    /// are drawn on a transparent background and then composited into
+    /// the parent, so descendants with a `mix_blend_mode` blend against
+    /// the other contents of this stacking context rather than the page
+    /// behind it.
+    pub fn push_isolated_stacking_context(&mut self,
+                                          scroll_policy: ScrollPolicy,
+                                          bounds: LayoutRect,
+                                          z_index: i32,
+                                          transform: Option<PropertyBinding<LayoutTransform>>,
+                                          perspective: Option<LayoutTransform>,
+                                          mix_blend_mode: MixBlendMode,
+                                          filters: Vec<FilterOp>) {
+        let mut stacking_context = StackingContext::new(scroll_policy,
+                                                        z_index,
+                                                        transform,
+                                                        perspective,
+                                                        mix_blend_mode,
+                                                        filters,
+                                                        &mut self.auxiliary_lists_builder);
+        stacking_context.isolate = true;
+        self.push_stacking_context_item(stacking_context, bounds);
+    }
+
+    fn push_stacking_context_item(&mut self,
+                                  stacking_context: StackingContext,
+                                  bounds: LayoutRect) {
         let item = SpecificDisplayItem::PushStackingContext(PushStackingContextDisplayItem {
-            stacking_context: StackingContext {
-                scroll_policy: scroll_policy,
-                z_index: z_index,
-                transform: transform,
-                perspective: perspective,
-                mix_blend_mode: mix_blend_mode,
-                filters: self.auxiliary_lists_builder.add_filters(&filters),
-            }
+            stacking_context: stacking_context,
         });
 
         self.push_item(item, bounds, ClipRegion::simple(&LayoutRect::zero()));
@@ -739,3 +1031,76 @@ unsafe fn convert_vec_blob_to_pod<T>(mut data: Vec<u8>) -> Vec<T> where T: Copy
     mem::forget(data);
     v
 }
+
+#[cfg(test)]
+fn test_rect() -> LayoutRect {
+    LayoutRect::new(LayoutPoint::zero(), LayoutSize::new(100.0, 100.0))
+}
+
+#[cfg(test)]
+fn push_test_stacking_context(builder: &mut DisplayListBuilder) {
+    builder.push_stacking_context(ScrollPolicy::Scrollable,
+                                  test_rect(),
+                                  0,
+                                  None,
+                                  None,
+                                  MixBlendMode::Normal,
+                                  Vec::new());
+}
+
+#[cfg(test)]
+fn push_test_text(builder: &mut DisplayListBuilder) {
+    let glyphs = vec![GlyphInstance { index: 1, point: LayoutPoint::new(10.0, 20.0).to_untyped() }];
+    builder.push_text(test_rect(),
+                      ClipRegion::simple(&test_rect()),
+                      glyphs,
+                      FontKey::new(0, 1),
+                      ColorF::new(0.0, 0.0, 0.0, 1.0),
+                      Au::from_px(12),
+                      Au(0),
+                      None);
+}
+
+#[test]
+fn valid_display_list_passes_validation() {
+    let pipeline_id = PipelineId(0, 0);
+    let mut builder = DisplayListBuilder::new(pipeline_id);
+    push_test_stacking_context(&mut builder);
+    builder.push_rect(test_rect(), ClipRegion::simple(&test_rect()), ColorF::new(1.0, 0.0, 0.0, 1.0));
+    push_test_text(&mut builder);
+    builder.pop_stacking_context();
+
+    let (_, display_list, auxiliary_lists) = builder.finalize();
+    assert_eq!(display_list.validate(pipeline_id, &auxiliary_lists), Ok(()));
+}
+
+#[test]
+fn unbalanced_stacking_contexts_fail_validation() {
+    let pipeline_id = PipelineId(0, 0);
+    let mut builder = DisplayListBuilder::new(pipeline_id);
+    builder.pop_stacking_context();
+    let (_, display_list, auxiliary_lists) = builder.finalize();
+    assert_eq!(display_list.validate(pipeline_id, &auxiliary_lists),
+               Err(DisplayListError::UnbalancedPopStackingContext(0)));
+
+    let mut builder = DisplayListBuilder::new(pipeline_id);
+    push_test_stacking_context(&mut builder);
+    push_test_stacking_context(&mut builder);
+    builder.pop_stacking_context();
+    let (_, display_list, auxiliary_lists) = builder.finalize();
+    assert_eq!(display_list.validate(pipeline_id, &auxiliary_lists),
+               Err(DisplayListError::UnclosedStackingContexts(1)));
+}
+
+#[test]
+fn out_of_range_auxiliary_items_fail_validation() {
+    let pipeline_id = PipelineId(0, 0);
+    let mut builder = DisplayListBuilder::new(pipeline_id);
+    push_test_text(&mut builder);
+    let (_, display_list, _) = builder.finalize();
+
+    // The glyphs of the text item aren't in these lists.
+    let (_, _, auxiliary_lists) = DisplayListBuilder::new(pipeline_id).finalize();
+    assert_eq!(display_list.validate(pipeline_id, &auxiliary_lists),
+               Err(DisplayListError::InvalidAuxiliaryRange(0, ItemRange { start: 0, length: 1 })));
+}
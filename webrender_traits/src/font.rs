@@ -4,6 +4,7 @@
 
 use app_units::Au;
 use euclid::Point2D;
+use std::cmp;
 use {ColorU, ColorF};
 
 #[cfg(target_os = "macos")] use core_graphics::font::CGFont;
@@ -15,14 +16,16 @@ pub type NativeFontHandle = CGFont;
 
 /// Native fonts are not used on Linux; all fonts are raw.
 #[cfg(not(any(target_os = "macos", target_os = "windows")))]
-#[cfg_attr(not(any(target_os = "macos", target_os = "windows")), derive(Clone, Serialize, Deserialize))]
+#[derive(Clone)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct NativeFontHandle;
 
 #[cfg(target_os = "windows")]
 pub type NativeFontHandle = FontDescriptor;
 
 #[repr(C)]
-#[derive(Copy, Clone, Deserialize, Serialize, Debug)]
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct GlyphDimensions {
     pub left: i32,
     pub top: i32,
@@ -31,7 +34,8 @@ pub struct GlyphDimensions {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize, Ord, PartialOrd)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct FontKey(pub u32, pub u32);
 
 impl FontKey {
@@ -40,7 +44,8 @@ impl FontKey {
     }
 }
 
-#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Serialize, Deserialize, Ord, PartialOrd)]
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum FontRenderMode {
     Mono,
     Alpha,
@@ -50,26 +55,67 @@ pub enum FontRenderMode {
 impl FontRenderMode {
     // Skia quantizes subpixel offets into 1/4 increments.
     // Given the absolute position, return the quantized increment
-    fn subpixel_quantize_offset(&self, pos: f32) -> SubpixelOffset {
+    fn subpixel_quantize_offset(&self,
+                                pos: f32,
+                                quantization: SubpixelQuantization) -> SubpixelOffset {
         if *self != FontRenderMode::Subpixel {
             return SubpixelOffset::Zero;
         }
 
-        const SUBPIXEL_ROUNDING :f32 = 0.125; // Skia chosen value.
-        let fraction = (pos + SUBPIXEL_ROUNDING).fract();
+        // Positions are rounded to the nearest bucket, like Skia does
+        // with its 1/8 rounding for quarters.
+        let buckets = quantization.buckets();
+        let fraction = (pos + 0.5 / buckets as f32).fract();
+        if !(fraction >= 0.0 && fraction <= 1.0) {
+            panic!("Should only be given the fractional part");
+        }
+
+        // A fraction on the boundary of two buckets goes to the lower one.
+        let bucket = cmp::max((fraction * buckets as f32).ceil() as u32, 1) - 1;
+        match bucket * (4 / buckets) {
+            0 => SubpixelOffset::Zero,
+            1 => SubpixelOffset::Quarter,
+            2 => SubpixelOffset::Half,
+            _ => SubpixelOffset::ThreeQuarters,
+        }
+    }
+}
+
+/// How many subpixel positions per pixel glyphs are rasterized at in
+/// subpixel mode. Every position a glyph is drawn at needs a copy of it in
+/// the glyph cache, so fewer positions save cache space, at the cost of
+/// placing glyphs up to half a bucket from where they belong.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
+pub enum SubpixelQuantization {
+    /// Glyphs snap to whole pixels.
+    Pixels,
+    /// Glyphs are placed at half pixels.
+    Halves,
+    /// Glyphs are placed at quarter pixels.
+    Quarters,
+}
 
-        match fraction {
-            0.0...0.25 => SubpixelOffset::Zero,
-            0.25...0.5 => SubpixelOffset::Quarter,
-            0.5...0.75 => SubpixelOffset::Half,
-            0.75...1.0 => SubpixelOffset::ThreeQuarters,
-            _ => panic!("Should only be given the fractional part"),
+impl SubpixelQuantization {
+    /// The number of positions per pixel along each axis.
+    pub fn buckets(&self) -> u32 {
+        match *self {
+            SubpixelQuantization::Pixels => 1,
+            SubpixelQuantization::Halves => 2,
+            SubpixelQuantization::Quarters => 4,
         }
     }
 }
 
+impl Default for SubpixelQuantization {
+    fn default() -> SubpixelQuantization {
+        SubpixelQuantization::Quarters
+    }
+}
+
 #[repr(u8)]
-#[derive(Hash, Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[derive(Hash, Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum SubpixelOffset {
     Zero            = 0,
     Quarter         = 1,
@@ -88,7 +134,8 @@ impl Into<f64> for SubpixelOffset {
     }
 }
 
-#[derive(Clone, Hash, PartialEq, Eq, Debug, Deserialize, Serialize, Ord, PartialOrd)]
+#[derive(Clone, Hash, PartialEq, Eq, Debug, Ord, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct SubpixelPoint {
     pub x: SubpixelOffset,
     pub y: SubpixelOffset,
@@ -98,8 +145,8 @@ impl SubpixelPoint {
     pub fn new(point: Point2D<f32>,
                render_mode: FontRenderMode) -> SubpixelPoint {
         SubpixelPoint {
-            x: render_mode.subpixel_quantize_offset(point.x),
-            y: render_mode.subpixel_quantize_offset(point.y),
+            x: render_mode.subpixel_quantize_offset(point.x, SubpixelQuantization::default()),
+            y: render_mode.subpixel_quantize_offset(point.y, SubpixelQuantization::default()),
         }
     }
 
@@ -108,12 +155,20 @@ impl SubpixelPoint {
     }
 
     pub fn set_offset(&mut self, point: Point2D<f32>, render_mode: FontRenderMode) {
-        self.x = render_mode.subpixel_quantize_offset(point.x);
-        self.y = render_mode.subpixel_quantize_offset(point.y);
+        self.set_quantized_offset(point, render_mode, SubpixelQuantization::default());
+    }
+
+    pub fn set_quantized_offset(&mut self,
+                                point: Point2D<f32>,
+                                render_mode: FontRenderMode,
+                                quantization: SubpixelQuantization) {
+        self.x = render_mode.subpixel_quantize_offset(point.x, quantization);
+        self.y = render_mode.subpixel_quantize_offset(point.y, quantization);
     }
 }
 
-#[derive(Clone, Hash, PartialEq, Eq, Debug, Deserialize, Serialize, Ord, PartialOrd)]
+#[derive(Clone, Hash, PartialEq, Eq, Debug, Ord, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct GlyphKey {
     pub font_key: FontKey,
     // The font size is in *device* pixels, not logical pixels.
@@ -145,7 +200,8 @@ impl GlyphKey {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct GlyphInstance {
     pub index: u32,
     pub point: Point2D<f32>,
@@ -3,10 +3,11 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use std::sync::Arc;
-use DeviceUintRect;
+use {ColorSpace, DeviceUintRect};
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ImageKey(pub u32, pub u32);
 
 impl ImageKey {
@@ -18,11 +19,13 @@ impl ImageKey {
 /// An arbitrary identifier for an external image provided by the
 /// application. It must be a unique identifier for each external
 /// image.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ExternalImageId(pub u64);
 
 #[repr(u32)]
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum ImageFormat {
     Invalid  = 0,
     A8       = 1,
@@ -43,7 +46,8 @@ impl ImageFormat {
     }
 }
 
-#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ImageDescriptor {
     pub format: ImageFormat,
     pub width: u32,
@@ -51,6 +55,9 @@ pub struct ImageDescriptor {
     pub stride: Option<u32>,
     pub offset: u32,
     pub is_opaque: bool,
+    /// The color space of RGBA8 and RGB8 images, which are converted to
+    /// the color space that frames are drawn in when they differ.
+    pub color_space: ColorSpace,
 }
 
 impl ImageDescriptor {
@@ -62,6 +69,7 @@ impl ImageDescriptor {
             stride: None,
             offset: 0,
             is_opaque: is_opaque,
+            color_space: ColorSpace::Srgb,
         }
     }
 
@@ -70,20 +78,23 @@ impl ImageDescriptor {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum ExternalImageType {
     Texture2DHandle,    // gl TEXTURE_2D handle
     TextureRectHandle,  // gl TEXTURE_RECT handle
     ExternalBuffer,
 }
 
-#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub struct ExternalImageData {
     pub id: ExternalImageId,
     pub image_type: ExternalImageType,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serialization", derive(Deserialize, Serialize))]
 pub enum ImageData {
     Raw(Arc<Vec<u8>>),
     Blob(Arc<BlobImageData>),
@@ -16,7 +16,9 @@ extern crate heapsize;
 extern crate ipc_channel;
 #[cfg(feature = "webgl")]
 extern crate offscreen_gl_context;
+#[cfg(feature = "serialization")]
 extern crate serde;
+#[cfg(feature = "serialization")]
 #[macro_use]
 extern crate serde_derive;
 
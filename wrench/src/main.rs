@@ -312,6 +312,11 @@ fn main() {
         }
     }).unwrap_or(DeviceUintSize::new(1920, 1080));
     let is_headless = args.is_present("headless");
+    let edge_aa_mode = if args.is_present("high_quality_aa") {
+        webrender::EdgeAaMode::HighQuality
+    } else {
+        webrender::EdgeAaMode::Fast
+    };
 
     let mut window = make_window(size,
                                  dp_ratio,
@@ -325,9 +330,18 @@ fn main() {
                                  size,
                                  args.is_present("rebuild"),
                                  args.is_present("subpixel-aa"),
+                                 edge_aa_mode,
+                                 args.is_present("software"),
+                                 args.is_present("dump_targets"),
                                  args.is_present("debug"),
                                  args.is_present("verbose"));
 
+    if let Some(dump_path) = args.value_of("dump_targets") {
+        wrench.renderer.set_render_target_dump_path(PathBuf::from(dump_path));
+        let flags = wrench.renderer.get_debug_flags();
+        wrench.renderer.set_debug_flags(flags | webrender::RENDER_TARGET_DUMP);
+    }
+
     let mut thing =
         if let Some(subargs) = args.subcommand_matches("show") {
             Box::new(YamlFrameReader::new_from_args(subargs)) as Box<WrenchThing>
@@ -510,6 +524,10 @@ fn main() {
                             profiler = !profiler;
                             wrench.renderer.set_profiler_enabled(profiler);
                         },
+                        VirtualKeyCode::D => {
+                            let flags = wrench.renderer.get_debug_flags();
+                            wrench.renderer.set_debug_flags(flags ^ webrender::RENDER_TARGET_DUMP);
+                        },
                         VirtualKeyCode::L => {
                             do_loop = !do_loop;
                         },
@@ -15,6 +15,7 @@ use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::{channel, Receiver, Sender};
+use webrender::EdgeAaMode;
 use webrender_traits::*;
 use wrench::{Wrench, WrenchThing};
 use yaml_frame_reader::YamlFrameReader;
@@ -29,6 +30,7 @@ pub struct Reftest {
     reference: PathBuf,
     max_difference: usize,
     num_differences: usize,
+    high_quality_aa: bool,
 }
 
 struct ReftestImage {
@@ -127,23 +129,35 @@ impl ReftestManifest {
 
                     reftests.append(&mut ReftestManifest::new(include.as_path()).reftests);
                 }
-                item_str => {
-                    // If the first item is "fuzzy(<val>,<count>)" the positions of the operator
-                    // and paths in the array are offset.
+                _ => {
+                    // Any items before the operator modify the test, and offset the positions
+                    // of the operator and paths in the array:
+                    // "fuzzy(<val>,<count>)" allows that many differing pixels, and
+                    // "high-quality-aa" only runs the test with --high-quality-aa.
                     // TODO: This is simple but not great because it does not support having spaces
                     // in the fuzzy syntax, like between the arguments.
-                    let (max, count, offset) =  if item_str.starts_with("fuzzy(") {
-                        let (_, args) = parse_function(item_str);
-                        (args[0].parse().unwrap(),  args[1].parse().unwrap(), 1)
-                    } else {
-                        (0, 0, 0)
-                    };
+                    let (mut max, mut count, mut high_quality_aa) = (0, 0, false);
+                    let mut offset = 0;
+                    while parse_operator(items[offset]).is_none() {
+                        let item_str = items[offset];
+                        if item_str.starts_with("fuzzy(") {
+                            let (_, args) = parse_function(item_str);
+                            max = args[0].parse().unwrap();
+                            count = args[1].parse().unwrap();
+                        } else if item_str == "high-quality-aa" {
+                            high_quality_aa = true;
+                        } else {
+                            panic!("unexpected reftest modifier: {}", item_str);
+                        }
+                        offset += 1;
+                    }
                     reftests.push(Reftest {
-                        op: parse_operator(items[offset]).expect("unexpected match operator"),
+                        op: parse_operator(items[offset]).unwrap(),
                         test: dir.join(items[offset + 1]),
                         reference: dir.join(items[offset + 2]),
                         max_difference: max,
                         num_differences: count,
+                        high_quality_aa: high_quality_aa,
                     });
                 }
             };
@@ -206,6 +220,9 @@ impl<'a> ReftestHarness<'a> {
         let mut total_failing = 0;
 
         for t in reftests {
+            if t.high_quality_aa && self.wrench.edge_aa_mode != EdgeAaMode::HighQuality {
+                continue;
+            }
             if self.run_reftest(&t) {
                 total_passing += 1;
             } else {
@@ -133,6 +133,7 @@ pub struct Wrench {
 
     pub rebuild_display_lists: bool,
     pub verbose: bool,
+    pub edge_aa_mode: webrender::EdgeAaMode,
 
     pub frame_start_sender: chase_lev::Worker<time::SteadyTime>,
 }
@@ -145,6 +146,9 @@ impl Wrench {
                size: DeviceUintSize,
                do_rebuild: bool,
                subpixel_aa: bool,
+               edge_aa_mode: webrender::EdgeAaMode,
+               software: bool,
+               deterministic: bool,
                debug: bool,
                verbose: bool)
            -> Wrench
@@ -165,18 +169,29 @@ impl Wrench {
             }
         });
 
+        let base_opts = if software {
+            webrender::RendererOptions::software_profile()
+        } else {
+            webrender::RendererOptions::default()
+        };
         let opts = webrender::RendererOptions {
             device_pixel_ratio: dp_ratio,
             resource_override_path: shader_override_path,
             recorder: recorder,
             enable_subpixel_aa: subpixel_aa,
+            edge_aa_mode: edge_aa_mode,
             debug: debug,
             max_recorded_profiles: 16,
-            .. Default::default()
+            deterministic: deterministic,
+            single_threaded_workers: deterministic,
+            .. base_opts
         };
 
-        let (renderer, sender) = webrender::renderer::Renderer::new(window.clone_gl(), opts, size).unwrap();
+        let (renderer, sender) = webrender::renderer::Renderer::new(window.clone_gl(), opts).unwrap();
         let api = sender.create_api();
+        api.set_window_parameters(size,
+                                  DeviceUintRect::new(DeviceUintPoint::zero(), size),
+                                  dp_ratio);
 
         let proxy = window.create_window_proxy();
         // put an Awakened event into the queue to kick off the first frame
@@ -201,6 +216,7 @@ impl Wrench {
 
             rebuild_display_lists: do_rebuild,
             verbose: verbose,
+            edge_aa_mode: edge_aa_mode,
             device_pixel_ratio: dp_ratio,
 
             image_map: HashMap::new(),
@@ -354,6 +370,9 @@ impl Wrench {
         if dim != self.window_size {
             self.renderer.gl().viewport(0, 0, dim.width as i32, dim.height as i32);
             self.window_size = dim;
+            self.api.set_window_parameters(dim,
+                                           DeviceUintRect::new(DeviceUintPoint::zero(), dim),
+                                           self.device_pixel_ratio);
         }
     }
 
@@ -376,7 +395,7 @@ impl Wrench {
             self.api.scroll_layer_with_id(*offset, *id);
         }
 
-        self.api.generate_frame(None);
+        self.api.generate_frame(None, None);
     }
 
     pub fn get_frame_profiles(&mut self) -> (Vec<CpuProfile>, Vec<GpuProfile>) {
@@ -390,7 +409,7 @@ impl Wrench {
 
     pub fn refresh(&mut self) {
         self.begin_frame();
-        self.api.generate_frame(None);
+        self.api.generate_frame(None, None);
     }
 
     pub fn show_onscreen_help(&mut self) {
@@ -398,7 +417,8 @@ impl Wrench {
             "Esc, Q - Quit",
             "H - Toggle help",
             "R - Toggle recreating display items each frame",
-            "P - Toggle profiler"
+            "P - Toggle profiler",
+            "D - Toggle saving render targets"
         ];
 
         let color_and_offset = [ (*BLACK_COLOR, 2.0), (*WHITE_COLOR, 0.0) ];
@@ -11,7 +11,7 @@ use std::io::Read;
 use std::path::{Path, PathBuf};
 use webrender_traits::*;
 use wrench::{Wrench, WrenchThing, layout_simple_ascii};
-use yaml_helper::YamlHelper;
+use yaml_helper::{YamlHelper, string_to_border_style};
 use yaml_rust::{Yaml, YamlLoader};
 use {WHITE_COLOR, BLACK_COLOR, PLATFORM_DEFAULT_FACE_NAME};
 
@@ -283,20 +283,8 @@ impl YamlFrameReader {
                 "normal" => {
                     let colors = item["color"].as_vec_colorf().expect("borders must have color(s)");
                     let styles = item["style"].as_vec_string().expect("borders must have style(s)");
-                    let styles = styles.iter().map(|s| match s.as_str() {
-                        "none" => BorderStyle::None,
-                        "solid" => BorderStyle::Solid,
-                        "double" => BorderStyle::Double,
-                        "dotted" => BorderStyle::Dotted,
-                        "dashed" => BorderStyle::Dashed,
-                        "hidden" => BorderStyle::Hidden,
-                        "ridge" => BorderStyle::Ridge,
-                        "inset" => BorderStyle::Inset,
-                        "outset" => BorderStyle::Outset,
-                        "groove" => BorderStyle::Groove,
-                        s => {
-                            panic!("Unknown border style '{}'", s);
-                        }
+                    let styles = styles.iter().map(|s| {
+                        string_to_border_style(s).unwrap_or_else(|| panic!("Unknown border style '{}'", s))
                     }).collect::<Vec<BorderStyle>>();
                     let radius = item["radius"].as_border_radius().unwrap_or(BorderRadius::zero());
 
@@ -389,6 +377,23 @@ impl YamlFrameReader {
         }
     }
 
+    fn handle_outline(&mut self, wrench: &mut Wrench, clip_region: &ClipRegion, item: &Yaml) {
+        let bounds_key = if item["type"].is_badvalue() { "outline" } else { "bounds" };
+        let bounds = item[bounds_key].as_rect().expect("outline must have bounds");
+        let width = item["width"].as_force_f32().expect("outline must have width");
+        let offset = item["offset"].as_force_f32().unwrap_or(0.0);
+        let color = item["color"].as_colorf().unwrap_or(*BLACK_COLOR);
+        let style = item["style"].as_str().map(|s| {
+            string_to_border_style(s).unwrap_or_else(|| panic!("Unknown border style '{}'", s))
+        }).unwrap_or(BorderStyle::Solid);
+        let radius = item["radius"].as_border_radius().unwrap_or(BorderRadius::zero());
+
+        let outline_bounds = bounds.inflate(offset + width, offset + width);
+        let clip = self.to_clip_region(&item["clip"], &outline_bounds, wrench).unwrap_or(*clip_region);
+        self.builder().push_outline(bounds, clip, width, offset,
+                                    BorderSide { color: color, style: style }, radius);
+    }
+
     fn handle_box_shadow(&mut self, wrench: &mut Wrench, clip_region: &ClipRegion, item: &Yaml) {
         let bounds_key = if item["type"].is_badvalue() { "box-shadow" } else { "bounds" };
         let bounds = item[bounds_key].as_rect().expect("box shadow must have bounds");
@@ -551,6 +556,8 @@ impl YamlFrameReader {
                     "box-shadow"
                 } else if !item["border"].is_badvalue() {
                     "border"
+                } else if !item["outline"].is_badvalue() {
+                    "outline"
                 } else if !item["gradient"].is_badvalue() {
                     "gradient"
                 } else if !item["radial-gradient"].is_badvalue() {
@@ -582,6 +589,7 @@ impl YamlFrameReader {
                 "scroll-layer" => self.add_scroll_layer_from_yaml(wrench, &item),
                 "clip" => { self.handle_clip_from_yaml(wrench, &item); }
                 "border" => self.handle_border(wrench, &full_clip_region, &item),
+                "outline" => self.handle_outline(wrench, &full_clip_region, &item),
                 "gradient" => self.handle_gradient(wrench, &full_clip_region, &item),
                 "radial-gradient" => self.handle_radial_gradient(wrench, &full_clip_region, &item),
                 "box-shadow" => self.handle_box_shadow(wrench, &full_clip_region, &item),
@@ -660,13 +668,23 @@ impl YamlFrameReader {
 
         let filters = yaml["filters"].as_vec_filter_op().unwrap_or(vec![]);
 
-        self.builder().push_stacking_context(scroll_policy,
-                                             bounds,
-                                             z_index as i32,
-                                             transform.into(),
-                                             perspective,
-                                             mix_blend_mode,
-                                             filters);
+        if yaml["isolation"].as_str() == Some("isolate") {
+            self.builder().push_isolated_stacking_context(scroll_policy,
+                                                          bounds,
+                                                          z_index as i32,
+                                                          transform.into(),
+                                                          perspective,
+                                                          mix_blend_mode,
+                                                          filters);
+        } else {
+            self.builder().push_stacking_context(scroll_policy,
+                                                 bounds,
+                                                 z_index as i32,
+                                                 transform.into(),
+                                                 perspective,
+                                                 mix_blend_mode,
+                                                 filters);
+        }
 
         if !yaml["items"].is_badvalue() {
             self.add_display_list_items_from_yaml(wrench, &yaml["items"]);
@@ -19,7 +19,7 @@ use time;
 use webrender;
 use webrender_traits::*;
 use webrender_traits::SpecificDisplayItem::*;
-use yaml_helper::{mix_blend_mode_to_string, scroll_policy_to_string};
+use yaml_helper::{border_style_to_string, mix_blend_mode_to_string, scroll_policy_to_string};
 use yaml_rust::{Yaml, YamlEmitter};
 
 type Table = yaml_rust::yaml::Hash;
@@ -190,6 +190,9 @@ fn write_sc(parent: &mut Table, sc: &StackingContext) {
     if sc.mix_blend_mode != MixBlendMode::Normal {
         mix_blend_mode_node(parent, "mix-blend-mode", sc.mix_blend_mode)
     }
+    if sc.isolate {
+        str_node(parent, "isolation", "isolate");
+    }
     // filters
 }
 
@@ -579,18 +582,7 @@ impl YamlFrameWriter {
                                                          item.widths.left ];
                             let colors: Vec<String> = trbl.iter().map(|x| color_to_string(x.color)).collect();
                             let styles: Vec<String> = trbl.iter().map(|x| {
-                                match x.style {
-                                    BorderStyle::None => "none",
-                                    BorderStyle::Solid => "solid",
-                                    BorderStyle::Double => "double",
-                                    BorderStyle::Dotted => "dotted",
-                                    BorderStyle::Dashed => "dashed",
-                                    BorderStyle::Hidden => "hidden",
-                                    BorderStyle::Ridge => "ridge",
-                                    BorderStyle::Inset => "inset",
-                                    BorderStyle::Outset => "outset",
-                                    BorderStyle::Groove => "groove",
-                                }.to_owned()
+                                border_style_to_string(x.style).to_owned()
                             }).collect();
                             yaml_node(&mut v, "width", f32_vec_yaml(&widths, true));
                             str_node(&mut v, "border-type", "normal");
@@ -684,6 +676,19 @@ impl YamlFrameWriter {
                         }
                     }
                 },
+                Outline(item) => {
+                    str_node(&mut v, "type", "outline");
+                    // Outlines are read back from the bounds of the box they
+                    // are drawn around, rather than of the ring.
+                    rect_node(&mut v, "bounds", &item.box_bounds);
+                    f32_node(&mut v, "width", item.width);
+                    f32_node(&mut v, "offset", item.offset);
+                    color_node(&mut v, "color", item.side.color);
+                    str_node(&mut v, "style", border_style_to_string(item.side.style));
+                    if let Some(radius_node) = maybe_radius_yaml(&item.radius) {
+                        yaml_node(&mut v, "radius", radius_node);
+                    }
+                },
                 BoxShadow(item) => {
                     str_node(&mut v, "type", "box-shadow");
                     rect_node(&mut v, "box-bounds", &item.box_bounds);
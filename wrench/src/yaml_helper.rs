@@ -91,6 +91,19 @@ define_enum_conversion!(string_to_scroll_policy, scroll_policy_to_string, Scroll
     ("fixed", ScrollPolicy::Fixed)
 ]);
 
+define_enum_conversion!(string_to_border_style, border_style_to_string, BorderStyle, [
+    ("none", BorderStyle::None),
+    ("solid", BorderStyle::Solid),
+    ("double", BorderStyle::Double),
+    ("dotted", BorderStyle::Dotted),
+    ("dashed", BorderStyle::Dashed),
+    ("hidden", BorderStyle::Hidden),
+    ("ridge", BorderStyle::Ridge),
+    ("inset", BorderStyle::Inset),
+    ("outset", BorderStyle::Outset),
+    ("groove", BorderStyle::Groove)
+]);
+
 impl YamlHelper for Yaml {
     fn as_force_f32(&self) -> Option<f32> {
         match *self {